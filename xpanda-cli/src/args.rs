@@ -1,6 +1,20 @@
-use crate::read::read_named_arg;
-use clap::Parser;
+use crate::diagnostic::ColorChoice;
+use crate::doc::DocFormat;
+use crate::fields::{parse_field_delimiter, parse_field_number, DEFAULT_FIELD_DELIMITER};
+use crate::graph::GraphFormat;
+use crate::lint::{LintFormat, LintRule};
+use crate::read::{read_named_arg, VarFileFormat, DEFAULT_MAX_LINE_BYTES};
+use crate::rotate::Rotation;
+use crate::timings::DEFAULT_SLOW_THRESHOLD_MS;
+use crate::write::TrailingNewline;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::path::PathBuf;
+use xpanda::FileProvider;
 
 /// Unix shell-like parameter expansion/variable substitution.
 ///
@@ -19,13 +33,13 @@ use std::path::PathBuf;
 /// ${VAR:+alternative} substituted with `alternative` if the corresponding value for `VAR` is
 ///                     set and non-empty, otherwise ``.
 /// ${VAR?}             substituted with the corresponding value for `VAR` if set, otherwise
-///                     causes the program to exit with a status code of 1 and an error
+///                     causes the program to exit with a status code of 3 and an error
 ///                     printed to standard error.
 /// ${VAR?error}        substituted with the corresponding value for `VAR` if set, otherwise
-///                     causes the program to exit with a status code of 1 and `error`
+///                     causes the program to exit with a status code of 3 and `error`
 ///                     printed to standard error.
 /// ${VAR?error}        substituted with the corresponding value for `VAR` if set and non-empty,
-///                     otherwise causes the program to exit with a status code of 1 and `error`
+///                     otherwise causes the program to exit with a status code of 3 and `error`
 ///                     printed to standard error.
 /// ${#VAR}             substituted with the length of the corresponding value for `VAR` if set,
 ///                     otherwise `0`.
@@ -43,15 +57,53 @@ use std::path::PathBuf;
 ///                     with the casing of the first character reversed.
 /// ${VAR~~}            substituted with the value of the variable named by the value of `VAR`,
 ///                     with the casing of all characters reversed.
+/// ${VAR#pattern}      substituted with the corresponding value for `VAR`, without the shortest
+///                     prefix matching the glob pattern. `${VAR##pattern}` removes the
+///                     longest matching prefix instead.
+/// ${VAR%pattern}      substituted with the corresponding value for `VAR`, without the shortest
+///                     suffix matching the glob pattern. `${VAR%%pattern}` removes the
+///                     longest matching suffix instead.
+/// ${VAR/pattern/text} substituted with the corresponding value for `VAR`, with the first match
+///                     of the glob pattern replaced with `text`, in which `&` stands for the
+///                     match and `\1` to `\9` for its groups. `${VAR//pattern/text}` replaces
+///                     every match instead.
+/// ${env:VAR}          substituted with the value of the environment variable `VAR`,
+///                     regardless of any other provided variables. Like other variables it may
+///                     be given a default value, as in `${env:VAR:-default}`.
+/// ${file:path}        substituted with the contents of the file at `path` if `--file-root` is
+///                     set.
+/// ${xpanda:line}      substituted with the line the variable appears on if `--context` is
+///                     set. See `--context` for more context variables.
+/// ${VAR@urlencode}    substituted with the corresponding value for `VAR`, percent-encoded.
+/// ${VAR@urldecode}    substituted with the corresponding value for `VAR`, percent-decoded.
+/// ${VAR@base64}       substituted with the corresponding value for `VAR`, base64 encoded.
+/// ${VAR@base64d}      substituted with the corresponding value for `VAR`, base64 decoded.
+/// ${VAR@shellquote}   substituted with the corresponding value for `VAR`, single quoted for
+///                     POSIX shells.
+/// ${VAR@pad:5}       substituted with the corresponding value for `VAR`, right-aligned to a
+///                     width of 5 using spaces. `${VAR@pad:05}` pads with zeros instead.
+/// ${VAR@fixed:2}      substituted with the corresponding value for `VAR`, formatted with 2
+///                     decimals.
+/// ${VAR@trunc:80}     substituted with the corresponding value for `VAR`, truncated to at
+///                     most 80 characters. `${VAR@trunc:80:...}` ends truncated values with
+///                     `...` instead, within the 80 characters.
+///                     Values that fail to decode, or that aren't numbers for `@pad` and
+///                     `@fixed`, cause the program to exit with a status code of 6.
 ///
 /// `VAR` above is a named variable. Positional variables are also supported and are passed as
 /// trailing arguments to the program (see the examples). They can be referenced using their
 /// index (starting at 1), for example, `$1` references the first positional variable, `$2` the
-/// second and so on. `$0` is a space concatenated string of all positional variables.
+/// second and so on. `$0` is a space concatenated string of all positional variables. Variables
+/// starting with a digit are read as a whole word, e.g. `$1abc` is rejected rather than read as
+/// `${1}abc`, unless `--lenient-identifiers` is set.
 ///
 /// The `$` character is assumed to be the start of a variable. If the variable does not match
 /// any of the forms listed above, the program will fail to parse the variable and exit the
-/// program with a status code of 1.
+/// program with a status code of 5. A variable can be escaped by prefixing it with an
+/// additional `$`, e.g. `$$VAR` yields `$VAR`. Inside a variable, `$}` and `$:` yield a
+/// literal `}` and `:` respectively, e.g. `${VAR-a$}b}` yields `a}b` if `VAR` is unset.
+/// With `--quoting`, text inside a variable can also be quoted using single or double quotes,
+/// e.g. `${VAR-"a } b"}` yields `a } b` if `VAR` is unset.
 ///
 /// EXAMPLES:
 /// `echo '$VAR' | xpanda -v VAR=value`   substitute `$VAR` with `value` using a named variable
@@ -60,6 +112,8 @@ use std::path::PathBuf;
 ///                                       variable.
 /// `echo '$1' | xpanda value`            substitute `$1` with `value` using a positional variable
 ///                                       argument.
+/// `echo '$1' | xpanda --arg -value`     substitute `$1` with `-value` using `--arg`, which also
+///                                       accepts values starting with `-`.
 /// `xpanda < some_file`                  output a copy of `some_file` with variables substituted
 ///                                       with environment variables.
 /// `xpanda -f var_file < some_file`      output a copy of `some_file` with variables substituted
@@ -69,13 +123,387 @@ use std::path::PathBuf;
 ///
 /// The given input must be ASCII or UTF-8 encoded. Output is UTF-8 encoded and may be written
 /// in chunks. If no variables are provided, then values are sourced from environment variables.
+///
+/// Errors are printed to standard error as `line:col message`. If standard error is a terminal,
+/// errors are instead colored and the offending column is highlighted. Colors can be disabled
+/// by setting the `NO_COLOR` environment variable or using `--color never`.
+///
+/// EXIT STATUS:
+/// 0   the input was successfully processed.
+/// 1   a general failure occurred, such as a file that could not be read.
+/// 2   the program was invoked with invalid arguments.
+/// 3   a variable was unset (or empty) in a `${VAR?}` (or `${VAR:?}`) pattern.
+/// 4   a variable without a default value was unset (or empty) while `--no-unset` (or
+///     `--no-empty`) is set.
+/// 5   the input could not be parsed.
+/// 6   the value of a variable could not be processed by a modifier, such as `@base64d`.
+/// 7   a namespaced variable, such as `${env:VAR}`, could not be resolved.
+/// 8   `render --dry-run` found an output file which would change.
+#[allow(clippy::doc_markdown)]
 #[derive(Parser, Debug)]
-#[command(name = "Xpanda", version, verbatim_doc_comment)]
+#[command(
+    name = "Xpanda",
+    version,
+    disable_version_flag = true,
+    verbatim_doc_comment
+)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Controls when to use colors in diagnostics printed to standard error. With `auto`,
+    /// colors are used if standard error is a terminal and the `NO_COLOR` environment variable
+    /// is not set.
+    #[arg(
+        long = "color",
+        value_name = "WHEN",
+        value_enum,
+        default_value_t = ColorChoice::Auto,
+        global = true,
+        verbatim_doc_comment
+    )]
+    pub color: ColorChoice,
+
+    /// Don't print progress messages or warnings to standard error, such as the templates
+    /// rendered or the timings printed by `render --timings`. Errors are still printed.
+    #[arg(
+        long = "quiet",
+        short = 'q',
+        conflicts_with = "verbose",
+        global = true,
+        verbatim_doc_comment
+    )]
+    pub quiet: bool,
+
+    /// Print more about what the program does to standard error, such as which variable
+    /// sources were loaded along with how many variables each contributed, and which input is
+    /// expanded to which output. Given twice, the number of substitutions made on each line of
+    /// the input is printed as well.
+    #[arg(
+        long = "verbose",
+        short = 'V',
+        action = ArgAction::Count,
+        global = true,
+        verbatim_doc_comment
+    )]
+    pub verbose: u8,
+
+    #[command(flatten)]
+    pub expand: ExpandArgs,
+
+    /// Print version
+    #[arg(long = "version", action = ArgAction::Version)]
+    pub version: Option<bool>,
+}
+
+impl Args {
+    /// Parses the arguments of the current process, exiting with a usage error on failure.
+    ///
+    /// Options of the default command (expand) can't be combined with a subcommand, since they
+    /// would be silently ignored. Global options such as `--color` may appear anywhere.
+    pub fn parse_args() -> Self {
+        let mut command = Self::command();
+        let matches = command.get_matches_mut();
+
+        if let Some((name, _)) = matches.subcommand() {
+            let misplaced = command
+                .get_arguments()
+                .filter(|arg| !arg.is_global_set())
+                .find(|arg| {
+                    matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+                });
+
+            if let Some(arg) = misplaced {
+                let arg_name = arg.get_long().map_or_else(
+                    || arg.get_id().as_str().to_uppercase(),
+                    |long| format!("--{long}"),
+                );
+                let message =
+                    format!("the argument '{arg_name}' cannot be used with the '{name}' command");
+
+                command.error(ErrorKind::ArgumentConflict, message).exit();
+            }
+        }
+
+        Self::from_arg_matches(&matches).unwrap_or_else(|error| error.exit())
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Expand variables in the input. This is the default command if none is given.
+    Expand(ExpandArgs),
+
+    /// Validate the syntax of the input without expanding it.
+    ///
+    /// Nothing is written to standard output. If the input fails to parse, the error is printed
+    /// to standard error and the program exits with a status code of 1.
+    ///
+    /// With `--schema`, the values of environment variables are also validated against the
+    /// schema. Every violation is printed along with the position of the variable in the input,
+    /// and the program exits with the status code of the first violation.
+    #[command(verbatim_doc_comment)]
+    Check {
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        schema: SchemaArgs,
+    },
+
+    /// Check the input for patterns which are valid but likely mistakes, or which go against a
+    /// style, without expanding it.
+    ///
+    /// Each rule has a severity of `allow`, `warning` or `error`, which can be changed using
+    /// `--allow`, `--warn` and `--deny`. Lints are printed to standard error along with the
+    /// name of the rule. If any lint is an error, the program exits with a status code of 1.
+    ///
+    /// A rule can be suppressed in the input using a comment containing `xpanda-lint: allow`
+    /// followed by the names of rules to allow, or `all`. The comment applies to the line it's
+    /// on and to the line after it.
+    ///
+    /// Example:
+    /// # xpanda-lint: allow unbraced-variable
+    /// url=http://$HOST
+    #[command(verbatim_doc_comment)]
+    Lint {
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        lint: LintArgs,
+
+        /// The format to write lints in.
+        #[arg(
+            long = "format",
+            value_name = "FORMAT",
+            value_enum,
+            default_value_t = LintFormat::Text,
+            verbatim_doc_comment
+        )]
+        format: LintFormat,
+    },
+
+    /// Apply the fixes of lints found in the input, as reported by the lint command, and write
+    /// the fixed input to standard output.
+    ///
+    /// Fixes are applied for lints of rules which aren't allowed, such as `$ {VAR}` becoming
+    /// `${VAR}`. Rules such as `unbraced-variable` are allowed by default, and can be enabled
+    /// using `--warn`. The number of fixes applied is printed to standard error.
+    ///
+    /// Example:
+    /// xpanda fix --warn unbraced-variable --write -i app.conf.tmpl
+    #[command(verbatim_doc_comment)]
+    Fix {
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        lint: LintArgs,
+
+        /// Overwrite the input file with the fixed input rather than writing it to standard
+        /// output.
+        #[arg(long = "write", requires = "input_file", verbatim_doc_comment)]
+        write: bool,
+    },
+
+    /// List the variables referenced by the input, one per line in order of first appearance.
+    ///
+    /// Positional variables are listed by their index.
+    ///
+    /// With `--doc`, a table documenting every variable referenced by the templates in a
+    /// directory is written instead, listing where each variable is used, whether it has a
+    /// default value and its description from the schema given by `--schema`, if any.
+    ///
+    /// With `--graph`, a graph of the dependencies between the variables of the input is
+    /// written instead.
+    #[command(verbatim_doc_comment)]
+    Vars {
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        doc: DocArgs,
+
+        /// Write a graph of the dependencies between variables in the given format, rather
+        /// than listing the variables. The template depends on each variable it references
+        /// directly, and a variable depends on each variable referenced by its default value,
+        /// alternative value or error message, as `A` depends on `B` in `${A:-$B}`.
+        /// Dependencies on variables whose values name yet another variable, as in `${!VAR}`,
+        /// are marked as indirect.
+        #[arg(
+            long = "graph",
+            value_name = "FORMAT",
+            value_enum,
+            conflicts_with = "doc_dir",
+            verbatim_doc_comment
+        )]
+        graph: Option<GraphFormat>,
+
+        #[command(flatten)]
+        schema: SchemaArgs,
+    },
+
+    /// Normalize the input by rewriting all variables to their braced form, e.g. `$VAR` is
+    /// rewritten as `${VAR}`.
+    #[command(verbatim_doc_comment)]
+    Fmt {
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+
+    /// Print `export` commands for the variables of var files, for use with `eval` in a shell.
+    ///
+    /// The values of the variables may reference other variables, which are resolved first,
+    /// such that `URL=http://$HOST` uses the value of `HOST` from the same var files. A value
+    /// referencing its own variable, as in `PATH=$PATH:/opt/bin`, uses the environment variable
+    /// of that name. Variables referencing each other in a cycle will cause the program to exit
+    /// with a status code of 1.
+    ///
+    /// Each variable is written as `export KEY='value'`, in order of name, with the value
+    /// single quoted such that it's safe to evaluate.
+    ///
+    /// Example:
+    /// eval "$(xpanda export -f app.env)"
+    #[command(verbatim_doc_comment)]
+    Export {
+        /// Provide a file to source variables from. This option can be used multiple times, in
+        /// which case variables of later files take precedence.
+        #[arg(
+            long = "var-file",
+            short = 'f',
+            num_args = 1,
+            required = true,
+            value_name = "FILE",
+            value_hint = clap::ValueHint::FilePath,
+            verbatim_doc_comment
+        )]
+        var_files: Vec<PathBuf>,
+
+        /// The format of all var files.
+        #[arg(
+            long = "var-file-format",
+            value_name = "FORMAT",
+            value_enum,
+            default_value_t = VarFileFormat::Env,
+            verbatim_doc_comment
+        )]
+        var_file_format: VarFileFormat,
+
+        /// With this flag set, variables referenced by values but not set by the var files or
+        /// the environment will cause the program to exit with a status code of 4.
+        #[arg(long = "no-unset", short = 'u', verbatim_doc_comment)]
+        no_unset: bool,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+
+    /// Render multiple templates in one run as listed in a manifest file.
+    ///
+    /// The manifest is a TOML file listing templates to render along with the variables to
+    /// render them with. Variables declared at the top level are shared by all templates, while
+    /// variables declared per template take precedence. Relative paths are resolved relative to
+    /// the directory of the manifest. Output files are overwritten if they already exist, unless
+    /// their contents wouldn't change.
+    ///
+    /// Every template is rendered even if some fail, after which a summary is printed to
+    /// standard error in the order the templates are listed in. The exit status is that of the
+    /// first failure, if any.
+    ///
+    /// If `hash_file` is set and every template renders, the SHA-256 hash of each output is
+    /// written to the given file in the format of `sha256sum`, ordered by output path, for use by
+    /// build systems which cache on the rendered output.
+    ///
+    /// Example:
+    /// no_unset = true                 # optional, same as `--no-unset`
+    /// strict_var_files = true         # optional, same as `--strict-var-files`
+    /// env_vars = true                 # optional, same as `--env-vars`
+    /// var_files = ["common.env"]      # optional, same as `--var-file`
+    /// var_file_format = "set"         # optional, same as `--var-file-format`
+    /// vars = { REGION = "eu" }        # optional, same as `--var`
+    /// aliases = { DB = "DB_URL" }     # optional, same as `--alias`
+    /// quoting = true                  # optional, same as `--quoting`
+    /// lenient_identifiers = true      # optional, same as `--lenient-identifiers`
+    /// powershell_env = true           # optional, same as `--powershell-env`
+    /// schema = "schema.toml"          # optional, same as `--schema`
+    /// front_matter = true             # optional, same as `--front-matter`
+    /// reproducible = true             # optional, same as `--reproducible`
+    /// hash_file = "xpanda.sha256"     # optional, see below
+    ///
+    /// [[templates]]
+    /// template = "app.conf.tmpl"
+    /// output = "out/app.conf"
+    /// var_files = ["app.env"]         # optional
+    /// vars = { PORT = "8080" }        # optional
+    #[allow(clippy::doc_markdown, clippy::doc_link_with_quotes)]
+    #[command(verbatim_doc_comment)]
+    Render {
+        #[command(flatten)]
+        render: RenderArgs,
+    },
+
+    /// Print a shell completion script to standard output.
+    ///
+    /// Example:
+    /// xpanda completions bash > /usr/share/bash-completion/completions/xpanda
+    #[command(verbatim_doc_comment)]
+    Completions {
+        /// The shell to generate completions for.
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+
+    /// Print a man page in roff format to standard output.
+    ///
+    /// Example:
+    /// xpanda man > /usr/share/man/man1/xpanda.1
+    #[command(verbatim_doc_comment)]
+    Man,
+}
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(clap::Args, Debug)]
+pub struct ExpandArgs {
     /// With this flag set, missing variables without any default value will cause the program
-    /// to exit with a status code of 1. Off by default.
-    #[arg(long = "no-unset", short = 'u', verbatim_doc_comment)]
-    pub no_unset: bool,
+    /// to exit with a status code of 4. Off by default.
+    #[arg(
+        long = "no-unset",
+        short = 'u',
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        verbatim_doc_comment
+    )]
+    pub no_unset: Option<bool>,
+
+    /// With this flag set, variables which are set to an empty value and have no default value
+    /// will cause the program to exit with a status code of 4. Off by default.
+    #[arg(
+        long = "no-empty",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        verbatim_doc_comment
+    )]
+    pub no_empty: Option<bool>,
+
+    /// Enables all checks at once, similar to `set -euo pipefail` in shell scripts. Same as
+    /// `--no-unset --no-empty --strict-var-files`, and additionally passing a variable more
+    /// than once using `--var` with different values, or any warning such as about a key
+    /// ignored in a var file, will cause the program to exit with a status code of 1.
+    #[arg(
+        long = "strict",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        verbatim_doc_comment
+    )]
+    pub strict: Option<bool>,
 
     /// Provide a file to source variable values from.
     ///
@@ -101,6 +529,42 @@ pub struct Args {
     )]
     pub var_files: Vec<PathBuf>,
 
+    /// The format of all var files. Var files may be encoded as UTF-8, or as UTF-16 if they
+    /// start with a byte order mark, as written by Windows PowerShell. Defaults to `env`.
+    #[arg(
+        long = "var-file-format",
+        value_name = "FORMAT",
+        value_enum,
+        verbatim_doc_comment
+    )]
+    pub var_file_format: Option<VarFileFormat>,
+
+    /// With this flag set, a key appearing more than once in the same var file with different
+    /// values will cause the program to exit with a status code of 1, rather than the last
+    /// value silently taking precedence. A key repeated with the same value yields a warning.
+    #[arg(
+        long = "strict-var-files",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        verbatim_doc_comment
+    )]
+    pub strict_var_files: Option<bool>,
+
+    /// With this flag set, variables provided using `--var` or `--var-file` which the input
+    /// never references will cause the program to exit with a status code of 1, listing all of
+    /// them. Such variables are likely left over, or misspelled. With `--json`, they are listed
+    /// as `unused` either way. With this flag set, the whole input is read before any output
+    /// is written.
+    #[arg(
+        long = "deny-unused",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        verbatim_doc_comment
+    )]
+    pub deny_unused: Option<bool>,
+
     /// With this flag set, named variables will be sourced from environment variables in
     /// addition to any other provided variables. Named variables will always take precedence
     /// over environment variables though. This flag is implicitly true if no other variables
@@ -115,6 +579,12 @@ pub struct Args {
     )]
     pub env_vars: Option<bool>,
 
+    /// Only source named variables from environment variables whose names start with the
+    /// given prefix, e.g. `--env-prefix APP_`. This option can be used multiple times in order
+    /// to allow multiple prefixes. Namespaced variables such as `${env:HOME}` are not affected.
+    #[arg(long = "env-prefix", value_name = "PREFIX", verbatim_doc_comment)]
+    pub env_prefixes: Vec<String>,
+
     /// Adds a named variable to source from. The value should be a key value pair separated
     /// by a `=`, e.g. `-v NAME=value`.
     ///
@@ -133,15 +603,371 @@ pub struct Args {
     )]
     pub named_vars: Vec<(String, String)>,
 
+    /// Adds an alias for a named variable, such that `OLD` in the input resolves as the
+    /// variable `NEW`, e.g. `--alias DB_URL=DATABASE_URL`. An aliased name always resolves as
+    /// its target, even if a variable named `OLD` is set.
+    ///
+    /// This option can be used multiple times in order to add multiple aliases.
+    #[arg(
+        long = "alias",
+        value_name = "OLD=NEW",
+        num_args = 1,
+        value_parser = read_named_arg,
+        verbatim_doc_comment
+    )]
+    pub aliases: Vec<(String, String)>,
+
+    /// Adds a positional variable value, e.g. `--arg value`. Unlike trailing arguments, the
+    /// value may start with `-` or be the name of a command.
+    ///
+    /// This option can be used multiple times in order to add multiple values. Values given
+    /// using this option precede any positional variable values given as trailing arguments.
+    #[arg(
+        long = "arg",
+        value_name = "VALUE",
+        num_args = 1,
+        allow_hyphen_values = true,
+        verbatim_doc_comment
+    )]
+    pub arg_vars: Vec<String>,
+
     /// Zero or more positional variable values. The first value can be referenced using `$1`,
-    /// the second `$2` and so on.
+    /// the second `$2` and so on. Values following `--` are never read as options or commands,
+    /// e.g. `xpanda -- -value check`.
     ///
     /// If any positional variables are provided then the default setting to source values
     /// from environment variables will be overridden. To continue sourcing from environment
     /// values as well, add the `--env-vars` flag.
-    #[arg(last = true, num_args = 0.., verbatim_doc_comment)]
+    #[arg(num_args = 0.., verbatim_doc_comment)]
     pub positional_vars: Vec<String>,
 
+    /// Names the positional variables in order, e.g. `--positional-names HOST,PORT`, such that
+    /// `$HOST` resolves as `$1` and `$PORT` as `$2`. A name resolves like any other named
+    /// variable, such as an environment variable of the same name, only if its positional
+    /// variable isn't given.
+    #[arg(
+        long = "positional-names",
+        value_name = "NAMES",
+        value_delimiter = ',',
+        verbatim_doc_comment
+    )]
+    pub positional_names: Vec<String>,
+
+    /// Print a warning to standard error for each reference to a positional variable beyond
+    /// the number of positional variables given, such as `$7` with three given, which is
+    /// substituted with an empty string. Params with a default or alternative value, such as
+    /// `${7-none}`, are exempt.
+    #[arg(long = "warn-positional", verbatim_doc_comment)]
+    pub warn_positional: bool,
+
+    /// Source positional variables from environment variables named by the given prefix
+    /// followed by an index starting at 1, e.g. `XPANDA_ARG_1`, `XPANDA_ARG_2` and so on for the
+    /// prefix `XPANDA_ARG_`. The first missing index ends the positional variables, and indexes
+    /// with leading zeros such as `XPANDA_ARG_01` are ignored. Useful when only environment
+    /// variables can be provided, such as in some CI runners.
+    ///
+    /// Just like positional variables given as arguments, this overrides the default setting to
+    /// source named values from environment variables.
+    #[arg(
+        long = "positional-from-env",
+        value_name = "PREFIX",
+        conflicts_with_all = ["arg_vars", "positional_vars"],
+        verbatim_doc_comment
+    )]
+    pub positional_env_prefix: Option<String>,
+
+    /// Enables the `file` namespace, such that `${file:path}` is substituted with the contents
+    /// of the file at `path` relative to the given directory. Paths leading outside of the
+    /// directory are rejected, and files that don't exist are treated as unset. Failure to read
+    /// a file will cause the program to exit with a status code of 7.
+    #[arg(
+        long = "file-root",
+        value_name = "DIR",
+        value_hint = clap::ValueHint::DirPath,
+        verbatim_doc_comment
+    )]
+    pub file_root: Option<PathBuf>,
+
+    /// The maximum size in bytes of files read using `${file:path}`.
+    #[arg(
+        long = "file-max-size",
+        value_name = "BYTES",
+        default_value_t = FileProvider::DEFAULT_MAX_SIZE,
+        requires = "file_root",
+        verbatim_doc_comment
+    )]
+    pub file_max_size: u64,
+
+    /// With this flag set, trailing newlines are removed from the contents of files read using
+    /// `${file:path}`.
+    #[arg(
+        long = "trim-file-newlines",
+        requires = "file_root",
+        verbatim_doc_comment
+    )]
+    pub trim_file_newlines: bool,
+
+    /// How `${#VAR}` counts the length of a value. Unlike in shells, the length doesn't depend
+    /// on the locale, e.g. `LANG` or `LC_ALL`. For the length that Bash reports in a UTF-8
+    /// locale use `chars`, and for the length in the `C` locale use `bytes`.
+    #[arg(
+        long = "length-mode",
+        value_name = "MODE",
+        value_enum,
+        default_value_t = LengthMode::Bytes,
+        verbatim_doc_comment
+    )]
+    pub length_mode: LengthMode,
+
+    /// The locale whose rules case modifiers such as `${VAR^^}` follow, given as a BCP 47
+    /// language tag, e.g. `tr` to uppercase `i` to `İ` as in Turkish. Without it, the default
+    /// Unicode case mapping is used, regardless of `LANG` or `LC_ALL`.
+    #[cfg(feature = "locale")]
+    #[arg(long = "case-locale", value_name = "LOCALE", verbatim_doc_comment)]
+    pub case_locale: Option<xpanda::CaseLocale>,
+
+    /// The maximum length in characters of the value of a variable. A longer value will cause
+    /// the program to exit with a status code of 6, unless `--truncate-long-values` is set.
+    /// Default and alternative values written in the input aren't limited.
+    #[arg(long = "max-value-length", value_name = "CHARS", verbatim_doc_comment)]
+    pub max_value_length: Option<usize>,
+
+    /// Truncate values longer than `--max-value-length` instead of exiting.
+    #[arg(
+        long = "truncate-long-values",
+        requires = "max_value_length",
+        verbatim_doc_comment
+    )]
+    pub truncate_long_values: bool,
+
+    /// The maximum number of bytes of input held in memory at once. Longer lines are expanded
+    /// in parts, but a longer param, or a longer line with `--fields`, will cause the program to
+    /// exit with a status code of 1. Doesn't apply if the whole input is read at once, such as
+    /// with `--front-matter` or `--raw-blocks`.
+    #[arg(
+        long = "max-line-bytes",
+        value_name = "BYTES",
+        default_value_t = DEFAULT_MAX_LINE_BYTES,
+        verbatim_doc_comment
+    )]
+    pub max_line_bytes: u64,
+
+    /// Treat the input as multiple documents, separated by lines consisting of nothing but the
+    /// given delimiter, e.g. `--delimiter '---'`. Each document is expanded independently,
+    /// including its front matter, and the delimiter lines are written to the output as is.
+    /// The whole input is read at once.
+    #[arg(
+        long = "delimiter",
+        value_name = "DELIMITER",
+        allow_hyphen_values = true,
+        verbatim_doc_comment
+    )]
+    pub delimiter: Option<String>,
+
+    /// Split the positional variables into groups of the given size, one group per document,
+    /// such that `$1` of the second document is the positional variable following the first
+    /// group. Documents without a group of their own have no positional variables.
+    #[arg(
+        long = "args-per-doc",
+        value_name = "N",
+        requires = "delimiter",
+        conflicts_with = "positional_env_prefix",
+        verbatim_doc_comment
+    )]
+    pub args_per_doc: Option<usize>,
+
+    /// Treat each line of the input as delimited data, such as TSV or CSV, and only expand the
+    /// given fields, counting from 1, e.g. `--fields 3,5`. All other fields are written to the
+    /// output as is, without interpreting `$`. Quoted fields aren't recognized, so a delimiter
+    /// within quotes still separates fields.
+    #[arg(
+        long = "fields",
+        value_name = "N",
+        value_delimiter = ',',
+        value_parser = parse_field_number,
+        conflicts_with_all = [
+            "delimiter",
+            "context",
+            "print_effective_precedence",
+            "json",
+            "front_matter",
+            "raw_blocks",
+            "conditionals",
+            "schema_file",
+        ],
+        verbatim_doc_comment
+    )]
+    pub fields: Vec<usize>,
+
+    /// The delimiter between the fields selected by `--fields`, where `\t` stands for a tab,
+    /// which is also the default.
+    #[arg(
+        long = "field-delimiter",
+        value_name = "DELIMITER",
+        default_value = DEFAULT_FIELD_DELIMITER,
+        hide_default_value = true,
+        value_parser = parse_field_delimiter,
+        requires = "fields",
+        allow_hyphen_values = true,
+        verbatim_doc_comment
+    )]
+    pub field_delimiter: String,
+
+    /// Enables context variables describing the expansion itself, e.g. to watermark generated
+    /// files. The whole input is read at once.
+    ///
+    /// ${xpanda:line}      substituted with the line of the input the variable appears on.
+    /// ${xpanda:file}      substituted with the path given by `--input`, if any.
+    /// ${xpanda:timestamp} substituted with the time of expansion in UTC, for example
+    ///                     `2024-01-02T03:04:05Z`. If the `SOURCE_DATE_EPOCH` environment
+    ///                     variable is set, it's used as the number of seconds since the Unix
+    ///                     epoch instead, which keeps generated files reproducible.
+    #[arg(long = "context", verbatim_doc_comment)]
+    pub context: bool,
+
+    /// Guarantee byte-identical output across runs and platforms given identical input and
+    /// variables. Environment variables are matched case-sensitively on Windows too, and
+    /// `${xpanda:timestamp}` is the Unix epoch unless `SOURCE_DATE_EPOCH` is set.
+    #[arg(long = "reproducible", verbatim_doc_comment)]
+    pub reproducible: bool,
+
+    /// Instead of expanding the input, print each variable it references along with the source
+    /// its value would be taken from, one per line separated by a tab. The source is one of
+    /// `positional`, `env`, `--var`, `file N (PATH)` for the Nth `--var-file`, `front matter`,
+    /// `namespace 'NAME'` or `unset`. The whole input is read at once.
+    #[arg(
+        long = "print-effective-precedence",
+        conflicts_with = "delimiter",
+        verbatim_doc_comment
+    )]
+    pub print_effective_precedence: bool,
+
+    /// Instead of the expanded text, print a JSON object with the expanded text as `output`,
+    /// the referenced variables which are unset as `missing`, the referenced variables which
+    /// are set along with their sources as `used`, the provided variables which are never
+    /// referenced along with their sources as `unused`, how each referenced variable resolves
+    /// as `resolutions`, with a `state` of `unset`, `empty` or `set` and the `length` of its
+    /// value in characters, each param as written in the input along with its position and
+    /// expanded value as `params`, and any errors as `errors`. `output` is `null` if
+    /// expansion fails, as is the `value` of each param which failed to expand, in which case
+    /// the program still exits with the status code of the error. The whole input is read at
+    /// once. Like the expanded text, the object is written to `--output` if given.
+    #[arg(
+        long = "json",
+        conflicts_with_all = ["delimiter", "print_effective_precedence"],
+        verbatim_doc_comment
+    )]
+    pub json: bool,
+
+    #[command(flatten)]
+    pub config: ConfigArgs,
+
+    #[command(flatten)]
+    pub schema: SchemaArgs,
+
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+impl ExpandArgs {
+    /// Enables the options implied by `--strict`, if set. Fails if a variable is passed more
+    /// than once using `--var` with different values.
+    pub fn apply_strict(mut self) -> Result<Self, String> {
+        if self.strict != Some(true) {
+            return Ok(self);
+        }
+
+        let mut values = HashMap::new();
+
+        for (key, value) in &self.named_vars {
+            if values
+                .insert(key, value)
+                .is_some_and(|previous| previous != value)
+            {
+                return Err(format!(
+                    "Variable '{key}' is passed more than once using --var with different values"
+                ));
+            }
+        }
+
+        self.no_unset = Some(true);
+        self.no_empty = Some(true);
+        self.strict_var_files = Some(true);
+
+        Ok(self)
+    }
+
+    /// Whether any variables are provided on the command line, in which case environment
+    /// variables are only used if `--env-vars` is set.
+    pub const fn provides_vars(&self) -> bool {
+        !self.var_files.is_empty()
+            || !self.named_vars.is_empty()
+            || !self.arg_vars.is_empty()
+            || !self.positional_vars.is_empty()
+            || self.positional_env_prefix.is_some()
+    }
+}
+
+/// How `${#VAR}` counts the length of a value.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum LengthMode {
+    /// The number of bytes of the value encoded as UTF-8.
+    #[default]
+    Bytes,
+    /// The number of Unicode characters.
+    Chars,
+    /// The number of characters as perceived by users, where e.g. a letter followed by a
+    /// combining accent counts as one.
+    Graphemes,
+}
+
+impl From<LengthMode> for xpanda::LengthMode {
+    fn from(mode: LengthMode) -> Self {
+        match mode {
+            LengthMode::Bytes => Self::Bytes,
+            LengthMode::Chars => Self::Chars,
+            LengthMode::Graphemes => Self::Graphemes,
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ConfigArgs {
+    /// Provide a config file to read default options from, instead of
+    /// `$XDG_CONFIG_HOME/xpanda/config.toml` or `~/.config/xpanda/config.toml`. Unlike the
+    /// default config file, the given file must exist.
+    ///
+    /// The config file is a TOML file which may set `var_files`, `var_file_format`,
+    /// `env_vars`, `env_prefixes`, `strict`, `no_unset`, `no_empty`, `strict_var_files`,
+    /// `deny_unused`, `quoting`, `lenient_identifiers`, `powershell_env`, `front_matter`,
+    /// `safe_mode`, `raw_blocks`, `comments`, `conditionals`, `dynamic_names` and
+    /// `ansi_c_quotes`.
+    /// Options given on the command line take precedence over the config file: var files and
+    /// env prefixes of the config file come before those given on the command line, and
+    /// other options of the config file only apply if not given on the command line. Flags
+    /// enabled by the config file can be disabled using e.g. `--quoting=false`. Unlike var
+    /// files given on the command line, those of the config file don't stop environment
+    /// variables from being used. Relative paths are resolved relative to the directory of
+    /// the config file.
+    #[arg(
+        long = "config",
+        value_name = "FILE",
+        value_hint = clap::ValueHint::FilePath,
+        conflicts_with = "no_config",
+        verbatim_doc_comment
+    )]
+    pub config_file: Option<PathBuf>,
+
+    /// Don't read the default config file.
+    #[arg(long = "no-config", verbatim_doc_comment)]
+    pub no_config: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct InputArgs {
     /// Provide a path to read from. This overrides the default behaviour of reading from
     /// standard input.
     #[arg(
@@ -153,6 +979,232 @@ pub struct Args {
     )]
     pub input_file: Option<PathBuf>,
 
+    /// Allow text inside variables to be quoted using single or double quotes, e.g.
+    /// `${VAR:-"a } b"}`. Inside double quotes, `\"` and `\\` yield `"` and `\`.
+    #[arg(
+        long = "quoting",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        verbatim_doc_comment
+    )]
+    pub quoting: Option<bool>,
+
+    /// Treat variables starting with a digit, e.g. `$1abc`, as named variables instead of
+    /// rejecting them. Variables consisting of only digits are always positional variables.
+    #[arg(
+        long = "lenient-identifiers",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        verbatim_doc_comment
+    )]
+    pub lenient_identifiers: Option<bool>,
+
+    /// Support PowerShell-style environment variables without braces, e.g. `$env:HOME` is
+    /// treated like `${env:HOME}`.
+    #[arg(
+        long = "powershell-env",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        verbatim_doc_comment
+    )]
+    pub powershell_env: Option<bool>,
+
+    /// Parse lines starting with `#! xpanda:` at the start of the input as front matter, in
+    /// which the template declares the variables it requires and their defaults, e.g.
+    /// `#! xpanda: require DB_URL, PORT; default REGION=us-east-1`. Expansion fails if any
+    /// required variable is unset. The front matter itself is not written to the output.
+    /// With this flag set, the whole input is read before any output is written.
+    #[arg(
+        long = "front-matter",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        verbatim_doc_comment
+    )]
+    pub front_matter: Option<bool>,
+
+    /// Reject params which could resolve values other than the provided variables, for use
+    /// with templates from untrusted sources. Indirect params as in `${!VAR}` and namespaced
+    /// variables as in `${env:HOME}` or `$env:HOME` will cause the program to exit with a
+    /// status code of 5. Consider also using `--var-file` or `-v` rather than environment
+    /// variables, in order to limit which variables the template can read.
+    #[arg(
+        long = "safe-mode",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        verbatim_doc_comment
+    )]
+    pub safe_mode: Option<bool>,
+
+    /// Leave text between `$[[raw]]` and `$[[/raw]]` as is, e.g. to embed scripts which use
+    /// `$` themselves. A raw block which is never closed will cause the program to exit with
+    /// a status code of 5. With this flag set, the whole input is read before any output is
+    /// written.
+    #[arg(
+        long = "raw-blocks",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        verbatim_doc_comment
+    )]
+    pub raw_blocks: Option<bool>,
+
+    /// Leave out lines starting with `$#` from the output, so that templates can be annotated
+    /// with comments. A literal `$#` at the start of a line can be written as `$$#`.
+    #[arg(
+        long = "comments",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        verbatim_doc_comment
+    )]
+    pub comments: Option<bool>,
+
+    /// Include text between `${if VAR}` and `${end}` only if `VAR` is set and non-empty, and
+    /// text between `${else}` and `${end}` otherwise, e.g. to include whole sections of a config
+    /// file only if a variable is set. The line break directly following a tag is left out. An
+    /// unclosed block will cause the program to exit with a status code of 5. With this flag set,
+    /// the whole input is read before any output is written.
+    #[arg(
+        long = "conditionals",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        verbatim_doc_comment
+    )]
+    pub conditionals: Option<bool>,
+
+    /// Expand variables inside the names of variables to compute the name, e.g.
+    /// `${DB_URL_${ENV}}` expands to the value of `DB_URL_PROD` if `ENV` is `PROD`. A computed
+    /// name which isn't a valid variable name will cause the program to exit with a status code
+    /// of 6.
+    #[arg(
+        long = "dynamic-names",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        verbatim_doc_comment
+    )]
+    pub dynamic_names: Option<bool>,
+
+    /// Evaluate default or alternative values starting with `=` as expressions, e.g.
+    /// `${REPLICAS:-=max(2, $CPU / 2)}`. A value which should start with a literal `=` is
+    /// written as `$=` instead.
+    #[cfg(feature = "expr")]
+    #[arg(long = "expressions", verbatim_doc_comment)]
+    pub expressions: bool,
+
+    /// Read ANSI-C quoted text of shell scripts, e.g. `$'a\tb'`, instead of exiting with a
+    /// status code of 5. Quoted text must end on the line it starts on.
+    #[arg(
+        long = "ansi-c-quotes",
+        value_name = "MODE",
+        value_enum,
+        verbatim_doc_comment
+    )]
+    pub ansi_c_quotes: Option<AnsiCQuotes>,
+}
+
+/// How ANSI-C quoted text, e.g. `$'a\tb'`, is read.
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AnsiCQuotes {
+    /// Interpret escape sequences such as `\n`, `\t`, `\xHH` and `\uHHHH` like Bash does
+    Interpret,
+    /// Keep the quoted text as is, including the `$` and the quotes
+    Keep,
+}
+
+impl From<AnsiCQuotes> for xpanda::AnsiCQuotes {
+    fn from(mode: AnsiCQuotes) -> Self {
+        match mode {
+            AnsiCQuotes::Interpret => Self::Interpret,
+            AnsiCQuotes::Keep => Self::Keep,
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct LintArgs {
+    /// Don't check the given rule. This option can be used multiple times.
+    #[arg(long = "allow", value_name = "RULE", value_enum, verbatim_doc_comment)]
+    pub allow: Vec<LintRule>,
+
+    /// Report violations of the given rule as warnings. This option can be used multiple
+    /// times.
+    #[arg(long = "warn", value_name = "RULE", value_enum, verbatim_doc_comment)]
+    pub warn: Vec<LintRule>,
+
+    /// Report violations of the given rule as errors. This option can be used multiple
+    /// times.
+    #[arg(long = "deny", value_name = "RULE", value_enum, verbatim_doc_comment)]
+    pub deny: Vec<LintRule>,
+
+    /// Declare a variable as optional, such that using it without a default value is
+    /// reported by the `missing-default` rule. This option can be used multiple times.
+    #[arg(long = "optional", value_name = "VAR", verbatim_doc_comment)]
+    pub optional_vars: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct DocArgs {
+    /// Document the variables of all templates in the given directory and its
+    /// subdirectories, rather than listing the variables of the input. Hidden files and
+    /// directories are skipped.
+    #[arg(
+        long = "doc",
+        value_name = "DIR",
+        value_hint = clap::ValueHint::DirPath,
+        conflicts_with = "input_file",
+        verbatim_doc_comment
+    )]
+    pub doc_dir: Option<PathBuf>,
+
+    /// The format of the documentation written by `--doc`.
+    #[arg(
+        long = "doc-format",
+        value_name = "FORMAT",
+        value_enum,
+        default_value_t = DocFormat::Markdown,
+        requires = "doc_dir",
+        verbatim_doc_comment
+    )]
+    pub doc_format: DocFormat,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SchemaArgs {
+    /// Provide a schema file describing the expected variables. Required variables of the
+    /// schema must be set, and the values of variables referenced by the input must satisfy
+    /// their constraints, or the program exits with a status code of 3 or 6 respectively.
+    /// With this option set, the whole input is read before any output is written.
+    ///
+    /// The file must be formatted as TOML, with one table per variable.
+    ///
+    /// Example:
+    /// [vars.PORT]
+    /// required = true                 # optional
+    /// pattern = "[0-9]+"              # optional, must match the whole value
+    /// description = "The port"        # optional
+    ///
+    /// [vars.LOG_LEVEL]
+    /// values = ["debug", "info"]      # optional, instead of a pattern
+    #[allow(clippy::doc_markdown, clippy::doc_link_with_quotes)]
+    #[arg(
+        long = "schema",
+        value_name = "FILE",
+        value_hint = clap::ValueHint::FilePath,
+        verbatim_doc_comment
+    )]
+    pub schema_file: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct OutputArgs {
     /// Provide a path to write to. This overrides the default behaviour of writing to
     /// standard output. A new file is created if it doesn't already exists. Output is
     /// appended to it if it already exists.
@@ -164,4 +1216,121 @@ pub struct Args {
         verbatim_doc_comment
     )]
     pub output_file: Option<PathBuf>,
+
+    /// Don't write the line ending at the end of the output, if there is one. Useful when
+    /// the output must not end with a newline, such as a secret read from a file.
+    #[arg(
+        long = "no-trailing-newline",
+        short = 'n',
+        conflicts_with = "ensure_trailing_newline",
+        verbatim_doc_comment
+    )]
+    pub no_trailing_newline: bool,
+
+    /// Write a newline at the end of the output, unless it's empty or already ends with one.
+    #[arg(long = "ensure-trailing-newline", verbatim_doc_comment)]
+    pub ensure_trailing_newline: bool,
+
+    /// Move the output file aside and start a new one before it would grow larger than the
+    /// given number of bytes. Useful when running as a persistent filter. Files are only
+    /// rotated after a complete line, and are moved to the output path followed by the first
+    /// free number, such as `out.log.1`.
+    #[arg(
+        long = "rotate-size",
+        value_name = "BYTES",
+        requires = "output_file",
+        value_parser = clap::value_parser!(u64).range(1..),
+        verbatim_doc_comment
+    )]
+    pub rotate_size: Option<u64>,
+
+    /// Move the output file aside and start a new one when the day changes, in UTC. Files are
+    /// moved to the output path followed by the day they were written, such as
+    /// `out.log.2024-01-02`.
+    #[arg(long = "rotate-daily", requires = "output_file", verbatim_doc_comment)]
+    pub rotate_daily: bool,
+}
+
+impl OutputArgs {
+    /// Returns how the line ending at the end of the output is treated.
+    pub const fn trailing_newline(&self) -> TrailingNewline {
+        if self.no_trailing_newline {
+            TrailingNewline::Remove
+        } else if self.ensure_trailing_newline {
+            TrailingNewline::Ensure
+        } else {
+            TrailingNewline::Keep
+        }
+    }
+
+    /// Returns when the output file is rotated.
+    pub const fn rotation(&self) -> Rotation {
+        Rotation {
+            max_size: self.rotate_size,
+            daily: self.rotate_daily,
+        }
+    }
+}
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(clap::Args, Debug)]
+pub struct RenderArgs {
+    /// The manifest file listing the templates to render.
+    #[arg(
+        long = "config",
+        short = 'c',
+        value_name = "FILE",
+        default_value = "xpanda.toml",
+        value_hint = clap::ValueHint::FilePath,
+        verbatim_doc_comment
+    )]
+    pub manifest: PathBuf,
+
+    /// The maximum number of templates to render at the same time.
+    #[arg(
+        long = "jobs",
+        short = 'j',
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u64).range(1..),
+        verbatim_doc_comment
+    )]
+    pub jobs: u64,
+
+    /// Render the templates without writing any output files, instead printing a unified
+    /// diff between the current contents of each output file and what would be written to
+    /// standard output. The program exits with a status code of 8 if any output would
+    /// change, or 0 if all are up to date.
+    #[arg(long = "dry-run", verbatim_doc_comment)]
+    pub dry_run: bool,
+
+    /// Write the output files only if every template renders. Outputs are first written to
+    /// temporary files next to the output files, which then replace the output files once all
+    /// are written, such that a failure doesn't leave some output files updated but not others.
+    #[arg(long = "atomic", conflicts_with = "dry_run", verbatim_doc_comment)]
+    pub atomic: bool,
+
+    /// Write every output file, including those whose contents wouldn't change. By default
+    /// such files are left untouched, preserving their modification times so that programs
+    /// watching them aren't triggered. Existing output files are then reported as updated,
+    /// without being read.
+    #[arg(long = "force", conflicts_with = "dry_run", verbatim_doc_comment)]
+    pub force: bool,
+
+    /// Print how long lexing, parsing and evaluating each template took to standard error,
+    /// warning about templates which took longer than --slow-threshold to render, such as to
+    /// find pathological templates in large manifests.
+    #[arg(long = "timings", verbatim_doc_comment)]
+    pub timings: bool,
+
+    /// The number of milliseconds after which rendering a template is reported as slow by
+    /// --timings.
+    #[arg(
+        long = "slow-threshold",
+        value_name = "MS",
+        default_value_t = DEFAULT_SLOW_THRESHOLD_MS,
+        requires = "timings",
+        verbatim_doc_comment
+    )]
+    pub slow_threshold: u64,
 }
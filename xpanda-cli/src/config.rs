@@ -0,0 +1,131 @@
+#![allow(clippy::module_name_repetitions)]
+
+use crate::args::{AnsiCQuotes, ExpandArgs};
+use crate::read::VarFileFormat;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Default options of the expand command, shared by everyone using the same config file.
+///
+/// Example:
+/// ```toml
+/// strict = true
+/// var_files = ["team.env"]
+/// env_prefixes = ["APP_"]
+/// quoting = true
+/// ```
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub var_files: Vec<PathBuf>,
+    pub var_file_format: Option<VarFileFormat>,
+    pub env_vars: Option<bool>,
+    #[serde(default)]
+    pub env_prefixes: Vec<String>,
+    pub strict: Option<bool>,
+    pub no_unset: Option<bool>,
+    pub no_empty: Option<bool>,
+    pub strict_var_files: Option<bool>,
+    pub deny_unused: Option<bool>,
+    pub quoting: Option<bool>,
+    pub lenient_identifiers: Option<bool>,
+    pub powershell_env: Option<bool>,
+    pub front_matter: Option<bool>,
+    pub safe_mode: Option<bool>,
+    pub raw_blocks: Option<bool>,
+    pub comments: Option<bool>,
+    pub conditionals: Option<bool>,
+    pub dynamic_names: Option<bool>,
+    pub ansi_c_quotes: Option<AnsiCQuotes>,
+}
+
+impl Config {
+    /// Merges the config into the given arguments, which take precedence. Var files and env
+    /// prefixes of the config come first, and other options only apply if not given as
+    /// arguments.
+    pub fn apply(self, mut args: ExpandArgs) -> ExpandArgs {
+        // Var files of the config add to the environment variables, rather than replacing
+        // them like variables given as arguments do
+        if self.env_vars.is_none() && !self.var_files.is_empty() && !args.provides_vars() {
+            args.env_vars = args.env_vars.or(Some(true));
+        }
+
+        args.var_files = self.var_files.into_iter().chain(args.var_files).collect();
+        args.env_prefixes = self
+            .env_prefixes
+            .into_iter()
+            .chain(args.env_prefixes)
+            .collect();
+        args.var_file_format = args.var_file_format.or(self.var_file_format);
+        args.env_vars = args.env_vars.or(self.env_vars);
+        args.strict = args.strict.or(self.strict);
+        args.no_unset = args.no_unset.or(self.no_unset);
+        args.no_empty = args.no_empty.or(self.no_empty);
+        args.strict_var_files = args.strict_var_files.or(self.strict_var_files);
+        args.deny_unused = args.deny_unused.or(self.deny_unused);
+        args.input.quoting = args.input.quoting.or(self.quoting);
+        args.input.lenient_identifiers =
+            args.input.lenient_identifiers.or(self.lenient_identifiers);
+        args.input.powershell_env = args.input.powershell_env.or(self.powershell_env);
+        args.input.front_matter = args.input.front_matter.or(self.front_matter);
+        args.input.safe_mode = args.input.safe_mode.or(self.safe_mode);
+        args.input.raw_blocks = args.input.raw_blocks.or(self.raw_blocks);
+        args.input.comments = args.input.comments.or(self.comments);
+        args.input.conditionals = args.input.conditionals.or(self.conditionals);
+        args.input.dynamic_names = args.input.dynamic_names.or(self.dynamic_names);
+        args.input.ansi_c_quotes = args.input.ansi_c_quotes.or(self.ansi_c_quotes);
+        args
+    }
+}
+
+/// Applies the config file given by `--config`, or else the default config file if it exists
+/// and `--no-config` isn't set.
+pub fn apply_config(args: ExpandArgs) -> Result<ExpandArgs, String> {
+    let path = if let Some(path) = &args.config.config_file {
+        path.clone()
+    } else if args.config.no_config {
+        return Ok(args);
+    } else {
+        match default_config_path().filter(|path| path.is_file()) {
+            Some(path) => path,
+            None => return Ok(args),
+        }
+    };
+
+    Ok(read_config(&path)?.apply(args))
+}
+
+/// Reads a config file. Relative paths within it are resolved relative to the directory
+/// containing the config file.
+pub fn read_config(path: &Path) -> Result<Config, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|error| format!("Failed to open config file '{}': {}", path.display(), error))?;
+    let mut config: Config = toml::from_str(&content).map_err(|error| {
+        format!(
+            "Failed to parse config file '{}': {}",
+            path.display(),
+            error
+        )
+    })?;
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+
+    for var_file in &mut config.var_files {
+        *var_file = base.join(&*var_file);
+    }
+
+    Ok(config)
+}
+
+/// The path of the default config file, `xpanda/config.toml` in `$XDG_CONFIG_HOME` or else in
+/// `~/.config`.
+fn default_config_path() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+
+    Some(config_dir.join("xpanda").join("config.toml"))
+}
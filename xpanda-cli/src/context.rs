@@ -0,0 +1,58 @@
+use std::env;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use xpanda::Context;
+
+/// Creates the context of expanding the given input file, or standard input if none is given.
+///
+/// The timestamp is the current time, unless the `SOURCE_DATE_EPOCH` environment variable is
+/// set to a number of seconds since the Unix epoch, which keeps generated files reproducible.
+/// If `reproducible` is `true` and the variable isn't set, the timestamp is the Unix epoch.
+pub fn create_context(input_file: Option<&Path>, reproducible: bool) -> Result<Context, String> {
+    let seconds = match env::var("SOURCE_DATE_EPOCH") {
+        Ok(value) => value.trim().parse().map_err(|_| {
+            format!("Invalid SOURCE_DATE_EPOCH '{value}', expected a number of seconds")
+        })?,
+        Err(_) if reproducible => 0,
+        Err(_) => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs()),
+    };
+    let context = Context::new().timestamp(format_timestamp(seconds));
+
+    Ok(match input_file {
+        Some(file) => context.file(file.display().to_string()),
+        None => context,
+    })
+}
+
+/// Formats a number of seconds since the Unix epoch as an RFC 3339 timestamp in UTC, such as
+/// `2024-01-02T03:04:05Z`.
+pub fn format_timestamp(seconds: u64) -> String {
+    let days = seconds / 86_400;
+    let time = seconds % 86_400;
+
+    // Converts days since the epoch to a civil date, see
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
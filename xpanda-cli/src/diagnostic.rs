@@ -0,0 +1,177 @@
+use clap::ValueEnum;
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use xpanda::{Error, ErrorKind};
+
+/// Exit code for failures unrelated to expansion, such as unreadable files.
+pub const EXIT_FAILURE: u8 = 1;
+/// Exit code for a variable being unset in a `${VAR?}` pattern.
+pub const EXIT_REQUIRED: u8 = 3;
+/// Exit code for a variable being unset while `--no-unset` is set, or empty while `--no-empty`
+/// is set.
+pub const EXIT_UNSET: u8 = 4;
+/// Exit code for input that fails to parse.
+pub const EXIT_SYNTAX: u8 = 5;
+/// Exit code for a variable value that can't be processed by a modifier applied to it.
+pub const EXIT_INVALID_VALUE: u8 = 6;
+/// Exit code for a namespaced variable that can't be resolved.
+pub const EXIT_PROVIDER: u8 = 7;
+/// Exit code for a dry run in which some output would change.
+pub const EXIT_CHANGED: u8 = 8;
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(ValueEnum, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ColorChoice {
+    /// Use colors if standard error is a terminal and `NO_COLOR` is not set.
+    Auto,
+    /// Always use colors.
+    Always,
+    /// Never use colors.
+    Never,
+}
+
+impl ColorChoice {
+    /// Resolves whether colors should be used when writing to standard error.
+    pub fn should_colorize(self) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Never => false,
+            Self::Auto => {
+                let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+                !no_color && io::stderr().is_terminal()
+            },
+        }
+    }
+}
+
+/// The place in the input that a [`Diagnostic`] refers to.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Location {
+    pub line: usize,
+    pub col: usize,
+    /// The full line of input at `line`, if available, used to highlight the column.
+    pub source: Option<String>,
+}
+
+/// An error to be reported to the user on standard error.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub location: Option<Location>,
+    /// The status code the program should exit with.
+    pub exit_code: u8,
+    /// Whether the diagnostic is a warning rather than an error.
+    pub is_warning: bool,
+}
+
+impl Diagnostic {
+    pub const fn new(message: String) -> Self {
+        Self {
+            message,
+            location: None,
+            exit_code: EXIT_FAILURE,
+            is_warning: false,
+        }
+    }
+
+    pub fn at(message: String, line: usize, col: usize, source: Option<&str>) -> Self {
+        let source = source.map(|source| source.trim_end_matches(['\n', '\r']).to_string());
+
+        Self {
+            message,
+            location: Some(Location { line, col, source }),
+            exit_code: EXIT_FAILURE,
+            is_warning: false,
+        }
+    }
+
+    /// Creates a diagnostic for an expansion error on the given line of input, with an exit
+    /// code depending on the kind of error.
+    pub fn from_error(error: Error, line: usize, source: Option<&str>) -> Self {
+        let exit_code = match error.kind {
+            ErrorKind::Required => EXIT_REQUIRED,
+            ErrorKind::Unset => EXIT_UNSET,
+            ErrorKind::Syntax => EXIT_SYNTAX,
+            ErrorKind::InvalidValue => EXIT_INVALID_VALUE,
+            ErrorKind::Provider => EXIT_PROVIDER,
+            _ => EXIT_FAILURE,
+        };
+
+        Self {
+            exit_code,
+            ..Self::at(error.message, line, error.col, source)
+        }
+    }
+
+    /// Writes the diagnostic. Without colors the output is kept terse (`line:col message`, or
+    /// `line:col warning: message` for warnings) so that it is easy to parse by other programs.
+    /// With colors the offending column in the source line is highlighted as well.
+    pub fn write(&self, output: &mut impl Write, colorize: bool) -> io::Result<()> {
+        if !colorize {
+            let prefix = if self.is_warning { "warning: " } else { "" };
+
+            return match &self.location {
+                Some(Location { line, col, .. }) => {
+                    write!(output, "{line}:{col} {prefix}{}", self.message)
+                },
+                None => write!(output, "{prefix}{}", self.message),
+            };
+        }
+
+        let (color, label) = if self.is_warning {
+            (YELLOW, "warning")
+        } else {
+            (RED, "error")
+        };
+
+        writeln!(
+            output,
+            "{color}{label}{RESET}{BOLD}: {}{RESET}",
+            self.message
+        )?;
+
+        let Some(Location { line, col, source }) = &self.location else {
+            return Ok(());
+        };
+
+        let gutter = " ".repeat(line.to_string().len());
+        writeln!(output, "{gutter}{BLUE}-->{RESET} {line}:{col}")?;
+
+        if let Some(source) = source {
+            // Keep tabs so that the caret lines up with the source regardless of tab width
+            let padding: String = source
+                .chars()
+                .take(col.saturating_sub(1))
+                .map(|char| if char == '\t' { '\t' } else { ' ' })
+                .collect();
+
+            writeln!(output, "{gutter} {BLUE}|{RESET}")?;
+            writeln!(output, "{BLUE}{line} |{RESET} {source}")?;
+            writeln!(output, "{gutter} {BLUE}|{RESET} {padding}{color}^{RESET}")?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the diagnostic just like [`Diagnostic::write`], always ending with a newline.
+    pub fn write_line(&self, output: &mut impl Write, colorize: bool) -> io::Result<()> {
+        self.write(output, colorize)?;
+
+        if colorize {
+            Ok(())
+        } else {
+            writeln!(output)
+        }
+    }
+}
+
+impl From<String> for Diagnostic {
+    fn from(message: String) -> Self {
+        Self::new(message)
+    }
+}
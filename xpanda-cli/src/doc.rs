@@ -0,0 +1,173 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use xpanda::{Schema, Template};
+
+/// The format of generated documentation of variables.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum DocFormat {
+    /// A Markdown table with one row per variable.
+    #[default]
+    Markdown,
+    /// A JSON array with one object per variable.
+    Json,
+}
+
+/// How many of the references to a variable have a default value.
+#[derive(Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Defaults {
+    Always,
+    Sometimes,
+    Never,
+}
+
+/// A reference to a variable in a file.
+#[derive(Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct Usage {
+    pub file: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+/// The documentation of a single variable.
+#[derive(Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct VarDoc {
+    pub name: String,
+    pub usages: Vec<Usage>,
+    pub defaults: Defaults,
+    pub description: Option<String>,
+}
+
+/// Collects documentation of the variables referenced by a number of templates, ordered by
+/// variable name.
+#[derive(Debug, Default)]
+pub struct VarDocs {
+    vars: BTreeMap<String, (Vec<Usage>, usize)>,
+}
+
+impl VarDocs {
+    /// Adds all references to variables in the template, which was read from the given file.
+    pub fn add(&mut self, file: &str, template: &Template) {
+        for reference in template.references() {
+            let (usages, defaults) = self.vars.entry(reference.name).or_default();
+
+            usages.push(Usage {
+                file: file.to_string(),
+                line: reference.position.line,
+                col: reference.position.col,
+            });
+
+            if reference.has_default {
+                *defaults += 1;
+            }
+        }
+    }
+
+    /// Returns the documentation of all variables, with descriptions taken from the schema.
+    pub fn into_docs(self, schema: Option<&Schema>) -> Vec<VarDoc> {
+        self.vars
+            .into_iter()
+            .map(|(name, (usages, defaults))| {
+                let defaults = match defaults {
+                    0 => Defaults::Never,
+                    defaults if defaults == usages.len() => Defaults::Always,
+                    _ => Defaults::Sometimes,
+                };
+                let description = schema
+                    .and_then(|schema| schema.get(&name))
+                    .and_then(|var| var.description())
+                    .map(String::from);
+
+                VarDoc {
+                    name,
+                    usages,
+                    defaults,
+                    description,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Lists all files within the directory and its subdirectories, ordered by path. Hidden files
+/// and directories, whose names start with `.`, are skipped. Symbolic links to files are listed,
+/// but symbolic links to directories aren't followed, so links back up the tree can't loop.
+pub fn find_templates(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    let entries = fs::read_dir(dir)
+        .map_err(|error| format!("Failed to read directory '{}': {}", dir.display(), error))?;
+
+    for entry in entries {
+        let entry = entry
+            .map_err(|error| format!("Failed to read directory '{}': {}", dir.display(), error))?;
+        let file_type = entry
+            .file_type()
+            .map_err(|error| format!("Failed to read directory '{}': {}", dir.display(), error))?;
+        let path = entry.path();
+
+        if path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+        {
+            continue;
+        }
+
+        if file_type.is_dir() {
+            files.extend(find_templates(&path)?);
+        } else if !file_type.is_symlink() || path.is_file() {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+/// Writes the documentation in the given format.
+pub fn write_docs(docs: &[VarDoc], format: DocFormat, output: &mut impl Write) -> io::Result<()> {
+    match format {
+        DocFormat::Markdown => write_markdown(docs, output),
+        DocFormat::Json => {
+            serde_json::to_writer_pretty(&mut *output, docs)?;
+            writeln!(output)
+        },
+    }
+}
+
+fn write_markdown(docs: &[VarDoc], output: &mut impl Write) -> io::Result<()> {
+    writeln!(output, "| Variable | Used in | Default | Description |")?;
+    writeln!(output, "|----------|---------|---------|-------------|")?;
+
+    for doc in docs {
+        let usages = doc
+            .usages
+            .iter()
+            .map(|usage| format!("`{}:{}:{}`", usage.file, usage.line, usage.col))
+            .collect::<Vec<_>>();
+        let defaults = match doc.defaults {
+            Defaults::Always => "Always",
+            Defaults::Sometimes => "Sometimes",
+            Defaults::Never => "Never",
+        };
+        let description = doc
+            .description
+            .as_deref()
+            .unwrap_or_default()
+            .replace('|', "\\|")
+            .replace('\n', " ");
+
+        writeln!(
+            output,
+            "| `{}` | {} | {defaults} | {description} |",
+            doc.name,
+            usages.join(", ")
+        )?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use crate::diagnostic::Diagnostic;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use xpanda::{Builder, Xpanda};
+
+/// Resolves the values of variables which may reference each other, such as those of a var
+/// file, returning the resolved values by name.
+///
+/// A variable referenced by the value of another is resolved before it, while a value
+/// referencing its own variable, as in `PATH=$PATH:/bin`, refers to the value it would have
+/// without the var file. Variables referencing each other in a cycle are an error.
+pub fn resolve_vars(
+    vars: &HashMap<String, String>,
+    builder: impl Fn() -> Builder,
+) -> Result<BTreeMap<String, String>, Diagnostic> {
+    let mut resolver = Resolver {
+        vars,
+        builder,
+        resolved: BTreeMap::new(),
+        stack: Vec::new(),
+    };
+
+    let mut names = vars.keys().collect::<Vec<_>>();
+    names.sort();
+
+    for name in names {
+        resolver.resolve(name)?;
+    }
+
+    Ok(resolver.resolved)
+}
+
+/// Writes an `export` command for each variable, with its value quoted such that the output
+/// can be evaluated by a POSIX shell.
+pub fn write_exports(vars: BTreeMap<String, String>) -> Result<String, Diagnostic> {
+    if let Some(name) = vars.keys().find(|name| !is_shell_name(name)) {
+        return Err(Diagnostic::new(format!(
+            "Can't export '{name}', which is not a valid shell variable name"
+        )));
+    }
+
+    let mut template = String::new();
+
+    for name in vars.keys() {
+        let _result = writeln!(template, "export {name}=${{{name}@shellquote}}");
+    }
+
+    Xpanda::builder()
+        .with_named_vars(vars.into_iter().collect())
+        .build()
+        .expand(&template)
+        .map_err(|error| Diagnostic::new(error.message))
+}
+
+struct Resolver<'a, F> {
+    vars: &'a HashMap<String, String>,
+    builder: F,
+    resolved: BTreeMap<String, String>,
+    /// The names of the variables currently being resolved, innermost last.
+    stack: Vec<&'a str>,
+}
+
+impl<'a, F: Fn() -> Builder> Resolver<'a, F> {
+    fn resolve(&mut self, name: &'a str) -> Result<(), Diagnostic> {
+        if self.resolved.contains_key(name) {
+            return Ok(());
+        }
+
+        if let Some(index) = self.stack.iter().position(|other| *other == name) {
+            let cycle = self.stack[index..].join(" -> ");
+            return Err(Diagnostic::new(format!(
+                "Variables reference each other in a cycle: {cycle} -> {name}"
+            )));
+        }
+
+        let Some((name, value)) = self.vars.get_key_value(name) else {
+            return Ok(());
+        };
+
+        self.stack.push(name);
+
+        let xpanda = (self.builder)().build();
+        let template = xpanda.parse(value).map_err(|error| {
+            Diagnostic::new(format!("Failed to parse the value of '{name}': {error}"))
+        })?;
+
+        for other in template.variables() {
+            if other != *name {
+                if let Some((other, _)) = self.vars.get_key_value(&other) {
+                    self.resolve(other)?;
+                }
+            }
+        }
+
+        self.stack.pop();
+
+        let xpanda = (self.builder)()
+            .with_named_vars(self.resolved.clone().into_iter().collect())
+            .build();
+        let result = xpanda.render(&template).map_err(|error| {
+            let message = format!("Failed to resolve '{name}': {}", error.message);
+
+            Diagnostic {
+                exit_code: Diagnostic::from_error(error, 1, None).exit_code,
+                ..Diagnostic::new(message)
+            }
+        })?;
+
+        self.resolved.insert(name.clone(), result);
+
+        Ok(())
+    }
+}
+
+/// Whether the name can be assigned to in a POSIX shell.
+fn is_shell_name(name: &str) -> bool {
+    name.bytes()
+        .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+        && name
+            .bytes()
+            .next()
+            .is_some_and(|byte| !byte.is_ascii_digit())
+}
@@ -0,0 +1,75 @@
+use xpanda::{Error, Xpanda};
+
+/// The default delimiter between the fields of a line, which is a tab as used by TSV.
+pub const DEFAULT_FIELD_DELIMITER: &str = "\t";
+
+/// Parses a field delimiter, where `\t` stands for a tab since it's hard to pass one as an
+/// argument.
+pub fn parse_field_delimiter(arg: &str) -> Result<String, String> {
+    match arg {
+        "" => Err(String::from("the delimiter must not be empty")),
+        "\\t" => Ok(String::from("\t")),
+        _ => Ok(arg.to_string()),
+    }
+}
+
+/// Parses the number of a field, counting from 1.
+pub fn parse_field_number(arg: &str) -> Result<usize, String> {
+    match arg.parse::<usize>() {
+        Ok(0) => Err(String::from("fields are counted from 1")),
+        Ok(number) => Ok(number),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+/// The fields of delimited data, such as TSV or CSV, which are expanded. All other fields are
+/// kept as is, without interpreting `$`.
+#[derive(Debug, Clone)]
+pub struct Fields {
+    numbers: Vec<usize>,
+    delimiter: String,
+}
+
+impl Fields {
+    pub const fn new(numbers: Vec<usize>, delimiter: String) -> Self {
+        Self { numbers, delimiter }
+    }
+
+    /// Expands the selected fields of a single line, which may end with a line ending. The
+    /// column of an error refers to the whole line rather than the field. After expanding a
+    /// field, `on_field` is called with the column the field starts at.
+    pub fn expand_line(
+        &self,
+        xpanda: &Xpanda,
+        line: &str,
+        mut on_field: impl FnMut(usize),
+    ) -> Result<String, Error> {
+        let text = line.trim_end_matches(['\n', '\r']);
+        let mut result = String::with_capacity(line.len());
+        let mut col = 0;
+
+        for (number, field) in (1..).zip(text.split(self.delimiter.as_str())) {
+            if number > 1 {
+                result.push_str(&self.delimiter);
+                col += self.delimiter.chars().count();
+            }
+
+            if self.numbers.contains(&number) {
+                let expanded = xpanda.expand(field).map_err(|mut error| {
+                    error.col += col;
+                    error
+                })?;
+                on_field(col + 1);
+                result.push_str(&expanded);
+            } else {
+                result.push_str(field);
+            }
+
+            col += field.chars().count();
+        }
+
+        result.push_str(&line[text.len()..]);
+
+        Ok(result)
+    }
+}
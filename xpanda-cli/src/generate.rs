@@ -0,0 +1,20 @@
+use crate::args::Args;
+use clap::CommandFactory;
+use clap_complete::Shell;
+use clap_mangen::Man;
+use std::io::{self, Write};
+
+/// The name the binary is installed as, which completion scripts are registered for.
+const BIN_NAME: &str = "xpanda";
+
+/// Writes a completion script for the given shell.
+pub fn write_completions(shell: Shell, output: &mut impl Write) {
+    let mut command = Args::command();
+    clap_complete::generate(shell, &mut command, BIN_NAME, output);
+}
+
+/// Writes a man page in roff format.
+pub fn write_man_page(output: &mut impl Write) -> io::Result<()> {
+    let command = Args::command().name(BIN_NAME);
+    Man::new(command).render(output)
+}
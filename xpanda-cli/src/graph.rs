@@ -0,0 +1,133 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use std::io::{self, Write};
+use xpanda::{Reached, Template};
+
+/// The format of a graph of dependencies between variables.
+#[derive(ValueEnum, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum GraphFormat {
+    /// A directed graph in the DOT language of Graphviz.
+    Dot,
+    /// A JSON object with an array of variables and an array of edges.
+    Json,
+}
+
+/// How a variable is reached from the template or from another variable.
+#[derive(Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeKind {
+    /// Referenced by the template itself.
+    Direct,
+    /// Referenced by the default value of another variable.
+    Default,
+    /// Referenced by the alternative value of another variable.
+    Alternative,
+    /// Referenced by the error message of another variable.
+    Error,
+}
+
+/// A dependency on a variable, either of the template or of another variable.
+#[derive(Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct Edge {
+    /// The variable depending on `to`, or [`None`] for the template itself.
+    pub from: Option<String>,
+    pub to: String,
+    pub kind: EdgeKind,
+    /// Whether the value of `to` names yet another variable, as in `${!VAR}`.
+    pub indirect: bool,
+}
+
+/// The dependencies between the variables of a template, in order of appearance.
+#[derive(Serialize, Debug, Default)]
+pub struct Graph {
+    pub variables: Vec<String>,
+    pub edges: Vec<Edge>,
+}
+
+impl Graph {
+    pub fn new(template: &Template) -> Self {
+        let mut graph = Self::default();
+
+        for reference in template.references() {
+            let (from, kind) = match reference.reached {
+                Reached::ViaDefault(name) => (Some(name), EdgeKind::Default),
+                Reached::ViaAlternative(name) => (Some(name), EdgeKind::Alternative),
+                Reached::ViaError(name) => (Some(name), EdgeKind::Error),
+                _ => (None, EdgeKind::Direct),
+            };
+            let edge = Edge {
+                from,
+                to: reference.name,
+                kind,
+                indirect: reference.is_indirect,
+            };
+
+            if !graph.variables.contains(&edge.to) {
+                graph.variables.push(edge.to.clone());
+            }
+
+            if !graph.edges.contains(&edge) {
+                graph.edges.push(edge);
+            }
+        }
+
+        graph
+    }
+}
+
+/// Writes the graph in the given format.
+pub fn write_graph(graph: &Graph, format: GraphFormat, output: &mut impl Write) -> io::Result<()> {
+    match format {
+        GraphFormat::Dot => write_dot(graph, output),
+        GraphFormat::Json => {
+            serde_json::to_writer_pretty(&mut *output, graph)?;
+            writeln!(output)
+        },
+    }
+}
+
+fn write_dot(graph: &Graph, output: &mut impl Write) -> io::Result<()> {
+    // Variables can't be named `$`, so the template can't clash with any of them
+    writeln!(output, "digraph variables {{")?;
+    writeln!(output, "    \"$\" [label=\"template\", shape=box];")?;
+
+    for variable in &graph.variables {
+        writeln!(output, "    {};", quote(variable))?;
+    }
+
+    for edge in &graph.edges {
+        let from = edge
+            .from
+            .as_deref()
+            .map_or_else(|| String::from("\"$\""), quote);
+        let mut attributes = Vec::new();
+
+        match edge.kind {
+            EdgeKind::Direct => {},
+            EdgeKind::Default => attributes.push("label=\"default\""),
+            EdgeKind::Alternative => attributes.push("label=\"alternative\""),
+            EdgeKind::Error => attributes.push("label=\"error\""),
+        }
+
+        if edge.indirect {
+            attributes.push("style=dashed");
+        }
+
+        if attributes.is_empty() {
+            writeln!(output, "    {from} -> {};", quote(&edge.to))?;
+        } else {
+            writeln!(
+                output,
+                "    {from} -> {} [{}];",
+                quote(&edge.to),
+                attributes.join(", ")
+            )?;
+        }
+    }
+
+    writeln!(output, "}}")
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,118 @@
+use crate::args::LintArgs;
+use crate::diagnostic::Diagnostic;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::io::{self, Write};
+use xpanda::{Lint, Linter, Rule, Severity};
+
+/// A rule checked by the lint command. See [`Rule`].
+#[derive(ValueEnum, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LintRule {
+    /// A variable written without braces, as in `$VAR`.
+    UnbracedVariable,
+    /// A variable given by `--optional` used without a default value.
+    MissingDefault,
+    /// A default or alternative value which is never used.
+    UnreachableBranch,
+    /// A named variable starting with a digit, as in `$1abc`.
+    DigitPrefixedName,
+    /// A `$` separated from a `{` by whitespace, as in `$ {VAR}`.
+    SpacedBrace,
+    /// A default value only used if the variable is unset, as in `${VAR-default}`.
+    UnsetOnlyDefault,
+}
+
+impl From<LintRule> for Rule {
+    fn from(rule: LintRule) -> Self {
+        match rule {
+            LintRule::UnbracedVariable => Self::UnbracedVariable,
+            LintRule::MissingDefault => Self::MissingDefault,
+            LintRule::UnreachableBranch => Self::UnreachableBranch,
+            LintRule::DigitPrefixedName => Self::DigitPrefixedName,
+            LintRule::SpacedBrace => Self::SpacedBrace,
+            LintRule::UnsetOnlyDefault => Self::UnsetOnlyDefault,
+        }
+    }
+}
+
+/// The format lints are written in.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum LintFormat {
+    /// One diagnostic per lint on standard error.
+    #[default]
+    Text,
+    /// A JSON array with one object per lint on standard output.
+    Json,
+}
+
+/// A lint as written in the JSON format.
+#[derive(Serialize, Debug)]
+struct JsonLint<'a> {
+    rule: String,
+    severity: String,
+    message: &'a str,
+    line: usize,
+    col: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fix: Option<JsonFix<'a>>,
+}
+
+/// A fix as written in the JSON format, replacing the input from `start` up to `end`, given as
+/// byte offsets.
+#[derive(Serialize, Debug)]
+struct JsonFix<'a> {
+    start: usize,
+    end: usize,
+    replacement: &'a str,
+}
+
+/// Creates a linter with the severities of the given rules overridden, where `--deny` takes
+/// precedence over `--warn`, which takes precedence over `--allow`.
+pub fn create_linter(args: &LintArgs) -> Linter {
+    let severities = [
+        (&args.allow, Severity::Allow),
+        (&args.warn, Severity::Warning),
+        (&args.deny, Severity::Error),
+    ];
+
+    severities
+        .into_iter()
+        .flat_map(|(rules, severity)| rules.iter().map(move |rule| (*rule, severity)))
+        .fold(Linter::new(), |linter, (rule, severity)| {
+            linter.severity(rule.into(), severity)
+        })
+        .optional_vars(&args.optional_vars)
+}
+
+/// Converts a lint into a diagnostic at the position of the offending param.
+pub fn to_diagnostic(lint: &Lint, text: &str) -> Diagnostic {
+    let message = format!("{} [{}]", lint.message, lint.rule);
+    let source = text.lines().nth(lint.position.line - 1);
+
+    Diagnostic {
+        is_warning: lint.severity == Severity::Warning,
+        ..Diagnostic::at(message, lint.position.line, lint.position.col, source)
+    }
+}
+
+/// Writes the lints as a JSON array.
+pub fn write_json(lints: &[Lint], output: &mut impl Write) -> io::Result<()> {
+    let lints = lints
+        .iter()
+        .map(|lint| JsonLint {
+            rule: lint.rule.to_string(),
+            severity: lint.severity.to_string(),
+            message: &lint.message,
+            line: lint.position.line,
+            col: lint.position.col,
+            fix: lint.fix.as_ref().map(|fix| JsonFix {
+                start: fix.span.start.index,
+                end: fix.span.end.index,
+                replacement: &fix.replacement,
+            }),
+        })
+        .collect::<Vec<_>>();
+
+    serde_json::to_writer_pretty(&mut *output, &lints)?;
+    writeln!(output)
+}
@@ -0,0 +1,208 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use xpanda::{Builder, Position, UnsetOutcome, Warning};
+
+/// How much is printed to standard error besides errors, as set by `--quiet` and `--verbose`.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Verbosity {
+    Quiet,
+    #[default]
+    Normal,
+    Verbose,
+    /// Also prints the number of substitutions made on each line.
+    Trace,
+}
+
+/// Prints progress messages and warnings to standard error, depending on the verbosity.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Log {
+    verbosity: Verbosity,
+}
+
+impl Log {
+    pub const fn new(quiet: bool, verbose: u8) -> Self {
+        let verbosity = match (quiet, verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, 0) => Verbosity::Normal,
+            (false, 1) => Verbosity::Verbose,
+            (false, _) => Verbosity::Trace,
+        };
+
+        Self { verbosity }
+    }
+
+    pub fn is_quiet(self) -> bool {
+        self.verbosity == Verbosity::Quiet
+    }
+
+    /// Prints a message about the progress of the program, unless `--quiet` is set.
+    pub fn info(self, message: impl Display) {
+        self.print(Verbosity::Normal, format_args!("{message}"));
+    }
+
+    /// Prints a warning, unless `--quiet` is set.
+    pub fn warn(self, message: impl Display) {
+        self.print(Verbosity::Normal, format_args!("Warning: {message}"));
+    }
+
+    /// Prints a message only if `--verbose` is set.
+    pub fn verbose(self, message: impl Display) {
+        self.print(Verbosity::Verbose, format_args!("{message}"));
+    }
+
+    /// Creates a counter of the substitutions made on each line if `--verbose` is given twice.
+    pub fn substitutions(self) -> Option<Substitutions> {
+        (self.verbosity >= Verbosity::Trace).then(Substitutions::default)
+    }
+
+    /// Creates a collector of the warnings reported while expanding, as for `--warn-positional`.
+    pub fn warnings(self) -> Warnings {
+        Warnings {
+            log: self,
+            warnings: Arc::default(),
+        }
+    }
+
+    fn print(self, verbosity: Verbosity, message: impl Display) {
+        if self.verbosity >= verbosity {
+            let _result = writeln!(io::stderr().lock(), "{message}");
+        }
+    }
+}
+
+/// Counts the substitutions made on each line using the resolve and unset hooks of the
+/// library, which see every variable substituted, including those in default values.
+#[derive(Debug, Clone, Default)]
+pub struct Substitutions {
+    /// The offsets of the params substituted on each line, relative to the text being
+    /// expanded. A param may be both resolved and unset, as `${VAR:-default}` is if empty.
+    lines: Arc<Mutex<BTreeMap<usize, BTreeSet<usize>>>>,
+}
+
+impl Substitutions {
+    /// Registers listeners counting the substitutions made by the built instance.
+    pub fn listen(&self, builder: Builder) -> Builder {
+        let resolved = self.clone();
+        let unset = self.clone();
+
+        builder
+            .on_resolve(move |event| resolved.count(event.position))
+            .on_unset(move |event| {
+                if event.outcome != UnsetOutcome::Error {
+                    unset.count(event.position);
+                }
+            })
+    }
+
+    /// Prints the substitutions counted since the last flush, for text starting at the given
+    /// line of the input.
+    pub fn flush(&self, first_line: usize) {
+        let lines = std::mem::take(&mut *self.lock());
+        let mut stderr = io::stderr().lock();
+
+        for (line, params) in lines {
+            let line = line + first_line - 1;
+            let count = params.len();
+            let _result = writeln!(stderr, "Line {line}: {count} substitution(s)");
+        }
+    }
+
+    fn count(&self, position: Position) {
+        self.lock()
+            .entry(position.line)
+            .or_default()
+            .insert(position.index);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<usize, BTreeSet<usize>>> {
+        // A panic while holding the lock can't leave the counts in an inconsistent state
+        self.lines.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Collects the warnings reported by the library, which are positioned relative to the text
+/// being expanded, to print them once it's known where that text starts in the input.
+#[derive(Debug, Clone)]
+pub struct Warnings {
+    log: Log,
+    warnings: Arc<Mutex<Vec<Warning>>>,
+}
+
+impl Warnings {
+    /// Registers a listener collecting the warnings reported by the built instance.
+    pub fn listen(&self, builder: Builder) -> Builder {
+        let warnings = self.clone();
+        builder.on_warning(move |warning| warnings.lock().push(warning.clone()))
+    }
+
+    /// Prints the warnings collected since the last flush, for text starting at the given
+    /// position of the input.
+    pub fn flush(&self, start: Position) {
+        let warnings = std::mem::take(&mut *self.lock());
+
+        for warning in warnings {
+            let Position { line, col, .. } = warning.position;
+            let col = if line == 1 { col + start.col - 1 } else { col };
+            let line = line + start.line - 1;
+            self.log.warn(format_args!(
+                "{} at line {line}, column {col}",
+                warning.message
+            ));
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Warning>> {
+        // A panic while holding the lock can't leave the warnings in an inconsistent state
+        self.warnings.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The listeners printing what happened while expanding some text, once it's known where that
+/// text starts in the input.
+#[derive(Debug, Clone, Default)]
+pub struct Listeners {
+    substitutions: Option<Substitutions>,
+    warnings: Option<Warnings>,
+}
+
+impl Listeners {
+    pub const fn new(substitutions: Option<Substitutions>, warnings: Option<Warnings>) -> Self {
+        Self {
+            substitutions,
+            warnings,
+        }
+    }
+
+    /// Registers the listeners with the builder.
+    pub fn listen(&self, mut builder: Builder) -> Builder {
+        if let Some(substitutions) = &self.substitutions {
+            builder = substitutions.listen(builder);
+        }
+
+        if let Some(warnings) = &self.warnings {
+            builder = warnings.listen(builder);
+        }
+
+        builder
+    }
+
+    /// Prints what happened since the last flush, for text starting at the given position of
+    /// the input.
+    pub fn flush(&self, start: Position) {
+        if let Some(substitutions) = &self.substitutions {
+            substitutions.flush(start.line);
+        }
+
+        self.flush_warnings(start);
+    }
+
+    /// Prints only the warnings reported since the last flush, for text starting at the given
+    /// position of the input, such as a field within a line.
+    pub fn flush_warnings(&self, start: Position) {
+        if let Some(warnings) = &self.warnings {
+            warnings.flush(start);
+        }
+    }
+}
@@ -1,103 +1,1265 @@
+#![forbid(unsafe_code)]
 #![deny(clippy::all)]
 #![warn(clippy::pedantic, clippy::nursery)]
 
 mod args;
+mod config;
+mod context;
+mod diagnostic;
+mod doc;
+mod export;
+mod fields;
+mod generate;
+mod graph;
+mod lint;
+mod log;
+mod manifest;
 mod read;
+mod report;
+mod rotate;
+mod schema;
+mod timings;
+mod transaction;
+mod write;
 
-use crate::args::Args;
-use crate::read::{read_input_file, read_line, read_output_file, read_var_file};
-use clap::Parser;
-use std::io::{self, BufRead, Write};
+use crate::args::{
+    Args, Command, DocArgs, ExpandArgs, InputArgs, OutputArgs, RenderArgs, SchemaArgs,
+};
+use crate::config::apply_config;
+use crate::context::create_context;
+use crate::diagnostic::{Diagnostic, EXIT_CHANGED};
+use crate::doc::{find_templates, write_docs, VarDocs};
+use crate::export::{resolve_vars, write_exports};
+use crate::fields::Fields;
+use crate::generate::{write_completions, write_man_page};
+use crate::graph::{write_graph, Graph, GraphFormat};
+use crate::lint::{create_linter, write_json, LintFormat};
+use crate::log::{Listeners, Log};
+use crate::manifest::{read_manifest, Entry, Manifest};
+use crate::read::{
+    read_input_file, read_input_line, read_output_file, read_var_file, VarFileFormat,
+    DEFAULT_MAX_LINE_BYTES,
+};
+use crate::report::source_label;
+use crate::rotate::RotatingFile;
+use crate::schema::read_schema;
+use crate::timings::Timings;
+use crate::transaction::Transaction;
+use crate::write::{OutputWriter, TrailingNewline};
+use sha2::{Digest, Sha256};
+use similar::TextDiff;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
-use xpanda::Xpanda;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use std::{panic, thread};
+use xpanda::{
+    Builder, Document, EnvProvider, ErrorKind, FileProvider, LengthPolicy, Linter, Position,
+    Report, Severity, Source, Template, Xpanda,
+};
 
 fn main() -> ExitCode {
-    let mut stderr = io::stderr().lock();
     let Args {
+        command,
+        color,
+        quiet,
+        verbose,
+        expand,
+        version: _,
+    } = Args::parse_args();
+    let log = Log::new(quiet, verbose);
+    let result = match command {
+        Some(Command::Expand(args)) => run_expand(args, log),
+        Some(Command::Check { input, schema }) => {
+            run_check(&input, &schema, color.should_colorize())
+        },
+        Some(Command::Lint {
+            input,
+            lint,
+            format,
+        }) => run_lint(
+            &input,
+            &create_linter(&lint),
+            format,
+            color.should_colorize(),
+        ),
+        Some(Command::Fix { input, lint, write }) => {
+            run_fix(&input, &create_linter(&lint), write, log)
+        },
+        Some(Command::Vars {
+            input,
+            doc,
+            graph,
+            schema,
+        }) => run_vars(&input, &doc, graph, &schema, log),
+        Some(Command::Fmt { input, output }) => run_fmt(&input, &output),
+        Some(Command::Export {
+            var_files,
+            var_file_format,
+            no_unset,
+            output,
+        }) => run_export(&var_files, var_file_format, no_unset, &output, log),
+        Some(Command::Render { render }) => run_render(&render, color.should_colorize(), log),
+        Some(Command::Completions { shell }) => {
+            write_completions(shell, &mut io::stdout().lock());
+            Ok(())
+        },
+        Some(Command::Man) => write_man_page(&mut io::stdout().lock())
+            .map_err(|error| Diagnostic::new(format!("Failed to write man page: {error}"))),
+        None => run_expand(expand, log),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(diagnostic) => {
+            let _result = diagnostic.write(&mut io::stderr().lock(), color.should_colorize());
+            ExitCode::from(diagnostic.exit_code)
+        },
+    }
+}
+
+#[allow(clippy::too_many_lines)]
+fn run_expand(args: ExpandArgs, log: Log) -> Result<(), Diagnostic> {
+    let args = apply_config(args)?.apply_strict()?;
+    let provides_vars = args.provides_vars();
+    let ExpandArgs {
         no_unset,
+        no_empty,
+        strict,
         var_files,
+        var_file_format,
+        strict_var_files,
+        deny_unused,
         env_vars,
+        env_prefixes,
         named_vars,
+        aliases,
+        arg_vars,
         positional_vars,
-        input_file,
-        output_file,
-    } = Args::parse();
-    let has_user_provided_vars =
-        !var_files.is_empty() || !named_vars.is_empty() || !positional_vars.is_empty();
-    let mut builder = Xpanda::builder().no_unset(no_unset);
-
-    if env_vars == Some(true) || (env_vars.is_none() && !has_user_provided_vars) {
-        builder = builder.with_env_vars();
+        positional_names,
+        warn_positional,
+        positional_env_prefix,
+        file_root,
+        file_max_size,
+        trim_file_newlines,
+        length_mode,
+        #[cfg(feature = "locale")]
+        case_locale,
+        max_value_length,
+        truncate_long_values,
+        max_line_bytes,
+        delimiter,
+        args_per_doc,
+        fields,
+        field_delimiter,
+        context,
+        reproducible,
+        print_effective_precedence,
+        json,
+        config: _,
+        schema,
+        input,
+        output,
+    } = args;
+    let var_file_format = var_file_format.unwrap_or_default();
+    let positional_vars = [arg_vars, positional_vars].concat();
+    let schema_file = schema.schema_file.as_deref().map(read_schema).transpose()?;
+    let file_vars = var_files
+        .iter()
+        .map(|var_file| {
+            read_vars(
+                var_file,
+                var_file_format,
+                strict_var_files.unwrap_or_default(),
+                strict.unwrap_or_default(),
+                log,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let use_env_vars = env_vars == Some(true) || (env_vars.is_none() && !provides_vars);
+    let listeners = Listeners::new(log.substitutions(), warn_positional.then(|| log.warnings()));
+
+    log_sources(
+        log,
+        use_env_vars.then_some(env_prefixes.as_slice()),
+        &var_files,
+        &file_vars,
+        named_vars.len(),
+        &positional_vars,
+        positional_env_prefix.as_deref(),
+    );
+
+    let context = context
+        .then(|| create_context(input.input_file.as_deref(), reproducible))
+        .transpose()?;
+
+    // Per document variables need an instance per document
+    let build = |positional_vars: Vec<String>, first_line: usize| {
+        let mut builder = input_builder(&input)
+            .no_unset(no_unset.unwrap_or_default())
+            .no_empty(no_empty.unwrap_or_default())
+            .length_mode(length_mode.into())
+            .reproducible(reproducible)
+            .register_namespace("env", EnvProvider);
+
+        #[cfg(feature = "locale")]
+        if let Some(locale) = &case_locale {
+            builder = builder.case_locale(locale.clone());
+        }
+
+        if let Some(max) = max_value_length {
+            let policy = if truncate_long_values {
+                LengthPolicy::Truncate
+            } else {
+                LengthPolicy::Error
+            };
+            builder = builder.max_value_length(max, policy);
+        }
+
+        if let Some(schema) = &schema_file {
+            builder = builder.schema(schema.clone());
+        }
+
+        if let Some(context) = &context {
+            builder = builder.with_context(context.clone().first_line(first_line));
+        }
+
+        if let Some(root) = &file_root {
+            let provider = FileProvider::new(root)
+                .max_size(file_max_size)
+                .trim_trailing_newlines(trim_file_newlines);
+            builder = builder.register_namespace("file", provider);
+        }
+
+        if let Some(prefix) = &positional_env_prefix {
+            builder = builder.with_positional_env_vars(prefix);
+        }
+
+        builder = listeners.listen(builder);
+
+        if use_env_vars {
+            builder = builder.with_env_vars_matching(env_filter(&env_prefixes));
+        }
+
+        for (index, (var_file, vars)) in var_files.iter().zip(&file_vars).enumerate() {
+            let label = format!("file {} ({})", index + 1, var_file.display());
+            builder = builder.with_labeled_vars(label, vars.clone());
+        }
+
+        builder
+            .with_positional_vars(positional_vars)
+            .map_positional_names(positional_names.iter().cloned())
+            .with_labeled_vars("--var", named_vars.iter().cloned().collect())
+            .with_aliases(aliases.iter().cloned().collect())
+            .build()
+    };
+
+    if print_effective_precedence {
+        return write_sources(&build(positional_vars, 1), &read_all(&input)?);
+    }
+
+    if json {
+        let xpanda = build(positional_vars, 1);
+        let deny_unused = deny_unused.unwrap_or_default();
+        let result = write_report(&xpanda, &read_all(&input)?, deny_unused, &output);
+        listeners.flush(Position::default());
+        return result;
+    }
+
+    let text = deny_unused
+        .unwrap_or_default()
+        .then(|| read_all(&input))
+        .transpose()?;
+
+    if let Some(text) = &text {
+        check_unused(&build(positional_vars.clone(), 1), text)?;
     }
 
+    let expand_whole_input = spans_lines(&input) || schema_file.is_some() || context.is_some();
+    log.verbose(format_args!(
+        "Expanding {} to {}",
+        describe_path(input.input_file.as_deref(), "standard input"),
+        describe_path(output.output_file.as_deref(), "standard output")
+    ));
+    let mut input: Box<dyn BufRead> = match text {
+        Some(text) => Box::new(io::Cursor::new(text)),
+        None => open_input(&input)?,
+    };
+    let mut output = open_output(&output)?;
+
+    if let Some(delimiter) = &delimiter {
+        expand_documents(
+            build,
+            &mut input,
+            delimiter,
+            &positional_vars,
+            args_per_doc,
+            &mut output,
+            &listeners,
+        )?;
+    } else if !fields.is_empty() {
+        let fields = Fields::new(fields, field_delimiter);
+        let xpanda = build(positional_vars, 1);
+        expand_fields(
+            &xpanda,
+            &fields,
+            &mut input,
+            max_line_bytes,
+            &mut output,
+            &listeners,
+        )?;
+    } else if expand_whole_input {
+        let xpanda = build(positional_vars, 1);
+        expand_all(&xpanda, &mut input, &mut output, &listeners)?;
+    } else {
+        let xpanda = build(positional_vars, 1);
+        expand_stream(&xpanda, &mut input, max_line_bytes, &mut output, &listeners)?;
+    }
+
+    Ok(output.finish()?)
+}
+
+/// Writes each variable referenced by the text along with where its value comes from.
+fn write_sources(xpanda: &Xpanda, text: &str) -> Result<(), Diagnostic> {
+    let template = xpanda
+        .parse(text)
+        .map_err(|error| to_diagnostic(error, text))?;
+    let mut output = io::stdout().lock();
+
+    for (name, source) in xpanda.sources(&template) {
+        let source = source_label(source.as_ref());
+        write_output(&mut output, &format!("{name}\t{source}\n"))?;
+    }
+
+    Ok(())
+}
+
+/// Fails if any provided variable is never referenced by the text, listing all of them.
+fn check_unused(xpanda: &Xpanda, text: &str) -> Result<(), Diagnostic> {
+    let template = xpanda
+        .parse(text)
+        .map_err(|error| to_diagnostic(error, text))?;
+    let unused = xpanda.unused_vars(&template);
+
+    if unused.is_empty() {
+        return Ok(());
+    }
+
+    Err(Diagnostic::new(unused_message(&unused)))
+}
+
+fn unused_message(unused: &[(String, Source)]) -> String {
+    let vars = unused
+        .iter()
+        .map(|(name, source)| format!("'{name}' ({})", source_label(Some(source))))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!("Provided variable(s) {vars} are never referenced")
+}
+
+/// Writes the output of expanding the text along with the variables it references as JSON,
+/// exiting with the status code of the first error, if any, or failing if `deny_unused` is set
+/// and any provided variable is never referenced.
+fn write_report(
+    xpanda: &Xpanda,
+    text: &str,
+    deny_unused: bool,
+    output: &OutputArgs,
+) -> Result<(), Diagnostic> {
+    let report = match xpanda.parse(text) {
+        Ok(template) => xpanda.report(&template),
+        Err(error) => {
+            let mut report = Report::default();
+            report.errors.push(error);
+            report
+        },
+    };
+
+    let mut json = Vec::new();
+    report::write_json(&report, &mut json)
+        .map_err(|error| format!("Failed to write output: {error}"))?;
+    let mut output = open_output(output)?;
+    output.write(&String::from_utf8_lossy(&json))?;
+    output.finish()?;
+
+    if let Some(error) = report.errors.into_iter().next() {
+        return Err(to_diagnostic(error, text));
+    }
+
+    if deny_unused && !report.unused.is_empty() {
+        return Err(Diagnostic::new(unused_message(&report.unused)));
+    }
+
+    Ok(())
+}
+
+fn run_export(
+    var_files: &[PathBuf],
+    format: VarFileFormat,
+    no_unset: bool,
+    output: &OutputArgs,
+    log: Log,
+) -> Result<(), Diagnostic> {
+    let mut vars = HashMap::new();
+
     for var_file in var_files {
-        let file_vars = match read_var_file(&var_file) {
-            Ok(vars) => vars,
-            Err(error) => {
-                let _result = stderr.write_all(error.as_bytes());
-                return ExitCode::from(1);
+        vars.extend(read_vars(var_file, format, false, false, log)?);
+    }
+
+    let resolved = resolve_vars(&vars, || {
+        Xpanda::builder()
+            .no_unset(no_unset)
+            .register_namespace("env", EnvProvider)
+            .with_env_vars()
+    })?;
+    let mut output = open_output(output)?;
+    output.write(&write_exports(resolved)?)?;
+
+    Ok(output.finish()?)
+}
+
+fn run_render(args: &RenderArgs, colorize: bool, log: Log) -> Result<(), Diagnostic> {
+    let manifest = read_manifest(&args.manifest)?;
+    let transaction = Transaction::new();
+    let results = render_entries(&manifest, args.jobs, |entry| {
+        let timings = args
+            .timings
+            .then(|| Arc::new(Mutex::new(Timings::default())));
+        let bytes = render_entry(&manifest, entry, timings.as_ref(), log)?;
+        let rendered = Rendered {
+            bytes,
+            previous: read_output(&entry.output, args.force)?,
+            timings: timings.map(|timings| *timings.lock().unwrap_or_else(PoisonError::into_inner)),
+        };
+
+        if args.dry_run || (rendered.status() == OutputStatus::Unchanged && !args.force) {
+            // Left untouched such that its modification time is preserved
+        } else if args.atomic {
+            transaction.stage(&entry.output, &rendered.bytes)?;
+        } else {
+            write_entry(entry, &rendered.bytes)?;
+        }
+
+        Ok(rendered)
+    });
+    let mut stdout = io::stdout().lock();
+    let mut stderr = io::stderr().lock();
+    let mut failures = Vec::new();
+    let mut changes = 0;
+    let mut hashes = BTreeMap::new();
+
+    for (entry, result) in manifest.templates.iter().zip(results) {
+        let template = entry.template.display();
+        let output = entry.output.display();
+        let timings = result.as_ref().ok().and_then(|rendered| rendered.timings);
+
+        match result {
+            Ok(rendered) if args.dry_run => {
+                if rendered.status() == OutputStatus::Unchanged {
+                    log.info(format_args!("'{output}' is up to date"));
+                } else {
+                    write_diff(&entry.output, &rendered, &mut stdout)?;
+                    log.info(format_args!("Would change '{output}'"));
+                    changes += 1;
+                }
+            },
+            Ok(rendered) => {
+                let status = rendered.status();
+                log.info(format_args!(
+                    "Rendered '{template}' to '{output}' ({status})"
+                ));
+                hashes.insert(
+                    &entry.output,
+                    format!("{:x}", Sha256::digest(&rendered.bytes)),
+                );
+            },
+            Err(diagnostic) => {
+                let _result = writeln!(stderr, "Failed to render '{template}':");
+                let _result = diagnostic.write_line(&mut stderr, colorize);
+                failures.push(diagnostic);
             },
+        }
+
+        if let Some(timings) = timings.filter(|_| !log.is_quiet()) {
+            let threshold = Duration::from_millis(args.slow_threshold);
+            let _result = timings.write(&template.to_string(), threshold, &mut stderr, colorize);
+        }
+    }
+
+    let total = manifest.templates.len();
+
+    if let Some(first) = failures.first() {
+        let suffix = if args.atomic {
+            ", no output files were written"
+        } else {
+            ""
         };
 
-        builder = builder.with_named_vars(file_vars);
+        return Err(Diagnostic {
+            exit_code: first.exit_code,
+            ..Diagnostic::new(format!(
+                "{} of {total} template(s) failed to render{suffix}",
+                failures.len()
+            ))
+        });
+    }
+
+    if args.dry_run {
+        if changes > 0 {
+            return Err(Diagnostic {
+                exit_code: EXIT_CHANGED,
+                is_warning: true,
+                ..Diagnostic::new(format!("{changes} of {total} output(s) would change"))
+            });
+        }
+
+        log.info(format_args!("All {total} output(s) are up to date"));
+        return Ok(());
+    }
+
+    transaction.commit()?;
+
+    if let Some(hash_file) = &manifest.hash_file {
+        let base = args.manifest.parent().unwrap_or_else(|| Path::new(""));
+        write_hashes(hash_file, base, &hashes)?;
+    }
+
+    log.info(format_args!("Rendered {total} template(s)"));
+
+    Ok(())
+}
+
+/// Renders every template of the manifest using up to `jobs` threads, returning the result of
+/// each in the order they are listed in.
+fn render_entries<T: Send>(
+    manifest: &Manifest,
+    jobs: u64,
+    render: impl Fn(&Entry) -> Result<T, Diagnostic> + Sync,
+) -> Vec<Result<T, Diagnostic>> {
+    let jobs = usize::try_from(jobs)
+        .unwrap_or(usize::MAX)
+        .min(manifest.templates.len());
+
+    if jobs <= 1 {
+        return manifest.templates.iter().map(&render).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let mut results = thread::scope(|scope| {
+        let workers = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(entry) = manifest.templates.get(index) else {
+                            break results;
+                        };
+
+                        results.push((index, render(entry)));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|error| panic::resume_unwind(error))
+            })
+            .collect::<Vec<_>>()
+    });
+
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// The rendered output of a template of the manifest.
+struct Rendered {
+    bytes: Vec<u8>,
+    previous: Previous,
+    /// How long rendering took, if requested by `--timings`.
+    timings: Option<Timings>,
+}
+
+impl Rendered {
+    fn status(&self) -> OutputStatus {
+        match &self.previous {
+            Previous::Missing => OutputStatus::Created,
+            Previous::Contents(previous) if *previous == self.bytes => OutputStatus::Unchanged,
+            Previous::Contents(_) | Previous::Existing => OutputStatus::Updated,
+        }
+    }
+}
+
+/// The output file of a template of the manifest before rendering.
+enum Previous {
+    /// The output file didn't exist.
+    Missing,
+    /// The output file existed, but its contents weren't read as it's written regardless.
+    Existing,
+    /// The output file existed with the given contents.
+    Contents(Vec<u8>),
+}
+
+/// How an output file is changed by rendering its template.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum OutputStatus {
+    Created,
+    Updated,
+    Unchanged,
+}
+
+impl Display for OutputStatus {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Created => write!(f, "created"),
+            Self::Updated => write!(f, "updated"),
+            Self::Unchanged => write!(f, "unchanged"),
+        }
+    }
+}
+
+/// Renders a single template of the manifest in memory, adding up the time spent in each phase
+/// if given timings.
+fn render_entry(
+    manifest: &Manifest,
+    entry: &Entry,
+    timings: Option<&Arc<Mutex<Timings>>>,
+    log: Log,
+) -> Result<Vec<u8>, Diagnostic> {
+    let has_user_provided_vars = !manifest.var_files.is_empty()
+        || !manifest.vars.is_empty()
+        || !entry.var_files.is_empty()
+        || !entry.vars.is_empty();
+    let mut builder = Xpanda::builder()
+        .no_unset(manifest.no_unset)
+        .quoting(manifest.quoting)
+        .lenient_identifiers(manifest.lenient_identifiers)
+        .powershell_env(manifest.powershell_env)
+        .front_matter(manifest.front_matter)
+        .reproducible(manifest.reproducible)
+        .register_namespace("env", EnvProvider);
+
+    if let Some(schema_file) = &manifest.schema {
+        builder = builder.schema(read_schema(schema_file)?);
+    }
+
+    if let Some(timings) = timings {
+        let timings = Arc::clone(timings);
+        builder = builder.on_timing(move |timing| {
+            timings
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .add(timing);
+        });
+    }
+
+    if manifest.env_vars == Some(true) || (manifest.env_vars.is_none() && !has_user_provided_vars) {
+        builder = builder.with_env_vars();
+    }
+
+    for var_file in manifest.var_files.iter().chain(&entry.var_files) {
+        let vars = read_vars(
+            var_file,
+            manifest.var_file_format,
+            manifest.strict_var_files,
+            false,
+            log,
+        )?;
+        log.verbose(format_args!(
+            "Loaded {} variable(s) from '{}'",
+            vars.len(),
+            var_file.display()
+        ));
+        builder = builder.with_named_vars(vars);
     }
 
     let xpanda = builder
-        .with_positional_vars(positional_vars)
-        .with_named_vars(named_vars.into_iter().collect())
+        .with_named_vars(manifest.vars.clone())
+        .with_named_vars(entry.vars.clone())
+        .with_aliases(manifest.aliases.clone())
         .build();
+    let mut input = read_input_file(&entry.template)?;
+    let mut bytes = Vec::new();
+    let mut output = OutputWriter::new(&mut bytes, TrailingNewline::Keep);
 
-    let mut input: Box<dyn BufRead> = if let Some(path) = input_file {
-        match read_input_file(&path) {
-            Ok(file) => Box::new(file),
-            Err(error) => {
-                let _result = stderr.write_all(error.as_bytes());
-                return ExitCode::from(1);
-            },
-        }
+    if manifest.front_matter || manifest.schema.is_some() {
+        expand_all(&xpanda, &mut input, &mut output, &Listeners::default())?;
     } else {
-        Box::new(io::stdin().lock())
-    };
+        expand_stream(
+            &xpanda,
+            &mut input,
+            DEFAULT_MAX_LINE_BYTES,
+            &mut output,
+            &Listeners::default(),
+        )?;
+    }
 
-    let mut output: Box<dyn Write> = if let Some(path) = output_file {
-        match read_output_file(&path) {
-            Ok(file) => Box::new(file),
-            Err(error) => {
-                let _result = stderr.write_all(error.as_bytes());
-                return ExitCode::from(1);
-            },
-        }
+    output.finish()?;
+
+    Ok(bytes)
+}
+
+/// Writes the rendered output of a template of the manifest, creating its directory if needed.
+fn write_entry(entry: &Entry, bytes: &[u8]) -> Result<(), Diagnostic> {
+    if let Some(parent) = entry.output.parent() {
+        fs::create_dir_all(parent).map_err(|error| {
+            format!(
+                "Failed to create directory '{}': {}",
+                parent.display(),
+                error
+            )
+        })?;
+    }
+
+    fs::write(&entry.output, bytes).map_err(|error| {
+        let path = entry.output.display();
+        Diagnostic::new(format!("Failed to write output file '{path}': {error}"))
+    })
+}
+
+/// Reads the current contents of an output file. With `force`, only whether it exists is
+/// checked, since the output file is written regardless of its contents.
+fn read_output(path: &Path, force: bool) -> Result<Previous, Diagnostic> {
+    let result = if force {
+        path.try_exists().map(|exists| {
+            if exists {
+                Previous::Existing
+            } else {
+                Previous::Missing
+            }
+        })
     } else {
-        Box::new(io::stdout().lock())
+        match fs::read(path) {
+            Ok(contents) => Ok(Previous::Contents(contents)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Previous::Missing),
+            Err(error) => Err(error),
+        }
+    };
+
+    result.map_err(|error| {
+        let path = path.display();
+        Diagnostic::new(format!("Failed to read output file '{path}': {error}"))
+    })
+}
+
+/// Writes a unified diff between the previous contents of the output file and the rendered
+/// output. A missing output file is diffed as empty.
+fn write_diff(path: &Path, rendered: &Rendered, output: &mut impl Write) -> Result<(), Diagnostic> {
+    let old = match &rendered.previous {
+        Previous::Contents(contents) => String::from_utf8_lossy(contents),
+        Previous::Missing | Previous::Existing => String::from_utf8_lossy(&[]),
+    };
+    let new = String::from_utf8_lossy(&rendered.bytes);
+    let name = path.display().to_string();
+    let old_name = match rendered.previous {
+        Previous::Missing => "/dev/null",
+        Previous::Existing | Previous::Contents(_) => name.as_str(),
     };
 
+    write!(
+        output,
+        "{}",
+        TextDiff::from_lines(&old, &new)
+            .unified_diff()
+            .missing_newline_hint(true)
+            .header(old_name, &name)
+    )
+    .map_err(|error| Diagnostic::new(format!("Failed to write diff: {error}")))
+}
+
+/// Writes the hashes of the outputs in the format of `sha256sum`, with paths relative to the
+/// given base directory.
+fn write_hashes(
+    hash_file: &Path,
+    base: &Path,
+    hashes: &BTreeMap<&PathBuf, String>,
+) -> Result<(), Diagnostic> {
+    let write_error = |error| {
+        let path = hash_file.display();
+        Diagnostic::new(format!("Failed to write hash file '{path}': {error}"))
+    };
+    let mut file = fs::File::create(hash_file).map_err(write_error)?;
+
+    for (output, hash) in hashes {
+        let path = output.strip_prefix(base).unwrap_or(output);
+        writeln!(file, "{hash}  {}", path.display()).map_err(write_error)?;
+    }
+
+    Ok(())
+}
+
+fn run_check(input: &InputArgs, schema: &SchemaArgs, colorize: bool) -> Result<(), Diagnostic> {
+    let text = read_all(input)?;
+
+    let Some(schema_file) = &schema.schema_file else {
+        parse_template(input, &text)?;
+        return Ok(());
+    };
+
+    let xpanda = input_builder(input)
+        .schema(read_schema(schema_file)?)
+        .with_env_vars()
+        .build();
+    let template = xpanda
+        .parse(&text)
+        .map_err(|error| to_diagnostic(error, &text))?;
+    let violations = xpanda.validate(&template);
+
+    let Some(first) = violations.first() else {
+        return Ok(());
+    };
+
+    let exit_code = to_diagnostic(first.clone(), &text).exit_code;
+    let mut stderr = io::stderr().lock();
+
+    for violation in &violations {
+        let _result = to_diagnostic(violation.clone(), &text).write_line(&mut stderr, colorize);
+    }
+
+    Err(Diagnostic {
+        exit_code,
+        ..Diagnostic::new(format!("{} schema violation(s) found", violations.len()))
+    })
+}
+
+fn run_lint(
+    input: &InputArgs,
+    linter: &Linter,
+    format: LintFormat,
+    colorize: bool,
+) -> Result<(), Diagnostic> {
+    let text = read_all(input)?;
+    let lints = linter
+        .lint(&input_builder(input).build(), &text)
+        .map_err(|error| to_diagnostic(error, &text))?;
+
+    match format {
+        LintFormat::Text => {
+            let mut stderr = io::stderr().lock();
+
+            for lint in &lints {
+                let _result = lint::to_diagnostic(lint, &text).write_line(&mut stderr, colorize);
+            }
+        },
+        LintFormat::Json => write_json(&lints, &mut io::stdout().lock())
+            .map_err(|error| Diagnostic::new(format!("Failed to write output: {error}")))?,
+    }
+
+    let errors = lints
+        .iter()
+        .filter(|lint| lint.severity == Severity::Error)
+        .count();
+
+    if errors > 0 {
+        return Err(Diagnostic::new(format!("{errors} lint error(s) found")));
+    }
+
+    Ok(())
+}
+
+fn run_fix(input: &InputArgs, linter: &Linter, write: bool, log: Log) -> Result<(), Diagnostic> {
+    let text = read_all(input)?;
+    let (fixed, count) = linter
+        .fix(&input_builder(input).build(), &text)
+        .map_err(|error| to_diagnostic(error, &text))?;
+
+    match &input.input_file {
+        Some(path) if write => fs::write(path, fixed).map_err(|error| {
+            format!("Failed to write input file '{}': {}", path.display(), error)
+        })?,
+        _ => write_output(&mut io::stdout().lock(), &fixed)?,
+    }
+
+    log.info(format_args!("Applied {count} fix(es)"));
+
+    Ok(())
+}
+
+fn run_vars(
+    input: &InputArgs,
+    doc: &DocArgs,
+    graph: Option<GraphFormat>,
+    schema: &SchemaArgs,
+    log: Log,
+) -> Result<(), Diagnostic> {
+    if let Some(dir) = &doc.doc_dir {
+        return run_doc(input, doc, dir, schema, log);
+    }
+
+    let text = read_all(input)?;
+    let template = parse_template(input, &text)?;
+    let mut output = io::stdout().lock();
+
+    if let Some(format) = graph {
+        return write_graph(&Graph::new(&template), format, &mut output)
+            .map_err(|error| Diagnostic::new(format!("Failed to write output: {error}")));
+    }
+
+    for variable in template.variables() {
+        write_output(&mut output, &format!("{variable}\n"))?;
+    }
+
+    Ok(())
+}
+
+/// Documents the variables of all templates in the directory.
+fn run_doc(
+    input: &InputArgs,
+    doc: &DocArgs,
+    dir: &Path,
+    schema: &SchemaArgs,
+    log: Log,
+) -> Result<(), Diagnostic> {
+    let schema = schema.schema_file.as_deref().map(read_schema).transpose()?;
+    let mut docs = VarDocs::default();
+
+    for path in find_templates(dir)? {
+        let file = path
+            .strip_prefix(dir)
+            .unwrap_or(&path)
+            .display()
+            .to_string();
+        let Ok(text) = fs::read_to_string(&path) else {
+            log.warn(format_args!(
+                "Skipping '{file}', which is not readable as UTF-8 text"
+            ));
+            continue;
+        };
+        let template = parse_template(input, &text).map_err(|diagnostic| Diagnostic {
+            message: format!("{} (in '{file}')", diagnostic.message),
+            ..diagnostic
+        })?;
+
+        docs.add(&file, &template);
+    }
+
+    write_docs(
+        &docs.into_docs(schema.as_ref()),
+        doc.doc_format,
+        &mut io::stdout().lock(),
+    )
+    .map_err(|error| Diagnostic::new(format!("Failed to write output: {error}")))
+}
+
+fn run_fmt(input: &InputArgs, output: &OutputArgs) -> Result<(), Diagnostic> {
+    let text = read_all(input)?;
+    let template = parse_template(input, &text)?;
+    let mut output = open_output(output)?;
+    output.write(&template.to_string())?;
+
+    Ok(output.finish()?)
+}
+
+/// Matches names of environment variables starting with any of the given prefixes, or all
+/// names if there are none.
+fn env_filter(prefixes: &[String]) -> impl Fn(&str) -> bool + '_ {
+    move |name| prefixes.is_empty() || prefixes.iter().any(|prefix| name.starts_with(prefix))
+}
+
+/// Whether the syntax options of the input allow syntax spanning several lines, in which case
+/// the input can't be expanded line by line.
+fn spans_lines(input: &InputArgs) -> bool {
+    [input.front_matter, input.raw_blocks, input.conditionals].contains(&Some(true))
+}
+
+/// Creates a builder with the syntax options of the input.
+fn input_builder(input: &InputArgs) -> Builder {
+    let builder = Xpanda::builder();
+    #[cfg(feature = "expr")]
+    let builder = builder.expressions(input.expressions);
+
+    builder
+        .quoting(input.quoting.unwrap_or_default())
+        .lenient_identifiers(input.lenient_identifiers.unwrap_or_default())
+        .powershell_env(input.powershell_env.unwrap_or_default())
+        .front_matter(input.front_matter.unwrap_or_default())
+        .safe_mode(input.safe_mode.unwrap_or_default())
+        .raw_blocks(input.raw_blocks.unwrap_or_default())
+        .comments(input.comments.unwrap_or_default())
+        .conditionals(input.conditionals.unwrap_or_default())
+        .dynamic_names(input.dynamic_names.unwrap_or_default())
+        .ansi_c_quotes(
+            input
+                .ansi_c_quotes
+                .map_or(xpanda::AnsiCQuotes::Error, Into::into),
+        )
+}
+
+fn parse_template<'a>(input: &InputArgs, text: &'a str) -> Result<Template<'a>, Diagnostic> {
+    input_builder(input)
+        .build()
+        .parse(text)
+        .map_err(|error| to_diagnostic(error, text))
+}
+
+/// Expands the input in chunks of bounded size, writing each chunk to the output as soon as it's
+/// been expanded, such that the input is never held in memory as a whole.
+fn expand_stream(
+    xpanda: &Xpanda,
+    input: &mut impl BufRead,
+    max_bytes: u64,
+    output: &mut OutputWriter<impl Write>,
+    listeners: &Listeners,
+) -> Result<(), Diagnostic> {
+    let buffer_bytes = usize::try_from(max_bytes).unwrap_or(usize::MAX);
+    let mut chunks = xpanda.expand_stream(input).buffer_bytes(buffer_bytes);
+
+    while let Some(result) = chunks.next() {
+        let text = result.map_err(|mut error| {
+            if error.kind == ErrorKind::TooLong {
+                error.message.push_str(", see --max-line-bytes");
+            }
+
+            // The source line can only be shown if the chunk contains it from its start
+            let start = chunks.chunk_start();
+            let source = chunks
+                .chunk()
+                .lines()
+                .nth(error.line.saturating_sub(start.line))
+                .filter(|line| {
+                    !matches!(error.kind, ErrorKind::Io | ErrorKind::TooLong)
+                        && (start.col == 1 || error.line > start.line)
+                        && !line.is_empty()
+                });
+            let line = error.line;
+            Diagnostic::from_error(error, line, source)
+        })?;
+
+        listeners.flush(chunks.chunk_start());
+
+        output.write(&text)?;
+    }
+
+    Ok(())
+}
+
+/// Expands the selected fields of each line of the input, writing each line to the output as
+/// soon as it's been expanded.
+fn expand_fields(
+    xpanda: &Xpanda,
+    fields: &Fields,
+    input: &mut impl BufRead,
+    max_line_bytes: u64,
+    output: &mut OutputWriter<impl Write>,
+    listeners: &Listeners,
+) -> Result<(), Diagnostic> {
     let mut line_number = 0;
-    while let Some(line) = read_line(&mut input) {
-        line_number += 1;
 
-        let line = match line {
-            Ok(line) => line,
-            Err(error) => {
-                let _result = stderr.write_all(error.as_bytes());
-                return ExitCode::from(1);
-            },
+    loop {
+        line_number += 1;
+        let line = read_input_line(input, max_line_bytes)
+            .map_err(|message| Diagnostic::at(message, line_number, 1, None))?;
+        let Some(line) = line else {
+            return Ok(());
         };
+        let text = fields
+            .expand_line(xpanda, &line, |col| {
+                listeners.flush_warnings(Position::new(0, line_number, col));
+            })
+            .map_err(|error| Diagnostic::from_error(error, line_number, Some(&line)))?;
 
-        let text = match xpanda.expand(&line) {
-            Ok(text) => text,
-            Err(error) => {
-                let _result = stderr.write_all(
-                    format!("{}:{} {}", line_number, error.col, error.message).as_bytes(),
-                );
-                return ExitCode::from(1);
+        listeners.flush(Position::new(0, line_number, 1));
+
+        output.write(&text)?;
+    }
+}
+
+/// Expands the whole input at once, which is needed to process its front matter before any
+/// output is written.
+fn expand_all(
+    xpanda: &Xpanda,
+    input: &mut impl BufRead,
+    output: &mut OutputWriter<impl Write>,
+    listeners: &Listeners,
+) -> Result<(), Diagnostic> {
+    let mut text = String::new();
+    input
+        .read_to_string(&mut text)
+        .map_err(|error| format!("Failed to read input: {error}"))?;
+
+    let result = xpanda
+        .expand(&text)
+        .map_err(|error| to_diagnostic(error, &text))?;
+
+    listeners.flush(Position::default());
+
+    Ok(output.write(&result)?)
+}
+
+/// Expands each document of the input independently, using an instance built with the
+/// positional variables and the first line of that document.
+fn expand_documents(
+    build: impl Fn(Vec<String>, usize) -> Xpanda,
+    input: &mut impl BufRead,
+    delimiter: &str,
+    positional_vars: &[String],
+    args_per_doc: Option<usize>,
+    output: &mut OutputWriter<impl Write>,
+    listeners: &Listeners,
+) -> Result<(), Diagnostic> {
+    let mut text = String::new();
+    input
+        .read_to_string(&mut text)
+        .map_err(|error| format!("Failed to read input: {error}"))?;
+
+    for (index, document) in Document::split(&text, delimiter).iter().enumerate() {
+        let vars = args_per_doc.map_or_else(
+            || positional_vars.to_vec(),
+            |count| {
+                positional_vars
+                    .iter()
+                    .skip(index.saturating_mul(count))
+                    .take(count)
+                    .cloned()
+                    .collect()
             },
-        };
+        );
+        let result = build(vars, document.start.line)
+            .expand(document.text)
+            .map_err(|error| to_diagnostic(document.locate(error), &text))?;
 
-        if let Err(error) = output.write_all(text.as_bytes()) {
-            let _result = stderr.write_all(format!("Failed to write output: {}", error).as_bytes());
-            return ExitCode::from(1);
-        }
+        listeners.flush(document.start);
+
+        output.write(&result)?;
+        output.write(document.delimiter)?;
     }
 
-    ExitCode::SUCCESS
+    Ok(())
+}
+
+/// Reads a var file, printing any warnings about its contents to standard error, or failing
+/// on the first warning if `warnings_as_errors` is set.
+fn read_vars(
+    path: &Path,
+    format: VarFileFormat,
+    strict: bool,
+    warnings_as_errors: bool,
+    log: Log,
+) -> Result<HashMap<String, String>, String> {
+    let var_file = read_var_file(path, format, strict)?;
+
+    if let Some(warning) = var_file.warnings.first().filter(|_| warnings_as_errors) {
+        return Err(warning.clone());
+    }
+
+    for warning in var_file.warnings {
+        log.warn(warning);
+    }
+
+    Ok(var_file.vars)
+}
+
+/// Prints which variable sources were loaded, along with how many variables each contributed,
+/// if `--verbose` is set. Environment variables are only counted if `env_prefixes` is given.
+fn log_sources(
+    log: Log,
+    env_prefixes: Option<&[String]>,
+    var_files: &[PathBuf],
+    file_vars: &[HashMap<String, String>],
+    named_vars: usize,
+    positional_vars: &[String],
+    positional_env_prefix: Option<&str>,
+) {
+    if let Some(prefixes) = env_prefixes {
+        let filter = env_filter(prefixes);
+        let count = env::vars_os()
+            .filter(|(name, _)| filter(&name.to_string_lossy()))
+            .count();
+        log.verbose(format_args!(
+            "Loaded {count} variable(s) from the environment"
+        ));
+    }
+
+    for (var_file, vars) in var_files.iter().zip(file_vars) {
+        log.verbose(format_args!(
+            "Loaded {} variable(s) from '{}'",
+            vars.len(),
+            var_file.display()
+        ));
+    }
+
+    if named_vars > 0 {
+        log.verbose(format_args!("Loaded {named_vars} variable(s) from --var"));
+    }
+
+    if !positional_vars.is_empty() {
+        log.verbose(format_args!(
+            "Loaded {} positional variable(s) from the arguments",
+            positional_vars.len()
+        ));
+    }
+
+    if let Some(prefix) = positional_env_prefix {
+        let count = (1..usize::MAX)
+            .take_while(|index| env::var_os(format!("{prefix}{index}")).is_some())
+            .count();
+        log.verbose(format_args!(
+            "Loaded {count} positional variable(s) from the environment prefixed by '{prefix}'"
+        ));
+    }
+}
+
+/// Describes a file given as input or output, or the standard stream used instead.
+fn describe_path(path: Option<&Path>, stream: &str) -> String {
+    path.map_or_else(
+        || stream.to_string(),
+        |path| format!("'{}'", path.display()),
+    )
+}
+
+fn open_input(input: &InputArgs) -> Result<Box<dyn BufRead>, String> {
+    if let Some(path) = &input.input_file {
+        Ok(Box::new(read_input_file(path)?))
+    } else {
+        Ok(Box::new(io::stdin().lock()))
+    }
+}
+
+fn open_output(output: &OutputArgs) -> Result<OutputWriter<Box<dyn Write>>, String> {
+    let rotation = output.rotation();
+    let writer: Box<dyn Write> = if let Some(path) = &output.output_file {
+        if rotation.is_enabled() {
+            Box::new(RotatingFile::open(path, rotation)?)
+        } else {
+            Box::new(read_output_file(path)?)
+        }
+    } else {
+        Box::new(io::stdout().lock())
+    };
+
+    Ok(OutputWriter::new(writer, output.trailing_newline()))
+}
+
+fn read_all(input: &InputArgs) -> Result<String, String> {
+    let mut text = String::new();
+    open_input(input)?
+        .read_to_string(&mut text)
+        .map_err(|error| format!("Failed to read input: {error}"))?;
+
+    Ok(text)
+}
+
+fn write_output(output: &mut impl Write, text: &str) -> Result<(), String> {
+    output
+        .write_all(text.as_bytes())
+        .map_err(|error| format!("Failed to write output: {error}"))
+}
+
+/// Creates a diagnostic for an error in the given multiline text.
+fn to_diagnostic(error: xpanda::Error, text: &str) -> Diagnostic {
+    let line = error.line;
+    let source = text.lines().nth(line.saturating_sub(1));
+    Diagnostic::from_error(error, line, source)
 }
@@ -0,0 +1,87 @@
+use crate::read::VarFileFormat;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A list of templates to render in a single run, along with the variables to render them
+/// with. Variables declared at the top level are shared by all templates.
+///
+/// Example:
+/// ```toml
+/// no_unset = true
+/// var_files = ["common.env"]
+///
+/// [[templates]]
+/// template = "app.conf.tmpl"
+/// output = "out/app.conf"
+/// var_files = ["app.env"]
+/// vars = { PORT = "8080" }
+/// ```
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(default)]
+    pub no_unset: bool,
+    #[serde(default)]
+    pub strict_var_files: bool,
+    pub env_vars: Option<bool>,
+    #[serde(default)]
+    pub var_files: Vec<PathBuf>,
+    #[serde(default)]
+    pub var_file_format: VarFileFormat,
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    #[serde(default)]
+    pub quoting: bool,
+    #[serde(default)]
+    pub lenient_identifiers: bool,
+    #[serde(default)]
+    pub powershell_env: bool,
+    #[serde(default)]
+    pub front_matter: bool,
+    pub schema: Option<PathBuf>,
+    #[serde(default)]
+    pub reproducible: bool,
+    pub hash_file: Option<PathBuf>,
+    #[serde(default)]
+    pub templates: Vec<Entry>,
+}
+
+/// A single template to render.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    pub template: PathBuf,
+    pub output: PathBuf,
+    #[serde(default)]
+    pub var_files: Vec<PathBuf>,
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+}
+
+/// Reads a manifest file. All relative paths within it are resolved relative to the directory
+/// containing the manifest.
+pub fn read_manifest(path: &Path) -> Result<Manifest, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|error| format!("Failed to open manifest '{}': {}", path.display(), error))?;
+    let mut manifest: Manifest = toml::from_str(&content)
+        .map_err(|error| format!("Failed to parse manifest '{}': {}", path.display(), error))?;
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    let resolve = |path: &mut PathBuf| *path = base.join(&*path);
+
+    manifest.var_files.iter_mut().for_each(resolve);
+    manifest.schema.iter_mut().for_each(resolve);
+    manifest.hash_file.iter_mut().for_each(resolve);
+
+    for entry in &mut manifest.templates {
+        resolve(&mut entry.template);
+        resolve(&mut entry.output);
+        entry.var_files.iter_mut().for_each(resolve);
+    }
+
+    Ok(manifest)
+}
@@ -1,10 +1,16 @@
 #![allow(clippy::module_name_repetitions)]
 
+use clap::ValueEnum;
+use serde::Deserialize;
 use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
 use std::path::Path;
 
+/// The default maximum number of bytes of input held in memory at once, such as of a line of
+/// input including its line ending. Longer lines are expanded in parts where possible.
+pub const DEFAULT_MAX_LINE_BYTES: u64 = 16 * 1024 * 1024;
+
 /// Tries to read a string in key=value format, returning the key and value as a tuple
 /// (in that order).
 pub fn read_named_arg(arg: &str) -> Result<(String, String), String> {
@@ -13,28 +19,223 @@ pub fn read_named_arg(arg: &str) -> Result<(String, String), String> {
         .ok_or_else(|| String::from("'=' character missing in key value pair"))
 }
 
-/// Reads a file of key=value pairs, ignoring empty lines.
-pub fn read_var_file(path: &Path) -> Result<HashMap<String, String>, String> {
-    let mut map = HashMap::new();
-    let file = File::open(path)
-        .map(BufReader::new)
+/// The format of a var file.
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum VarFileFormat {
+    /// Lines of `KEY=value` pairs.
+    #[default]
+    Env,
+    /// The output of the Windows `SET` command. Lines are split at the first `=`, and lines
+    /// starting with `=` are ignored.
+    Set,
+    /// A Windows registry export (`.reg` file) with lines such as `"KEY"="value"`. Section
+    /// headers are ignored, as are values other than strings, with a warning.
+    Reg,
+}
+
+/// The variables read from a var file, along with any warnings about its contents.
+#[derive(Debug, Default)]
+pub struct VarFile {
+    pub vars: HashMap<String, String>,
+    pub warnings: Vec<String>,
+}
+
+/// Reads a file of key=value pairs in the given format, ignoring empty lines. The file may be
+/// encoded as UTF-8, or as UTF-16 if it starts with a byte order mark. If a key appears more
+/// than once, the last value wins. With `strict` set, keys appearing more than once with
+/// different values are instead treated as an error, while keys repeated with the same value
+/// yield a warning.
+pub fn read_var_file(path: &Path, format: VarFileFormat, strict: bool) -> Result<VarFile, String> {
+    let mut var_file = VarFile::default();
+    let mut line_numbers = HashMap::new();
+    let bytes = fs::read(path)
         .map_err(|error| format!("Failed to open var file '{}': {}", path.display(), error))?;
+    let text = decode_text(bytes).ok_or_else(|| {
+        format!(
+            "Failed to read var file '{}': stream did not contain valid UTF-8 or UTF-16",
+            path.display()
+        )
+    })?;
+
+    for (line_number, line) in logical_lines(&text, format) {
+        let entry = match format {
+            VarFileFormat::Env => parse_env_line(&line),
+            VarFileFormat::Set => parse_set_line(&line),
+            VarFileFormat::Reg => parse_reg_line(&line),
+        }
+        .map_err(|error| format!("Failed to parse named arg: {error}"))?;
+
+        let (key, value) = match entry {
+            Entry::Var(key, value) => (key, value),
+            Entry::Ignored => continue,
+            Entry::Unsupported(key) => {
+                var_file.warnings.push(format!(
+                    "Ignoring key '{key}' in var file '{}' on line {line_number}, only string \
+                     values are supported",
+                    path.display()
+                ));
+                continue;
+            },
+        };
+
+        if strict {
+            if let Some(previous) = line_numbers.insert(key.clone(), line_number) {
+                let message = format!(
+                    "Duplicate key '{key}' in var file '{}' on lines {previous} and {line_number}",
+                    path.display()
+                );
+
+                if var_file.vars.get(&key) == Some(&value) {
+                    var_file.warnings.push(message);
+                } else {
+                    return Err(message + " with different values");
+                }
+            }
+        }
+
+        var_file.vars.insert(key, value);
+    }
+
+    Ok(var_file)
+}
+
+/// A single line of a var file.
+enum Entry {
+    Var(String, String),
+    Ignored,
+    /// A key with a value that can't be used as a variable, such as a number in a `.reg` file.
+    Unsupported(String),
+}
+
+/// Decodes text as UTF-8, or as UTF-16 if it starts with a byte order mark. A UTF-8 byte order
+/// mark is removed.
+fn decode_text(bytes: Vec<u8>) -> Option<String> {
+    match bytes.as_slice() {
+        [0xFF, 0xFE, rest @ ..] => decode_utf16(rest, u16::from_le_bytes),
+        [0xFE, 0xFF, rest @ ..] => decode_utf16(rest, u16::from_be_bytes),
+        [0xEF, 0xBB, 0xBF, rest @ ..] => String::from_utf8(rest.to_vec()).ok(),
+        _ => String::from_utf8(bytes).ok(),
+    }
+}
+
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> Option<String> {
+    if !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+
+    let units = bytes
+        .chunks_exact(2)
+        .map(|chunk| from_bytes([chunk[0], chunk[1]]));
+
+    char::decode_utf16(units).collect::<Result<_, _>>().ok()
+}
 
-    for line in file.lines() {
-        let line = line
-            .map_err(|error| format!("Failed to read var file '{}': {}", path.display(), error))?;
+/// Splits text into lines along with their line numbers, accepting both LF and CRLF line
+/// endings. In `.reg` files, lines ending with a `\` are joined with the following line,
+/// which is numbered by the first of the joined lines.
+fn logical_lines(text: &str, format: VarFileFormat) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    let mut is_continued = false;
 
-        if line.trim().is_empty() {
-            continue;
+    for (index, line) in text.lines().enumerate() {
+        let (line, continues) = match line.trim_end().strip_suffix('\\') {
+            Some(line) if format == VarFileFormat::Reg => (line, true),
+            _ => (line, false),
+        };
+
+        match lines.last_mut() {
+            Some((_, previous)) if is_continued => previous.push_str(line.trim_start()),
+            _ => lines.push((index + 1, line.to_string())),
         }
 
-        let (key, value) = read_named_arg(&line)
-            .map_err(|error| format!("Failed to parse named arg: {}", error))?;
+        is_continued = continues;
+    }
+
+    lines
+}
 
-        map.insert(key, value);
+fn parse_env_line(line: &str) -> Result<Entry, String> {
+    if line.trim().is_empty() {
+        return Ok(Entry::Ignored);
     }
 
-    Ok(map)
+    read_named_arg(line).map(|(key, value)| Entry::Var(key, value))
+}
+
+fn parse_set_line(line: &str) -> Result<Entry, String> {
+    // Variables such as `=C:` are internal to the Windows command prompt
+    if line.trim().is_empty() || line.starts_with('=') {
+        return Ok(Entry::Ignored);
+    }
+
+    line.split_once('=')
+        .map(|(key, value)| Entry::Var(key.to_string(), value.to_string()))
+        .ok_or_else(|| String::from("'=' character missing in key value pair"))
+}
+
+fn parse_reg_line(line: &str) -> Result<Entry, String> {
+    let line = line.trim();
+    let is_header = line.starts_with("Windows Registry Editor") || line == "REGEDIT4";
+
+    // Empty lines, comments, section headers and default values (`@=`) carry no variables
+    if line.is_empty() || is_header || line.starts_with([';', '[', '@']) {
+        return Ok(Entry::Ignored);
+    }
+
+    let (key, rest) = read_reg_string(line)?;
+    let value = rest
+        .strip_prefix('=')
+        .ok_or_else(|| String::from("'=' character missing after key"))?;
+
+    if value.starts_with('"') {
+        let (value, rest) = read_reg_string(value)?;
+
+        if !rest.trim().is_empty() {
+            return Err(format!("unexpected text '{rest}' after value"));
+        }
+
+        Ok(Entry::Var(key, value))
+    } else if let Some(hex) = value.strip_prefix("hex(2):") {
+        // Expandable strings, such as `Path`, are stored as UTF-16LE bytes ending with a NUL
+        decode_reg_hex_string(hex)
+            .map(|value| Entry::Var(key, value))
+            .ok_or_else(|| String::from("invalid hex(2) value"))
+    } else {
+        Ok(Entry::Unsupported(key))
+    }
+}
+
+/// Reads a double quoted string in which `\\` and `\"` are escaped, returning the unescaped
+/// string and the remaining text.
+fn read_reg_string(text: &str) -> Result<(String, &str), String> {
+    let mut chars = text
+        .strip_prefix('"')
+        .ok_or_else(|| String::from("expected a double quoted string"))?
+        .char_indices();
+    let mut string = String::new();
+
+    while let Some((index, char)) = chars.next() {
+        match char {
+            '"' => return Ok((string, &text[index + 2..])),
+            '\\' => string.push(chars.next().map_or('\\', |(_, char)| char)),
+            char => string.push(char),
+        }
+    }
+
+    Err(String::from("unterminated double quoted string"))
+}
+
+fn decode_reg_hex_string(hex: &str) -> Option<String> {
+    let bytes = hex
+        .split(',')
+        .map(str::trim)
+        .filter(|byte| !byte.is_empty())
+        .map(|byte| u8::from_str_radix(byte, 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    let string = decode_utf16(&bytes, u16::from_le_bytes)?;
+
+    Some(string.trim_end_matches('\0').to_string())
 }
 
 pub fn read_input_file(path: &Path) -> Result<impl BufRead, String> {
@@ -43,25 +244,37 @@ pub fn read_input_file(path: &Path) -> Result<impl BufRead, String> {
         .map_err(|error| format!("Failed to open input file '{}': {}", path.display(), error))
 }
 
+/// Reads a single line of input including its line ending, or [`None`] at the end of the input.
+/// A line longer than `max_bytes` is an error rather than being read into memory.
+pub fn read_input_line(input: &mut impl BufRead, max_bytes: u64) -> Result<Option<String>, String> {
+    let mut bytes = Vec::new();
+    let len = input
+        .by_ref()
+        .take(max_bytes.saturating_add(1))
+        .read_until(b'\n', &mut bytes)
+        .map_err(|error| format!("Failed to read input: {error}"))?;
+
+    if len == 0 {
+        return Ok(None);
+    }
+
+    if u64::try_from(len).map_or(true, |len| len > max_bytes) {
+        return Err(format!(
+            "Failed to read input: line exceeds the maximum length of {max_bytes} bytes, see \
+             --max-line-bytes"
+        ));
+    }
+
+    String::from_utf8(bytes)
+        .map(Some)
+        .map_err(|_| String::from("Failed to read input: stream did not contain valid UTF-8"))
+}
+
 pub fn read_output_file(path: &Path) -> Result<impl Write, String> {
     OpenOptions::new()
-        .write(true)
         .create_new(!path.exists())
         .append(true)
         .open(path)
         .map(BufWriter::new)
         .map_err(|error| format!("Failed to open output file '{}': {}", path.display(), error))
 }
-
-/// Reads the next line from stdin just like [`std::io::Lines::next`] except that it includes
-/// the line ending in the returned string.
-pub fn read_line(buf: &mut impl BufRead) -> Option<Result<String, String>> {
-    let mut string = String::new();
-
-    #[allow(clippy::significant_drop_in_scrutinee)]
-    match buf.read_line(&mut string) {
-        Ok(0) => None,
-        Ok(_) => Some(Ok(string)),
-        Err(error) => Some(Err(format!("Failed to read input: {}", error))),
-    }
-}
@@ -0,0 +1,126 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use xpanda::{ErrorKind, Report, Resolution, Source};
+
+/// A report as written by `--json`.
+#[derive(Serialize, Debug)]
+struct JsonReport<'a> {
+    output: Option<&'a str>,
+    missing: &'a [String],
+    used: BTreeMap<&'a str, String>,
+    unused: BTreeMap<&'a str, String>,
+    resolutions: BTreeMap<&'a str, JsonResolution>,
+    params: Vec<JsonParam<'a>>,
+    errors: Vec<JsonError<'a>>,
+}
+
+/// How a variable resolves as written in the JSON format, with a `length` of `null` if unset.
+#[derive(Serialize, Debug)]
+struct JsonResolution {
+    state: &'static str,
+    length: Option<usize>,
+}
+
+impl From<Resolution> for JsonResolution {
+    fn from(resolution: Resolution) -> Self {
+        let (state, length) = match resolution {
+            Resolution::Empty => ("empty", Some(0)),
+            Resolution::Set(length) => ("set", Some(length)),
+            _ => ("unset", None),
+        };
+
+        Self { state, length }
+    }
+}
+
+/// A param as written in the JSON format, along with its expanded value.
+#[derive(Serialize, Debug)]
+struct JsonParam<'a> {
+    text: &'a str,
+    line: usize,
+    col: usize,
+    value: Option<&'a str>,
+}
+
+/// An error as written in the JSON format.
+#[derive(Serialize, Debug)]
+struct JsonError<'a> {
+    kind: &'static str,
+    message: &'a str,
+    line: usize,
+    col: usize,
+}
+
+/// Describes where the value of a variable comes from, or `unset`.
+pub fn source_label(source: Option<&Source>) -> String {
+    match source {
+        None => String::from("unset"),
+        Some(Source::Positional) => String::from("positional"),
+        Some(Source::Env | Source::LiveEnv) => String::from("env"),
+        Some(Source::Named(label)) => label.clone().unwrap_or_else(|| String::from("named")),
+        Some(Source::FrontMatter) => String::from("front matter"),
+        Some(Source::Namespace(namespace)) => format!("namespace '{namespace}'"),
+        Some(_) => String::from("unknown"),
+    }
+}
+
+const fn kind_name(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::Syntax => "syntax",
+        ErrorKind::Unset => "unset",
+        ErrorKind::Required => "required",
+        ErrorKind::InvalidValue => "invalid-value",
+        ErrorKind::Provider => "provider",
+        ErrorKind::Policy => "policy",
+        ErrorKind::Io => "io",
+        ErrorKind::TooLong => "too-long",
+        _ => "other",
+    }
+}
+
+/// Writes the report as a JSON object.
+pub fn write_json(report: &Report, output: &mut impl Write) -> io::Result<()> {
+    let report = JsonReport {
+        output: report.output.as_deref(),
+        missing: &report.missing,
+        used: report
+            .used
+            .iter()
+            .map(|(name, source)| (name.as_str(), source_label(Some(source))))
+            .collect(),
+        unused: report
+            .unused
+            .iter()
+            .map(|(name, source)| (name.as_str(), source_label(Some(source))))
+            .collect(),
+        resolutions: report
+            .resolutions
+            .iter()
+            .map(|(name, resolution)| (name.as_str(), JsonResolution::from(*resolution)))
+            .collect(),
+        params: report
+            .params
+            .iter()
+            .map(|param| JsonParam {
+                text: &param.text,
+                line: param.position.line,
+                col: param.position.col,
+                value: param.value.as_deref(),
+            })
+            .collect(),
+        errors: report
+            .errors
+            .iter()
+            .map(|error| JsonError {
+                kind: kind_name(error.kind),
+                message: &error.message,
+                line: error.line,
+                col: error.col,
+            })
+            .collect(),
+    };
+
+    serde_json::to_writer_pretty(&mut *output, &report)?;
+    writeln!(output)
+}
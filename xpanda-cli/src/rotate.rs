@@ -0,0 +1,153 @@
+use crate::context::format_timestamp;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// When a [`RotatingFile`] moves its file aside to start a new one.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Rotation {
+    /// The size in bytes which a file may not exceed, unless a single write is larger.
+    pub max_size: Option<u64>,
+    /// Whether to start a new file when the day changes, in UTC.
+    pub daily: bool,
+}
+
+impl Rotation {
+    pub const fn is_enabled(self) -> bool {
+        self.max_size.is_some() || self.daily
+    }
+}
+
+/// A file which is rotated according to a [`Rotation`], such as to run as a persistent filter
+/// without the output growing forever.
+///
+/// Files are only rotated in between writes, and only after a line ending, so each line ends up
+/// in a single file. Rotating moves the file to its path followed by the day it was written
+/// (when rotating daily) and the first free number (when that name is taken, or when not
+/// rotating daily), e.g. `out.log.2024-01-02` or `out.log.1`. A new file is then created at the
+/// path.
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    file: File,
+    size: u64,
+    /// Whether the file is empty or ends with a line ending.
+    at_line_start: bool,
+    /// The day the file was last written, in days since the Unix epoch.
+    day: u64,
+}
+
+impl RotatingFile {
+    /// Opens the file at the given path for appending, creating it if it doesn't exist. An
+    /// existing file counts as written on the day it was last modified, and is assumed to end
+    /// with a line ending.
+    pub fn open(path: &Path, rotation: Rotation) -> Result<Self, String> {
+        let error = |error| format!("Failed to open output file '{}': {}", path.display(), error);
+        let file = open_append(path).map_err(error)?;
+        let metadata = file.metadata().map_err(error)?;
+        let day = metadata.modified().map_or_else(|_| today(), day_of);
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            rotation,
+            file,
+            size: metadata.len(),
+            at_line_start: true,
+            day,
+        })
+    }
+
+    fn should_rotate(&self, len: u64, day: u64) -> bool {
+        if !self.at_line_start {
+            return false;
+        }
+
+        let is_full = self
+            .rotation
+            .max_size
+            .is_some_and(|max_size| self.size > 0 && self.size.saturating_add(len) > max_size);
+        let is_new_day = self.rotation.daily && day != self.day;
+
+        is_full || is_new_day
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.move_aside()?;
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+
+        Ok(())
+    }
+
+    /// Moves the file to the first free path it may be rotated to. The file is linked to that
+    /// path and then removed, rather than renamed, since linking fails if a file was created at
+    /// the path in the meantime, while renaming would replace it.
+    fn move_aside(&self) -> io::Result<()> {
+        for path in self.rotated_paths() {
+            match fs::hard_link(&self.path, &path) {
+                Ok(()) => return fs::remove_file(&self.path),
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {},
+                Err(error) => return Err(error),
+            }
+        }
+
+        Err(io::ErrorKind::AlreadyExists.into())
+    }
+
+    /// The paths the file may be rotated to, in order of preference.
+    fn rotated_paths(&self) -> impl Iterator<Item = PathBuf> {
+        let mut base = OsString::from(self.path.as_os_str());
+
+        if self.rotation.daily {
+            base.push(".");
+            base.push(&format_timestamp(self.day * 86_400)[..10]);
+        }
+
+        let daily = self.rotation.daily.then(|| PathBuf::from(&base));
+        let numbered = (1_u64..).map(move |number| {
+            let mut path = base.clone();
+            path.push(format!(".{number}"));
+            PathBuf::from(path)
+        });
+
+        daily.into_iter().chain(numbered)
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len() as u64;
+        let day = today();
+
+        if self.should_rotate(len, day) {
+            self.rotate()?;
+        }
+
+        self.file.write_all(buf)?;
+        self.size += len;
+        self.at_line_start = buf.ends_with(b"\n");
+        self.day = day;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn today() -> u64 {
+    day_of(SystemTime::now())
+}
+
+fn day_of(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() / 86_400)
+}
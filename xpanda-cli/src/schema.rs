@@ -0,0 +1,79 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use xpanda::{Schema, VarSchema};
+
+/// A schema describing the expected variables, keyed by variable name.
+///
+/// Example:
+/// ```toml
+/// [vars.PORT]
+/// required = true
+/// pattern = "[0-9]+"
+/// description = "The port to listen on"
+///
+/// [vars.LOG_LEVEL]
+/// values = ["debug", "info"]
+/// ```
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct SchemaFile {
+    #[serde(default)]
+    vars: BTreeMap<String, SchemaVar>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct SchemaVar {
+    #[serde(default)]
+    required: bool,
+    pattern: Option<String>,
+    values: Option<Vec<String>>,
+    description: Option<String>,
+}
+
+/// Reads a schema file in TOML format.
+pub fn read_schema(path: &Path) -> Result<Schema, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|error| format!("Failed to open schema '{}': {}", path.display(), error))?;
+    let file: SchemaFile = toml::from_str(&content)
+        .map_err(|error| format!("Failed to parse schema '{}': {}", path.display(), error))?;
+    let mut schema = Schema::new();
+
+    for (name, var) in file.vars {
+        let mut var_schema = VarSchema::new(&name).required(var.required);
+
+        var_schema = match (var.pattern, var.values) {
+            (Some(_), Some(_)) => {
+                return Err(format!(
+                    "Failed to parse schema '{}': variable '{name}' can't have both a pattern \
+                     and values",
+                    path.display()
+                ));
+            },
+            #[cfg(feature = "regex")]
+            (Some(pattern), None) => var_schema.pattern(&pattern).map_err(|error| {
+                format!("Failed to parse schema '{}': {}", path.display(), error)
+            })?,
+            #[cfg(not(feature = "regex"))]
+            (Some(_), None) => {
+                return Err(format!(
+                    "Failed to parse schema '{}': variable '{name}' has a pattern, but \
+                     xpanda-cli was built without the 'regex' feature",
+                    path.display()
+                ));
+            },
+            (None, Some(values)) => var_schema.one_of(values),
+            (None, None) => var_schema,
+        };
+
+        if let Some(description) = var.description {
+            var_schema = var_schema.with_description(description);
+        }
+
+        schema = schema.var(var_schema);
+    }
+
+    Ok(schema)
+}
@@ -0,0 +1,72 @@
+use crate::diagnostic::Diagnostic;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Write};
+use std::time::Duration;
+use xpanda::{Phase, Timing};
+
+/// The default number of milliseconds after which rendering a template is reported as slow.
+pub const DEFAULT_SLOW_THRESHOLD_MS: u64 = 100;
+
+/// The time spent in each phase of rendering a template, as reported by `--timings`.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Timings {
+    pub lex: Duration,
+    pub parse: Duration,
+    pub eval: Duration,
+}
+
+impl Timings {
+    /// Adds the duration of a phase, which may be reported several times as templates are
+    /// rendered line by line.
+    pub fn add(&mut self, timing: &Timing) {
+        match timing.phase {
+            Phase::Lex => self.lex += timing.duration,
+            Phase::Parse => self.parse += timing.duration,
+            Phase::Eval => self.eval += timing.duration,
+            _ => {},
+        }
+    }
+
+    pub fn total(&self) -> Duration {
+        self.lex + self.parse + self.eval
+    }
+
+    /// Writes the timings of a template, followed by a warning if rendering it took longer
+    /// than the threshold.
+    pub fn write(
+        &self,
+        template: &str,
+        threshold: Duration,
+        output: &mut impl Write,
+        colorize: bool,
+    ) -> io::Result<()> {
+        writeln!(output, "Timings for '{template}': {self}")?;
+
+        if self.total() <= threshold {
+            return Ok(());
+        }
+
+        let total = self.total();
+        let diagnostic = Diagnostic {
+            is_warning: true,
+            ..Diagnostic::new(format!(
+                "'{template}' is slow to render, taking {total:.3?} which exceeds {threshold:?}"
+            ))
+        };
+
+        diagnostic.write_line(output, colorize)
+    }
+}
+
+impl Display for Timings {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "lex {:.3?}, parse {:.3?}, eval {:.3?}, total {:.3?}",
+            self.lex,
+            self.parse,
+            self.eval,
+            self.total()
+        )
+    }
+}
@@ -0,0 +1,165 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+/// A set of files which are written together, such that either all of them are updated or none
+/// are.
+///
+/// Files are first staged by writing them to temporary files next to their paths, which are
+/// then renamed over their paths when the transaction is committed. Renaming a file is atomic,
+/// so a file is never left partially written, and as it can only fail in rare cases (such as a
+/// path having become a directory) a failure while staging leaves every path untouched. Staged
+/// files which aren't committed are removed when the transaction is dropped, and so are the
+/// directories created to stage them if they're left empty.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    staged: Mutex<Vec<Staged>>,
+    /// The directories created while staging, in order of creation.
+    created: Mutex<Vec<PathBuf>>,
+    /// The number of files staged so far, used to give each temporary file a unique name.
+    next: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct Staged {
+    temp: PathBuf,
+    path: PathBuf,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes the contents of the file at the given path to a temporary file, creating the
+    /// directory of the path if needed.
+    pub fn stage(&self, path: &Path, bytes: &[u8]) -> Result<(), String> {
+        let error = |error| {
+            format!(
+                "Failed to write output file '{}': {}",
+                path.display(),
+                error
+            )
+        };
+
+        if let Some(parent) = path.parent() {
+            self.create_dir(parent).map_err(|error| {
+                format!(
+                    "Failed to create directory '{}': {}",
+                    parent.display(),
+                    error
+                )
+            })?;
+        }
+
+        let temp = temp_path(path, self.next.fetch_add(1, Ordering::Relaxed));
+
+        if let Err(err) = fs::write(&temp, bytes) {
+            let _result = fs::remove_file(&temp);
+            return Err(error(err));
+        }
+
+        self.staged
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Staged {
+                temp,
+                path: path.to_path_buf(),
+            });
+
+        Ok(())
+    }
+
+    /// Creates the directory and any missing parents, keeping track of the ones created.
+    fn create_dir(&self, dir: &Path) -> io::Result<()> {
+        let missing = dir
+            .ancestors()
+            .take_while(|dir| !dir.as_os_str().is_empty() && !dir.is_dir())
+            .collect::<Vec<_>>();
+
+        for dir in missing.into_iter().rev() {
+            match fs::create_dir(dir) {
+                Ok(()) => self
+                    .created
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .push(dir.to_path_buf()),
+                // Another file may be staged into the same directory at the same time
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists && dir.is_dir() => {},
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Renames every staged file over its path, in the order they were staged.
+    pub fn commit(mut self) -> Result<(), String> {
+        let files = self
+            .staged
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        let failure = files
+            .iter()
+            .enumerate()
+            .find_map(|(index, Staged { temp, path })| {
+                let error = fs::rename(temp, path).err()?;
+                let message = format!(
+                    "Failed to write output file '{}': {}",
+                    path.display(),
+                    error
+                );
+                Some((index, message))
+            });
+
+        // Files which were renamed no longer need to be removed
+        if let Some((index, message)) = failure {
+            files.drain(..index);
+            return Err(message);
+        }
+
+        files.clear();
+        self.created
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+
+        Ok(())
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        let files = self
+            .staged
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        for Staged { temp, .. } in files.drain(..) {
+            let _result = fs::remove_file(temp);
+        }
+
+        let dirs = self
+            .created
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        // Directories are only removed if empty, such as if no file inside them was committed
+        for dir in dirs.drain(..).rev() {
+            let _result = fs::remove_dir(dir);
+        }
+    }
+}
+
+/// A hidden path in the same directory as the given one, since renaming across file systems
+/// isn't atomic.
+fn temp_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".xpanda-{}-{index}.tmp", process::id()));
+    path.with_file_name(name)
+}
@@ -0,0 +1,85 @@
+use std::io::Write;
+
+/// How the line ending at the very end of the output is treated.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum TrailingNewline {
+    /// The output is written as is.
+    #[default]
+    Keep,
+    /// A single line ending (`\n` or `\r\n`) at the end of the output is removed.
+    Remove,
+    /// A `\n` is appended unless the output is empty or already ends with one.
+    Ensure,
+}
+
+/// Streams text to a writer while controlling the final line ending of the output.
+///
+/// Since the end of the output isn't known until [`OutputWriter::finish`] is called, a line
+/// ending at the end of each write is held back when removing the trailing newline, and only
+/// written once more text follows.
+pub struct OutputWriter<W: Write> {
+    inner: W,
+    trailing_newline: TrailingNewline,
+    pending: &'static str,
+    ends_with_newline: bool,
+    is_empty: bool,
+}
+
+impl<W: Write> OutputWriter<W> {
+    pub const fn new(inner: W, trailing_newline: TrailingNewline) -> Self {
+        Self {
+            inner,
+            trailing_newline,
+            pending: "",
+            ends_with_newline: false,
+            is_empty: true,
+        }
+    }
+
+    pub fn write(&mut self, text: &str) -> Result<(), String> {
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        self.is_empty = false;
+        self.ends_with_newline = text.ends_with('\n');
+
+        if self.trailing_newline != TrailingNewline::Remove {
+            return self.write_all(text);
+        }
+
+        let pending = std::mem::take(&mut self.pending);
+        self.write_all(pending)?;
+
+        let line_ending = if text.ends_with("\r\n") {
+            "\r\n"
+        } else if text.ends_with('\n') {
+            "\n"
+        } else {
+            ""
+        };
+
+        self.pending = line_ending;
+        self.write_all(&text[..text.len() - line_ending.len()])
+    }
+
+    /// Writes the final line ending, if any, and flushes the writer.
+    pub fn finish(mut self) -> Result<(), String> {
+        if self.trailing_newline == TrailingNewline::Ensure
+            && !self.is_empty
+            && !self.ends_with_newline
+        {
+            self.write_all("\n")?;
+        }
+
+        self.inner
+            .flush()
+            .map_err(|error| format!("Failed to write output: {error}"))
+    }
+
+    fn write_all(&mut self, text: &str) -> Result<(), String> {
+        self.inner
+            .write_all(text.as_bytes())
+            .map_err(|error| format!("Failed to write output: {error}"))
+    }
+}
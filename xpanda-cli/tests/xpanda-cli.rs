@@ -1,7 +1,10 @@
+#![allow(clippy::needless_borrows_for_generic_args)]
+
 use assert_cmd::Command;
-use predicates::prelude::predicate::str::diff;
+use predicates::prelude::predicate::str::{contains, diff};
 use std::env::temp_dir;
 use std::fs;
+use std::path::Path;
 use uuid::Uuid;
 
 #[test]
@@ -15,6 +18,39 @@ fn positional_var_success() {
         .stdout(diff("woop"));
 }
 
+#[test]
+fn positional_var_without_separator() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["one", "-v", "VAR=woop", "two"])
+        .write_stdin("$1 $2 $VAR")
+        .assert()
+        .success()
+        .stdout(diff("one two woop"));
+}
+
+#[test]
+fn positional_var_command_name() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--", "check", "-x"])
+        .write_stdin("$1 $2")
+        .assert()
+        .success()
+        .stdout(diff("check -x"));
+}
+
+#[test]
+fn arg_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--arg", "-one", "--arg", "check", "three"])
+        .write_stdin("$1 $2 $3")
+        .assert()
+        .success()
+        .stdout(diff("-one check three"));
+}
+
 #[test]
 fn named_var_success() {
     Command::cargo_bin("xpanda-cli")
@@ -37,6 +73,48 @@ fn no_unset_error() {
         .stderr(diff("1:1 'VAR' is unset"));
 }
 
+#[test]
+fn alias_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["-v", "DATABASE_URL=woop", "--alias", "DB_URL=DATABASE_URL"])
+        .write_stdin("$DB_URL")
+        .assert()
+        .success()
+        .stdout(diff("woop"));
+}
+
+#[test]
+fn quoting_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .arg("--quoting")
+        .write_stdin(r#"${VAR:-"a } b"}"#)
+        .assert()
+        .success()
+        .stdout(diff("a } b"));
+}
+
+#[test]
+fn lenient_identifiers_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--lenient-identifiers", "-v", "2x=woop"])
+        .write_stdin("$2x")
+        .assert()
+        .success()
+        .stdout(diff("woop"));
+}
+
+#[test]
+fn digit_identifier_exit_code() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .write_stdin("$2x")
+        .assert()
+        .code(5);
+}
+
 #[test]
 fn env_var_success() {
     Command::cargo_bin("xpanda-cli")
@@ -48,6 +126,52 @@ fn env_var_success() {
         .stdout(diff("woop"));
 }
 
+#[test]
+fn env_namespace_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .env("VAR", "woop")
+        .args(["-v", "VAR=boop"])
+        .write_stdin("${env:VAR} $VAR")
+        .assert()
+        .success()
+        .stdout(diff("woop boop"));
+}
+
+#[test]
+fn env_namespace_default() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .env_remove("VAR")
+        .env("SET", "woop")
+        .write_stdin("${env:VAR:-default} ${env:SET:+set}")
+        .assert()
+        .success()
+        .stdout(diff("default set"));
+}
+
+#[test]
+fn powershell_env_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .arg("--powershell-env")
+        .env("VAR", "woop")
+        .write_stdin("$env:VAR")
+        .assert()
+        .success()
+        .stdout(diff("woop"));
+}
+
+#[test]
+fn unknown_namespace_exit_code() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .write_stdin("${vault:secret}")
+        .assert()
+        .code(7)
+        .stderr(diff("1:1 Unknown namespace 'vault'"));
+}
+
 #[test]
 fn var_error() {
     Command::cargo_bin("xpanda-cli")
@@ -80,106 +204,2556 @@ fn var_unset_or_empty_error() {
 }
 
 #[test]
-fn arity_success() {
+fn front_matter_success() {
     Command::cargo_bin("xpanda-cli")
         .unwrap()
-        .args(&["--", "one", "two"])
-        .write_stdin("${#}")
+        .args(["--front-matter", "-v", "DB_URL=db"])
+        .write_stdin("#! xpanda: require DB_URL; default REGION=eu\n$DB_URL\n$REGION\n")
         .assert()
         .success()
-        .stdout(diff("2"));
+        .stdout(diff("db\neu\n"));
 }
 
 #[test]
-fn ref_success() {
+fn front_matter_required_error() {
     Command::cargo_bin("xpanda-cli")
         .unwrap()
-        .args(&["-v", "VAR=woop"])
-        .args(&["--", "VAR"])
-        .write_stdin("${!1}")
+        .args(["--front-matter", "--env-vars=false"])
+        .write_stdin("#! xpanda: require DB_URL, PORT\n$DB_URL\n")
+        .assert()
+        .code(3)
+        .stdout(diff(""))
+        .stderr(contains("Required variable(s) 'DB_URL', 'PORT' are unset"));
+}
+
+#[test]
+fn max_line_bytes_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--max-line-bytes", "5", "-v", "VAR=woop"])
+        .write_stdin("$VAR\nåä")
         .assert()
         .success()
-        .stdout(diff("woop"));
+        .stdout(diff("woop\nåä"));
 }
 
 #[test]
-fn input_file_success() {
-    let mut file = temp_dir();
-    file.push(Uuid::new_v4().to_string() + "-xpanda-test-input");
-    fs::write(&file, "$VAR").unwrap();
+fn max_line_bytes_long_line() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--max-line-bytes", "5", "-v", "VAR=woop"])
+        .write_stdin("$VAR $VAR åäö $$ $VAR\n")
+        .assert()
+        .success()
+        .stdout(diff("woop woop åäö $ woop\n"));
+}
 
+#[test]
+fn max_line_bytes_error() {
     Command::cargo_bin("xpanda-cli")
         .unwrap()
-        .args(&["-i", file.to_str().unwrap()])
-        .args(&["-v", "VAR=woop"])
+        .args(["--max-line-bytes", "5", "-v", "VAR=woop"])
+        .write_stdin("$VAR\n${VAR}\n")
+        .assert()
+        .code(1)
+        .stdout(diff("woop\n"))
+        .stderr(diff(
+            "2:1 Failed to read input: param exceeds the maximum length of 5 bytes, see \
+             --max-line-bytes",
+        ));
+}
+
+#[test]
+fn max_line_bytes_comment_mid_line() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--comments", "--max-line-bytes", "8"])
+        .write_stdin("abcdef $# args\nnext\n")
+        .assert()
+        .code(5)
+        .stdout(diff("abcdef "))
+        .stderr(diff("1:10 Expected identifier, found '#'"));
+}
+
+#[test]
+fn max_line_bytes_fields_error() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--max-line-bytes", "5", "--fields", "1", "-v", "VAR=woop"])
+        .write_stdin("$VAR\n$VAR $VAR\n")
+        .assert()
+        .code(1)
+        .stdout(diff("woop\n"))
+        .stderr(contains("line exceeds the maximum length of 5 bytes"));
+}
+
+/// Pipes the given number of bytes of input, in lines far longer than the buffer, through the
+/// program and returns its peak memory usage in kB.
+#[cfg(target_os = "linux")]
+fn stream_peak_memory(input_bytes: usize) -> usize {
+    use std::io::{Read, Write};
+    use std::process::{Command, Stdio};
+    use std::thread;
+
+    let text = "åäö text ".repeat(20);
+    let line = format!("{}\n", format!("$VAR ${{VAR}} $$ {text}").repeat(5000));
+    let expected_line = format!("{}\n", format!("woop woop $ {text}").repeat(5000));
+    let line_count = input_bytes / line.len();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_xpanda-cli"))
+        .args(["--max-line-bytes", "65536", "-v", "VAR=woop"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = thread::spawn(move || {
+        for _ in 0..line_count {
+            stdin.write_all(line.as_bytes()).unwrap();
+        }
+    });
+
+    let status_path = format!("/proc/{}/status", child.id());
+    let mut stdout = child.stdout.take().unwrap();
+    let mut buffer = vec![0; 1 << 16];
+    let mut output_bytes = 0;
+    let mut peak = 0;
+
+    loop {
+        let len = stdout.read(&mut buffer).unwrap();
+
+        if len == 0 {
+            break;
+        }
+
+        output_bytes += len;
+
+        // The peak resident set size is only available while the process is running
+        if let Some(hwm) = fs::read_to_string(&status_path).ok().and_then(|status| {
+            let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+            line.split_whitespace().nth(1)?.parse::<usize>().ok()
+        }) {
+            peak = hwm;
+        }
+    }
+
+    writer.join().unwrap();
+    assert!(child.wait().unwrap().success());
+    assert_eq!(output_bytes, expected_line.len() * line_count);
+
+    peak
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn stream_constant_memory() {
+    let peak = stream_peak_memory(64 << 20);
+
+    assert!(
+        peak > 0 && peak < 32 << 10,
+        "peak memory usage of {peak} kB"
+    );
+}
+
+#[test]
+#[ignore = "pipes several gigabytes through the program"]
+#[cfg(target_os = "linux")]
+fn stream_constant_memory_large() {
+    let peak = stream_peak_memory(4 << 30);
+
+    assert!(
+        peak > 0 && peak < 32 << 10,
+        "peak memory usage of {peak} kB"
+    );
+}
+
+#[test]
+fn delimiter_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--delimiter", "---", "--args-per-doc", "1", "--", "a", "b"])
+        .write_stdin("one=$1\n---\ntwo=$1\n---\nthree=${1-none}\n")
         .assert()
         .success()
-        .stdout(diff("woop"));
+        .stdout(diff("one=a\n---\ntwo=b\n---\nthree=none\n"));
 }
 
 #[test]
-fn output_file_success() {
-    let mut file = temp_dir();
-    file.push(Uuid::new_v4().to_string() + "-xpanda-test-output");
+fn delimiter_front_matter() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--delimiter", "---", "--front-matter", "-v", "A=x"])
+        .write_stdin("#! xpanda: default B=1\n$A$B\n---\n#! xpanda: default B=2\n$A$B\n")
+        .assert()
+        .success()
+        .stdout(diff("x1\n---\nx2\n"));
+}
 
+#[test]
+fn delimiter_error() {
     Command::cargo_bin("xpanda-cli")
         .unwrap()
-        .args(&["-o", file.to_str().unwrap()])
-        .args(&["-v", "VAR=woop"])
-        .write_stdin("$VAR")
+        .args(["--delimiter", "---"])
+        .write_stdin("a\n---\nb ${A\n")
+        .assert()
+        .code(5)
+        .stdout(diff("a\n---\n"))
+        .stderr(diff("3:3 Expected a matching '}' for the '${' opened"));
+}
+
+#[test]
+fn positional_names_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args([
+            "--positional-names",
+            "HOST,PORT",
+            "-v",
+            "PORT=80",
+            "-v",
+            "HOST=unused",
+        ])
+        .args(["--", "localhost"])
+        .write_stdin("$HOST:$PORT $1")
+        .assert()
+        .success()
+        .stdout(diff("localhost:80 localhost"));
+}
+
+#[test]
+fn max_value_length_error() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--max-value-length", "3", "--", "abcdef"])
+        .write_stdin("$1")
+        .assert()
+        .code(6)
+        .stdout(diff(""))
+        .stderr(diff("1:1 '1' is longer than 3 characters"));
+}
+
+#[test]
+fn max_value_length_truncate() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args([
+            "--max-value-length",
+            "3",
+            "--truncate-long-values",
+            "--",
+            "abcdef",
+        ])
+        .write_stdin("$1 ${1@trunc:2} ${2-abcdef}")
+        .assert()
+        .success()
+        .stdout(diff("abc ab abcdef"));
+}
+
+#[test]
+fn conditionals_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--conditionals", "-v", "TLS=1"])
+        .write_stdin("${if TLS}\nlisten 443 ssl;\n${else}\nlisten 80;\n${end}\n")
         .assert()
         .success()
+        .stdout(diff("listen 443 ssl;\n"));
+}
+
+#[test]
+fn conditionals_unclosed() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .arg("--conditionals")
+        .write_stdin("${if TLS}\nlisten 443 ssl;\n")
+        .assert()
+        .code(5)
         .stdout(diff(""));
+}
 
-    let content = fs::read_to_string(&file).unwrap();
-    assert_eq!(content, "woop");
+#[test]
+fn dynamic_names_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args([
+            "--dynamic-names",
+            "-v",
+            "ENV=PROD",
+            "-v",
+            "DB_URL_PROD=db.prod",
+        ])
+        .write_stdin("${DB_URL_${ENV}}\n")
+        .assert()
+        .success()
+        .stdout(diff("db.prod\n"));
 }
 
 #[test]
-fn var_file_success() {
-    let mut file = temp_dir();
-    file.push(Uuid::new_v4().to_string() + "-xpanda-test-vars");
-    fs::write(&file, "VAR=woop").unwrap();
+fn dynamic_names_invalid() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--dynamic-names", "-v", "ENV=a-b"])
+        .write_stdin("${DB_URL_${ENV}}\n")
+        .assert()
+        .code(6)
+        .stderr(diff(
+            "1:1 Invalid variable name 'DB_URL_a-b' computed from 'DB_URL_${ENV}'",
+        ));
+}
 
+#[test]
+#[cfg(feature = "expr")]
+fn expressions() {
     Command::cargo_bin("xpanda-cli")
         .unwrap()
-        .args(&["-f", file.to_str().unwrap()])
-        .write_stdin("$VAR")
+        .args(["--expressions", "-v", "CPU=6"])
+        .write_stdin("${REPLICAS:-=max(2, $CPU / 2)} ${OP-$=}\n")
         .assert()
         .success()
-        .stdout(diff("woop"));
+        .stdout(diff("3 =\n"));
 }
 
 #[test]
-fn unexpected_eof_error() {
+#[cfg(feature = "locale")]
+fn case_locale() {
     Command::cargo_bin("xpanda-cli")
         .unwrap()
-        .args(&["-v", "VAR=woop"])
-        .write_stdin("${VAR")
+        .args(["--case-locale", "tr", "-v", "CITY=istanbul"])
+        .write_stdin("${CITY^^}\n")
         .assert()
-        .failure()
-        .stderr(diff("1:6 Invalid param, unexpected EOF"));
+        .success()
+        .stdout(diff("İSTANBUL\n"));
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--case-locale", "not a tag"])
+        .assert()
+        .code(2);
 }
 
 #[test]
-fn unexpected_token_error() {
+fn expressions_disabled() {
     Command::cargo_bin("xpanda-cli")
         .unwrap()
-        .args(&["-v", "VAR=woop"])
-        .write_stdin("${VAR-:def}")
+        .args(["-v", "CPU=6"])
+        .write_stdin("${REPLICAS:-=max(2, $CPU / 2)}\n")
         .assert()
-        .failure()
-        .stderr(diff("1:7 Unexpected token ':'"));
+        .success()
+        .stdout(diff("=max(2, $CPU / 2)\n"));
 }
 
 #[test]
-fn multiline_success() {
+fn ansi_c_quotes_success() {
     Command::cargo_bin("xpanda-cli")
         .unwrap()
-        .args(&["-v", "DEF=def"])
-        .args(&["--", "jkl"])
-        .write_stdin("abc$DEF\nghi$1")
+        .args(["--ansi-c-quotes", "interpret", "-v", "A=a"])
+        .write_stdin("$A$'\\t\\x62\\u00e9'\n$'\\''\n")
         .assert()
         .success()
-        .stdout(diff("abcdef\nghijkl"));
+        .stdout(diff("a\tbé\n'\n"));
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--ansi-c-quotes", "keep", "-v", "A=a"])
+        .write_stdin("IFS=$'\\n' $A\n")
+        .assert()
+        .success()
+        .stdout(diff("IFS=$'\\n' a\n"));
+}
+
+#[test]
+fn ansi_c_quotes_error() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .write_stdin("IFS=$'\\n'\n")
+        .assert()
+        .code(5);
+}
+
+#[test]
+fn warn_positional_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--warn-positional", "--", "a", "b"])
+        .write_stdin("$1 $2 $3 ${4-none} ${5:+set}")
+        .assert()
+        .success()
+        .stdout(diff("a b  none "))
+        .stderr(diff(
+            "Warning: '$3' is out of range, 2 positional variable(s) are set at line 1, \
+             column 7\n",
+        ));
+}
+
+#[test]
+fn warn_positional_position() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--warn-positional", "--", "a"])
+        .write_stdin("$1\n  $2\n")
+        .assert()
+        .success()
+        .stdout(diff("a\n  \n"))
+        .stderr(diff(
+            "Warning: '$2' is out of range, 1 positional variable(s) are set at line 2, \
+             column 3\n",
+        ));
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--warn-positional", "--fields", "2", "--", "a"])
+        .write_stdin("$2\t$1\n$2\tx $2\n")
+        .assert()
+        .success()
+        .stdout(diff("$2\ta\n$2\tx \n"))
+        .stderr(diff(
+            "Warning: '$2' is out of range, 1 positional variable(s) are set at line 2, \
+             column 6\n",
+        ));
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--warn-positional", "--delimiter", "---", "--", "a"])
+        .write_stdin("$1\n--- $2\n")
+        .assert()
+        .success()
+        .stdout(diff("a\n--- \n"))
+        .stderr(diff(
+            "Warning: '$2' is out of range, 1 positional variable(s) are set at line 2, \
+             column 5\n",
+        ));
+}
+
+#[test]
+fn fields_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--fields", "2,4", "-v", "A=x", "-v", "B=y"])
+        .write_stdin("$A\t$A\t$B\t${B^^}\n$$\t$$\n")
+        .assert()
+        .success()
+        .stdout(diff("$A\tx\t$B\tY\n$$\t$\n"));
+}
+
+#[test]
+fn fields_delimiter() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--fields", "3", "--field-delimiter", ",", "-v", "A=x"])
+        .write_stdin("$A,$A,$A\r\n")
+        .assert()
+        .success()
+        .stdout(diff("$A,$A,x\r\n"));
+}
+
+#[test]
+fn fields_error() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--fields", "2", "--field-delimiter", "::"])
+        .write_stdin("a::b\n${::${A\n")
+        .assert()
+        .code(5)
+        .stdout(diff("a::b\n"))
+        .stderr(diff("2:5 Expected a matching '}' for the '${' opened"));
+}
+
+#[test]
+fn fields_counted_from_one() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--fields", "0"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn args_per_doc_requires_delimiter() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--args-per-doc", "1"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn context_success() {
+    let mut file = temp_dir();
+    file.push(Uuid::new_v4().to_string() + "-xpanda-test-context.tpl");
+    fs::write(
+        &file,
+        "# ${xpanda:timestamp}\n# ${xpanda:file}:${xpanda:line}\n",
+    )
+    .unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .env("SOURCE_DATE_EPOCH", "1704164645")
+        .args(["--context", "-i", file.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(diff(format!(
+            "# 2024-01-02T03:04:05Z\n# {}:2\n",
+            file.display()
+        )));
+}
+
+#[test]
+fn context_reproducible() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .env_remove("SOURCE_DATE_EPOCH")
+        .args(["--context", "--reproducible"])
+        .write_stdin("# ${xpanda:timestamp}\n")
+        .assert()
+        .success()
+        .stdout(diff("# 1970-01-01T00:00:00Z\n"));
+}
+
+#[test]
+fn print_effective_precedence_success() {
+    let mut file = temp_dir();
+    file.push(Uuid::new_v4().to_string() + "-xpanda-test-precedence.env");
+    fs::write(&file, "A=file\nB=file\n").unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--print-effective-precedence", "--env-vars", "-f"])
+        .arg(&file)
+        .args(["-v", "B=var", "positional"])
+        .env("XPANDA_TEST_PRECEDENCE", "env")
+        .write_stdin("$1 $A $B ${B}\n$XPANDA_TEST_PRECEDENCE $C $2")
+        .assert()
+        .success()
+        .stdout(diff(format!(
+            "1\tpositional\nA\tfile 1 ({})\nB\t--var\nXPANDA_TEST_PRECEDENCE\tenv\nC\tunset\n2\tunset\n",
+            file.display()
+        )));
+}
+
+#[test]
+fn quiet_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--quiet", "--warn-positional"])
+        .write_stdin("a$1\n")
+        .assert()
+        .success()
+        .stdout(diff("a\n"))
+        .stderr(diff(""));
+}
+
+#[test]
+fn quiet_error() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["-q", "-V"])
+        .assert()
+        .code(2)
+        .stderr(contains("cannot be used with"));
+}
+
+#[test]
+fn verbose_success() {
+    let mut file = temp_dir();
+    file.push(Uuid::new_v4().to_string() + "-xpanda-test-verbose.env");
+    fs::write(&file, "A=a\nB=b\n").unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["-V", "-f"])
+        .arg(&file)
+        .args(["-v", "C=", "one", "two"])
+        .write_stdin("$A\n")
+        .assert()
+        .success()
+        .stdout(diff("a\n"))
+        .stderr(diff(format!(
+            "Loaded 2 variable(s) from '{}'\n\
+             Loaded 1 variable(s) from --var\n\
+             Loaded 2 positional variable(s) from the arguments\n\
+             Expanding standard input to standard output\n",
+            file.display()
+        )));
+}
+
+#[test]
+fn verbose_substitutions() {
+    let assert = |args: &[&str]| {
+        Command::cargo_bin("xpanda-cli")
+            .unwrap()
+            .args(["-VV", "-v", "A=a", "-v", "B="])
+            .args(args)
+            .write_stdin("$A $A\n${B:-$A}\n\n$C\n")
+            .assert()
+            .success()
+            .stdout(diff("a a\na\n\n\n"))
+            .stderr(contains(
+                "Line 1: 2 substitution(s)\n\
+                 Line 2: 2 substitution(s)\n\
+                 Line 4: 1 substitution(s)\n",
+            ));
+    };
+
+    assert(&[]);
+    assert(&["--conditionals"]);
+}
+
+#[test]
+fn json_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--json", "-v", "A=a", "one"])
+        .write_stdin("$1 $A ${B:-b}\n")
+        .assert()
+        .success()
+        .stdout(diff(
+            r#"{
+  "output": "one a b\n",
+  "missing": [
+    "B"
+  ],
+  "used": {
+    "1": "positional",
+    "A": "--var"
+  },
+  "unused": {},
+  "resolutions": {
+    "1": {
+      "state": "set",
+      "length": 3
+    },
+    "A": {
+      "state": "set",
+      "length": 1
+    },
+    "B": {
+      "state": "unset",
+      "length": null
+    }
+  },
+  "params": [
+    {
+      "text": "$1",
+      "line": 1,
+      "col": 1,
+      "value": "one"
+    },
+    {
+      "text": "$A",
+      "line": 1,
+      "col": 4,
+      "value": "a"
+    },
+    {
+      "text": "${B:-b}",
+      "line": 1,
+      "col": 7,
+      "value": "b"
+    }
+  ],
+  "errors": []
+}
+"#,
+        ));
+}
+
+#[test]
+fn json_error() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--json", "--env-vars=false"])
+        .write_stdin("$A\n${B?no B}\n")
+        .assert()
+        .code(3)
+        .stdout(diff(
+            r#"{
+  "output": null,
+  "missing": [
+    "A",
+    "B"
+  ],
+  "used": {},
+  "unused": {},
+  "resolutions": {
+    "A": {
+      "state": "unset",
+      "length": null
+    },
+    "B": {
+      "state": "unset",
+      "length": null
+    }
+  },
+  "params": [
+    {
+      "text": "$A",
+      "line": 1,
+      "col": 1,
+      "value": ""
+    },
+    {
+      "text": "${B?no B}",
+      "line": 2,
+      "col": 1,
+      "value": null
+    }
+  ],
+  "errors": [
+    {
+      "kind": "required",
+      "message": "no B",
+      "line": 2,
+      "col": 1
+    }
+  ]
+}
+"#,
+        ))
+        .stderr(contains("no B"));
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--json"])
+        .write_stdin("${")
+        .assert()
+        .code(5)
+        .stdout(contains(r#""kind": "syntax""#));
+}
+
+#[test]
+fn deny_unused_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--deny-unused", "-v", "A=a", "-v", "B=b", "--alias", "C=B"])
+        .write_stdin("$A $C\n")
+        .assert()
+        .success()
+        .stdout(diff("a b\n"));
+}
+
+#[test]
+fn deny_unused_dynamic_names() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args([
+            "--dynamic-names",
+            "--deny-unused",
+            "-v",
+            "ENV=PROD",
+            "-v",
+            "DB_PROD=x",
+        ])
+        .write_stdin("${DB_${ENV}}\n")
+        .assert()
+        .success()
+        .stdout(diff("x\n"));
+}
+
+#[test]
+fn deny_unused_error() {
+    let mut file = temp_dir();
+    file.push(Uuid::new_v4().to_string() + "-xpanda-test-unused.env");
+    fs::write(&file, "HOST=db\nPROT=5432\n").unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .arg("--deny-unused")
+        .args(["-v", "USER=me", "-f"])
+        .arg(&file)
+        .write_stdin("$HOST:${PORT:-80}\n")
+        .assert()
+        .code(1)
+        .stdout(diff(""))
+        .stderr(contains(format!(
+            "Provided variable(s) 'PROT' (file 1 ({})), 'USER' (--var) are never referenced",
+            file.display()
+        )));
+}
+
+#[test]
+fn json_resolutions() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--json", "--env-vars=false", "-v", "A=", "-v", "B=åä"])
+        .write_stdin("${A-a}${A:-a}${B:-b}${C-c}\n")
+        .assert()
+        .success()
+        .stdout(contains(
+            r#"  "resolutions": {
+    "A": {
+      "state": "empty",
+      "length": 0
+    },
+    "B": {
+      "state": "set",
+      "length": 2
+    },
+    "C": {
+      "state": "unset",
+      "length": null
+    }
+  },"#,
+        ));
+}
+
+#[test]
+fn json_unused() {
+    let assert = |args: &[&str], code: i32| {
+        Command::cargo_bin("xpanda-cli")
+            .unwrap()
+            .args(args)
+            .write_stdin("$A\n")
+            .assert()
+            .code(code)
+            .stdout(contains("\"unused\": {\n    \"B\": \"--var\"\n  },"));
+    };
+
+    assert(&["--json", "-v", "A=a", "-v", "B=b"], 0);
+    assert(&["--json", "--deny-unused", "-v", "A=a", "-v", "B=b"], 1);
+}
+
+#[test]
+fn json_output_file() {
+    let mut file = temp_dir();
+    file.push(Uuid::new_v4().to_string() + "-xpanda-test-output.json");
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--json", "-o", file.to_str().unwrap(), "-v", "A=a"])
+        .write_stdin("$A\n")
+        .assert()
+        .success()
+        .stdout(diff(""));
+
+    let content = fs::read_to_string(&file).unwrap();
+    assert!(content.starts_with("{\n  \"output\": \"a\\n\","));
+}
+
+#[test]
+fn context_delimiter() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--context", "--delimiter", "---"])
+        .write_stdin("${xpanda:line}\n---\n\n${xpanda:line}${xpanda:file}\n")
+        .assert()
+        .success()
+        .stdout(diff("1\n---\n\n4\n"));
+}
+
+#[test]
+fn context_invalid_source_date_epoch() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .env("SOURCE_DATE_EPOCH", "yesterday")
+        .args(["--context"])
+        .write_stdin("${xpanda:timestamp}")
+        .assert()
+        .code(1)
+        .stderr(diff(
+            "Invalid SOURCE_DATE_EPOCH 'yesterday', expected a number of seconds",
+        ));
+}
+
+#[test]
+fn safe_mode_error() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--safe-mode", "-v", "A=a"])
+        .write_stdin("$A\n${env:HOME}\n")
+        .assert()
+        .code(5)
+        .stdout(diff("a\n"))
+        .stderr(diff(
+            "2:6 Namespaced variables are not allowed in safe mode",
+        ));
+}
+
+#[test]
+fn safe_mode_check() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["check", "--safe-mode"])
+        .write_stdin("${file:/etc/passwd}")
+        .assert()
+        .code(5)
+        .stderr(contains(
+            "Namespaced variables are not allowed in safe mode",
+        ));
+}
+
+#[test]
+fn raw_blocks_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--raw-blocks", "-v", "A=a"])
+        .write_stdin("$A\n$[[raw]]\nfor f in \"$@\"; do\n  echo \"${f%.sh}\"\ndone\n$[[/raw]]\n")
+        .assert()
+        .success()
+        .stdout(diff(
+            "a\n\nfor f in \"$@\"; do\n  echo \"${f%.sh}\"\ndone\n\n",
+        ));
+}
+
+#[test]
+fn raw_blocks_unclosed_error() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--raw-blocks"])
+        .write_stdin("$[[raw]]\n$1\n")
+        .assert()
+        .code(5)
+        .stderr(contains("Unclosed raw block"));
+}
+
+#[test]
+fn comments_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--comments", "-v", "A=a"])
+        .write_stdin("$# Comment with $UNSET_VAR\n$A\n$$# text\n")
+        .assert()
+        .success()
+        .stdout(diff("a\n$# text\n"));
+}
+
+#[test]
+fn schema_success() {
+    let mut file = temp_dir();
+    file.push(Uuid::new_v4().to_string() + "-xpanda-test-schema.toml");
+    fs::write(
+        &file,
+        "[vars.LEVEL]\nrequired = true\nvalues = [\"debug\", \"info\"]\n",
+    )
+    .unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--schema", file.to_str().unwrap(), "-v", "LEVEL=info"])
+        .write_stdin("level=$LEVEL\n")
+        .assert()
+        .success()
+        .stdout(diff("level=info\n"));
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--schema", file.to_str().unwrap(), "-v", "LEVEL=loud"])
+        .write_stdin("text\nlevel=$LEVEL\n")
+        .assert()
+        .code(6)
+        .stdout(diff(""))
+        .stderr(contains("2:7 'LEVEL' must be one of 'debug', 'info'"));
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--schema", file.to_str().unwrap(), "--env-vars=false"])
+        .write_stdin("text\n")
+        .assert()
+        .code(3)
+        .stderr(contains("'LEVEL' is required by the schema but unset"));
+}
+
+#[test]
+fn schema_file_error() {
+    let mut file = temp_dir();
+    file.push(Uuid::new_v4().to_string() + "-xpanda-test-schema.toml");
+    fs::write(&file, "[vars.A]\npattern = \"(\"\n").unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--schema", file.to_str().unwrap()])
+        .write_stdin("$A")
+        .assert()
+        .code(1)
+        .stderr(contains("Invalid pattern '('"));
+}
+
+#[test]
+#[cfg(feature = "length")]
+fn arity_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(&["--", "one", "two"])
+        .write_stdin("${#}")
+        .assert()
+        .success()
+        .stdout(diff("2"));
+}
+
+#[test]
+#[cfg(feature = "length")]
+fn length_mode_success() {
+    for (mode, length) in [("bytes", "4"), ("chars", "3"), ("graphemes", "2")] {
+        Command::cargo_bin("xpanda-cli")
+            .unwrap()
+            .args(["--length-mode", mode, "--", "e\u{301}a"])
+            .write_stdin("${#1}")
+            .assert()
+            .success()
+            .stdout(diff(length));
+    }
+}
+
+#[test]
+#[cfg(feature = "indirection")]
+fn ref_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(&["-v", "VAR=woop"])
+        .args(&["--", "VAR"])
+        .write_stdin("${!1}")
+        .assert()
+        .success()
+        .stdout(diff("woop"));
+}
+
+#[test]
+fn input_file_success() {
+    let mut file = temp_dir();
+    file.push(Uuid::new_v4().to_string() + "-xpanda-test-input");
+    fs::write(&file, "$VAR").unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(&["-i", file.to_str().unwrap()])
+        .args(&["-v", "VAR=woop"])
+        .assert()
+        .success()
+        .stdout(diff("woop"));
+}
+
+#[test]
+fn output_file_success() {
+    let mut file = temp_dir();
+    file.push(Uuid::new_v4().to_string() + "-xpanda-test-output");
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(&["-o", file.to_str().unwrap()])
+        .args(&["-v", "VAR=woop"])
+        .write_stdin("$VAR")
+        .assert()
+        .success()
+        .stdout(diff(""));
+
+    let content = fs::read_to_string(&file).unwrap();
+    assert_eq!(content, "woop");
+}
+
+#[test]
+fn rotate_size_success() {
+    let mut file = temp_dir();
+    file.push(Uuid::new_v4().to_string() + "-xpanda-test-output");
+    let rotated = file.with_extension("1");
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["-o", file.to_str().unwrap(), "--rotate-size", "10"])
+        .args(["-v", "VAR=woop"])
+        .write_stdin("$VAR\n$VAR\n$VAR\n")
+        .assert()
+        .success()
+        .stdout(diff(""));
+
+    assert_eq!(fs::read_to_string(&rotated).unwrap(), "woop\nwoop\n");
+    assert_eq!(fs::read_to_string(&file).unwrap(), "woop\n");
+}
+
+#[test]
+fn rotate_size_taken() {
+    let mut file = temp_dir();
+    file.push(Uuid::new_v4().to_string() + "-xpanda-test-output");
+    let taken = file.with_extension("1");
+    let rotated = file.with_extension("2");
+    fs::write(&taken, "taken\n").unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["-o", file.to_str().unwrap(), "--rotate-size", "10"])
+        .args(["-v", "VAR=woop"])
+        .write_stdin("$VAR\n$VAR\n$VAR\n")
+        .assert()
+        .success()
+        .stdout(diff(""));
+
+    assert_eq!(fs::read_to_string(&taken).unwrap(), "taken\n");
+    assert_eq!(fs::read_to_string(&rotated).unwrap(), "woop\nwoop\n");
+    assert_eq!(fs::read_to_string(&file).unwrap(), "woop\n");
+}
+
+#[test]
+fn rotate_size_without_output_error() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--rotate-size", "10"])
+        .write_stdin("")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn no_trailing_newline_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["-n", "-v", "VAR=woop"])
+        .write_stdin("$VAR\n$VAR\r\n")
+        .assert()
+        .success()
+        .stdout(diff("woop\nwoop"));
+}
+
+#[test]
+fn no_trailing_newline_without_newline_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--no-trailing-newline", "-v", "VAR=woop"])
+        .write_stdin("$VAR\n\n$VAR")
+        .assert()
+        .success()
+        .stdout(diff("woop\n\nwoop"));
+}
+
+#[test]
+fn ensure_trailing_newline_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--ensure-trailing-newline", "-v", "VAR=woop"])
+        .write_stdin("$VAR\n$VAR")
+        .assert()
+        .success()
+        .stdout(diff("woop\nwoop\n"));
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--ensure-trailing-newline", "-v", "VAR=woop"])
+        .write_stdin("$VAR\n")
+        .assert()
+        .success()
+        .stdout(diff("woop\n"));
+}
+
+#[test]
+fn trailing_newline_conflict_error() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["-n", "--ensure-trailing-newline"])
+        .write_stdin("")
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn fmt_no_trailing_newline_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["fmt", "-n"])
+        .write_stdin("${VAR}\n")
+        .assert()
+        .success()
+        .stdout(diff("${VAR}"));
+}
+
+#[test]
+fn var_file_success() {
+    let mut file = temp_dir();
+    file.push(Uuid::new_v4().to_string() + "-xpanda-test-vars");
+    fs::write(&file, "VAR=woop").unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(&["-f", file.to_str().unwrap()])
+        .write_stdin("$VAR")
+        .assert()
+        .success()
+        .stdout(diff("woop"));
+}
+
+#[test]
+fn var_file_duplicate_success() {
+    let mut file = temp_dir();
+    file.push(Uuid::new_v4().to_string() + "-xpanda-test-vars");
+    fs::write(&file, "VAR=woop\nVAR=boop").unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["-f", file.to_str().unwrap()])
+        .write_stdin("$VAR")
+        .assert()
+        .success()
+        .stdout(diff("boop"))
+        .stderr(diff(""));
+}
+
+#[test]
+fn strict_var_files_error() {
+    let mut file = temp_dir();
+    file.push(Uuid::new_v4().to_string() + "-xpanda-test-vars");
+    fs::write(&file, "VAR=woop\nOTHER=value\nVAR=boop").unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--strict-var-files", "-f", file.to_str().unwrap()])
+        .write_stdin("$VAR")
+        .assert()
+        .code(1)
+        .stdout(diff(""))
+        .stderr(diff(format!(
+            "Duplicate key 'VAR' in var file '{}' on lines 1 and 3 with different values",
+            file.display()
+        )));
+}
+
+#[test]
+fn strict_var_files_warning() {
+    let mut file = temp_dir();
+    file.push(Uuid::new_v4().to_string() + "-xpanda-test-vars");
+    fs::write(&file, "VAR=woop\nVAR=woop").unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--strict-var-files", "-f", file.to_str().unwrap()])
+        .write_stdin("$VAR")
+        .assert()
+        .success()
+        .stdout(diff("woop"))
+        .stderr(diff(format!(
+            "Warning: Duplicate key 'VAR' in var file '{}' on lines 1 and 2\n",
+            file.display()
+        )));
+}
+
+#[test]
+fn strict_warning() {
+    let mut file = temp_dir();
+    file.push(Uuid::new_v4().to_string() + "-xpanda-test-vars");
+    fs::write(&file, "VAR=woop\nVAR=woop").unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--strict", "-f", file.to_str().unwrap()])
+        .write_stdin("$VAR")
+        .assert()
+        .code(1)
+        .stdout(diff(""))
+        .stderr(diff(format!(
+            "Duplicate key 'VAR' in var file '{}' on lines 1 and 2",
+            file.display()
+        )));
+}
+
+#[test]
+fn strict_duplicate_var() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--strict", "-v", "VAR=woop", "-v", "VAR=boop"])
+        .write_stdin("$VAR")
+        .assert()
+        .code(1)
+        .stdout(diff(""))
+        .stderr(diff(
+            "Variable 'VAR' is passed more than once using --var with different values",
+        ));
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--strict", "-v", "VAR=woop", "-v", "VAR=woop"])
+        .write_stdin("$VAR")
+        .assert()
+        .success()
+        .stdout(diff("woop"));
+}
+
+#[test]
+fn strict_unset_and_empty() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--strict", "-v", "VAR="])
+        .write_stdin("$VAR")
+        .assert()
+        .code(4)
+        .stdout(diff(""))
+        .stderr(diff("1:1 'VAR' is empty"));
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--strict", "-v", "VAR="])
+        .write_stdin("${VAR:-default} $OTHER")
+        .assert()
+        .code(4)
+        .stdout(diff(""))
+        .stderr(diff("1:17 'OTHER' is unset"));
+}
+
+#[test]
+fn var_file_set_format_utf16_success() {
+    let mut file = temp_dir();
+    file.push(Uuid::new_v4().to_string() + "-xpanda-test-vars");
+    let text = "=C:=C:\\\r\nPATH=C:\\bin;D:\\x=y\r\nUSERNAME=woop\r\n";
+    let mut bytes = vec![0xFF, 0xFE];
+    bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+    fs::write(&file, bytes).unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--var-file-format", "set", "-f", file.to_str().unwrap()])
+        .write_stdin("$USERNAME $PATH")
+        .assert()
+        .success()
+        .stdout(diff("woop C:\\bin;D:\\x=y"));
+}
+
+#[test]
+fn var_file_reg_format_success() {
+    let mut file = temp_dir();
+    file.push(Uuid::new_v4().to_string() + "-xpanda-test-vars");
+    let text = concat!(
+        "Windows Registry Editor Version 5.00\r\n",
+        "\r\n",
+        "[HKEY_CURRENT_USER\\Environment]\r\n",
+        "\"TEMP\"=\"C:\\\\Temp \\\"quoted\\\"\"\r\n",
+        "\"Path\"=hex(2):25,00,41,00,25,00,3b,00,\\\r\n",
+        "  43,00,00,00\r\n",
+        "\"COUNT\"=dword:00000001\r\n",
+    );
+    fs::write(&file, text).unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--var-file-format", "reg", "-f", file.to_str().unwrap()])
+        .write_stdin("$TEMP $Path")
+        .assert()
+        .success()
+        .stdout(diff("C:\\Temp \"quoted\" %A%;C"))
+        .stderr(diff(format!(
+            "Warning: Ignoring key 'COUNT' in var file '{}' on line 7, only string values are \
+             supported\n",
+            file.display()
+        )));
+}
+
+#[test]
+fn file_root_success() {
+    let mut root = temp_dir();
+    root.push(Uuid::new_v4().to_string() + "-xpanda-test-root");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("cert.pem"), "woop\n").unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args([
+            "--file-root",
+            root.to_str().unwrap(),
+            "--trim-file-newlines",
+        ])
+        .write_stdin("cert=${file:cert.pem}")
+        .assert()
+        .success()
+        .stdout(diff("cert=woop"));
+}
+
+#[test]
+fn file_outside_root_error() {
+    let mut root = temp_dir();
+    root.push(Uuid::new_v4().to_string() + "-xpanda-test-root");
+    fs::create_dir_all(&root).unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--file-root", root.to_str().unwrap()])
+        .write_stdin("${file:/etc/passwd}")
+        .assert()
+        .code(7)
+        .stdout(diff(""))
+        .stderr(diff(
+            "1:1 Failed to resolve 'file:/etc/passwd': File '/etc/passwd' is outside of the root \
+             directory",
+        ));
+}
+
+#[test]
+fn unexpected_eof_error() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(&["-v", "VAR=woop"])
+        .write_stdin("${VAR")
+        .assert()
+        .failure()
+        .stderr(diff("1:1 Expected a matching '}' for the '${' opened"));
+}
+
+#[test]
+fn unexpected_token_error() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(&["-v", "VAR=woop"])
+        .write_stdin("${VAR-:def}")
+        .assert()
+        .failure()
+        .stderr(diff("1:7 Unexpected token ':'"));
+}
+
+#[test]
+fn multiline_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(&["-v", "DEF=def"])
+        .args(&["--", "jkl"])
+        .write_stdin("abc$DEF\nghi$1")
+        .assert()
+        .success()
+        .stdout(diff("abcdef\nghijkl"));
+}
+
+#[test]
+fn completions_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["completions", "bash"])
+        .assert()
+        .success()
+        .stdout(contains("_xpanda()"));
+}
+
+#[test]
+fn man_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .arg("man")
+        .assert()
+        .success()
+        .stdout(contains(".TH xpanda 1"));
+}
+
+#[test]
+fn expand_command_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["expand", "-v", "VAR=woop"])
+        .write_stdin("$VAR")
+        .assert()
+        .success()
+        .stdout(diff("woop"));
+}
+
+#[test]
+fn check_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .arg("check")
+        .write_stdin("${VAR:-default}\n$OTHER")
+        .assert()
+        .success()
+        .stdout(diff(""));
+}
+
+#[test]
+fn check_error() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .arg("check")
+        .write_stdin("text\n${VAR")
+        .assert()
+        .failure()
+        .stderr(diff("2:1 Expected a matching '}' for the '${' opened"));
+}
+
+#[test]
+fn check_schema_error() {
+    let mut file = temp_dir();
+    file.push(Uuid::new_v4().to_string() + "-xpanda-test-schema.toml");
+    fs::write(
+        &file,
+        "[vars.XPANDA_TEST_CHECK_PORT]\npattern = \"[0-9]+\"\n",
+    )
+    .unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["check", "--schema", file.to_str().unwrap()])
+        .env("XPANDA_TEST_CHECK_PORT", "abc")
+        .write_stdin("port=$XPANDA_TEST_CHECK_PORT\n${XPANDA_TEST_CHECK_PORT}")
+        .assert()
+        .code(6)
+        .stdout(diff(""))
+        .stderr(diff(
+            "1:6 'XPANDA_TEST_CHECK_PORT' doesn't match the pattern '[0-9]+'\n\
+             2:1 'XPANDA_TEST_CHECK_PORT' doesn't match the pattern '[0-9]+'\n\
+             2 schema violation(s) found",
+        ));
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["check", "--schema", file.to_str().unwrap()])
+        .env("XPANDA_TEST_CHECK_PORT", "80")
+        .write_stdin("port=$XPANDA_TEST_CHECK_PORT")
+        .assert()
+        .success();
+}
+
+#[test]
+fn vars_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .arg("vars")
+        .write_stdin("$B ${A:-$1}\n$B")
+        .assert()
+        .success()
+        .stdout(diff("B\nA\n1\n"));
+}
+
+#[test]
+fn vars_doc_success() {
+    let mut dir = temp_dir();
+    dir.push(Uuid::new_v4().to_string() + "-xpanda-test-doc");
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    fs::create_dir_all(dir.join(".git")).unwrap();
+    fs::write(dir.join("a.tmpl"), "$DB_URL\n${PORT:-80}").unwrap();
+    fs::write(dir.join("nested").join("b.tmpl"), "${PORT}").unwrap();
+    fs::write(dir.join(".git").join("config"), "$HIDDEN").unwrap();
+    let schema = dir.with_extension("schema.toml");
+    fs::write(
+        &schema,
+        "[vars.DB_URL]\ndescription = \"The database | primary\"\n",
+    )
+    .unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["vars", "--doc", dir.to_str().unwrap()])
+        .args(["--schema", schema.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(diff(format!(
+            "| Variable | Used in | Default | Description |\n\
+             |----------|---------|---------|-------------|\n\
+             | `DB_URL` | `a.tmpl:1:1` | Never | The database \\| primary |\n\
+             | `PORT` | `a.tmpl:2:1`, `{}:1:1` | Sometimes |  |\n",
+            std::path::Path::new("nested").join("b.tmpl").display()
+        )));
+}
+
+#[test]
+fn vars_doc_json_success() {
+    let mut dir = temp_dir();
+    dir.push(Uuid::new_v4().to_string() + "-xpanda-test-doc");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.tmpl"), "${PORT:-80}").unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args([
+            "vars",
+            "--doc",
+            dir.to_str().unwrap(),
+            "--doc-format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .stdout(diff(
+            r#"[
+  {
+    "name": "PORT",
+    "usages": [
+      {
+        "file": "a.tmpl",
+        "line": 1,
+        "col": 1
+      }
+    ],
+    "defaults": "always",
+    "description": null
+  }
+]
+"#,
+        ));
+}
+
+#[test]
+#[cfg(unix)]
+fn vars_doc_symlinks() {
+    use std::os::unix::fs::symlink;
+
+    let mut dir = temp_dir();
+    dir.push(Uuid::new_v4().to_string() + "-xpanda-test-doc");
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    fs::write(dir.join("a.tmpl"), "$A").unwrap();
+    symlink(&dir, dir.join("nested").join("loop")).unwrap();
+    symlink(dir.join("a.tmpl"), dir.join("b.tmpl")).unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["vars", "--doc", dir.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(diff(
+            "| Variable | Used in | Default | Description |\n\
+             |----------|---------|---------|-------------|\n\
+             | `A` | `a.tmpl:1:1`, `b.tmpl:1:1` | Never |  |\n",
+        ));
+}
+
+#[test]
+fn vars_graph_dot_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["vars", "--graph", "dot"])
+        .write_stdin("$A ${B:-$C} ${A:+$C} $A")
+        .assert()
+        .success()
+        .stdout(diff(
+            r#"digraph variables {
+    "$" [label="template", shape=box];
+    "A";
+    "B";
+    "C";
+    "$" -> "A";
+    "$" -> "B";
+    "B" -> "C" [label="default"];
+    "A" -> "C" [label="alternative"];
+}
+"#,
+        ));
+}
+
+#[test]
+#[cfg(feature = "indirection")]
+fn vars_graph_json_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["vars", "--graph", "json"])
+        .write_stdin("${A-${!B}}")
+        .assert()
+        .success()
+        .stdout(diff(
+            r#"{
+  "variables": [
+    "A",
+    "B"
+  ],
+  "edges": [
+    {
+      "from": null,
+      "to": "A",
+      "kind": "direct",
+      "indirect": false
+    },
+    {
+      "from": "A",
+      "to": "B",
+      "kind": "default",
+      "indirect": true
+    }
+  ]
+}
+"#,
+        ));
+}
+
+#[test]
+fn vars_doc_error() {
+    let mut dir = temp_dir();
+    dir.push(Uuid::new_v4().to_string() + "-xpanda-test-doc");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.tmpl"), "text\n${PORT").unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["vars", "--doc", dir.to_str().unwrap()])
+        .assert()
+        .code(5)
+        .stderr(diff(
+            "2:1 Expected a matching '}' for the '${' opened (in 'a.tmpl')",
+        ));
+}
+
+#[test]
+fn fmt_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .arg("fmt")
+        .write_stdin("$A ${B-$C}\n$$D")
+        .assert()
+        .success()
+        .stdout(diff("${A} ${B-${C}}\n$$D"));
+}
+
+#[test]
+fn fmt_quoting_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["fmt", "--quoting"])
+        .write_stdin(r#"$A ${B-'say "hi"'} ${C-"a } b"}"#)
+        .assert()
+        .success()
+        .stdout(diff(r#"${A} ${B-'say "hi"'} ${C-"a } b"}"#));
+}
+
+#[test]
+fn color_always_error() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--color", "always", "-v", "VAR=woop"])
+        .write_stdin("${VAR")
+        .assert()
+        .failure()
+        .stderr(diff(concat!(
+            "\u{1b}[1;31merror\u{1b}[0m\u{1b}[1m: Expected a matching '}' for the '${' opened\u{1b}[0m\n",
+            " \u{1b}[1;34m-->\u{1b}[0m 1:1\n",
+            "  \u{1b}[1;34m|\u{1b}[0m\n",
+            "\u{1b}[1;34m1 |\u{1b}[0m ${VAR\n",
+            "  \u{1b}[1;34m|\u{1b}[0m \u{1b}[1;31m^\u{1b}[0m\n",
+        )));
+}
+
+#[test]
+fn color_never_error() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["check", "--color", "never"])
+        .write_stdin("${VAR")
+        .assert()
+        .failure()
+        .stderr(diff("1:1 Expected a matching '}' for the '${' opened"));
+}
+
+#[test]
+fn no_color_env_error() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .env("NO_COLOR", "1")
+        .args(["--color", "auto", "check"])
+        .write_stdin("${VAR")
+        .assert()
+        .failure()
+        .stderr(diff("1:1 Expected a matching '}' for the '${' opened"));
+}
+
+#[test]
+fn expand_args_with_command_error() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["-v", "VAR=woop", "check"])
+        .write_stdin("$VAR")
+        .assert()
+        .failure()
+        .stderr(contains(
+            "the argument '--var' cannot be used with the 'check' command",
+        ));
+}
+
+#[test]
+fn required_exit_code() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .write_stdin("${VAR?}")
+        .assert()
+        .code(3);
+}
+
+#[test]
+fn no_unset_exit_code() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .arg("-u")
+        .write_stdin("$VAR")
+        .assert()
+        .code(4);
+}
+
+#[test]
+fn syntax_exit_code() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .write_stdin("${VAR")
+        .assert()
+        .code(5);
+}
+
+#[test]
+fn invalid_value_exit_code() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["-v", "VAR=woop!"])
+        .write_stdin("${VAR@base64d}")
+        .assert()
+        .code(6)
+        .stderr(diff("1:1 'VAR' is not valid base64-encoded UTF-8"));
+}
+
+#[test]
+fn check_syntax_exit_code() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .arg("check")
+        .write_stdin("${VAR")
+        .assert()
+        .code(5);
+}
+
+#[test]
+fn missing_var_file_exit_code() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["-f", "/nonexistent/xpanda-test-vars"])
+        .write_stdin("$VAR")
+        .assert()
+        .code(1);
+}
+
+#[test]
+#[cfg(feature = "length")]
+fn positional_from_env_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .env("XPANDA_TEST_ARG_1", "one")
+        .env("XPANDA_TEST_ARG_2", "two")
+        .env("VAR", "woop")
+        .args(["--positional-from-env", "XPANDA_TEST_ARG_"])
+        .write_stdin("$1 $2 ${#} $VAR")
+        .assert()
+        .success()
+        .stdout(diff("one two 2 "));
+}
+
+#[test]
+fn render_success() {
+    let mut dir = temp_dir();
+    dir.push(Uuid::new_v4().to_string() + "-xpanda-test-render");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("common.env"), "VAR=woop").unwrap();
+    fs::write(dir.join("a.tmpl"), "$VAR $PORT").unwrap();
+    fs::write(dir.join("b.tmpl"), "${VAR:-default}").unwrap();
+    fs::write(
+        dir.join("xpanda.toml"),
+        r#"
+            var_files = ["common.env"]
+
+            [[templates]]
+            template = "a.tmpl"
+            output = "out/a"
+            vars = { PORT = "80" }
+
+            [[templates]]
+            template = "b.tmpl"
+            output = "out/b"
+        "#,
+    )
+    .unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args([
+            "render",
+            "--config",
+            dir.join("xpanda.toml").to_str().unwrap(),
+        ])
+        .assert()
+        .success()
+        .stderr(contains("Rendered 2 template(s)"));
+
+    assert_eq!(fs::read_to_string(dir.join("out/a")).unwrap(), "woop 80");
+    assert_eq!(fs::read_to_string(dir.join("out/b")).unwrap(), "woop");
+}
+
+#[test]
+fn render_hash_file() {
+    let mut dir = temp_dir();
+    dir.push(Uuid::new_v4().to_string() + "-xpanda-test-render");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.tmpl"), "$VAR").unwrap();
+    fs::write(
+        dir.join("xpanda.toml"),
+        r#"
+            vars = { VAR = "woop" }
+            reproducible = true
+            hash_file = "xpanda.sha256"
+
+            [[templates]]
+            template = "a.tmpl"
+            output = "out/b"
+
+            [[templates]]
+            template = "a.tmpl"
+            output = "out/a"
+        "#,
+    )
+    .unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["render", "-c", dir.join("xpanda.toml").to_str().unwrap()])
+        .assert()
+        .success();
+
+    let hash = "bdde2e5e8d3818f2d1a6212cb25497354b063ed4168cd30d01471bc531c16260";
+    let (a, b) = (Path::new("out").join("a"), Path::new("out").join("b"));
+    assert_eq!(
+        fs::read_to_string(dir.join("xpanda.sha256")).unwrap(),
+        format!("{hash}  {}\n{hash}  {}\n", a.display(), b.display())
+    );
+}
+
+#[test]
+fn render_error() {
+    let mut dir = temp_dir();
+    dir.push(Uuid::new_v4().to_string() + "-xpanda-test-render");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.tmpl"), "${VAR?msg}").unwrap();
+    fs::write(dir.join("b.tmpl"), "text").unwrap();
+    fs::write(
+        dir.join("xpanda.toml"),
+        r#"
+            vars = { OTHER = "woop" }
+
+            [[templates]]
+            template = "a.tmpl"
+            output = "a"
+
+            [[templates]]
+            template = "b.tmpl"
+            output = "b"
+        "#,
+    )
+    .unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["render", "-c", dir.join("xpanda.toml").to_str().unwrap()])
+        .assert()
+        .code(3)
+        .stderr(contains("1:1 msg\n"))
+        .stderr(contains("1 of 2 template(s) failed to render"));
+
+    assert!(!dir.join("a").exists());
+    assert_eq!(fs::read_to_string(dir.join("b")).unwrap(), "text");
+}
+
+#[test]
+fn render_jobs() {
+    let mut dir = temp_dir();
+    dir.push(Uuid::new_v4().to_string() + "-xpanda-test-render");
+    fs::create_dir_all(&dir).unwrap();
+    let mut manifest = String::new();
+
+    for index in 0..8 {
+        let template = if index % 3 == 1 {
+            String::from("${UNSET?failed}")
+        } else {
+            format!("{index}")
+        };
+
+        fs::write(dir.join(format!("{index}.tmpl")), template).unwrap();
+        manifest += &format!("[[templates]]\ntemplate = \"{index}.tmpl\"\noutput = \"{index}\"\n");
+    }
+
+    fs::write(dir.join("xpanda.toml"), manifest).unwrap();
+
+    let expected = (0..8)
+        .map(|index| {
+            if index % 3 == 1 {
+                format!("Failed to render '{index}.tmpl':\n1:1 failed\n")
+            } else {
+                format!("Rendered '{index}.tmpl' to '{index}' (created)\n")
+            }
+        })
+        .collect::<String>()
+        + "3 of 8 template(s) failed to render";
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .current_dir(&dir)
+        .args(["render", "--jobs", "4"])
+        .assert()
+        .code(3)
+        .stderr(diff(expected));
+
+    for index in [0, 2, 3, 5, 6] {
+        assert_eq!(
+            fs::read_to_string(dir.join(index.to_string())).unwrap(),
+            index.to_string()
+        );
+    }
+}
+
+#[test]
+fn render_jobs_zero_error() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["render", "-j", "0"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn render_dry_run() {
+    let mut dir = temp_dir();
+    dir.push(Uuid::new_v4().to_string() + "-xpanda-test-render");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.tmpl"), "a\n$VAR\nc\n").unwrap();
+    fs::write(dir.join("b.tmpl"), "b\n").unwrap();
+    fs::write(dir.join("a"), "a\nold\nc\n").unwrap();
+    fs::write(dir.join("b"), "b\n").unwrap();
+    fs::write(
+        dir.join("xpanda.toml"),
+        r#"
+            vars = { VAR = "new" }
+
+            [[templates]]
+            template = "a.tmpl"
+            output = "a"
+
+            [[templates]]
+            template = "b.tmpl"
+            output = "b"
+        "#,
+    )
+    .unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .current_dir(&dir)
+        .args(["render", "--dry-run"])
+        .assert()
+        .code(8)
+        .stdout(diff("--- a\n+++ a\n@@ -1,3 +1,3 @@\n a\n-old\n+new\n c\n"))
+        .stderr(diff(
+            "Would change 'a'\n'b' is up to date\nwarning: 1 of 2 output(s) would change",
+        ));
+
+    assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "a\nold\nc\n");
+
+    fs::write(dir.join("a"), "a\nnew\nc\n").unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .current_dir(&dir)
+        .args(["render", "--dry-run"])
+        .assert()
+        .success()
+        .stdout("")
+        .stderr(contains("All 2 output(s) are up to date"));
+}
+
+#[test]
+fn render_long_line() {
+    let mut dir = temp_dir();
+    dir.push(Uuid::new_v4().to_string() + "-xpanda-test-render");
+    fs::create_dir_all(&dir).unwrap();
+    // Longer than the 16 MiB held in memory at once
+    let text = "x".repeat(17 * 1024 * 1024);
+    fs::write(dir.join("a.tmpl"), format!("$VAR {text} $VAR\n")).unwrap();
+    fs::write(
+        dir.join("xpanda.toml"),
+        r#"
+            [vars]
+            VAR = "a"
+
+            [[templates]]
+            template = "a.tmpl"
+            output = "a"
+        "#,
+    )
+    .unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .current_dir(&dir)
+        .arg("render")
+        .assert()
+        .success();
+
+    assert!(fs::read_to_string(dir.join("a")).unwrap() == format!("a {text} a\n"));
+}
+
+#[test]
+fn render_atomic() {
+    let mut dir = temp_dir();
+    dir.push(Uuid::new_v4().to_string() + "-xpanda-test-render");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.tmpl"), "new").unwrap();
+    fs::write(dir.join("b.tmpl"), "${VAR?msg}").unwrap();
+    fs::write(dir.join("c.tmpl"), "c").unwrap();
+    fs::write(dir.join("a"), "old").unwrap();
+    fs::write(
+        dir.join("xpanda.toml"),
+        r#"
+            [[templates]]
+            template = "a.tmpl"
+            output = "a"
+
+            [[templates]]
+            template = "b.tmpl"
+            output = "out/b"
+
+            [[templates]]
+            template = "c.tmpl"
+            output = "new/dir/c"
+        "#,
+    )
+    .unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .current_dir(&dir)
+        .args(["render", "--atomic", "-j", "2"])
+        .env_remove("VAR")
+        .assert()
+        .code(3)
+        .stderr(contains(
+            "1 of 3 template(s) failed to render, no output files were written",
+        ));
+
+    assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "old");
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 5);
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .current_dir(&dir)
+        .args(["render", "--atomic"])
+        .env("VAR", "b")
+        .assert()
+        .success();
+
+    assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "new");
+    assert_eq!(fs::read_to_string(dir.join("out/b")).unwrap(), "b");
+    assert_eq!(fs::read_to_string(dir.join("new/dir/c")).unwrap(), "c");
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 7);
+}
+
+#[test]
+fn render_quiet() {
+    let mut dir = temp_dir();
+    dir.push(Uuid::new_v4().to_string() + "-xpanda-test-render");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.tmpl"), "${VAR:-a}").unwrap();
+    fs::write(dir.join("b.tmpl"), "${VAR?}").unwrap();
+    fs::write(
+        dir.join("xpanda.toml"),
+        r#"
+            [[templates]]
+            template = "a.tmpl"
+            output = "a"
+        "#,
+    )
+    .unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .current_dir(&dir)
+        .args(["render", "--quiet", "--timings", "--slow-threshold", "0"])
+        .assert()
+        .success()
+        .stderr(diff(""));
+
+    assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "a");
+
+    fs::write(
+        dir.join("xpanda.toml"),
+        r#"
+            [[templates]]
+            template = "b.tmpl"
+            output = "b"
+        "#,
+    )
+    .unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .current_dir(&dir)
+        .args(["-q", "render"])
+        .assert()
+        .code(3)
+        .stderr(contains("Failed to render 'b.tmpl':"))
+        .stderr(contains("1 of 1 template(s) failed to render"));
+}
+
+#[test]
+fn render_timings() {
+    let mut dir = temp_dir();
+    dir.push(Uuid::new_v4().to_string() + "-xpanda-test-render");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.tmpl"), "${VAR:-a}").unwrap();
+    fs::write(
+        dir.join("xpanda.toml"),
+        r#"
+            [[templates]]
+            template = "a.tmpl"
+            output = "a"
+        "#,
+    )
+    .unwrap();
+
+    let assert = Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .current_dir(&dir)
+        .args(["render", "--timings"])
+        .assert()
+        .success()
+        .stderr(contains("Timings for 'a.tmpl': lex "));
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+
+    assert!(
+        stderr.contains(", parse ") && stderr.contains(", eval ") && stderr.contains(", total ")
+    );
+    assert!(!stderr.contains("slow"));
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .current_dir(&dir)
+        .args(["render", "--timings", "--slow-threshold", "0"])
+        .assert()
+        .success()
+        .stderr(contains("warning: 'a.tmpl' is slow to render, taking "))
+        .stderr(contains(" which exceeds 0ns\n"));
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .current_dir(&dir)
+        .args(["render", "--slow-threshold", "0"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn render_unchanged() {
+    let mut dir = temp_dir();
+    dir.push(Uuid::new_v4().to_string() + "-xpanda-test-render");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.tmpl"), "a").unwrap();
+    fs::write(dir.join("b.tmpl"), "b").unwrap();
+    fs::write(dir.join("c.tmpl"), "c").unwrap();
+    fs::write(dir.join("a"), "a").unwrap();
+    fs::write(dir.join("b"), "old").unwrap();
+    fs::write(
+        dir.join("xpanda.toml"),
+        r#"
+            [[templates]]
+            template = "a.tmpl"
+            output = "a"
+
+            [[templates]]
+            template = "b.tmpl"
+            output = "b"
+
+            [[templates]]
+            template = "c.tmpl"
+            output = "c"
+        "#,
+    )
+    .unwrap();
+    let modified = || fs::metadata(dir.join("a")).unwrap().modified().unwrap();
+    let before = modified();
+    std::thread::sleep(std::time::Duration::from_millis(10));
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .current_dir(&dir)
+        .arg("render")
+        .assert()
+        .success()
+        .stderr(contains(
+            "Rendered 'a.tmpl' to 'a' (unchanged)\n\
+             Rendered 'b.tmpl' to 'b' (updated)\n\
+             Rendered 'c.tmpl' to 'c' (created)\n",
+        ));
+
+    assert_eq!(modified(), before);
+    assert_eq!(fs::read_to_string(dir.join("b")).unwrap(), "b");
+    assert_eq!(fs::read_to_string(dir.join("c")).unwrap(), "c");
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .current_dir(&dir)
+        .args(["render", "--force"])
+        .assert()
+        .success()
+        .stderr(contains(
+            "Rendered 'a.tmpl' to 'a' (updated)\n\
+             Rendered 'b.tmpl' to 'b' (updated)\n\
+             Rendered 'c.tmpl' to 'c' (updated)\n",
+        ));
+
+    assert_ne!(modified(), before);
+}
+
+#[test]
+fn export_success() {
+    let mut path = temp_dir();
+    path.push(Uuid::new_v4().to_string() + "-xpanda-test-export.env");
+    fs::write(
+        &path,
+        "URL=http://$HOST:${PORT:-80}/\nHOST=localhost\nQUOTE=it's $$5\nEXTRA=$XPANDA_TEST_EXTRA:b",
+    )
+    .unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .env("XPANDA_TEST_EXTRA", "a")
+        .args(["export", "-f", path.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(diff(
+            "export EXTRA='a:b'\nexport HOST='localhost'\nexport QUOTE='it'\\''s $5'\nexport URL='http://localhost:80/'\n",
+        ));
+}
+
+#[test]
+fn export_self_reference() {
+    let mut path = temp_dir();
+    path.push(Uuid::new_v4().to_string() + "-xpanda-test-export.env");
+    fs::write(&path, "XPANDA_TEST_PATH=$XPANDA_TEST_PATH:/opt/bin").unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .env("XPANDA_TEST_PATH", "/bin")
+        .args(["export", "-f", path.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(diff("export XPANDA_TEST_PATH='/bin:/opt/bin'\n"));
+}
+
+#[test]
+fn export_cycle_error() {
+    let mut path = temp_dir();
+    path.push(Uuid::new_v4().to_string() + "-xpanda-test-export.env");
+    fs::write(&path, "A=$B\nB=${C:-$A}\nC=").unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["export", "-f", path.to_str().unwrap()])
+        .assert()
+        .code(1)
+        .stderr(diff(
+            "Variables reference each other in a cycle: A -> B -> A",
+        ));
+}
+
+#[test]
+fn config_file_success() {
+    let mut dir = temp_dir();
+    dir.push(Uuid::new_v4().to_string() + "-xpanda-test-config");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("team.env"), "VAR=woop\nOTHER=config").unwrap();
+    fs::write(dir.join("cli.env"), "OTHER=cli").unwrap();
+    fs::write(
+        dir.join("config.toml"),
+        r#"
+            var_files = ["team.env"]
+            no_unset = true
+        "#,
+    )
+    .unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--config", dir.join("config.toml").to_str().unwrap()])
+        .args(["-f", dir.join("cli.env").to_str().unwrap()])
+        .write_stdin("$VAR $OTHER $MISSING")
+        .assert()
+        .failure()
+        .code(4);
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--config", dir.join("config.toml").to_str().unwrap()])
+        .args(["-f", dir.join("cli.env").to_str().unwrap()])
+        .write_stdin("$VAR $OTHER")
+        .assert()
+        .success()
+        .stdout(diff("woop cli"));
+}
+
+#[test]
+fn config_file_flag_disabled() {
+    let mut dir = temp_dir();
+    dir.push(Uuid::new_v4().to_string() + "-xpanda-test-config");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("config.toml"), "quoting = true\nno_unset = false").unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--config", dir.join("config.toml").to_str().unwrap()])
+        .args(["--quoting=false", "--no-unset"])
+        .write_stdin(r#"${VAR-"a"}"#)
+        .assert()
+        .success()
+        .stdout(diff("\"a\""));
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--config", dir.join("config.toml").to_str().unwrap()])
+        .args(["--no-unset"])
+        .write_stdin("$VAR")
+        .assert()
+        .code(4);
+}
+
+#[test]
+fn config_file_var_files_with_env_vars() {
+    let mut dir = temp_dir();
+    dir.push(Uuid::new_v4().to_string() + "-xpanda-test-config");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("team.env"), "VAR=team").unwrap();
+    fs::write(dir.join("config.toml"), r#"var_files = ["team.env"]"#).unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--config", dir.join("config.toml").to_str().unwrap()])
+        .env("XPANDA_TEST_CONFIG_ENV", "env")
+        .write_stdin("$VAR $XPANDA_TEST_CONFIG_ENV")
+        .assert()
+        .success()
+        .stdout(diff("team env"));
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--config", dir.join("config.toml").to_str().unwrap()])
+        .args(["-v", "OTHER=cli"])
+        .env("XPANDA_TEST_CONFIG_ENV", "env")
+        .write_stdin("$VAR $OTHER ${XPANDA_TEST_CONFIG_ENV-unset}")
+        .assert()
+        .success()
+        .stdout(diff("team cli unset"));
+}
+
+#[test]
+fn config_file_env_prefixes() {
+    let mut dir = temp_dir();
+    dir.push(Uuid::new_v4().to_string() + "-xpanda-test-config");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("config.toml"), r#"env_prefixes = ["APP_"]"#).unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--config", dir.join("config.toml").to_str().unwrap()])
+        .env("APP_NAME", "woop")
+        .env("TOKEN", "secret")
+        .write_stdin("$APP_NAME ${TOKEN-unset}")
+        .assert()
+        .success()
+        .stdout(diff("woop unset"));
+}
+
+#[test]
+fn config_file_default_location() {
+    let mut dir = temp_dir();
+    dir.push(Uuid::new_v4().to_string() + "-xpanda-test-config");
+    fs::create_dir_all(dir.join("xpanda")).unwrap();
+    fs::write(dir.join("xpanda").join("config.toml"), "quoting = true").unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .env("XDG_CONFIG_HOME", &dir)
+        .write_stdin(r#"${VAR-"a } b"}"#)
+        .assert()
+        .success()
+        .stdout(diff("a } b"));
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .env("XDG_CONFIG_HOME", &dir)
+        .arg("--no-config")
+        .write_stdin(r#"${VAR-"a } b"}"#)
+        .assert()
+        .success()
+        .stdout(diff("\"a  b\"}"));
+}
+
+#[test]
+fn config_file_missing_error() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["--config", "this-config-does-not-exist.toml"])
+        .write_stdin("")
+        .assert()
+        .failure()
+        .code(1)
+        .stderr(contains("Failed to open config file"));
+}
+
+#[test]
+fn lint_warning() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["lint", "--optional", "REGION"])
+        .write_stdin("${HOST}\n$REGION")
+        .assert()
+        .success()
+        .stderr(diff(
+            "2:1 warning: 'REGION' is optional but used without a default value \
+             [missing-default]\n",
+        ));
+}
+
+#[test]
+fn lint_deny_error() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["lint", "--deny", "unbraced-variable"])
+        .write_stdin("$A\n# xpanda-lint: allow unbraced-variable\n$B ${C}")
+        .assert()
+        .failure()
+        .code(1)
+        .stderr(diff(
+            "1:1 '$A' is not braced, write '${A}' instead [unbraced-variable]\n\
+             1 lint error(s) found",
+        ));
+}
+
+#[test]
+fn lint_json() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["lint", "--format", "json", "--allow", "unreachable-branch"])
+        .args(["--warn", "unbraced-variable"])
+        .write_stdin("${A:+${A:-x}} $B")
+        .assert()
+        .success()
+        .stdout(diff(
+            r#"[
+  {
+    "rule": "unbraced-variable",
+    "severity": "warning",
+    "message": "'$B' is not braced, write '${B}' instead",
+    "line": 1,
+    "col": 15,
+    "fix": {
+      "start": 14,
+      "end": 16,
+      "replacement": "${B}"
+    }
+  }
+]
+"#,
+        ));
+}
+
+#[test]
+fn fix_success() {
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["fix", "--warn", "unset-only-default"])
+        .write_stdin("$ {A} ${B-b} $C")
+        .assert()
+        .success()
+        .stdout(diff("${A} ${B:-b} $C"))
+        .stderr(diff("Applied 2 fix(es)\n"));
+}
+
+#[test]
+fn fix_write() {
+    let mut file = temp_dir();
+    file.push(Uuid::new_v4().to_string() + "-xpanda-test-fix");
+    fs::write(&file, "$VAR.txt").unwrap();
+
+    Command::cargo_bin("xpanda-cli")
+        .unwrap()
+        .args(["fix", "--warn", "unbraced-variable", "--write"])
+        .args(["-i", file.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(diff(""));
+
+    assert_eq!(fs::read_to_string(&file).unwrap(), "${VAR}.txt");
 }
@@ -1,12 +1,7 @@
 #![no_main]
 
 use libfuzzer_sys::fuzz_target;
-use xpanda::Xpanda;
 
-fuzz_target!(|data: &[u8]| {
-    let xpanda = Xpanda::default();
+mod harness;
 
-    if let Ok(input) = std::str::from_utf8(data) {
-        let _ = xpanda.expand(input);
-    }
-});
+fuzz_target!(|data: &[u8]| harness::fuzz_input(data));
@@ -0,0 +1,7 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+mod harness;
+
+fuzz_target!(|data: &[u8]| harness::fuzz_vars(data));
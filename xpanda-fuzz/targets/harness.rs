@@ -0,0 +1,55 @@
+//! The code run by the fuzz targets, shared with the tests of `xpanda` replaying the inputs in
+//! `xpanda-fuzz/regressions`.
+
+// Each target only uses its own function
+#![allow(dead_code)]
+
+use xpanda::Xpanda;
+
+pub fn fuzz_input(data: &[u8]) {
+    let xpanda = Xpanda::default();
+
+    if let Ok(input) = std::str::from_utf8(data) {
+        let _ = xpanda.expand(input);
+    }
+}
+
+// The input is split at null bytes into a template followed by the values of variables. Each
+// value is available both as a positional variable and as a named variable `V1`, `V2` and so on,
+// and the first byte selects the syntax options.
+pub fn fuzz_vars(data: &[u8]) {
+    let Some((&flags, data)) = data.split_first() else {
+        return;
+    };
+    let Ok(data) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    let mut parts = data.split('\0');
+    let input = parts.next().unwrap_or_default();
+    let values = parts.map(String::from).collect::<Vec<_>>();
+    let named_vars = values
+        .iter()
+        .enumerate()
+        .map(|(index, value)| (format!("V{}", index + 1), value.clone()))
+        .collect();
+
+    let xpanda = Xpanda::builder()
+        .no_unset(flags & 1 != 0)
+        .quoting(flags & 2 != 0)
+        .lenient_identifiers(flags & 4 != 0)
+        .powershell_env(flags & 8 != 0)
+        .front_matter(flags & 16 != 0)
+        .safe_mode(flags & 32 != 0)
+        .raw_blocks(flags & 64 != 0)
+        .comments(flags & 128 != 0)
+        .with_named_vars(named_vars)
+        .with_positional_vars(values)
+        .build();
+
+    if let Ok(template) = xpanda.parse(input) {
+        let _ = template.to_string();
+        let _ = template.references();
+        let _ = xpanda.render(&template);
+    }
+}
@@ -1,6 +1,6 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 use std::collections::HashMap;
-use xpanda::Xpanda;
+use xpanda::{Template, Xpanda};
 
 pub fn expand(c: &mut Criterion) {
     let content = include_str!("input.txt");
@@ -14,5 +14,36 @@ pub fn expand(c: &mut Criterion) {
     c.bench_function("Xpanda::expand", |b| b.iter(|| xpanda.expand(content)));
 }
 
-criterion_group!(benches, expand);
+pub fn expand_text(c: &mut Criterion) {
+    // A large input consisting mostly of text, with a single variable per paragraph
+    let paragraph = include_str!("input.txt").replace('$', "");
+    let content = format!("{paragraph}$VAL\n").repeat(100);
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAL"), String::from("named"));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+
+    c.bench_function("Xpanda::expand text", |b| {
+        b.iter(|| xpanda.expand(&content))
+    });
+}
+
+pub fn render(c: &mut Criterion) {
+    // Parsing is excluded, comparing the recursive evaluation of templates to compiled ones
+    let content = include_str!("input.txt");
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAL"), String::from("named"));
+    let xpanda = Xpanda::builder()
+        .with_positional_vars(vec![String::from("one")])
+        .with_named_vars(named_vars)
+        .build();
+    let template = Template::parse(content).unwrap();
+    let compiled = template.clone().compile();
+
+    c.bench_function("Xpanda::render", |b| b.iter(|| xpanda.render(&template)));
+    c.bench_function("Xpanda::render_compiled", |b| {
+        b.iter(|| xpanda.render_compiled(&compiled))
+    });
+}
+
+criterion_group!(benches, expand, expand_text, render);
 criterion_main!(benches);
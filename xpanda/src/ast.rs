@@ -1,3 +1,8 @@
+#[cfg(feature = "expr")]
+use crate::expr::Expression;
+use crate::position::Position;
+use crate::syntax::{Syntax, ELSE, END, IF, RAW_BLOCK_CLOSE, RAW_BLOCK_OPEN};
+use std::borrow::Cow;
 use std::fmt::{self, Display, Formatter};
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -6,13 +11,55 @@ pub enum Identifier<'a> {
     Named(&'a str),
     // $1
     Indexed(usize),
+    // ${namespace:key}
+    Namespaced {
+        namespace: &'a str,
+        key: Cow<'a, str>,
+    },
+    // $?
+    Special(char),
+    // ${PREFIX_${ENV}}
+    // Only inside braces, if dynamic names are enabled. The name is computed by evaluating the
+    // nodes, which are either text or params.
+    Dynamic(Box<[Node<'a>]>),
+}
+
+impl<'a> Identifier<'a> {
+    /// Recovers the identifier from its [`Display`] form, such as a name computed from a dynamic
+    /// name or given to [`Template::bind`](crate::Template::bind). Single characters are only
+    /// special variables if `is_special` accepts them, so that the names of disabled special
+    /// variables stay named.
+    pub fn from_name(name: &'a str, is_special: impl Fn(char) -> bool) -> Self {
+        if let Ok(index) = name.parse() {
+            return Self::Indexed(index);
+        }
+
+        if let Some(c) = name
+            .chars()
+            .next()
+            .filter(|c| name.len() == 1 && is_special(*c))
+        {
+            return Self::Special(c);
+        }
+
+        match name.split_once(':') {
+            Some((namespace, key)) => Self::Namespaced {
+                namespace,
+                key: Cow::Borrowed(key),
+            },
+            None => Self::Named(name),
+        }
+    }
 }
 
 impl Display for Identifier<'_> {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
-            Self::Named(name) => write!(f, "{}", name),
-            Self::Indexed(index) => write!(f, "{}", index),
+            Self::Named(name) => write!(f, "{name}"),
+            Self::Indexed(index) => write!(f, "{index}"),
+            Self::Namespaced { namespace, key } => write!(f, "{namespace}:{key}"),
+            Self::Special(c) => write!(f, "{c}"),
+            Self::Dynamic(parts) => parts.iter().try_for_each(|part| write!(f, "{part}")),
         }
     }
 }
@@ -20,11 +67,121 @@ impl Display for Identifier<'_> {
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Modifier {
     // ${identifier^} | ${identifier^^}
-    Upper { all: bool },
+    #[cfg(feature = "case-modifiers")]
+    Upper {
+        all: bool,
+    },
     // ${identifier,} | ${identifier,,}
-    Lower { all: bool },
+    #[cfg(feature = "case-modifiers")]
+    Lower {
+        all: bool,
+    },
     // ${identifier~} | ${identifier~~}
-    Reverse { all: bool },
+    #[cfg(feature = "case-modifiers")]
+    Reverse {
+        all: bool,
+    },
+    // ${identifier@urlencode}
+    UrlEncode,
+    // ${identifier@urldecode}
+    UrlDecode,
+    // ${identifier@base64}
+    Base64Encode,
+    // ${identifier@base64d}
+    Base64Decode,
+    // ${identifier@shellquote}
+    ShellQuote,
+    // ${identifier@pad:width} | ${identifier@pad:0width}
+    Pad {
+        width: usize,
+        zero: bool,
+    },
+    // ${identifier@fixed:precision}
+    Fixed {
+        precision: usize,
+    },
+    // ${identifier@trunc:width} | ${identifier@trunc:width:ellipsis}
+    Truncate {
+        width: usize,
+        ellipsis: String,
+    },
+    // ${identifier#pattern} | ${identifier##pattern}
+    RemovePrefix {
+        pattern: String,
+        longest: bool,
+    },
+    // ${identifier%pattern} | ${identifier%%pattern}
+    RemoveSuffix {
+        pattern: String,
+        longest: bool,
+    },
+    // ${identifier/pattern/replacement} | ${identifier//pattern/replacement}
+    Replace {
+        pattern: String,
+        replacement: String,
+        all: bool,
+    },
+}
+
+impl Modifier {
+    fn fmt_with(&self, f: &mut Formatter, syntax: &Syntax) -> fmt::Result {
+        let operator = |f: &mut Formatter, symbol: char, all: bool| {
+            if all {
+                write!(f, "{symbol}{symbol}")
+            } else {
+                write!(f, "{symbol}")
+            }
+        };
+
+        match self {
+            #[cfg(feature = "case-modifiers")]
+            Self::Upper { all } => operator(f, '^', *all),
+            #[cfg(feature = "case-modifiers")]
+            Self::Lower { all } => operator(f, ',', *all),
+            #[cfg(feature = "case-modifiers")]
+            Self::Reverse { all } => operator(f, '~', *all),
+            Self::UrlEncode => write!(f, "@urlencode"),
+            Self::UrlDecode => write!(f, "@urldecode"),
+            Self::Base64Encode => write!(f, "@base64"),
+            Self::Base64Decode => write!(f, "@base64d"),
+            Self::ShellQuote => write!(f, "@shellquote"),
+            Self::Pad { width, zero } => write!(f, "@pad:{}{width}", if *zero { "0" } else { "" }),
+            Self::Fixed { precision } => write!(f, "@fixed:{precision}"),
+            Self::Truncate { width, ellipsis } if ellipsis.is_empty() => {
+                write!(f, "@trunc:{width}")
+            },
+            Self::Truncate { width, ellipsis } => {
+                write!(f, "@trunc:{width}:{}", escape_param(ellipsis, syntax))
+            },
+            Self::RemovePrefix { pattern, longest } => {
+                operator(f, '#', *longest)?;
+                write!(f, "{}", escape_pattern(pattern, '#', *longest, syntax))
+            },
+            Self::RemoveSuffix { pattern, longest } => {
+                operator(f, '%', *longest)?;
+                write!(f, "{}", escape_pattern(pattern, '%', *longest, syntax))
+            },
+            Self::Replace {
+                pattern,
+                replacement,
+                all,
+            } => {
+                operator(f, '/', *all)?;
+                write!(
+                    f,
+                    "{}/{}",
+                    escape_pattern(pattern, '/', *all, syntax),
+                    escape_param(replacement, syntax)
+                )
+            },
+        }
+    }
+}
+
+impl Display for Modifier {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.fmt_with(f, &Syntax::default())
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -33,6 +190,8 @@ pub enum Param<'a> {
     // ${identifier^} | ${identifier^^}
     // ${identifier,} | ${identifier,,}
     // ${identifier~} | ${identifier~~}
+    // ${identifier@name}
+    // ${identifier#pattern} | ${identifier%pattern} | ${identifier/pattern/replacement}
     Simple {
         identifier: Identifier<'a>,
         modifier: Option<Modifier>,
@@ -50,27 +209,191 @@ pub enum Param<'a> {
         treat_empty_as_unset: bool,
     },
     // ${identifier?} | ${identifier:?} | ${identifier?error} | ${identifier:?error}
+    // The error is empty if there's no custom message
     WithError {
         identifier: Identifier<'a>,
-        error: Option<String>,
+        error: Vec<Node<'a>>,
         treat_empty_as_unset: bool,
     },
     // ${#identifier}
-    Length {
-        identifier: Identifier<'a>,
-    },
+    #[cfg(feature = "length")]
+    Length { identifier: Identifier<'a> },
     // ${#}
+    #[cfg(feature = "length")]
     Arity,
     // ${!identifier}
-    Ref {
+    #[cfg(feature = "indirection")]
+    Ref { identifier: Identifier<'a> },
+    // ${if identifier}then${end} | ${if identifier}then${else}otherwise${end}
+    // Only outside of params, if conditionals are enabled. The nodes are boxed slices rather
+    // than vectors to keep params small, as parsing and evaluating nested params is recursive.
+    Conditional {
         identifier: Identifier<'a>,
+        then: Box<[Node<'a>]>,
+        otherwise: Box<[Node<'a>]>,
     },
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+impl<'a> Param<'a> {
+    /// Returns the identifier of the variable the param refers to, if any.
+    #[cfg_attr(not(feature = "length"), allow(clippy::unnecessary_wraps))]
+    pub const fn identifier(&self) -> Option<&Identifier<'a>> {
+        match self {
+            Self::Simple { identifier, .. }
+            | Self::WithDefault { identifier, .. }
+            | Self::WithAlt { identifier, .. }
+            | Self::WithError { identifier, .. }
+            | Self::Conditional { identifier, .. } => Some(identifier),
+            #[cfg(feature = "length")]
+            Self::Length { identifier } => Some(identifier),
+            #[cfg(feature = "indirection")]
+            Self::Ref { identifier } => Some(identifier),
+            #[cfg(feature = "length")]
+            Self::Arity => None,
+        }
+    }
+
+    fn fmt_with(&self, f: &mut Formatter, syntax: &Syntax) -> fmt::Result {
+        let colon = |treat_empty_as_unset: bool| if treat_empty_as_unset { ":" } else { "" };
+        let name = |identifier| ParamIdentifier(identifier, syntax);
+
+        match self {
+            Self::Simple {
+                identifier,
+                modifier,
+            } => match modifier {
+                Some(modifier) => {
+                    write!(f, "${{{}", name(identifier))?;
+                    modifier.fmt_with(f, syntax)?;
+                    write!(f, "}}")
+                },
+                // Special parameters can only be written unbraced
+                None if matches!(identifier, Identifier::Special(_)) => write!(f, "${identifier}"),
+                None => write!(f, "${{{}}}", name(identifier)),
+            },
+            Self::WithDefault {
+                identifier,
+                default,
+                treat_empty_as_unset,
+            } => write!(
+                f,
+                "${{{}{}-{}}}",
+                name(identifier),
+                colon(*treat_empty_as_unset),
+                ParamNode(default, syntax)
+            ),
+            Self::WithAlt {
+                identifier,
+                alt,
+                treat_empty_as_unset,
+            } => write!(
+                f,
+                "${{{}{}+{}}}",
+                name(identifier),
+                colon(*treat_empty_as_unset),
+                ParamNode(alt, syntax)
+            ),
+            Self::WithError {
+                identifier,
+                error,
+                treat_empty_as_unset,
+            } => {
+                write!(
+                    f,
+                    "${{{}{}?",
+                    name(identifier),
+                    colon(*treat_empty_as_unset)
+                )?;
+
+                for node in error {
+                    write!(f, "{}", ParamNode(node, syntax))?;
+                }
+
+                write!(f, "}}")
+            },
+            #[cfg(feature = "length")]
+            Self::Length { identifier } => write!(f, "${{#{identifier}}}"),
+            #[cfg(feature = "length")]
+            Self::Arity => write!(f, "${{#}}"),
+            #[cfg(feature = "indirection")]
+            Self::Ref { identifier } => write!(f, "${{!{identifier}}}"),
+            Self::Conditional {
+                identifier,
+                then,
+                otherwise,
+            } => {
+                write!(f, "${{{IF} {identifier}}}")?;
+                fmt_block(f, then, syntax)?;
+
+                if !otherwise.is_empty() {
+                    write!(f, "${{{ELSE}}}")?;
+                    fmt_block(f, otherwise, syntax)?;
+                }
+
+                write!(f, "${{{END}}}")
+            },
+        }
+    }
+}
+
+impl Display for Param<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.fmt_with(f, &Syntax::default())
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Node<'a> {
-    Text(String),
-    Param(Param<'a>),
+    // The raw text is only known for text which was parsed, and is lost once text is changed
+    Text(Cow<'a, str>, Option<Raw<'a>>),
+    // The position is that of the `$` starting the param
+    Param(Param<'a>, Position),
+    // =expression, only as the default or alternative value of a param
+    // The position is that of the `=` starting the expression
+    #[cfg(feature = "expr")]
+    Expr(Expression, Position),
+}
+
+/// Positions are not compared, so that nodes are equal regardless of where they appear.
+impl PartialEq for Node<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Text(text, _), Self::Text(other, _)) => text == other,
+            (Self::Param(param, _), Self::Param(other, _)) => param == other,
+            #[cfg(feature = "expr")]
+            (Self::Expr(expression, _), Self::Expr(other, _)) => expression == other,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Node<'_> {}
+
+/// Text as written in the source, including any escapes and quotes, which is formatted as is
+/// rather than escaped again. Text is escaped differently inside of params, so the raw text is
+/// only used where the text was written.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Raw<'a> {
+    pub text: &'a str,
+    pub in_param: bool,
+}
+
+impl Node<'_> {
+    fn fmt_with(&self, f: &mut Formatter, syntax: &Syntax) -> fmt::Result {
+        match self {
+            Self::Text(_, Some(raw)) if !raw.in_param => write!(f, "{}", raw.text),
+            Self::Text(text, _) => write!(f, "{}", escape_text(text, syntax)),
+            Self::Param(param, _) => param.fmt_with(f, syntax),
+            #[cfg(feature = "expr")]
+            Self::Expr(expression, _) => write!(f, "={expression}"),
+        }
+    }
+}
+
+impl Display for Node<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.fmt_with(f, &Syntax::default())
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -79,7 +402,179 @@ pub struct Ast<'a> {
 }
 
 impl<'a> Ast<'a> {
-    pub fn new(nodes: Vec<Node<'a>>) -> Self {
+    pub const fn new(nodes: Vec<Node<'a>>) -> Self {
         Self { nodes }
     }
+
+    /// Returns a [`Display`] implementation which formats the AST such that it parses back into
+    /// the same AST using the given syntax.
+    pub const fn display<'b>(&'b self, syntax: &'b Syntax) -> AstDisplay<'a, 'b> {
+        AstDisplay { ast: self, syntax }
+    }
+}
+
+impl Display for Ast<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.display(&Syntax::default()))
+    }
+}
+
+pub struct AstDisplay<'a, 'b> {
+    ast: &'b Ast<'a>,
+    syntax: &'b Syntax,
+}
+
+impl Display for AstDisplay<'_, '_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        fmt_nodes(f, &self.ast.nodes, self.syntax)
+    }
+}
+
+/// Formats nodes outside of params. The line break directly following the tag of a
+/// conditional block is left out when parsing, so another one is written in front of text
+/// starting with a line break in that place.
+fn fmt_nodes(f: &mut Formatter, nodes: &[Node], syntax: &Syntax) -> fmt::Result {
+    let mut after_tag = false;
+
+    for node in nodes {
+        if after_tag && matches!(node, Node::Text(text, _) if starts_with_line_break(text)) {
+            writeln!(f)?;
+        }
+
+        node.fmt_with(f, syntax)?;
+        after_tag = matches!(node, Node::Param(Param::Conditional { .. }, _));
+    }
+
+    Ok(())
+}
+
+/// Formats the nodes of a conditional block, which directly follow a tag.
+fn fmt_block(f: &mut Formatter, nodes: &[Node], syntax: &Syntax) -> fmt::Result {
+    if matches!(nodes.first(), Some(Node::Text(text, _)) if starts_with_line_break(text)) {
+        writeln!(f)?;
+    }
+
+    fmt_nodes(f, nodes, syntax)
+}
+
+/// Whether the text starts with a line break, which is either `\n` or `\r\n`.
+pub fn starts_with_line_break(text: &str) -> bool {
+    text.starts_with('\n') || text.starts_with("\r\n")
+}
+
+/// Formats a node nested inside a param, such as a default value.
+struct ParamNode<'a, 'b>(&'b Node<'a>, &'b Syntax);
+
+impl Display for ParamNode<'_, '_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.0 {
+            Node::Text(_, Some(raw)) if raw.in_param => write!(f, "{}", raw.text),
+            Node::Text(text, _) => write!(f, "{}", escape_param(text, self.1)),
+            Node::Param(param, _) => param.fmt_with(f, self.1),
+            #[cfg(feature = "expr")]
+            node @ Node::Expr(..) => node.fmt_with(f, self.1),
+        }
+    }
+}
+
+/// The identifier of a braced param, where the key of a namespaced variable is escaped such
+/// that it doesn't end early, as the key in `${env:a$:-b}` would at the `:-`.
+struct ParamIdentifier<'a, 'b>(&'b Identifier<'a>, &'b Syntax);
+
+impl Display for ParamIdentifier<'_, '_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let Identifier::Namespaced { namespace, key } = self.0 else {
+            return write!(f, "{}", self.0);
+        };
+
+        if self.1.quoting && key.contains(['"', '\'']) {
+            return write!(f, "{namespace}:{}", quote_param(key));
+        }
+
+        let mut escaped = escape(key).replace('}', "$}");
+
+        for operator in [":-", ":+", ":?"] {
+            escaped = escaped.replace(operator, &format!("${operator}"));
+        }
+
+        if escaped.starts_with(':') {
+            escaped.insert(0, '$');
+        }
+
+        write!(f, "{namespace}:{escaped}")
+    }
+}
+
+/// Escapes text so that it lexes back into the same text.
+fn escape(text: &str) -> String {
+    text.replace('$', "$$")
+}
+
+/// Escapes text outside of params like [`escape`], except for opaque regions which are read as
+/// is. If raw blocks are enabled, text which would otherwise be escaped is a raw block instead,
+/// unless it's the lone `$` of an escaped `$$`, so that it parses back into a single node.
+fn escape_text(text: &str, syntax: &Syntax) -> String {
+    let escaped = escape_regions(text, syntax);
+
+    if syntax.raw_blocks
+        && text != "$"
+        && escaped.len() > text.len()
+        && !text.contains(RAW_BLOCK_CLOSE)
+    {
+        return format!("{RAW_BLOCK_OPEN}{text}{RAW_BLOCK_CLOSE}");
+    }
+
+    escaped
+}
+
+fn escape_regions(mut text: &str, syntax: &Syntax) -> String {
+    let Some(regions) = &syntax.opaque_regions else {
+        return escape(text);
+    };
+
+    let mut escaped = String::with_capacity(text.len());
+
+    while let Some(region) = regions.find(text) {
+        escaped.push_str(&escape(&text[..region.start]));
+        escaped.push_str(&text[region.clone()]);
+        text = &text[region.end..];
+    }
+
+    escaped.push_str(&escape(text));
+    escaped
+}
+
+/// Escapes text inside a param so that it lexes back into the same text. Colons only need to be
+/// escaped at the start, where they would otherwise be part of the pattern, as do equals signs
+/// if expressions are enabled. If quoting is enabled, text containing quotes is double quoted
+/// instead.
+fn escape_param(text: &str, syntax: &Syntax) -> String {
+    if syntax.quoting && text.contains(['"', '\'']) {
+        return quote_param(text);
+    }
+
+    let escaped = escape(text).replace('}', "$}");
+
+    if escaped.starts_with(':') || (syntax.expressions && escaped.starts_with('=')) {
+        format!("${escaped}")
+    } else {
+        escaped
+    }
+}
+
+/// Escapes the pattern of an operator such as `${VAR#pattern}`. A pattern starting with the
+/// symbol of an operator which isn't doubled would otherwise read as part of the operator, so
+/// it's double quoted if quoting is enabled.
+fn escape_pattern(pattern: &str, symbol: char, doubled: bool, syntax: &Syntax) -> String {
+    if syntax.quoting && !doubled && pattern.starts_with(symbol) {
+        quote_param(pattern)
+    } else {
+        escape_param(pattern, syntax)
+    }
+}
+
+/// Double quotes text inside a param, escaping any double quote or backslash inside it.
+fn quote_param(text: &str) -> String {
+    let quoted = text.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{quoted}\"")
 }
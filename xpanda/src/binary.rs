@@ -0,0 +1,775 @@
+use crate::ast::{Ast, Identifier, Modifier, Node, Param, Raw};
+#[cfg(feature = "expr")]
+use crate::expr::Expression;
+use crate::front_matter::FrontMatter;
+use crate::parser::{MAX_MODIFIER_NUMBER, MAX_NESTING};
+use crate::position::{Position, Span};
+use crate::syntax::{AnsiCQuotes, OpaqueRegions, Syntax};
+use crate::template::Template;
+use crate::{Error, ErrorKind};
+use std::borrow::Cow;
+use std::str;
+use std::sync::Arc;
+
+/// The bytes every serialized template starts with.
+const MAGIC: &[u8] = b"xpanda\0";
+
+/// The version of the format, incremented whenever it changes. Only templates serialized with
+/// the same version can be loaded.
+const VERSION: u8 = 4;
+
+const SYNTAX_QUOTING: u16 = 1 << 0;
+const SYNTAX_LENIENT_IDENTIFIERS: u16 = 1 << 1;
+const SYNTAX_POWERSHELL_ENV: u16 = 1 << 2;
+const SYNTAX_FRONT_MATTER: u16 = 1 << 3;
+const SYNTAX_SAFE_MODE: u16 = 1 << 4;
+const SYNTAX_OPAQUE_REGIONS: u16 = 1 << 5;
+const SYNTAX_RAW_BLOCKS: u16 = 1 << 6;
+const SYNTAX_COMMENTS: u16 = 1 << 7;
+const SYNTAX_CONDITIONALS: u16 = 1 << 8;
+const SYNTAX_DYNAMIC_NAMES: u16 = 1 << 9;
+const SYNTAX_ANSI_C_INTERPRET: u16 = 1 << 10;
+const SYNTAX_ANSI_C_KEEP: u16 = 1 << 11;
+const SYNTAX_EXPRESSIONS: u16 = 1 << 12;
+
+const NODE_TEXT: u8 = 0;
+const NODE_PARAM: u8 = 1;
+const NODE_EXPR: u8 = 2;
+// Text along with its raw form, as written in the source
+const NODE_RAW_TEXT: u8 = 3;
+
+const PARAM_SIMPLE: u8 = 0;
+const PARAM_WITH_DEFAULT: u8 = 1;
+const PARAM_WITH_ALT: u8 = 2;
+const PARAM_WITH_ERROR: u8 = 3;
+const PARAM_LENGTH: u8 = 4;
+const PARAM_ARITY: u8 = 5;
+const PARAM_REF: u8 = 6;
+const PARAM_CONDITIONAL: u8 = 7;
+
+const IDENTIFIER_NAMED: u8 = 0;
+const IDENTIFIER_INDEXED: u8 = 1;
+const IDENTIFIER_NAMESPACED: u8 = 2;
+const IDENTIFIER_SPECIAL: u8 = 3;
+const IDENTIFIER_DYNAMIC: u8 = 4;
+
+const MODIFIER_NONE: u8 = 0;
+const MODIFIER_UPPER: u8 = 1;
+const MODIFIER_LOWER: u8 = 2;
+const MODIFIER_REVERSE: u8 = 3;
+const MODIFIER_URL_ENCODE: u8 = 4;
+const MODIFIER_URL_DECODE: u8 = 5;
+const MODIFIER_BASE64_ENCODE: u8 = 6;
+const MODIFIER_BASE64_DECODE: u8 = 7;
+const MODIFIER_SHELL_QUOTE: u8 = 8;
+const MODIFIER_PAD: u8 = 9;
+const MODIFIER_FIXED: u8 = 10;
+const MODIFIER_TRUNCATE: u8 = 11;
+const MODIFIER_REMOVE_PREFIX: u8 = 12;
+const MODIFIER_REMOVE_SUFFIX: u8 = 13;
+const MODIFIER_REPLACE: u8 = 14;
+
+/// Serializes the template. Numbers are written as LEB128 varints and strings as their length
+/// followed by their UTF-8 bytes, so that loading a template can borrow its text.
+pub fn write(template: &Template) -> Vec<u8> {
+    let mut writer = Writer::default();
+
+    writer.bytes.extend_from_slice(MAGIC);
+    writer.byte(VERSION);
+    writer.syntax(&template.syntax);
+    writer.str(&template.header);
+
+    match &template.front_matter {
+        Some(front_matter) => {
+            writer.byte(1);
+            writer.front_matter(front_matter);
+        },
+        None => writer.byte(0),
+    }
+
+    writer.nodes(&template.ast.nodes);
+    writer.number(template.param_texts.len());
+
+    for text in &template.param_texts {
+        writer.str(text);
+    }
+
+    writer.bytes
+}
+
+/// Loads a template serialized by [`write`], borrowing its text from the bytes.
+pub fn read(bytes: &[u8]) -> Result<Template<'_>, Error> {
+    let mut reader = Reader { bytes, depth: 0 };
+
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(format_error("Not a serialized template"));
+    }
+
+    let version = reader.byte()?;
+
+    if version != VERSION {
+        return Err(format_error(&format!(
+            "Unsupported template format version {version}, expected version {VERSION}"
+        )));
+    }
+
+    let syntax = reader.syntax()?;
+    let header = reader.str()?;
+    let front_matter = match reader.byte()? {
+        0 => None,
+        1 => Some(reader.front_matter()?),
+        tag => return Err(invalid_tag("front matter", tag)),
+    };
+    let nodes = reader.nodes()?;
+    let param_texts = (0..reader.number()?)
+        .map(|_| reader.str().map(Cow::Borrowed))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if !reader.bytes.is_empty() {
+        return Err(format_error(
+            "Unexpected bytes after the end of the template",
+        ));
+    }
+
+    Ok(Template::new(
+        Ast::new(nodes),
+        param_texts,
+        front_matter,
+        Cow::Borrowed(header),
+        syntax,
+    ))
+}
+
+fn format_error(message: &str) -> Error {
+    Error::unlocated(
+        ErrorKind::Format,
+        format!("Invalid serialized template: {message}"),
+    )
+}
+
+fn invalid_tag(kind: &str, tag: u8) -> Error {
+    format_error(&format!("Unknown {kind} tag {tag}"))
+}
+
+/// An error for syntax which was serialized by a build with a feature which is disabled in
+/// this build.
+fn disabled_feature(feature: &str) -> Error {
+    format_error(&format!(
+        "The template uses syntax of the '{feature}' feature, which is disabled"
+    ))
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn byte(&mut self, byte: u8) {
+        self.bytes.push(byte);
+    }
+
+    fn bool(&mut self, bool: bool) {
+        self.byte(u8::from(bool));
+    }
+
+    fn number(&mut self, mut number: usize) {
+        while number >= 0x80 {
+            // Truncation is intended, as only the lowest 7 bits are written at a time
+            #[allow(clippy::cast_possible_truncation)]
+            self.byte((number as u8 & 0x7F) | 0x80);
+            number >>= 7;
+        }
+
+        #[allow(clippy::cast_possible_truncation)]
+        self.byte(number as u8);
+    }
+
+    fn str(&mut self, str: &str) {
+        self.number(str.len());
+        self.bytes.extend_from_slice(str.as_bytes());
+    }
+
+    fn syntax(&mut self, syntax: &Syntax) {
+        let flags = [
+            (syntax.quoting, SYNTAX_QUOTING),
+            (syntax.lenient_identifiers, SYNTAX_LENIENT_IDENTIFIERS),
+            (syntax.powershell_env, SYNTAX_POWERSHELL_ENV),
+            (syntax.front_matter, SYNTAX_FRONT_MATTER),
+            (syntax.safe_mode, SYNTAX_SAFE_MODE),
+            (syntax.opaque_regions.is_some(), SYNTAX_OPAQUE_REGIONS),
+            (syntax.raw_blocks, SYNTAX_RAW_BLOCKS),
+            (syntax.comments, SYNTAX_COMMENTS),
+            (syntax.conditionals, SYNTAX_CONDITIONALS),
+            (syntax.dynamic_names, SYNTAX_DYNAMIC_NAMES),
+            (syntax.expressions, SYNTAX_EXPRESSIONS),
+            (
+                syntax.ansi_c_quotes == AnsiCQuotes::Interpret,
+                SYNTAX_ANSI_C_INTERPRET,
+            ),
+            (
+                syntax.ansi_c_quotes == AnsiCQuotes::Keep,
+                SYNTAX_ANSI_C_KEEP,
+            ),
+        ];
+
+        self.number(usize::from(
+            flags
+                .iter()
+                .filter(|(enabled, _)| *enabled)
+                .fold(0, |flags, (_, flag)| flags | flag),
+        ));
+        self.byte(syntax.special_vars);
+
+        if let Some(regions) = &syntax.opaque_regions {
+            self.str(&regions.open);
+            self.str(&regions.close);
+        }
+    }
+
+    fn position(&mut self, position: Position) {
+        self.number(position.index);
+        self.number(position.line);
+        self.number(position.col);
+    }
+
+    fn front_matter(&mut self, front_matter: &FrontMatter) {
+        self.number(front_matter.required.len());
+
+        for name in &front_matter.required {
+            self.str(name);
+        }
+
+        self.number(front_matter.defaults.len());
+
+        for (name, value) in &front_matter.defaults {
+            self.str(name);
+            self.str(value);
+        }
+
+        self.position(front_matter.span.start);
+        self.position(front_matter.span.end);
+    }
+
+    fn nodes(&mut self, nodes: &[Node]) {
+        self.number(nodes.len());
+
+        for node in nodes {
+            self.node(node);
+        }
+    }
+
+    fn node(&mut self, node: &Node) {
+        match node {
+            Node::Text(text, None) => {
+                self.byte(NODE_TEXT);
+                self.str(text);
+            },
+            Node::Text(text, Some(raw)) => {
+                self.byte(NODE_RAW_TEXT);
+                self.str(text);
+                self.str(raw.text);
+                self.bool(raw.in_param);
+            },
+            Node::Param(param, position) => {
+                self.byte(NODE_PARAM);
+                self.position(*position);
+                self.param(param);
+            },
+            #[cfg(feature = "expr")]
+            Node::Expr(expression, position) => {
+                self.byte(NODE_EXPR);
+                self.position(*position);
+                self.str(expression.source());
+            },
+        }
+    }
+
+    fn param(&mut self, param: &Param) {
+        match param {
+            Param::Simple {
+                identifier,
+                modifier,
+            } => {
+                self.byte(PARAM_SIMPLE);
+                self.identifier(identifier);
+                self.modifier(modifier.as_ref());
+            },
+            Param::WithDefault {
+                identifier,
+                default: node,
+                treat_empty_as_unset,
+            }
+            | Param::WithAlt {
+                identifier,
+                alt: node,
+                treat_empty_as_unset,
+            } => {
+                let tag = if matches!(param, Param::WithDefault { .. }) {
+                    PARAM_WITH_DEFAULT
+                } else {
+                    PARAM_WITH_ALT
+                };
+
+                self.byte(tag);
+                self.identifier(identifier);
+                self.bool(*treat_empty_as_unset);
+                self.node(node);
+            },
+            Param::WithError {
+                identifier,
+                error,
+                treat_empty_as_unset,
+            } => {
+                self.byte(PARAM_WITH_ERROR);
+                self.identifier(identifier);
+                self.bool(*treat_empty_as_unset);
+                self.nodes(error);
+            },
+            #[cfg(feature = "length")]
+            Param::Length { identifier } => {
+                self.byte(PARAM_LENGTH);
+                self.identifier(identifier);
+            },
+            #[cfg(feature = "length")]
+            Param::Arity => self.byte(PARAM_ARITY),
+            #[cfg(feature = "indirection")]
+            Param::Ref { identifier } => {
+                self.byte(PARAM_REF);
+                self.identifier(identifier);
+            },
+            Param::Conditional {
+                identifier,
+                then,
+                otherwise,
+            } => {
+                self.byte(PARAM_CONDITIONAL);
+                self.identifier(identifier);
+                self.nodes(then);
+                self.nodes(otherwise);
+            },
+        }
+    }
+
+    fn identifier(&mut self, identifier: &Identifier) {
+        match identifier {
+            Identifier::Named(name) => {
+                self.byte(IDENTIFIER_NAMED);
+                self.str(name);
+            },
+            Identifier::Indexed(index) => {
+                self.byte(IDENTIFIER_INDEXED);
+                self.number(*index);
+            },
+            Identifier::Namespaced { namespace, key } => {
+                self.byte(IDENTIFIER_NAMESPACED);
+                self.str(namespace);
+                self.str(key);
+            },
+            Identifier::Special(c) => {
+                self.byte(IDENTIFIER_SPECIAL);
+                self.str(c.encode_utf8(&mut [0; 4]));
+            },
+            Identifier::Dynamic(parts) => {
+                self.byte(IDENTIFIER_DYNAMIC);
+                self.nodes(parts);
+            },
+        }
+    }
+
+    fn modifier(&mut self, modifier: Option<&Modifier>) {
+        let Some(modifier) = modifier else {
+            self.byte(MODIFIER_NONE);
+            return;
+        };
+
+        match modifier {
+            #[cfg(feature = "case-modifiers")]
+            Modifier::Upper { all } => {
+                self.byte(MODIFIER_UPPER);
+                self.bool(*all);
+            },
+            #[cfg(feature = "case-modifiers")]
+            Modifier::Lower { all } => {
+                self.byte(MODIFIER_LOWER);
+                self.bool(*all);
+            },
+            #[cfg(feature = "case-modifiers")]
+            Modifier::Reverse { all } => {
+                self.byte(MODIFIER_REVERSE);
+                self.bool(*all);
+            },
+            Modifier::UrlEncode => self.byte(MODIFIER_URL_ENCODE),
+            Modifier::UrlDecode => self.byte(MODIFIER_URL_DECODE),
+            Modifier::Base64Encode => self.byte(MODIFIER_BASE64_ENCODE),
+            Modifier::Base64Decode => self.byte(MODIFIER_BASE64_DECODE),
+            Modifier::ShellQuote => self.byte(MODIFIER_SHELL_QUOTE),
+            Modifier::Pad { width, zero } => {
+                self.byte(MODIFIER_PAD);
+                self.number(*width);
+                self.bool(*zero);
+            },
+            Modifier::Fixed { precision } => {
+                self.byte(MODIFIER_FIXED);
+                self.number(*precision);
+            },
+            Modifier::Truncate { width, ellipsis } => {
+                self.byte(MODIFIER_TRUNCATE);
+                self.number(*width);
+                self.str(ellipsis);
+            },
+            Modifier::RemovePrefix { pattern, longest } => {
+                self.byte(MODIFIER_REMOVE_PREFIX);
+                self.str(pattern);
+                self.bool(*longest);
+            },
+            Modifier::RemoveSuffix { pattern, longest } => {
+                self.byte(MODIFIER_REMOVE_SUFFIX);
+                self.str(pattern);
+                self.bool(*longest);
+            },
+            Modifier::Replace {
+                pattern,
+                replacement,
+                all,
+            } => {
+                self.byte(MODIFIER_REPLACE);
+                self.str(pattern);
+                self.str(replacement);
+                self.bool(*all);
+            },
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    /// The number of params currently being read which contain the node being read, limited
+    /// like when parsing so that reading never overflows the stack.
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.bytes.len() < len {
+            return Err(format_error("Unexpected end of input"));
+        }
+
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, Error> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            byte => Err(format_error(&format!("Invalid boolean {byte}"))),
+        }
+    }
+
+    fn number(&mut self) -> Result<usize, Error> {
+        let mut number = 0_usize;
+        let mut shift = 0;
+
+        loop {
+            let byte = self.byte()?;
+            let bits = usize::from(byte & 0x7F);
+
+            if shift >= usize::BITS || (bits << shift) >> shift != bits {
+                return Err(format_error("Number out of range"));
+            }
+
+            number |= bits << shift;
+            shift += 7;
+
+            if byte & 0x80 == 0 {
+                return Ok(number);
+            }
+        }
+    }
+
+    fn str(&mut self) -> Result<&'a str, Error> {
+        let len = self.number()?;
+        let bytes = self.take(len)?;
+
+        str::from_utf8(bytes).map_err(|_| format_error("Invalid UTF-8 in string"))
+    }
+
+    fn syntax(&mut self) -> Result<Syntax, Error> {
+        let flags =
+            u16::try_from(self.number()?).map_err(|_| format_error("Invalid syntax flags"))?;
+        let special_vars = self.byte()?;
+        let opaque_regions = if flags & SYNTAX_OPAQUE_REGIONS == 0 {
+            None
+        } else {
+            let open = self.str()?;
+
+            if open.is_empty() || open.contains('$') {
+                return Err(format_error("Invalid opaque region delimiter"));
+            }
+
+            Some(Arc::new(OpaqueRegions {
+                open: open.to_string(),
+                close: self.str()?.to_string(),
+            }))
+        };
+        let ansi_c_quotes = match (
+            flags & SYNTAX_ANSI_C_INTERPRET != 0,
+            flags & SYNTAX_ANSI_C_KEEP != 0,
+        ) {
+            (false, false) => AnsiCQuotes::Error,
+            (true, false) => AnsiCQuotes::Interpret,
+            (false, true) => AnsiCQuotes::Keep,
+            (true, true) => return Err(format_error("Invalid syntax flags")),
+        };
+
+        if flags & SYNTAX_EXPRESSIONS != 0 && !cfg!(feature = "expr") {
+            return Err(disabled_feature("expr"));
+        }
+
+        Ok(Syntax {
+            quoting: flags & SYNTAX_QUOTING != 0,
+            lenient_identifiers: flags & SYNTAX_LENIENT_IDENTIFIERS != 0,
+            powershell_env: flags & SYNTAX_POWERSHELL_ENV != 0,
+            front_matter: flags & SYNTAX_FRONT_MATTER != 0,
+            safe_mode: flags & SYNTAX_SAFE_MODE != 0,
+            raw_blocks: flags & SYNTAX_RAW_BLOCKS != 0,
+            comments: flags & SYNTAX_COMMENTS != 0,
+            conditionals: flags & SYNTAX_CONDITIONALS != 0,
+            dynamic_names: flags & SYNTAX_DYNAMIC_NAMES != 0,
+            expressions: flags & SYNTAX_EXPRESSIONS != 0,
+            special_vars,
+            opaque_regions,
+            ansi_c_quotes,
+        })
+    }
+
+    fn position(&mut self) -> Result<Position, Error> {
+        Ok(Position::new(
+            self.number()?,
+            self.number()?,
+            self.number()?,
+        ))
+    }
+
+    /// Reads the number of items which follow, none of which can take up less than a byte, so
+    /// that a corrupt count can't cause a huge allocation.
+    fn count(&mut self) -> Result<usize, Error> {
+        let count = self.number()?;
+
+        if count > self.bytes.len() {
+            return Err(format_error("Unexpected end of input"));
+        }
+
+        Ok(count)
+    }
+
+    fn front_matter(&mut self) -> Result<FrontMatter, Error> {
+        let mut front_matter = FrontMatter::default();
+
+        for _ in 0..self.count()? {
+            front_matter.required.push(self.str()?.to_string());
+        }
+
+        for _ in 0..self.count()? {
+            let name = self.str()?.to_string();
+            let value = self.str()?.to_string();
+            front_matter.defaults.push((name, value));
+        }
+
+        front_matter.span = Span::new(self.position()?, self.position()?);
+
+        Ok(front_matter)
+    }
+
+    fn nodes(&mut self) -> Result<Vec<Node<'a>>, Error> {
+        let count = self.count()?;
+        let mut nodes = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            nodes.push(self.node()?);
+        }
+
+        Ok(nodes)
+    }
+
+    fn node(&mut self) -> Result<Node<'a>, Error> {
+        let node = match self.byte()? {
+            NODE_TEXT => Node::Text(Cow::Borrowed(self.str()?), None),
+            NODE_RAW_TEXT => {
+                let text = self.str()?;
+                let raw = Raw {
+                    text: self.str()?,
+                    in_param: self.bool()?,
+                };
+
+                Node::Text(Cow::Borrowed(text), Some(raw))
+            },
+            NODE_PARAM => {
+                let position = self.position()?;
+
+                if self.depth >= MAX_NESTING {
+                    return Err(format_error(&format!(
+                        "Params are nested more than {MAX_NESTING} levels deep"
+                    )));
+                }
+
+                self.depth += 1;
+                let param = self.param()?;
+                self.depth -= 1;
+
+                Node::Param(param, position)
+            },
+            #[cfg(feature = "expr")]
+            NODE_EXPR => {
+                let position = self.position()?;
+                let source = self.str()?;
+                let expression =
+                    Expression::parse(source, self.depth).map_err(|(message, _)| {
+                        format_error(&format!("Invalid expression '{source}': {message}"))
+                    })?;
+
+                Node::Expr(expression, position)
+            },
+            #[cfg(not(feature = "expr"))]
+            NODE_EXPR => return Err(disabled_feature("expr")),
+            tag => return Err(invalid_tag("node", tag)),
+        };
+
+        Ok(node)
+    }
+
+    fn param(&mut self) -> Result<Param<'a>, Error> {
+        let param = match self.byte()? {
+            PARAM_SIMPLE => Param::Simple {
+                identifier: self.identifier()?,
+                modifier: self.modifier()?,
+            },
+            PARAM_WITH_DEFAULT => Param::WithDefault {
+                identifier: self.identifier()?,
+                treat_empty_as_unset: self.bool()?,
+                default: Box::new(self.node()?),
+            },
+            PARAM_WITH_ALT => Param::WithAlt {
+                identifier: self.identifier()?,
+                treat_empty_as_unset: self.bool()?,
+                alt: Box::new(self.node()?),
+            },
+            PARAM_WITH_ERROR => Param::WithError {
+                identifier: self.identifier()?,
+                treat_empty_as_unset: self.bool()?,
+                error: self.nodes()?,
+            },
+            #[cfg(feature = "length")]
+            PARAM_LENGTH => Param::Length {
+                identifier: self.identifier()?,
+            },
+            #[cfg(feature = "length")]
+            PARAM_ARITY => Param::Arity,
+            #[cfg(not(feature = "length"))]
+            PARAM_LENGTH | PARAM_ARITY => return Err(disabled_feature("length")),
+            #[cfg(feature = "indirection")]
+            PARAM_REF => Param::Ref {
+                identifier: self.identifier()?,
+            },
+            #[cfg(not(feature = "indirection"))]
+            PARAM_REF => return Err(disabled_feature("indirection")),
+            PARAM_CONDITIONAL => Param::Conditional {
+                identifier: self.identifier()?,
+                then: self.nodes()?.into_boxed_slice(),
+                otherwise: self.nodes()?.into_boxed_slice(),
+            },
+            tag => return Err(invalid_tag("param", tag)),
+        };
+
+        Ok(param)
+    }
+
+    fn identifier(&mut self) -> Result<Identifier<'a>, Error> {
+        let identifier = match self.byte()? {
+            IDENTIFIER_NAMED => Identifier::Named(self.str()?),
+            IDENTIFIER_INDEXED => Identifier::Indexed(self.number()?),
+            IDENTIFIER_NAMESPACED => Identifier::Namespaced {
+                namespace: self.str()?,
+                key: Cow::Borrowed(self.str()?),
+            },
+            IDENTIFIER_SPECIAL => {
+                let mut chars = self.str()?.chars();
+
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Identifier::Special(c),
+                    _ => return Err(format_error("Invalid special parameter")),
+                }
+            },
+            IDENTIFIER_DYNAMIC => Identifier::Dynamic(self.nodes()?.into_boxed_slice()),
+            tag => return Err(invalid_tag("identifier", tag)),
+        };
+
+        Ok(identifier)
+    }
+
+    fn modifier(&mut self) -> Result<Option<Modifier>, Error> {
+        let modifier = match self.byte()? {
+            MODIFIER_NONE => return Ok(None),
+            #[cfg(feature = "case-modifiers")]
+            MODIFIER_UPPER => Modifier::Upper { all: self.bool()? },
+            #[cfg(feature = "case-modifiers")]
+            MODIFIER_LOWER => Modifier::Lower { all: self.bool()? },
+            #[cfg(feature = "case-modifiers")]
+            MODIFIER_REVERSE => Modifier::Reverse { all: self.bool()? },
+            #[cfg(not(feature = "case-modifiers"))]
+            MODIFIER_UPPER | MODIFIER_LOWER | MODIFIER_REVERSE => {
+                return Err(disabled_feature("case-modifiers"));
+            },
+            MODIFIER_URL_ENCODE => Modifier::UrlEncode,
+            MODIFIER_URL_DECODE => Modifier::UrlDecode,
+            MODIFIER_BASE64_ENCODE => Modifier::Base64Encode,
+            MODIFIER_BASE64_DECODE => Modifier::Base64Decode,
+            MODIFIER_SHELL_QUOTE => Modifier::ShellQuote,
+            MODIFIER_PAD => Modifier::Pad {
+                width: self.modifier_number()?,
+                zero: self.bool()?,
+            },
+            MODIFIER_FIXED => Modifier::Fixed {
+                precision: self.modifier_number()?,
+            },
+            MODIFIER_TRUNCATE => Modifier::Truncate {
+                width: self.modifier_number()?,
+                ellipsis: self.str()?.to_string(),
+            },
+            MODIFIER_REMOVE_PREFIX => Modifier::RemovePrefix {
+                pattern: self.str()?.to_string(),
+                longest: self.bool()?,
+            },
+            MODIFIER_REMOVE_SUFFIX => Modifier::RemoveSuffix {
+                pattern: self.str()?.to_string(),
+                longest: self.bool()?,
+            },
+            MODIFIER_REPLACE => Modifier::Replace {
+                pattern: self.str()?.to_string(),
+                replacement: self.str()?.to_string(),
+                all: self.bool()?,
+            },
+            tag => return Err(invalid_tag("modifier", tag)),
+        };
+
+        Ok(Some(modifier))
+    }
+
+    /// Reads the number of a modifier, which is limited like when parsing.
+    fn modifier_number(&mut self) -> Result<usize, Error> {
+        let number = self.number()?;
+
+        if number > MAX_MODIFIER_NUMBER {
+            return Err(format_error("Modifier number out of range"));
+        }
+
+        Ok(number)
+    }
+}
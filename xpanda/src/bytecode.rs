@@ -0,0 +1,175 @@
+use crate::ast::{Ast, Identifier, Node, Param};
+#[cfg(feature = "expr")]
+use crate::expr::Expression;
+use crate::position::Position;
+use crate::template::Template;
+use std::fmt::{self, Display, Formatter};
+
+/// A single instruction of a [`Program`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Op<'a> {
+    /// Appends the given range of the text of the program to the output.
+    Text { start: usize, end: usize },
+    /// Evaluates a param without nested nodes, appending its value to the output.
+    Param(Param<'a>, Position),
+    /// Evaluates the expression of a default or alternative value, appending its value to the
+    /// output.
+    #[cfg(feature = "expr")]
+    Expr(Expression, Position),
+    /// Resolves the variable of a `${VAR-default}` param. If it's set its value is appended to
+    /// the output and execution continues at `end`, skipping the ops of the default value.
+    Default {
+        identifier: Identifier<'a>,
+        treat_empty_as_unset: bool,
+        position: Position,
+        end: usize,
+    },
+    /// Resolves the variable of a `${VAR+alt}` param. If it's unset execution continues at
+    /// `end`, skipping the ops of the alternative value.
+    Alt {
+        identifier: Identifier<'a>,
+        treat_empty_as_unset: bool,
+        position: Position,
+        end: usize,
+    },
+}
+
+/// An AST flattened into a list of ops, which can be evaluated in a single loop rather than
+/// recursively. All text is stored in one string, which the ops refer to by range.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Program<'a> {
+    pub ops: Vec<Op<'a>>,
+    pub text: String,
+    // Text ops before this index are part of a different branch than the ops which follow, so
+    // text can't be merged into them
+    boundary: usize,
+}
+
+impl<'a> Program<'a> {
+    pub fn compile(ast: &Ast<'a>) -> Self {
+        let mut program = Self {
+            ops: Vec::new(),
+            text: String::new(),
+            boundary: 0,
+        };
+
+        for node in &ast.nodes {
+            program.compile_node(node);
+        }
+
+        program
+    }
+
+    fn compile_node(&mut self, node: &Node<'a>) {
+        match node {
+            Node::Text(text, _) => self.push_text(text),
+            Node::Param(
+                Param::WithDefault {
+                    identifier,
+                    default: node,
+                    treat_empty_as_unset,
+                },
+                position,
+            ) => self.compile_branch(node, |end| Op::Default {
+                identifier: identifier.clone(),
+                treat_empty_as_unset: *treat_empty_as_unset,
+                position: *position,
+                end,
+            }),
+            Node::Param(
+                Param::WithAlt {
+                    identifier,
+                    alt: node,
+                    treat_empty_as_unset,
+                },
+                position,
+            ) => self.compile_branch(node, |end| Op::Alt {
+                identifier: identifier.clone(),
+                treat_empty_as_unset: *treat_empty_as_unset,
+                position: *position,
+                end,
+            }),
+            Node::Param(param, position) => self.ops.push(Op::Param(param.clone(), *position)),
+            #[cfg(feature = "expr")]
+            Node::Expr(expression, position) => {
+                self.ops.push(Op::Expr(expression.clone(), *position));
+            },
+        }
+    }
+
+    /// Compiles a branch op followed by the ops of the nested node, which the branch skips by
+    /// jumping to the end of them.
+    fn compile_branch(&mut self, node: &Node<'a>, branch: impl FnOnce(usize) -> Op<'a>) {
+        let index = self.ops.len();
+        // A placeholder until the end of the branch is known
+        self.ops.push(Op::Text { start: 0, end: 0 });
+        self.boundary = self.ops.len();
+        self.compile_node(node);
+        self.boundary = self.ops.len();
+        self.ops[index] = branch(self.boundary);
+    }
+
+    fn push_text(&mut self, text: &str) {
+        let start = self.text.len();
+        self.text.push_str(text);
+        let end = self.text.len();
+
+        if self.ops.len() > self.boundary {
+            if let Some(Op::Text { end: last_end, .. }) = self.ops.last_mut() {
+                *last_end = end;
+                return;
+            }
+        }
+
+        self.ops.push(Op::Text { start, end });
+    }
+}
+
+/// A [`Template`] compiled into a flat list of instructions, created by [`Template::compile`].
+///
+/// Compiled templates are faster to render repeatedly than the template itself, and are
+/// rendered using [`Xpanda::render_compiled`](crate::Xpanda::render_compiled), which behaves
+/// exactly like [`Xpanda::render`](crate::Xpanda::render). Compiling only pays off for
+/// templates which are rendered many times, such as by a server rendering the same templates
+/// for every request.
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::HashMap;
+/// use xpanda::{Template, Xpanda};
+///
+/// let template = Template::parse("Hello ${NAME:-world}!").unwrap().compile();
+/// let xpanda = Xpanda::builder()
+///     .with_named_vars(HashMap::from([(String::from("NAME"), String::from("Zoë"))]))
+///     .build();
+///
+/// assert_eq!(xpanda.render_compiled(&template), Ok(String::from("Hello Zoë!")));
+/// assert_eq!(template.template().variables(), vec!["NAME"]);
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CompiledTemplate<'a> {
+    template: Template<'a>,
+    pub(crate) program: Program<'a>,
+}
+
+impl<'a> CompiledTemplate<'a> {
+    pub(crate) fn new(template: Template<'a>) -> Self {
+        let program = Program::compile(&template.ast);
+
+        Self { template, program }
+    }
+
+    /// Returns the template which was compiled, such as to inspect the variables it
+    /// references.
+    #[must_use]
+    pub const fn template(&self) -> &Template<'a> {
+        &self.template
+    }
+}
+
+impl Display for CompiledTemplate<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.template)
+    }
+}
@@ -0,0 +1,81 @@
+use crate::position::{LineIndex, Position};
+use crate::{Error, ErrorKind};
+
+/// Bytes which aren't valid UTF-8 are represented by the last 256 code points of the
+/// supplementary private use area B, such that they pass through expansion unchanged.
+const FIRST_RAW_BYTE: u32 = 0x10_FF00;
+
+/// Converts the input to a string losslessly, replacing every byte which isn't part of valid
+/// UTF-8 with a code point standing in for it.
+pub fn decode(input: &[u8]) -> Result<String, Error> {
+    let mut text = String::with_capacity(input.len());
+    let mut offset = 0;
+
+    for chunk in input.utf8_chunks() {
+        let valid = chunk.valid();
+
+        if let Some((index, char)) = valid.char_indices().find(|(_, char)| is_raw(*char)) {
+            text.push_str(&valid[..index]);
+
+            let position = Position {
+                index: offset + index,
+                ..LineIndex::new(&text).position(text.len())
+            };
+            let message = format!(
+                "The character U+{:X} is reserved for passing through invalid UTF-8",
+                u32::from(char)
+            );
+
+            return Err(Error::new(ErrorKind::Syntax, message, &position));
+        }
+
+        text.push_str(valid);
+        text.extend(
+            chunk
+                .invalid()
+                .iter()
+                .filter_map(|byte| char::from_u32(FIRST_RAW_BYTE + u32::from(*byte))),
+        );
+        offset += valid.len() + chunk.invalid().len();
+    }
+
+    Ok(text)
+}
+
+/// Converts expanded text back to bytes, restoring the bytes which weren't valid UTF-8.
+pub fn encode(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len());
+
+    for char in text.chars() {
+        if is_raw(char) {
+            bytes.extend(u8::try_from(u32::from(char) - FIRST_RAW_BYTE));
+        } else {
+            bytes.extend_from_slice(char.encode_utf8(&mut [0; 4]).as_bytes());
+        }
+    }
+
+    bytes
+}
+
+/// Translates the byte offsets of an error in the decoded text into offsets in the input.
+pub fn locate_error(mut error: Error, text: &str) -> Error {
+    if let Some(span) = &mut error.span {
+        span.start.index = original_index(text, span.start.index);
+        span.end.index = original_index(text, span.end.index);
+    }
+
+    error
+}
+
+const fn is_raw(char: char) -> bool {
+    char as u32 >= FIRST_RAW_BYTE
+}
+
+fn original_index(text: &str, index: usize) -> usize {
+    text.get(..index).map_or(index, |prefix| {
+        prefix
+            .chars()
+            .map(|char| if is_raw(char) { 1 } else { char.len_utf8() })
+            .sum()
+    })
+}
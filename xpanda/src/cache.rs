@@ -0,0 +1,150 @@
+use crate::wipe::wipe;
+use crate::{Error, Xpanda};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// A wrapper around [`Xpanda`] which remembers the results of expansion, such that expanding the
+/// same input again returns the previous result without parsing or evaluating it.
+///
+/// Results are keyed by a hash of the input and of the variables of the wrapped instance, and
+/// include errors. Instances which resolve variables at the time of expansion, because
+/// [`Builder::with_live_env`](crate::Builder::with_live_env) is set or a namespace is
+/// registered, are never cached since their results may change between expansions. Neither are
+/// listeners registered using [`Builder::on_unset`](crate::Builder::on_unset),
+/// [`Builder::on_resolve`](crate::Builder::on_resolve) or
+/// [`Builder::on_warning`](crate::Builder::on_warning) invoked for cached results.
+///
+/// # Examples
+///
+/// ```
+/// use xpanda::{CachedXpanda, Xpanda};
+///
+/// let xpanda = CachedXpanda::new(Xpanda::default());
+///
+/// assert_eq!(xpanda.expand("${1:-default}"), Ok(String::from("default")));
+/// assert_eq!(xpanda.expand("${1:-default}"), Ok(String::from("default")));
+/// assert_eq!(xpanda.len(), 1);
+/// ```
+pub struct CachedXpanda {
+    xpanda: Xpanda,
+    vars_hash: u64,
+    capacity: Option<usize>,
+    entries: Mutex<HashMap<u64, CacheEntry>>,
+}
+
+struct CacheEntry {
+    input: String,
+    result: Result<String, Error>,
+}
+
+#[cfg(feature = "zeroize")]
+impl Drop for CacheEntry {
+    fn drop(&mut self) {
+        if let Ok(output) = &mut self.result {
+            wipe(mem::take(output));
+        }
+    }
+}
+
+impl CachedXpanda {
+    /// Wraps the given instance with a cache of unlimited size.
+    #[must_use]
+    pub fn new(xpanda: Xpanda) -> Self {
+        let mut hasher = DefaultHasher::new();
+        xpanda.evaluator.hash_vars(&mut hasher);
+        xpanda.syntax.hash(&mut hasher);
+
+        Self {
+            xpanda,
+            vars_hash: hasher.finish(),
+            capacity: None,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Wraps the given instance with a cache holding at most `capacity` results. The cache is
+    /// cleared when it's full.
+    #[must_use]
+    pub fn with_capacity(xpanda: Xpanda, capacity: usize) -> Self {
+        Self {
+            capacity: Some(capacity),
+            ..Self::new(xpanda)
+        }
+    }
+
+    /// Expands the given text just like [`Xpanda::expand`], returning a cached result if the
+    /// same text has been expanded before.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the given string is badly formatted and cannot be parsed, or if
+    /// rendering it fails. See [`Xpanda::render`].
+    pub fn expand(&self, input: &str) -> Result<String, Error> {
+        if !self.xpanda.evaluator.is_static() {
+            return self.xpanda.expand(input);
+        }
+
+        let mut hasher = DefaultHasher::new();
+        self.vars_hash.hash(&mut hasher);
+        input.hash(&mut hasher);
+        let key = hasher.finish();
+
+        if let Some(entry) = self.lock().get(&key) {
+            if entry.input == input {
+                return entry.result.clone();
+            }
+        }
+
+        // The lock isn't held while expanding, so other threads aren't blocked meanwhile
+        let result = self.xpanda.expand(input);
+        let mut entries = self.lock();
+
+        if self
+            .capacity
+            .is_some_and(|capacity| entries.len() >= capacity)
+        {
+            entries.clear();
+        }
+
+        if self.capacity != Some(0) {
+            let entry = CacheEntry {
+                input: input.to_string(),
+                result: result.clone(),
+            };
+            entries.insert(key, entry);
+        }
+
+        result
+    }
+
+    /// Returns the number of cached results.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Returns `true` if no results are cached.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Removes all cached results.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Returns the wrapped instance.
+    #[must_use]
+    pub const fn inner(&self) -> &Xpanda {
+        &self.xpanda
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<u64, CacheEntry>> {
+        // A panic while holding the lock can't leave the map in an inconsistent state
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
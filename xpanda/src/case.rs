@@ -0,0 +1,84 @@
+#[cfg(feature = "locale")]
+use icu_casemap::CaseMapper;
+#[cfg(feature = "locale")]
+use icu_locid::LanguageIdentifier;
+#[cfg(feature = "locale")]
+use std::fmt::{self, Display, Formatter};
+#[cfg(feature = "locale")]
+use std::str::FromStr;
+
+/// A locale whose language specific rules apply to case modifiers such as `${VAR^^}`, set using
+/// [`Builder::case_locale`](crate::Builder::case_locale).
+///
+/// The default Unicode case mapping is wrong for some languages, most notably Turkish and
+/// Azerbaijani, in which the uppercase of `i` is `İ` and the lowercase of `I` is `ı`.
+///
+/// # Examples
+///
+/// ```
+/// use xpanda::CaseLocale;
+///
+/// let locale = "tr-TR".parse::<CaseLocale>().unwrap();
+///
+/// assert_eq!(locale.to_string(), "tr-TR");
+/// assert!("not a locale".parse::<CaseLocale>().is_err());
+/// ```
+#[cfg(feature = "locale")]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct CaseLocale(LanguageIdentifier);
+
+#[cfg(feature = "locale")]
+impl FromStr for CaseLocale {
+    type Err = String;
+
+    /// Parses a BCP 47 language tag, such as `tr` or `az-Latn-AZ`.
+    fn from_str(tag: &str) -> Result<Self, Self::Err> {
+        tag.parse()
+            .map(Self)
+            .map_err(|_| format!("Invalid locale '{tag}'"))
+    }
+}
+
+#[cfg(feature = "locale")]
+impl Display for CaseLocale {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Maps the case of values for case modifiers, using the rules of the locale if one is set and
+/// the default Unicode case mapping otherwise.
+#[derive(Debug, Clone, Default, Hash)]
+pub struct CaseMapping {
+    #[cfg(feature = "locale")]
+    locale: Option<CaseLocale>,
+}
+
+impl CaseMapping {
+    #[cfg(feature = "locale")]
+    pub const fn new(locale: CaseLocale) -> Self {
+        Self {
+            locale: Some(locale),
+        }
+    }
+
+    #[cfg_attr(not(feature = "locale"), allow(clippy::unused_self))]
+    pub fn uppercase(&self, text: &str) -> String {
+        #[cfg(feature = "locale")]
+        if let Some(locale) = &self.locale {
+            return CaseMapper::new().uppercase_to_string(text, &locale.0);
+        }
+
+        text.to_uppercase()
+    }
+
+    #[cfg_attr(not(feature = "locale"), allow(clippy::unused_self))]
+    pub fn lowercase(&self, text: &str) -> String {
+        #[cfg(feature = "locale")]
+        if let Some(locale) = &self.locale {
+            return CaseMapper::new().lowercase_to_string(text, &locale.0);
+        }
+
+        text.to_lowercase()
+    }
+}
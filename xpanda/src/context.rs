@@ -0,0 +1,81 @@
+use crate::position::Position;
+
+/// The namespace of context variables, as in `${xpanda:line}`.
+pub const NAMESPACE: &str = "xpanda";
+
+/// Information about the expansion itself, such as the name of the input file.
+///
+/// Once set using [`Builder::with_context`](crate::Builder::with_context), templates can
+/// reference it as variables in the `xpanda` namespace, for example to watermark generated
+/// files:
+///
+/// * `${xpanda:line}`: the line of the input the variable appears on.
+/// * `${xpanda:file}`: the name of the input file, unset unless one is given.
+/// * `${xpanda:timestamp}`: the time of expansion, unset unless one is given.
+///
+/// The context takes precedence over any provider registered under the `xpanda` namespace.
+///
+/// # Examples
+///
+/// ```
+/// use xpanda::{Context, Xpanda};
+///
+/// let context = Context::new().file("config.tpl");
+/// let xpanda = Xpanda::builder().with_context(context).build();
+/// let input = "# Generated from ${xpanda:file}\n# line ${xpanda:line}";
+///
+/// assert_eq!(
+///     xpanda.expand(input),
+///     Ok(String::from("# Generated from config.tpl\n# line 2"))
+/// );
+/// ```
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Context {
+    file: Option<String>,
+    timestamp: Option<String>,
+    line_offset: usize,
+}
+
+impl Context {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the name of the input file, substituted for `${xpanda:file}`.
+    #[must_use]
+    pub fn file(mut self, file: impl Into<String>) -> Self {
+        self.file = Some(file.into());
+        self
+    }
+
+    /// Sets the time of expansion, substituted for `${xpanda:timestamp}` as is. Any format can
+    /// be used, such as RFC 3339.
+    #[must_use]
+    pub fn timestamp(mut self, timestamp: impl Into<String>) -> Self {
+        self.timestamp = Some(timestamp.into());
+        self
+    }
+
+    /// Sets the line number of the first line of the input, for inputs which are part of a
+    /// larger file. Defaults to 1.
+    #[must_use]
+    pub const fn first_line(mut self, line: usize) -> Self {
+        self.line_offset = line.saturating_sub(1);
+        self
+    }
+
+    /// Returns the value of the given key for a variable at the given position.
+    pub(crate) fn get(&self, key: &str, position: Position) -> Result<Option<String>, String> {
+        match key {
+            "line" => Ok(Some(
+                position.line.saturating_add(self.line_offset).to_string(),
+            )),
+            "file" => Ok(self.file.clone()),
+            "timestamp" => Ok(self.timestamp.clone()),
+            _ => Err(String::from(
+                "Unknown key, expected 'line', 'file' or 'timestamp'",
+            )),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use crate::position::{Position, Span};
+use crate::Error;
+
+/// A single document of an input consisting of multiple documents, separated by lines which
+/// consist of nothing but a delimiter such as `---`.
+///
+/// Each document can be expanded independently, and errors which occur while doing so can be
+/// located within the whole input using [`Document::locate`].
+///
+/// # Examples
+///
+/// ```
+/// use xpanda::{Document, Xpanda};
+///
+/// let input = "a\n---\n${1\n";
+/// let documents = Document::split(input, "---");
+/// let error = Xpanda::default().expand(documents[1].text).unwrap_err();
+///
+/// assert_eq!(documents[0].text, "a\n");
+/// assert_eq!(documents[1].text, "${1\n");
+/// assert_eq!(documents[1].locate(error).line, 3);
+/// ```
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Document<'a> {
+    /// The text of the document, excluding the delimiter line which ends it.
+    pub text: &'a str,
+    /// The position of the first character of the document within the whole input.
+    pub start: Position,
+    /// The delimiter line which ends the document, including its line ending. Empty for the
+    /// last document.
+    pub delimiter: &'a str,
+}
+
+impl<'a> Document<'a> {
+    /// Splits the input into documents at every line which equals the delimiter once its line
+    /// ending is removed. There is always at least one document, and joining the text and
+    /// delimiter of all documents yields the input.
+    #[must_use]
+    pub fn split(input: &'a str, delimiter: &str) -> Vec<Self> {
+        let mut documents = Vec::new();
+        let mut start = Position::default();
+        let mut index = 0;
+
+        for (line, text) in (1..).zip(input.split_inclusive('\n')) {
+            if text.trim_end_matches(['\n', '\r']) == delimiter {
+                documents.push(Self {
+                    text: &input[start.index..index],
+                    start,
+                    delimiter: text,
+                });
+                start = Position::new(index + text.len(), line + 1, 1);
+            }
+
+            index += text.len();
+        }
+
+        documents.push(Self {
+            text: &input[start.index..],
+            start,
+            delimiter: "",
+        });
+
+        documents
+    }
+
+    /// Translates the position of an error which occurred while expanding this document into
+    /// the position within the whole input.
+    #[must_use]
+    pub fn locate(&self, error: Error) -> Error {
+        let Some(span) = error.span else {
+            return error;
+        };
+
+        Error {
+            line: error.line + self.start.line - 1,
+            span: Some(Span::new(self.offset(span.start), self.offset(span.end))),
+            ..error
+        }
+    }
+
+    // Documents always start at the beginning of a line, so columns stay the same
+    const fn offset(&self, position: Position) -> Position {
+        Position::new(
+            position.index + self.start.index,
+            position.line + self.start.line - 1,
+            position.col,
+        )
+    }
+}
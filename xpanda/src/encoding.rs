@@ -0,0 +1,176 @@
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const HEX_DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+/// Percent-encodes all bytes of the text except the unreserved characters of RFC 3986.
+pub fn percent_encode(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            result.push(char::from(byte));
+        } else {
+            result.push('%');
+            result.push(char::from(HEX_DIGITS[usize::from(byte >> 4)]));
+            result.push(char::from(HEX_DIGITS[usize::from(byte & 0x0f)]));
+        }
+    }
+
+    result
+}
+
+/// Decodes percent-encoded text. Returns [`None`] if an escape sequence is malformed or if the
+/// decoded bytes are not valid UTF-8.
+pub fn percent_decode(text: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut iter = text.bytes();
+
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let high = hex_value(iter.next()?)?;
+            let low = hex_value(iter.next()?)?;
+            bytes.push(high << 4 | low);
+        } else {
+            bytes.push(byte);
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+/// Encodes the text as padded base64 using the standard alphabet.
+pub fn base64_encode(text: &str) -> String {
+    let mut result = String::with_capacity(text.len().div_ceil(3) * 4);
+
+    for chunk in text.as_bytes().chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let sextets = [
+            bytes[0] >> 2,
+            (bytes[0] & 0x03) << 4 | bytes[1] >> 4,
+            (bytes[1] & 0x0f) << 2 | bytes[2] >> 6,
+            bytes[2] & 0x3f,
+        ];
+
+        for (index, sextet) in sextets.iter().enumerate() {
+            if index <= chunk.len() {
+                result.push(char::from(BASE64_ALPHABET[usize::from(*sextet)]));
+            } else {
+                result.push('=');
+            }
+        }
+    }
+
+    result
+}
+
+/// Decodes base64 text using the standard alphabet, where padding is optional. Returns [`None`]
+/// if the text is not valid base64 or if the decoded bytes are not valid UTF-8.
+pub fn base64_decode(text: &str) -> Option<String> {
+    let text = text.trim_end_matches('=');
+
+    if text.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+
+    for chunk in text.as_bytes().chunks(4) {
+        let mut buffer: u32 = 0;
+
+        for byte in chunk {
+            let value = BASE64_ALPHABET.iter().position(|char| char == byte)?;
+            buffer = buffer << 6 | u32::try_from(value).ok()?;
+        }
+
+        buffer <<= 6 * (4 - chunk.len());
+
+        let decoded = buffer.to_be_bytes();
+        bytes.extend_from_slice(&decoded[1..chunk.len()]);
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+/// Quotes the text as a single word for POSIX shells using single quotes, inside which nothing
+/// is special. Single quotes inside the text are written as `'\''`.
+pub fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    char::from(byte)
+        .to_digit(16)
+        .and_then(|digit| u8::try_from(digit).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_encode_reserved() {
+        assert_eq!(percent_encode("a b&c/d"), "a%20b%26c%2Fd");
+    }
+
+    #[test]
+    fn percent_encode_unreserved() {
+        assert_eq!(percent_encode("AZaz09-._~"), "AZaz09-._~");
+    }
+
+    #[test]
+    fn percent_encode_multibyte() {
+        assert_eq!(percent_encode("å"), "%C3%A5");
+    }
+
+    #[test]
+    fn percent_decode_valid() {
+        assert_eq!(
+            percent_decode("a%20b%2fc%C3%A5"),
+            Some(String::from("a b/cå"))
+        );
+    }
+
+    #[test]
+    fn percent_decode_malformed() {
+        assert_eq!(percent_decode("%2"), None);
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%FF"), None);
+    }
+
+    #[test]
+    fn base64_encode_padding() {
+        assert_eq!(base64_encode(""), "");
+        assert_eq!(base64_encode("f"), "Zg==");
+        assert_eq!(base64_encode("fo"), "Zm8=");
+        assert_eq!(base64_encode("foo"), "Zm9v");
+        assert_eq!(base64_encode("foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn base64_decode_valid() {
+        assert_eq!(base64_decode(""), Some(String::new()));
+        assert_eq!(base64_decode("Zg=="), Some(String::from("f")));
+        assert_eq!(base64_decode("Zm8"), Some(String::from("fo")));
+        assert_eq!(base64_decode("Zm9vYmFy"), Some(String::from("foobar")));
+    }
+
+    #[test]
+    fn shell_quote_special() {
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("word"), "'word'");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("$HOME"), "'$HOME'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn base64_decode_invalid() {
+        assert_eq!(base64_decode("Z"), None);
+        assert_eq!(base64_decode("Zm9v!"), None);
+        assert_eq!(base64_decode("/w=="), None);
+    }
+}
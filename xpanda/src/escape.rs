@@ -0,0 +1,121 @@
+use crate::front_matter;
+use crate::syntax::Syntax;
+
+/// Escapes the given text such that expanding it yields the text as is, by doubling every `$`.
+///
+/// This uses the default syntax, see [`Xpanda::escape`](crate::Xpanda::escape) for text which
+/// is expanded using other syntax options. See [`unescape`] for the inverse.
+///
+/// # Examples
+///
+/// ```
+/// use xpanda::{escape, Xpanda};
+///
+/// let text = "Price: $5, or ${PRICE:-$10}";
+/// assert_eq!(escape(text), "Price: $$5, or $${PRICE:-$$10}");
+/// assert_eq!(Xpanda::default().expand(&escape(text)), Ok(String::from(text)));
+/// ```
+#[must_use]
+pub fn escape(text: &str) -> String {
+    escape_with_syntax(text, &Syntax::default()).unwrap_or_default()
+}
+
+/// Reverses [`escape`], turning every `$$` back into a single `$`.
+///
+/// Other text is left as is, including any params. This uses the default syntax, see
+/// [`Xpanda::unescape`](crate::Xpanda::unescape) for text which is escaped using other syntax
+/// options.
+///
+/// # Examples
+///
+/// ```
+/// use xpanda::{escape, unescape};
+///
+/// assert_eq!(unescape("Price: $$5, or $${PRICE:-$$10}"), "Price: $5, or ${PRICE:-$10}");
+/// assert_eq!(unescape(&escape("$$$")), "$$$");
+/// ```
+#[must_use]
+pub fn unescape(text: &str) -> String {
+    unescape_with_syntax(text, &Syntax::default())
+}
+
+/// Escapes the text for the syntax, or returns [`None`] if the syntax has no way to write some
+/// of it, which is the case for a `$` if `$$` is a special variable, or for text which starts
+/// like front matter.
+pub fn escape_with_syntax(text: &str, syntax: &Syntax) -> Option<String> {
+    if syntax.front_matter && text.starts_with(front_matter::PREFIX) {
+        return None;
+    }
+
+    let mut escaped = String::with_capacity(text.len());
+
+    for (part, is_opaque) in split_opaque(text, syntax) {
+        if is_opaque {
+            escaped.push_str(part);
+        } else if part.contains('$') && syntax.is_special_var('$') {
+            return None;
+        } else {
+            escaped.push_str(&part.replace('$', "$$"));
+        }
+    }
+
+    Some(escaped)
+}
+
+/// Unescapes the text for the syntax, in which `$$` is only an escaped `$` if it isn't a
+/// special variable.
+pub fn unescape_with_syntax(text: &str, syntax: &Syntax) -> String {
+    if syntax.is_special_var('$') {
+        return text.to_string();
+    }
+
+    split_opaque(text, syntax)
+        .into_iter()
+        .map(|(part, is_opaque)| {
+            if is_opaque {
+                part.to_string()
+            } else {
+                part.replace("$$", "$")
+            }
+        })
+        .collect()
+}
+
+/// Splits the text into the opaque regions of the syntax and the text around them, along with
+/// whether each part is opaque. An opaque region is only recognized if it starts before the
+/// next `$`, just like when reading a template, which isn't affected by escaping each `$`.
+fn split_opaque<'a>(text: &'a str, syntax: &Syntax) -> Vec<(&'a str, bool)> {
+    let Some(regions) = &syntax.opaque_regions else {
+        return vec![(text, false)];
+    };
+
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut index = 0;
+
+    while index < text.len() {
+        let rest = &text[index..];
+        let dollar = rest.find('$').unwrap_or(rest.len());
+        let region = rest[..dollar].find(&regions.open).map(|open| {
+            regions
+                .find(&rest[open..])
+                .map(|region| open + region.start..open + region.end)
+        });
+
+        match region {
+            Some(Some(region)) => {
+                parts.push((&text[start..index + region.start], false));
+                parts.push((&text[index + region.start..index + region.end], true));
+                index += region.end;
+                start = index;
+            },
+            // Once a region is never closed, none of the following ones are either
+            Some(None) => break,
+            None => index += (dollar + 1).min(rest.len()),
+        }
+    }
+
+    parts.push((&text[start..], false));
+    parts.retain(|(part, _)| !part.is_empty());
+    parts
+}
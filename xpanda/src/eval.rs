@@ -1,102 +1,785 @@
 use crate::ast::{Ast, Identifier, Modifier, Node, Param};
+use crate::bytecode::{Op, Program};
+use crate::case::CaseMapping;
+use crate::context::{self, Context};
+use crate::encoding;
+#[cfg(feature = "expr")]
+use crate::expr::Expression;
+use crate::metrics::{Phase, ProviderMetrics, Recorder, Timing};
 use crate::parser::{self, Parser};
+use crate::pattern::{self, GlobEngine, Pattern, PatternEngine};
 use crate::position::Position;
-use std::collections::HashMap;
+use crate::positional::PositionalVars;
+use crate::provider::Provider;
+use crate::schema::Schema;
+use crate::syntax::Syntax;
+use crate::wipe::{wipe, Buffer, NamedVars};
+use crate::{Builder, ErrorKind};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::hash::{Hash, Hasher};
+use std::slice;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+#[cfg(feature = "length")]
+use unicode_segmentation::UnicodeSegmentation;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Error {
+    pub kind: ErrorKind,
     pub message: String,
     pub position: Position,
 }
 
 impl Error {
-    const fn new(message: String, position: Position) -> Self {
-        Self { message, position }
+    pub(crate) const fn new(kind: ErrorKind, message: String, position: Position) -> Self {
+        Self {
+            kind,
+            message,
+            position,
+        }
     }
 }
 
-#[derive(Default)]
+/// A function transforming the value of a variable, given its name and value.
+pub type ValueTransform = Arc<dyn Fn(&str, &str) -> String + Send + Sync>;
+
+/// How named variables are resolved from the environment of the process.
+#[derive(Debug, Clone, Default)]
+pub struct EnvLookup {
+    /// Whether to resolve variables from the environment at the time of expansion.
+    pub live: bool,
+    /// Whether names of environment variables are matched case-insensitively.
+    pub case_insensitive: bool,
+    /// Whether the output must not depend on the platform, see
+    /// [`Builder::reproducible`](crate::Builder::reproducible).
+    pub reproducible: bool,
+    /// The uppercased names of all captured environment variables, mapped to their actual names.
+    pub names: Arc<HashMap<String, String>>,
+}
+
+/// Default values of named variables, such as those declared in the front matter of a template,
+/// used when a variable is otherwise unset.
+pub type Defaults<'a> = &'a [(String, String)];
+
+/// A function invoked whenever an unset variable is encountered.
+pub type UnsetListener = Arc<dyn Fn(&UnsetEvent) + Send + Sync>;
+
+/// A function invoked whenever a variable is resolved.
+pub type ResolveListener = Arc<dyn Fn(&ResolveEvent) + Send + Sync>;
+
+/// A function invoked whenever a warning is raised.
+pub type WarningListener = Arc<dyn Fn(&Warning) + Send + Sync>;
+
+/// A function invoked whenever a phase of expanding a template is finished.
+pub type TimingListener = Arc<dyn Fn(&Timing) + Send + Sync>;
+
+/// A function deciding whether the variable of the given name may be resolved.
+pub type AccessPolicy = Arc<dyn Fn(&str) -> Access + Send + Sync>;
+
+/// Whether a variable may be resolved, as decided by the policy set using
+/// [`Builder::access_policy`](crate::Builder::access_policy).
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum Access {
+    /// The variable is resolved as usual.
+    Allow,
+    /// The variable is treated as unset, whether or not it's set. Default values given by the
+    /// template still apply.
+    Hide,
+    /// Resolving the variable yields an error of kind
+    /// [`ErrorKind::Policy`](crate::ErrorKind::Policy).
+    Deny,
+    /// The value of the variable, if set, is replaced with the given text.
+    Redact(String),
+}
+
+/// Where the value of a variable comes from, see [`Xpanda::sources`](crate::Xpanda::sources) and
+/// [`Builder::on_resolve`](crate::Builder::on_resolve).
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum Source {
+    /// A positional variable.
+    Positional,
+    /// A special parameter added using
+    /// [`Builder::with_special_vars`](crate::Builder::with_special_vars).
+    Special,
+    /// A named variable added using [`Builder::with_named_vars`](crate::Builder::with_named_vars),
+    /// or using [`Builder::with_labeled_vars`](crate::Builder::with_labeled_vars) along with
+    /// the given label.
+    Named(Option<String>),
+    /// An environment variable added using
+    /// [`Builder::with_env_vars`](crate::Builder::with_env_vars).
+    Env,
+    /// An environment variable resolved at the time of expansion, see
+    /// [`Builder::with_live_env`](crate::Builder::with_live_env).
+    LiveEnv,
+    /// A default value declared by the front matter of the template.
+    FrontMatter,
+    /// A namespaced variable, resolved by the provider of the given namespace or from the
+    /// context of the expansion.
+    Namespace(String),
+}
+
+/// A named variable found by [`Evaluator::find_named`].
+enum Found<'a> {
+    /// The name of a variable added while building the evaluator.
+    Captured(&'a str),
+    /// The value of a variable of the live environment.
+    Live(String),
+}
+
+/// Describes an occurrence of an unset variable, see
+/// [`Builder::on_unset`](crate::Builder::on_unset).
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct UnsetEvent {
+    /// The name of the variable, or its index for positional variables.
+    pub name: String,
+    /// The position of the `$` of the param referencing the variable.
+    pub position: Position,
+    /// How the param was resolved.
+    pub outcome: UnsetOutcome,
+}
+
+/// A likely mistake in a template noticed while expanding it, see
+/// [`Builder::on_warning`](crate::Builder::on_warning).
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub message: String,
+    /// The position of the `$` of the offending param.
+    pub position: Position,
+}
+
+/// The kind of a [`Warning`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum WarningKind {
+    /// A positional variable beyond the number of positional variables given, such as `$7`
+    /// with three given, was substituted with an empty string. Params with a default or
+    /// alternative value, such as `${7-none}` or `${7:+set}`, are exempt.
+    PositionalOutOfRange {
+        /// The index of the referenced variable, starting at 1.
+        index: usize,
+        /// The number of positional variables given.
+        count: usize,
+    },
+}
+
+/// Describes the resolution of a variable while expanding, see
+/// [`Builder::on_resolve`](crate::Builder::on_resolve).
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct ResolveEvent {
+    /// The name of the variable, or its index for positional variables.
+    pub name: String,
+    /// The position of the `$` of the param referencing the variable.
+    pub position: Position,
+    /// Where the value of the variable came from.
+    pub source: Source,
+}
+
+/// How a param referencing an unset variable was resolved.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum UnsetOutcome {
+    /// Substituted with an empty string, or `0` in case of `${#VAR}`.
+    Empty,
+    /// Substituted with the default value, as in `${VAR-default}`.
+    Default,
+    /// Yielded an error, either because of the pattern used, as in `${VAR?}`, or because
+    /// [`Builder::no_unset`](crate::Builder::no_unset) is `true`.
+    Error,
+}
+
+/// How the length of a value is counted by `${#VAR}`, set using
+/// [`Builder::length_mode`](crate::Builder::length_mode).
+///
+/// Unlike in Bash, where the length depends on the locale, the length is counted the same way
+/// regardless of the locale.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum LengthMode {
+    /// The number of bytes of the value encoded as UTF-8, e.g. `2` for `é`.
+    #[default]
+    Bytes,
+    /// The number of Unicode scalar values, e.g. `1` for `é` but `2` for `e` followed by a
+    /// combining acute accent.
+    Chars,
+    /// The number of extended grapheme clusters, i.e. characters as perceived by users, e.g.
+    /// `1` for `é` however it's encoded.
+    Graphemes,
+}
+
+/// What happens to values longer than the limit set using
+/// [`Builder::max_value_length`](crate::Builder::max_value_length).
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum LengthPolicy {
+    /// Yields an error of kind [`ErrorKind::InvalidValue`].
+    #[default]
+    Error,
+    /// Truncates the value to the limit.
+    Truncate,
+}
+
+/// The outcome of evaluating a param, which is either its value or the nested nodes to evaluate
+/// in its place, such as the default value of `${VAR-default}` if `VAR` is unset or the nodes of
+/// the branch of a conditional block which is taken. Nested nodes are only evaluated once they're
+/// reached, which guarantees that nodes are evaluated from left to right and only if needed.
+enum Step<'n, 'a> {
+    Value(String),
+    Node(&'n Node<'a>),
+    Nodes(&'n [Node<'a>]),
+}
+
+/// Evaluates templates using the variables and callbacks given to the builder. Variables and
+/// callbacks are shared between clones, and so are the metrics of providers.
+#[derive(Clone, Default)]
 pub struct Evaluator {
     no_unset: bool,
-    positional_vars: Vec<String>,
-    named_vars: HashMap<String, String>,
+    /// Names of variables for which `no_unset` is overridden, mapped to whether being unset is
+    /// an error.
+    unset_overrides: Arc<HashMap<String, bool>>,
+    no_empty: bool,
+    safe_mode: bool,
+    length_mode: LengthMode,
+    max_value_length: Option<(usize, LengthPolicy)>,
+    context: Option<Context>,
+    positional_vars: Arc<PositionalVars>,
+    /// Names of positional variables, mapped to their zero-based index.
+    positional_names: Arc<HashMap<String, usize>>,
+    special_vars: Arc<HashMap<char, String>>,
+    named_vars: Arc<NamedVars>,
+    named_sources: Arc<HashMap<String, Source>>,
+    env: EnvLookup,
+    aliases: Arc<HashMap<String, String>>,
+    providers: HashMap<String, Arc<dyn Provider>>,
+    metrics: Arc<HashMap<String, Recorder>>,
+    value_transforms: Vec<ValueTransform>,
+    unset_listeners: Vec<UnsetListener>,
+    resolve_listeners: Vec<ResolveListener>,
+    warning_listeners: Vec<WarningListener>,
+    timing_listeners: Vec<TimingListener>,
+    access_policy: Option<AccessPolicy>,
+    pattern_engine: Option<Arc<dyn PatternEngine>>,
+    case_mapping: CaseMapping,
 }
 
 impl Evaluator {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         no_unset: bool,
-        positional_vars: Vec<String>,
-        named_vars: HashMap<String, String>,
+        unset_overrides: Arc<HashMap<String, bool>>,
+        no_empty: bool,
+        safe_mode: bool,
+        length_mode: LengthMode,
+        max_value_length: Option<(usize, LengthPolicy)>,
+        context: Option<Context>,
+        positional_vars: Arc<PositionalVars>,
+        positional_names: Arc<HashMap<String, usize>>,
+        special_vars: Arc<HashMap<char, String>>,
+        named_vars: Arc<NamedVars>,
+        named_sources: Arc<HashMap<String, Source>>,
+        env: EnvLookup,
+        aliases: Arc<HashMap<String, String>>,
+        providers: HashMap<String, Arc<dyn Provider>>,
+        value_transforms: Vec<ValueTransform>,
+        unset_listeners: Vec<UnsetListener>,
+        resolve_listeners: Vec<ResolveListener>,
+        warning_listeners: Vec<WarningListener>,
+        timing_listeners: Vec<TimingListener>,
+        access_policy: Option<AccessPolicy>,
+        pattern_engine: Option<Arc<dyn PatternEngine>>,
+        case_mapping: CaseMapping,
     ) -> Self {
+        let metrics = providers
+            .keys()
+            .map(|namespace| (namespace.clone(), Recorder::default()))
+            .collect::<HashMap<_, _>>();
+
         Self {
             no_unset,
+            unset_overrides,
+            no_empty,
+            safe_mode,
+            length_mode,
+            max_value_length,
+            context,
             positional_vars,
+            positional_names,
+            special_vars,
             named_vars,
+            named_sources,
+            env,
+            aliases,
+            providers,
+            metrics: Arc::new(metrics),
+            value_transforms,
+            unset_listeners,
+            resolve_listeners,
+            warning_listeners,
+            timing_listeners,
+            access_policy,
+            pattern_engine,
+            case_mapping,
         }
     }
 
-    pub fn eval(&self, ast: Ast) -> Result<String, Error> {
-        let mut result = String::new();
+    /// Compiles a pattern matched against values, using the configured engine or glob patterns
+    /// by default.
+    fn compile_pattern(
+        &self,
+        pattern: &str,
+        position: Position,
+    ) -> Result<Box<dyn Pattern>, Error> {
+        let engine = self.pattern_engine.as_deref().unwrap_or(&GlobEngine);
+
+        pattern::compile(engine, pattern).map_err(|message| {
+            Error::new(
+                ErrorKind::Syntax,
+                format!("Invalid pattern '{pattern}': {message}"),
+                position,
+            )
+        })
+    }
 
-        for node in ast.nodes {
-            let text = self.eval_node(node)?;
-            result.push_str(&text);
+    /// Evaluates the AST, falling back to the given defaults for named variables which are
+    /// otherwise unset.
+    pub fn eval(&self, ast: &Ast, defaults: Defaults) -> Result<String, Error> {
+        self.eval_nodes(&ast.nodes, defaults)
+    }
+
+    /// Evaluates a compiled program, which yields the same result as evaluating the AST it was
+    /// compiled from, but without recursion or allocating the output of each node separately.
+    pub fn run(&self, program: &Program, defaults: Defaults) -> Result<String, Error> {
+        let mut output = Buffer::with_capacity(program.text.len());
+        let mut index = 0;
+
+        while let Some(op) = program.ops.get(index) {
+            index += 1;
+
+            match op {
+                Op::Text { start, end } => output.push_str(&program.text[*start..*end]),
+                Op::Param(param, position) => {
+                    let value = match self.eval_param(param, *position, defaults)? {
+                        Step::Value(value) => value,
+                        Step::Node(node) => self.eval_nodes(slice::from_ref(node), defaults)?,
+                        Step::Nodes(nodes) => self.eval_nodes(nodes, defaults)?,
+                    };
+                    output.push_str(&value);
+                    wipe(value);
+                },
+                #[cfg(feature = "expr")]
+                Op::Expr(expression, position) => {
+                    let value = self.eval_expression(expression, *position, defaults)?;
+                    output.push_str(&value);
+                    wipe(value);
+                },
+                Op::Default {
+                    identifier,
+                    treat_empty_as_unset,
+                    position,
+                    end,
+                } => {
+                    let name = self.eval_dynamic_name(identifier, *position, defaults)?;
+                    let computed = name.as_deref().map(|name| {
+                        Identifier::from_name(name, |c| self.special_vars.contains_key(&c))
+                    });
+                    let identifier = computed.as_ref().unwrap_or(identifier);
+
+                    match self
+                        .eval_identifier(identifier, *position, defaults)?
+                        .filter(|value| !(*treat_empty_as_unset && value.is_empty()))
+                    {
+                        Some(value) => {
+                            output.push_str(&value);
+                            wipe(value);
+                            index = *end;
+                        },
+                        None => self.notify_unset(identifier, *position, UnsetOutcome::Default),
+                    }
+                },
+                Op::Alt {
+                    identifier,
+                    treat_empty_as_unset,
+                    position,
+                    end,
+                } => {
+                    let name = self.eval_dynamic_name(identifier, *position, defaults)?;
+                    let computed = name.as_deref().map(|name| {
+                        Identifier::from_name(name, |c| self.special_vars.contains_key(&c))
+                    });
+                    let identifier = computed.as_ref().unwrap_or(identifier);
+
+                    let value = self
+                        .eval_identifier(identifier, *position, defaults)?
+                        .filter(|value| !(*treat_empty_as_unset && value.is_empty()));
+
+                    if let Some(value) = value {
+                        wipe(value);
+                    } else {
+                        self.notify_unset(identifier, *position, UnsetOutcome::Empty);
+                        index = *end;
+                    }
+                },
+            }
         }
 
-        Ok(result)
+        Ok(output.into_string())
+    }
+
+    /// Whether all values are known up front, as opposed to being resolved from the
+    /// environment or a provider at the time of expansion.
+    pub fn is_static(&self) -> bool {
+        !self.env.live && self.providers.is_empty()
+    }
+
+    /// Feeds everything affecting the result of evaluation, other than the AST itself, into the
+    /// hasher. Only meaningful if the evaluator [is static](Self::is_static).
+    pub fn hash_vars(&self, state: &mut impl Hasher) {
+        self.no_unset.hash(state);
+        self.unset_overrides
+            .iter()
+            .collect::<BTreeMap<_, _>>()
+            .hash(state);
+        self.no_empty.hash(state);
+        self.safe_mode.hash(state);
+        self.length_mode.hash(state);
+        self.max_value_length.hash(state);
+        self.case_mapping.hash(state);
+        self.context.hash(state);
+        self.positional_vars.hash(state);
+        self.special_vars
+            .iter()
+            .collect::<BTreeMap<_, _>>()
+            .hash(state);
+        self.named_vars
+            .iter()
+            .collect::<BTreeMap<_, _>>()
+            .hash(state);
+        self.positional_names
+            .iter()
+            .collect::<BTreeMap<_, _>>()
+            .hash(state);
+        self.aliases.iter().collect::<BTreeMap<_, _>>().hash(state);
+        self.env.case_insensitive.hash(state);
+        self.env
+            .names
+            .iter()
+            .collect::<BTreeMap<_, _>>()
+            .hash(state);
+    }
+
+    /// Creates a builder with the same variables and callbacks, which share their memory with
+    /// this evaluator until the builder changes them.
+    pub fn to_builder(&self, schema: Schema, syntax: Syntax) -> Builder {
+        Builder {
+            no_unset: self.no_unset,
+            unset_overrides: Arc::clone(&self.unset_overrides),
+            no_empty: self.no_empty,
+            length_mode: self.length_mode,
+            max_value_length: self.max_value_length,
+            context: self.context.clone(),
+            positional_vars: Arc::clone(&self.positional_vars),
+            positional_names: Arc::clone(&self.positional_names),
+            special_vars: Arc::clone(&self.special_vars),
+            named_vars: Arc::clone(&self.named_vars),
+            named_sources: Arc::clone(&self.named_sources),
+            env_names: Arc::clone(&self.env.names),
+            live_env: self.env.live,
+            case_insensitive_env: Some(self.env.case_insensitive),
+            reproducible: self.env.reproducible,
+            aliases: Arc::clone(&self.aliases),
+            providers: self.providers.clone(),
+            value_transforms: self.value_transforms.clone(),
+            unset_listeners: self.unset_listeners.clone(),
+            resolve_listeners: self.resolve_listeners.clone(),
+            warning_listeners: self.warning_listeners.clone(),
+            timing_listeners: self.timing_listeners.clone(),
+            access_policy: self.access_policy.clone(),
+            schema,
+            syntax,
+            pattern_engine: self.pattern_engine.clone(),
+            case_mapping: self.case_mapping.clone(),
+            validate_vars: false,
+            not_utf8_env: HashSet::new(),
+        }
+    }
+
+    /// Returns a snapshot of the metrics of each provider, keyed by namespace.
+    pub fn provider_metrics(&self) -> BTreeMap<String, ProviderMetrics> {
+        self.metrics
+            .iter()
+            .map(|(namespace, recorder)| (namespace.clone(), recorder.snapshot()))
+            .collect()
+    }
+
+    /// Resolves the raw value of the named variable, following aliases.
+    pub fn lookup_var(&self, name: &str, defaults: Defaults) -> Option<String> {
+        self.lookup_identifier(&Identifier::Named(name), defaults)
+    }
+
+    /// Resolves the raw value of any variable, such as a positional one, see
+    /// [`Evaluator::lookup_var`].
+    pub fn lookup_identifier(&self, identifier: &Identifier, defaults: Defaults) -> Option<String> {
+        self.lookup(identifier, Position::default(), defaults)
+            .ok()
+            .flatten()
+    }
+
+    /// Computes the name of a dynamic identifier, as in `${PREFIX_${ENV}}`. Returns [`None`] if
+    /// it can't be computed, or for any other identifier.
+    pub fn lookup_dynamic_name(
+        &self,
+        identifier: &Identifier,
+        defaults: Defaults,
+    ) -> Option<String> {
+        self.eval_dynamic_name(identifier, Position::default(), defaults)
+            .ok()
+            .flatten()
+    }
+
+    /// Returns the named variables which were provided rather than taken from the environment,
+    /// but which none of the given names refer to, following aliases, sorted by name.
+    pub fn unused_vars<'n>(
+        &self,
+        names: impl IntoIterator<Item = &'n str>,
+    ) -> Vec<(String, Source)> {
+        let used = names
+            .into_iter()
+            .filter_map(|name| match self.find_named(self.resolve_alias(name)) {
+                Some(Found::Captured(key)) => Some(key),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        let mut unused = self
+            .named_sources
+            .iter()
+            .filter(|(name, source)| {
+                matches!(source, Source::Named(_)) && !used.contains(name.as_str())
+            })
+            .map(|(name, source)| (name.clone(), source.clone()))
+            .collect::<Vec<_>>();
+
+        unused.sort_by(|(a, _), (b, _)| a.cmp(b));
+        unused
+    }
+
+    /// Determines where the value of the identifier comes from, without resolving it. Returns
+    /// [`None`] if the variable is unset or can't be resolved.
+    pub fn source(&self, identifier: &Identifier, defaults: Defaults) -> Option<Source> {
+        let from_defaults = |name| lookup_default(name, defaults).map(|_| Source::FrontMatter);
+
+        match (self.access(identifier), identifier) {
+            (Access::Hide, Identifier::Named(name)) => from_defaults(self.resolve_alias(name)),
+            // Dynamic names are computed before their variable is resolved
+            (Access::Hide | Access::Deny, _) | (_, Identifier::Dynamic(_)) => None,
+            (_, Identifier::Named(name)) => {
+                let name = self.resolve_alias(name);
+
+                if self.lookup_positional_name(name).is_some() {
+                    return Some(Source::Positional);
+                }
+
+                match self.find_named(name) {
+                    Some(Found::Captured(key)) => self.named_sources.get(key).cloned(),
+                    Some(Found::Live(_)) => Some(Source::LiveEnv),
+                    None => from_defaults(name),
+                }
+            },
+            (_, Identifier::Indexed(index)) => self
+                .positional_vars
+                .get(index - 1)
+                .map(|_| Source::Positional),
+            (_, Identifier::Special(c)) => self.special_vars.get(c).map(|_| Source::Special),
+            (_, Identifier::Namespaced { namespace, .. }) => {
+                let is_context = self.context.is_some() && *namespace == context::NAMESPACE;
+                let is_resolvable = is_context || self.providers.contains_key(*namespace);
+
+                (is_resolvable && !self.safe_mode).then(|| Source::Namespace(namespace.to_string()))
+            },
+        }
     }
 
-    fn eval_node(&self, node: Node) -> Result<String, Error> {
-        match node {
-            Node::Text(text) => Ok(text),
-            Node::Param(param) => self.eval_param(param),
+    /// Evaluates the nodes in order, concatenating their values. The nested nodes a param
+    /// evaluates to are evaluated in its place before moving on to the next node, in a loop
+    /// rather than recursively, other than those in names and error messages.
+    fn eval_nodes(&self, nodes: &[Node], defaults: Defaults) -> Result<String, Error> {
+        let mut result = Buffer::default();
+        // The nodes left to evaluate at each level of nesting, innermost last
+        let mut pending = vec![nodes.iter()];
+
+        while let Some(nodes) = pending.last_mut() {
+            let Some(node) = nodes.next() else {
+                pending.pop();
+                continue;
+            };
+
+            let step = match node {
+                Node::Text(text, _) => {
+                    result.push_str(text);
+                    continue;
+                },
+                Node::Param(param, position) => self.eval_param(param, *position, defaults)?,
+                #[cfg(feature = "expr")]
+                Node::Expr(expression, position) => {
+                    Step::Value(self.eval_expression(expression, *position, defaults)?)
+                },
+            };
+
+            match step {
+                Step::Value(value) => {
+                    result.push_str(&value);
+                    wipe(value);
+                },
+                Step::Node(nested) => pending.push(slice::from_ref(nested).iter()),
+                Step::Nodes(nested) => pending.push(nested.iter()),
+            }
         }
+
+        Ok(result.into_string())
     }
 
-    fn eval_param(&self, param: Param) -> Result<String, Error> {
-        match param {
+    fn eval_param<'n, 'a>(
+        &self,
+        param: &'n Param<'a>,
+        position: Position,
+        defaults: Defaults,
+    ) -> Result<Step<'n, 'a>, Error> {
+        let name = match param.identifier() {
+            Some(identifier) => self.eval_dynamic_name(identifier, position, defaults)?,
+            None => None,
+        };
+        let computed = name
+            .as_deref()
+            .map(|name| Identifier::from_name(name, |c| self.special_vars.contains_key(&c)));
+        let named = |identifier| computed.as_ref().unwrap_or(identifier);
+
+        let value = match param {
             Param::Simple {
                 identifier,
                 modifier,
-            } => modifier.map_or_else(
-                || self.eval_simple_param(&identifier),
-                |modifier| self.eval_param_with_modifier(&identifier, &modifier),
+            } => modifier.as_ref().map_or_else(
+                || self.eval_simple_param(named(identifier), position, defaults),
+                |modifier| {
+                    self.eval_param_with_modifier(named(identifier), modifier, position, defaults)
+                },
             ),
             Param::WithDefault {
                 identifier,
                 default,
                 treat_empty_as_unset,
-            } => self.eval_default_param(&identifier, *default, treat_empty_as_unset),
+            } => {
+                return self.eval_default_param(
+                    named(identifier),
+                    default,
+                    *treat_empty_as_unset,
+                    position,
+                    defaults,
+                );
+            },
             Param::WithAlt {
                 identifier,
                 alt,
                 treat_empty_as_unset,
-            } => self.eval_alt_param(&identifier, *alt, treat_empty_as_unset),
+            } => {
+                return self.eval_alt_param(
+                    named(identifier),
+                    alt,
+                    *treat_empty_as_unset,
+                    position,
+                    defaults,
+                );
+            },
             Param::WithError {
                 identifier,
                 error,
                 treat_empty_as_unset,
-            } => self.eval_error_param(&identifier, error, treat_empty_as_unset),
-            Param::Length { identifier } => self.eval_length_param(&identifier),
+            } => self.eval_error_param(
+                named(identifier),
+                error,
+                *treat_empty_as_unset,
+                position,
+                defaults,
+            ),
+            #[cfg(feature = "length")]
+            Param::Length { identifier } => {
+                self.eval_length_param(named(identifier), position, defaults)
+            },
+            #[cfg(feature = "length")]
             Param::Arity => self.eval_arity_param(),
-            Param::Ref { identifier } => self.eval_ref_param(&identifier),
+            #[cfg(feature = "indirection")]
+            Param::Ref { identifier } => self.eval_ref_param(named(identifier), position, defaults),
+            Param::Conditional {
+                identifier,
+                then,
+                otherwise,
+            } => {
+                return self.eval_conditional(
+                    named(identifier),
+                    then,
+                    otherwise,
+                    position,
+                    defaults,
+                );
+            },
+        };
+
+        value.map(Step::Value)
+    }
+
+    /// Computes the name of a dynamic identifier, as in `${PREFIX_${ENV}}`, by evaluating its
+    /// parts. Returns [`None`] for any other identifier, whose name is known up front.
+    fn eval_dynamic_name(
+        &self,
+        identifier: &Identifier,
+        position: Position,
+        defaults: Defaults,
+    ) -> Result<Option<String>, Error> {
+        let Identifier::Dynamic(parts) = identifier else {
+            return Ok(None);
+        };
+
+        let name = self.eval_nodes(parts, defaults)?;
+
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(Error::new(
+                ErrorKind::InvalidValue,
+                format!("Invalid variable name '{name}' computed from '{identifier}'"),
+                position,
+            ));
         }
+
+        Ok(Some(name))
     }
 
-    fn eval_simple_param(&self, identifier: &Identifier) -> Result<String, Error> {
-        self.eval_identifier(identifier).map_or_else(
-            || {
-                if self.no_unset {
-                    // TODO wrong line/col
-                    Err(Error::new(
-                        Self::error_message(identifier, false),
-                        Position::default(),
-                    ))
-                } else {
-                    Ok(String::from(""))
-                }
-            },
-            Ok,
+    /// Evaluates an expression, resolving the variables it references like `$VAR`.
+    #[cfg(feature = "expr")]
+    fn eval_expression(
+        &self,
+        expression: &Expression,
+        position: Position,
+        defaults: Defaults,
+    ) -> Result<String, Error> {
+        expression.eval(position, &|name| {
+            let identifier = Identifier::from_name(name, |c| self.special_vars.contains_key(&c));
+            self.eval_simple_param(&identifier, position, defaults)
+        })
+    }
+
+    fn eval_simple_param(
+        &self,
+        identifier: &Identifier,
+        position: Position,
+        defaults: Defaults,
+    ) -> Result<String, Error> {
+        self.or_unset(
+            self.eval_identifier(identifier, position, defaults)?,
+            identifier,
+            position,
         )
     }
 
@@ -104,144 +787,744 @@ impl Evaluator {
         &self,
         identifier: &Identifier,
         modifier: &Modifier,
+        position: Position,
+        defaults: Defaults,
     ) -> Result<String, Error> {
-        self.eval_simple_param(identifier)
-            .map(|string| match modifier {
-                Modifier::Upper { all } => {
-                    if *all {
-                        string.to_uppercase()
-                    } else {
-                        let mut chars = string.chars();
-                        match chars.next() {
-                            Some(char) => char.to_uppercase().collect::<String>() + chars.as_str(),
-                            None => String::new(),
-                        }
-                    }
-                },
-                Modifier::Lower { all } => {
-                    if *all {
-                        string.to_lowercase()
-                    } else {
-                        let mut chars = string.chars();
-                        match chars.next() {
-                            Some(char) => char.to_lowercase().collect::<String>() + chars.as_str(),
-                            None => String::new(),
-                        }
-                    }
-                },
-                Modifier::Reverse { all } => {
-                    if *all {
-                        string
-                            .chars()
-                            .map(|char| {
-                                if char.is_uppercase() {
-                                    char.to_lowercase().to_string()
-                                } else {
-                                    char.to_uppercase().to_string()
-                                }
-                            })
-                            .collect()
+        let string = self.eval_simple_param(identifier, position, defaults)?;
+        let result = self.apply_modifier(identifier, modifier, &string, position);
+        wipe(string);
+
+        result
+    }
+
+    fn apply_modifier(
+        &self,
+        identifier: &Identifier,
+        modifier: &Modifier,
+        string: &str,
+        position: Position,
+    ) -> Result<String, Error> {
+        let invalid = |reason| Self::invalid_value_error(identifier, reason, position);
+        let result = match modifier {
+            #[cfg(feature = "case-modifiers")]
+            Modifier::Upper { all } => {
+                if *all {
+                    self.case_mapping.uppercase(string)
+                } else {
+                    map_first_char(string, |char| self.case_mapping.uppercase(char))
+                }
+            },
+            #[cfg(feature = "case-modifiers")]
+            Modifier::Lower { all } => {
+                if *all {
+                    self.case_mapping.lowercase(string)
+                } else {
+                    map_first_char(string, |char| self.case_mapping.lowercase(char))
+                }
+            },
+            #[cfg(feature = "case-modifiers")]
+            Modifier::Reverse { all } => {
+                let reverse_case = |char: &str| {
+                    if char.chars().all(char::is_uppercase) {
+                        self.case_mapping.lowercase(char)
                     } else {
-                        let mut chars = string.chars();
-                        match chars.next() {
-                            Some(char) => {
-                                if char.is_uppercase() {
-                                    char.to_lowercase().collect::<String>() + chars.as_str()
-                                } else {
-                                    char.to_uppercase().collect::<String>() + chars.as_str()
-                                }
-                            },
-                            None => String::new(),
-                        }
+                        self.case_mapping.uppercase(char)
                     }
-                },
-            })
+                };
+
+                if *all {
+                    let mut buffer = [0; 4];
+                    string
+                        .chars()
+                        .map(|char| reverse_case(char.encode_utf8(&mut buffer)))
+                        .collect()
+                } else {
+                    map_first_char(string, reverse_case)
+                }
+            },
+            Modifier::UrlEncode => encoding::percent_encode(string),
+            Modifier::UrlDecode => encoding::percent_decode(string)
+                .ok_or_else(|| invalid("is not valid percent-encoded UTF-8"))?,
+            Modifier::Base64Encode => encoding::base64_encode(string),
+            Modifier::Base64Decode => encoding::base64_decode(string)
+                .ok_or_else(|| invalid("is not valid base64-encoded UTF-8"))?,
+            Modifier::ShellQuote => encoding::shell_quote(string),
+            Modifier::Pad { .. } | Modifier::Fixed { .. } if string.is_empty() => String::new(),
+            Modifier::Pad { width, zero } => {
+                Self::parse_number(identifier, string, position)?;
+
+                let number = string.trim();
+
+                if *zero {
+                    let (sign, digits) = number
+                        .strip_prefix(['-', '+'])
+                        .map_or(("", number), |digits| (&number[..1], digits));
+                    let width = width.saturating_sub(sign.len());
+
+                    format!("{sign}{digits:0>width$}")
+                } else {
+                    format!("{number:>width$}")
+                }
+            },
+            Modifier::Fixed { precision } => {
+                let number = Self::parse_number(identifier, string, position)?;
+
+                format!("{number:.precision$}")
+            },
+            Modifier::Truncate { width, ellipsis } => truncate(string, *width, ellipsis),
+            Modifier::RemovePrefix { .. }
+            | Modifier::RemoveSuffix { .. }
+            | Modifier::Replace { .. } => self.apply_pattern(modifier, string, position)?,
+        };
+
+        Ok(result)
     }
 
-    fn eval_default_param(
+    /// Applies a pattern operator, which leaves the value as is if the pattern doesn't match.
+    fn apply_pattern(
+        &self,
+        modifier: &Modifier,
+        string: &str,
+        position: Position,
+    ) -> Result<String, Error> {
+        let result = match modifier {
+            Modifier::RemovePrefix { pattern, longest } => {
+                let end = self
+                    .compile_pattern(pattern, position)?
+                    .match_prefix(string, *longest)
+                    .unwrap_or(0);
+
+                string[end..].to_string()
+            },
+            Modifier::RemoveSuffix { pattern, longest } => {
+                let start = self
+                    .compile_pattern(pattern, position)?
+                    .match_suffix(string, *longest)
+                    .unwrap_or(string.len());
+
+                string[..start].to_string()
+            },
+            // Like in Bash, an empty pattern matches nothing rather than the empty text
+            Modifier::Replace { pattern, .. } if pattern.is_empty() => string.to_string(),
+            Modifier::Replace {
+                pattern,
+                replacement,
+                all,
+            } => self
+                .compile_pattern(pattern, position)?
+                .replace(string, replacement, *all),
+            _ => string.to_string(),
+        };
+
+        Ok(result)
+    }
+
+    fn eval_default_param<'n, 'a>(
         &self,
         identifier: &Identifier,
-        default: Node,
+        default: &'n Node<'a>,
         treat_empty_as_unset: bool,
-    ) -> Result<String, Error> {
-        self.eval_identifier(identifier)
-            .filter(|value| !(treat_empty_as_unset && value.is_empty()))
-            .map_or_else(|| self.eval_node(default), Ok)
+        position: Position,
+        defaults: Defaults,
+    ) -> Result<Step<'n, 'a>, Error> {
+        let value = self
+            .eval_identifier(identifier, position, defaults)?
+            .filter(|value| !(treat_empty_as_unset && value.is_empty()));
+
+        Ok(value.map_or_else(
+            || {
+                self.notify_unset(identifier, position, UnsetOutcome::Default);
+                Step::Node(default)
+            },
+            Step::Value,
+        ))
     }
 
-    fn eval_alt_param(
+    fn eval_alt_param<'n, 'a>(
         &self,
         identifier: &Identifier,
-        alt: Node,
+        alt: &'n Node<'a>,
         treat_empty_as_unset: bool,
-    ) -> Result<String, Error> {
-        self.eval_identifier(identifier)
-            .filter(|value| !(treat_empty_as_unset && value.is_empty()))
-            .map_or_else(|| Ok(String::from("")), |_| self.eval_node(alt))
+        position: Position,
+        defaults: Defaults,
+    ) -> Result<Step<'n, 'a>, Error> {
+        let value = self
+            .eval_identifier(identifier, position, defaults)?
+            .filter(|value| !(treat_empty_as_unset && value.is_empty()));
+
+        let Some(value) = value else {
+            self.notify_unset(identifier, position, UnsetOutcome::Empty);
+            return Ok(Step::Value(String::new()));
+        };
+
+        wipe(value);
+
+        Ok(Step::Node(alt))
+    }
+
+    /// Selects the nodes of a conditional block if the variable is set and non-empty, or the
+    /// nodes of its `${else}` otherwise.
+    fn eval_conditional<'n, 'a>(
+        &self,
+        identifier: &Identifier,
+        then: &'n [Node<'a>],
+        otherwise: &'n [Node<'a>],
+        position: Position,
+        defaults: Defaults,
+    ) -> Result<Step<'n, 'a>, Error> {
+        let value = self
+            .eval_identifier(identifier, position, defaults)?
+            .filter(|value| !value.is_empty());
+
+        let nodes = value.map_or_else(
+            || {
+                let outcome = if otherwise.is_empty() {
+                    UnsetOutcome::Empty
+                } else {
+                    UnsetOutcome::Default
+                };
+                self.notify_unset(identifier, position, outcome);
+                otherwise
+            },
+            |value| {
+                wipe(value);
+                then
+            },
+        );
+
+        Ok(Step::Nodes(nodes))
     }
 
     fn eval_error_param(
         &self,
         identifier: &Identifier,
-        error: Option<String>,
+        error: &[Node],
         treat_empty_as_unset: bool,
+        position: Position,
+        defaults: Defaults,
     ) -> Result<String, Error> {
-        self.eval_identifier(identifier)
-            .filter(|value| !(treat_empty_as_unset && value.is_empty()))
-            .ok_or_else(|| {
-                let msg =
-                    error.unwrap_or_else(|| Self::error_message(identifier, treat_empty_as_unset));
-
-                // TODO wrong line/col
-                Error::new(msg, Position::default())
-            })
+        let value = self
+            .eval_identifier(identifier, position, defaults)?
+            .filter(|value| !(treat_empty_as_unset && value.is_empty()));
+
+        if let Some(value) = value {
+            return Ok(value);
+        }
+
+        self.notify_unset(identifier, position, UnsetOutcome::Error);
+
+        // The message is only evaluated once it's needed, and errors while evaluating it take
+        // precedence over the error it describes
+        let msg = if error.is_empty() {
+            Self::error_message(identifier, treat_empty_as_unset)
+        } else {
+            self.eval_nodes(error, defaults)?
+        };
+
+        Err(Error::new(ErrorKind::Required, msg, position))
+    }
+
+    #[cfg(feature = "length")]
+    fn eval_length_param(
+        &self,
+        identifier: &Identifier,
+        position: Position,
+        defaults: Defaults,
+    ) -> Result<String, Error> {
+        self.eval_identifier(identifier, position, defaults)?
+            .map_or_else(
+                || {
+                    if self.is_required(identifier) {
+                        self.notify_unset(identifier, position, UnsetOutcome::Error);
+                        Err(self.unset_error(identifier, position))
+                    } else {
+                        self.notify_unset(identifier, position, UnsetOutcome::Empty);
+                        Ok(String::from("0"))
+                    }
+                },
+                |value| {
+                    let length = match self.length_mode {
+                        LengthMode::Bytes => value.len(),
+                        LengthMode::Chars => value.chars().count(),
+                        LengthMode::Graphemes => value.graphemes(true).count(),
+                    };
+
+                    Ok(length.to_string())
+                },
+            )
     }
 
-    fn eval_length_param(&self, identifier: &Identifier) -> Result<String, Error> {
-        self.eval_identifier(identifier).map_or_else(
+    #[cfg(feature = "length")]
+    #[allow(clippy::unnecessary_wraps)]
+    fn eval_arity_param(&self) -> Result<String, Error> {
+        Ok(self.positional_vars.len().to_string())
+    }
+
+    #[cfg(feature = "indirection")]
+    fn eval_ref_param(
+        &self,
+        identifier: &Identifier,
+        position: Position,
+        defaults: Defaults,
+    ) -> Result<String, Error> {
+        if self.safe_mode {
+            return Err(Error::new(
+                ErrorKind::Syntax,
+                String::from(parser::INDIRECTION_IN_SAFE_MODE),
+                position,
+            ));
+        }
+
+        // The value is used as a name rather than inserted, so it's not transformed
+        let name = self.or_unset(
+            self.resolve(identifier, position, defaults)?,
+            identifier,
+            position,
+        )?;
+
+        let value = self.eval_simple_param(&Identifier::Named(&name), position, defaults);
+        wipe(name);
+
+        value
+    }
+
+    /// Whether the variable being unset is an error, which is the case if `no_unset` is set,
+    /// unless it's overridden for the variable. Overrides for the name written in the template
+    /// take precedence over those for the variable it's an alias of.
+    fn is_required(&self, identifier: &Identifier) -> bool {
+        if self.unset_overrides.is_empty() {
+            return self.no_unset;
+        }
+
+        let overridden = |name: &str| self.unset_overrides.get(name).copied();
+        let required = match identifier {
+            Identifier::Named(name) => {
+                overridden(name).or_else(|| overridden(self.resolve_alias(name)))
+            },
+            _ => overridden(&identifier.to_string()),
+        };
+
+        required.unwrap_or(self.no_unset)
+    }
+
+    /// Falls back to an empty string for unset values, unless `no_unset` is set or the variable
+    /// is required. Empty values are an error if `no_empty` is set.
+    fn or_unset(
+        &self,
+        value: Option<String>,
+        identifier: &Identifier,
+        position: Position,
+    ) -> Result<String, Error> {
+        if self.no_empty && value.as_deref() == Some("") {
+            return Err(Error::new(
+                ErrorKind::Unset,
+                format!("'{identifier}' is empty"),
+                position,
+            ));
+        }
+
+        value.map_or_else(
             || {
-                if self.no_unset {
-                    // TODO wrong line/col
-                    Err(Error::new(
-                        Self::error_message(identifier, false),
-                        Position::default(),
-                    ))
+                if self.is_required(identifier) {
+                    self.notify_unset(identifier, position, UnsetOutcome::Error);
+                    Err(self.unset_error(identifier, position))
                 } else {
-                    Ok(String::from("0"))
+                    self.notify_unset(identifier, position, UnsetOutcome::Empty);
+                    self.warn_out_of_range(identifier, position);
+                    Ok(String::new())
                 }
             },
-            |value| Ok(value.len().to_string()),
+            Ok,
         )
     }
 
-    #[allow(clippy::unnecessary_wraps)]
-    fn eval_arity_param(&self) -> Result<String, Error> {
-        Ok(self.positional_vars.len().to_string())
+    /// Resolves the value of the identifier, applying all value transforms and then the
+    /// maximum value length.
+    fn eval_identifier(
+        &self,
+        identifier: &Identifier,
+        position: Position,
+        defaults: Defaults,
+    ) -> Result<Option<String>, Error> {
+        let Some(value) = self.resolve(identifier, position, defaults)? else {
+            return Ok(None);
+        };
+
+        if self.value_transforms.is_empty() {
+            return self.limit_length(identifier, value, position).map(Some);
+        }
+
+        let name = identifier.to_string();
+        let value = self
+            .value_transforms
+            .iter()
+            .fold(value, |value, transform| {
+                let transformed = transform(&name, &value);
+                wipe(value);
+                transformed
+            });
+
+        self.limit_length(identifier, value, position).map(Some)
     }
 
-    fn eval_ref_param(&self, identifier: &Identifier) -> Result<String, Error> {
-        self.eval_simple_param(identifier)
-            .and_then(|name| self.eval_simple_param(&Identifier::Named(&name)))
+    /// Applies the maximum value length, if any, to the value of the identifier.
+    fn limit_length(
+        &self,
+        identifier: &Identifier,
+        value: String,
+        position: Position,
+    ) -> Result<String, Error> {
+        let Some((max, policy)) = self.max_value_length else {
+            return Ok(value);
+        };
+
+        if value.chars().nth(max).is_none() {
+            return Ok(value);
+        }
+
+        let result = match policy {
+            LengthPolicy::Error => Err(Self::invalid_value_error(
+                identifier,
+                &format!("is longer than {max} characters"),
+                position,
+            )),
+            LengthPolicy::Truncate => Ok(truncate(&value, max, "")),
+        };
+        wipe(value);
+
+        result
     }
 
-    fn eval_identifier(&self, identifier: &Identifier) -> Option<String> {
+    /// Looks up the raw value of the identifier, notifying the resolve listeners if it's set.
+    fn resolve(
+        &self,
+        identifier: &Identifier,
+        position: Position,
+        defaults: Defaults,
+    ) -> Result<Option<String>, Error> {
+        let value = self.lookup(identifier, position, defaults)?;
+
+        if value.is_some() && !self.resolve_listeners.is_empty() {
+            if let Some(source) = self.source(identifier, defaults) {
+                let event = ResolveEvent {
+                    name: identifier.to_string(),
+                    position,
+                    source,
+                };
+
+                for listener in &self.resolve_listeners {
+                    listener(&event);
+                }
+            }
+        }
+
+        Ok(value)
+    }
+
+    /// Resolves the raw value of the identifier at the given position, following aliases of
+    /// named variables and applying the access policy, if any.
+    fn lookup(
+        &self,
+        identifier: &Identifier,
+        position: Position,
+        defaults: Defaults,
+    ) -> Result<Option<String>, Error> {
+        match self.access(identifier) {
+            Access::Allow => self.lookup_value(identifier, position, defaults),
+            Access::Hide => Ok(match identifier {
+                Identifier::Named(name) => lookup_default(self.resolve_alias(name), defaults),
+                _ => None,
+            }),
+            Access::Deny => Err(Error::new(
+                ErrorKind::Policy,
+                format!("Access to '{identifier}' is denied"),
+                position,
+            )),
+            Access::Redact(text) => {
+                Ok(self
+                    .lookup_value(identifier, position, defaults)?
+                    .map(|value| {
+                        wipe(value);
+                        text
+                    }))
+            },
+        }
+    }
+
+    fn access(&self, identifier: &Identifier) -> Access {
+        let Some(policy) = &self.access_policy else {
+            return Access::Allow;
+        };
+
         match identifier {
-            Identifier::Named(name) => self.named_vars.get(*name).cloned(),
+            Identifier::Named(name) => policy(self.resolve_alias(name)),
+            _ => policy(&identifier.to_string()),
+        }
+    }
+
+    fn lookup_value(
+        &self,
+        identifier: &Identifier,
+        position: Position,
+        defaults: Defaults,
+    ) -> Result<Option<String>, Error> {
+        let value = match identifier {
+            Identifier::Named(name) => {
+                let name = self.resolve_alias(name);
+                self.lookup_positional_name(name)
+                    .or_else(|| self.lookup_named(name))
+                    .or_else(|| lookup_default(name, defaults))
+            },
             Identifier::Indexed(index) => {
                 if *index == 0 {
-                    Some(self.positional_vars.join(" "))
+                    Some(self.positional_vars.join())
                 } else {
-                    self.positional_vars.get(index - 1).cloned()
+                    self.positional_vars.get(index - 1)
                 }
             },
+            Identifier::Special(c) => self.special_vars.get(c).cloned(),
+            Identifier::Dynamic(_) => None,
+            Identifier::Namespaced { .. } if self.safe_mode => {
+                return Err(Error::new(
+                    ErrorKind::Syntax,
+                    String::from(parser::NAMESPACE_IN_SAFE_MODE),
+                    position,
+                ));
+            },
+            Identifier::Namespaced { namespace, key } => {
+                let context = self
+                    .context
+                    .as_ref()
+                    .filter(|_| *namespace == context::NAMESPACE);
+
+                if let Some(context) = context {
+                    return context.get(key, position).map_err(|error| {
+                        Error::new(
+                            ErrorKind::Provider,
+                            format!("Failed to resolve '{identifier}': {error}"),
+                            position,
+                        )
+                    });
+                }
+
+                let provider = self.providers.get(*namespace).ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::Provider,
+                        format!("Unknown namespace '{namespace}'"),
+                        position,
+                    )
+                })?;
+
+                let started = Instant::now();
+                let value = provider.get(key);
+
+                if let Some(recorder) = self.metrics.get(*namespace) {
+                    recorder.record(&value, started.elapsed());
+                }
+
+                value.map_err(|error| {
+                    Error::new(
+                        ErrorKind::Provider,
+                        format!("Failed to resolve '{identifier}': {error}"),
+                        position,
+                    )
+                })?
+            },
+        };
+
+        Ok(value)
+    }
+
+    fn notify_unset(&self, identifier: &Identifier, position: Position, outcome: UnsetOutcome) {
+        if self.unset_listeners.is_empty() {
+            return;
+        }
+
+        let event = UnsetEvent {
+            name: identifier.to_string(),
+            position,
+            outcome,
+        };
+
+        for listener in &self.unset_listeners {
+            listener(&event);
+        }
+    }
+
+    /// Warns about a reference to a positional variable beyond those given.
+    fn warn_out_of_range(&self, identifier: &Identifier, position: Position) {
+        let Identifier::Indexed(index) = identifier else {
+            return;
+        };
+        let count = self.positional_vars.len_up_to(*index);
+        let kind = WarningKind::PositionalOutOfRange {
+            index: *index,
+            count,
+        };
+        let message = format!("'${index}' is out of range, {count} positional variable(s) are set");
+
+        self.warn(kind, message, position);
+    }
+
+    fn warn(&self, kind: WarningKind, message: String, position: Position) {
+        if self.warning_listeners.is_empty() {
+            return;
+        }
+
+        let warning = Warning {
+            kind,
+            message,
+            position,
+        };
+
+        for listener in &self.warning_listeners {
+            listener(&warning);
+        }
+    }
+
+    /// Whether any listener is interested in how long expanding takes, which is only measured
+    /// if so.
+    pub fn is_timed(&self) -> bool {
+        !self.timing_listeners.is_empty()
+    }
+
+    pub fn notify_timing(&self, phase: Phase, duration: Duration) {
+        let timing = Timing { phase, duration };
+
+        for listener in &self.timing_listeners {
+            listener(&timing);
         }
     }
 
+    fn resolve_alias<'b>(&'b self, name: &'b str) -> &'b str {
+        self.aliases.get(name).map_or(name, String::as_str)
+    }
+
+    /// Resolves a name given to a positional variable, if that is set.
+    fn lookup_positional_name(&self, name: &str) -> Option<String> {
+        let index = self.positional_names.get(name)?;
+        self.positional_vars.get(*index)
+    }
+
+    /// Resolves a named variable, see [`Evaluator::find_named`].
+    fn lookup_named(&self, name: &str) -> Option<String> {
+        match self.find_named(name)? {
+            Found::Captured(key) => self.named_vars.get(key).cloned(),
+            Found::Live(value) => Some(value),
+        }
+    }
+
+    /// Finds a named variable, falling back to a case-insensitive match among environment
+    /// variables and then to the live environment if enabled.
+    fn find_named(&self, name: &str) -> Option<Found<'_>> {
+        if let Some((key, _)) = self.named_vars.get_key_value(name) {
+            return Some(Found::Captured(key));
+        }
+
+        if self.env.case_insensitive {
+            let key = self
+                .env
+                .names
+                .get(&name.to_uppercase())
+                .filter(|key| self.named_vars.contains_key(*key));
+
+            if let Some(key) = key {
+                return Some(Found::Captured(key));
+            }
+        }
+
+        if !self.env.live {
+            return None;
+        }
+
+        if let Ok(value) = env::var(name) {
+            return Some(Found::Live(value));
+        }
+
+        if !self.env.case_insensitive {
+            return None;
+        }
+
+        let name = name.to_uppercase();
+
+        env::vars()
+            .filter(|(key, _)| key.to_uppercase() == name)
+            .min()
+            .map(|(_, value)| Found::Live(value))
+    }
+
+    fn unset_error(&self, identifier: &Identifier, position: Position) -> Error {
+        let msg = match identifier {
+            Identifier::Indexed(index) => format!(
+                "'{identifier}' is unset, {} positional variable(s) are set",
+                self.positional_vars.len_up_to(*index)
+            ),
+            _ => Self::error_message(identifier, false),
+        };
+
+        Error::new(ErrorKind::Unset, msg, position)
+    }
+
+    fn parse_number(
+        identifier: &Identifier,
+        value: &str,
+        position: Position,
+    ) -> Result<f64, Error> {
+        value
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|number| number.is_finite())
+            .ok_or_else(|| Self::invalid_value_error(identifier, "is not a number", position))
+    }
+
+    fn invalid_value_error(identifier: &Identifier, reason: &str, position: Position) -> Error {
+        Error::new(
+            ErrorKind::InvalidValue,
+            format!("'{identifier}' {reason}"),
+            position,
+        )
+    }
+
     fn error_message(identifier: &Identifier, treat_empty_as_unset: bool) -> String {
         if treat_empty_as_unset {
-            format!("'{}' is unset or empty", identifier)
+            format!("'{identifier}' is unset or empty")
         } else {
-            format!("'{}' is unset", identifier)
+            format!("'{identifier}' is unset")
         }
     }
 }
+
+/// Maps the first character of the string, given as a string of its own.
+#[cfg(feature = "case-modifiers")]
+fn map_first_char(string: &str, map: impl FnOnce(&str) -> String) -> String {
+    let Some(first) = string.chars().next() else {
+        return String::new();
+    };
+    let (first, rest) = string.split_at(first.len_utf8());
+
+    map(first) + rest
+}
+
+/// Truncates the string to at most `width` characters. If it's longer, its end is replaced with
+/// the ellipsis, which counts towards the width.
+fn truncate(string: &str, width: usize, ellipsis: &str) -> String {
+    if string.chars().nth(width).is_none() {
+        return string.to_string();
+    }
+
+    let kept = width.saturating_sub(ellipsis.chars().count());
+
+    string
+        .chars()
+        .take(kept)
+        .chain(ellipsis.chars().take(width))
+        .collect()
+}
+
+fn lookup_default(name: &str, defaults: Defaults) -> Option<String> {
+    defaults
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.clone())
+}
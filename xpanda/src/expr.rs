@@ -0,0 +1,735 @@
+use crate::eval::Error;
+use crate::parser::MAX_NESTING;
+use crate::position::Position;
+use crate::syntax::SPECIAL_VARS;
+use crate::ErrorKind;
+use std::cmp::Ordering;
+use std::fmt::{self, Display, Formatter};
+
+/// The maximum number of operands, operators and calls in a single expression.
+const MAX_NODES: usize = 1024;
+
+/// An expression used as the default or alternative value of a param, written after a `=` as
+/// in `${REPLICAS:-=max(2, $CPU / 2)}`.
+///
+/// Values are either numbers or strings. Strings are converted to numbers where a number is
+/// expected, which is an error if they aren't numeric, while numbers which are whole are
+/// written without a fraction, as in `2` rather than `2.0`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    /// The source of the expression, without the leading `=`.
+    source: String,
+    /// The expression in postfix order, such that it's evaluated in a single loop using a stack
+    /// of values rather than recursively.
+    ops: Vec<Op>,
+}
+
+/// Literals are never NaN, so equality is reflexive.
+impl Eq for Expression {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Op {
+    /// Pushes a number.
+    Number(f64),
+    /// Pushes a string.
+    String(String),
+    /// Pushes the value of a variable, as in `$name`, `$1` or `$?`.
+    Var(String),
+    /// Converts the value on top of the stack to a number, such that the left operand of an
+    /// arithmetic operator fails before the right operand is evaluated.
+    ToNumber,
+    /// Replaces the value on top of the stack with its negation.
+    Neg,
+    /// Replaces the value on top of the stack with its logical negation.
+    Not,
+    /// Replaces the value on top of the stack with `1` if it's true, `0` otherwise.
+    Truth,
+    /// Pops the right and then the left operand, pushing the result.
+    Binary(BinaryOp),
+    /// Pops the given number of arguments, pushing the result.
+    Call(Function, usize),
+    /// Pops the left operand of `&&`. If it's false, pushes `0` and continues at the given
+    /// index, skipping the right operand.
+    And(usize),
+    /// Pops the left operand of `||`. If it's true, pushes `1` and continues at the given
+    /// index, skipping the right operand.
+    Or(usize),
+    /// Pops the condition of `?:`, continuing at the given index if it's false.
+    JumpUnless(usize),
+    /// Continues at the given index.
+    Jump(usize),
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Function {
+    Min,
+    Max,
+    Floor,
+    Ceil,
+    Round,
+    Concat,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "min" => Some(Self::Min),
+            "max" => Some(Self::Max),
+            "floor" => Some(Self::Floor),
+            "ceil" => Some(Self::Ceil),
+            "round" => Some(Self::Round),
+            "concat" => Some(Self::Concat),
+            _ => None,
+        }
+    }
+
+    /// The minimum and maximum number of arguments the function takes.
+    const fn arity(self) -> (usize, usize) {
+        match self {
+            Self::Min | Self::Max => (1, usize::MAX),
+            Self::Floor | Self::Ceil | Self::Round => (1, 1),
+            Self::Concat => (0, usize::MAX),
+        }
+    }
+
+    /// Whether all arguments are converted to numbers.
+    const fn takes_numbers(self) -> bool {
+        !matches!(self, Self::Concat)
+    }
+}
+
+impl BinaryOp {
+    /// Whether both operands are converted to numbers.
+    const fn takes_numbers(self) -> bool {
+        !matches!(self, Self::Eq | Self::Ne | Self::And | Self::Or)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Number(f64),
+    String(String),
+}
+
+impl Value {
+    fn to_number(&self, position: Position) -> Result<f64, Error> {
+        match self {
+            Self::Number(number) => Ok(*number),
+            Self::String(string) => parse_number(string).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidValue,
+                    format!("Expected a number in expression, found '{string}'"),
+                    position,
+                )
+            }),
+        }
+    }
+
+    /// Numbers are true unless zero, strings are true unless empty or numerically zero.
+    fn is_true(&self) -> bool {
+        match self {
+            Self::Number(number) => *number != 0.0,
+            Self::String(string) => parse_number(string).map_or(!string.is_empty(), |n| n != 0.0),
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            // Whole numbers are written without a fraction, and adding zero turns -0 into 0
+            Self::Number(number) => write!(f, "{}", number + 0.0),
+            Self::String(string) => write!(f, "{string}"),
+        }
+    }
+}
+
+impl Expression {
+    /// Parses the source of an expression, without the leading `=`. If it's invalid, the error
+    /// message is returned together with the byte offset it refers to. The expression is nested
+    /// inside the given number of params and conditional blocks, which count towards
+    /// [`MAX_NESTING`] like parentheses do, as parsing expressions is recursive while evaluating
+    /// them is not.
+    pub fn parse(source: &str, nesting: usize) -> Result<Self, (String, usize)> {
+        let mut parser = Parser {
+            source,
+            index: 0,
+            nesting,
+            nodes: 0,
+            ops: Vec::new(),
+        };
+        parser.parse_expr()?;
+
+        parser.skip_whitespace();
+
+        if let Some(c) = parser.peek() {
+            return Err((format!("Unexpected '{c}' in expression"), parser.index));
+        }
+
+        Ok(Self {
+            source: source.to_string(),
+            ops: parser.ops,
+        })
+    }
+
+    /// Returns the source of the expression, without the leading `=`.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns the names of the variables referenced by the expression, in order of
+    /// appearance.
+    pub fn variables(&self) -> Vec<&str> {
+        self.ops
+            .iter()
+            .filter_map(|op| match op {
+                Op::Var(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Evaluates the expression, resolving variables using the given function. Errors are
+    /// located at the given position.
+    pub fn eval(
+        &self,
+        position: Position,
+        lookup: &dyn Fn(&str) -> Result<String, Error>,
+    ) -> Result<String, Error> {
+        let evaluator = Evaluator { position, lookup };
+
+        Ok(evaluator.eval(&self.ops)?.to_string())
+    }
+}
+
+/// Writes the source such that it lexes back into the same text inside a param.
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut chars = self.source.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '}' => write!(f, "$}}")?,
+                '$' if matches!(chars.peek(), None | Some('$' | ':' | '}' | '=')) => {
+                    write!(f, "$$")?;
+                },
+                c => write!(f, "{c}")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_number(string: &str) -> Option<f64> {
+    string
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite())
+}
+
+type ParseResult<T> = Result<T, (String, usize)>;
+
+/// A recursive descent parser, from the lowest precedence to the highest:
+/// `?:`, `||`, `&&`, comparisons, `+` and `-`, `*`, `/` and `%`, unary `-` and `!`. The ops of
+/// the expression are emitted in postfix order while parsing.
+struct Parser<'s> {
+    source: &'s str,
+    index: usize,
+    nesting: usize,
+    nodes: usize,
+    ops: Vec<Op>,
+}
+
+impl Parser<'_> {
+    fn parse_expr(&mut self) -> ParseResult<()> {
+        self.nesting += 1;
+
+        if self.nesting > MAX_NESTING {
+            return Err((
+                format!("Expressions can't be nested more than {MAX_NESTING} levels deep"),
+                self.index,
+            ));
+        }
+
+        self.parse_binary(0)?;
+        self.skip_whitespace();
+
+        if self.consume("?") {
+            // Placeholders until the ends of the branches are known
+            let condition = self.emit(Op::JumpUnless(0));
+            self.parse_expr()?;
+            let then = self.emit(Op::Jump(0));
+            self.expect(":")?;
+            self.ops[condition] = Op::JumpUnless(self.ops.len());
+            self.parse_expr()?;
+            self.ops[then] = Op::Jump(self.ops.len());
+            self.node()?;
+        }
+
+        self.nesting -= 1;
+
+        Ok(())
+    }
+
+    /// Parses a chain of binary operators with at least the given precedence, left to right, by
+    /// only recursing for operands of operators with a higher precedence.
+    fn parse_binary(&mut self, min_precedence: usize) -> ParseResult<()> {
+        const OPERATORS: [&[(&str, BinaryOp)]; 5] = [
+            &[("||", BinaryOp::Or)],
+            &[("&&", BinaryOp::And)],
+            &[
+                ("==", BinaryOp::Eq),
+                ("!=", BinaryOp::Ne),
+                ("<=", BinaryOp::Le),
+                (">=", BinaryOp::Ge),
+                ("<", BinaryOp::Lt),
+                (">", BinaryOp::Gt),
+            ],
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+            &[
+                ("*", BinaryOp::Mul),
+                ("/", BinaryOp::Div),
+                ("%", BinaryOp::Rem),
+            ],
+        ];
+
+        self.parse_unary()?;
+
+        loop {
+            self.skip_whitespace();
+
+            let Some((precedence, op)) =
+                OPERATORS.iter().enumerate().skip(min_precedence).find_map(
+                    |(precedence, operators)| {
+                        let (_, op) = operators.iter().find(|(symbol, _)| self.consume(symbol))?;
+                        Some((precedence, *op))
+                    },
+                )
+            else {
+                return Ok(());
+            };
+
+            match op {
+                // A placeholder until the end of the right operand is known
+                BinaryOp::And | BinaryOp::Or => {
+                    let index = self.emit(Op::And(0));
+                    self.parse_binary(precedence + 1)?;
+                    self.emit(Op::Truth);
+                    let end = self.ops.len();
+                    self.ops[index] = if op == BinaryOp::And {
+                        Op::And(end)
+                    } else {
+                        Op::Or(end)
+                    };
+                },
+                op if op.takes_numbers() => {
+                    self.emit(Op::ToNumber);
+                    self.parse_binary(precedence + 1)?;
+                    self.emit(Op::ToNumber);
+                    self.emit(Op::Binary(op));
+                },
+                op => {
+                    self.parse_binary(precedence + 1)?;
+                    self.emit(Op::Binary(op));
+                },
+            }
+
+            self.node()?;
+        }
+    }
+
+    fn parse_unary(&mut self) -> ParseResult<()> {
+        self.skip_whitespace();
+
+        let op = if self.consume("-") {
+            Op::Neg
+        } else if self.consume("!") {
+            Op::Not
+        } else {
+            return self.parse_primary();
+        };
+
+        // Nested like parentheses, as in `--1`
+        self.nesting += 1;
+
+        if self.nesting > MAX_NESTING {
+            return Err((
+                format!("Expressions can't be nested more than {MAX_NESTING} levels deep"),
+                self.index,
+            ));
+        }
+
+        self.parse_unary()?;
+        self.nesting -= 1;
+        self.emit(op);
+
+        self.node()
+    }
+
+    fn parse_primary(&mut self) -> ParseResult<()> {
+        let start = self.index;
+
+        let op = match self.peek() {
+            Some('(') => {
+                self.index += 1;
+                self.parse_expr()?;
+                return self.expect(")");
+            },
+            Some('\'' | '"') => Op::String(self.parse_string()?),
+            Some('$') => {
+                self.index += 1;
+
+                match self.peek() {
+                    Some(c) if SPECIAL_VARS.contains(c) => {
+                        self.index += c.len_utf8();
+                        Op::Var(c.to_string())
+                    },
+                    _ => match self.consume_name() {
+                        "" => {
+                            return Err((String::from("Expected a variable name after '$'"), start))
+                        },
+                        name => Op::Var(name.to_string()),
+                    },
+                }
+            },
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let number = self.consume_while(|c| c.is_ascii_digit() || c == '.');
+
+                Op::Number(
+                    number
+                        .parse()
+                        .map_err(|_| (format!("Invalid number '{number}'"), start))?,
+                )
+            },
+            Some(c) if c.is_alphabetic() => {
+                let name = self.consume_name().to_string();
+                let function = Function::from_name(&name)
+                    .ok_or_else(|| (format!("Unknown function '{name}'"), start))?;
+                let args = self.parse_args(function)?;
+                let (min, max) = function.arity();
+
+                if args < min || args > max {
+                    let expected = match (min, max) {
+                        (min, max) if min == max => format!("{min}"),
+                        (min, _) => format!("at least {min}"),
+                    };
+
+                    return Err((
+                        format!("Function '{name}' expects {expected} argument(s), found {args}"),
+                        start,
+                    ));
+                }
+
+                Op::Call(function, args)
+            },
+            Some(c) => return Err((format!("Unexpected '{c}' in expression"), start)),
+            None => return Err((String::from("Unexpected end of expression"), start)),
+        };
+
+        self.emit(op);
+
+        self.node()
+    }
+
+    /// Parses the arguments of a call to the function, returning how many there are.
+    fn parse_args(&mut self, function: Function) -> ParseResult<usize> {
+        self.skip_whitespace();
+        self.expect("(")?;
+        self.skip_whitespace();
+
+        let mut args = 0;
+
+        if self.consume(")") {
+            return Ok(args);
+        }
+
+        loop {
+            self.parse_expr()?;
+            args += 1;
+
+            if function.takes_numbers() {
+                self.emit(Op::ToNumber);
+            }
+
+            self.skip_whitespace();
+
+            if self.consume(")") {
+                return Ok(args);
+            }
+
+            self.expect(",")?;
+        }
+    }
+
+    /// Parses a single or double quoted string, in which a backslash escapes the quote or
+    /// another backslash.
+    fn parse_string(&mut self) -> ParseResult<String> {
+        let start = self.index;
+        let mut chars = self.source[self.index..].char_indices();
+        let Some((_, quote)) = chars.next() else {
+            return Err((String::from("Expected a string"), start));
+        };
+        let mut string = String::new();
+
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_, c)) if c == quote || c == '\\' => string.push(c),
+                    Some((_, c)) => {
+                        string.push('\\');
+                        string.push(c);
+                    },
+                    None => break,
+                },
+                c if c == quote => {
+                    self.index += offset + c.len_utf8();
+                    return Ok(string);
+                },
+                c => string.push(c),
+            }
+        }
+
+        Err((String::from("Unterminated string in expression"), start))
+    }
+
+    /// Counts a node towards [`MAX_NODES`].
+    fn node(&mut self) -> ParseResult<()> {
+        self.nodes += 1;
+
+        if self.nodes > MAX_NODES {
+            return Err((
+                format!("Expressions can't be made up of more than {MAX_NODES} parts"),
+                self.index,
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Appends the op, returning its index.
+    fn emit(&mut self, op: Op) -> usize {
+        self.ops.push(op);
+        self.ops.len() - 1
+    }
+
+    fn expect(&mut self, expected: &str) -> ParseResult<()> {
+        self.skip_whitespace();
+
+        if self.consume(expected) {
+            return Ok(());
+        }
+
+        let message = self.peek().map_or_else(
+            || format!("Expected '{expected}', found end of expression"),
+            |c| format!("Expected '{expected}', found '{c}'"),
+        );
+
+        Err((message, self.index))
+    }
+
+    fn consume(&mut self, expected: &str) -> bool {
+        let is_match = self.source[self.index..].starts_with(expected);
+
+        if is_match {
+            self.index += expected.len();
+        }
+
+        is_match
+    }
+
+    fn consume_name(&mut self) -> &str {
+        self.consume_while(|c| c.is_alphanumeric() || c == '_')
+    }
+
+    fn consume_while(&mut self, predicate: impl Fn(char) -> bool) -> &str {
+        let rest = &self.source[self.index..];
+        let len = rest.find(|c| !predicate(c)).unwrap_or(rest.len());
+        self.index += len;
+        &rest[..len]
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.consume_while(char::is_whitespace);
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.index..].chars().next()
+    }
+}
+
+struct Evaluator<'f> {
+    position: Position,
+    lookup: &'f dyn Fn(&str) -> Result<String, Error>,
+}
+
+impl Evaluator<'_> {
+    fn eval(&self, ops: &[Op]) -> Result<Value, Error> {
+        let mut stack = Vec::new();
+        let mut index = 0;
+
+        while let Some(op) = ops.get(index) {
+            index += 1;
+
+            let value = match op {
+                Op::Number(number) => Value::Number(*number),
+                Op::String(string) => Value::String(string.clone()),
+                Op::Var(name) => Value::String((self.lookup)(name)?),
+                Op::ToNumber => Value::Number(pop(&mut stack).to_number(self.position)?),
+                Op::Neg => Value::Number(-pop(&mut stack).to_number(self.position)?),
+                Op::Not => bool_value(!pop(&mut stack).is_true()),
+                Op::Truth => bool_value(pop(&mut stack).is_true()),
+                Op::Binary(op) => {
+                    let right = pop(&mut stack);
+                    let left = pop(&mut stack);
+                    self.binary(*op, &left, &right)?
+                },
+                Op::Call(function, args) => {
+                    let args = stack.split_off(stack.len().saturating_sub(*args));
+                    self.call(*function, &args)?
+                },
+                Op::And(end) | Op::Or(end) => {
+                    let is_true = pop(&mut stack).is_true();
+
+                    if is_true != matches!(op, Op::Or(_)) {
+                        continue;
+                    }
+
+                    index = *end;
+                    bool_value(is_true)
+                },
+                Op::JumpUnless(end) => {
+                    if !pop(&mut stack).is_true() {
+                        index = *end;
+                    }
+
+                    continue;
+                },
+                Op::Jump(end) => {
+                    index = *end;
+                    continue;
+                },
+            };
+
+            match value {
+                Value::Number(number) if !number.is_finite() => {
+                    return Err(self.error(String::from(
+                        "Expression yields a number which is out of range",
+                    )));
+                },
+                value => stack.push(value),
+            }
+        }
+
+        Ok(pop(&mut stack))
+    }
+
+    /// Applies the operator to its operands, which are already numbers if it takes numbers.
+    fn binary(&self, op: BinaryOp, left: &Value, right: &Value) -> Result<Value, Error> {
+        let numbers = || {
+            Ok::<_, Error>((
+                left.to_number(self.position)?,
+                right.to_number(self.position)?,
+            ))
+        };
+
+        let value = match op {
+            BinaryOp::Add => numbers().map(|(left, right)| Value::Number(left + right))?,
+            BinaryOp::Sub => numbers().map(|(left, right)| Value::Number(left - right))?,
+            BinaryOp::Mul => numbers().map(|(left, right)| Value::Number(left * right))?,
+            BinaryOp::Div | BinaryOp::Rem => {
+                let (left, right) = numbers()?;
+
+                if right == 0.0 {
+                    return Err(self.error(String::from("Division by zero in expression")));
+                }
+
+                if op == BinaryOp::Div {
+                    Value::Number(left / right)
+                } else {
+                    Value::Number(left % right)
+                }
+            },
+            BinaryOp::Lt => numbers().map(|(left, right)| bool_value(left < right))?,
+            BinaryOp::Le => numbers().map(|(left, right)| bool_value(left <= right))?,
+            BinaryOp::Gt => numbers().map(|(left, right)| bool_value(left > right))?,
+            BinaryOp::Ge => numbers().map(|(left, right)| bool_value(left >= right))?,
+            // Compared as numbers if both are numeric, so that `$N == 1` holds for `N=1.0`
+            BinaryOp::Eq | BinaryOp::Ne => {
+                let is_equal = match (
+                    left.to_number(self.position),
+                    right.to_number(self.position),
+                ) {
+                    (Ok(left), Ok(right)) => left.partial_cmp(&right) == Some(Ordering::Equal),
+                    _ => left.to_string() == right.to_string(),
+                };
+
+                bool_value(is_equal == (op == BinaryOp::Eq))
+            },
+            BinaryOp::And => bool_value(left.is_true() && right.is_true()),
+            BinaryOp::Or => bool_value(left.is_true() || right.is_true()),
+        };
+
+        Ok(value)
+    }
+
+    /// Calls the function with its arguments, which are already numbers if it takes numbers.
+    /// The number of arguments is checked when parsing.
+    fn call(&self, function: Function, args: &[Value]) -> Result<Value, Error> {
+        let numbers = || {
+            args.iter()
+                .map(|arg| arg.to_number(self.position))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let first = || {
+            args.first()
+                .map_or(Ok(0.0), |arg| arg.to_number(self.position))
+        };
+
+        let number = match function {
+            Function::Min => numbers()?.into_iter().fold(f64::INFINITY, f64::min),
+            Function::Max => numbers()?.into_iter().fold(f64::NEG_INFINITY, f64::max),
+            Function::Floor => first()?.floor(),
+            Function::Ceil => first()?.ceil(),
+            Function::Round => first()?.round(),
+            Function::Concat => {
+                let string = args.iter().map(ToString::to_string).collect();
+                return Ok(Value::String(string));
+            },
+        };
+
+        Ok(Value::Number(number))
+    }
+
+    const fn error(&self, message: String) -> Error {
+        Error::new(ErrorKind::InvalidValue, message, self.position)
+    }
+}
+
+/// Pops the value on top of the stack. The ops of a parsed expression never pop more values
+/// than they pushed.
+fn pop(stack: &mut Vec<Value>) -> Value {
+    stack.pop().unwrap_or(Value::Number(0.0))
+}
+
+const fn bool_value(value: bool) -> Value {
+    Value::Number(if value { 1.0 } else { 0.0 })
+}
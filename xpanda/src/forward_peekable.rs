@@ -13,7 +13,7 @@ impl<I> ForwardPeekable<I>
 where
     I: Iterator,
 {
-    pub fn new(iter: I) -> Self {
+    pub const fn new(iter: I) -> Self {
         Self {
             iter,
             peeked: VecDeque::new(),
@@ -39,6 +39,11 @@ where
 
         self.peeked.get(n)
     }
+
+    pub fn peek_mut(&mut self) -> Option<&mut I::Item> {
+        self.peek_nth(0)?;
+        self.peeked.front_mut()
+    }
 }
 
 impl<I> Iterator for ForwardPeekable<I>
@@ -109,4 +114,18 @@ mod tests {
         // there is nothing past the end
         assert_eq!(peekable.peek_nth(2), None);
     }
+
+    #[test]
+    fn peek_mut() {
+        let mut peekable = [1, 2].into_iter().forward_peekable();
+
+        // the peeked value can be changed before it's returned
+        if let Some(value) = peekable.peek_mut() {
+            *value = 3;
+        }
+
+        assert_eq!(peekable.next(), Some(3));
+        assert_eq!(peekable.next(), Some(2));
+        assert_eq!(peekable.peek_mut(), None);
+    }
 }
@@ -0,0 +1,139 @@
+use crate::position::{Position, Span};
+use crate::{Error, ErrorKind};
+
+/// The prefix of every line of front matter.
+pub const PREFIX: &str = "#! xpanda:";
+
+/// Declarations made by a template about the variables it uses, in lines at the very start of
+/// the template such as `#! xpanda: require DB_URL, PORT; default REGION=us-east-1`.
+///
+/// Each line consists of directives separated by `;`. `require` takes a comma separated list of
+/// variables which must be set, and `default` takes a single `NAME=value` pair used when the
+/// variable is otherwise unset. Front matter is only recognized if
+/// [`Builder::front_matter`](crate::Builder::front_matter) is `true`, and is not part of the
+/// expanded output.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct FrontMatter {
+    /// The names of variables which must be set, in order of declaration.
+    pub required: Vec<String>,
+    /// The default values of variables, in order of declaration.
+    pub defaults: Vec<(String, String)>,
+    /// The range of the input covered by the front matter.
+    pub span: Span,
+}
+
+/// Parses the front matter at the start of the input, if there is any, returning it together
+/// with the length of the front matter in bytes.
+pub fn parse(input: &str) -> Result<Option<(FrontMatter, usize)>, Error> {
+    let mut front_matter = FrontMatter::default();
+    let mut end = Position::default();
+
+    while input[end.index..].starts_with(PREFIX) {
+        let rest = &input[end.index..];
+        let line = rest.split_inclusive('\n').next().unwrap_or(rest);
+        let mut offset = PREFIX.len();
+
+        for directive in line.trim_end_matches(['\n', '\r'])[offset..].split(';') {
+            let start = Position::new(
+                end.index + offset,
+                end.line,
+                end.col + line[..offset].chars().count(),
+            );
+            let span = Span::new(
+                start,
+                Position::new(
+                    start.index + directive.len(),
+                    start.line,
+                    start.col + directive.chars().count(),
+                ),
+            );
+
+            parse_directive(directive.trim(), span, &mut front_matter)?;
+            offset += directive.len() + 1;
+        }
+
+        end = if line.ends_with('\n') {
+            Position::new(end.index + line.len(), end.line + 1, 1)
+        } else {
+            Position::new(end.index + line.len(), end.line, line.chars().count() + 1)
+        };
+    }
+
+    if end.index == 0 {
+        return Ok(None);
+    }
+
+    front_matter.span = Span::new(Position::default(), end);
+
+    Ok(Some((front_matter, end.index)))
+}
+
+fn parse_directive(
+    directive: &str,
+    span: Span,
+    front_matter: &mut FrontMatter,
+) -> Result<(), Error> {
+    if directive.is_empty() {
+        return Ok(());
+    }
+
+    let (keyword, args) = directive
+        .split_once(char::is_whitespace)
+        .map_or((directive, ""), |(keyword, args)| (keyword, args.trim()));
+
+    match keyword {
+        "require" => {
+            if args.is_empty() {
+                return Err(syntax_error(
+                    "Expected variable names after 'require'",
+                    span,
+                ));
+            }
+
+            for name in args.split(',').map(str::trim) {
+                front_matter.required.push(parse_name(name, span)?);
+            }
+        },
+        "default" => {
+            let (name, value) = args
+                .split_once('=')
+                .ok_or_else(|| syntax_error("Expected 'NAME=value' after 'default'", span))?;
+
+            front_matter
+                .defaults
+                .push((parse_name(name.trim(), span)?, value.trim().to_string()));
+        },
+        _ => {
+            return Err(syntax_error(
+                &format!(
+                    "Unknown front matter directive '{keyword}', expected 'require' or 'default'"
+                ),
+                span,
+            ));
+        },
+    }
+
+    Ok(())
+}
+
+fn parse_name(name: &str, span: Span) -> Result<String, Error> {
+    let is_valid = name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        && name.chars().next().is_some_and(|c| !c.is_ascii_digit());
+
+    if is_valid {
+        Ok(name.to_string())
+    } else {
+        Err(syntax_error(
+            &format!("Invalid variable name '{name}' in front matter"),
+            span,
+        ))
+    }
+}
+
+fn syntax_error(message: &str, span: Span) -> Error {
+    Error {
+        span: Some(span),
+        ..Error::new(ErrorKind::Syntax, message.to_string(), &span.start)
+    }
+}
@@ -1,34 +1,123 @@
-use crate::position::Position;
 use crate::str_read::StrRead;
+use crate::syntax::{AnsiCQuotes, Syntax, COMMENT, RAW_BLOCK_CLOSE, RAW_BLOCK_OPEN};
 use crate::token::Token;
+use std::borrow::Cow;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Escape sequences for characters which would otherwise end or alter text inside a param.
+const PARAM_ESCAPES: [&str; 3] = ["$$", "$}", "$:"];
+
+/// The escape sequence for a leading `=` inside a param, which would otherwise start an
+/// expression if expressions are enabled.
+const EXPRESSION_ESCAPE: &str = "$=";
 
 pub struct Lexer<'a> {
     reader: StrRead<'a>,
     previous_token: Option<Token<'a>>,
     nesting_level: usize,
+    // The nesting levels of the params whose error message is being read, as in `${VAR?message}`
+    message_levels: Vec<usize>,
+    // The nesting levels of the params whose name is being read, if dynamic names are enabled,
+    // as in `${PREFIX_${ENV}_SUFFIX}`
+    name_levels: Vec<usize>,
+    // The number of tokens read since the last `{`, which tells whether text following a colon
+    // is the key of a namespaced variable, as in `${env:HOME}`
+    param_tokens: usize,
+    syntax: Syntax,
+    // Whether the rest of the input may contain a closing delimiter of an opaque region, which
+    // is no longer the case once searching for one failed
+    may_close_region: bool,
+    // Whether the source starts at the start of a line, which isn't the case for a chunk split
+    // off in the middle of a line
+    starts_line: bool,
 }
 
 impl<'a> Lexer<'a> {
-    pub fn new(source: &'a str) -> Self {
+    pub const fn new(source: &'a str, syntax: Syntax) -> Self {
         Self {
             reader: StrRead::new(source),
             previous_token: None,
             nesting_level: 0,
+            message_levels: Vec::new(),
+            name_levels: Vec::new(),
+            param_tokens: 0,
+            syntax,
+            may_close_region: true,
+            starts_line: true,
         }
     }
 
+    pub const fn syntax(&self) -> &Syntax {
+        &self.syntax
+    }
+
+    pub const fn source(&self) -> &'a str {
+        self.reader.input()
+    }
+
+    /// Returns the byte offset of the next token.
+    pub const fn index(&self) -> usize {
+        self.reader.index()
+    }
+
+    /// Whether the input can be split in two before the next token, such that lexing both parts
+    /// on their own yields the same tokens. That's the case outside of params, unless the next
+    /// token depends on the one before it or would start a comment line in the second part.
+    pub fn is_at_boundary(&self) -> bool {
+        let depends_on_previous = match self.previous_token {
+            Some(Token::DollarSign | Token::Colon) => true,
+            Some(Token::Identifier("env")) => self.syntax.powershell_env,
+            _ => false,
+        };
+
+        self.nesting_level == 0
+            && !depends_on_previous
+            && !(self.syntax.comments && self.reader.rest().starts_with(COMMENT))
+    }
+
+    /// Sets whether the source starts at the start of a line, where a comment line may start.
+    pub const fn starts_line(&mut self, starts_line: bool) {
+        self.starts_line = starts_line;
+    }
+
+    /// Skips the given number of bytes at the start of the source, such as front matter.
+    pub fn skip(&mut self, len: usize) {
+        self.reader.skip_to(len);
+    }
+
     pub const fn into_iter(mut self) -> IterMut<'a> {
         IterMut::new(self)
     }
 
-    pub fn next_token(&mut self) -> Option<(Token<'a>, Position)> {
+    /// Returns the next token together with the byte offset of its end.
+    pub fn next_token(&mut self) -> Option<(Token<'a>, usize)> {
         let is_param = self.nesting_level > 0 || self.previous_token == Some(Token::DollarSign);
+        let is_message = self.message_levels.last() == Some(&self.nesting_level)
+            && self.previous_token != Some(Token::DollarSign);
 
-        let token = if is_param {
+        let token = if is_message {
+            self.read_message()
+        } else if self.is_powershell_env_colon() {
+            self.reader.consume_char();
+            Some(Token::Colon)
+        } else if self.is_powershell_env_key() {
+            let key = self
+                .reader
+                .consume_while(|c| c.is_alphanumeric() || c == '_');
+            Some(Token::Text(Cow::Borrowed(key)))
+        } else if let Some(text) = self.read_ansi_c_quoted() {
+            Some(Token::Text(text))
+        } else if is_param {
             self.read_param()
+        } else if self.is_comment() {
+            self.read_comment();
+            Some(Token::Comment)
+        } else if self.syntax.raw_blocks && self.reader.rest().starts_with(RAW_BLOCK_OPEN) {
+            Some(self.read_raw_block())
         } else {
             let next_char = self.reader.peek_char();
-            let is_escaped = self.reader.peek_count(2) == "$$";
+            let is_escaped = self.reader.peek_count(2) == "$$" && !self.syntax.is_special_var('$');
 
             if next_char == Some('$') && !is_escaped {
                 self.read_param()
@@ -37,53 +126,218 @@ impl<'a> Lexer<'a> {
             }
         };
 
-        self.nesting_level = match token {
-            Some(Token::OpenBrace) => self.nesting_level.saturating_add(1),
-            Some(Token::CloseBrace) => self.nesting_level.saturating_sub(1),
-            _ => self.nesting_level,
-        };
+        if self.name_levels.last() == Some(&self.nesting_level)
+            && !self.continues_name(token.as_ref())
+        {
+            self.name_levels.pop();
+        }
+
+        self.param_tokens = self.param_tokens.saturating_add(1);
+
+        match token {
+            Some(Token::OpenBrace) => {
+                self.nesting_level = self.nesting_level.saturating_add(1);
+                self.param_tokens = 0;
+
+                if self.syntax.dynamic_names {
+                    self.name_levels.push(self.nesting_level);
+                }
+            },
+            Some(Token::CloseBrace) => {
+                if self.message_levels.last() == Some(&self.nesting_level) {
+                    self.message_levels.pop();
+                }
+
+                self.nesting_level = self.nesting_level.saturating_sub(1);
+            },
+            Some(Token::QuestionMark) if self.nesting_level > 0 => {
+                self.message_levels.push(self.nesting_level);
+            },
+            _ => {},
+        }
 
         self.previous_token = token.clone();
 
-        token.map(|token| (token, self.reader.position().clone()))
+        token.map(|token| (token, self.reader.index()))
     }
 
-    fn read_text(&mut self) -> Option<Token<'a>> {
-        let mut slices = Vec::new();
+    /// Whether the token is part of the name of the param being read, which ends at the first
+    /// other token such as the `:-` of `${PREFIX_${ENV}:-default}`.
+    fn continues_name(&self, token: Option<&Token>) -> bool {
+        match token {
+            Some(
+                Token::Identifier(_) | Token::Index(_) | Token::Special(_) | Token::DollarSign,
+            ) => true,
+            Some(Token::OpenBrace) => self.previous_token == Some(Token::DollarSign),
+            Some(Token::PoundSign | Token::ExclamationMark) => {
+                self.previous_token == Some(Token::OpenBrace)
+            },
+            _ => false,
+        }
+    }
 
-        loop {
-            let is_escaped = self.reader.peek_count(2) == "$$";
+    /// Whether the next character is the colon of an unbraced PowerShell-style environment
+    /// variable, as in `$env:NAME`.
+    fn is_powershell_env_colon(&self) -> bool {
+        if !self.syntax.powershell_env
+            || self.nesting_level > 0
+            || self.previous_token != Some(Token::Identifier("env"))
+        {
+            return false;
+        }
 
-            if is_escaped {
-                self.reader.consume_char();
-                self.reader.consume_char();
-                slices.push("$");
-            }
+        let mut chars = self.reader.peek_count(2).chars();
+        chars.next() == Some(':')
+            && chars
+                .next()
+                .is_some_and(|c| c.is_alphanumeric() || c == '_')
+    }
 
-            let text = self.reader.consume_while(|c| c != '$');
+    /// Whether the next characters are the name of an unbraced PowerShell-style environment
+    /// variable, following the colon in `$env:NAME`.
+    fn is_powershell_env_key(&self) -> bool {
+        self.syntax.powershell_env
+            && self.nesting_level == 0
+            && self.previous_token == Some(Token::Colon)
+    }
 
-            if text.is_empty() {
-                break;
+    /// Reads text up until the next param. An escaped `$$` is read as a token of its own,
+    /// borrowing the second `$`, so that no text has to be copied. Opaque regions are read as
+    /// part of the text, including any `$` inside them.
+    fn read_text(&mut self) -> Option<Token<'a>> {
+        let text = if self.reader.peek_count(2) == "$$" {
+            self.reader.consume_char();
+            self.reader.consume_count(1)
+        } else {
+            match self.find_opaque_region() {
+                Some(end) => self.reader.advance(end),
+                None => self.reader.consume_until(b"$"),
             }
+        };
 
-            slices.push(text);
-        }
-
-        if slices.is_empty() {
+        if text.is_empty() {
             None
         } else {
-            let text = String::from_iter(slices);
-            Some(Token::Text(text))
+            Some(Token::Text(Cow::Borrowed(text)))
+        }
+    }
+
+    /// Finds an opaque region which starts before the next `$`, returning the offset of its end
+    /// relative to the next character.
+    fn find_opaque_region(&mut self) -> Option<usize> {
+        let regions = self.syntax.opaque_regions.as_ref()?;
+
+        if !self.may_close_region {
+            return None;
+        }
+
+        let rest = self.reader.rest();
+        let dollar = rest.find('$').unwrap_or(rest.len());
+        let start = rest[..dollar].find(&regions.open)?;
+        let region = regions.find(&rest[start..]);
+
+        self.may_close_region = region.is_some();
+
+        region.map(|region| start + region.end)
+    }
+
+    /// Reads ANSI-C quoted text, as in `$'a\tb'`, if it's enabled and the next characters
+    /// start quoted text which ends on the same line. It isn't read after a `$`, which starts a
+    /// param.
+    fn read_ansi_c_quoted(&mut self) -> Option<Cow<'a, str>> {
+        if self.syntax.ansi_c_quotes == AnsiCQuotes::Error
+            || self.previous_token == Some(Token::DollarSign)
+            || self.reader.peek_count(2) != "$'"
+        {
+            return None;
+        }
+
+        let rest = self.reader.rest();
+        let mut bytes = rest.bytes().enumerate().skip(2);
+        let end = loop {
+            match bytes.next()? {
+                (_, b'\\') => {
+                    bytes.next();
+                },
+                (_, b'\n') => return None,
+                (index, b'\'') => break index,
+                _ => {},
+            }
+        };
+        let quoted = self.reader.advance(end + 1);
+
+        match self.syntax.ansi_c_quotes {
+            AnsiCQuotes::Interpret => Some(Cow::Owned(unescape_ansi_c(&quoted[2..end]))),
+            _ => Some(Cow::Borrowed(quoted)),
         }
     }
 
+    /// Whether the next characters start a comment line, outside of params.
+    fn is_comment(&self) -> bool {
+        let index = self.reader.index();
+
+        self.syntax.comments
+            && self.reader.rest().starts_with(COMMENT)
+            && if index == 0 {
+                self.starts_line
+            } else {
+                self.reader.input()[..index].ends_with('\n')
+            }
+    }
+
+    /// Reads a comment line up until and including its line break.
+    fn read_comment(&mut self) {
+        let rest = self.reader.rest();
+        let len = rest.find('\n').map_or(rest.len(), |index| index + 1);
+
+        self.reader.advance(len);
+    }
+
+    /// Reads a raw block, whose text is read as is up until the closing marker. If the block is
+    /// never closed, only the opening marker is consumed.
+    fn read_raw_block(&mut self) -> Token<'a> {
+        self.reader.advance(RAW_BLOCK_OPEN.len());
+
+        let Some(len) = self.reader.rest().find(RAW_BLOCK_CLOSE) else {
+            return Token::UnclosedRaw;
+        };
+
+        let text = self.reader.advance(len);
+        self.reader.advance(RAW_BLOCK_CLOSE.len());
+
+        Token::Raw(text)
+    }
+
+    /// Whether the next characters continue the name of a param after a param inside it, as
+    /// in the `_SUFFIX` of `${PREFIX_${ENV}_SUFFIX}`.
+    fn is_name_suffix(&self) -> bool {
+        self.previous_token == Some(Token::CloseBrace)
+            && self.name_levels.last() == Some(&self.nesting_level)
+    }
+
     fn read_param(&mut self) -> Option<Token<'a>> {
         let next_char = self.reader.peek_char()?;
+
+        if self.previous_token == Some(Token::DollarSign) && self.syntax.is_special_var(next_char) {
+            self.reader.consume_char();
+            return Some(Token::Special(next_char));
+        }
+
         let can_be_identifier = matches!(
             self.previous_token,
-            Some(Token::DollarSign | Token::OpenBrace | Token::PoundSign | Token::ExclamationMark)
-        );
-        let mut is_escaped = self.reader.peek_count(2) == "$$";
+            Some(
+                Token::DollarSign
+                    | Token::OpenBrace
+                    | Token::PoundSign
+                    | Token::ExclamationMark
+                    | Token::AtSign
+            )
+        ) || self.is_name_suffix();
+        let next = self.reader.peek_count(2);
+        // Outside of params `$$` is the special parameter `$`, if it's recognized
+        let is_special_dollar =
+            self.nesting_level == 0 && next == "$$" && self.syntax.is_special_var('$');
+        let is_escaped = self.is_param_escape(next) && !is_special_dollar;
         let token = match next_char {
             '$' if !is_escaped => {
                 self.reader.consume_char();
@@ -117,7 +371,12 @@ impl<'a> Lexer<'a> {
                 self.reader.consume_char();
                 Token::QuestionMark
             },
-            '#' => {
+            // Elsewhere a `#` is text, such as the operator of `${VAR#prefix}`
+            '#' if matches!(
+                self.previous_token,
+                Some(Token::DollarSign | Token::OpenBrace)
+            ) =>
+            {
                 self.reader.consume_char();
                 Token::PoundSign
             },
@@ -133,34 +392,231 @@ impl<'a> Lexer<'a> {
                 self.reader.consume_char();
                 Token::Tilde
             },
-            c if can_be_identifier && c.is_numeric() => {
-                let text = self.reader.consume_while(char::is_numeric);
-                let number = text.parse().unwrap_or(0);
-                Token::Index(number)
+            '@' => {
+                self.reader.consume_char();
+                Token::AtSign
             },
             c if can_be_identifier && (c.is_alphanumeric() || c == '_') => {
                 let text = self
                     .reader
                     .consume_while(|c| c.is_alphanumeric() || c == '_');
-                Token::Identifier(text)
+
+                if text.bytes().all(|byte| byte.is_ascii_digit()) {
+                    Token::Index(text)
+                } else {
+                    Token::Identifier(text)
+                }
             },
             _ => {
-                if is_escaped {
-                    self.reader.consume_char();
-                }
-
-                let text = self.reader.consume_while(|c| c != '}' && c != '\n');
+                let start = self.reader.index();
+                let text = self.read_param_text(false);
 
-                if text.is_empty() {
+                // Quotes may yield empty text, which still has to be a token
+                if self.reader.index() == start {
                     return None;
                 }
 
-                Token::Text(String::from(text))
+                Token::Text(text)
             },
         };
 
         Some(token)
     }
+
+    /// Whether the next two characters escape a character inside a param.
+    fn is_param_escape(&self, next: &str) -> bool {
+        PARAM_ESCAPES.contains(&next) || (self.syntax.expressions && next == EXPRESSION_ESCAPE)
+    }
+
+    /// Reads the error message of a param such as `${VAR?message}`, which is text up until the
+    /// closing brace, except that params inside it start with a `$` just like outside of params.
+    fn read_message(&mut self) -> Option<Token<'a>> {
+        let is_escaped = self.is_param_escape(self.reader.peek_count(2));
+
+        match self.reader.peek_char()? {
+            '$' if !is_escaped => {
+                self.reader.consume_char();
+                Some(Token::DollarSign)
+            },
+            '}' => {
+                self.reader.consume_char();
+                Some(Token::CloseBrace)
+            },
+            _ => {
+                let start = self.reader.index();
+                let text = self.read_param_text(true);
+
+                // Quotes may yield empty text, which still has to be a token
+                (self.reader.index() != start).then_some(Token::Text(text))
+            },
+        }
+    }
+
+    /// Whether the next text is the key of a namespaced variable, which follows the colon after
+    /// the name at the start of a braced param, as in `${env:HOME}`.
+    fn is_namespace_key(&self) -> bool {
+        self.previous_token == Some(Token::Colon) && self.param_tokens == 2
+    }
+
+    /// Reads text inside a param up until a closing brace or line break, and optionally up until
+    /// a `$`, unescaping `$$`, `$}` and `$:` along the way. If quoting is enabled, quoted text
+    /// is read as is. The key of a namespaced variable also ends at a `:-`, `:+` or `:?`,
+    /// as in `${env:HOME:-/root}`. The text is only copied if it's made up of more than one
+    /// slice of the input.
+    fn read_param_text(&mut self, stop_at_dollar: bool) -> Cow<'a, str> {
+        let quoting = self.syntax.quoting;
+        let is_key = self.is_namespace_key();
+        let mut text = Cow::Borrowed("");
+
+        loop {
+            let next = self.reader.peek_count(2);
+
+            if self.is_param_escape(next) {
+                self.reader.consume_char();
+                append(&mut text, self.reader.consume_count(1));
+                continue;
+            }
+
+            if next.starts_with('$') {
+                if stop_at_dollar {
+                    break;
+                }
+
+                append(&mut text, self.reader.consume_count(1));
+                continue;
+            }
+
+            if quoting && next.starts_with(['"', '\'']) {
+                self.read_quoted_text(&mut text);
+                continue;
+            }
+
+            if is_key && next.starts_with(':') {
+                if matches!(next, ":-" | ":+" | ":?") {
+                    break;
+                }
+
+                append(&mut text, self.reader.consume_count(1));
+                continue;
+            }
+
+            let slice = match (quoting, is_key) {
+                (false, false) => self.reader.consume_until(b"}\n$"),
+                (false, true) => self.reader.consume_until(b"}\n$:"),
+                (true, false) => self.reader.consume_until(b"}\n$\"'"),
+                (true, true) => self.reader.consume_until(b"}\n$\"':"),
+            };
+
+            if slice.is_empty() {
+                break;
+            }
+
+            append(&mut text, slice);
+        }
+
+        text
+    }
+
+    /// Reads quoted text up until the matching closing quote, which is consumed but not included
+    /// in the text. Inside double quotes, `\"` and `\\` yield a literal `"` and `\`
+    /// respectively. An unterminated quote ends at the next line break.
+    fn read_quoted_text(&mut self, text: &mut Cow<'a, str>) {
+        let Some(quote) = self.reader.consume_char() else {
+            return;
+        };
+        let needles: &[u8] = if quote == '"' { b"\"\\\n" } else { b"'\n" };
+
+        loop {
+            append(text, self.reader.consume_until(needles));
+
+            match self.reader.peek_char() {
+                Some('\\') => {
+                    let backslash = self.reader.consume_count(1);
+
+                    if matches!(self.reader.peek_char(), Some('"' | '\\')) {
+                        append(text, self.reader.consume_count(1));
+                    } else {
+                        append(text, backslash);
+                    }
+                },
+                Some(next_char) if next_char == quote => {
+                    self.reader.consume_char();
+                    break;
+                },
+                _ => break,
+            }
+        }
+    }
+}
+
+/// Interprets the escape sequences of ANSI-C quoted text like Bash does, where `\xHH`, `\uHHHH`,
+/// `\UHHHHHHHH` and `\NNN` stand for the character with the given hexadecimal or octal code
+/// point. Unknown escape sequences, and those not yielding a character, are left as is.
+fn unescape_ansi_c(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        let rest = chars.clone();
+        let unescaped = match chars.next() {
+            Some('a') => Some('\x07'),
+            Some('b') => Some('\x08'),
+            Some('e' | 'E') => Some('\x1b'),
+            Some('f') => Some('\x0c'),
+            Some('n') => Some('\n'),
+            Some('r') => Some('\r'),
+            Some('t') => Some('\t'),
+            Some('v') => Some('\x0b'),
+            Some(c @ ('\\' | '\'' | '"' | '?')) => Some(c),
+            Some('x') => read_code_point(&mut chars, 16, 2),
+            Some('u') => read_code_point(&mut chars, 16, 4),
+            Some('U') => read_code_point(&mut chars, 16, 8),
+            Some('0'..='7') => {
+                chars = rest.clone();
+                read_code_point(&mut chars, 8, 3)
+            },
+            _ => None,
+        };
+
+        if let Some(c) = unescaped {
+            result.push(c);
+        } else {
+            result.push('\\');
+            chars = rest;
+        }
+    }
+
+    result
+}
+
+/// Reads up to the given number of digits, returning the character with that code point.
+fn read_code_point(chars: &mut Peekable<Chars>, radix: u32, max_digits: usize) -> Option<char> {
+    let mut code_point = None;
+
+    for _ in 0..max_digits {
+        let Some(digit) = chars.peek().and_then(|c| c.to_digit(radix)) else {
+            break;
+        };
+
+        chars.next();
+        code_point = Some(code_point.unwrap_or(0) * radix + digit);
+    }
+
+    code_point.and_then(char::from_u32)
+}
+
+/// Appends a slice of the input to the text, which stays borrowed if it was empty.
+fn append<'a>(text: &mut Cow<'a, str>, slice: &'a str) {
+    if text.is_empty() {
+        *text = Cow::Borrowed(slice);
+    } else if !slice.is_empty() {
+        text.to_mut().push_str(slice);
+    }
 }
 
 pub struct IterMut<'a> {
@@ -174,7 +630,7 @@ impl<'a> IterMut<'a> {
 }
 
 impl<'a> Iterator for IterMut<'a> {
-    type Item = (Token<'a>, Position);
+    type Item = (Token<'a>, usize);
 
     fn next(&mut self) -> Option<Self::Item> {
         self.lexer.next_token()
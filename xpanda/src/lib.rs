@@ -2,70 +2,315 @@
 This crate provides the ability to expand/substitute variables in strings similar to [`envsubst`]
 and [`Bash parameter expansion`].
 
-There is a single public struct (not counting errors and builders), [`Xpanda`], which in turn
-contains a single method: `expand`. The expand method takes a string by reference and returns
-a copy of it with all variables expanded/substituted according to some patterns.
+The main public struct (not counting errors and builders) is [`Xpanda`], which contains the
+method `expand`. The expand method takes a string by reference and returns a copy of it with all
+variables expanded/substituted according to some patterns.
+
+Templates can also be parsed up front into a [`Template`], which can be inspected (for example to
+list the variables it references) and later rendered using `Xpanda::render`.
+
+Parsing, rendering and expanding never panic, whatever the input and the values of the
+variables, so it's safe to expand untrusted templates and values. Input which can't be handled,
+such as params nested more than 64 levels deep, results in an [`Error`] instead. The only
+exception are panics in user provided code, such as a [`Provider`]. The crate also contains no
+unsafe code.
+
+Some families of syntax can be compiled out by disabling the cargo features enabling them, all of
+which are enabled by default. Disabled syntax is a syntax error instead.
+
+* `case-modifiers`: `${VAR^}`, `${VAR,}` and `${VAR~}`, as well as their doubled forms.
+* `indirection`: `${!VAR}`.
+* `length`: `${#VAR}` and `${#}`, pulling in `unicode-segmentation` to count graphemes.
+
+The `expr` feature, which is disabled by default, adds `Builder::expressions`, which enables
+expressions as the default or alternative value of a param, written after a `=` as in
+`${REPLICAS:-=max(2, $CPU / 2)}`. Expressions support numbers, quoted strings, variables
+written as `$NAME`, the arithmetic operators `+`, `-`, `*`, `/` and `%`, comparisons, `&&`,
+`||`, `!`, the ternary operator `condition ? a : b`, and the functions `min`, `max`, `floor`,
+`ceil`, `round` and `concat`. A default or alternative value which should start with a literal
+`=` is written as `$=` instead.
+
+The `serialize` feature, disabled by default, adds `Template::to_bytes` and
+`Template::from_bytes`, which save and load parsed templates in a compact binary format. Build
+steps can this way ship templates which don't need to be parsed at runtime.
+
+How inputs are expanded is pinned down by a corpus of cases, each an input along with variables
+and the expected output, which is available in the `semantics` directory of the repository. A
+[`Corpus`] can be loaded from there and run against any configuration, which reimplementations
+can use to check that they behave the same.
+
+Patterns matched against values, as in `${VAR#pattern}`, are shell glob patterns by default,
+compiled by [`GlobEngine`]. A different [`PatternEngine`] can be set using
+`Builder::pattern_engine`. The `regex` feature, which is also disabled by default, adds
+[`RegexEngine`] and makes any pattern enclosed in tildes, as in `~[0-9]+~`, a regular expression.
+It also adds `VarSchema::pattern`, constraining values of a [`Schema`] to a regular expression.
+
+The `zeroize` feature, disabled by default as well, adds `Builder::with_secret_vars` for named
+variables whose values are wiped from memory once they are no longer needed, as are the copies
+of them made while expanding.
+
+The `locale` feature, also disabled by default, adds [`CaseLocale`] and `Builder::case_locale`,
+which make case modifiers follow the rules of a language, such as the dotted and dotless i of
+Turkish, using the case mapping of ICU4X.
 
 [`envsubst`]: https://www.gnu.org/software/gettext/manual/html_node/envsubst-Invocation.html
 [`Bash parameter expansion`]: https://www.gnu.org/software/bash/manual/html_node/Bourne-Shell-Builtins.html
 [`Xpanda`]: struct.Xpanda.html
+[`Template`]: struct.Template.html
+[`Error`]: struct.Error.html
+[`Provider`]: trait.Provider.html
+[`GlobEngine`]: struct.GlobEngine.html
+[`PatternEngine`]: trait.PatternEngine.html
+[`RegexEngine`]: struct.RegexEngine.html
+[`Schema`]: struct.Schema.html
+[`CaseLocale`]: struct.CaseLocale.html
+[`Corpus`]: struct.Corpus.html
 */
 
+#![forbid(unsafe_code)]
 #![deny(clippy::all)]
 #![warn(clippy::pedantic, clippy::nursery)]
 #![allow(unused)]
 
 mod ast;
+#[cfg(feature = "serialize")]
+mod binary;
+mod bytecode;
+mod bytes;
+mod cache;
+mod case;
+mod context;
+mod document;
+mod encoding;
+mod escape;
 mod eval;
+#[cfg(feature = "expr")]
+mod expr;
 mod forward_peekable;
+mod front_matter;
 mod lexer;
+mod lines;
+mod lint;
+mod metrics;
 mod parser;
+mod pattern;
 mod position;
+mod positional;
+mod provider;
+mod report;
+mod schema;
+mod semantics;
+mod simplify;
 mod str_read;
+mod stream;
+mod syntax;
+mod template;
 mod token;
+mod validate;
+mod wipe;
 
-use crate::eval::Evaluator;
+use crate::ast::{Ast, Identifier, Node};
+use crate::case::CaseMapping;
+use crate::eval::{
+    AccessPolicy, EnvLookup, Evaluator, ResolveListener, TimingListener, UnsetListener,
+    ValueTransform, WarningListener,
+};
 use crate::lexer::Lexer;
-use crate::parser::Parser;
-use crate::position::Position;
-use std::collections::HashMap;
+use crate::positional::PositionalVars;
+pub use crate::syntax::AnsiCQuotes;
+use crate::syntax::{OpaqueRegions, Syntax, SPECIAL_VARS};
+use crate::validate::invalid_vars;
+use crate::wipe::{wipe, NamedVars};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
+use std::error;
+use std::ffi::OsString;
+use std::fmt::{self, Display, Formatter};
+use std::io::BufRead;
+use std::sync::Arc;
+use std::time::Instant;
+
+pub use crate::bytecode::CompiledTemplate;
+pub use crate::cache::CachedXpanda;
+#[cfg(feature = "locale")]
+pub use crate::case::CaseLocale;
+pub use crate::context::Context;
+pub use crate::document::Document;
+pub use crate::escape::{escape, unescape};
+pub use crate::eval::{
+    Access, LengthMode, LengthPolicy, ResolveEvent, Source, UnsetEvent, UnsetOutcome, Warning,
+    WarningKind,
+};
+pub use crate::front_matter::FrontMatter;
+pub use crate::lines::LineExpander;
+pub use crate::lint::{Fix, Lint, Linter, Rule, Severity};
+pub use crate::metrics::{Phase, ProviderMetrics, Timing};
+#[cfg(feature = "regex")]
+pub use crate::pattern::RegexEngine;
+pub use crate::pattern::{GlobEngine, Pattern, PatternEngine};
+pub use crate::position::{Position, Span};
+pub use crate::provider::{EnvProvider, FileProvider, Provider};
+pub use crate::report::{ExpandedParam, Report, Resolution};
+pub use crate::schema::{Schema, VarSchema};
+pub use crate::semantics::{Case, Corpus, Expected, Mismatch};
+pub use crate::stream::{StreamExpander, DEFAULT_BUFFER_BYTES};
+pub use crate::template::{BoundTemplate, ParseError, Reached, Reference, Template};
+pub use crate::validate::{InvalidVar, InvalidVarReason};
+
+/// The category of an [`Error`], allowing callers to react differently to different failures.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The input is badly formatted and could not be parsed.
+    Syntax,
+    /// A variable without a default value is unset while [`Builder::no_unset`] is `true` or the
+    /// variable is required using [`Builder::require_vars`], or empty while
+    /// [`Builder::no_empty`] is `true`.
+    Unset,
+    /// A variable is unset (or empty) in a `${VAR?}` or `${VAR:?}` pattern.
+    Required,
+    /// The value of a variable can't be processed by the modifier applied to it, for example
+    /// `${VAR@base64d}` where the value of `VAR` isn't valid base64, or `${VAR@fixed:2}` where
+    /// the value of `VAR` isn't a number. Evaluating an expression fails with this kind as well,
+    /// for example if a variable used in arithmetic isn't a number.
+    InvalidValue,
+    /// A namespaced variable such as `${env:HOME}` could not be resolved, either because no
+    /// [`Provider`] is registered for the namespace or because the provider failed.
+    Provider,
+    /// A variable was denied by the access policy set using [`Builder::access_policy`].
+    Policy,
+    /// The input could not be read by a [`LineExpander`] or [`StreamExpander`], because reading
+    /// it failed or because it isn't valid UTF-8.
+    Io,
+    /// A line read by a [`LineExpander`] exceeds [`LineExpander::max_line_bytes`], or a param
+    /// read by a [`StreamExpander`] exceeds [`StreamExpander::buffer_bytes`].
+    TooLong,
+    /// The bytes given to `Template::from_bytes` aren't a serialized template, or were
+    /// serialized with a different version of the format.
+    Format,
+}
 
+/// An error which occurred while parsing or rendering a template.
+///
+/// Formatting an [`Error`] using [`Display`] yields its message followed by its location, unless
+/// it has none, such as errors reading a corpus or deserializing a template. As it implements
+/// [`std::error::Error`], it can be propagated using `?` into a `Box<dyn std::error::Error>`.
+///
+/// # Examples
+///
+/// ```
+/// use xpanda::Xpanda;
+///
+/// let error = Xpanda::default().expand("${VAR").unwrap_err();
+/// assert_eq!(
+///     error.to_string(),
+///     "Expected a matching '}' for the '${' opened at line 1, column 1"
+/// );
+///
+/// let error = Xpanda::default().expand("${VAR?}").unwrap_err();
+/// assert_eq!(error.to_string(), "'VAR' is unset at line 1, column 1");
+/// ```
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Error {
+    pub kind: ErrorKind,
     pub message: String,
+    /// The line of the error, or 1 if it has no location.
     pub line: usize,
+    /// The column of the error, or 1 if it has no location.
     pub col: usize,
+    /// The range of the input which caused the error, including byte offsets, or [`None`] if
+    /// it has no location. Errors which occur while rendering have an empty span at the start
+    /// of the param causing them.
+    pub span: Option<Span>,
 }
 
 impl Error {
+    /// Creates an error at the given position, with an empty span.
     #[must_use]
-    pub const fn new(message: String, position: &Position) -> Self {
+    pub const fn new(kind: ErrorKind, message: String, position: &Position) -> Self {
         Self {
+            kind,
             message,
             line: position.line,
             col: position.col,
+            span: Some(Span::new(*position, *position)),
+        }
+    }
+
+    /// Creates an error which isn't located anywhere in the input.
+    #[must_use]
+    pub fn unlocated(kind: ErrorKind, message: String) -> Self {
+        Self {
+            span: None,
+            ..Self::new(kind, message, &Position::default())
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.span.is_some() {
+            write!(
+                f,
+                "{} at line {}, column {}",
+                self.message, self.line, self.col
+            )
+        } else {
+            write!(f, "{}", self.message)
         }
     }
 }
 
+impl error::Error for Error {}
+
 impl From<parser::Error> for Error {
     fn from(error: parser::Error) -> Self {
-        Self::new(error.message, &error.position)
+        Self {
+            span: Some(error.span),
+            ..Self::new(ErrorKind::Syntax, error.message, &error.position)
+        }
     }
 }
 
 impl From<eval::Error> for Error {
     fn from(error: eval::Error) -> Self {
-        Self::new(error.message, &Position::default())
+        Self::new(error.kind, error.message, &error.position)
     }
 }
 
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Default)]
 pub struct Builder {
     no_unset: bool,
-    positional_vars: Vec<String>,
-    named_vars: HashMap<String, String>,
+    unset_overrides: Arc<HashMap<String, bool>>,
+    no_empty: bool,
+    length_mode: LengthMode,
+    max_value_length: Option<(usize, LengthPolicy)>,
+    context: Option<Context>,
+    positional_vars: Arc<PositionalVars>,
+    positional_names: Arc<HashMap<String, usize>>,
+    special_vars: Arc<HashMap<char, String>>,
+    named_vars: Arc<NamedVars>,
+    named_sources: Arc<HashMap<String, Source>>,
+    env_names: Arc<HashMap<String, String>>,
+    live_env: bool,
+    case_insensitive_env: Option<bool>,
+    reproducible: bool,
+    aliases: Arc<HashMap<String, String>>,
+    providers: HashMap<String, Arc<dyn Provider>>,
+    value_transforms: Vec<ValueTransform>,
+    unset_listeners: Vec<UnsetListener>,
+    resolve_listeners: Vec<ResolveListener>,
+    warning_listeners: Vec<WarningListener>,
+    timing_listeners: Vec<TimingListener>,
+    access_policy: Option<AccessPolicy>,
+    schema: Schema,
+    syntax: Syntax,
+    pattern_engine: Option<Arc<dyn PatternEngine>>,
+    case_mapping: CaseMapping,
+    validate_vars: bool,
+    /// The names of environment variables whose name or value isn't valid UTF-8.
+    not_utf8_env: HashSet<String>,
 }
 
 impl Builder {
@@ -77,420 +322,2712 @@ impl Builder {
         self
     }
 
-    /// Adds all environment variables as named variables.
+    /// Marks the given variables as required, such that an error is returned if they are unset
+    /// and have no default value, just like with [`Builder::no_unset`] but regardless of it.
+    /// This allows mixing hard requirements with optional variables. Marking a variable as
+    /// optional using [`Builder::optional_vars`] later on overrides this, and vice versa.
+    /// Positional variables are marked by their index, such as `1`. Marking a variable also
+    /// applies to its aliases, see [`Builder::with_aliases`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::{ErrorKind, Xpanda};
+    ///
+    /// let xpanda = Xpanda::builder().require_vars(["DB_URL"]).build();
+    ///
+    /// assert_eq!(xpanda.expand("$DB_URL").unwrap_err().kind, ErrorKind::Unset);
+    /// assert_eq!(xpanda.expand("${DB_URL:-db}$LOG_LEVEL"), Ok(String::from("db")));
+    /// ```
     #[must_use]
-    pub fn with_env_vars(mut self) -> Self {
-        self.named_vars.extend(env::vars());
-        self
+    pub fn require_vars<I, S>(self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.override_unset(names, true)
     }
 
-    /// Adds the given map values as named variables.
+    /// Marks the given variables as optional, such that they expand to an empty string if they
+    /// are unset, even with [`Builder::no_unset`] set. Marking a variable as required using
+    /// [`Builder::require_vars`] later on overrides this, and vice versa.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::{ErrorKind, Xpanda};
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .no_unset(true)
+    ///     .optional_vars(["LOG_LEVEL"])
+    ///     .build();
+    ///
+    /// assert_eq!(xpanda.expand("[$LOG_LEVEL]"), Ok(String::from("[]")));
+    /// assert_eq!(xpanda.expand("$DB_URL").unwrap_err().kind, ErrorKind::Unset);
+    /// ```
     #[must_use]
-    pub fn with_named_vars(mut self, vars: HashMap<String, String>) -> Self {
-        self.named_vars.extend(vars);
-        self
+    pub fn optional_vars<I, S>(self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.override_unset(names, false)
     }
 
-    /// Adds the given strings as positional variables.
-    #[must_use]
-    pub fn with_positional_vars(mut self, vars: Vec<String>) -> Self {
-        self.positional_vars.extend(vars);
+    fn override_unset<I, S>(mut self, names: I, required: bool) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let unset_overrides = Arc::make_mut(&mut self.unset_overrides);
+
+        for name in names {
+            unset_overrides.insert(name.into(), required);
+        }
+
         self
     }
 
-    /// Builds a new [`Xpanda`] instance.
+    /// With this flag set, variables which are set to an empty value and have no default value
+    /// will cause an error, just like unset variables do with [`Builder::no_unset`]. Patterns
+    /// such as `${VAR:-default}` still substitute empty variables. Off by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::{ErrorKind, Xpanda};
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .no_empty(true)
+    ///     .with_named_vars(HashMap::from([(String::from("VAR"), String::new())]))
+    ///     .build();
+    ///
+    /// assert_eq!(xpanda.expand("$VAR").unwrap_err().kind, ErrorKind::Unset);
+    /// assert_eq!(xpanda.expand("${VAR:-default}"), Ok(String::from("default")));
+    /// ```
     #[must_use]
-    pub fn build(self) -> Xpanda {
-        Xpanda::new(self)
+    pub const fn no_empty(mut self, no_empty: bool) -> Self {
+        self.no_empty = no_empty;
+        self
     }
-}
-
-/// [`Xpanda`] substitutes the values of variables in strings similar to [`envsubst`] and
-/// [`Bash parameter expansion`].
-///
-/// [`envsubst`]: https://www.gnu.org/software/gettext/manual/html_node/envsubst-Invocation.html
-/// [`Bash parameter expansion`]: https://www.gnu.org/software/bash/manual/html_node/Shell-Parameter-Expansion.html
-#[derive(Default)]
-pub struct Xpanda {
-    evaluator: Evaluator,
-}
 
-impl Xpanda {
-    fn new(builder: Builder) -> Self {
-        Self {
-            evaluator: Evaluator::new(
-                builder.no_unset,
-                builder.positional_vars,
-                builder.named_vars,
-            ),
-        }
+    /// Sets how `${#VAR}` counts the length of a value. Defaults to [`LengthMode::Bytes`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::{LengthMode, Xpanda};
+    ///
+    /// # #[cfg(feature = "length")]
+    /// # {
+    /// let input = "${#1}";
+    /// let bytes = Xpanda::builder().with_positional_vars(vec![String::from("åä")]);
+    /// let chars = Xpanda::builder()
+    ///     .with_positional_vars(vec![String::from("åä")])
+    ///     .length_mode(LengthMode::Chars);
+    ///
+    /// assert_eq!(bytes.build().expand(input), Ok(String::from("4")));
+    /// assert_eq!(chars.build().expand(input), Ok(String::from("2")));
+    /// # }
+    /// ```
+    #[must_use]
+    pub const fn length_mode(mut self, length_mode: LengthMode) -> Self {
+        self.length_mode = length_mode;
+        self
     }
 
+    /// Limits the values of variables to at most `max` characters, counted as Unicode scalar
+    /// values like `${VAR@trunc:width}` does, guarding against runaway values. Longer values
+    /// either cause an error or are truncated, depending on the policy. The limit applies to
+    /// values after [value transforms](Builder::value_transform), but not to default or
+    /// alternative values written in the template. Unlimited by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::{ErrorKind, LengthPolicy, Xpanda};
+    ///
+    /// let vars = vec![String::from("runaway")];
+    /// let error = Xpanda::builder()
+    ///     .with_positional_vars(vars.clone())
+    ///     .max_value_length(3, LengthPolicy::Error);
+    /// let truncate = Xpanda::builder()
+    ///     .with_positional_vars(vars)
+    ///     .max_value_length(3, LengthPolicy::Truncate);
+    ///
+    /// assert_eq!(error.build().expand("$1").unwrap_err().kind, ErrorKind::InvalidValue);
+    /// assert_eq!(truncate.build().expand("$1"), Ok(String::from("run")));
+    /// ```
     #[must_use]
-    pub fn builder() -> Builder {
-        Builder::default()
+    pub const fn max_value_length(mut self, max: usize, policy: LengthPolicy) -> Self {
+        self.max_value_length = Some((max, policy));
+        self
     }
 
-    /// Expands the given text by substituting the values of the variables inside it.
+    /// With this flag set, single and double quotes inside params quote text, so that quoted
+    /// text may contain characters which otherwise have a special meaning, as in
+    /// `${VAR:-"a } b"}`. Inside double quotes, `\"` and `\\` yield a literal `"` and `\`
+    /// respectively. Off by default.
     ///
-    /// Variables can appear in any of the following forms:
+    /// # Examples
     ///
-    /// <table>
-    ///   <thead>
-    ///     <tr>
-    ///       <th>Pattern</th>
-    ///       <th>Description</th>
-    ///     </tr>
-    ///   </thead>
-    ///   <tbody>
-    ///     <tr>
-    ///       <td>$VAR</td>
-    ///       <td>substituted with the corresponding value for 'VAR' if set, otherwise "".</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR}</td>
-    ///       <td>substituted with the corresponding value for 'VAR' if set, otherwise "".</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR-default}</td>
-    ///       <td>
-    ///         substituted with the corresponding value for 'VAR' if set, otherwise "default".
-    ///       </td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR:-default}</td>
-    ///       <td>
-    ///         substituted with the corresponding value for 'VAR' if set and non-empty, otherwise
-    ///         "default".
-    ///       </td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR+alternative}</td>
-    ///       <td>
-    ///         substituted with "alternative" if the corresponding value for 'VAR' is set,
-    ///         otherwise "".
-    ///       </td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR:+alternative}</td>
-    ///       <td>
-    ///         substituted with "alternative" if the corresponding value for 'VAR' is set and
-    ///         non-empty, otherwise "".
-    ///       </td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR?}</td>
-    ///       <td>
-    ///         substituted with the corresponding value for 'VAR' if set, otherwise yields an
-    ///         error.
-    ///       </td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR?error}</td>
-    ///       <td>
-    ///         substituted with the corresponding value for 'VAR' if set, otherwise yields an
-    ///         error with the given message (in this case "error").
-    ///       </td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR?error}</td>
-    ///       <td>
-    ///         substituted with the corresponding value for 'VAR' if set and non-empty, otherwise
-    ///         yields an error with the given message (in this case "error").
-    ///       </td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${#VAR}</td>
-    ///       <td>
-    ///         substituted with the length of the corresponding value for 'VAR' if set, otherwise
-    ///         "0".
-    ///       </td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR^}</td>
-    ///       <td>
-    ///         substituted with the value of the variable named by the value of `VAR`, with the
-    ///         first character uppercased.
-    ///       </td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR^^}</td>
-    ///       <td>
-    ///         substituted with the value of the variable named by the value of `VAR`, with all
-    ///         characters uppercased.
-    ///       </td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR,}</td>
-    ///       <td>
-    ///         substituted with the value of the variable named by the value of `VAR`, with the
-    ///         first character lowercased.
-    ///       </td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR,,}</td>
-    ///       <td>
-    ///         substituted with the value of the variable named by the value of `VAR`, with all
-    ///         characters lowercased.
-    ///       </td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR~}</td>
-    ///       <td>
-    ///         substituted with the value of the variable named by the value of `VAR`, with the
-    ///         casing of the first character reversed.
-    ///       </td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR~~}</td>
-    ///       <td>
-    ///         substituted with the value of the variable named by the value of `VAR`, with the
-    ///         casing of all characters reversed.
-    ///       </td>
-    ///     </tr>
-    ///   </tbody>
-    /// </table>
+    /// ```
+    /// use xpanda::Xpanda;
     ///
-    /// `VAR` above is a named variable. Named variables can be provided using the builder:
+    /// let xpanda = Xpanda::builder().quoting(true).build();
     ///
-    /// ```rust
+    /// assert_eq!(
+    ///     xpanda.expand("${VAR:-'{a: 1}'}"),
+    ///     Ok(String::from("{a: 1}"))
+    /// );
+    /// ```
+    #[must_use]
+    pub const fn quoting(mut self, quoting: bool) -> Self {
+        self.syntax.quoting = quoting;
+        self
+    }
+
+    /// With this flag set, identifiers which start with a digit but also contain other
+    /// characters, such as `$1abc` or `${3_}`, are named variables. Otherwise they are rejected
+    /// with an error, since they are likely a mistake for `${1}abc`. Identifiers consisting of
+    /// only digits, such as `${12}`, are always positional variables. Off by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
     /// use std::collections::HashMap;
     /// use xpanda::Xpanda;
     ///
-    /// let named_vars = HashMap::new();
+    /// let mut named_vars = HashMap::new();
+    /// named_vars.insert(String::from("2x"), String::from("double"));
     /// let xpanda = Xpanda::builder()
     ///     .with_named_vars(named_vars)
+    ///     .lenient_identifiers(true)
     ///     .build();
+    ///
+    /// assert_eq!(xpanda.expand("$2x"), Ok(String::from("double")));
     /// ```
+    #[must_use]
+    pub const fn lenient_identifiers(mut self, lenient_identifiers: bool) -> Self {
+        self.syntax.lenient_identifiers = lenient_identifiers;
+        self
+    }
+
+    /// With this flag set, PowerShell-style references to environment variables are supported
+    /// for compatibility with templates written for PowerShell. Both `$env:NAME` and
+    /// `${env:NAME}` resolve the environment variable `NAME` at the time of expansion, unless a
+    /// different provider is registered for the namespace `env` using
+    /// [`Builder::register_namespace`]. Off by default.
     ///
-    /// Positional variables are also supported and can be provided in the same way:
+    /// # Examples
     ///
-    /// ```rust
+    /// ```
+    /// use std::env;
     /// use xpanda::Xpanda;
     ///
-    /// let xpanda = Xpanda::builder()
-    ///     .with_positional_vars(Vec::new())
-    ///     .build();
+    /// env::set_var("XPANDA_POWERSHELL_EXAMPLE", "woop");
+    /// let xpanda = Xpanda::builder().powershell_env(true).build();
+    ///
+    /// assert_eq!(
+    ///     xpanda.expand("$env:XPANDA_POWERSHELL_EXAMPLE!"),
+    ///     Ok(String::from("woop!"))
+    /// );
     /// ```
+    #[must_use]
+    pub const fn powershell_env(mut self, powershell_env: bool) -> Self {
+        self.syntax.powershell_env = powershell_env;
+        self
+    }
+
+    /// With this flag set, lines starting with `#! xpanda:` at the start of a template are
+    /// parsed as [`FrontMatter`], in which the template declares the variables it requires and
+    /// their default values. Expanding the template fails if any required variable is unset,
+    /// and the front matter itself is not part of the output. Off by default.
     ///
-    /// Positional variables can be referenced using their index (starting at 1), for example, `$1`
-    /// references the first positional variable, `$2` the second and so on. `$0` is a space concatenated
-    /// string of all positional variables.
+    /// # Examples
     ///
-    /// Here are some examples and their output:
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::Xpanda;
     ///
-    /// <table>
-    ///   <thead>
-    ///     <tr>
-    ///       <th>Pattern</th>
-    ///       <th>VAR unset</th>
-    ///       <th>VAR=""</th>
-    ///       <th>VAR="example"</th>
-    ///     </tr>
-    ///   </thead>
-    ///   <tbody>
-    ///     <tr>
-    ///       <td>$VAR</td>
-    ///       <td></td>
-    ///       <td></td>
-    ///       <td>"example"</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR}</td>
-    ///       <td></td>
-    ///       <td></td>
-    ///       <td>"example"</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR-default}</td>
-    ///       <td>"default"</td>
-    ///       <td></td>
-    ///       <td>"example"</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR:-default}</td>
-    ///       <td>"default"</td>
-    ///       <td>"default"</td>
-    ///       <td>"example"</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR+alternative}</td>
-    ///       <td></td>
-    ///       <td>"alternative"</td>
-    ///       <td>"alternative"</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR:+alternative}</td>
-    ///       <td></td>
-    ///       <td></td>
-    ///       <td>"alternative"</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR?message}</td>
-    ///       <td>error: "message"</td>
-    ///       <td></td>
-    ///       <td>"example"</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR:?message}</td>
-    ///       <td>error: "message"</td>
-    ///       <td>error: "message"</td>
-    ///       <td>"example"</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${#VAR}</td>
-    ///       <td>"0"</td>
-    ///       <td>"0"</td>
-    ///       <td>"7"</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${!VAR}</td>
-    ///       <td></td>
-    ///       <td></td>
-    ///       <td>$example</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR^}</td>
-    ///       <td></td>
-    ///       <td></td>
-    ///       <td>"Example"</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR^^}</td>
-    ///       <td></td>
-    ///       <td></td>
-    ///       <td>"EXAMPLE"</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR,}</td>
-    ///       <td></td>
-    ///       <td></td>
-    ///       <td>"example"</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR,,}</td>
-    ///       <td></td>
-    ///       <td></td>
-    ///       <td>"example"</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR~}</td>
-    ///       <td></td>
-    ///       <td></td>
-    ///       <td>"Example"</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR~~}</td>
-    ///       <td></td>
-    ///       <td></td>
-    ///       <td>"EXAMPLE"</td>
-    ///     </tr>
-    ///   </tbody>
-    /// </table>
+    /// let xpanda = Xpanda::builder()
+    ///     .front_matter(true)
+    ///     .with_named_vars(HashMap::from([(String::from("DB_URL"), String::from("db"))]))
+    ///     .build();
+    /// let template = "#! xpanda: require DB_URL; default REGION=us-east-1\n$DB_URL@$REGION";
     ///
-    /// Special rules take precedence when [`Builder::no_unset`] is `true`:
+    /// assert_eq!(xpanda.expand(template), Ok(String::from("db@us-east-1")));
+    /// ```
+    #[must_use]
+    pub const fn front_matter(mut self, front_matter: bool) -> Self {
+        self.syntax.front_matter = front_matter;
+        self
+    }
+
+    /// With this flag set, templates may only reference the provided variables, for use with
+    /// templates from untrusted sources. Params which could resolve any other value are
+    /// rejected when parsing, namely indirect params as in `${!VAR}` and namespaced variables
+    /// as in `${env:HOME}`, regardless of the registered providers. Templates parsed elsewhere
+    /// fail to render if they contain any such params. Off by default.
     ///
-    /// <table>
-    ///   <thead>
-    ///     <tr>
-    ///       <th>Pattern</th>
-    ///       <th>VAR unset</th>
-    ///     </tr>
-    ///   </thead>
-    ///   <tbody>
-    ///     <tr>
-    ///       <td>$VAR</td>
-    ///       <td>error</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR}</td>
-    ///       <td>error</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${#VAR}</td>
-    ///       <td>error</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${!VAR}</td>
-    ///       <td>error</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR^}</td>
-    ///       <td>error</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR^^}</td>
-    ///       <td>error</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR,}</td>
-    ///       <td>error</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR,,}</td>
-    ///       <td>error</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR~}</td>
-    ///       <td>error</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>${VAR~~}</td>
-    ///       <td>error</td>
-    ///     </tr>
-    ///   </tbody>
-    /// </table>
+    /// # Examples
     ///
-    /// Default/Alternative values can also be variables:
+    /// ```
+    /// use xpanda::Xpanda;
     ///
-    /// <table>
-    ///   <thead>
-    ///     <tr>
-    ///       <th>Pattern</th>
-    ///       <th>VAR unset</th>
-    ///       <th>VAR=""</th>
-    ///       <th>VAR="example"</th>
-    ///     </tr>
-    ///   </thead>
-    ///   <tbody>
-    ///     <tr>
-    ///       <td>`${VAR:-$DEF}`</td>
-    ///       <td>`$DEF`</td>
-    ///       <td></td>
-    ///       <td>"example"</td>
-    ///     </tr>
-    ///     <tr>
-    ///       <td>`${VAR+${ALT:-alternative}}`</td>
-    ///       <td></td>
-    ///       <td>`${ALT:-alternative}`</td>
-    ///       <td>${ALT:-alternative}</td>
-    ///     </tr>
-    ///   </tbody>
-    /// </table>
+    /// let xpanda = Xpanda::builder().safe_mode(true).with_env_vars().build();
+    /// let error = xpanda.expand("${env:HOME}").unwrap_err();
     ///
-    /// The `$` character is assumed to be the start of a variable. If the variable does not match
-    /// any of the forms listed above, an error is returned. Variables can be escaped by prefixing them
-    /// by an additional '$', for example: `$$VAR` which yields `$VAR` and `${VAR-$$text}` which yields
-    /// `$text` if `VAR` is unset.
+    /// assert_eq!(
+    ///     error.message,
+    ///     "Namespaced variables are not allowed in safe mode"
+    /// );
+    /// ```
+    #[must_use]
+    pub const fn safe_mode(mut self, safe_mode: bool) -> Self {
+        self.syntax.safe_mode = safe_mode;
+        self
+    }
+
+    /// With this flag set, text between `$[[raw]]` and `$[[/raw]]` outside of params is left as
+    /// is, for embedding scripts which use `$` heavily themselves. A raw block which is never
+    /// closed is a syntax error, and raw blocks can't span lines when expanding line by line
+    /// using [`Xpanda::expand_lines`]. Off by default.
     ///
-    /// # Errors
+    /// # Examples
     ///
-    /// Returns [`Err`] if the given string is badly formatted and cannot be parsed.
+    /// ```
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::builder().raw_blocks(true).build();
+    /// let template = "$[[raw]]for f in $@; do echo \"${f%.txt}\"; done$[[/raw]]";
+    ///
+    /// assert_eq!(
+    ///     xpanda.expand(template),
+    ///     Ok(String::from("for f in $@; do echo \"${f%.txt}\"; done"))
+    /// );
+    /// ```
+    #[must_use]
+    pub const fn raw_blocks(mut self, raw_blocks: bool) -> Self {
+        self.syntax.raw_blocks = raw_blocks;
+        self
+    }
+
+    /// With this flag set, lines starting with `$#` are comments, which are left out of the
+    /// output together with their line break, so that templates can be annotated. The `$#` has
+    /// to be at the very start of the line, and takes precedence over the special parameter
+    /// `#` if it's recognized. A literal `$#` at the start of a line is written as `$$#`. Off by
+    /// default.
     ///
     /// # Examples
     ///
     /// ```
+    /// use std::collections::HashMap;
     /// use xpanda::Xpanda;
     ///
-    /// let xpanda = Xpanda::default();
-    /// assert_eq!(xpanda.expand("${1:-default}"), Ok(String::from("default")));
+    /// let xpanda = Xpanda::builder()
+    ///     .comments(true)
+    ///     .with_named_vars(HashMap::from([(String::from("PORT"), String::from("80"))]))
+    ///     .build();
+    /// let template = "$# The port is set by the deployment\nport: $PORT\n";
+    ///
+    /// assert_eq!(xpanda.expand(template), Ok(String::from("port: 80\n")));
     /// ```
-    pub fn expand(&self, input: &str) -> Result<String, Error> {
-        let lexer = Lexer::new(input);
-        let mut parser = Parser::new(lexer);
-        let ast = parser.parse()?;
-        let result = self.evaluator.eval(ast)?;
+    #[must_use]
+    pub const fn comments(mut self, comments: bool) -> Self {
+        self.syntax.comments = comments;
+        self
+    }
+
+    /// With this flag set, text between `${if VAR}` and `${end}` is only included if `VAR` is
+    /// set and non-empty, and text between `${else}` and `${end}` otherwise, so that whole
+    /// sections of a template can be included or left out. `VAR` is a named or positional
+    /// variable, which isn't an error if unset even with [`Builder::no_unset`]. Blocks can be
+    /// nested, but only appear outside of params. The line break directly following a tag is
+    /// left out, so that tags on lines of their own don't leave empty lines behind. Blocks
+    /// can't span lines when expanding line by line using [`Xpanda::expand_lines`]. Off by
+    /// default.
+    ///
+    /// With this flag set, `${else}` and `${end}` can't be used to reference variables named
+    /// `else` or `end`, but `$else` and `$end` still can.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .conditionals(true)
+    ///     .with_named_vars(HashMap::from([(String::from("CERT"), String::from("a.pem"))]))
+    ///     .build();
+    /// let template = "${if CERT}\nssl_certificate $CERT;\n${else}\nlisten 80;\n${end}\n";
+    ///
+    /// assert_eq!(xpanda.expand(template), Ok(String::from("ssl_certificate a.pem;\n")));
+    /// ```
+    #[must_use]
+    pub const fn conditionals(mut self, conditionals: bool) -> Self {
+        self.syntax.conditionals = conditionals;
+        self
+    }
+
+    /// With this flag set, the name of a braced param may contain params, which are expanded
+    /// first to compute the name of the variable, as in `${DB_URL_${ENV}}`. This allows
+    /// selecting one of several variables following a naming convention, such as
+    /// `DB_URL_PROD` and `DB_URL_STAGE`. The computed name must consist of only letters,
+    /// digits and underscores, and names a positional variable if it's only digits. Dynamic
+    /// names also work with other params, such as `${DB_URL_${ENV}:-localhost}` or
+    /// `${#DB_URL_${ENV}}`. Off by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .dynamic_names(true)
+    ///     .with_named_vars(HashMap::from([
+    ///         (String::from("ENV"), String::from("PROD")),
+    ///         (String::from("DB_URL_PROD"), String::from("db.prod")),
+    ///         (String::from("DB_URL_STAGE"), String::from("db.stage")),
+    ///     ]))
+    ///     .build();
+    ///
+    /// assert_eq!(xpanda.expand("${DB_URL_${ENV}}"), Ok(String::from("db.prod")));
+    /// ```
+    #[must_use]
+    pub const fn dynamic_names(mut self, dynamic_names: bool) -> Self {
+        self.syntax.dynamic_names = dynamic_names;
+        self
+    }
+
+    /// With this flag set, a default or alternative value starting with `=` is an expression
+    /// rather than text, as in `${REPLICAS:-=max(2, $CPU / 2)}`, and a value which should start
+    /// with a literal `=` is written as `$=` instead. Only available with the `expr` feature
+    /// enabled. Off by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .expressions(true)
+    ///     .with_named_vars(HashMap::from([(String::from("CPU"), String::from("8"))]))
+    ///     .build();
+    ///
+    /// assert_eq!(xpanda.expand("${REPLICAS:-=max(2, $CPU / 2)}"), Ok(String::from("4")));
+    /// assert_eq!(xpanda.expand("${OP:-$=}"), Ok(String::from("=")));
+    /// ```
+    #[cfg(feature = "expr")]
+    #[must_use]
+    pub const fn expressions(mut self, expressions: bool) -> Self {
+        self.syntax.expressions = expressions;
+        self
+    }
+
+    /// Sets the delimiters of opaque regions, such as `{{` and `}}` of Jinja or Helm templates.
+    /// Outside of params, everything from an opening delimiter up to and including the next
+    /// closing delimiter is text which is left as is, even if it contains `$`. An opening
+    /// delimiter without a matching closing delimiter is ordinary text. Regions can't span
+    /// lines when expanding line by line using [`Xpanda::expand_lines`].
+    ///
+    /// An opening delimiter which is empty or contains `$` disables opaque regions. Off by
+    /// default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .opaque_regions("{{", "}}")
+    ///     .with_named_vars(HashMap::from([(String::from("NAME"), String::from("app"))]))
+    ///     .build();
+    ///
+    /// assert_eq!(
+    ///     xpanda.expand("name: $NAME\nport: {{ $.Values.port }}"),
+    ///     Ok(String::from("name: app\nport: {{ $.Values.port }}"))
+    /// );
+    /// ```
+    #[must_use]
+    pub fn opaque_regions(mut self, open: impl Into<String>, close: impl Into<String>) -> Self {
+        let open = open.into();
+
+        self.syntax.opaque_regions = (!open.is_empty() && !open.contains('$')).then(|| {
+            Arc::new(OpaqueRegions {
+                open,
+                close: close.into(),
+            })
+        });
+        self
+    }
+
+    /// Sets how ANSI-C quoted text of shell scripts, as in `$'a\tb'`, is read, which is
+    /// otherwise a syntax error. With [`AnsiCQuotes::Interpret`] the text is unquoted like Bash
+    /// does, interpreting escape sequences such as `\n`, `\t`, `\xHH` and `\uHHHH`, while with
+    /// [`AnsiCQuotes::Keep`] it's left as is, such as for templates of shell scripts. Quoted
+    /// text may appear outside of params or as the value of a param, as in `${VAR:-$'\t'}`, and
+    /// must end on the line it starts on. Unknown escape sequences are left as is. Defaults to
+    /// [`AnsiCQuotes::Error`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::{AnsiCQuotes, Xpanda};
+    ///
+    /// let xpanda = Xpanda::builder().ansi_c_quotes(AnsiCQuotes::Interpret).build();
+    /// assert_eq!(xpanda.expand(r"a$'\t\x62\u00e9'"), Ok(String::from("a\tbé")));
+    ///
+    /// let xpanda = Xpanda::builder().ansi_c_quotes(AnsiCQuotes::Keep).build();
+    /// assert_eq!(xpanda.expand(r"IFS=$'\n'"), Ok(String::from(r"IFS=$'\n'")));
+    /// ```
+    #[must_use]
+    pub const fn ansi_c_quotes(mut self, ansi_c_quotes: AnsiCQuotes) -> Self {
+        self.syntax.ansi_c_quotes = ansi_c_quotes;
+        self
+    }
+
+    /// Adds all environment variables as named variables. The environment is captured when this
+    /// method is called, so later changes to it are not reflected when expanding. See
+    /// [`Builder::with_live_env`] for resolving variables from the environment when expanding
+    /// instead.
+    ///
+    /// Named variables added later take precedence over those added earlier, so calling this
+    /// method after [`Builder::with_named_vars`] lets the environment override the given
+    /// values, and vice versa.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::env;
+    /// use xpanda::Xpanda;
+    ///
+    /// env::set_var("XPANDA_SNAPSHOT_EXAMPLE", "before");
+    /// let xpanda = Xpanda::builder().with_env_vars().build();
+    /// env::set_var("XPANDA_SNAPSHOT_EXAMPLE", "after");
+    ///
+    /// assert_eq!(
+    ///     xpanda.expand("$XPANDA_SNAPSHOT_EXAMPLE"),
+    ///     Ok(String::from("before"))
+    /// );
+    /// ```
+    #[must_use]
+    pub fn with_env_vars(self) -> Self {
+        self.with_env_vars_matching(|_| true)
+    }
+
+    /// Adds the environment variables whose names match the given filter as named variables,
+    /// such as to only expose variables with a known prefix to a template. Otherwise the same as
+    /// [`Builder::with_env_vars`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::env;
+    /// use xpanda::Xpanda;
+    ///
+    /// env::set_var("XPANDA_MATCHING_APP_NAME", "app");
+    /// env::set_var("XPANDA_MATCHING_TOKEN", "secret");
+    /// let xpanda = Xpanda::builder()
+    ///     .with_env_vars_matching(|name| name.starts_with("XPANDA_MATCHING_APP_"))
+    ///     .build();
+    ///
+    /// assert_eq!(
+    ///     xpanda.expand("$XPANDA_MATCHING_APP_NAME ${XPANDA_MATCHING_TOKEN-unset}"),
+    ///     Ok(String::from("app unset"))
+    /// );
+    /// ```
+    #[must_use]
+    pub fn with_env_vars_matching<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&str) -> bool,
+    {
+        let mut vars = Vec::new();
+
+        for (key, value) in env::vars_os() {
+            let (key, value) = match (key.into_string(), value.into_string()) {
+                (Ok(key), Ok(value)) => (key, value),
+                (key, value) => {
+                    let lossy = |string: Result<String, OsString>| {
+                        string.unwrap_or_else(|string| string.to_string_lossy().into_owned())
+                    };
+                    let key = lossy(key);
+                    self.not_utf8_env.insert(key.clone());
+                    (key, lossy(value))
+                },
+            };
+
+            if filter(&key) {
+                vars.push((key, value));
+            }
+        }
+
+        // Sorted so that names differing only in casing resolve the same way on every platform
+        vars.sort();
+
+        let env_names = Arc::make_mut(&mut self.env_names);
+        let named_sources = Arc::make_mut(&mut self.named_sources);
+        let named_vars = Arc::make_mut(&mut self.named_vars);
+
+        for (key, value) in vars {
+            env_names
+                .entry(key.to_uppercase())
+                .or_insert_with(|| key.clone());
+            named_sources.insert(key.clone(), Source::Env);
+
+            if let Some(previous) = named_vars.insert(key, value) {
+                wipe(previous);
+            }
+        }
+
+        self
+    }
+
+    /// With this flag set, named variables which aren't otherwise provided are resolved from the
+    /// environment of the process each time a template is expanded, so changes to the
+    /// environment are reflected without building a new instance. This is useful for long-lived
+    /// processes. Named variables provided using the builder always take precedence. Off by
+    /// default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::env;
+    /// use xpanda::Xpanda;
+    ///
+    /// env::set_var("XPANDA_LIVE_EXAMPLE", "before");
+    /// let xpanda = Xpanda::builder().with_live_env(true).build();
+    /// env::set_var("XPANDA_LIVE_EXAMPLE", "after");
+    ///
+    /// assert_eq!(
+    ///     xpanda.expand("$XPANDA_LIVE_EXAMPLE"),
+    ///     Ok(String::from("after"))
+    /// );
+    /// ```
+    #[must_use]
+    pub const fn with_live_env(mut self, live_env: bool) -> Self {
+        self.live_env = live_env;
+        self
+    }
+
+    /// With this flag set, environment variables are matched regardless of the casing of their
+    /// names, so that `$path` resolves to the environment variable `Path`. An exact match is
+    /// always preferred. This applies to environment variables added using
+    /// [`Builder::with_env_vars`] or resolved using [`Builder::with_live_env`], but not to other
+    /// named variables. If several environment variables match, the one whose name sorts first
+    /// is used. On by default on Windows, where names of environment variables are
+    /// case-insensitive, and off by default elsewhere, unless [`Builder::reproducible`] is set.
+    #[must_use]
+    pub const fn case_insensitive_env(mut self, case_insensitive_env: bool) -> Self {
+        self.case_insensitive_env = Some(case_insensitive_env);
+        self
+    }
+
+    /// With this flag set, the output depends solely on the template and the variables, and is
+    /// byte-identical on every platform. Environment variables are then matched case-sensitively
+    /// on Windows too, unless [`Builder::case_insensitive_env`] is set explicitly. Nothing else
+    /// of the expansion depends on the platform, the locale or the time. Off by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::env;
+    /// use xpanda::Xpanda;
+    ///
+    /// env::set_var("XPANDA_REPRODUCIBLE_EXAMPLE", "value");
+    /// let xpanda = Xpanda::builder().reproducible(true).with_env_vars().build();
+    ///
+    /// assert_eq!(
+    ///     xpanda.expand("${xpanda_reproducible_example-unset}"),
+    ///     Ok(String::from("unset"))
+    /// );
+    /// ```
+    #[must_use]
+    pub const fn reproducible(mut self, reproducible: bool) -> Self {
+        self.reproducible = reproducible;
+        self
+    }
+
+    /// Adds the given map values as named variables.
+    #[must_use]
+    pub fn with_named_vars(self, vars: HashMap<String, String>) -> Self {
+        self.with_vars_from(&Source::Named(None), vars)
+    }
+
+    /// Adds the given map values as named variables, labeled to tell where they come from, e.g.
+    /// the file they were read from. See [`Xpanda::sources`].
+    #[must_use]
+    pub fn with_labeled_vars(
+        self,
+        label: impl Into<String>,
+        vars: HashMap<String, String>,
+    ) -> Self {
+        self.with_vars_from(&Source::Named(Some(label.into())), vars)
+    }
+
+    /// Adds the given map values as named variables which are secret. Their values are wiped
+    /// from memory once the [`Xpanda`] instance, its clones and the builders sharing its
+    /// variables are all dropped, and so are the copies of them made while expanding, other than
+    /// the output itself. Outputs cached by a [`CachedXpanda`] are wiped when they are evicted
+    /// from the cache.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .with_secret_vars(HashMap::from([(String::from("TOKEN"), String::from("hunter2"))]))
+    ///     .build();
+    ///
+    /// assert_eq!(xpanda.expand("token=$TOKEN"), Ok(String::from("token=hunter2")));
+    /// ```
+    #[cfg(feature = "zeroize")]
+    #[must_use]
+    pub fn with_secret_vars(mut self, vars: HashMap<String, String>) -> Self {
+        Arc::make_mut(&mut self.named_vars).add_secret(vars.keys().cloned());
+        self.with_named_vars(vars)
+    }
+
+    fn with_vars_from(mut self, source: &Source, vars: HashMap<String, String>) -> Self {
+        let named_sources = Arc::make_mut(&mut self.named_sources);
+        let named_vars = Arc::make_mut(&mut self.named_vars);
+
+        for (key, value) in vars {
+            self.not_utf8_env.remove(&key);
+            named_sources.insert(key.clone(), source.clone());
+
+            if let Some(previous) = named_vars.insert(key, value) {
+                wipe(previous);
+            }
+        }
+
+        self
+    }
+
+    /// Adds the given strings as positional variables.
+    #[must_use]
+    pub fn with_positional_vars(mut self, vars: Vec<String>) -> Self {
+        Arc::make_mut(&mut self.positional_vars).extend(vars);
+        self
+    }
+
+    /// Removes all positional variables, such as to bind those of a template.
+    pub(crate) fn without_positional_vars(mut self) -> Self {
+        self.positional_vars = Arc::default();
+        self
+    }
+
+    /// Adds positional variables read from the given iterator, such as a large generated list
+    /// of arguments, following any positional variables added before.
+    ///
+    /// Values are only read from the iterator once a positional variable at or beyond their
+    /// index is referenced, and are kept once read, so referencing the first few positional
+    /// variables doesn't read the rest. Referencing `$0`, or `${#}` if the iterator doesn't
+    /// report its exact length through [`Iterator::size_hint`], reads all values. So does adding
+    /// positional variables to a builder created by [`Xpanda::to_builder`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .with_positional_vars(vec![String::from("first")])
+    ///     .with_positional_iter((2..=1_000_000).map(|index| format!("arg{index}")))
+    ///     .build();
+    ///
+    /// assert_eq!(xpanda.expand("$1 $2 $3"), Ok(String::from("first arg2 arg3")));
+    /// # #[cfg(feature = "length")]
+    /// assert_eq!(xpanda.expand("${#}"), Ok(String::from("1000000")));
+    /// ```
+    #[must_use]
+    pub fn with_positional_iter<I>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = String>,
+        I::IntoIter: Send + 'static,
+    {
+        Arc::make_mut(&mut self.positional_vars).extend_lazy(vars);
+        self
+    }
+
+    /// Adds values for special parameters, such as `$?` for the exit status of the last command
+    /// when expanding text captured from a shell. The special parameters `$?`, `$$`, `$#`,
+    /// `$*`, `$@`, `$!` and `$-` are supported, other characters are ignored.
+    ///
+    /// Special parameters are only recognized once a value has been added for them, and only
+    /// in their unbraced form. Once a value for `$` has been added, `$$` outside of params
+    /// refers to it rather than being an escaped `$`. A literal `$` can then still be written
+    /// as `$$` inside a param, or inside a raw block if [`Builder::raw_blocks`] is enabled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .raw_blocks(true)
+    ///     .with_special_vars(HashMap::from([
+    ///         ('?', String::from("0")),
+    ///         ('$', String::from("42")),
+    ///     ]))
+    ///     .build();
+    ///
+    /// assert_eq!(
+    ///     xpanda.expand("pid $$ exited with $?"),
+    ///     Ok(String::from("pid 42 exited with 0"))
+    /// );
+    /// assert_eq!(xpanda.expand("${VAR:-$?}"), Ok(String::from("0")));
+    /// assert_eq!(xpanda.expand("$[[raw]]$$[[/raw]]5"), Ok(String::from("$5")));
+    /// ```
+    #[must_use]
+    pub fn with_special_vars(mut self, vars: HashMap<char, String>) -> Self {
+        for (c, value) in vars {
+            if let Some(index) = SPECIAL_VARS.find(c) {
+                self.syntax.special_vars |= 1 << index;
+                Arc::make_mut(&mut self.special_vars).insert(c, value);
+            }
+        }
+
+        self
+    }
+
+    /// Adds positional variables sourced from environment variables named by the given prefix
+    /// followed by an index, e.g. `XPANDA_ARG_1`, `XPANDA_ARG_2` and so on for the prefix
+    /// `XPANDA_ARG_`. See [`Builder::with_prefixed_positional_vars`] for details.
+    #[must_use]
+    pub fn with_positional_env_vars(self, prefix: &str) -> Self {
+        self.with_prefixed_positional_vars(prefix, env::vars())
+    }
+
+    /// Adds positional variables sourced from the given named variables (such as a snapshot
+    /// of the environment) which are named by the given prefix followed by an index starting
+    /// at 1. Indexes must be contiguous, the first missing index ends the positional variables.
+    /// Indexes with leading zeros, as in `ARG_01`, are ignored, so that each index is named by
+    /// exactly one variable.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::Xpanda;
+    ///
+    /// let vars = vec![
+    ///     (String::from("ARG_2"), String::from("second")),
+    ///     (String::from("ARG_1"), String::from("first")),
+    ///     (String::from("ARG_4"), String::from("ignored")),
+    /// ];
+    /// let xpanda = Xpanda::builder()
+    ///     .with_prefixed_positional_vars("ARG_", vars)
+    ///     .build();
+    ///
+    /// assert_eq!(xpanda.expand("$1 $2 ${3-none}"), Ok(String::from("first second none")));
+    /// ```
+    #[must_use]
+    pub fn with_prefixed_positional_vars(
+        mut self,
+        prefix: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Self {
+        let mut indexed = vars
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let suffix = key.strip_prefix(prefix)?;
+
+                if !suffix.starts_with('0') && suffix.bytes().all(|byte| byte.is_ascii_digit()) {
+                    Some((suffix.parse::<usize>().ok()?, value))
+                } else {
+                    None
+                }
+            })
+            .collect::<HashMap<_, _>>();
+
+        Arc::make_mut(&mut self.positional_vars)
+            .extend((1..).map_while(|index| indexed.remove(&index)));
+        self
+    }
+
+    /// Names the positional variables in order, such that the first name resolves as `$1`, the
+    /// second as `$2` and so on, so templates can be readable while the values are still given
+    /// positionally. Replaces any names given before.
+    ///
+    /// A name resolves as its positional variable if that is set. Otherwise, such as if fewer
+    /// positional variables are given than names, it resolves like any other named variable.
+    /// A named variable of the same name is thus only used as a fallback. If a name is given
+    /// more than once, its first position applies.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .map_positional_names(vec!["HOST", "PORT"])
+    ///     .with_positional_vars(vec![String::from("localhost")])
+    ///     .with_named_vars(HashMap::from([
+    ///         (String::from("HOST"), String::from("example.com")),
+    ///         (String::from("PORT"), String::from("80")),
+    ///     ]))
+    ///     .build();
+    ///
+    /// assert_eq!(xpanda.expand("$HOST:$PORT"), Ok(String::from("localhost:80")));
+    /// ```
+    #[must_use]
+    pub fn map_positional_names(
+        mut self,
+        names: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        let mut positional_names = HashMap::new();
+
+        for (index, name) in names.into_iter().enumerate() {
+            positional_names.entry(name.into()).or_insert(index);
+        }
+
+        self.positional_names = Arc::new(positional_names);
+        self
+    }
+
+    /// Adds aliases for named variables, mapping the name used in templates to the name of the
+    /// variable to resolve it as instead. This makes it possible to reuse templates written for
+    /// other variable names without editing them.
+    ///
+    /// An aliased name always resolves as its target, even if a variable with the aliased name
+    /// itself is set. Aliases are not followed transitively. Error messages and value transforms
+    /// refer to variables by the name used in the template.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::Xpanda;
+    ///
+    /// let mut named_vars = HashMap::new();
+    /// named_vars.insert(String::from("DATABASE_URL"), String::from("postgres://db"));
+    /// let mut aliases = HashMap::new();
+    /// aliases.insert(String::from("DB_URL"), String::from("DATABASE_URL"));
+    /// let xpanda = Xpanda::builder()
+    ///     .with_named_vars(named_vars)
+    ///     .with_aliases(aliases)
+    ///     .build();
+    ///
+    /// assert_eq!(xpanda.expand("$DB_URL"), Ok(String::from("postgres://db")));
+    /// ```
+    #[must_use]
+    pub fn with_aliases(mut self, aliases: HashMap<String, String>) -> Self {
+        Arc::make_mut(&mut self.aliases).extend(aliases);
+        self
+    }
+
+    /// Registers a [`Provider`] to resolve namespaced variables with, such as `${env:HOME}` for
+    /// the namespace `env`. Everything following the colon up until the closing brace, or up
+    /// until a `:-`, `:+` or `:?` as in `${env:HOME:-/root}`, is passed to the provider as the
+    /// key. Registering a namespace a second time replaces the previous provider.
+    ///
+    /// Namespaced variables are only recognized in their braced form, and only if the namespace
+    /// contains no uppercase letters. Referencing a namespace without a registered provider
+    /// yields an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .register_namespace("upper", |key: &str| Ok(Some(key.to_uppercase())))
+    ///     .build();
+    ///
+    /// assert_eq!(xpanda.expand("${upper:woop}"), Ok(String::from("WOOP")));
+    /// ```
+    #[must_use]
+    pub fn register_namespace(
+        mut self,
+        namespace: impl Into<String>,
+        provider: impl Provider + 'static,
+    ) -> Self {
+        self.providers.insert(namespace.into(), Arc::new(provider));
+        self
+    }
+
+    /// Enables context variables such as `${xpanda:line}`, which describe the expansion itself
+    /// rather than being provided by the caller. See [`Context`] for the available variables.
+    /// Off by default.
+    #[must_use]
+    pub fn with_context(mut self, context: Context) -> Self {
+        self.context = Some(context);
+        self
+    }
+
+    /// Adds a function which is invoked with the name and value of every variable which is
+    /// successfully resolved, returning the value to use in its place. This makes it possible
+    /// to apply global transformations such as trimming, encoding or auditing of values.
+    ///
+    /// Positional variables are named by their index. The transform is applied before any
+    /// modifiers, and before values are checked for emptiness (as in `${VAR:-default}`).
+    /// Values used as names in `${!VAR}` are not transformed, but the value they refer to is.
+    ///
+    /// This method can be called multiple times, in which case the transforms are applied in
+    /// the order they were added, each receiving the output of the previous one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::Xpanda;
+    ///
+    /// let mut named_vars = HashMap::new();
+    /// named_vars.insert(String::from("VAR"), String::from("  value  "));
+    /// let xpanda = Xpanda::builder()
+    ///     .with_named_vars(named_vars)
+    ///     .value_transform(|_name, value| value.trim().to_string())
+    ///     .value_transform(|name, value| format!("{name}={value}"))
+    ///     .build();
+    ///
+    /// assert_eq!(xpanda.expand("$VAR"), Ok(String::from("VAR=value")));
+    /// ```
+    #[must_use]
+    pub fn value_transform<F>(mut self, transform: F) -> Self
+    where
+        F: Fn(&str, &str) -> String + Send + Sync + 'static,
+    {
+        self.value_transforms.push(Arc::new(transform));
+        self
+    }
+
+    /// Adds a function which is invoked whenever an unset variable is encountered, regardless of
+    /// whether the param referencing it has a default value. The [`UnsetEvent`] describes the
+    /// variable, where it's referenced and how the param was resolved. This makes it possible to
+    /// collect metrics on which defaults are actually used.
+    ///
+    /// Variables with empty values are also reported for patterns which treat them as unset, such
+    /// as `${VAR:-default}`. This method can be called multiple times, in which case all functions
+    /// are invoked in the order they were added.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use xpanda::{UnsetOutcome, Xpanda};
+    ///
+    /// let events = Arc::new(Mutex::new(Vec::new()));
+    /// let recorded = Arc::clone(&events);
+    /// let xpanda = Xpanda::builder()
+    ///     .on_unset(move |event| {
+    ///         recorded.lock().unwrap().push((event.name.clone(), event.outcome));
+    ///     })
+    ///     .build();
+    ///
+    /// assert_eq!(xpanda.expand("$A ${B:-default}"), Ok(String::from(" default")));
+    /// assert_eq!(
+    ///     *events.lock().unwrap(),
+    ///     vec![
+    ///         (String::from("A"), UnsetOutcome::Empty),
+    ///         (String::from("B"), UnsetOutcome::Default),
+    ///     ]
+    /// );
+    /// ```
+    #[must_use]
+    pub fn on_unset<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(&UnsetEvent) + Send + Sync + 'static,
+    {
+        self.unset_listeners.push(Arc::new(listener));
+        self
+    }
+
+    /// Registers a listener invoked whenever a set variable is resolved while expanding, telling
+    /// where its value came from, such as to debug the precedence of the provided variables.
+    /// Listeners are invoked in the order they were registered. See [`Xpanda::sources`] for
+    /// finding the sources without expanding.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use std::sync::{Arc, Mutex};
+    /// use xpanda::{Source, Xpanda};
+    ///
+    /// let events = Arc::new(Mutex::new(Vec::new()));
+    /// let recorded = Arc::clone(&events);
+    /// let xpanda = Xpanda::builder()
+    ///     .with_positional_vars(vec![String::from("one")])
+    ///     .with_labeled_vars("app.env", HashMap::from([(String::from("A"), String::from("a"))]))
+    ///     .on_resolve(move |event| {
+    ///         recorded.lock().unwrap().push((event.name.clone(), event.source.clone()));
+    ///     })
+    ///     .build();
+    ///
+    /// assert_eq!(xpanda.expand("$1 $A $B"), Ok(String::from("one a ")));
+    /// assert_eq!(
+    ///     *events.lock().unwrap(),
+    ///     vec![
+    ///         (String::from("1"), Source::Positional),
+    ///         (String::from("A"), Source::Named(Some(String::from("app.env")))),
+    ///     ]
+    /// );
+    /// ```
+    #[must_use]
+    pub fn on_resolve<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(&ResolveEvent) + Send + Sync + 'static,
+    {
+        self.resolve_listeners.push(Arc::new(listener));
+        self
+    }
+
+    /// Registers a listener invoked whenever a likely mistake in a template is noticed while
+    /// expanding it, such as a reference to a positional variable beyond the number of positional
+    /// variables given. Unlike errors, warnings don't stop the expansion. Listeners are invoked
+    /// in the order they were registered. See [`WarningKind`] for the possible warnings.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use xpanda::{WarningKind, Xpanda};
+    ///
+    /// let warnings = Arc::new(Mutex::new(Vec::new()));
+    /// let recorded = Arc::clone(&warnings);
+    /// let xpanda = Xpanda::builder()
+    ///     .with_positional_vars(vec![String::from("one")])
+    ///     .on_warning(move |warning| {
+    ///         recorded.lock().unwrap().push((warning.kind, warning.position.col));
+    ///     })
+    ///     .build();
+    ///
+    /// assert_eq!(xpanda.expand("$1 $2 ${3-none}"), Ok(String::from("one  none")));
+    /// assert_eq!(
+    ///     *warnings.lock().unwrap(),
+    ///     vec![(WarningKind::PositionalOutOfRange { index: 2, count: 1 }, 4)]
+    /// );
+    /// ```
+    #[must_use]
+    pub fn on_warning<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(&Warning) + Send + Sync + 'static,
+    {
+        self.warning_listeners.push(Arc::new(listener));
+        self
+    }
+
+    /// Registers a listener invoked whenever a phase of expanding a template is finished,
+    /// telling how long it took, such as to find templates which are slow to expand. Parsing
+    /// reports the [`Phase::Lex`] and [`Phase::Parse`] phases, and rendering the [`Phase::Eval`]
+    /// phase. Since lexing is interleaved with parsing, it's measured by lexing the text once
+    /// more on its own, which is why expanding is slower when a listener is registered.
+    /// Listeners are invoked in the order they were registered.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use xpanda::{Phase, Xpanda};
+    ///
+    /// let phases = Arc::new(Mutex::new(Vec::new()));
+    /// let recorded = Arc::clone(&phases);
+    /// let xpanda = Xpanda::builder()
+    ///     .on_timing(move |timing| recorded.lock().unwrap().push(timing.phase))
+    ///     .build();
+    ///
+    /// assert_eq!(xpanda.expand("${VAR:-default}"), Ok(String::from("default")));
+    /// assert_eq!(*phases.lock().unwrap(), vec![Phase::Lex, Phase::Parse, Phase::Eval]);
+    /// ```
+    #[must_use]
+    pub fn on_timing<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(&Timing) + Send + Sync + 'static,
+    {
+        self.timing_listeners.push(Arc::new(listener));
+        self
+    }
+
+    /// Sets a policy deciding, by name, whether each variable may be resolved while rendering,
+    /// replacing any previous policy. Named variables are passed with aliases followed, positional
+    /// variables by their index and namespaced variables as `namespace:key`. See [`Access`] for
+    /// the possible outcomes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::{Access, ErrorKind, Xpanda};
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .with_named_vars(HashMap::from([
+    ///         (String::from("PUBLIC_NAME"), String::from("app")),
+    ///         (String::from("TOKEN"), String::from("secret")),
+    ///     ]))
+    ///     .access_policy(|name| {
+    ///         if name.starts_with("PUBLIC_") {
+    ///             Access::Allow
+    ///         } else {
+    ///             Access::Deny
+    ///         }
+    ///     })
+    ///     .build();
+    ///
+    /// assert_eq!(xpanda.expand("$PUBLIC_NAME"), Ok(String::from("app")));
+    /// assert_eq!(xpanda.expand("$TOKEN").unwrap_err().kind, ErrorKind::Policy);
+    /// ```
+    #[must_use]
+    pub fn access_policy<F>(mut self, policy: F) -> Self
+    where
+        F: Fn(&str) -> Access + Send + Sync + 'static,
+    {
+        self.access_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Attaches a [`Schema`] describing the expected variables. Before expanding, required
+    /// variables of the schema are checked to be set, and the values of variables referenced by
+    /// the template are checked against their constraints. See [`Xpanda::validate`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::{ErrorKind, Schema, VarSchema, Xpanda};
+    ///
+    /// let schema = Schema::new().var(VarSchema::new("LEVEL").one_of(["debug", "info"]));
+    /// let xpanda = Xpanda::builder()
+    ///     .schema(schema)
+    ///     .with_named_vars(HashMap::from([(String::from("LEVEL"), String::from("all"))]))
+    ///     .build();
+    ///
+    /// let error = xpanda.expand("level=$LEVEL").unwrap_err();
+    /// assert_eq!(error.kind, ErrorKind::InvalidValue);
+    /// assert_eq!((error.line, error.col), (1, 7));
+    /// ```
+    #[must_use]
+    pub fn schema(mut self, schema: Schema) -> Self {
+        self.schema = schema;
+        self
+    }
+
+    /// Sets the [`PatternEngine`] compiling the patterns matched against values, replacing any
+    /// previous engine. Defaults to [`GlobEngine`]. With the `regex` feature enabled, a pattern
+    /// enclosed in tildes is a regular expression regardless of the engine.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::{GlobEngine, Xpanda};
+    ///
+    /// let xpanda = Xpanda::builder().pattern_engine(GlobEngine).build();
+    /// ```
+    #[must_use]
+    pub fn pattern_engine<E>(mut self, engine: E) -> Self
+    where
+        E: PatternEngine + 'static,
+    {
+        self.pattern_engine = Some(Arc::new(engine));
+        self
+    }
+
+    /// Sets the [`CaseLocale`] whose language specific rules case modifiers such as `${VAR^^}`
+    /// follow, instead of the default Unicode case mapping. Only available with the `locale`
+    /// feature enabled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .case_locale("tr".parse().unwrap())
+    ///     .with_named_vars(HashMap::from([(String::from("CITY"), String::from("istanbul"))]))
+    ///     .build();
+    ///
+    /// assert_eq!(xpanda.expand("${CITY^^}"), Ok(String::from("İSTANBUL")));
+    /// ```
+    #[cfg(feature = "locale")]
+    #[must_use]
+    pub fn case_locale(mut self, locale: CaseLocale) -> Self {
+        self.case_mapping = CaseMapping::new(locale);
+        self
+    }
+
+    /// With this flag set, named variables which can never be referenced by a template are
+    /// rejected by [`Builder::try_build`], rather than being silently unused. That's the case
+    /// for names which are empty, contain characters other than letters, digits and
+    /// underscores such as spaces, consist of only digits, or start with a digit without
+    /// [`Builder::lenient_identifiers`]. Environment variables added using
+    /// [`Builder::with_env_vars`] whose name or value isn't valid UTF-8 are rejected as well,
+    /// rather than being converted lossily. Off by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::{InvalidVarReason, Xpanda};
+    ///
+    /// let invalid = Xpanda::builder()
+    ///     .validate_vars(true)
+    ///     .with_named_vars(HashMap::from([
+    ///         (String::from("DB URL"), String::from("db")),
+    ///         (String::from("PORT"), String::from("80")),
+    ///     ]))
+    ///     .try_build()
+    ///     .err()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(invalid.len(), 1);
+    /// assert_eq!(invalid[0].name, "DB URL");
+    /// assert_eq!(invalid[0].reason, InvalidVarReason::InvalidChar(' '));
+    /// ```
+    #[must_use]
+    pub const fn validate_vars(mut self, validate_vars: bool) -> Self {
+        self.validate_vars = validate_vars;
+        self
+    }
+
+    /// Builds a new [`Xpanda`] instance. Only [`Builder::try_build`] rejects invalid named
+    /// variables if [`Builder::validate_vars`] is set, here they're silently unused.
+    #[must_use]
+    pub fn build(self) -> Xpanda {
+        Xpanda::new(self)
+    }
+
+    /// Builds a new [`Xpanda`] instance, unless [`Builder::validate_vars`] is set and any named
+    /// variable is invalid, in which case all invalid variables are returned, sorted by name.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] listing the invalid variables if [`Builder::validate_vars`] is set and
+    /// any named variable is invalid.
+    pub fn try_build(self) -> Result<Xpanda, Vec<InvalidVar>> {
+        if self.validate_vars {
+            let invalid = invalid_vars(
+                &self.named_vars,
+                &self.named_sources,
+                &self.not_utf8_env,
+                &self.syntax,
+            );
+
+            if !invalid.is_empty() {
+                return Err(invalid);
+            }
+        }
+
+        Ok(Xpanda::new(self))
+    }
+}
+
+/// [`Xpanda`] substitutes the values of variables in strings similar to [`envsubst`] and
+/// [`Bash parameter expansion`].
+///
+/// An instance is [`Send`] and [`Sync`], which is why providers, listeners and other callbacks
+/// given to the [`Builder`] must be too, so a single instance can expand any number of templates
+/// concurrently.
+///
+/// Cloning an instance is cheap, as clones share their variables, providers and callbacks. To
+/// derive an instance with different variables, such as a base configuration with overrides per
+/// tenant, use [`Xpanda::to_builder`].
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::HashMap;
+/// use std::thread;
+/// use xpanda::Xpanda;
+///
+/// let xpanda = Xpanda::builder()
+///     .with_named_vars(HashMap::from([(String::from("NAME"), String::from("world"))]))
+///     .build();
+///
+/// let outputs = thread::scope(|scope| {
+///     let threads = ["Hello $NAME", "Bye $NAME"]
+///         .map(|template| scope.spawn(|| xpanda.expand(template).unwrap()));
+///     threads.map(|thread| thread.join().unwrap())
+/// });
+///
+/// assert_eq!(outputs, ["Hello world", "Bye world"]);
+/// ```
+///
+/// [`envsubst`]: https://www.gnu.org/software/gettext/manual/html_node/envsubst-Invocation.html
+/// [`Bash parameter expansion`]: https://www.gnu.org/software/bash/manual/html_node/Shell-Parameter-Expansion.html
+#[derive(Clone, Default)]
+pub struct Xpanda {
+    evaluator: Evaluator,
+    schema: Schema,
+    syntax: Syntax,
+}
+
+impl Xpanda {
+    fn new(mut builder: Builder) -> Self {
+        if builder.syntax.powershell_env {
+            builder
+                .providers
+                .entry(String::from("env"))
+                .or_insert_with(|| Arc::new(EnvProvider));
+        }
+
+        Self {
+            schema: builder.schema,
+            evaluator: Evaluator::new(
+                builder.no_unset,
+                builder.unset_overrides,
+                builder.no_empty,
+                builder.syntax.safe_mode,
+                builder.length_mode,
+                builder.max_value_length,
+                builder.context,
+                builder.positional_vars,
+                builder.positional_names,
+                builder.special_vars,
+                builder.named_vars,
+                builder.named_sources,
+                EnvLookup {
+                    live: builder.live_env,
+                    case_insensitive: builder
+                        .case_insensitive_env
+                        .unwrap_or(cfg!(windows) && !builder.reproducible),
+                    reproducible: builder.reproducible,
+                    names: builder.env_names,
+                },
+                builder.aliases,
+                builder.providers,
+                builder.value_transforms,
+                builder.unset_listeners,
+                builder.resolve_listeners,
+                builder.warning_listeners,
+                builder.timing_listeners,
+                builder.access_policy,
+                builder.pattern_engine,
+                builder.case_mapping,
+            ),
+            syntax: builder.syntax,
+        }
+    }
+
+    #[must_use]
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    /// Creates a builder with the configuration, variables and callbacks of this instance, such
+    /// as to build an instance with some variables added or overridden. The variables are shared
+    /// with this instance rather than copied, until the builder changes them.
+    ///
+    /// Whether environment variables are matched case-insensitively is carried over as
+    /// resolved, see [`Builder::case_insensitive_env`]. Providers share their metrics with this
+    /// instance only until the builder builds a new one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::Xpanda;
+    ///
+    /// let base = Xpanda::builder()
+    ///     .no_unset(true)
+    ///     .with_named_vars(HashMap::from([
+    ///         (String::from("HOST"), String::from("example.com")),
+    ///         (String::from("TENANT"), String::from("none")),
+    ///     ]))
+    ///     .build();
+    /// let tenant = base
+    ///     .to_builder()
+    ///     .with_named_vars(HashMap::from([(String::from("TENANT"), String::from("acme"))]))
+    ///     .build();
+    ///
+    /// assert_eq!(base.expand("$TENANT.$HOST"), Ok(String::from("none.example.com")));
+    /// assert_eq!(tenant.expand("$TENANT.$HOST"), Ok(String::from("acme.example.com")));
+    /// assert!(tenant.expand("$UNSET").is_err());
+    /// ```
+    #[must_use]
+    pub fn to_builder(&self) -> Builder {
+        self.evaluator
+            .to_builder(self.schema.clone(), self.syntax.clone())
+    }
+
+    /// Expands the given text by substituting the values of the variables inside it.
+    ///
+    /// Variables can appear in any of the following forms:
+    ///
+    /// <table>
+    ///   <thead>
+    ///     <tr>
+    ///       <th>Pattern</th>
+    ///       <th>Description</th>
+    ///     </tr>
+    ///   </thead>
+    ///   <tbody>
+    ///     <tr>
+    ///       <td>$VAR</td>
+    ///       <td>substituted with the corresponding value for 'VAR' if set, otherwise "".</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR}</td>
+    ///       <td>substituted with the corresponding value for 'VAR' if set, otherwise "".</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR-default}</td>
+    ///       <td>
+    ///         substituted with the corresponding value for 'VAR' if set, otherwise "default".
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR:-default}</td>
+    ///       <td>
+    ///         substituted with the corresponding value for 'VAR' if set and non-empty, otherwise
+    ///         "default".
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR+alternative}</td>
+    ///       <td>
+    ///         substituted with "alternative" if the corresponding value for 'VAR' is set,
+    ///         otherwise "".
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR:+alternative}</td>
+    ///       <td>
+    ///         substituted with "alternative" if the corresponding value for 'VAR' is set and
+    ///         non-empty, otherwise "".
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR?}</td>
+    ///       <td>
+    ///         substituted with the corresponding value for 'VAR' if set, otherwise yields an
+    ///         error.
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR?error}</td>
+    ///       <td>
+    ///         substituted with the corresponding value for 'VAR' if set, otherwise yields an
+    ///         error with the given message (in this case "error").
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR?error}</td>
+    ///       <td>
+    ///         substituted with the corresponding value for 'VAR' if set and non-empty, otherwise
+    ///         yields an error with the given message (in this case "error").
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${#VAR}</td>
+    ///       <td>
+    ///         substituted with the length of the corresponding value for 'VAR' if set, otherwise
+    ///         "0". The length is counted in bytes by default.
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR^}</td>
+    ///       <td>
+    ///         substituted with the value of the variable named by the value of `VAR`, with the
+    ///         first character uppercased.
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR^^}</td>
+    ///       <td>
+    ///         substituted with the value of the variable named by the value of `VAR`, with all
+    ///         characters uppercased.
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR,}</td>
+    ///       <td>
+    ///         substituted with the value of the variable named by the value of `VAR`, with the
+    ///         first character lowercased.
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR,,}</td>
+    ///       <td>
+    ///         substituted with the value of the variable named by the value of `VAR`, with all
+    ///         characters lowercased.
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR~}</td>
+    ///       <td>
+    ///         substituted with the value of the variable named by the value of `VAR`, with the
+    ///         casing of the first character reversed.
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR~~}</td>
+    ///       <td>
+    ///         substituted with the value of the variable named by the value of `VAR`, with the
+    ///         casing of all characters reversed.
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${ns:key}</td>
+    ///       <td>
+    ///         substituted with the value for 'key' from the provider registered for the
+    ///         namespace 'ns' (see [`Builder::register_namespace`]) if set, otherwise "".
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR@urlencode}</td>
+    ///       <td>
+    ///         substituted with the corresponding value for 'VAR' if set, otherwise "", but
+    ///         percent-encoded (URL encoded). All characters except ASCII letters, digits and
+    ///         `-`, `.`, `_` and `~` are encoded.
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR@urldecode}</td>
+    ///       <td>
+    ///         substituted with the corresponding value for 'VAR' if set, otherwise "", but
+    ///         percent-decoded (URL decoded). Yields an error if the decoded value isn't valid
+    ///         UTF-8.
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR@base64}</td>
+    ///       <td>
+    ///         substituted with the corresponding value for 'VAR' if set, otherwise "", but
+    ///         base64 encoded using the standard alphabet with padding.
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR@base64d}</td>
+    ///       <td>
+    ///         substituted with the corresponding value for 'VAR' if set, otherwise "", but
+    ///         base64 decoded. Padding is optional. Yields an error if the decoded value isn't
+    ///         valid UTF-8.
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR@shellquote}</td>
+    ///       <td>
+    ///         substituted with the corresponding value for 'VAR' if set, otherwise "", but
+    ///         single quoted such that a POSIX shell reads it as a single word.
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR@pad:5}</td>
+    ///       <td>
+    ///         substituted with the corresponding value for 'VAR' if set, otherwise "", but
+    ///         right-aligned to a width of 5 characters using spaces, or zeros if the width is
+    ///         written with a leading zero (as in `${VAR@pad:05}`). Yields an error if the value
+    ///         isn't a number.
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR@fixed:2}</td>
+    ///       <td>
+    ///         substituted with the corresponding value for 'VAR' if set, otherwise "", but
+    ///         formatted as a decimal number with 2 decimals. Yields an error if the value isn't
+    ///         a number.
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR@trunc:80}</td>
+    ///       <td>
+    ///         substituted with the corresponding value for 'VAR' if set, otherwise "", but
+    ///         truncated to at most 80 characters. With an ellipsis, as in `${VAR@trunc:80:...}`,
+    ///         the end of a truncated value is replaced with the ellipsis such that the result
+    ///         is still at most 80 characters.
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR#pattern}</td>
+    ///       <td>
+    ///         substituted with the corresponding value for 'VAR' if set, otherwise "", but
+    ///         with the shortest prefix matching the pattern removed. `${VAR##pattern}` removes
+    ///         the longest matching prefix instead.
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR%pattern}</td>
+    ///       <td>
+    ///         substituted with the corresponding value for 'VAR' if set, otherwise "", but
+    ///         with the shortest suffix matching the pattern removed. `${VAR%%pattern}` removes
+    ///         the longest matching suffix instead.
+    ///       </td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR/pattern/text}</td>
+    ///       <td>
+    ///         substituted with the corresponding value for 'VAR' if set, otherwise "", but
+    ///         with the first match of the pattern replaced with the text, in which `&` stands
+    ///         for the match and `\1` to `\9` for its groups. `${VAR//pattern/text}` replaces
+    ///         every match instead.
+    ///       </td>
+    ///     </tr>
+    ///   </tbody>
+    /// </table>
+    ///
+    /// `VAR` above is a named variable. Named variables can be provided using the builder:
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use xpanda::Xpanda;
+    ///
+    /// let named_vars = HashMap::new();
+    /// let xpanda = Xpanda::builder()
+    ///     .with_named_vars(named_vars)
+    ///     .build();
+    /// ```
+    ///
+    /// Positional variables are also supported and can be provided in the same way:
+    ///
+    /// ```rust
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .with_positional_vars(Vec::new())
+    ///     .build();
+    /// ```
+    ///
+    /// Positional variables can be referenced using their index (starting at 1), for example, `$1`
+    /// references the first positional variable, `$2` the second and so on. `$0` is a space concatenated
+    /// string of all positional variables. An identifier starting with a digit is always read as a
+    /// whole word, so `$1abc` is rejected rather than read as `${1}abc`, unless
+    /// [`Builder::lenient_identifiers`] is `true`.
+    ///
+    /// Here are some examples and their output:
+    ///
+    /// <table>
+    ///   <thead>
+    ///     <tr>
+    ///       <th>Pattern</th>
+    ///       <th>VAR unset</th>
+    ///       <th>VAR=""</th>
+    ///       <th>VAR="example"</th>
+    ///     </tr>
+    ///   </thead>
+    ///   <tbody>
+    ///     <tr>
+    ///       <td>$VAR</td>
+    ///       <td></td>
+    ///       <td></td>
+    ///       <td>"example"</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR}</td>
+    ///       <td></td>
+    ///       <td></td>
+    ///       <td>"example"</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR-default}</td>
+    ///       <td>"default"</td>
+    ///       <td></td>
+    ///       <td>"example"</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR:-default}</td>
+    ///       <td>"default"</td>
+    ///       <td>"default"</td>
+    ///       <td>"example"</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR+alternative}</td>
+    ///       <td></td>
+    ///       <td>"alternative"</td>
+    ///       <td>"alternative"</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR:+alternative}</td>
+    ///       <td></td>
+    ///       <td></td>
+    ///       <td>"alternative"</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR?message}</td>
+    ///       <td>error: "message"</td>
+    ///       <td></td>
+    ///       <td>"example"</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR:?message}</td>
+    ///       <td>error: "message"</td>
+    ///       <td>error: "message"</td>
+    ///       <td>"example"</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${#VAR}</td>
+    ///       <td>"0"</td>
+    ///       <td>"0"</td>
+    ///       <td>"7"</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${!VAR}</td>
+    ///       <td></td>
+    ///       <td></td>
+    ///       <td>$example</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR^}</td>
+    ///       <td></td>
+    ///       <td></td>
+    ///       <td>"Example"</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR^^}</td>
+    ///       <td></td>
+    ///       <td></td>
+    ///       <td>"EXAMPLE"</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR,}</td>
+    ///       <td></td>
+    ///       <td></td>
+    ///       <td>"example"</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR,,}</td>
+    ///       <td></td>
+    ///       <td></td>
+    ///       <td>"example"</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR~}</td>
+    ///       <td></td>
+    ///       <td></td>
+    ///       <td>"Example"</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR~~}</td>
+    ///       <td></td>
+    ///       <td></td>
+    ///       <td>"EXAMPLE"</td>
+    ///     </tr>
+    ///   </tbody>
+    /// </table>
+    ///
+    /// Special rules take precedence when [`Builder::no_unset`] is `true`:
+    ///
+    /// <table>
+    ///   <thead>
+    ///     <tr>
+    ///       <th>Pattern</th>
+    ///       <th>VAR unset</th>
+    ///     </tr>
+    ///   </thead>
+    ///   <tbody>
+    ///     <tr>
+    ///       <td>$VAR</td>
+    ///       <td>error</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR}</td>
+    ///       <td>error</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${#VAR}</td>
+    ///       <td>error</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${!VAR}</td>
+    ///       <td>error</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR^}</td>
+    ///       <td>error</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR^^}</td>
+    ///       <td>error</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR,}</td>
+    ///       <td>error</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR,,}</td>
+    ///       <td>error</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR~}</td>
+    ///       <td>error</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>${VAR~~}</td>
+    ///       <td>error</td>
+    ///     </tr>
+    ///   </tbody>
+    /// </table>
+    ///
+    /// Default/Alternative values can also be variables:
+    ///
+    /// <table>
+    ///   <thead>
+    ///     <tr>
+    ///       <th>Pattern</th>
+    ///       <th>VAR unset</th>
+    ///       <th>VAR=""</th>
+    ///       <th>VAR="example"</th>
+    ///     </tr>
+    ///   </thead>
+    ///   <tbody>
+    ///     <tr>
+    ///       <td>`${VAR:-$DEF}`</td>
+    ///       <td>`$DEF`</td>
+    ///       <td></td>
+    ///       <td>"example"</td>
+    ///     </tr>
+    ///     <tr>
+    ///       <td>`${VAR+${ALT:-alternative}}`</td>
+    ///       <td></td>
+    ///       <td>`${ALT:-alternative}`</td>
+    ///       <td>${ALT:-alternative}</td>
+    ///     </tr>
+    ///   </tbody>
+    /// </table>
+    ///
+    /// The `$` character is assumed to be the start of a variable. If the variable does not match
+    /// any of the forms listed above, an error is returned. Variables can be escaped by prefixing them
+    /// by an additional '$', for example: `$$VAR` which yields `$VAR` and `${VAR-$$text}` which yields
+    /// `$text` if `VAR` is unset.
+    ///
+    /// Inside a variable, `$}` and `$:` yield a literal `}` and `:` respectively, for example
+    /// `${VAR-a$}b}` which yields `a}b` and `${VAR-$:text}` which yields `:text` if `VAR` is
+    /// unset. If [`Builder::quoting`] is `true`, text inside a variable can also be quoted, for
+    /// example `${VAR-"a } b"}` which yields `a } b` if `VAR` is unset.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the given string is badly formatted and cannot be parsed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::default();
+    /// assert_eq!(xpanda.expand("${1:-default}"), Ok(String::from("default")));
+    /// ```
+    pub fn expand(&self, input: &str) -> Result<String, Error> {
+        let template = self.parse(input)?;
+
+        self.render(&template)
+    }
+
+    /// Expands the given text repeatedly, until expanding it again wouldn't change it or
+    /// `max_passes` passes have been made, such as when the values of variables themselves
+    /// contain params. Returns the output of the last pass along with the number of passes
+    /// which changed the text. If that's `max_passes`, the output may not be stable yet, which
+    /// [`Xpanda::is_fully_expanded`] can tell.
+    ///
+    /// Note that each pass also unescapes `$$`, so text meant to keep a literal `$` must escape
+    /// it once per pass.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the output of any pass is badly formatted and cannot be parsed, or if
+    /// rendering it fails, in which case the position of the error refers to the input of that
+    /// pass. See [`Xpanda::render`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::Xpanda;
+    ///
+    /// let mut named_vars = HashMap::new();
+    /// named_vars.insert(String::from("URL"), String::from("https://$HOST/"));
+    /// named_vars.insert(String::from("HOST"), String::from("${NAME}.example.com"));
+    /// named_vars.insert(String::from("NAME"), String::from("www"));
+    /// let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+    ///
+    /// assert_eq!(
+    ///     xpanda.expand_until_stable("$URL", 10),
+    ///     Ok((String::from("https://www.example.com/"), 3))
+    /// );
+    /// assert_eq!(
+    ///     xpanda.expand_until_stable("$URL", 2),
+    ///     Ok((String::from("https://${NAME}.example.com/"), 2))
+    /// );
+    /// ```
+    pub fn expand_until_stable(
+        &self,
+        input: &str,
+        max_passes: usize,
+    ) -> Result<(String, usize), Error> {
+        let mut text = input.to_string();
+
+        for pass in 0..max_passes {
+            let template = self.parse(&text)?;
+
+            if template.is_literal(&text) {
+                return Ok((text, pass));
+            }
+
+            let expanded = self.render(&template)?;
+
+            // Such as `$A` where the value of `A` is `$A`
+            if expanded == text {
+                return Ok((text, pass));
+            }
+
+            text = expanded;
+        }
+
+        Ok((text, max_passes))
+    }
+
+    /// Expands the given bytes just like [`Xpanda::expand`], but without requiring them to be
+    /// valid UTF-8, such as files in legacy encodings. Only ASCII syntax such as `$VAR` and
+    /// `${VAR-default}` is interpreted, while any bytes which aren't valid UTF-8 are passed
+    /// through untouched, also inside default values. The values of variables are inserted as
+    /// UTF-8.
+    ///
+    /// The last 256 code points of the supplementary private use area B, `U+10FF00` to
+    /// `U+10FFFF`, stand in for invalid bytes while expanding, so the input must not contain
+    /// them, and any such code points in values are written as the bytes they stand in for.
+    /// The byte offsets of errors refer to the input, while columns count invalid bytes as one
+    /// character each.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the given bytes are badly formatted and cannot be parsed, or if
+    /// rendering them fails. See [`Xpanda::render`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .with_named_vars(HashMap::from([(String::from("NAME"), String::from("Zoë"))]))
+    ///     .build();
+    ///
+    /// // "caf\xE9" is "café" encoded as Latin-1
+    /// assert_eq!(
+    ///     xpanda.expand_bytes(b"caf\xE9 $NAME ${UNSET-\xE9}"),
+    ///     Ok(b"caf\xE9 Zo\xC3\xAB \xE9".to_vec())
+    /// );
+    /// ```
+    pub fn expand_bytes(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+        let text = bytes::decode(input)?;
+        let output = self
+            .expand(&text)
+            .map_err(|error| bytes::locate_error(error, &text))?;
+
+        Ok(bytes::encode(&output))
+    }
+
+    /// Returns an iterator which reads the given input line by line, yielding each line as
+    /// soon as it's been expanded. See [`LineExpander`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::default();
+    /// let lines = xpanda
+    ///     .expand_lines("${1:-a}\n${2:-b}".as_bytes())
+    ///     .collect::<Result<Vec<_>, _>>();
+    ///
+    /// assert_eq!(lines, Ok(vec![String::from("a\n"), String::from("b")]));
+    /// ```
+    pub const fn expand_lines<R: BufRead>(&self, input: R) -> LineExpander<'_, R> {
+        LineExpander::new(self, input)
+    }
+
+    /// Returns an iterator which reads the given input in chunks, yielding each chunk as soon as
+    /// it's been expanded, while holding no more than a fixed number of bytes of input in
+    /// memory. See [`StreamExpander`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::default();
+    /// let output = xpanda
+    ///     .expand_stream("${1:-a}\n${2:-b}".as_bytes())
+    ///     .collect::<Result<String, _>>();
+    ///
+    /// assert_eq!(output, Ok(String::from("a\nb")));
+    /// ```
+    pub fn expand_stream<R: BufRead>(&self, input: R) -> StreamExpander<'_, R> {
+        StreamExpander::new(self, input)
+    }
+
+    /// Parses the given text into a [`Template`] using the syntax options of this instance, such
+    /// as [`Builder::quoting`] and [`Builder::lenient_identifiers`]. [`Template::parse`] uses the
+    /// default syntax options instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the given string is badly formatted and cannot be parsed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::builder().quoting(true).build();
+    /// let template = xpanda.parse("${VAR:-'a } b'}").unwrap();
+    /// assert_eq!(template.to_string(), "${VAR:-'a } b'}");
+    /// ```
+    pub fn parse<'a>(&self, input: &'a str) -> Result<Template<'a>, Error> {
+        self.parse_at(input, true)
+    }
+
+    /// Parses the given text like [`Xpanda::parse`], except that the text starts in the middle
+    /// of a line unless `starts_line` is set, as does a chunk split off a long line.
+    pub(crate) fn parse_at<'a>(
+        &self,
+        input: &'a str,
+        starts_line: bool,
+    ) -> Result<Template<'a>, Error> {
+        let parse = || {
+            Ok(Template::parse_partial_at(
+                input,
+                self.syntax.clone(),
+                starts_line,
+            )?)
+        };
+
+        if !self.evaluator.is_timed() {
+            return parse();
+        }
+
+        // Lexing is interleaved with parsing, so it's measured on its own and left out of the
+        // time spent parsing
+        let start = Instant::now();
+        let mut tokens = Lexer::new(input, self.syntax.clone());
+        tokens.starts_line(starts_line);
+        tokens.into_iter().for_each(drop);
+        let lexed = start.elapsed();
+
+        let start = Instant::now();
+        let result = parse();
+        let parsed = start.elapsed().saturating_sub(lexed);
+
+        self.evaluator.notify_timing(Phase::Lex, lexed);
+        self.evaluator.notify_timing(Phase::Parse, parsed);
+
+        result
+    }
+
+    /// Parses the given text just like [`Xpanda::parse`], but on failure also returns the part
+    /// of the template which was parsed before the error. See [`Template::parse_partial`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the given string is badly formatted and cannot be parsed.
+    #[allow(clippy::result_large_err)]
+    pub fn parse_partial<'a>(&self, input: &'a str) -> Result<Template<'a>, ParseError<'a>> {
+        Template::parse_partial_with_syntax(input, self.syntax.clone())
+    }
+
+    /// Returns `true` if the given text contains nothing which would be expanded using the
+    /// syntax options of this instance, such that expanding it returns it unchanged no matter
+    /// the values of variables. Escapes such as `$$`, front matter and text which can't be
+    /// parsed all count as something to expand.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::default();
+    ///
+    /// assert!(xpanda.is_fully_expanded("plain text"));
+    /// assert!(!xpanda.is_fully_expanded("$VAR"));
+    /// assert!(!xpanda.is_fully_expanded("$$VAR"));
+    /// ```
+    #[must_use]
+    pub fn is_fully_expanded(&self, input: &str) -> bool {
+        self.parse(input)
+            .is_ok_and(|template| template.is_literal(input))
+    }
+
+    /// Escapes the given text such that expanding it using the syntax options of this instance
+    /// yields the text as is, like [`escape`] does for the default syntax. Opaque regions, as
+    /// set by [`Builder::opaque_regions`], are left as is since they're never expanded.
+    ///
+    /// Returns [`None`] if the syntax has no way to write the text, which is the case if it
+    /// contains a `$` outside of opaque regions while `$$` is a special variable, see
+    /// [`Builder::with_special_vars`], or if it starts like front matter while
+    /// [`Builder::front_matter`] is enabled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::builder().opaque_regions("{{", "}}").build();
+    /// let text = "$HOME {{ $.Values.home }}";
+    /// let escaped = xpanda.escape(text).unwrap();
+    ///
+    /// assert_eq!(escaped, "$$HOME {{ $.Values.home }}");
+    /// assert_eq!(xpanda.expand(&escaped), Ok(String::from(text)));
+    /// ```
+    #[must_use]
+    pub fn escape(&self, text: &str) -> Option<String> {
+        escape::escape_with_syntax(text, &self.syntax)
+    }
+
+    /// Reverses [`Xpanda::escape`], turning every `$$` outside of opaque regions back into a
+    /// single `$`, unless `$$` is a special variable. Other text is left as is, including any
+    /// params.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::builder().opaque_regions("{{", "}}").build();
+    /// assert_eq!(xpanda.unescape("$$HOME {{ $$ }}"), "$HOME {{ $$ }}");
+    /// ```
+    #[must_use]
+    pub fn unescape(&self, text: &str) -> String {
+        escape::unescape_with_syntax(text, &self.syntax)
+    }
+
+    /// Returns a snapshot of the metrics of every provider registered using
+    /// [`Builder::register_namespace`], keyed by namespace, such as to export them to a
+    /// monitoring system. The metrics cover all expansions made using this instance, including
+    /// concurrent ones. See [`ProviderMetrics`].
+    #[must_use]
+    pub fn provider_metrics(&self) -> BTreeMap<String, ProviderMetrics> {
+        self.evaluator.provider_metrics()
+    }
+
+    /// Renders a previously parsed [`Template`] by substituting the values of the variables
+    /// inside it. See [`Xpanda::expand`] for the supported patterns.
+    ///
+    /// Params are evaluated from left to right, stopping at the first error, and only as far as
+    /// needed for the output. The default value of `${VAR-default}` is only evaluated if it's
+    /// used, and so is the alternative value of `${VAR+alt}`. Variables required by the front
+    /// matter or the [`Schema`] are checked before evaluating anything.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if a variable is unset and that is considered an error, either because of
+    /// the pattern used or because [`Builder::no_unset`] is `true`, if the value of a variable
+    /// can't be processed by a modifier such as `${VAR@base64d}`, if a namespaced variable
+    /// can't be resolved, or if a variable required by the [`FrontMatter`] of the template is
+    /// unset.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::{Template, Xpanda};
+    ///
+    /// let xpanda = Xpanda::default();
+    /// let template = Template::parse("${1:-default}").unwrap();
+    /// assert_eq!(xpanda.render(&template), Ok(String::from("default")));
+    /// ```
+    pub fn render(&self, template: &Template) -> Result<String, Error> {
+        self.check(template)?;
+
+        let start = self.evaluator.is_timed().then(Instant::now);
+        let result = self
+            .evaluator
+            .eval(&template.ast, template.defaults())
+            .map_err(Error::from);
+
+        if let Some(start) = start {
+            self.evaluator.notify_timing(Phase::Eval, start.elapsed());
+        }
+
+        result
+    }
+
+    /// Binds values to the variables of the template like [`Template::bind`], but renders it
+    /// using the options, callbacks and [`Schema`] of this instance. The bound variables are
+    /// added to those of this instance, with positional variables replacing those of this
+    /// instance if any are bound, and a required variable counts as bound if it's set either
+    /// way.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] with [`ErrorKind::Required`], listing every unbound required variable,
+    /// at the first reference to any of them. Otherwise returns [`Err`] if a variable required
+    /// by the front matter or the schema is unset, or if the schema rejects a value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::{ErrorKind, Schema, Template, VarSchema, Xpanda};
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .schema(Schema::new().var(VarSchema::new("LEVEL").one_of(["debug", "info"])))
+    ///     .with_named_vars(HashMap::from([(String::from("APP"), String::from("api"))]))
+    ///     .build();
+    /// let template = Template::parse("$APP:${LEVEL:-${FALLBACK}}").unwrap();
+    ///
+    /// let error = xpanda.bind(&template, HashMap::new()).unwrap_err();
+    /// assert_eq!(error.message, "Unbound required variable(s) FALLBACK");
+    ///
+    /// let vars = HashMap::from([(String::from("LEVEL"), String::from("trace"))]);
+    /// assert_eq!(xpanda.bind(&template, vars).unwrap_err().kind, ErrorKind::InvalidValue);
+    ///
+    /// let vars = HashMap::from([(String::from("LEVEL"), String::from("info"))]);
+    /// let bound = xpanda.bind(&template, vars).unwrap();
+    /// assert_eq!(bound.render(), Ok(String::from("api:info")));
+    /// ```
+    pub fn bind<'t, 'a>(
+        &self,
+        template: &'t Template<'a>,
+        vars: HashMap<String, String>,
+    ) -> Result<BoundTemplate<'t, 'a>, Error> {
+        let xpanda = template::bind_vars(self.to_builder(), vars, &template.syntax).build();
+        let defaults = template.defaults();
+        let is_empty = |identifier: &Identifier| {
+            let value = match xpanda.evaluator.lookup_dynamic_name(identifier, defaults) {
+                Some(name) => xpanda.evaluator.lookup_var(&name, defaults),
+                None => xpanda.evaluator.lookup_identifier(identifier, defaults),
+            }?;
+            let is_empty = value.is_empty();
+            wipe(value);
+
+            Some(is_empty)
+        };
+
+        let mut unbound = Vec::new();
+        let mut position = None;
+
+        for (identifier, at) in template.required_identifiers(Some(&is_empty)) {
+            let name = identifier.to_string();
+
+            if is_empty(identifier).is_none() && !unbound.contains(&name) {
+                unbound.push(name);
+                position.get_or_insert(at);
+            }
+        }
+
+        if !unbound.is_empty() {
+            let message = format!("Unbound required variable(s) {}", unbound.join(", "));
+
+            return Err(Error::new(
+                ErrorKind::Required,
+                message,
+                &position.unwrap_or_default(),
+            ));
+        }
+
+        xpanda.check(template)?;
+
+        Ok(BoundTemplate { template, xpanda })
+    }
+
+    /// Renders a [`CompiledTemplate`] just like [`Xpanda::render`] renders the template it was
+    /// compiled from, but faster.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] in the same cases as [`Xpanda::render`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::{Template, Xpanda};
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .with_positional_vars(vec![String::from("one")])
+    ///     .build();
+    /// let template = Template::parse("${1:+set} ${2:-unset}").unwrap().compile();
+    ///
+    /// for _ in 0..3 {
+    ///     assert_eq!(xpanda.render_compiled(&template), Ok(String::from("set unset")));
+    /// }
+    /// ```
+    pub fn render_compiled(&self, template: &CompiledTemplate) -> Result<String, Error> {
+        self.check(template.template())?;
+
+        let defaults = template.template().defaults();
+        let start = self.evaluator.is_timed().then(Instant::now);
+        let result = self
+            .evaluator
+            .run(&template.program, defaults)
+            .map_err(Error::from);
+
+        if let Some(start) = start {
+            self.evaluator.notify_timing(Phase::Eval, start.elapsed());
+        }
+
+        result
+    }
+
+    /// Validates the variables of the template against the [`Schema`] of this instance, if any,
+    /// returning all violations. Required variables of the schema which are unset are reported
+    /// at their first reference in the template, or at the start of the template if it doesn't
+    /// reference them. Values which don't satisfy the constraint of their variable are reported
+    /// at every reference.
+    ///
+    /// [`Xpanda::render`] and [`Xpanda::expand`] fail with the first violation, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::{Schema, VarSchema, Xpanda};
+    ///
+    /// let schema = Schema::new()
+    ///     .var(VarSchema::new("DB_URL").required(true))
+    ///     .var(VarSchema::new("LOG_LEVEL").one_of(["debug", "info"]));
+    /// let xpanda = Xpanda::builder()
+    ///     .schema(schema)
+    ///     .with_named_vars(HashMap::from([(String::from("LOG_LEVEL"), String::from("loud"))]))
+    ///     .build();
+    /// let template = xpanda.parse("$DB_URL $LOG_LEVEL").unwrap();
+    /// let messages = xpanda
+    ///     .validate(&template)
+    ///     .into_iter()
+    ///     .map(|error| error.message)
+    ///     .collect::<Vec<_>>();
+    ///
+    /// assert_eq!(
+    ///     messages,
+    ///     vec![
+    ///         "'DB_URL' is required by the schema but unset",
+    ///         "'LOG_LEVEL' must be one of 'debug', 'info'",
+    ///     ]
+    /// );
+    /// ```
+    #[must_use]
+    pub fn validate(&self, template: &Template) -> Vec<Error> {
+        if self.schema.is_empty() {
+            return Vec::new();
+        }
+
+        let defaults = template.defaults();
+        let references = template.references();
+        let mut errors = Vec::new();
+
+        for var in self.schema.vars() {
+            let positions = references
+                .iter()
+                .filter(|reference| reference.name == var.name())
+                .map(|reference| reference.position)
+                .collect::<Vec<_>>();
+
+            let Some(value) = self.evaluator.lookup_var(var.name(), defaults) else {
+                if var.is_required() {
+                    let message = format!("'{}' is required by the schema but unset", var.name());
+                    let position = positions.first().copied().unwrap_or_default();
+                    errors.push(Error::new(ErrorKind::Required, message, &position));
+                }
+
+                continue;
+            };
+
+            if let Some(message) = var.check(&value) {
+                for position in positions {
+                    errors.push(Error::new(
+                        ErrorKind::InvalidValue,
+                        message.clone(),
+                        &position,
+                    ));
+                }
+            }
+        }
+
+        errors.sort_by_key(|error| error.span.map(|span| span.start.index));
+
+        errors
+    }
+
+    /// Determines where the value of each variable referenced by the template comes from,
+    /// in order of first reference, without resolving them. The source is [`None`] for
+    /// variables which are unset. Variables referenced indirectly, as in `${!VAR}`, are
+    /// omitted since which variable that is depends on the value of another.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::{Source, Xpanda};
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .front_matter(true)
+    ///     .with_positional_vars(vec![String::from("one")])
+    ///     .with_named_vars(HashMap::from([(String::from("A"), String::from("a"))]))
+    ///     .with_labeled_vars("vars.env", HashMap::from([(String::from("B"), String::from("b"))]))
+    ///     .build();
+    /// let template = xpanda.parse("#! xpanda: default C=c\n$1 $A $B $C $D").unwrap();
+    ///
+    /// assert_eq!(
+    ///     xpanda.sources(&template),
+    ///     vec![
+    ///         (String::from("1"), Some(Source::Positional)),
+    ///         (String::from("A"), Some(Source::Named(None))),
+    ///         (String::from("B"), Some(Source::Named(Some(String::from("vars.env"))))),
+    ///         (String::from("C"), Some(Source::FrontMatter)),
+    ///         (String::from("D"), None),
+    ///     ]
+    /// );
+    /// ```
+    #[must_use]
+    pub fn sources(&self, template: &Template) -> Vec<(String, Option<Source>)> {
+        let defaults = template.defaults();
+
+        template
+            .referenced_identifiers()
+            .into_iter()
+            .map(|(name, identifier)| (name, self.evaluator.source(&identifier, defaults)))
+            .collect()
+    }
+
+    /// Determines how each variable referenced by the template resolves, in order of first
+    /// reference, which tells whether a param with a default or alternative value takes it. Like
+    /// [`Xpanda::sources`], variables referenced indirectly are omitted.
+    ///
+    /// Variables are resolved without notifying listeners, but namespaced variables are resolved
+    /// by their providers, and are unset if that fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::{Resolution, Xpanda};
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .with_named_vars(HashMap::from([
+    ///         (String::from("HOST"), String::from("db")),
+    ///         (String::from("USER"), String::new()),
+    ///     ]))
+    ///     .build();
+    /// let template = xpanda.parse("${USER-root}@${HOST:-localhost}:${PORT:-5432}").unwrap();
+    ///
+    /// assert_eq!(
+    ///     xpanda.resolutions(&template),
+    ///     vec![
+    ///         (String::from("USER"), Resolution::Empty),
+    ///         (String::from("HOST"), Resolution::Set(2)),
+    ///         (String::from("PORT"), Resolution::Unset),
+    ///     ]
+    /// );
+    /// assert_eq!(xpanda.render(&template), Ok(String::from("@db:5432")));
+    /// ```
+    #[must_use]
+    pub fn resolutions(&self, template: &Template) -> Vec<(String, Resolution)> {
+        let defaults = template.defaults();
+
+        template
+            .referenced_identifiers()
+            .into_iter()
+            .map(|(name, identifier)| {
+                let value = self.evaluator.lookup_identifier(&identifier, defaults);
+                let resolution = Resolution::of(value.as_deref());
+                (name, resolution)
+            })
+            .collect()
+    }
+
+    /// Returns the named variables which were provided, as by [`Builder::with_named_vars`] or
+    /// [`Builder::with_labeled_vars`], but are never referenced by the template, along with
+    /// where their values come from, sorted by name. Such variables are likely left over, or
+    /// misspelled in either the template or where they're provided.
+    ///
+    /// Environment variables are never listed, as they aren't provided for the template. A
+    /// variable counts as referenced if an alias of it is, if the front matter requires it, or
+    /// if an indirect param as in `${!VAR}` or a dynamic name as in `${PREFIX_${ENV}}`
+    /// currently refers to it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::{Source, Xpanda};
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .with_labeled_vars(
+    ///         "app.env",
+    ///         HashMap::from([
+    ///             (String::from("HOST"), String::from("db")),
+    ///             (String::from("PROT"), String::from("5432")),
+    ///         ]),
+    ///     )
+    ///     .build();
+    ///
+    /// let template = xpanda.parse("$HOST:${PORT:-80}").unwrap();
+    /// assert_eq!(
+    ///     xpanda.unused_vars(&template),
+    ///     vec![(String::from("PROT"), Source::Named(Some(String::from("app.env"))))]
+    /// );
+    /// ```
+    #[must_use]
+    pub fn unused_vars(&self, template: &Template) -> Vec<(String, Source)> {
+        let defaults = template.defaults();
+        let mut names = template
+            .front_matter
+            .as_ref()
+            .map_or_else(Vec::new, |front_matter| front_matter.required.clone());
+
+        for reference in template.references() {
+            if reference.is_indirect {
+                names.extend(self.evaluator.lookup_var(&reference.name, defaults));
+            }
+
+            names.push(reference.name);
+        }
+
+        for identifier in template.dynamic_identifiers() {
+            names.extend(self.evaluator.lookup_dynamic_name(identifier, defaults));
+        }
+
+        self.evaluator.unused_vars(names.iter().map(String::as_str))
+    }
+
+    /// Renders the template like [`Xpanda::render`], reporting which variables it references
+    /// along with the output, or along with the errors which prevented rendering it. Variables
+    /// referenced indirectly, as in `${!VAR}`, are omitted as in [`Xpanda::sources`].
+    ///
+    /// Every param outside of other params is listed along with the value it expanded to, even
+    /// if another param failed to expand, so that the template can be compared before and after
+    /// rendering it without parsing it again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::{ErrorKind, Source, Xpanda};
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .with_named_vars(HashMap::from([(String::from("HOST"), String::from("db"))]))
+    ///     .build();
+    ///
+    /// let report = xpanda.report(&xpanda.parse("$HOST:${PORT:-5432}").unwrap());
+    /// assert_eq!(report.output, Some(String::from("db:5432")));
+    /// assert_eq!(report.missing, vec!["PORT"]);
+    /// assert_eq!(report.used, vec![(String::from("HOST"), Source::Named(None))]);
+    /// assert!(report.errors.is_empty());
+    ///
+    /// let params = report
+    ///     .params
+    ///     .iter()
+    ///     .map(|param| (param.text.as_str(), param.value.as_deref()))
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(params, vec![("$HOST", Some("db")), ("${PORT:-5432}", Some("5432"))]);
+    ///
+    /// let report = xpanda.report(&xpanda.parse("$HOST:${PORT?}").unwrap());
+    /// assert_eq!(report.output, None);
+    /// assert_eq!(report.errors[0].kind, ErrorKind::Required);
+    /// assert_eq!(report.params[0].value, Some(String::from("db")));
+    /// assert_eq!(report.params[1].value, None);
+    /// ```
+    #[must_use]
+    pub fn report(&self, template: &Template) -> Report {
+        let mut report = Report::default();
+
+        for (name, source) in self.sources(template) {
+            match source {
+                Some(source) => report.used.push((name, source)),
+                None => report.missing.push(name),
+            }
+        }
+
+        report.unused = self.unused_vars(template);
+        report.resolutions = self.resolutions(template);
+        report.errors = self.validate(template);
+
+        let front_matter = template
+            .front_matter
+            .as_ref()
+            .filter(|_| report.errors.is_empty());
+
+        if let Some(error) =
+            front_matter.and_then(|front_matter| self.check_required(front_matter).err())
+        {
+            report.errors.push(error);
+        }
+
+        let is_checked = report.errors.is_empty();
+        let start = (is_checked && self.evaluator.is_timed()).then(Instant::now);
+        let mut output = Some(String::new());
+        let mut param_texts = template.param_texts.iter();
+
+        // Nodes are evaluated one by one, which yields the same output as evaluating all of
+        // them at once, so that the value of each param is known
+        for node in &template.ast.nodes {
+            let value = is_checked
+                .then(|| {
+                    self.evaluator
+                        .eval(&Ast::new(vec![node.clone()]), template.defaults())
+                        .map_err(|error| {
+                            if report.errors.is_empty() {
+                                report.errors.push(error.into());
+                            }
+                        })
+                        .ok()
+                })
+                .flatten();
+
+            if let Node::Param(_, position) = node {
+                let text = param_texts.next().map_or_else(
+                    || {
+                        Ast::new(vec![node.clone()])
+                            .display(&template.syntax)
+                            .to_string()
+                    },
+                    ToString::to_string,
+                );
+
+                report.params.push(ExpandedParam {
+                    text,
+                    position: *position,
+                    value: value.clone(),
+                });
+            }
+
+            output = output.zip(value).map(|(output, value)| output + &value);
+        }
+
+        if let Some(start) = start {
+            self.evaluator.notify_timing(Phase::Eval, start.elapsed());
+        }
+
+        if report.errors.is_empty() {
+            report.output = output;
+        }
+
+        report
+    }
+
+    /// Checks the front matter and the schema before rendering the template.
+    fn check(&self, template: &Template) -> Result<(), Error> {
+        if let Some(front_matter) = &template.front_matter {
+            self.check_required(front_matter)?;
+        }
+
+        if let Some(error) = self.validate(template).into_iter().next() {
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    /// Checks that all variables required by the front matter are set, reporting all of the
+    /// unset variables at once.
+    fn check_required(&self, front_matter: &FrontMatter) -> Result<(), Error> {
+        let unset = front_matter
+            .required
+            .iter()
+            .filter(|name| {
+                self.evaluator
+                    .lookup_var(name, &front_matter.defaults)
+                    .is_none()
+            })
+            .map(|name| format!("'{name}'"))
+            .collect::<Vec<_>>();
+
+        if unset.is_empty() {
+            return Ok(());
+        }
+
+        let message = format!("Required variable(s) {} are unset", unset.join(", "));
 
-        Ok(result)
+        Err(Error {
+            span: Some(front_matter.span),
+            ..Error::new(ErrorKind::Required, message, &front_matter.span.start)
+        })
     }
 }
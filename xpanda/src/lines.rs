@@ -0,0 +1,166 @@
+use crate::position::Position;
+use crate::{Document, Error, ErrorKind, Xpanda};
+use std::io::{BufRead, Read};
+
+/// An iterator which expands the lines of a [`BufRead`], created by [`Xpanda::expand_lines`].
+///
+/// Each line is expanded as soon as it has been read, such as to expand a stream of log lines
+/// without waiting for it to end. Each item is a single expanded line, including its line
+/// ending if it has one. The line and byte offsets of errors refer to the whole input rather
+/// than the line itself. Reading stops at the first line which can't be read, but lines which
+/// can't be expanded don't prevent the following lines from being expanded.
+///
+/// Since every line is expanded on its own, params can't span several lines, and front matter
+/// isn't supported.
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::HashMap;
+/// use xpanda::Xpanda;
+///
+/// let xpanda = Xpanda::builder()
+///     .with_named_vars(HashMap::from([(String::from("LEVEL"), String::from("info"))]))
+///     .build();
+/// let input = "[$LEVEL] started\n[${LEVEL^^}] ${\n".as_bytes();
+/// let mut lines = xpanda.expand_lines(input);
+///
+/// assert_eq!(lines.next(), Some(Ok(String::from("[info] started\n"))));
+/// assert_eq!(lines.next().unwrap().unwrap_err().line, 2);
+/// assert_eq!(lines.next(), None);
+/// ```
+pub struct LineExpander<'a, R> {
+    xpanda: &'a Xpanda,
+    reader: R,
+    max_line_bytes: Option<u64>,
+    line: String,
+    line_number: usize,
+    offset: usize,
+    failed: bool,
+}
+
+impl<'a, R: BufRead> LineExpander<'a, R> {
+    pub(crate) const fn new(xpanda: &'a Xpanda, reader: R) -> Self {
+        Self {
+            xpanda,
+            reader,
+            max_line_bytes: None,
+            line: String::new(),
+            line_number: 0,
+            offset: 0,
+            failed: false,
+        }
+    }
+
+    /// Sets the maximum length of a line in bytes, including its line ending. Longer lines are
+    /// an error of kind [`ErrorKind::TooLong`] instead of being read into memory in their entirety.
+    /// Lines are unlimited by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::{ErrorKind, Xpanda};
+    ///
+    /// let xpanda = Xpanda::default();
+    /// let mut lines = xpanda.expand_lines("short\ntoo long\n".as_bytes()).max_line_bytes(6);
+    ///
+    /// assert_eq!(lines.next(), Some(Ok(String::from("short\n"))));
+    /// assert_eq!(lines.next().unwrap().unwrap_err().kind, ErrorKind::TooLong);
+    /// assert_eq!(lines.next(), None);
+    /// ```
+    #[must_use]
+    pub const fn max_line_bytes(mut self, max_line_bytes: u64) -> Self {
+        self.max_line_bytes = Some(max_line_bytes);
+        self
+    }
+
+    /// Returns the line which was read last, before expanding it, such as to show the line in
+    /// which an error occurred. Empty before the first line has been read.
+    #[must_use]
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// Returns the number of lines read so far, which is the line number of the last line.
+    #[must_use]
+    pub const fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    fn read_line(&mut self) -> Option<Result<(), (ErrorKind, String)>> {
+        let mut bytes = Vec::new();
+        let limit = self
+            .max_line_bytes
+            .map_or(u64::MAX, |max| max.saturating_add(1));
+
+        self.line.clear();
+
+        match self
+            .reader
+            .by_ref()
+            .take(limit)
+            .read_until(b'\n', &mut bytes)
+        {
+            Ok(0) => None,
+            Ok(len)
+                if self
+                    .max_line_bytes
+                    .is_some_and(|max| u64::try_from(len).map_or(true, |len| len > max)) =>
+            {
+                Some(Err((
+                    ErrorKind::TooLong,
+                    format!(
+                        "Failed to read input: line exceeds the maximum length of {} bytes",
+                        self.max_line_bytes.unwrap_or_default()
+                    ),
+                )))
+            },
+            Ok(_) => Some(match String::from_utf8(bytes) {
+                Ok(line) => {
+                    self.line = line;
+                    Ok(())
+                },
+                Err(_) => Err((
+                    ErrorKind::Io,
+                    String::from("Failed to read input: stream did not contain valid UTF-8"),
+                )),
+            }),
+            Err(error) => Some(Err((
+                ErrorKind::Io,
+                format!("Failed to read input: {error}"),
+            ))),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for LineExpander<'_, R> {
+    type Item = Result<String, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        self.offset += self.line.len();
+        let result = self.read_line()?;
+        self.line_number += 1;
+        let start = Position::new(self.offset, self.line_number, 1);
+
+        if let Err((kind, message)) = result {
+            self.failed = true;
+            return Some(Err(Error::new(kind, message, &start)));
+        }
+
+        let document = Document {
+            text: &self.line,
+            start,
+            delimiter: "",
+        };
+
+        Some(
+            self.xpanda
+                .expand(&self.line)
+                .map_err(|error| document.locate(error)),
+        )
+    }
+}
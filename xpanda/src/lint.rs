@@ -0,0 +1,654 @@
+use crate::ast::{Identifier, Node, Param};
+use crate::front_matter::FrontMatter;
+use crate::position::{LineIndex, Position, Span};
+use crate::{Error, Xpanda};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+/// The marker of a suppression comment, followed by the names of the rules to allow.
+const SUPPRESSION_MARKER: &str = "xpanda-lint: allow";
+
+/// The maximum number of times [`Linter::fix`] lints the input.
+const MAX_FIX_PASSES: usize = 8;
+
+/// A check performed by a [`Linter`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum Rule {
+    /// A variable written without braces, as in `$VAR`, which is easy to misread when followed
+    /// by text. Special parameters such as `$?`, which can't be braced, are exempt.
+    UnbracedVariable,
+    /// A variable which is known to be optional, as given by [`Linter::optional_vars`], used
+    /// without a default value.
+    MissingDefault,
+    /// A default or alternative value which is never used, such as the default of the inner
+    /// param in `${A:+${A:-default}}`, or the default of a variable with a default declared by
+    /// the front matter.
+    UnreachableBranch,
+    /// A named variable starting with a digit, as in `$1abc` with
+    /// [`Builder::lenient_identifiers`](crate::Builder::lenient_identifiers) set, which was
+    /// probably meant to be a positional variable followed by text.
+    DigitPrefixedName,
+    /// A `$` separated from the `{` of a param by whitespace, as in `$ {VAR}`, which is a syntax
+    /// error.
+    SpacedBrace,
+    /// A default value only used if the variable is unset, as in `${VAR-default}`, where an
+    /// empty value was likely meant to use the default as well.
+    UnsetOnlyDefault,
+}
+
+impl Rule {
+    /// All rules, in order of declaration.
+    pub const ALL: [Self; 6] = [
+        Self::UnbracedVariable,
+        Self::MissingDefault,
+        Self::UnreachableBranch,
+        Self::DigitPrefixedName,
+        Self::SpacedBrace,
+        Self::UnsetOnlyDefault,
+    ];
+
+    /// The name of the rule, as used in suppression comments.
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::UnbracedVariable => "unbraced-variable",
+            Self::MissingDefault => "missing-default",
+            Self::UnreachableBranch => "unreachable-branch",
+            Self::DigitPrefixedName => "digit-prefixed-name",
+            Self::SpacedBrace => "spaced-brace",
+            Self::UnsetOnlyDefault => "unset-only-default",
+        }
+    }
+
+    /// The severity of the rule unless configured otherwise.
+    #[must_use]
+    pub const fn default_severity(self) -> Severity {
+        match self {
+            Self::UnbracedVariable | Self::UnsetOnlyDefault => Severity::Allow,
+            Self::MissingDefault | Self::UnreachableBranch | Self::DigitPrefixedName => {
+                Severity::Warning
+            },
+            Self::SpacedBrace => Severity::Error,
+        }
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|rule| rule.name() == name)
+            .ok_or_else(|| format!("Unknown lint rule '{name}'"))
+    }
+}
+
+/// How a violation of a [`Rule`] is reported.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Severity {
+    /// The rule is not checked.
+    Allow,
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Allow => write!(f, "allow"),
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A violation of a [`Rule`] found by a [`Linter`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Lint {
+    pub rule: Rule,
+    pub severity: Severity,
+    pub message: String,
+    /// The position of the `$` starting the offending param.
+    pub position: Position,
+    /// A fix which can be applied without further input, if any.
+    pub fix: Option<Fix>,
+}
+
+/// A change to the input fixing a [`Lint`], replacing the range of the input covered by `span`
+/// with `replacement`. Fixes inserting text have an empty span.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Fix {
+    pub span: Span,
+    pub replacement: String,
+}
+
+/// Checks templates for constructs which are valid but likely mistakes, or which go against a
+/// style, according to a configurable set of [`Rule`]s.
+///
+/// A rule can be suppressed for parts of a template using a comment in whatever syntax the
+/// surrounding text uses, containing `xpanda-lint: allow` followed by the names of the rules
+/// to allow, or `all`. The comment applies to the line it's on and to the line after it.
+///
+/// # Examples
+///
+/// ```
+/// use xpanda::{Linter, Rule, Severity, Xpanda};
+///
+/// let linter = Linter::new()
+///     .severity(Rule::UnbracedVariable, Severity::Error)
+///     .optional_vars(["REGION"]);
+/// let input = "$HOST ${REGION}\n# xpanda-lint: allow unbraced-variable\n$PORT";
+/// let lints = linter.lint(&Xpanda::default(), input).unwrap();
+/// let messages = lints
+///     .iter()
+///     .map(|lint| (lint.severity, lint.message.as_str()))
+///     .collect::<Vec<_>>();
+///
+/// assert_eq!(
+///     messages,
+///     vec![
+///         (Severity::Error, "'$HOST' is not braced, write '${HOST}' instead"),
+///         (Severity::Warning, "'REGION' is optional but used without a default value"),
+///     ]
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Linter {
+    severities: Vec<(Rule, Severity)>,
+    optional_vars: Vec<String>,
+}
+
+/// What's known about the value of a variable inside a default, alternative or error message.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Known {
+    Unset,
+    UnsetOrEmpty,
+    Set,
+    NonEmpty,
+}
+
+impl Known {
+    const fn set(treat_empty_as_unset: bool) -> Self {
+        if treat_empty_as_unset {
+            Self::NonEmpty
+        } else {
+            Self::Set
+        }
+    }
+
+    const fn unset(treat_empty_as_unset: bool) -> Self {
+        if treat_empty_as_unset {
+            Self::UnsetOrEmpty
+        } else {
+            Self::Unset
+        }
+    }
+
+    /// Whether a variable known to be in this state is also in the other state.
+    const fn implies(self, other: Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::Unset, Self::Unset | Self::UnsetOrEmpty)
+                | (Self::UnsetOrEmpty, Self::UnsetOrEmpty)
+                | (Self::Set, Self::Set)
+                | (Self::NonEmpty, Self::Set | Self::NonEmpty)
+        )
+    }
+}
+
+impl Display for Known {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Unset => write!(f, "unset"),
+            Self::UnsetOrEmpty => write!(f, "unset or empty"),
+            Self::Set => write!(f, "set"),
+            Self::NonEmpty => write!(f, "set and non-empty"),
+        }
+    }
+}
+
+impl Linter {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the severity of a rule, overriding [`Rule::default_severity`].
+    #[must_use]
+    pub fn severity(mut self, rule: Rule, severity: Severity) -> Self {
+        self.severities.retain(|(other, _)| *other != rule);
+        self.severities.push((rule, severity));
+        self
+    }
+
+    /// Adds the names of variables which are known to be optional, and should therefore always
+    /// be used with a default value. See [`Rule::MissingDefault`].
+    #[must_use]
+    pub fn optional_vars<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.optional_vars.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    /// Returns the severity of the given rule.
+    #[must_use]
+    pub fn severity_of(&self, rule: Rule) -> Severity {
+        self.severities
+            .iter()
+            .find(|(other, _)| *other == rule)
+            .map_or_else(|| rule.default_severity(), |(_, severity)| *severity)
+    }
+
+    /// Parses the input using the syntax options of the given instance and checks it, returning
+    /// the violations of all rules which aren't allowed, in order of appearance.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the input is badly formatted and cannot be parsed, unless
+    /// [`Rule::SpacedBrace`] is violated, which explains the error. In that case only the
+    /// violations of that rule are returned.
+    pub fn lint(&self, xpanda: &Xpanda, input: &str) -> Result<Vec<Lint>, Error> {
+        let line_index = LineIndex::new(input);
+        let mut lints = self.check_spaced_braces(input, &line_index);
+
+        match xpanda.parse(input) {
+            Ok(template) => {
+                let mut walker = Walker {
+                    linter: self,
+                    input,
+                    line_index: &line_index,
+                    front_matter: template.front_matter(),
+                    known: Vec::new(),
+                    lints: Vec::new(),
+                };
+
+                walker.walk(&template.ast.nodes);
+                lints.extend(walker.lints);
+            },
+            Err(_) if !lints.is_empty() => {},
+            Err(error) => return Err(error),
+        }
+
+        let suppressions = suppressions(input);
+        lints.retain(|lint| {
+            !suppressions.iter().any(|(line, rules)| {
+                (*line == lint.position.line || *line + 1 == lint.position.line)
+                    && rules
+                        .as_ref()
+                        .is_none_or(|rules| rules.contains(&lint.rule))
+            })
+        });
+        lints.sort_by_key(|lint| lint.position.index);
+
+        Ok(lints)
+    }
+
+    /// Applies the fixes of the lints found in the input, returning the fixed input along with
+    /// the number of fixes applied. As fixing may uncover more lints, such as in the part of the
+    /// input after a syntax error, the input is linted again until no more fixes apply, at most
+    /// 8 times. Fixes overlapping another fix are left to the next time.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the input is badly formatted and cannot be parsed. See
+    /// [`Linter::lint`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::{Linter, Rule, Severity, Xpanda};
+    ///
+    /// let linter = Linter::new()
+    ///     .severity(Rule::UnbracedVariable, Severity::Warning)
+    ///     .severity(Rule::UnsetOnlyDefault, Severity::Warning);
+    /// let fixed = linter.fix(&Xpanda::default(), "$NAME.txt ${DIR-/tmp} $ {EXT}");
+    ///
+    /// assert_eq!(fixed, Ok((String::from("${NAME}.txt ${DIR:-/tmp} ${EXT}"), 3)));
+    /// ```
+    pub fn fix(&self, xpanda: &Xpanda, input: &str) -> Result<(String, usize), Error> {
+        let mut text = input.to_string();
+        let mut count = 0;
+
+        for _ in 0..MAX_FIX_PASSES {
+            let lints = self.lint(xpanda, &text)?;
+            let (fixed, applied) =
+                apply_fixes(&text, lints.iter().filter_map(|lint| lint.fix.as_ref()));
+
+            if applied == 0 {
+                break;
+            }
+
+            text = fixed;
+            count += applied;
+        }
+
+        Ok((text, count))
+    }
+
+    /// Finds each `$` which is separated from a `{` by whitespace. A `$` preceded by an odd
+    /// number of `$` is part of an escape rather than a param, as in `$$ {`.
+    fn check_spaced_braces(&self, input: &str, line_index: &LineIndex) -> Vec<Lint> {
+        let mut lints = Vec::new();
+        let mut dollars = 0;
+
+        for (index, c) in input.char_indices() {
+            if c == '$' {
+                dollars += 1;
+                continue;
+            }
+
+            let is_unescaped = dollars % 2 == 1;
+            dollars = 0;
+
+            if !is_unescaped || !matches!(c, ' ' | '\t') {
+                continue;
+            }
+
+            let rest = &input[index..];
+            let whitespace = rest.len() - rest.trim_start_matches([' ', '\t']).len();
+
+            if rest[whitespace..].starts_with('{') {
+                let message =
+                    String::from("'$' is separated from '{' by whitespace, write '${' instead");
+                let fix = create_fix(line_index, index, index + whitespace, String::new());
+                let position = line_index.position(index - 1);
+                self.report(&mut lints, Rule::SpacedBrace, message, position, Some(fix));
+            }
+        }
+
+        lints
+    }
+
+    fn report(
+        &self,
+        lints: &mut Vec<Lint>,
+        rule: Rule,
+        message: String,
+        position: Position,
+        fix: Option<Fix>,
+    ) {
+        let severity = self.severity_of(rule);
+
+        if severity != Severity::Allow {
+            lints.push(Lint {
+                rule,
+                severity,
+                message,
+                position,
+                fix,
+            });
+        }
+    }
+}
+
+struct Walker<'a, 'b> {
+    linter: &'b Linter,
+    input: &'b str,
+    line_index: &'b LineIndex<'b>,
+    front_matter: Option<&'b FrontMatter>,
+    /// What's known about variables in the branch being walked, innermost last.
+    known: Vec<(&'b Identifier<'a>, Known)>,
+    lints: Vec<Lint>,
+}
+
+impl<'a, 'b> Walker<'a, 'b> {
+    fn walk(&mut self, nodes: &'b [Node<'a>]) {
+        for node in nodes {
+            if let Node::Param(param, position) = node {
+                self.check_param(param, *position);
+            }
+        }
+    }
+
+    fn check_param(&mut self, param: &'b Param<'a>, position: Position) {
+        let Some(identifier) = param.identifier() else {
+            return;
+        };
+
+        self.check_identifier(identifier, position);
+
+        let known = self.known(identifier);
+
+        match param {
+            Param::Simple { .. } => {
+                let is_set = known.is_some_and(|known| known.implies(Known::Set));
+
+                if let Identifier::Named(name) = identifier {
+                    if !is_set && self.linter.optional_vars.iter().any(|var| var == name) {
+                        let message =
+                            format!("'{name}' is optional but used without a default value");
+                        self.report(Rule::MissingDefault, message, position, None);
+                    }
+                }
+            },
+            Param::WithDefault {
+                default,
+                treat_empty_as_unset,
+                ..
+            } => {
+                let set = Known::set(*treat_empty_as_unset);
+
+                if known.is_some_and(|known| known.implies(set)) {
+                    let message = format!(
+                        "The default value of '{identifier}' is never used, since it's always \
+                         {set} here"
+                    );
+                    self.report(Rule::UnreachableBranch, message, position, None);
+                }
+
+                let is_empty = matches!(default.as_ref(), Node::Text(text, _) if text.is_empty());
+
+                if !treat_empty_as_unset && !is_empty {
+                    let message = format!(
+                        "The default value of '{identifier}' isn't used if it's empty, write \
+                         '${{{identifier}:-...}}' to use it then as well"
+                    );
+                    let fix = self.replace(
+                        position,
+                        &format!("${{{identifier}-"),
+                        format!("${{{identifier}:-"),
+                    );
+                    self.report(Rule::UnsetOnlyDefault, message, position, fix);
+                }
+
+                let unset = Known::unset(*treat_empty_as_unset);
+                self.walk_branch(identifier, unset, std::slice::from_ref(default.as_ref()));
+            },
+            Param::WithAlt {
+                alt,
+                treat_empty_as_unset,
+                ..
+            } => {
+                let unset = Known::unset(*treat_empty_as_unset);
+
+                if known.is_some_and(|known| known.implies(unset)) {
+                    let message = format!(
+                        "The alternative value of '{identifier}' is never used, since it's always \
+                         {unset} here"
+                    );
+                    self.report(Rule::UnreachableBranch, message, position, None);
+                }
+
+                let set = Known::set(*treat_empty_as_unset);
+                self.walk_branch(identifier, set, std::slice::from_ref(alt.as_ref()));
+            },
+            Param::WithError {
+                error,
+                treat_empty_as_unset,
+                ..
+            } => {
+                self.walk_branch(identifier, Known::unset(*treat_empty_as_unset), error);
+            },
+            #[cfg(feature = "length")]
+            Param::Length { .. } | Param::Arity => {},
+            #[cfg(feature = "indirection")]
+            Param::Ref { .. } => {},
+            Param::Conditional {
+                then, otherwise, ..
+            } => {
+                self.walk_branch(identifier, Known::NonEmpty, then);
+                self.walk_branch(identifier, Known::UnsetOrEmpty, otherwise);
+            },
+        }
+    }
+
+    /// Checks how the identifier of a param is written.
+    fn check_identifier(&mut self, identifier: &'b Identifier<'a>, position: Position) {
+        let is_braced = self.input[position.index..].starts_with("${");
+
+        if let Identifier::Dynamic(parts) = identifier {
+            self.walk(parts);
+        }
+
+        if let Identifier::Named(name) = identifier {
+            if let Some(digits) = name
+                .find(|c: char| !c.is_ascii_digit())
+                .filter(|index| *index > 0)
+                .map(|index| &name[..index])
+            {
+                let suggestion = format!("${{{digits}}}{}", &name[digits.len()..]);
+                let message = format!(
+                    "'{name}' is read as a named variable, write '{suggestion}' for the \
+                     positional variable {digits} followed by text"
+                );
+                let fix = (!is_braced)
+                    .then(|| self.replace(position, &format!("${name}"), suggestion))
+                    .flatten();
+                self.report(Rule::DigitPrefixedName, message, position, fix);
+            }
+        }
+
+        if !is_braced && !matches!(identifier, Identifier::Special(_)) {
+            let message =
+                format!("'${identifier}' is not braced, write '${{{identifier}}}' instead");
+            let fix = self.replace(
+                position,
+                &format!("${identifier}"),
+                format!("${{{identifier}}}"),
+            );
+            self.report(Rule::UnbracedVariable, message, position, fix);
+        }
+    }
+
+    fn walk_branch(&mut self, identifier: &'b Identifier<'a>, known: Known, nodes: &'b [Node<'a>]) {
+        self.known.push((identifier, known));
+        self.walk(nodes);
+        self.known.pop();
+    }
+
+    /// Returns what's known about the variable in the branch being walked. Variables required
+    /// or given a default by the front matter are always set.
+    fn known(&self, identifier: &Identifier) -> Option<Known> {
+        if let Some((_, known)) = self
+            .known
+            .iter()
+            .rev()
+            .find(|(other, _)| *other == identifier)
+        {
+            return Some(*known);
+        }
+
+        let Identifier::Named(name) = identifier else {
+            return None;
+        };
+
+        self.front_matter.and_then(|front_matter| {
+            let is_set = front_matter.required.iter().any(|other| other == name)
+                || front_matter.defaults.iter().any(|(other, _)| other == name);
+            is_set.then_some(Known::Set)
+        })
+    }
+
+    fn report(&mut self, rule: Rule, message: String, position: Position, fix: Option<Fix>) {
+        self.linter
+            .report(&mut self.lints, rule, message, position, fix);
+    }
+
+    /// Creates a fix replacing the given text starting at the given position, provided the
+    /// input contains that text there. The text might differ from the input if it was written
+    /// differently, such as `${01}` for `${1}`.
+    fn replace(&self, position: Position, text: &str, replacement: String) -> Option<Fix> {
+        let end = position.index + text.len();
+
+        (self.input.get(position.index..end) == Some(text))
+            .then(|| create_fix(self.line_index, position.index, end, replacement))
+    }
+}
+
+/// Finds the suppression comments in the input, returning the line of each along with the
+/// rules it allows, or [`None`] if it allows all rules.
+fn suppressions(input: &str) -> Vec<(usize, Option<Vec<Rule>>)> {
+    let mut suppressions = Vec::new();
+
+    for (index, line) in input.lines().enumerate() {
+        let Some((_, rest)) = line.split_once(SUPPRESSION_MARKER) else {
+            continue;
+        };
+
+        let mut rules = Vec::new();
+        let mut all = false;
+
+        // Stops at the first word which isn't a rule, such as the end of an HTML comment
+        for word in rest.split([',', ' ', '\t']).filter(|word| !word.is_empty()) {
+            if word == "all" {
+                all = true;
+                break;
+            }
+
+            match word.parse() {
+                Ok(rule) => rules.push(rule),
+                Err(_) => break,
+            }
+        }
+
+        suppressions.push((index + 1, (!all).then_some(rules)));
+    }
+
+    suppressions
+}
+
+fn create_fix(line_index: &LineIndex, start: usize, end: usize, replacement: String) -> Fix {
+    Fix {
+        span: Span::new(line_index.position(start), line_index.position(end)),
+        replacement,
+    }
+}
+
+/// Applies the given fixes to the input in order of their position, skipping fixes which
+/// overlap a fix before them. Returns the fixed input along with the number of fixes applied.
+fn apply_fixes<'a>(input: &str, fixes: impl Iterator<Item = &'a Fix>) -> (String, usize) {
+    let mut fixes = fixes.collect::<Vec<_>>();
+    fixes.sort_by_key(|fix| fix.span.start.index);
+
+    let mut output = String::with_capacity(input.len());
+    let mut end = 0;
+    let mut count = 0;
+
+    for fix in fixes {
+        if fix.span.start.index < end {
+            continue;
+        }
+
+        output.push_str(&input[end..fix.span.start.index]);
+        output.push_str(&fix.replacement);
+        end = fix.span.end.index;
+        count += 1;
+    }
+
+    output.push_str(&input[end..]);
+
+    (output, count)
+}
@@ -0,0 +1,187 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// The number of latency buckets, the upper bounds of which are powers of two microseconds from
+/// 1µs up to about 17 seconds. Slower lookups are counted in an additional overflow bucket.
+const BUCKETS: usize = 25;
+
+/// Counts the lookups of a single provider. Updated concurrently by all expansions using the
+/// provider, so all counters are atomic.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    failures: AtomicU64,
+    total_micros: AtomicU64,
+    max_micros: AtomicU64,
+    buckets: [AtomicU64; BUCKETS + 1],
+}
+
+impl Recorder {
+    pub fn record<T>(&self, result: &Result<Option<T>, String>, latency: Duration) {
+        let counter = match result {
+            Ok(Some(_)) => &self.hits,
+            Ok(None) => &self.misses,
+            Err(_) => &self.failures,
+        };
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        let bucket = (0..BUCKETS)
+            .find(|bucket| micros <= 1 << bucket)
+            .unwrap_or(BUCKETS);
+
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.total_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ProviderMetrics {
+        ProviderMetrics {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            total_latency: Duration::from_micros(self.total_micros.load(Ordering::Relaxed)),
+            max_latency: Duration::from_micros(self.max_micros.load(Ordering::Relaxed)),
+            buckets: self
+                .buckets
+                .iter()
+                .map(|bucket| bucket.load(Ordering::Relaxed))
+                .collect(),
+        }
+    }
+}
+
+/// A snapshot of the lookups made using a single [`Provider`](crate::Provider), as returned by
+/// [`Xpanda::provider_metrics`](crate::Xpanda::provider_metrics).
+///
+/// Latencies are measured in microseconds, and recorded in buckets whose upper bounds are powers
+/// of two, like a Prometheus histogram. Percentiles are therefore estimates, which are at most
+/// twice the actual latency.
+///
+/// # Examples
+///
+/// ```
+/// use xpanda::Xpanda;
+///
+/// let xpanda = Xpanda::builder()
+///     .register_namespace("upper", |key: &str| Ok(Some(key.to_uppercase())))
+///     .build();
+///
+/// assert_eq!(xpanda.expand("${upper:a}${upper:b}"), Ok(String::from("AB")));
+///
+/// let metrics = &xpanda.provider_metrics()["upper"];
+/// assert_eq!(metrics.lookups(), 2);
+/// assert_eq!(metrics.hits(), 2);
+/// assert!(metrics.latency_percentile(0.99).is_some());
+/// ```
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ProviderMetrics {
+    hits: u64,
+    misses: u64,
+    failures: u64,
+    total_latency: Duration,
+    max_latency: Duration,
+    buckets: Vec<u64>,
+}
+
+impl ProviderMetrics {
+    /// The number of lookups, whatever their outcome.
+    #[must_use]
+    pub const fn lookups(&self) -> u64 {
+        self.hits + self.misses + self.failures
+    }
+
+    /// The number of lookups which yielded a value.
+    #[must_use]
+    pub const fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// The number of lookups of keys which are unset.
+    #[must_use]
+    pub const fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// The number of lookups which failed with an error.
+    #[must_use]
+    pub const fn failures(&self) -> u64 {
+        self.failures
+    }
+
+    /// The sum of the latencies of all lookups.
+    #[must_use]
+    pub const fn total_latency(&self) -> Duration {
+        self.total_latency
+    }
+
+    /// The latency of the slowest lookup.
+    #[must_use]
+    pub const fn max_latency(&self) -> Duration {
+        self.max_latency
+    }
+
+    /// Estimates the latency which the given fraction of lookups didn't exceed, e.g. `0.99` for
+    /// the 99th percentile. Returns [`None`] if there were no lookups.
+    #[must_use]
+    pub fn latency_percentile(&self, quantile: f64) -> Option<Duration> {
+        let lookups = self.lookups();
+
+        if lookups == 0 {
+            return None;
+        }
+
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_precision_loss,
+            clippy::cast_sign_loss
+        )]
+        let rank = ((quantile.clamp(0.0, 1.0) * lookups as f64).ceil() as u64).max(1);
+
+        let latency = self
+            .latency_buckets()
+            .into_iter()
+            .find(|(_, count)| *count >= rank)
+            .map_or(self.max_latency, |(bound, _)| bound.min(self.max_latency));
+
+        Some(latency)
+    }
+
+    /// Returns the upper bound of each latency bucket together with the number of lookups which
+    /// didn't exceed it, in ascending order. Lookups slower than the last bound are only
+    /// included in [`ProviderMetrics::lookups`].
+    #[must_use]
+    pub fn latency_buckets(&self) -> Vec<(Duration, u64)> {
+        self.buckets
+            .iter()
+            .take(BUCKETS)
+            .scan(0, |total, count| {
+                *total += count;
+                Some(*total)
+            })
+            .enumerate()
+            .map(|(bucket, total)| (Duration::from_micros(1 << bucket), total))
+            .collect()
+    }
+}
+
+/// A phase of expanding a template, see [`Timing`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum Phase {
+    /// Splitting the text of the template into tokens.
+    Lex,
+    /// Building the template from the tokens, excluding the time spent lexing.
+    Parse,
+    /// Evaluating the params of the parsed template.
+    Eval,
+}
+
+/// How long a single phase of expanding a template took, see
+/// [`Builder::on_timing`](crate::Builder::on_timing).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Timing {
+    pub phase: Phase,
+    pub duration: Duration,
+}
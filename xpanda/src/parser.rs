@@ -1,45 +1,117 @@
-use crate::ast::{Ast, Identifier, Modifier, Node, Param};
+use crate::ast::{Ast, Identifier, Modifier, Node, Param, Raw};
+#[cfg(feature = "expr")]
+use crate::expr::Expression;
 use crate::forward_peekable::{ForwardPeekable, IteratorExt};
 use crate::lexer::{self, Lexer};
-use crate::position::Position;
+use crate::position::{LineIndex, Position, Span};
+use crate::syntax::{Syntax, ELSE, END, IF, RAW_BLOCK_CLOSE};
 use crate::token::Token;
+use std::borrow::Cow;
+
+/// The maximum number of braced params, conditional blocks and expressions nested inside each
+/// other, counted together. Evaluating is iterative, but parsing is recursive, as are traversals
+/// of the AST such as formatting it, so deeper nesting would risk overflowing the stack.
+pub const MAX_NESTING: usize = 64;
+
+/// The maximum argument of modifiers such as `${VAR@pad:width}`, which is the largest width or
+/// precision supported by [`format!`].
+pub const MAX_MODIFIER_NUMBER: usize = u16::MAX as usize;
+
+/// The error messages for params rejected by [`Syntax::safe_mode`], also used when rendering
+/// templates which were parsed without it.
+pub const INDIRECTION_IN_SAFE_MODE: &str = "Indirect params are not allowed in safe mode";
+pub const NAMESPACE_IN_SAFE_MODE: &str = "Namespaced variables are not allowed in safe mode";
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Error {
     pub message: String,
     pub position: Position,
+    pub span: Span,
 }
 
 impl Error {
-    const fn new(message: String, position: Position) -> Self {
-        Self { message, position }
+    const fn new(message: String, position: Position, span: Span) -> Self {
+        Self {
+            message,
+            position,
+            span,
+        }
     }
 }
 
 pub struct Parser<'a> {
     iter: ForwardPeekable<lexer::IterMut<'a>>,
-    position: Option<Position>,
+    source: &'a str,
+    /// Built on first use, as only params and errors need line and column numbers.
+    lines: Option<LineIndex<'a>>,
+    /// The byte offset of the end of the most recently consumed token.
+    position: Option<usize>,
+    /// The byte offset of the start of the most recently consumed token.
+    token_start: usize,
+    /// The length of the line break left out at the start of the next text token, see
+    /// [`Self::skip_line_break`].
+    skipped_line_break: usize,
+    /// The byte offsets of the `${` of all braced params currently being parsed, innermost last.
+    open_params: Vec<usize>,
+    /// The number of conditional blocks currently being parsed.
+    open_blocks: usize,
+    /// The text of each param outside of other params as written in the source, in order.
+    param_texts: Vec<Cow<'a, str>>,
+    syntax: Syntax,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(lexer: Lexer<'a>) -> Self {
         Self {
+            syntax: lexer.syntax().clone(),
+            source: lexer.source(),
+            // Not necessarily 0, as front matter is skipped
+            token_start: lexer.index(),
             iter: lexer.into_iter().forward_peekable(),
+            lines: None,
             position: None,
+            skipped_line_break: 0,
+            open_params: Vec::new(),
+            open_blocks: 0,
+            param_texts: Vec::new(),
         }
     }
 
-    pub fn parse(&mut self) -> Result<Ast<'a>, Error> {
+    /// Parses all tokens into an AST. If parsing fails, the error is returned together with the
+    /// nodes parsed before it.
+    pub fn parse(&mut self) -> Result<Ast<'a>, (Error, Ast<'a>)> {
         let mut nodes = Vec::new();
 
-        while self.peek_token().is_some() {
-            let node = self.parse_node()?;
-            nodes.push(node);
+        while let Some(token) = self.peek_token() {
+            // Comment lines are left out of the AST
+            if *token == Token::Comment {
+                self.skip_token();
+                continue;
+            }
+
+            match self.parse_outer_node() {
+                Ok(node) => {
+                    if let Node::Param(_, position) = &node {
+                        let end = self.position.unwrap_or(position.index);
+                        self.param_texts
+                            .push(Cow::Borrowed(&self.source[position.index..end]));
+                    }
+
+                    nodes.push(node);
+                },
+                Err(error) => return Err((error, Ast::new(nodes))),
+            }
         }
 
         Ok(Ast::new(nodes))
     }
 
+    /// Returns the text of each param outside of other params which was parsed, as written in
+    /// the source.
+    pub fn take_param_texts(&mut self) -> Vec<Cow<'a, str>> {
+        std::mem::take(&mut self.param_texts)
+    }
+
     #[must_use]
     fn peek_token(&mut self) -> Option<&Token<'a>> {
         self.iter.peek().map(|(token, _)| token)
@@ -48,6 +120,7 @@ impl<'a> Parser<'a> {
     #[must_use]
     fn next_token(&mut self) -> Option<Token<'a>> {
         if let Some((token, position)) = self.iter.next() {
+            self.token_start = self.position.unwrap_or(self.token_start);
             self.position = Some(position);
             Some(token)
         } else {
@@ -61,54 +134,325 @@ impl<'a> Parser<'a> {
 
     fn expect_token(&mut self, expected: &Token<'a>) -> Result<(), Error> {
         match self.next_token() {
-            Some(expected) => Ok(()),
+            Some(token) if &token == expected => Ok(()),
             Some(unexpected) => {
-                Err(self.create_error(format!("Expected {}, found {}", expected, unexpected)))
+                Err(self.create_error(format!("Expected {expected}, found {unexpected}")))
             },
-            _ => Err(self.create_error(format!("Expected {}, found EOF", expected))),
+            _ => Err(self.eof_error(format!("Expected {expected}, found EOF"))),
         }
     }
 
+    /// Returns the number of params and conditional blocks the parser is inside of.
+    const fn nesting(&self) -> usize {
+        self.open_params.len() + self.open_blocks
+    }
+
     fn parse_node(&mut self) -> Result<Node<'a>, Error> {
         match self.peek_token() {
-            Some(Token::Text(_)) => Ok(Node::Text(
-                self.parse_text()?.unwrap_or_else(|| String::from("")),
-            )),
+            Some(Token::Text(_) | Token::Raw(_)) => self.parse_text_node(),
+            Some(Token::UnclosedRaw) => {
+                self.skip_token();
+                let opened_at = self.token_start;
+                let msg = format!("Unclosed raw block, expected a matching '{RAW_BLOCK_CLOSE}'");
+                Err(self.tag_error(msg, opened_at))
+            },
             Some(Token::DollarSign) => {
                 self.skip_token();
-                Ok(Node::Param(self.parse_param()?))
+                let position = self.position_at(self.token_start);
+                Ok(Node::Param(self.parse_param()?, position))
             },
             Some(token) => {
-                let msg = format!("Unexpected token {}", token);
+                let msg = format!("Unexpected token {token}");
                 Err(self.create_error(msg))
             },
-            _ => Err(self.create_error("Unexpected EOF")),
+            _ => Err(self.eof_error("Unexpected EOF")),
+        }
+    }
+
+    /// Parses a node outside of params, which is either a conditional block or any other node.
+    fn parse_outer_node(&mut self) -> Result<Node<'a>, Error> {
+        match self.peek_keyword() {
+            Some(keyword) => self.parse_conditional(keyword),
+            None => self.parse_node(),
+        }
+    }
+
+    /// Returns the keyword of the tag of a conditional block which follows, as in `${if VAR}`,
+    /// if conditionals are enabled. Tags are only recognized outside of params.
+    fn peek_keyword(&mut self) -> Option<&'static str> {
+        if !self.syntax.conditionals || !self.open_params.is_empty() {
+            return None;
+        }
+
+        let mut peek = |n| self.iter.peek_nth(n).map(|(token, _)| token.clone());
+
+        if peek(0) != Some(Token::DollarSign) || peek(1) != Some(Token::OpenBrace) {
+            return None;
+        }
+
+        match (peek(2), peek(3)) {
+            (Some(Token::Identifier(IF)), Some(Token::Text(text)))
+                if text.starts_with(char::is_whitespace) =>
+            {
+                Some(IF)
+            },
+            (Some(Token::Identifier(ELSE)), Some(Token::CloseBrace)) => Some(ELSE),
+            (Some(Token::Identifier(END)), Some(Token::CloseBrace)) => Some(END),
+            _ => None,
+        }
+    }
+
+    /// Parses a conditional block starting with the given keyword, which is an error for
+    /// anything but `if`, as the other tags end the blocks they're part of.
+    fn parse_conditional(&mut self, keyword: &str) -> Result<Node<'a>, Error> {
+        self.skip_token();
+        let opened_at = self.token_start;
+        let position = self.position_at(opened_at);
+        self.skip_token();
+        self.skip_token();
+
+        if keyword != IF {
+            self.skip_token();
+            let msg = format!("Unexpected '${{{keyword}}}' outside of a conditional block");
+            return Err(self.tag_error(msg, opened_at));
+        }
+
+        let text = self.parse_text()?.unwrap_or_default();
+        let identifier = self.parse_condition(&text)?;
+        self.expect_token(&Token::CloseBrace)?;
+
+        let tag = Span::new(
+            position,
+            self.position_at(self.position.unwrap_or(opened_at)),
+        );
+        self.skip_line_break();
+
+        self.open_blocks += 1;
+
+        if self.nesting() > MAX_NESTING {
+            let msg =
+                format!("Conditional blocks can't be nested more than {MAX_NESTING} levels deep");
+            return Err(Error::new(msg, tag.start, tag));
+        }
+
+        let (then, end) = self.parse_block(tag, true)?;
+        let otherwise = if end == ELSE {
+            self.parse_block(tag, false)?.0
+        } else {
+            Vec::new()
+        };
+
+        self.open_blocks -= 1;
+
+        Ok(Node::Param(
+            Param::Conditional {
+                identifier,
+                then: then.into_boxed_slice(),
+                otherwise: otherwise.into_boxed_slice(),
+            },
+            position,
+        ))
+    }
+
+    /// Parses the nodes of a conditional block up until its `${else}`, if allowed, or `${end}`,
+    /// returning the nodes along with the keyword which ended them. The tag which opened the
+    /// block is where an error is reported if the block is never closed.
+    fn parse_block(
+        &mut self,
+        tag: Span,
+        allow_else: bool,
+    ) -> Result<(Vec<Node<'a>>, &'static str), Error> {
+        let mut nodes = Vec::new();
+
+        loop {
+            match self.peek_token() {
+                None => {
+                    let msg = format!("Unclosed conditional block, expected '${{{END}}}'");
+                    return Err(Error::new(msg, tag.start, tag));
+                },
+                // Comment lines are left out of the AST
+                Some(Token::Comment) => {
+                    self.skip_token();
+                    continue;
+                },
+                Some(_) => {},
+            }
+
+            if let Some(keyword @ (ELSE | END)) = self.peek_keyword() {
+                self.skip_token();
+                let opened_at = self.token_start;
+
+                for _ in 0..3 {
+                    self.skip_token();
+                }
+
+                if keyword == ELSE && !allow_else {
+                    let msg = format!("Unexpected second '${{{ELSE}}}', expected '${{{END}}}'");
+                    return Err(self.tag_error(msg, opened_at));
+                }
+
+                self.skip_line_break();
+
+                return Ok((nodes, keyword));
+            }
+
+            nodes.push(self.parse_outer_node()?);
+        }
+    }
+
+    /// Parses the variable a conditional block depends on, as in `${if VAR}`, which is a named
+    /// or positional variable surrounded by whitespace.
+    fn parse_condition(&mut self, text: &Cow<'a, str>) -> Result<Identifier<'a>, Error> {
+        let name = match text {
+            Cow::Borrowed(text) => text.trim(),
+            Cow::Owned(_) => "",
+        };
+
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(self.create_error(format!(
+                "Invalid condition '{}', expected a variable name",
+                text.trim()
+            )));
+        }
+
+        if name.bytes().all(|byte| byte.is_ascii_digit()) {
+            return name
+                .parse()
+                .map(Identifier::Indexed)
+                .map_err(|_| self.create_error(format!("Index '{name}' is out of range")));
+        }
+
+        if name.starts_with(char::is_numeric) && !self.syntax.lenient_identifiers {
+            return Err(self.create_error(format!(
+                "Invalid identifier '{name}', identifiers can't start with a digit"
+            )));
+        }
+
+        Ok(Identifier::Named(name))
+    }
+
+    /// Leaves out the line break directly following the tag of a conditional block, so that
+    /// tags on lines of their own don't leave empty lines behind.
+    fn skip_line_break(&mut self) {
+        let Some((Token::Text(text), _)) = self.iter.peek_mut() else {
+            return;
+        };
+
+        let len = if text.starts_with("\r\n") {
+            2
+        } else {
+            usize::from(text.starts_with('\n'))
+        };
+
+        match text {
+            Cow::Borrowed(text) => *text = &text[len..],
+            Cow::Owned(text) => {
+                text.drain(..len);
+            },
+        }
+
+        // Nothing is left of text which was only a line break
+        if text.is_empty() {
+            self.skip_token();
+        } else {
+            self.skipped_line_break = len;
+        }
+    }
+
+    /// Parses text or a raw block, along with its raw form. This is kept out of
+    /// [`Self::parse_node`], which is recursive, so as not to grow its stack frame.
+    fn parse_text_node(&mut self) -> Result<Node<'a>, Error> {
+        let text = match self.next_token() {
+            Some(Token::Text(text)) => text,
+            Some(Token::Raw(text)) => Cow::Borrowed(text),
+            Some(token) => return Err(self.create_error(format!("Expected text, found {token}"))),
+            None => Cow::default(),
+        };
+
+        Ok(Node::Text(text, Some(self.raw())))
+    }
+
+    /// Returns the most recently consumed token as written in the source, which is the raw form
+    /// of the text it was read as.
+    fn raw(&mut self) -> Raw<'a> {
+        let start = self.token_start + std::mem::take(&mut self.skipped_line_break);
+        let end = self.position.unwrap_or(start);
+
+        Raw {
+            text: &self.source[start..end],
+            in_param: !self.open_params.is_empty(),
         }
     }
 
+    /// Creates an error for a misplaced tag of a conditional block, spanning the whole tag.
+    fn tag_error(&mut self, msg: String, opened_at: usize) -> Error {
+        let start = self.position_at(opened_at);
+        let end = self.position_at(self.position.unwrap_or(opened_at));
+
+        Error::new(msg, start, Span::new(start, end))
+    }
+
     fn parse_param(&mut self) -> Result<Param<'a>, Error> {
         match self.peek_token() {
             Some(Token::OpenBrace) => {
+                // The current offset is right after the `$`
+                let opened_at = self
+                    .position
+                    .map_or(0, |position| position.saturating_sub(1));
+
                 self.skip_token();
+                self.open_params.push(opened_at);
+
+                if self.nesting() > MAX_NESTING {
+                    let msg = format!("Params can't be nested more than {MAX_NESTING} levels deep");
+                    let start = self.position_at(opened_at);
+                    let end = self.position_at(opened_at + 2);
+                    return Err(Error::new(msg, start, Span::new(start, end)));
+                }
 
                 let param = match self.peek_token() {
+                    #[cfg(feature = "length")]
                     Some(Token::PoundSign) => self.parse_len_or_arity_param(),
+                    #[cfg(not(feature = "length"))]
+                    Some(Token::PoundSign) => Err(self.unsupported("Length params", "length")),
+                    #[cfg(feature = "indirection")]
                     Some(Token::ExclamationMark) => self.parse_ref_param(),
+                    #[cfg(not(feature = "indirection"))]
+                    Some(Token::ExclamationMark) => {
+                        Err(self.unsupported("Indirect params", "indirection"))
+                    },
+                    Some(Token::CloseBrace) => {
+                        self.skip_token();
+                        Err(self.create_error("Empty param"))
+                    },
                     Some(_) => {
-                        let identifier = self.parse_identifier()?;
+                        let identifier = self.parse_name()?;
+                        let is_pattern_operator = self.is_pattern_operator();
 
                         match self.peek_token() {
+                            #[cfg(feature = "case-modifiers")]
                             Some(Token::Caret) => self.parse_uppercase_param(identifier),
+                            #[cfg(feature = "case-modifiers")]
                             Some(Token::Comma) => self.parse_lowercase_param(identifier),
+                            #[cfg(feature = "case-modifiers")]
                             Some(Token::Tilde) => self.parse_reverse_case_param(identifier),
+                            #[cfg(not(feature = "case-modifiers"))]
+                            Some(Token::Caret | Token::Comma | Token::Tilde) => {
+                                Err(self.unsupported("Case modifiers", "case-modifiers"))
+                            },
+                            Some(Token::AtSign) => self.parse_named_modifier_param(identifier),
+                            Some(Token::Text(_)) if is_pattern_operator => {
+                                self.parse_pattern_param(identifier)
+                            },
                             Some(_) => self.parse_default_alt_error_or_sub_param(identifier),
-                            _ => Err(self.create_error("Invalid param, unexpected EOF")),
+                            _ => Err(self.eof_error("Invalid param, unexpected EOF")),
                         }
                     },
-                    None => Err(self.create_error("Expected param, found EOF")),
+                    None => Err(self.eof_error("Expected param, found EOF")),
                 }?;
 
                 self.expect_token(&Token::CloseBrace)?;
+                self.open_params.pop();
 
                 Ok(param)
             },
@@ -116,23 +460,29 @@ impl<'a> Parser<'a> {
         }
     }
 
+    #[cfg(feature = "length")]
     fn parse_len_or_arity_param(&mut self) -> Result<Param<'a>, Error> {
-        self.expect_token(&Token::ExclamationMark)?;
+        self.expect_token(&Token::PoundSign)?;
 
         match self.peek_token() {
             Some(Token::CloseBrace) => Ok(Param::Arity),
             Some(_) => Ok(Param::Length {
-                identifier: self.parse_identifier()?,
+                identifier: self.parse_name()?,
             }),
-            _ => Err(self.create_error("Expected identifier or close brace, found EOF")),
+            _ => Err(self.eof_error("Expected identifier or close brace, found EOF")),
         }
     }
 
+    #[cfg(feature = "indirection")]
     fn parse_ref_param(&mut self) -> Result<Param<'a>, Error> {
         self.expect_token(&Token::ExclamationMark)?;
 
+        if self.syntax.safe_mode {
+            return Err(self.create_error(INDIRECTION_IN_SAFE_MODE));
+        }
+
         Ok(Param::Ref {
-            identifier: self.parse_identifier()?,
+            identifier: self.parse_name()?,
         })
     }
 
@@ -154,7 +504,7 @@ impl<'a> Parser<'a> {
 
                 Ok(Param::WithDefault {
                     identifier,
-                    default: Box::new(self.parse_node()?),
+                    default: Box::new(self.parse_value_node()?),
                     treat_empty_as_unset,
                 })
             },
@@ -163,19 +513,22 @@ impl<'a> Parser<'a> {
 
                 Ok(Param::WithAlt {
                     identifier,
-                    alt: Box::new(self.parse_node()?),
+                    alt: Box::new(self.parse_value_node()?),
                     treat_empty_as_unset,
                 })
             },
             Some(Token::QuestionMark) => {
                 self.skip_token();
 
+                let mut error = Vec::new();
+
+                while matches!(self.peek_token(), Some(Token::Text(_) | Token::DollarSign)) {
+                    error.push(self.parse_node()?);
+                }
+
                 Ok(Param::WithError {
                     identifier,
-                    error: match self.peek_token() {
-                        Some(Token::Text(_)) => self.parse_text()?,
-                        _ => None,
-                    },
+                    error,
                     treat_empty_as_unset,
                 })
             },
@@ -184,13 +537,58 @@ impl<'a> Parser<'a> {
                 modifier: None,
             }),
             Some(token) => {
-                let msg = format!("Invalid param, unexpected token {}", token);
+                let msg = format!("Invalid param, unexpected token {token}");
                 Err(self.create_error(msg))
             },
-            _ => Err(self.create_error("Invalid param, unexpected EOF")),
+            _ => Err(self.eof_error("Invalid param, unexpected EOF")),
+        }
+    }
+
+    /// Parses the default or alternative value of a param, which is an expression if it starts
+    /// with an unescaped `=` and expressions are enabled.
+    fn parse_value_node(&mut self) -> Result<Node<'a>, Error> {
+        #[cfg(feature = "expr")]
+        {
+            // The next token starts where the most recently consumed token ended
+            let start = self.position.unwrap_or(self.token_start);
+            let is_expression = self.syntax.expressions
+                && matches!(self.peek_token(), Some(Token::Text(_)))
+                && self.source[start..].starts_with('=');
+
+            if is_expression {
+                return self.parse_expression();
+            }
         }
+
+        self.parse_node()
+    }
+
+    #[cfg(feature = "expr")]
+    fn parse_expression(&mut self) -> Result<Node<'a>, Error> {
+        let Some(Token::Text(text)) = self.next_token() else {
+            return Err(self.create_error("Expected expression"));
+        };
+
+        let start = self.token_start;
+        let end = self.position.unwrap_or(start);
+        let position = self.position_at(start);
+        let source = text.strip_prefix('=').unwrap_or(&text);
+
+        Expression::parse(source, self.nesting())
+            .map(|expression| Node::Expr(expression, position))
+            .map_err(|(message, offset)| {
+                // Offsets are only exact if the expression contains no escape sequences
+                let index = (start + 1 + offset).min(end);
+                let index = (0..=index)
+                    .rev()
+                    .find(|index| self.source.is_char_boundary(*index))
+                    .unwrap_or_default();
+                let position = self.position_at(index);
+                Error::new(message, position, Span::new(position, position))
+            })
     }
 
+    #[cfg(feature = "case-modifiers")]
     fn parse_uppercase_param(&mut self, identifier: Identifier<'a>) -> Result<Param<'a>, Error> {
         self.expect_token(&Token::Caret)?;
 
@@ -207,6 +605,7 @@ impl<'a> Parser<'a> {
         })
     }
 
+    #[cfg(feature = "case-modifiers")]
     fn parse_lowercase_param(&mut self, identifier: Identifier<'a>) -> Result<Param<'a>, Error> {
         self.expect_token(&Token::Comma)?;
 
@@ -223,6 +622,7 @@ impl<'a> Parser<'a> {
         })
     }
 
+    #[cfg(feature = "case-modifiers")]
     fn parse_reverse_case_param(&mut self, identifier: Identifier<'a>) -> Result<Param<'a>, Error> {
         self.expect_token(&Token::Tilde)?;
 
@@ -239,6 +639,129 @@ impl<'a> Parser<'a> {
         })
     }
 
+    fn parse_named_modifier_param(
+        &mut self,
+        identifier: Identifier<'a>,
+    ) -> Result<Param<'a>, Error> {
+        self.expect_token(&Token::AtSign)?;
+
+        let modifier = match self.next_token() {
+            Some(Token::Identifier(name)) => match name {
+                "urlencode" => Modifier::UrlEncode,
+                "urldecode" => Modifier::UrlDecode,
+                "base64" => Modifier::Base64Encode,
+                "base64d" => Modifier::Base64Decode,
+                "shellquote" => Modifier::ShellQuote,
+                "pad" => {
+                    let arg = self.parse_modifier_arg(name)?;
+
+                    Modifier::Pad {
+                        width: self.parse_modifier_number(name, &arg)?,
+                        zero: arg.len() > 1 && arg.starts_with('0'),
+                    }
+                },
+                "fixed" => {
+                    let arg = self.parse_modifier_arg(name)?;
+
+                    Modifier::Fixed {
+                        precision: self.parse_modifier_number(name, &arg)?,
+                    }
+                },
+                "trunc" => {
+                    let arg = self.parse_modifier_arg(name)?;
+                    let (width, ellipsis) = arg.split_once(':').unwrap_or((&*arg, ""));
+
+                    Modifier::Truncate {
+                        width: self.parse_modifier_number(name, width)?,
+                        ellipsis: ellipsis.to_string(),
+                    }
+                },
+                _ => return Err(self.create_error(format!("Unknown modifier '{name}'"))),
+            },
+            Some(token) => {
+                return Err(self.create_error(format!("Expected modifier name, found {token}")))
+            },
+            None => return Err(self.eof_error("Expected modifier name, found EOF")),
+        };
+
+        Ok(Param::Simple {
+            identifier,
+            modifier: Some(modifier),
+        })
+    }
+
+    /// Whether the next token starts with a pattern operator, as in `${VAR#pattern}`. The source
+    /// is checked rather than the text, in which a quoted symbol looks the same.
+    fn is_pattern_operator(&self) -> bool {
+        // The next token starts where the most recently consumed token ended
+        let start = self.position.unwrap_or(self.token_start);
+        self.source[start..].starts_with(['#', '%', '/'])
+    }
+
+    /// Parses a pattern operator, which removes the shortest (or longest) prefix or suffix
+    /// matching a pattern, or replaces the first (or every) match of it. The pattern of a
+    /// replacement ends at the first `/` which isn't escaped by a backslash.
+    fn parse_pattern_param(&mut self, identifier: Identifier<'a>) -> Result<Param<'a>, Error> {
+        let start = self.position.unwrap_or(self.token_start);
+        let mut symbols = self.source[start..].chars();
+        let symbol = symbols.next();
+        let doubled = symbols.next() == symbol;
+        let Some(Token::Text(text)) = self.next_token() else {
+            return Err(self.create_error("Expected pattern"));
+        };
+        let pattern = &text[if doubled { 2 } else { 1 }..];
+
+        let modifier = match symbol {
+            Some('#') => Modifier::RemovePrefix {
+                pattern: pattern.to_string(),
+                longest: doubled,
+            },
+            Some('%') => Modifier::RemoveSuffix {
+                pattern: pattern.to_string(),
+                longest: doubled,
+            },
+            _ => {
+                let (pattern, replacement) = split_pattern(pattern);
+
+                Modifier::Replace {
+                    pattern: pattern.to_string(),
+                    replacement: replacement.to_string(),
+                    all: doubled,
+                }
+            },
+        };
+
+        Ok(Param::Simple {
+            identifier,
+            modifier: Some(modifier),
+        })
+    }
+
+    fn parse_modifier_arg(&mut self, name: &str) -> Result<Cow<'a, str>, Error> {
+        if self.peek_token() != Some(&Token::Colon) {
+            return Err(self.create_error(format!("Modifier '{name}' expects an argument")));
+        }
+
+        self.skip_token();
+
+        let arg = self.parse_text()?;
+
+        arg.ok_or_else(|| self.eof_error(format!("Modifier '{name}' expects an argument")))
+    }
+
+    fn parse_modifier_number(&mut self, name: &str, arg: &str) -> Result<usize, Error> {
+        match arg.parse() {
+            Ok(number) if number <= MAX_MODIFIER_NUMBER => Ok(number),
+            Ok(_) => Err(self.create_error(format!(
+                "Argument '{arg}' for modifier '{name}' is too large, expected at most \
+                 {MAX_MODIFIER_NUMBER}"
+            ))),
+            Err(_) => Err(self.create_error(format!(
+                "Invalid argument '{arg}' for modifier '{name}', expected a number"
+            ))),
+        }
+    }
+
     fn parse_simple_param(&mut self) -> Result<Param<'a>, Error> {
         let identifier = self.parse_identifier()?;
         Ok(Param::Simple {
@@ -247,24 +770,152 @@ impl<'a> Parser<'a> {
         })
     }
 
-    fn parse_text(&mut self) -> Result<Option<String>, Error> {
+    fn parse_text(&mut self) -> Result<Option<Cow<'a, str>>, Error> {
         match self.next_token() {
             Some(Token::Text(text)) => Ok(Some(text)),
-            Some(token) => Err(self.create_error(format!("Expected text, found {}", token))),
+            Some(token) => Err(self.create_error(format!("Expected text, found {token}"))),
             None => Ok(None),
         }
     }
 
+    /// Parses the identifier of a braced param. If dynamic names are enabled, a name may contain
+    /// params, as in `${PREFIX_${ENV}_SUFFIX}`, which yields a dynamic identifier.
+    fn parse_name(&mut self) -> Result<Identifier<'a>, Error> {
+        if !self.syntax.dynamic_names {
+            return self.parse_identifier();
+        }
+
+        let mut parts = Vec::new();
+
+        if self.peek_token() != Some(&Token::DollarSign) {
+            match self.parse_identifier()? {
+                Identifier::Named(name) if self.peek_token() == Some(&Token::DollarSign) => {
+                    parts.push(Node::Text(Cow::Borrowed(name), None));
+                },
+                identifier => return Ok(identifier),
+            }
+        }
+
+        loop {
+            match self.peek_token() {
+                Some(Token::DollarSign) => parts.push(self.parse_node()?),
+                Some(Token::Identifier(text) | Token::Index(text)) => {
+                    let text = Cow::Borrowed(*text);
+                    self.skip_token();
+                    parts.push(Node::Text(text, None));
+                },
+                _ => return Ok(Identifier::Dynamic(parts.into_boxed_slice())),
+            }
+        }
+    }
+
     fn parse_identifier(&mut self) -> Result<Identifier<'a>, Error> {
         match self.next_token() {
-            Some(Token::Identifier(name)) => Ok(Identifier::Named(name)),
-            Some(Token::Index(index)) => Ok(Identifier::Indexed(index)),
-            Some(token) => Err(self.create_error(format!("Expected identifier, found {}", token))),
-            None => Err(self.create_error("Expected identifier, found EOF")),
+            Some(Token::Identifier(name)) => {
+                if name.starts_with(char::is_numeric) && !self.syntax.lenient_identifiers {
+                    return Err(self.token_error(format!(
+                        "Invalid identifier '{name}', identifiers can't start with a digit"
+                    )));
+                }
+
+                // A lowercase name followed by a colon and text, as in `${env:HOME}`, selects a
+                // namespace. Any other token following a colon is part of a pattern such as
+                // `${VAR:-text}`, and text following the colon after other names is invalid.
+                let is_namespaced = !name.contains(char::is_uppercase)
+                    && self.iter.peek_nth(0).map(|(token, _)| token) == Some(&Token::Colon)
+                    && matches!(self.iter.peek_nth(1), Some((Token::Text(_), _)));
+
+                if !is_namespaced {
+                    return Ok(Identifier::Named(name));
+                }
+
+                if self.syntax.safe_mode {
+                    return Err(self.create_error(NAMESPACE_IN_SAFE_MODE));
+                }
+
+                self.skip_token();
+
+                Ok(Identifier::Namespaced {
+                    namespace: name,
+                    key: self.parse_text()?.unwrap_or_default(),
+                })
+            },
+            Some(Token::Index(index)) => index
+                .parse()
+                .map(Identifier::Indexed)
+                .map_err(|_| self.token_error(format!("Index '{index}' is out of range"))),
+            Some(Token::Special(c)) => Ok(Identifier::Special(c)),
+            Some(token) => Err(self.create_error(format!("Expected identifier, found {token}"))),
+            None => Err(self.eof_error("Expected identifier, found EOF")),
         }
     }
 
+    /// Creates an error for unexpectedly reaching the end of the input. Within a braced param
+    /// the error is instead reported at the `${` which was never closed, so that its location
+    /// completes the message, as in "Expected a matching '}' for the '${' opened at line 1,
+    /// column 1".
+    fn eof_error(&mut self, msg: impl Into<String>) -> Error {
+        if let Some(&opened_at) = self.open_params.last() {
+            let msg = String::from("Expected a matching '}' for the '${' opened");
+            let start = self.position_at(opened_at);
+            let end = self.position_at(opened_at + 2);
+            return Error::new(msg, start, Span::new(start, end));
+        }
+
+        self.create_error(msg)
+    }
+
+    /// Creates an error for syntax which is compiled out by a disabled cargo feature, located at
+    /// the next token.
+    fn unsupported(&mut self, syntax: &str, feature: &str) -> Error {
+        self.skip_token();
+        self.create_error(format!(
+            "{syntax} are not supported, xpanda was built without the '{feature}' feature"
+        ))
+    }
+
+    /// Creates an error located at the start of the most recently consumed token, spanning it,
+    /// for errors about the token as a whole, such as an invalid identifier.
+    fn token_error(&mut self, msg: String) -> Error {
+        let start = self.position_at(self.token_start);
+        let end = self.position_at(self.position.unwrap_or(self.token_start));
+
+        Error::new(msg, start, Span::new(start, end))
+    }
+
+    /// Creates an error located at the most recently consumed token.
     fn create_error(&mut self, msg: impl Into<String>) -> Error {
-        Error::new(msg.into(), self.position.take().unwrap_or_default())
+        let Some(end) = self.position.take() else {
+            return Error::new(msg.into(), Position::default(), Span::default());
+        };
+
+        let start = self.position_at(self.token_start);
+        let end = self.position_at(end);
+
+        Error::new(msg.into(), end, Span::new(start, end))
+    }
+
+    fn position_at(&mut self, index: usize) -> Position {
+        self.lines
+            .get_or_insert_with(|| LineIndex::new(self.source))
+            .position(index)
+    }
+}
+
+/// Splits the text of a replacement such as `${VAR/pattern/replacement}` at the first `/` which
+/// isn't escaped by a backslash. Without one, the replacement is empty.
+fn split_pattern(text: &str) -> (&str, &str) {
+    let mut chars = text.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            },
+            '/' => return (&text[..index], &text[index + 1..]),
+            _ => {},
+        }
     }
+
+    (text, "")
 }
@@ -0,0 +1,491 @@
+#[cfg(feature = "regex")]
+use regex::Regex;
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::iter;
+use std::ops::Range;
+
+/// An engine compiling the patterns matched against values, such as when removing a prefix.
+///
+/// The engine is set using [`Builder::pattern_engine`](crate::Builder::pattern_engine), and
+/// defaults to [`GlobEngine`], which matches patterns like Bash does.
+///
+/// With the `regex` feature enabled, a pattern enclosed in tildes, as in `~[0-9]+~`, is always a
+/// regular expression compiled by [`RegexEngine`], whatever the engine.
+pub trait PatternEngine: Send + Sync {
+    /// Compiles a pattern.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] with a message describing the problem if the pattern is invalid.
+    fn compile(&self, pattern: &str) -> Result<Box<dyn Pattern>, String>;
+}
+
+/// A compiled pattern, created by a [`PatternEngine`].
+///
+/// Only [`Pattern::is_match`] has to be implemented, on which the other methods are based by
+/// trying every substring in turn, like Bash does. Engines which can search more efficiently
+/// may override them.
+pub trait Pattern: Debug + Send + Sync {
+    /// Whether the pattern matches the whole text.
+    fn is_match(&self, text: &str) -> bool;
+
+    /// Returns the length in bytes of the shortest (or longest) prefix of the text which the
+    /// pattern matches, if any.
+    fn match_prefix(&self, text: &str, longest: bool) -> Option<usize> {
+        let mut ends = char_boundaries(text, 0);
+
+        if longest {
+            ends.rev().find(|end| self.is_match(&text[..*end]))
+        } else {
+            ends.find(|end| self.is_match(&text[..*end]))
+        }
+    }
+
+    /// Returns the start in bytes of the shortest (or longest) suffix of the text which the
+    /// pattern matches, if any.
+    fn match_suffix(&self, text: &str, longest: bool) -> Option<usize> {
+        let mut starts = char_boundaries(text, 0);
+
+        if longest {
+            starts.find(|start| self.is_match(&text[*start..]))
+        } else {
+            starts.rev().find(|start| self.is_match(&text[*start..]))
+        }
+    }
+
+    /// Returns the byte range of the first match in the text starting at or after the given byte
+    /// offset, preferring the longest match at the earliest position.
+    fn find(&self, text: &str, from: usize) -> Option<Range<usize>> {
+        char_boundaries(text, from).find_map(|start| {
+            char_boundaries(text, start)
+                .rev()
+                .find(|end| self.is_match(&text[start..*end]))
+                .map(|end| start..end)
+        })
+    }
+
+    /// Returns the byte ranges of the groups captured by a match found by [`Pattern::find`],
+    /// starting with the whole match as group 0. Groups which didn't take part in the match are
+    /// [`None`]. By default the pattern has no groups but the whole match.
+    fn captures(&self, text: &str, range: Range<usize>) -> Vec<Option<Range<usize>>> {
+        let _ = text;
+        vec![Some(range)]
+    }
+
+    /// Replaces the first (or every) match in the text with the replacement. Empty matches are
+    /// replaced as well, except directly after another match.
+    ///
+    /// Within the replacement, `&` stands for the whole match and `\1` to `\9` for the text
+    /// captured by the groups of the pattern, see [`Pattern::captures`], such that the matched
+    /// text can be reused. A group which didn't capture anything is replaced by nothing. A
+    /// backslash escapes `&` or another backslash.
+    fn replace(&self, text: &str, replacement: &str, all: bool) -> String {
+        let mut result = String::with_capacity(text.len());
+        // The end of the text copied so far, and where to search for the next match
+        let (mut index, mut search) = (0, 0);
+        let mut last_end = None;
+
+        while let Some(range) = self.find(text, search) {
+            if range.is_empty() && last_end == Some(range.start) {
+                // Move on a character, so that the search doesn't stop at the same position
+                match text[range.start..].chars().next() {
+                    Some(c) => search = range.start + c.len_utf8(),
+                    None => break,
+                }
+                continue;
+            }
+
+            result.push_str(&text[index..range.start]);
+
+            if replacement.contains(['&', '\\']) {
+                let captures = self.captures(text, range.clone());
+                expand_replacement(replacement, text, &captures, &mut result);
+            } else {
+                result.push_str(replacement);
+            }
+
+            index = range.end;
+            search = range.end;
+            last_end = Some(range.end);
+
+            if !all {
+                break;
+            }
+        }
+
+        result.push_str(&text[index..]);
+        result
+    }
+}
+
+/// Compiles a pattern using the engine, unless it's a regular expression enclosed in tildes.
+pub fn compile(engine: &dyn PatternEngine, pattern: &str) -> Result<Box<dyn Pattern>, String> {
+    #[cfg(feature = "regex")]
+    if let Some(regex) = pattern
+        .strip_prefix('~')
+        .and_then(|pattern| pattern.strip_suffix('~'))
+    {
+        return RegexEngine.compile(regex);
+    }
+
+    engine.compile(pattern)
+}
+
+/// Appends the replacement of a match, substituting `&` and `\1` to `\9` by the captured groups.
+fn expand_replacement(
+    replacement: &str,
+    text: &str,
+    captures: &[Option<Range<usize>>],
+    output: &mut String,
+) {
+    let push_group = |output: &mut String, group: Option<u32>| {
+        let range = group.and_then(|group| captures.get(usize::try_from(group).ok()?));
+
+        if let Some(Some(range)) = range {
+            output.push_str(&text[range.clone()]);
+        }
+    };
+    let mut chars = replacement.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => push_group(output, Some(0)),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => push_group(output, digit.to_digit(10)),
+                Some(c @ ('&' | '\\')) => output.push(c),
+                Some(c) => {
+                    output.push('\\');
+                    output.push(c);
+                },
+                None => output.push('\\'),
+            },
+            c => output.push(c),
+        }
+    }
+}
+
+/// The byte offsets of the characters of the text from the given offset, including the end of
+/// the text.
+fn char_boundaries(text: &str, from: usize) -> impl DoubleEndedIterator<Item = usize> + '_ {
+    (from..=text.len()).filter(|index| text.is_char_boundary(*index))
+}
+
+/// A [`PatternEngine`] for shell glob patterns.
+///
+/// In a glob pattern, `*` matches any text, `?` matches any single character and `[...]`
+/// matches any single character in the brackets. Brackets may
+/// contain ranges such as `a-z`, and match any character not in them if they start with `!` or
+/// `^`. A backslash matches the character following it literally.
+///
+/// Each `*`, `?` and `[...]` captures the text it matches as a group, numbered from 1 in order,
+/// which a replacement can refer to as in `\1`. To capture, every `*` matches as much of the
+/// text as possible, earlier ones first.
+///
+/// # Examples
+///
+/// ```
+/// use xpanda::{GlobEngine, PatternEngine};
+///
+/// let pattern = GlobEngine.compile("*.[ch]").unwrap();
+///
+/// assert!(pattern.is_match("main.c"));
+/// assert_eq!(pattern.match_suffix("src/main.c", false), Some(8));
+/// assert_eq!(pattern.replace("a.c b.h", "x", true), "x");
+///
+/// let pattern = GlobEngine.compile("*:*").unwrap();
+///
+/// assert_eq!(pattern.replace("nginx:1.25", "\\1:latest", false), "nginx:latest");
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GlobEngine;
+
+impl PatternEngine for GlobEngine {
+    fn compile(&self, pattern: &str) -> Result<Box<dyn Pattern>, String> {
+        Ok(Box::new(Glob::parse(pattern)?))
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Glob {
+    tokens: Vec<GlobToken>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum GlobToken {
+    Char(char),
+    AnyChar,
+    AnyText,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl GlobToken {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Self::Char(expected) => c == *expected,
+            Self::AnyChar => true,
+            Self::AnyText => false,
+            Self::Class { negated, ranges } => {
+                ranges
+                    .iter()
+                    .any(|(start, end)| (*start..=*end).contains(&c))
+                    != *negated
+            },
+        }
+    }
+}
+
+impl Glob {
+    fn parse(pattern: &str) -> Result<Self, String> {
+        let mut tokens = Vec::new();
+        let mut chars = pattern.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            let token = match c {
+                '*' => GlobToken::AnyText,
+                '?' => GlobToken::AnyChar,
+                '\\' => GlobToken::Char(chars.next().unwrap_or('\\')),
+                '[' => {
+                    let negated = chars.next_if(|c| matches!(c, '!' | '^')).is_some();
+                    let mut ranges = Vec::new();
+
+                    loop {
+                        let start = match chars.next() {
+                            // A `]` right after the opening bracket is part of the class
+                            Some(']') if !ranges.is_empty() => break,
+                            Some('\\') => chars.next().unwrap_or('\\'),
+                            Some(c) => c,
+                            None => return Err(format!("Unclosed '[' in pattern '{pattern}'")),
+                        };
+                        let end = if chars.peek() == Some(&'-') {
+                            chars.next();
+
+                            match chars.next() {
+                                Some(']') => {
+                                    ranges.push((start, start));
+                                    ranges.push(('-', '-'));
+                                    break;
+                                },
+                                Some('\\') => chars.next().unwrap_or('\\'),
+                                Some(end) => end,
+                                None => {
+                                    return Err(format!("Unclosed '[' in pattern '{pattern}'"));
+                                },
+                            }
+                        } else {
+                            start
+                        };
+
+                        ranges.push((start, end));
+                    }
+
+                    GlobToken::Class { negated, ranges }
+                },
+                c => GlobToken::Char(c),
+            };
+
+            // Consecutive stars match the same as a single one
+            if !(token == GlobToken::AnyText && tokens.last() == Some(&GlobToken::AnyText)) {
+                tokens.push(token);
+            }
+        }
+
+        Ok(Self { tokens })
+    }
+}
+
+impl Pattern for Glob {
+    /// Matches in linear time for patterns without stars, and otherwise backtracks only to the
+    /// most recent star, which is enough as every star can match any text.
+    fn is_match(&self, text: &str) -> bool {
+        let chars = text.chars().collect::<Vec<_>>();
+        let (mut token, mut index) = (0, 0);
+        // The token after the most recent star, and the index it started matching at
+        let mut backtrack = None;
+
+        while index < chars.len() {
+            match self.tokens.get(token) {
+                Some(GlobToken::AnyText) => {
+                    token += 1;
+                    backtrack = Some((token, index));
+                },
+                Some(expected) if expected.matches(chars[index]) => {
+                    token += 1;
+                    index += 1;
+                },
+                _ => match backtrack {
+                    Some((star_token, star_index)) => {
+                        token = star_token;
+                        index = star_index + 1;
+                        backtrack = Some((star_token, index));
+                    },
+                    None => return false,
+                },
+            }
+        }
+
+        self.tokens[token..]
+            .iter()
+            .all(|token| *token == GlobToken::AnyText)
+    }
+
+    fn captures(&self, text: &str, range: Range<usize>) -> Vec<Option<Range<usize>>> {
+        let matched = &text[range.clone()];
+        let mut capture = GlobCapture {
+            tokens: &self.tokens,
+            chars: matched.char_indices().collect(),
+            len: matched.len(),
+            groups: Vec::new(),
+            failed: HashSet::new(),
+        };
+
+        if !capture.capture(0, 0) {
+            return vec![Some(range)];
+        }
+
+        let start = range.start;
+        let groups = capture
+            .groups
+            .into_iter()
+            .map(|group| Some(group.start + start..group.end + start));
+
+        iter::once(Some(range)).chain(groups).collect()
+    }
+}
+
+/// Finds the text matched by each wildcard of a glob pattern matching a whole text, by
+/// backtracking.
+struct GlobCapture<'a> {
+    tokens: &'a [GlobToken],
+    chars: Vec<(usize, char)>,
+    len: usize,
+    /// The byte ranges matched by the wildcards before the current token.
+    groups: Vec<Range<usize>>,
+    /// The tokens and character indices from which the rest of the text can't be matched.
+    failed: HashSet<(usize, usize)>,
+}
+
+impl GlobCapture<'_> {
+    /// Whether the text from the character index matches the pattern from the token, in which
+    /// case the groups hold the ranges of all wildcards.
+    fn capture(&mut self, token: usize, index: usize) -> bool {
+        let Some(expected) = self.tokens.get(token) else {
+            return index == self.chars.len();
+        };
+
+        if self.failed.contains(&(token, index)) {
+            return false;
+        }
+
+        let captured = self.groups.len();
+        let is_match = match expected {
+            GlobToken::AnyText => (index..=self.chars.len()).rev().any(|end| {
+                self.groups.truncate(captured);
+                self.groups.push(self.offset(index)..self.offset(end));
+                self.capture(token + 1, end)
+            }),
+            GlobToken::Char(expected) => {
+                self.chars.get(index).is_some_and(|(_, c)| c == expected)
+                    && self.capture(token + 1, index + 1)
+            },
+            _ => {
+                let is_match = self
+                    .chars
+                    .get(index)
+                    .is_some_and(|(_, c)| expected.matches(*c));
+                self.groups.push(self.offset(index)..self.offset(index + 1));
+                is_match && self.capture(token + 1, index + 1)
+            },
+        };
+
+        if !is_match {
+            self.groups.truncate(captured);
+            self.failed.insert((token, index));
+        }
+
+        is_match
+    }
+
+    /// The byte offset of the character at the index, or the length of the text past its end.
+    fn offset(&self, index: usize) -> usize {
+        self.chars
+            .get(index)
+            .map_or(self.len, |(offset, _)| *offset)
+    }
+}
+
+/// A [`PatternEngine`] for regular expressions, using the syntax of the [`regex`] crate.
+///
+/// Unlike glob patterns, a regular expression finds the first match which the leftmost
+/// alternative yields rather than the longest one, as is usual for regular expressions. The
+/// groups of the regular expression are captured, such that a replacement can refer to them as
+/// in `\1`.
+///
+/// # Examples
+///
+/// ```
+/// use xpanda::{PatternEngine, RegexEngine};
+///
+/// let pattern = RegexEngine.compile("[0-9]+").unwrap();
+///
+/// assert!(pattern.is_match("123"));
+/// assert_eq!(pattern.replace("v1.22", "x", true), "vx.x");
+///
+/// let pattern = RegexEngine.compile("([0-9]+)\\.([0-9]+)").unwrap();
+///
+/// assert_eq!(pattern.replace("v1.22", "\\2.\\1 (&)", false), "v22.1 (1.22)");
+/// ```
+#[cfg(feature = "regex")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RegexEngine;
+
+#[cfg(feature = "regex")]
+impl PatternEngine for RegexEngine {
+    fn compile(&self, pattern: &str) -> Result<Box<dyn Pattern>, String> {
+        let error = |error| format!("Invalid regular expression '{pattern}': {error}");
+
+        Ok(Box::new(RegexPattern {
+            regex: Regex::new(pattern).map_err(error)?,
+            whole: Regex::new(&format!("^(?:{pattern})$")).map_err(error)?,
+        }))
+    }
+}
+
+#[cfg(feature = "regex")]
+#[derive(Debug, Clone)]
+struct RegexPattern {
+    regex: Regex,
+    /// The regular expression anchored to match whole texts only.
+    whole: Regex,
+}
+
+#[cfg(feature = "regex")]
+impl Pattern for RegexPattern {
+    fn is_match(&self, text: &str) -> bool {
+        self.whole.is_match(text)
+    }
+
+    fn find(&self, text: &str, from: usize) -> Option<Range<usize>> {
+        self.regex.find_at(text, from).map(|found| found.range())
+    }
+
+    fn captures(&self, text: &str, range: Range<usize>) -> Vec<Option<Range<usize>>> {
+        let mut locations = self.regex.capture_locations();
+
+        // Searching from the start of the match finds the same match again
+        if self
+            .regex
+            .read_captures_at(&mut locations, text, range.start)
+            .is_none()
+        {
+            return vec![Some(range)];
+        }
+
+        (0..locations.len())
+            .map(|group| locations.get(group).map(|(start, end)| start..end))
+            .collect()
+    }
+}
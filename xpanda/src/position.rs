@@ -1,11 +1,18 @@
-#[derive(Debug, Clone, Eq, PartialEq)]
+use memchr::memchr_iter;
+use std::cell::Cell;
+
+/// A location in the input. Lines and columns start at 1, and columns count characters rather
+/// than bytes.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct Position {
+    /// The byte offset from the start of the input.
     pub index: usize,
     pub line: usize,
     pub col: usize,
 }
 
 impl Position {
+    #[must_use]
     pub const fn new(index: usize, line: usize, col: usize) -> Self {
         Self { index, line, col }
     }
@@ -20,3 +27,75 @@ impl Default for Position {
         }
     }
 }
+
+/// A range of the input between two positions, where `start` is inclusive and `end` is
+/// exclusive.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    #[must_use]
+    pub const fn new(start: Position, end: Position) -> Self {
+        Self { start, end }
+    }
+}
+
+/// The byte offsets at which lines start, used to find the line and column of a byte offset.
+/// While lexing and parsing only byte offsets are tracked, as most of them are never needed as
+/// a [`Position`].
+pub struct LineIndex<'a> {
+    input: &'a str,
+    line_starts: Vec<usize>,
+    /// The position last returned, from which following columns on the same line are counted,
+    /// as offsets are mostly looked up in order and lines may be very long.
+    last: Cell<Position>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(input: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(memchr_iter(b'\n', input.as_bytes()).map(|index| index + 1))
+            .collect();
+
+        Self {
+            input,
+            line_starts,
+            last: Cell::new(Position::new(0, 1, 1)),
+        }
+    }
+
+    /// Returns the position of the given byte offset. Offsets which aren't at a char boundary
+    /// are counted up to the start of the char they're in.
+    pub fn position(&self, index: usize) -> Position {
+        let line = self.line_starts.partition_point(|&start| start <= index);
+        let last = self.last.get();
+        let (start, col) = if last.line == line && last.index <= index {
+            (last.index, last.col)
+        } else {
+            let line_start = line
+                .checked_sub(1)
+                .and_then(|line| self.line_starts.get(line))
+                .copied()
+                .unwrap_or_default();
+            (line_start, 1)
+        };
+        let col = self
+            .input
+            .get(start..)
+            .unwrap_or_default()
+            .char_indices()
+            .take_while(|(offset, _)| start + offset < index)
+            .count()
+            + col;
+        let position = Position::new(index, line, col);
+
+        if self.input.is_char_boundary(index) {
+            self.last.set(position);
+        }
+
+        position
+    }
+}
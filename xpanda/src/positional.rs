@@ -0,0 +1,142 @@
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+type Values = Box<dyn Iterator<Item = String> + Send>;
+
+/// The positional variables, some of which may be read from iterators, as given to
+/// [`Builder::with_positional_iter`](crate::Builder::with_positional_iter).
+///
+/// Values are only read from the iterators once a positional variable at or beyond their index
+/// is referenced, and are kept once read. Counting the positional variables reads all of them,
+/// unless each remaining iterator reports its exact length, or the count is limited as by
+/// [`PositionalVars::len_up_to`].
+#[derive(Default)]
+pub struct PositionalVars {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// The values read so far.
+    values: Vec<String>,
+    /// The iterators which the following values are read from, in order.
+    pending: VecDeque<Values>,
+}
+
+impl State {
+    /// Reads values until there are more than `len`, or until all iterators are exhausted.
+    fn fill(&mut self, len: usize) {
+        while self.values.len() <= len {
+            let Some(pending) = self.pending.front_mut() else {
+                return;
+            };
+
+            match pending.next() {
+                Some(value) => self.values.push(value),
+                None => drop(self.pending.pop_front()),
+            }
+        }
+    }
+
+    fn fill_all(&mut self) {
+        self.fill(usize::MAX);
+    }
+}
+
+impl PositionalVars {
+    pub fn extend(&mut self, values: impl IntoIterator<Item = String>) {
+        let state = self.state.get_mut().unwrap_or_else(PoisonError::into_inner);
+
+        if state.pending.is_empty() {
+            state.values.extend(values);
+        } else {
+            state
+                .pending
+                .push_back(Box::new(values.into_iter().collect::<Vec<_>>().into_iter()));
+        }
+    }
+
+    /// Adds values which are only read once referenced.
+    pub fn extend_lazy<I>(&mut self, values: I)
+    where
+        I: IntoIterator<Item = String>,
+        I::IntoIter: Send + 'static,
+    {
+        let state = self.state.get_mut().unwrap_or_else(PoisonError::into_inner);
+        state.pending.push_back(Box::new(values.into_iter()));
+    }
+
+    /// Returns the value at the zero-based index, reading values up to it if needed.
+    pub fn get(&self, index: usize) -> Option<String> {
+        let mut state = self.lock();
+        state.fill(index);
+        state.values.get(index).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        let mut state = self.lock();
+        let remaining = state
+            .pending
+            .iter()
+            .map(|pending| match pending.size_hint() {
+                (lower, Some(upper)) if lower == upper => Some(lower),
+                _ => None,
+            })
+            .sum::<Option<usize>>();
+
+        if let Some(remaining) = remaining {
+            return state.values.len() + remaining;
+        }
+
+        state.fill_all();
+        state.values.len()
+    }
+
+    /// Counts the values, but reads at most `limit` of them and counts no more than that, so
+    /// that counting can't hang on an endless iterator. Used to report how many positional
+    /// variables are set once one at an index of at least `limit` turned out to be unset.
+    pub fn len_up_to(&self, limit: usize) -> usize {
+        let mut state = self.lock();
+        state.fill(limit.saturating_sub(1));
+        state.values.len().min(limit)
+    }
+
+    /// Joins all values by spaces, as `$0` does.
+    pub fn join(&self) -> String {
+        let mut state = self.lock();
+        state.fill_all();
+        state.values.join(" ")
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // A panic while holding the lock can't leave the values in an inconsistent state
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Cloning reads all values, as the iterators they're read from can't be cloned.
+impl Clone for PositionalVars {
+    fn clone(&self) -> Self {
+        let mut state = self.lock();
+        state.fill_all();
+
+        Self {
+            state: Mutex::new(State {
+                values: state.values.clone(),
+                pending: VecDeque::new(),
+            }),
+        }
+    }
+}
+
+/// Hashing doesn't read any values, so only the values read so far and the number of iterators
+/// left are hashed. This suffices to key the results of a single instance, which never changes
+/// its values, as [`CachedXpanda`](crate::CachedXpanda) does.
+impl Hash for PositionalVars {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let inner = self.lock();
+        inner.values.hash(state);
+        inner.pending.len().hash(state);
+    }
+}
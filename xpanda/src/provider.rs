@@ -0,0 +1,146 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+/// A source of values for namespaced variables, such as `${env:HOME}`. Providers are registered
+/// under a namespace using [`Builder::register_namespace`](crate::Builder::register_namespace).
+///
+/// Any function or closure taking a key and returning `Result<Option<String>, String>` is also a
+/// provider.
+pub trait Provider: Send + Sync {
+    /// Returns the value for the given key, or [`None`] if it is unset.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] with a message describing the failure if the value could not be
+    /// retrieved, for example because a secret manager could not be reached.
+    fn get(&self, key: &str) -> Result<Option<String>, String>;
+}
+
+impl<F> Provider for F
+where
+    F: Fn(&str) -> Result<Option<String>, String> + Send + Sync,
+{
+    fn get(&self, key: &str) -> Result<Option<String>, String> {
+        self(key)
+    }
+}
+
+/// A [`Provider`] which reads environment variables of the current process at the time of
+/// expansion.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EnvProvider;
+
+impl Provider for EnvProvider {
+    fn get(&self, key: &str) -> Result<Option<String>, String> {
+        Ok(env::var(key).ok())
+    }
+}
+
+/// A [`Provider`] which substitutes the contents of files, such as certificates or keys.
+///
+/// Keys are paths relative to a root directory. Paths leading outside the root directory, for
+/// example by being absolute, using `..` or following symbolic links, are rejected. Files that
+/// don't exist are treated as unset.
+///
+/// # Examples
+///
+/// ```no_run
+/// use xpanda::{FileProvider, Xpanda};
+///
+/// let xpanda = Xpanda::builder()
+///     .register_namespace("file", FileProvider::new("secrets").trim_trailing_newlines(true))
+///     .build();
+///
+/// let config = xpanda.expand("password=${file:db_password}");
+/// ```
+#[derive(Debug, Clone)]
+pub struct FileProvider {
+    root: PathBuf,
+    max_size: u64,
+    trim_trailing_newlines: bool,
+}
+
+impl FileProvider {
+    /// The default maximum size of files, 1 MiB.
+    pub const DEFAULT_MAX_SIZE: u64 = 1024 * 1024;
+
+    /// Creates a provider for files within the given root directory.
+    #[must_use]
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            max_size: Self::DEFAULT_MAX_SIZE,
+            trim_trailing_newlines: false,
+        }
+    }
+
+    /// Sets the maximum size of files in bytes. Reading a larger file yields an error. Defaults
+    /// to [`FileProvider::DEFAULT_MAX_SIZE`].
+    #[must_use]
+    pub const fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// With this flag set, trailing newlines are removed from the contents of files. Off by
+    /// default.
+    #[must_use]
+    pub const fn trim_trailing_newlines(mut self, trim_trailing_newlines: bool) -> Self {
+        self.trim_trailing_newlines = trim_trailing_newlines;
+        self
+    }
+}
+
+impl Provider for FileProvider {
+    fn get(&self, key: &str) -> Result<Option<String>, String> {
+        let root = self
+            .root
+            .canonicalize()
+            .map_err(|error| format!("Failed to open root '{}': {}", self.root.display(), error))?;
+        let is_relative = Path::new(key)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+
+        if !is_relative {
+            return Err(format!("File '{key}' is outside of the root directory"));
+        }
+
+        let path = match root.join(key).canonicalize() {
+            Ok(path) => path,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(format!("Failed to open file '{key}': {error}")),
+        };
+
+        if !path.starts_with(&root) {
+            return Err(format!("File '{key}' is outside of the root directory"));
+        }
+
+        let file =
+            File::open(&path).map_err(|error| format!("Failed to open file '{key}': {error}"))?;
+        let mut bytes = Vec::new();
+
+        // Read one byte past the limit to detect files that are too large, even if they grow
+        file.take(self.max_size.saturating_add(1))
+            .read_to_end(&mut bytes)
+            .map_err(|error| format!("Failed to read file '{key}': {error}"))?;
+
+        if bytes.len() as u64 > self.max_size {
+            return Err(format!(
+                "File '{key}' exceeds the maximum size of {} bytes",
+                self.max_size
+            ));
+        }
+
+        let mut contents =
+            String::from_utf8(bytes).map_err(|_| format!("File '{key}' is not valid UTF-8"))?;
+
+        if self.trim_trailing_newlines {
+            let len = contents.trim_end_matches(['\n', '\r']).len();
+            contents.truncate(len);
+        }
+
+        Ok(Some(contents))
+    }
+}
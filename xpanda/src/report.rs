@@ -0,0 +1,76 @@
+use crate::eval::Source;
+use crate::position::Position;
+use crate::Error;
+
+/// The outcome of rendering a template along with the variables it references, as returned by
+/// [`Xpanda::report`](crate::Xpanda::report).
+///
+/// A report is meant for tools which consume the result of an expansion programmatically.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Report {
+    /// The rendered template, or [`None`] if rendering it failed.
+    pub output: Option<String>,
+    /// The variables referenced by the template which are unset, in order of first reference,
+    /// whether or not the template provides a default value for them.
+    pub missing: Vec<String>,
+    /// The variables referenced by the template which are set, along with where their values
+    /// come from, in order of first reference.
+    pub used: Vec<(String, Source)>,
+    /// The variables which were provided but are never referenced by the template, along with
+    /// where their values come from, sorted by name. See [`Xpanda::unused_vars`].
+    ///
+    /// [`Xpanda::unused_vars`]: crate::Xpanda::unused_vars
+    pub unused: Vec<(String, Source)>,
+    /// The variables referenced by the template along with how they resolve, in order of first
+    /// reference. See [`Xpanda::resolutions`].
+    ///
+    /// [`Xpanda::resolutions`]: crate::Xpanda::resolutions
+    pub resolutions: Vec<(String, Resolution)>,
+    /// Every param outside of other params, in order of appearance, along with the value it
+    /// expanded to, such as to compare the template before and after rendering it.
+    pub params: Vec<ExpandedParam>,
+    /// The errors which prevented rendering the template, which is empty if it was rendered.
+    /// All violations of the [`Schema`](crate::Schema) are reported at once, while evaluating
+    /// stops at the first error.
+    pub errors: Vec<Error>,
+}
+
+/// A param of a template along with the value it expanded to, as listed in a [`Report`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct ExpandedParam {
+    /// The text of the param exactly as written in the template, such as `${PORT:-5432}`.
+    /// Conditional blocks into which [`Template::embed`](crate::Template::embed) embedded a
+    /// template are formatted instead.
+    pub text: String,
+    /// The position of the `$` starting the param.
+    pub position: Position,
+    /// The value the param expanded to, or [`None`] if expanding it failed or the template
+    /// wasn't rendered because it failed a check beforehand.
+    pub value: Option<String>,
+}
+
+/// How a variable referenced by a template resolves, as listed in a [`Report`].
+///
+/// This tells which value a param takes, as the default value in `${VAR-default}` is only used
+/// if the variable is unset, while the one in `${VAR:-default}` is also used if it's empty.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum Resolution {
+    Unset,
+    /// The variable is set to an empty value.
+    Empty,
+    /// The variable is set to a value of the given length, counted in characters.
+    Set(usize),
+}
+
+impl Resolution {
+    pub(crate) fn of(value: Option<&str>) -> Self {
+        match value {
+            None => Self::Unset,
+            Some("") => Self::Empty,
+            Some(value) => Self::Set(value.chars().count()),
+        }
+    }
+}
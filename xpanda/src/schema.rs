@@ -0,0 +1,166 @@
+#[cfg(feature = "regex")]
+use regex::Regex;
+
+/// A description of the variables a template expects, used to validate their values before
+/// expanding. A schema is attached using [`Builder::schema`](crate::Builder::schema).
+///
+/// # Examples
+///
+/// ```
+/// use xpanda::{Schema, VarSchema};
+///
+/// let schema = Schema::new()
+///     .var(VarSchema::new("PORT").required(true))
+///     .var(VarSchema::new("LOG_LEVEL").one_of(["debug", "info"]));
+///
+/// assert!(schema.get("PORT").unwrap().is_required());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Schema {
+    vars: Vec<VarSchema>,
+}
+
+impl Schema {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a variable to the schema, replacing any variable previously added with the same
+    /// name.
+    #[must_use]
+    pub fn var(mut self, var: VarSchema) -> Self {
+        self.vars.retain(|existing| existing.name != var.name);
+        self.vars.push(var);
+        self
+    }
+
+    /// Returns all variables of the schema, in the order they were added.
+    #[must_use]
+    pub fn vars(&self) -> &[VarSchema] {
+        &self.vars
+    }
+
+    /// Returns the variable with the given name, if it's part of the schema.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&VarSchema> {
+        self.vars.iter().find(|var| var.name == name)
+    }
+
+    pub(crate) const fn is_empty(&self) -> bool {
+        self.vars.is_empty()
+    }
+}
+
+/// The expectations on a single named variable of a [`Schema`].
+#[derive(Debug, Clone)]
+pub struct VarSchema {
+    name: String,
+    required: bool,
+    constraint: Option<Constraint>,
+    description: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+enum Constraint {
+    #[cfg(feature = "regex")]
+    Pattern(String, Regex),
+    OneOf(Vec<String>),
+}
+
+impl VarSchema {
+    #[must_use]
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            required: false,
+            constraint: None,
+            description: None,
+        }
+    }
+
+    /// With this flag set, the variable must be set, whether or not a template references it.
+    /// Off by default.
+    #[must_use]
+    pub const fn required(mut self, required: bool) -> Self {
+        self.required = required;
+        self
+    }
+
+    /// Requires the whole value of the variable to match the given regular expression,
+    /// replacing any previous constraint. Requires the `regex` feature.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] with a message describing the problem if the pattern is not a valid
+    /// regular expression.
+    #[cfg(feature = "regex")]
+    pub fn pattern(mut self, pattern: &str) -> Result<Self, String> {
+        let regex = Regex::new(&format!("^(?:{pattern})$"))
+            .map_err(|error| format!("Invalid pattern '{pattern}': {error}"))?;
+
+        self.constraint = Some(Constraint::Pattern(pattern.to_string(), regex));
+
+        Ok(self)
+    }
+
+    /// Requires the value of the variable to be one of the given values, replacing any previous
+    /// constraint.
+    #[must_use]
+    pub fn one_of<I, S>(mut self, values: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let values = values.into_iter().map(Into::into).collect();
+        self.constraint = Some(Constraint::OneOf(values));
+        self
+    }
+
+    /// Sets a human readable description of the variable.
+    #[must_use]
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[must_use]
+    pub const fn is_required(&self) -> bool {
+        self.required
+    }
+
+    #[must_use]
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Checks the value against the constraint of the variable, returning a description of the
+    /// violation if it doesn't satisfy it.
+    pub(crate) fn check(&self, value: &str) -> Option<String> {
+        match &self.constraint {
+            #[cfg(feature = "regex")]
+            Some(Constraint::Pattern(pattern, regex)) if !regex.is_match(value) => Some(format!(
+                "'{}' doesn't match the pattern '{pattern}'",
+                self.name
+            )),
+            Some(Constraint::OneOf(values)) if !values.iter().any(|allowed| allowed == value) => {
+                let values = values
+                    .iter()
+                    .map(|value| format!("'{value}'"))
+                    .collect::<Vec<_>>();
+
+                Some(format!(
+                    "'{}' must be one of {}",
+                    self.name,
+                    values.join(", ")
+                ))
+            },
+            _ => None,
+        }
+    }
+}
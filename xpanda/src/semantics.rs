@@ -0,0 +1,470 @@
+use crate::position::Position;
+use crate::{Builder, Error, ErrorKind, Xpanda};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::Path;
+
+/// The file extension of the cases of a [`Corpus`].
+const CASE_EXTENSION: &str = "case";
+
+/// The prefix of the lines which start a section of a case.
+const SECTION_PREFIX: &str = "--- ";
+
+/// The cargo features a case may require, along with whether they're enabled.
+const FEATURES: [(&str, bool); 8] = [
+    ("case-modifiers", cfg!(feature = "case-modifiers")),
+    ("indirection", cfg!(feature = "indirection")),
+    ("length", cfg!(feature = "length")),
+    ("expr", cfg!(feature = "expr")),
+    ("regex", cfg!(feature = "regex")),
+    ("serialize", cfg!(feature = "serialize")),
+    ("zeroize", cfg!(feature = "zeroize")),
+    ("locale", cfg!(feature = "locale")),
+];
+
+/// The names of the kinds of errors a case may expect.
+const ERROR_KINDS: [(&str, ErrorKind); 9] = [
+    ("syntax", ErrorKind::Syntax),
+    ("unset", ErrorKind::Unset),
+    ("required", ErrorKind::Required),
+    ("invalid-value", ErrorKind::InvalidValue),
+    ("provider", ErrorKind::Provider),
+    ("policy", ErrorKind::Policy),
+    ("io", ErrorKind::Io),
+    ("too-long", ErrorKind::TooLong),
+    ("format", ErrorKind::Format),
+];
+
+/// The outcome expected of expanding the input of a [`Case`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Expected {
+    /// The expanded text.
+    Output(String),
+    /// An error of the given kind.
+    Error(ErrorKind),
+}
+
+/// An input along with the variables to expand it with and the expected outcome, as read from
+/// a file of a [`Corpus`].
+///
+/// A case consists of sections, each started by a line such as `--- input`, which hold the
+/// lines up to the next section. The line ending before the next section, or the one at the
+/// end of the file, isn't part of a section, so an output ending with a line ending is followed
+/// by an empty line. Any text before the first section describes the case.
+///
+/// * `vars`: named variables, one `NAME=value` per line.
+/// * `args`: positional variables, one per line.
+/// * `features`: cargo features the case requires, one per line.
+/// * `input`: the text to expand.
+/// * `output`: the expected expanded text.
+/// * `error`: instead of an output, the expected kind of error, such as `syntax` or `required`.
+///
+/// # Examples
+///
+/// ```
+/// use xpanda::{Case, Expected, Xpanda};
+///
+/// let text = "Values aren't expanded again.\n\
+///             --- vars\n\
+///             A=$B\n\
+///             B=b\n\
+///             --- input\n\
+///             $A\n\
+///             --- output\n\
+///             $B\n";
+/// let case = Case::parse("single_pass", text).unwrap();
+///
+/// assert_eq!(case.expected, Expected::Output(String::from("$B")));
+/// assert!(case.run(Xpanda::builder()).is_ok());
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Case {
+    pub name: String,
+    pub description: String,
+    pub named_vars: BTreeMap<String, String>,
+    pub positional_vars: Vec<String>,
+    pub features: Vec<String>,
+    pub input: String,
+    pub expected: Expected,
+}
+
+impl Case {
+    /// Parses the text of a case.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`ErrorKind::Syntax`] which refers to the invalid line if the
+    /// text isn't a valid case.
+    pub fn parse(name: impl Into<String>, text: &str) -> Result<Self, Error> {
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        let mut description = Vec::new();
+        let mut sections: Vec<(&str, Position, Vec<&str>)> = Vec::new();
+        let mut index = 0;
+
+        for (line, text) in (1..).zip(text.split('\n')) {
+            let position = Position::new(index, line, 1);
+            index += text.len() + 1;
+
+            if let Some(section) = text.strip_prefix(SECTION_PREFIX) {
+                if sections.iter().any(|(name, ..)| *name == section) {
+                    return Err(syntax_error(
+                        format!("Duplicate section '{section}'"),
+                        &position,
+                    ));
+                }
+
+                sections.push((section, position, Vec::new()));
+            } else if let Some((_, _, lines)) = sections.last_mut() {
+                lines.push(text);
+            } else {
+                description.push(text);
+            }
+        }
+
+        let mut case = Self {
+            name: name.into(),
+            description: description.join("\n").trim().to_string(),
+            named_vars: BTreeMap::new(),
+            positional_vars: Vec::new(),
+            features: Vec::new(),
+            input: String::new(),
+            expected: Expected::Output(String::new()),
+        };
+        let mut has_input = false;
+        let mut has_expected = false;
+
+        for (section, position, lines) in sections {
+            match section {
+                "vars" => case.named_vars = parse_vars(&lines, &position)?,
+                "args" => case.positional_vars = lines.iter().map(ToString::to_string).collect(),
+                "features" => case.features = parse_features(&lines, &position)?,
+                "input" => {
+                    case.input = lines.join("\n");
+                    has_input = true;
+                },
+                "output" | "error" if has_expected => {
+                    let message = String::from("A case expects either an output or an error");
+                    return Err(syntax_error(message, &position));
+                },
+                "output" => {
+                    case.expected = Expected::Output(lines.join("\n"));
+                    has_expected = true;
+                },
+                "error" => {
+                    case.expected = Expected::Error(parse_error_kind(&lines, &position)?);
+                    has_expected = true;
+                },
+                _ => {
+                    let message = format!("Unknown section '{section}'");
+                    return Err(syntax_error(message, &position));
+                },
+            }
+        }
+
+        if !has_input || !has_expected {
+            let message = String::from("A case needs an input and either an output or an error");
+            return Err(Error::unlocated(ErrorKind::Syntax, message));
+        }
+
+        Ok(case)
+    }
+
+    /// Returns whether all cargo features the case requires are enabled.
+    #[must_use]
+    pub fn is_supported(&self) -> bool {
+        self.features.iter().all(|feature| {
+            FEATURES
+                .iter()
+                .any(|(name, enabled)| name == feature && *enabled)
+        })
+    }
+
+    /// Expands the input using an instance built from the given builder along with the
+    /// variables of the case, and compares the outcome to the expected one. Expressions are
+    /// enabled for cases which require the `expr` feature.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`Mismatch`] if the outcome differs from the expected one.
+    #[allow(clippy::result_large_err)]
+    pub fn run(&self, builder: Builder) -> Result<(), Mismatch> {
+        #[cfg(feature = "expr")]
+        let builder = if self.features.iter().any(|feature| feature == "expr") {
+            builder.expressions(true)
+        } else {
+            builder
+        };
+        let xpanda = builder
+            .with_named_vars(self.named_vars.clone().into_iter().collect())
+            .with_positional_vars(self.positional_vars.clone())
+            .build();
+        let actual = xpanda.expand(&self.input);
+        let matches = match (&self.expected, &actual) {
+            (Expected::Output(expected), Ok(actual)) => expected == actual,
+            (Expected::Error(expected), Err(actual)) => *expected == actual.kind,
+            _ => false,
+        };
+
+        if matches {
+            Ok(())
+        } else {
+            Err(Mismatch {
+                case: self.name.clone(),
+                expected: self.expected.clone(),
+                actual,
+            })
+        }
+    }
+}
+
+/// A collection of [`Case`]s which pin down how inputs are expanded, such as the `semantics`
+/// directory of this repository.
+///
+/// Every case is a file with the extension `.case`, named after the case. Running a corpus
+/// against a configuration of [`Xpanda`] checks that the configuration doesn't change the
+/// meaning of any input, and reimplementations can load the same files to check that they
+/// behave the same.
+///
+/// # Examples
+///
+/// ```
+/// use xpanda::{Case, Corpus};
+///
+/// let mut corpus = Corpus::default();
+/// corpus.cases.push(Case::parse("unset", "--- input\n[$A]\n--- output\n[]").unwrap());
+/// corpus.cases.push(Case::parse("required", "--- input\n${A?}\n--- error\nrequired").unwrap());
+///
+/// assert!(corpus.run(|builder| builder).is_empty());
+/// assert_eq!(corpus.run(|builder| builder.no_unset(true))[0].case, "unset");
+/// ```
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Corpus {
+    /// The cases of the corpus, ordered by name.
+    pub cases: Vec<Case>,
+}
+
+impl Corpus {
+    /// Loads all cases in the directory, ignoring files with other extensions.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`ErrorKind::Io`] if the directory or a case can't be read, and
+    /// an error of kind [`ErrorKind::Syntax`] if a case is invalid.
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let dir = dir.as_ref();
+        let mut paths = fs::read_dir(dir)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|error| {
+                let message = format!("Failed to read corpus '{}': {error}", dir.display());
+                Error::unlocated(ErrorKind::Io, message)
+            })?;
+        paths.retain(|path| path.extension().is_some_and(|ext| ext == CASE_EXTENSION));
+        paths.sort();
+
+        let cases = paths
+            .iter()
+            .map(|path| {
+                let text = fs::read_to_string(path).map_err(|error| {
+                    let message = format!("Failed to read case '{}': {error}", path.display());
+                    Error::unlocated(ErrorKind::Io, message)
+                })?;
+                let name = path
+                    .file_stem()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+
+                Case::parse(name, &text).map_err(|mut error| {
+                    error.message = format!("Invalid case '{}': {}", path.display(), error.message);
+                    error
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { cases })
+    }
+
+    /// Runs every supported case, each with an instance configured by the given function, and
+    /// returns the cases whose outcome differs from the expected one. Cases which require a
+    /// cargo feature that isn't enabled are skipped.
+    pub fn run(&self, configure: impl Fn(Builder) -> Builder) -> Vec<Mismatch> {
+        self.cases
+            .iter()
+            .filter(|case| case.is_supported())
+            .filter_map(|case| case.run(configure(Xpanda::builder())).err())
+            .collect()
+    }
+}
+
+/// A [`Case`] whose outcome differs from the expected one.
+///
+/// Formatting a mismatch using [`Display`] yields a line based diff between the expected and
+/// the actual outcome.
+///
+/// # Examples
+///
+/// ```
+/// use xpanda::{Case, Xpanda};
+///
+/// let case = Case::parse("case", "--- input\n$A\n--- output\nb\nc").unwrap();
+/// let mismatch = case.run(Xpanda::builder()).unwrap_err();
+///
+/// assert_eq!(
+///     mismatch.to_string(),
+///     "Case 'case' expanded differently than expected:\n\
+///      --- expected\n\
+///      +++ actual\n\
+///      -b\n\
+///      -c\n\
+///      +\n"
+/// );
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Mismatch {
+    /// The name of the case.
+    pub case: String,
+    pub expected: Expected,
+    pub actual: Result<String, Error>,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let expected = match &self.expected {
+            Expected::Output(output) => output.clone(),
+            Expected::Error(kind) => format!("error: {}", kind_name(*kind)),
+        };
+        let actual = match &self.actual {
+            Ok(output) => output.clone(),
+            Err(error) => format!("error: {} ({error})", kind_name(error.kind)),
+        };
+
+        writeln!(
+            f,
+            "Case '{}' expanded differently than expected:",
+            self.case
+        )?;
+        writeln!(f, "--- expected")?;
+        writeln!(f, "+++ actual")?;
+
+        // Differing line endings at the end are invisible otherwise
+        let endings_differ = expected.ends_with('\n') != actual.ends_with('\n');
+
+        for (marker, line) in diff_lines(&expected, &actual) {
+            writeln!(f, "{marker}{}", line.strip_suffix('\n').unwrap_or(line))?;
+
+            if endings_differ && !line.ends_with('\n') && !line.is_empty() {
+                writeln!(f, "\\ No line ending")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+const fn syntax_error(message: String, position: &Position) -> Error {
+    Error::new(ErrorKind::Syntax, message, position)
+}
+
+fn parse_vars(lines: &[&str], position: &Position) -> Result<BTreeMap<String, String>, Error> {
+    lines
+        .iter()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.split_once('=')
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .ok_or_else(|| {
+                    let message = format!("Expected a var such as 'NAME=value', got '{line}'");
+                    syntax_error(message, position)
+                })
+        })
+        .collect()
+}
+
+fn parse_features(lines: &[&str], position: &Position) -> Result<Vec<String>, Error> {
+    lines
+        .iter()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            if FEATURES.iter().any(|(name, _)| *name == line) {
+                Ok(line.to_string())
+            } else {
+                Err(syntax_error(format!("Unknown feature '{line}'"), position))
+            }
+        })
+        .collect()
+}
+
+fn parse_error_kind(lines: &[&str], position: &Position) -> Result<ErrorKind, Error> {
+    let name = lines.join("\n");
+    let name = name.trim();
+
+    ERROR_KINDS
+        .iter()
+        .find(|(kind_name, _)| *kind_name == name)
+        .map(|(_, kind)| *kind)
+        .ok_or_else(|| syntax_error(format!("Unknown kind of error '{name}'"), position))
+}
+
+fn kind_name(kind: ErrorKind) -> &'static str {
+    ERROR_KINDS
+        .iter()
+        .find(|(_, other)| *other == kind)
+        .map_or("unknown", |(name, _)| name)
+}
+
+/// Compares two texts line by line, returning the lines of the longest common subsequence
+/// marked with a space, and the other lines marked with `-` if they're only in the expected
+/// text and `+` if they're only in the actual one. An empty text is a single empty line.
+fn diff_lines<'a>(expected: &'a str, actual: &'a str) -> Vec<(char, &'a str)> {
+    let expected = split_lines(expected);
+    let actual = split_lines(actual);
+    let mut lengths = vec![vec![0_usize; actual.len() + 1]; expected.len() + 1];
+
+    for (i, a) in expected.iter().enumerate().rev() {
+        for (j, b) in actual.iter().enumerate().rev() {
+            lengths[i][j] = if a == b {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            lines.push((' ', expected[i]));
+            i += 1;
+            j += 1;
+        } else if j == actual.len()
+            || (i < expected.len() && lengths[i + 1][j] >= lengths[i][j + 1])
+        {
+            lines.push(('-', expected[i]));
+            i += 1;
+        } else {
+            lines.push(('+', actual[j]));
+            j += 1;
+        }
+    }
+
+    lines
+}
+
+fn split_lines(text: &str) -> Vec<&str> {
+    if text.is_empty() {
+        vec![""]
+    } else {
+        text.split_inclusive('\n').collect()
+    }
+}
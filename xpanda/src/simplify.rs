@@ -0,0 +1,419 @@
+use crate::ast::{Ast, Identifier, Node, Param};
+use crate::eval::Defaults;
+use crate::position::Position;
+use crate::{Error, Xpanda};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::HashMap;
+
+/// Folds the values of known variables into the nodes of a template, leaving only the params
+/// which depend on unknown variables, as done by [`Template::simplify`](crate::Template::simplify).
+pub struct Simplifier<'v, 'd> {
+    vars: &'v HashMap<String, String>,
+    defaults: Defaults<'d>,
+    /// Evaluates the params whose result only depends on known variables.
+    xpanda: Xpanda,
+    /// The number of nested branches being simplified which may not be taken when rendering,
+    /// such as default values of params whose variable isn't known.
+    untaken: Cell<usize>,
+}
+
+impl<'v, 'd> Simplifier<'v, 'd> {
+    pub const fn new(
+        vars: &'v HashMap<String, String>,
+        defaults: Defaults<'d>,
+        xpanda: Xpanda,
+    ) -> Self {
+        Self {
+            vars,
+            defaults,
+            xpanda,
+            untaken: Cell::new(0),
+        }
+    }
+
+    pub fn simplify_nodes<'a>(&self, nodes: &[Node<'a>]) -> Result<Vec<Node<'a>>, Error> {
+        let mut simplified = Vec::with_capacity(nodes.len());
+
+        for node in nodes {
+            self.simplify_into(node, &mut simplified)?;
+        }
+
+        Ok(simplified)
+    }
+
+    /// Simplifies a node nested inside a param, such as a default value, which stays a single
+    /// node.
+    fn simplify_node<'a>(&self, node: &Node<'a>) -> Result<Node<'a>, Error> {
+        let mut simplified = self.simplify_nodes(std::slice::from_ref(node))?;
+
+        Ok(match simplified.len() {
+            0 => Node::Text(Cow::Borrowed(""), None),
+            1 => simplified.remove(0),
+            _ => node.clone(),
+        })
+    }
+
+    /// Simplifies a node which is only evaluated in some cases, such as a default value. Unless
+    /// it's known to be evaluated, params which fail to evaluate are kept such that they fail
+    /// when rendering instead, if they're evaluated then, see [`Self::eval_param`].
+    fn simplify_branch<'a>(&self, node: &Node<'a>, is_taken: bool) -> Result<Node<'a>, Error> {
+        if is_taken {
+            return self.simplify_node(node);
+        }
+
+        self.untaken.set(self.untaken.get() + 1);
+        let result = self.simplify_node(node);
+        self.untaken.set(self.untaken.get() - 1);
+
+        result
+    }
+
+    /// Simplifies nodes which are only evaluated in some cases, like [`Self::simplify_branch`].
+    fn simplify_branch_nodes<'a>(&self, nodes: &[Node<'a>]) -> Result<Vec<Node<'a>>, Error> {
+        self.untaken.set(self.untaken.get() + 1);
+        let result = self.simplify_nodes(nodes);
+        self.untaken.set(self.untaken.get() - 1);
+
+        result
+    }
+
+    fn simplify_into<'a>(&self, node: &Node<'a>, output: &mut Vec<Node<'a>>) -> Result<(), Error> {
+        let node = match node {
+            Node::Param(param, position) => return self.simplify_param(param, *position, output),
+            #[cfg(feature = "expr")]
+            Node::Expr(expression, _)
+                if expression
+                    .variables()
+                    .into_iter()
+                    .all(|name| self.vars.contains_key(name)) =>
+            {
+                self.eval(node.clone())?
+            },
+            _ => node.clone(),
+        };
+
+        push(output, node);
+
+        Ok(())
+    }
+
+    fn simplify_param<'a>(
+        &self,
+        param: &Param<'a>,
+        position: Position,
+        output: &mut Vec<Node<'a>>,
+    ) -> Result<(), Error> {
+        let node = match param {
+            Param::Simple {
+                identifier,
+                modifier,
+            } => self.fold(
+                Param::Simple {
+                    identifier: self.simplify_identifier(identifier, position)?,
+                    modifier: modifier.clone(),
+                },
+                position,
+            )?,
+            Param::WithDefault {
+                identifier,
+                default,
+                treat_empty_as_unset,
+            } => self.simplify_default(identifier, default, *treat_empty_as_unset, position)?,
+            Param::WithAlt {
+                identifier,
+                alt,
+                treat_empty_as_unset,
+            } => self.simplify_alt(identifier, alt, *treat_empty_as_unset, position)?,
+            // Fails if the known value isn't set, just like rendering would. The message is only
+            // evaluated if the variable isn't set
+            Param::WithError {
+                identifier,
+                error,
+                treat_empty_as_unset,
+            } => self.fold(
+                Param::WithError {
+                    identifier: self.simplify_identifier(identifier, position)?,
+                    error: self.simplify_branch_nodes(error)?,
+                    treat_empty_as_unset: *treat_empty_as_unset,
+                },
+                position,
+            )?,
+            #[cfg(feature = "length")]
+            Param::Length { identifier } => self.fold(
+                Param::Length {
+                    identifier: self.simplify_identifier(identifier, position)?,
+                },
+                position,
+            )?,
+            // The number of positional variables is only known once rendering
+            #[cfg(feature = "length")]
+            Param::Arity => Node::Param(Param::Arity, position),
+            // Folded into a param referring to the variable directly if only its name is known,
+            // as the variable holding the name isn't known when rendering
+            #[cfg(feature = "indirection")]
+            Param::Ref { identifier } => {
+                let identifier = self.simplify_identifier(identifier, position)?;
+
+                match self.value(&identifier) {
+                    Some(name) if !self.vars.contains_key(name) && is_named(name) => {
+                        let part = Node::Text(Cow::Owned(name.to_string()), None);
+                        let param = Param::Simple {
+                            identifier: Identifier::Dynamic(Box::new([part])),
+                            modifier: None,
+                        };
+
+                        Node::Param(param, position)
+                    },
+                    // A variable whose name can't be written is never known when rendering
+                    Some(_) => self.eval_param(Param::Ref { identifier }, position)?,
+                    None => Node::Param(Param::Ref { identifier }, position),
+                }
+            },
+            Param::Conditional {
+                identifier,
+                then,
+                otherwise,
+            } => return self.simplify_conditional(identifier, then, otherwise, position, output),
+        };
+
+        push(output, node);
+
+        Ok(())
+    }
+
+    /// Simplifies `${VAR-default}`, or `${VAR:-default}` if `treat_empty_as_unset` is set.
+    fn simplify_default<'a>(
+        &self,
+        identifier: &Identifier<'a>,
+        default: &Node<'a>,
+        treat_empty_as_unset: bool,
+        position: Position,
+    ) -> Result<Node<'a>, Error> {
+        let identifier = self.simplify_identifier(identifier, position)?;
+        let value = self.value(&identifier);
+
+        if let Some(value) = value.filter(|value| is_set(value, treat_empty_as_unset)) {
+            return Ok(text(value));
+        }
+
+        let is_known = value.is_some();
+
+        Ok(match self.simplify_branch(default, is_known)? {
+            // An expression can't stand on its own outside of the param
+            default if is_known && !is_expression(&default) => default,
+            // An empty default can't be written, but `${VAR:-}` is the same as `${VAR:+$VAR}`,
+            // which unlike `${VAR}` isn't an error if unset
+            default if is_empty(&default) => Node::Param(
+                Param::WithAlt {
+                    identifier: identifier.clone(),
+                    alt: Box::new(Node::Param(
+                        Param::Simple {
+                            identifier,
+                            modifier: None,
+                        },
+                        position,
+                    )),
+                    treat_empty_as_unset,
+                },
+                position,
+            ),
+            default => Node::Param(
+                Param::WithDefault {
+                    identifier,
+                    default: Box::new(default),
+                    treat_empty_as_unset,
+                },
+                position,
+            ),
+        })
+    }
+
+    /// Simplifies `${VAR+alt}`, or `${VAR:+alt}` if `treat_empty_as_unset` is set.
+    fn simplify_alt<'a>(
+        &self,
+        identifier: &Identifier<'a>,
+        alt: &Node<'a>,
+        treat_empty_as_unset: bool,
+        position: Position,
+    ) -> Result<Node<'a>, Error> {
+        let identifier = self.simplify_identifier(identifier, position)?;
+        let value = self.value(&identifier);
+
+        if value.is_some_and(|value| !is_set(value, treat_empty_as_unset)) {
+            return Ok(text(""));
+        }
+
+        let is_known = value.is_some();
+
+        Ok(match self.simplify_branch(alt, is_known)? {
+            alt if is_known && !is_expression(&alt) => alt,
+            // An empty alternative value can't be written, and is empty either way
+            alt if is_empty(&alt) => text(""),
+            alt => Node::Param(
+                Param::WithAlt {
+                    identifier,
+                    alt: Box::new(alt),
+                    treat_empty_as_unset,
+                },
+                position,
+            ),
+        })
+    }
+
+    /// Simplifies the branches of a conditional block, or replaces the block by the branch it
+    /// takes if its variable is known.
+    fn simplify_conditional<'a>(
+        &self,
+        identifier: &Identifier<'a>,
+        then: &[Node<'a>],
+        otherwise: &[Node<'a>],
+        position: Position,
+        output: &mut Vec<Node<'a>>,
+    ) -> Result<(), Error> {
+        let identifier = self.simplify_identifier(identifier, position)?;
+
+        let Some(value) = self.value(&identifier) else {
+            let param = Param::Conditional {
+                then: self.simplify_branch_nodes(then)?.into_boxed_slice(),
+                otherwise: self.simplify_branch_nodes(otherwise)?.into_boxed_slice(),
+                identifier,
+            };
+            output.push(Node::Param(param, position));
+            return Ok(());
+        };
+
+        let branch = if value.is_empty() { otherwise } else { then };
+
+        for node in self.simplify_nodes(branch)? {
+            push(output, node);
+        }
+
+        Ok(())
+    }
+
+    /// Evaluates the param if its variable is known, or keeps it otherwise.
+    fn fold<'a>(&self, param: Param<'a>, position: Position) -> Result<Node<'a>, Error> {
+        let is_known = param
+            .identifier()
+            .is_some_and(|identifier| self.value(identifier).is_some());
+
+        if is_known {
+            self.eval_param(param, position)
+        } else {
+            Ok(Node::Param(param, position))
+        }
+    }
+
+    /// Evaluates a param whose variable is known. If that fails in a branch which may not be
+    /// taken, the param is replaced by one which fails with the same message when rendering
+    /// instead, as the known variable is left unset then.
+    fn eval_param<'a>(&self, param: Param<'a>, position: Position) -> Result<Node<'a>, Error> {
+        let identifier = param.identifier().cloned();
+
+        match (self.eval(Node::Param(param, position)), identifier) {
+            (Err(error), Some(identifier)) if self.untaken.get() > 0 => {
+                let param = Param::WithError {
+                    identifier,
+                    error: vec![text(&error.message)],
+                    treat_empty_as_unset: true,
+                };
+
+                Ok(Node::Param(param, position))
+            },
+            (result, _) => result,
+        }
+    }
+
+    /// Folds known variables into the parts of a dynamic name, as in `${PREFIX_${ENV}}`, which
+    /// becomes a single part once all of them are known.
+    fn simplify_identifier<'a>(
+        &self,
+        identifier: &Identifier<'a>,
+        position: Position,
+    ) -> Result<Identifier<'a>, Error> {
+        let Identifier::Dynamic(parts) = identifier else {
+            return Ok(identifier.clone());
+        };
+
+        let parts = self.simplify_nodes(parts)?;
+
+        if let [Node::Text(name, _)] = parts.as_slice() {
+            if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                // Evaluating the param reports the invalid name
+                let param = Param::Simple {
+                    identifier: identifier.clone(),
+                    modifier: None,
+                };
+                self.eval(Node::Param(param, position))?;
+            }
+        }
+
+        Ok(Identifier::Dynamic(parts.into_boxed_slice()))
+    }
+
+    /// Returns the value of the variable, if it's known.
+    fn value(&self, identifier: &Identifier) -> Option<&str> {
+        let name = match identifier {
+            Identifier::Named(_) | Identifier::Special(_) => identifier.to_string(),
+            Identifier::Indexed(index) if *index > 0 => identifier.to_string(),
+            Identifier::Dynamic(parts) => match parts.as_ref() {
+                [Node::Text(name, _)] => name.to_string(),
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        self.vars.get(&name).map(String::as_str)
+    }
+
+    fn eval<'a>(&self, node: Node<'a>) -> Result<Node<'a>, Error> {
+        let value = self
+            .xpanda
+            .evaluator
+            .eval(&Ast::new(vec![node]), self.defaults)?;
+
+        Ok(Node::Text(Cow::Owned(value), None))
+    }
+}
+
+fn is_empty(node: &Node) -> bool {
+    matches!(node, Node::Text(text, _) if text.is_empty())
+}
+
+/// Whether the name can be written as the name of a named variable, as in `${NAME}`.
+#[cfg(feature = "indirection")]
+fn is_named(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+const fn is_set(value: &str, treat_empty_as_unset: bool) -> bool {
+    !(treat_empty_as_unset && value.is_empty())
+}
+
+#[cfg(feature = "expr")]
+const fn is_expression(node: &Node) -> bool {
+    matches!(node, Node::Expr(..))
+}
+
+#[cfg(not(feature = "expr"))]
+const fn is_expression(_node: &Node) -> bool {
+    false
+}
+
+fn text<'a>(value: &str) -> Node<'a> {
+    Node::Text(Cow::Owned(value.to_string()), None)
+}
+
+/// Appends the node, merging adjacent text, which then no longer has a raw form.
+fn push<'a>(nodes: &mut Vec<Node<'a>>, node: Node<'a>) {
+    match (nodes.last_mut(), node) {
+        (_, Node::Text(text, _)) if text.is_empty() => {},
+        (Some(Node::Text(previous, raw)), Node::Text(text, _)) => {
+            previous.to_mut().push_str(&text);
+            *raw = None;
+        },
+        (_, node) => nodes.push(node),
+    }
+}
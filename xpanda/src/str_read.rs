@@ -1,79 +1,98 @@
-use crate::forward_peekable::{ForwardPeekable, IteratorExt};
-use crate::position::Position;
-use std::str::CharIndices;
+use memchr::{memchr, memchr2, memchr3};
 
 pub struct StrRead<'a> {
-    position: Position,
+    index: usize,
     input: &'a str,
-    iter: ForwardPeekable<CharIndices<'a>>,
 }
 
 impl<'a> StrRead<'a> {
     #[must_use]
-    pub fn new(input: &'a str) -> Self {
-        Self {
-            position: Position::default(),
-            input,
-            iter: input.char_indices().forward_peekable(),
-        }
+    pub const fn new(input: &'a str) -> Self {
+        Self { index: 0, input }
     }
 
+    /// Returns the byte offset of the next char.
     #[must_use]
-    pub const fn position(&self) -> &Position {
-        &self.position
+    pub const fn index(&self) -> usize {
+        self.index
     }
 
-    pub fn peek_char(&mut self) -> Option<char> {
-        self.iter.peek().map(|(_, c)| *c)
+    pub const fn input(&self) -> &'a str {
+        self.input
     }
 
-    pub fn peek_count(&mut self, n: usize) -> &'a str {
-        let start = self.position.index;
-        let mut end = start;
+    /// Consumes everything up until the given byte offset, which must be at a char boundary.
+    pub fn skip_to(&mut self, index: usize) {
+        debug_assert!(index >= self.index && self.input.is_char_boundary(index));
 
-        for i in 1..=n {
-            if let Some((index, char)) = self.iter.peek_nth(i - 1) {
-                end = index + char.len_utf8();
-            } else {
-                break;
-            }
-        }
+        self.index = index;
+    }
 
-        &self.input[start..end]
+    pub fn peek_char(&self) -> Option<char> {
+        self.rest().chars().next()
     }
 
-    pub fn consume_char(&mut self) -> Option<char> {
-        let (i, c) = self.iter.next()?;
+    pub fn peek_count(&self, n: usize) -> &'a str {
+        let rest = self.rest();
+        let end = rest
+            .char_indices()
+            .nth(n)
+            .map_or(rest.len(), |(index, _)| index);
 
-        self.position.index = i + c.len_utf8();
+        &rest[..end]
+    }
+
+    pub fn consume_char(&mut self) -> Option<char> {
+        let c = self.peek_char()?;
 
-        if c == '\n' {
-            self.position.line += 1;
-            self.position.col = 1;
-        } else {
-            self.position.col += 1;
-        }
+        self.advance(c.len_utf8());
 
         Some(c)
     }
 
+    pub fn consume_count(&mut self, n: usize) -> &'a str {
+        let len = self.peek_count(n).len();
+
+        self.advance(len)
+    }
+
     pub fn consume_while<P>(&mut self, predicate: P) -> &'a str
     where
         P: Fn(char) -> bool,
     {
-        let start = self.position.index;
+        let rest = self.rest();
+        let len = rest.find(|c| !predicate(c)).unwrap_or(rest.len());
 
-        while let Some(c) = self.peek_char() {
-            if !predicate(c) {
-                break;
-            }
+        self.advance(len)
+    }
 
-            self.consume_char();
-        }
+    /// Consumes up until, but not including, the first occurrence of any of the given ASCII
+    /// characters. Up to three characters are searched for using `memchr`, which is much faster
+    /// than [`StrRead::consume_while`] for long stretches of text.
+    pub fn consume_until(&mut self, needles: &[u8]) -> &'a str {
+        debug_assert!(needles.is_ascii());
+
+        let rest = self.rest();
+        let haystack = rest.as_bytes();
+        let len = match *needles {
+            [a] => memchr(a, haystack),
+            [a, b] => memchr2(a, b, haystack),
+            [a, b, c] => memchr3(a, b, c, haystack),
+            _ => haystack.iter().position(|byte| needles.contains(byte)),
+        };
+
+        self.advance(len.unwrap_or(rest.len()))
+    }
 
-        let end = self.position.index;
+    pub fn rest(&self) -> &'a str {
+        &self.input[self.index..]
+    }
 
-        &self.input[start..end]
+    /// Consumes the given number of bytes, which must end at a char boundary.
+    pub fn advance(&mut self, len: usize) -> &'a str {
+        let slice = &self.rest()[..len];
+        self.index += len;
+        slice
     }
 }
 
@@ -96,6 +115,33 @@ mod tests {
         assert_eq!(reader.peek_count(0), "");
     }
 
+    #[test]
+    fn consume_until() {
+        let mut reader = StrRead::new("åä\nö$x}");
+        assert_eq!(reader.consume_until(b"$"), "åä\nö");
+        assert_eq!(reader.index(), 7);
+        assert_eq!(reader.consume_until(b"}\n"), "$x");
+        assert_eq!(reader.consume_until(b"$"), "}");
+        assert_eq!(reader.consume_until(b"$"), "");
+    }
+
+    #[test]
+    fn consume_char_index() {
+        let mut reader = StrRead::new("å\nb");
+        assert_eq!(reader.consume_char(), Some('å'));
+        assert_eq!(reader.index(), 2);
+        assert_eq!(reader.consume_char(), Some('\n'));
+        assert_eq!(reader.index(), 3);
+    }
+
+    #[test]
+    fn consume_count() {
+        let mut reader = StrRead::new("åbc");
+        assert_eq!(reader.consume_count(2), "åb");
+        assert_eq!(reader.consume_count(2), "c");
+        assert_eq!(reader.consume_count(1), "");
+    }
+
     #[test]
     fn consume_while() {
         let mut reader = StrRead::new("hi!");
@@ -0,0 +1,355 @@
+use crate::lexer::Lexer;
+use crate::position::{Position, Span};
+use crate::syntax::Syntax;
+use crate::token::Token;
+use crate::{Error, ErrorKind, Xpanda};
+use std::io::BufRead;
+use std::str;
+
+/// The default maximum number of bytes of input held in memory at once by a [`StreamExpander`].
+pub const DEFAULT_BUFFER_BYTES: usize = 64 * 1024;
+
+/// An iterator which expands a [`BufRead`] in chunks, created by [`Xpanda::expand_stream`].
+///
+/// Unlike [`LineExpander`](crate::LineExpander), no more than a fixed number of bytes of input
+/// is held in memory at once, however long its lines are, so that inputs of any size can be
+/// expanded in constant memory. Each chunk is a single line, unless the line doesn't fit into
+/// the buffer, in which case it's split between params, and each chunk is yielded as soon as it
+/// has been expanded. Joining the chunks yields the same result as expanding the whole input at
+/// once. The line and byte offsets of errors refer to the whole input rather than the chunk.
+/// Reading stops at the first error reading the input, but chunks which can't be expanded
+/// don't prevent the following chunks from being expanded.
+///
+/// A single param which doesn't fit into the buffer is an error of kind
+/// [`ErrorKind::TooLong`]. If the syntax allows text spanning several lines, as with
+/// [`Builder::front_matter`], [`Builder::raw_blocks`], [`Builder::conditionals`] or
+/// [`Builder::opaque_regions`], the whole input is read at once instead, as a single chunk.
+///
+/// [`Builder::front_matter`]: crate::Builder::front_matter
+/// [`Builder::raw_blocks`]: crate::Builder::raw_blocks
+/// [`Builder::conditionals`]: crate::Builder::conditionals
+/// [`Builder::opaque_regions`]: crate::Builder::opaque_regions
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::HashMap;
+/// use xpanda::Xpanda;
+///
+/// let xpanda = Xpanda::builder()
+///     .with_named_vars(HashMap::from([(String::from("A"), String::from("a"))]))
+///     .build();
+/// let input = "$A ".repeat(1000);
+/// let chunks = xpanda
+///     .expand_stream(input.as_bytes())
+///     .buffer_bytes(64)
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+///
+/// assert!(chunks.len() > 1);
+/// assert_eq!(chunks.concat(), "a ".repeat(1000));
+/// ```
+pub struct StreamExpander<'a, R> {
+    xpanda: &'a Xpanda,
+    reader: R,
+    buffer_bytes: usize,
+    /// Input which has been read but not expanded yet.
+    buffer: String,
+    /// The input of the chunk which was expanded last.
+    chunk: String,
+    /// The position of the start of the last chunk within the whole input.
+    chunk_start: Position,
+    /// The bytes of a character which has only partially been read yet.
+    partial: Vec<u8>,
+    /// The position of the start of the buffer within the whole input.
+    start: Position,
+    eof: bool,
+    failed: bool,
+}
+
+impl<'a, R: BufRead> StreamExpander<'a, R> {
+    pub(crate) fn new(xpanda: &'a Xpanda, reader: R) -> Self {
+        Self {
+            xpanda,
+            reader,
+            buffer_bytes: DEFAULT_BUFFER_BYTES,
+            buffer: String::new(),
+            chunk: String::new(),
+            chunk_start: Position::default(),
+            partial: Vec::new(),
+            start: Position::default(),
+            eof: false,
+            failed: false,
+        }
+    }
+
+    /// Sets the maximum number of bytes of input held in memory at once, which is also the
+    /// maximum length of a single param. Defaults to [`DEFAULT_BUFFER_BYTES`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::{ErrorKind, Xpanda};
+    ///
+    /// let xpanda = Xpanda::default();
+    /// let mut chunks = xpanda.expand_stream("a ${VAR:-long default}".as_bytes()).buffer_bytes(8);
+    ///
+    /// assert_eq!(chunks.next(), Some(Ok(String::from("a "))));
+    /// assert_eq!(chunks.next().unwrap().unwrap_err().kind, ErrorKind::TooLong);
+    /// assert_eq!(chunks.next(), None);
+    /// ```
+    #[must_use]
+    pub const fn buffer_bytes(mut self, buffer_bytes: usize) -> Self {
+        self.buffer_bytes = buffer_bytes;
+        self
+    }
+
+    /// Returns the input of the chunk which was expanded last, before expanding it, such as to
+    /// show the line in which an error occurred. Empty before the first chunk has been read.
+    #[must_use]
+    pub fn chunk(&self) -> &str {
+        &self.chunk
+    }
+
+    /// Returns the position within the whole input of the start of the chunk which was
+    /// expanded last, which may be in the middle of a line.
+    #[must_use]
+    pub const fn chunk_start(&self) -> Position {
+        self.chunk_start
+    }
+
+    /// Reads input until a chunk can be split off, returning its length, or [`None`] at the end
+    /// of the input.
+    fn read_chunk(&mut self) -> Result<Option<usize>, Error> {
+        loop {
+            if let Some(end) = self.split_point() {
+                return Ok(Some(end));
+            }
+
+            if self.eof {
+                return Ok(None);
+            }
+
+            if self.is_splittable() && self.buffer.len() >= self.buffer_bytes {
+                return Err(Error::new(
+                    ErrorKind::TooLong,
+                    format!(
+                        "Failed to read input: param exceeds the maximum length of {} bytes",
+                        self.buffer_bytes
+                    ),
+                    &self.start,
+                ));
+            }
+
+            self.read()?;
+        }
+    }
+
+    /// Whether the input may be split into chunks, which isn't the case if the syntax allows
+    /// text spanning several lines.
+    const fn is_splittable(&self) -> bool {
+        let syntax = &self.xpanda.syntax;
+
+        !(syntax.front_matter
+            || syntax.raw_blocks
+            || syntax.conditionals
+            || syntax.opaque_regions.is_some())
+    }
+
+    /// Returns the length of the next chunk, if it can be expanded without reading more input.
+    fn split_point(&self) -> Option<usize> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+
+        if self.eof {
+            return Some(self.buffer.len());
+        }
+
+        if !self.is_splittable() {
+            return None;
+        }
+
+        // Params can't span lines, so lines are expanded one by one if they fit into the buffer
+        if let Some(index) = self.buffer.find('\n') {
+            return Some(index + 1);
+        }
+
+        // Otherwise the line is split as late as possible, once the buffer is full
+        if self.buffer.len() + self.partial.len() < self.buffer_bytes {
+            return None;
+        }
+
+        Some(split_line(&self.buffer, &self.xpanda.syntax)).filter(|index| *index > 0)
+    }
+
+    /// Reads more input into the buffer, without exceeding its size unless the whole input is
+    /// read at once.
+    fn read(&mut self) -> Result<(), Error> {
+        let limit = self.is_splittable().then(|| {
+            let free = self
+                .buffer_bytes
+                .saturating_sub(self.buffer.len() + self.partial.len());
+            free.max(1)
+        });
+        let available = match self.reader.fill_buf() {
+            Ok(available) => available,
+            Err(error) => return Err(self.read_error(&error.to_string())),
+        };
+
+        if available.is_empty() {
+            self.eof = true;
+
+            return if self.partial.is_empty() {
+                Ok(())
+            } else {
+                Err(self.read_error("stream did not contain valid UTF-8"))
+            };
+        }
+
+        let len = limit.map_or(available.len(), |limit| available.len().min(limit));
+
+        self.partial.extend_from_slice(&available[..len]);
+        self.reader.consume(len);
+
+        // A character may be split across reads, in which case its first bytes are kept
+        let valid = match str::from_utf8(&self.partial) {
+            Ok(text) => text.len(),
+            Err(error) if error.error_len().is_none() => error.valid_up_to(),
+            Err(_) => return Err(self.read_error("stream did not contain valid UTF-8")),
+        };
+
+        self.buffer
+            .push_str(str::from_utf8(&self.partial[..valid]).unwrap_or_default());
+        self.partial.drain(..valid);
+
+        Ok(())
+    }
+
+    fn read_error(&self, message: &str) -> Error {
+        Error::new(
+            ErrorKind::Io,
+            format!("Failed to read input: {message}"),
+            &self.start,
+        )
+    }
+
+    /// Moves the start of the buffer past the chunk which was split off.
+    fn advance(&mut self) {
+        let chunk = &self.chunk;
+        self.start.index += chunk.len();
+
+        match chunk.rfind('\n') {
+            Some(index) => {
+                self.start.line += chunk.matches('\n').count();
+                self.start.col = chunk[index + 1..].chars().count() + 1;
+            },
+            None => self.start.col += chunk.chars().count(),
+        }
+    }
+
+    /// Translates the position of an error which occurred while expanding the last chunk into
+    /// the position within the whole input.
+    fn locate(&self, error: Error) -> Error {
+        let Some(span) = error.span else {
+            return error;
+        };
+        let start = self.chunk_start;
+        let offset = |position: Position| {
+            Position::new(
+                position.index + start.index,
+                position.line + start.line - 1,
+                offset_col(position.line, position.col, start),
+            )
+        };
+
+        Error {
+            line: error.line + start.line - 1,
+            col: offset_col(error.line, error.col, start),
+            span: Some(Span::new(offset(span.start), offset(span.end))),
+            ..error
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for StreamExpander<'_, R> {
+    type Item = Result<String, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let end = match self.read_chunk() {
+            Ok(end) => end?,
+            Err(error) => {
+                self.failed = true;
+                return Some(Err(error));
+            },
+        };
+
+        self.chunk.clear();
+        self.chunk.extend(self.buffer.drain(..end));
+        self.chunk_start = self.start;
+        self.advance();
+
+        // A chunk split off in the middle of a line can't start a comment line
+        let result = self
+            .xpanda
+            .parse_at(&self.chunk, self.chunk_start.col == 1)
+            .and_then(|template| self.xpanda.render(&template));
+
+        Some(result.map_err(|error| self.locate(error)))
+    }
+}
+
+/// Chunks may start in the middle of a line, in which case columns on their first line are
+/// offset by the start of the chunk.
+const fn offset_col(line: usize, col: usize, start: Position) -> usize {
+    if line == 1 {
+        col + start.col - 1
+    } else {
+        col
+    }
+}
+
+/// Finds the last offset at which a line can be split in two, such that expanding both parts
+/// on their own yields the same result as expanding the whole line, or 0 if there's none. Lines
+/// are split before tokens outside of params, or within text, leaving the last character of the
+/// text to the second part as the text might continue past the end of what was read so far, or
+/// after a braced param at the end of the line.
+fn split_line(line: &str, syntax: &Syntax) -> usize {
+    let mut lexer = Lexer::new(line, syntax.clone());
+    let mut split = 0;
+    let mut is_closed = false;
+
+    loop {
+        let start = lexer.index();
+        let is_boundary = lexer.is_at_boundary();
+        let Some((token, end)) = lexer.next_token() else {
+            // A param which ends with the line can't continue past it
+            return if is_boundary && is_closed {
+                line.len()
+            } else {
+                split
+            };
+        };
+
+        is_closed = token == Token::CloseBrace;
+
+        if !is_boundary {
+            continue;
+        }
+
+        split = start;
+
+        // Escaped dollar signs are text as well, but can't be split
+        if matches!(token, Token::Text(_)) && !line[start..end].starts_with('$') {
+            let last = line[start..end]
+                .chars()
+                .next_back()
+                .map_or(0, char::len_utf8);
+            split = end - last;
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+/// The characters naming the special parameters which can be enabled, as in `$?`.
+pub const SPECIAL_VARS: &str = "?$#*@!-";
+
+/// The markers starting and ending raw blocks, inside which text is read as is.
+pub const RAW_BLOCK_OPEN: &str = "$[[raw]]";
+pub const RAW_BLOCK_CLOSE: &str = "$[[/raw]]";
+
+/// The marker starting comment lines, which are left out of the output.
+pub const COMMENT: &str = "$#";
+
+/// The keywords of conditional blocks, as in `${if VAR}...${else}...${end}`.
+pub const IF: &str = "if";
+pub const ELSE: &str = "else";
+pub const END: &str = "end";
+
+/// Options changing how templates are lexed and parsed.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Syntax {
+    /// Whether single and double quotes inside params quote text, as in `${VAR:-"a } b"}`.
+    pub quoting: bool,
+    /// Whether identifiers starting with a digit, such as `1abc`, are named variables rather than
+    /// rejected. Identifiers consisting of only digits are always positional variables.
+    pub lenient_identifiers: bool,
+    /// Whether PowerShell-style environment variables, as in `$env:NAME`, are recognized.
+    pub powershell_env: bool,
+    /// Whether lines starting with `#! xpanda:` at the start of the input are front matter.
+    pub front_matter: bool,
+    /// Whether params which may resolve variables other than those provided, such as `${!VAR}`
+    /// and `${env:HOME}`, are rejected.
+    pub safe_mode: bool,
+    /// Whether text between [`RAW_BLOCK_OPEN`] and [`RAW_BLOCK_CLOSE`] is read as is.
+    pub raw_blocks: bool,
+    /// Whether lines starting with [`COMMENT`] are comments.
+    pub comments: bool,
+    /// Whether `${if VAR}`, `${else}` and `${end}` delimit conditional blocks rather than
+    /// being params.
+    pub conditionals: bool,
+    /// Whether the names of braced params may contain params, as in `${PREFIX_${ENV}}`, which
+    /// are expanded to compute the name of the variable.
+    pub dynamic_names: bool,
+    /// Whether a default or alternative value starting with `=` is an expression, as in
+    /// `${VAR:-=$A + 1}`. Only ever set if the `expr` feature is enabled.
+    pub expressions: bool,
+    /// The special parameters which are recognized, as a set of bits indexed by the position
+    /// of their character in [`SPECIAL_VARS`].
+    pub special_vars: u8,
+    /// The delimiters of regions outside of params which are read as text, as is.
+    pub opaque_regions: Option<Arc<OpaqueRegions>>,
+    /// How ANSI-C quoted text, as in `$'a\tb'`, is read.
+    pub ansi_c_quotes: AnsiCQuotes,
+}
+
+/// How ANSI-C quoted text of shell scripts, as in `$'a\tb'`, is read, see
+/// [`Builder::ansi_c_quotes`](crate::Builder::ansi_c_quotes).
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum AnsiCQuotes {
+    /// A syntax error, since a `$` which isn't escaped starts a param.
+    #[default]
+    Error,
+    /// Text with its escape sequences interpreted, such as a tab for `$'a\tb'`.
+    Interpret,
+    /// Text which is kept as is, including the `$` and the quotes.
+    Keep,
+}
+
+/// The delimiters of regions which are read as text, as is, such as `{{ ... }}` of other
+/// template engines.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct OpaqueRegions {
+    pub open: String,
+    pub close: String,
+}
+
+impl OpaqueRegions {
+    /// Finds the first region in the text, returning its byte range including the delimiters.
+    /// An opening delimiter without a matching closing delimiter doesn't start a region.
+    pub fn find(&self, text: &str) -> Option<Range<usize>> {
+        let start = text.find(&self.open)?;
+        let body = start + self.open.len();
+        let end = body + text[body..].find(&self.close)? + self.close.len();
+
+        Some(start..end)
+    }
+}
+
+impl Syntax {
+    /// Whether the character names a special parameter which is recognized, as in `$?`.
+    pub fn is_special_var(&self, c: char) -> bool {
+        SPECIAL_VARS
+            .find(c)
+            .is_some_and(|index| self.special_vars & (1 << index) != 0)
+    }
+}
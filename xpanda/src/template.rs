@@ -0,0 +1,1110 @@
+use crate::ast::{Ast, Identifier, Node, Param};
+#[cfg(feature = "serialize")]
+use crate::binary;
+use crate::bytecode::CompiledTemplate;
+use crate::front_matter::{self, FrontMatter};
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::position::Position;
+use crate::simplify::Simplifier;
+use crate::syntax::Syntax;
+use crate::{Builder, Error, ErrorKind, Xpanda};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::slice;
+
+/// A parsed template which can be inspected without being expanded.
+///
+/// Formatting a [`Template`] using [`Display`] yields a normalized version of the original text
+/// in which all params are braced, for example `$VAR` is formatted as `${VAR}`. Text is formatted
+/// as it was written, keeping its escapes and quotes, as in `${VAR:-a$ b}`. Templates parsed
+/// using [`Xpanda::parse`](crate::Xpanda::parse) are formatted using the same syntax options.
+#[derive(Debug, Clone)]
+pub struct Template<'a> {
+    pub(crate) ast: Ast<'a>,
+    /// The text of each param outside of other params as written in the input, in order, or as
+    /// formatted for conditional blocks changed by [`Template::embed`].
+    pub(crate) param_texts: Vec<Cow<'a, str>>,
+    pub(crate) front_matter: Option<FrontMatter>,
+    pub(crate) header: Cow<'a, str>,
+    pub(crate) syntax: Syntax,
+}
+
+/// The text of params is not compared, just like their positions, so that templates are equal
+/// regardless of how their params are written.
+impl PartialEq for Template<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ast == other.ast
+            && self.front_matter == other.front_matter
+            && self.header == other.header
+            && self.syntax == other.syntax
+    }
+}
+
+impl Eq for Template<'_> {}
+
+impl<'a> Template<'a> {
+    /// Parses the given text into a template.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the given string is badly formatted and cannot be parsed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::Template;
+    ///
+    /// let template = Template::parse("$VAR").unwrap();
+    /// assert_eq!(template.to_string(), "${VAR}");
+    /// ```
+    pub fn parse(input: &'a str) -> Result<Self, Error> {
+        Self::parse_with_syntax(input, Syntax::default())
+    }
+
+    /// Parses the given text into a template just like [`Template::parse`], but on failure also
+    /// returns the part of the template which was parsed before the error.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the given string is badly formatted and cannot be parsed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::Template;
+    ///
+    /// let error = Template::parse_partial("$A ${B:-").unwrap_err();
+    /// assert_eq!(error.partial.variables(), vec!["A"]);
+    /// assert_eq!(error.error.message, "Expected a matching '}' for the '${' opened");
+    /// ```
+    #[allow(clippy::result_large_err)]
+    pub fn parse_partial(input: &'a str) -> Result<Self, ParseError<'a>> {
+        Self::parse_partial_with_syntax(input, Syntax::default())
+    }
+
+    pub(crate) fn parse_with_syntax(input: &'a str, syntax: Syntax) -> Result<Self, Error> {
+        Ok(Self::parse_partial_with_syntax(input, syntax)?)
+    }
+
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn parse_partial_with_syntax(
+        input: &'a str,
+        syntax: Syntax,
+    ) -> Result<Self, ParseError<'a>> {
+        Self::parse_partial_at(input, syntax, true)
+    }
+
+    /// Parses the input, which starts in the middle of a line unless `starts_line` is set, as
+    /// does a chunk split off a long line.
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn parse_partial_at(
+        input: &'a str,
+        syntax: Syntax,
+        starts_line: bool,
+    ) -> Result<Self, ParseError<'a>> {
+        let (front_matter, header_len) = if syntax.front_matter {
+            match front_matter::parse(input) {
+                Ok(Some((front_matter, len))) => (Some(front_matter), len),
+                Ok(None) => (None, 0),
+                Err(error) => {
+                    return Err(ParseError {
+                        partial: Self::new(
+                            Ast::new(Vec::new()),
+                            Vec::new(),
+                            None,
+                            Cow::Borrowed(""),
+                            syntax,
+                        ),
+                        error,
+                    });
+                },
+            }
+        } else {
+            (None, 0)
+        };
+
+        let mut lexer = Lexer::new(input, syntax.clone());
+        lexer.starts_line(starts_line);
+        lexer.skip(header_len);
+
+        let header = Cow::Borrowed(&input[..header_len]);
+        let mut parser = Parser::new(lexer);
+
+        match parser.parse() {
+            Ok(ast) => Ok(Self::new(
+                ast,
+                parser.take_param_texts(),
+                front_matter,
+                header,
+                syntax,
+            )),
+            Err((error, partial)) => Err(ParseError {
+                partial: Self::new(
+                    partial,
+                    parser.take_param_texts(),
+                    front_matter,
+                    header,
+                    syntax,
+                ),
+                error: error.into(),
+            }),
+        }
+    }
+
+    pub(crate) const fn new(
+        ast: Ast<'a>,
+        param_texts: Vec<Cow<'a, str>>,
+        front_matter: Option<FrontMatter>,
+        header: Cow<'a, str>,
+        syntax: Syntax,
+    ) -> Self {
+        Self {
+            ast,
+            param_texts,
+            front_matter,
+            header,
+            syntax,
+        }
+    }
+
+    /// Serializes the template to a compact binary format, which can be loaded using
+    /// [`Template::from_bytes`] without parsing the template again. The format starts with a
+    /// version number, and only templates serialized with the same version can be loaded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::{Template, Xpanda};
+    ///
+    /// let bytes = Template::parse("Hello ${NAME:-world}!").unwrap().to_bytes();
+    /// let template = Template::from_bytes(&bytes).unwrap().compile();
+    ///
+    /// assert_eq!(Xpanda::default().render_compiled(&template), Ok(String::from("Hello world!")));
+    /// ```
+    #[cfg(feature = "serialize")]
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        binary::write(self)
+    }
+
+    /// Loads a template serialized using [`Template::to_bytes`], borrowing its text from the
+    /// given bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] with [`ErrorKind::Format`] if the bytes aren't a serialized template,
+    /// were serialized with a different version of the format, or use syntax of a cargo
+    /// feature which is disabled.
+    #[cfg(feature = "serialize")]
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, Error> {
+        binary::read(bytes)
+    }
+
+    /// Whether the template renders as the given input it was parsed from, which is the case if
+    /// it has no front matter and no params, and none of its text was unescaped.
+    pub(crate) fn is_literal(&self, input: &str) -> bool {
+        let text_len = self
+            .ast
+            .nodes
+            .iter()
+            .map(|node| match node {
+                Node::Text(text, _) => Some(text.len()),
+                Node::Param(..) => None,
+                #[cfg(feature = "expr")]
+                Node::Expr(..) => None,
+            })
+            .sum::<Option<usize>>();
+
+        self.front_matter.is_none() && text_len == Some(input.len())
+    }
+
+    /// Compiles the template for rendering it repeatedly. See [`CompiledTemplate`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::{Template, Xpanda};
+    ///
+    /// let template = Template::parse("${1:-default}").unwrap().compile();
+    /// assert_eq!(Xpanda::default().render_compiled(&template), Ok(String::from("default")));
+    /// ```
+    #[must_use]
+    pub fn compile(self) -> CompiledTemplate<'a> {
+        CompiledTemplate::new(self)
+    }
+
+    /// The default values declared by the front matter, if any.
+    pub(crate) fn defaults(&self) -> &[(String, String)] {
+        self.front_matter
+            .as_ref()
+            .map_or(&[][..], |front_matter| &front_matter.defaults)
+    }
+
+    /// Returns the front matter of the template, if it has any and front matter is enabled
+    /// using [`Builder::front_matter`](crate::Builder::front_matter).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::Xpanda;
+    ///
+    /// let xpanda = Xpanda::builder().front_matter(true).build();
+    /// let template = xpanda.parse("#! xpanda: require DB_URL\n$DB_URL").unwrap();
+    /// assert_eq!(template.front_matter().unwrap().required, vec!["DB_URL"]);
+    /// ```
+    #[must_use]
+    pub const fn front_matter(&self) -> Option<&FrontMatter> {
+        self.front_matter.as_ref()
+    }
+
+    /// Returns the names of all variables referenced by the template, in order of first
+    /// appearance. Positional variables are represented by their index.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::Template;
+    ///
+    /// let template = Template::parse("$B ${A:-$1} $B").unwrap();
+    /// assert_eq!(template.variables(), vec!["B", "A", "1"]);
+    /// ```
+    #[must_use]
+    pub fn variables(&self) -> Vec<String> {
+        let mut variables = Vec::new();
+
+        for reference in self.references() {
+            if !variables.contains(&reference.name) {
+                variables.push(reference.name);
+            }
+        }
+
+        variables
+    }
+
+    /// Returns every reference to a variable in the template, in order of appearance. A
+    /// reference has a default if it's part of a `${VAR-default}` pattern, or if the front
+    /// matter of the template declares a default value for the variable.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::{Position, Template};
+    ///
+    /// let template = Template::parse("$A\n${B:-$A}").unwrap();
+    /// let references = template
+    ///     .references()
+    ///     .into_iter()
+    ///     .map(|reference| (reference.name, reference.position, reference.has_default))
+    ///     .collect::<Vec<_>>();
+    ///
+    /// assert_eq!(
+    ///     references,
+    ///     vec![
+    ///         (String::from("A"), Position::new(0, 1, 1), false),
+    ///         (String::from("B"), Position::new(3, 2, 1), true),
+    ///         (String::from("A"), Position::new(8, 2, 6), false),
+    ///     ]
+    /// );
+    /// ```
+    #[must_use]
+    pub fn references(&self) -> Vec<Reference> {
+        let mut references = self
+            .identified_references()
+            .into_iter()
+            .map(|(_, reference)| reference)
+            .collect::<Vec<_>>();
+
+        if let Some(front_matter) = &self.front_matter {
+            for reference in &mut references {
+                reference.has_default |= front_matter
+                    .defaults
+                    .iter()
+                    .any(|(name, _)| *name == reference.name);
+            }
+        }
+
+        references
+    }
+
+    /// Returns the identifier of each variable referenced by the template in order of first
+    /// reference, along with its name. Like for [`Xpanda::sources`], variables referenced
+    /// indirectly are omitted.
+    pub(crate) fn referenced_identifiers(&self) -> Vec<(String, Cow<'_, Identifier<'_>>)> {
+        let mut identifiers = Vec::<(String, Cow<Identifier>)>::new();
+
+        for (identifier, reference) in self.identified_references() {
+            if !reference.is_indirect && identifiers.iter().all(|(name, _)| *name != reference.name)
+            {
+                identifiers.push((reference.name, identifier));
+            }
+        }
+
+        identifiers
+    }
+
+    /// Returns the references of the template along with the identifier each refers to,
+    /// without applying the defaults of the front matter.
+    fn identified_references(&self) -> Vec<(Cow<'_, Identifier<'_>>, Reference)> {
+        let mut references = Vec::new();
+
+        for node in &self.ast.nodes {
+            collect_references(node, &Reached::Directly, &self.syntax, &mut references);
+        }
+
+        references
+    }
+
+    /// Returns the dynamic identifiers of the template, as in `${PREFIX_${ENV}}`, whose names
+    /// are only known once computed, including those nested inside other params.
+    pub(crate) fn dynamic_identifiers(&self) -> Vec<&Identifier<'a>> {
+        let mut identifiers = Vec::new();
+        collect_dynamic_identifiers(&self.ast.nodes, &mut identifiers);
+        identifiers
+    }
+
+    /// Returns the names of the variables which must be set to render the template, in order of
+    /// first appearance. A variable is required if the front matter requires it, or if the
+    /// template references it directly without a default or alternative value, as in `$VAR`,
+    /// `${VAR:?message}` or `${#VAR}`, or in the name of any param, as `ENV` in
+    /// `${DB_${ENV}:-none}`. Namespaced variables are never required since they are resolved
+    /// by providers, and neither is `$0`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::Template;
+    ///
+    /// let template = Template::parse("$A ${B:-$C} ${D:+x} ${E:?missing} $A").unwrap();
+    /// assert_eq!(template.required_variables(), vec!["A", "E"]);
+    /// ```
+    #[must_use]
+    pub fn required_variables(&self) -> Vec<String> {
+        let defaults = self.defaults();
+        let mut required = self
+            .front_matter
+            .as_ref()
+            .map_or_else(Vec::new, |front_matter| front_matter.required.clone());
+
+        for (identifier, _) in self.required_identifiers(None) {
+            let name = identifier.to_string();
+
+            if !required.contains(&name) && !defaults.iter().any(|(default, _)| *default == name) {
+                required.push(name);
+            }
+        }
+
+        required
+    }
+
+    /// Returns the identifiers of the variables which must be set to render the template,
+    /// together with the position of the param referring to each of them, including those
+    /// nested inside dynamic names. The default, alternative or conditional value of a param is
+    /// only followed if `is_empty`, which tells whether a variable is empty or [`None`] if it's
+    /// unset, shows that the value is used.
+    pub(crate) fn required_identifiers(
+        &self,
+        is_empty: Option<IsEmpty>,
+    ) -> Vec<(&Identifier<'a>, Position)> {
+        let mut identifiers = Vec::new();
+        collect_required(&self.ast.nodes, is_empty, &mut identifiers);
+        identifiers
+    }
+
+    /// Returns the names of the variables referenced by the template which aren't required to
+    /// render it, in order of first appearance. See [`Template::required_variables`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::Template;
+    ///
+    /// let template = Template::parse("$A ${B:-$C} ${D:+x} ${E:?missing} $A").unwrap();
+    /// assert_eq!(template.optional_variables(), vec!["B", "C", "D"]);
+    /// ```
+    #[must_use]
+    pub fn optional_variables(&self) -> Vec<String> {
+        let required = self.required_variables();
+
+        self.variables()
+            .into_iter()
+            .filter(|name| !required.contains(name))
+            .collect()
+    }
+
+    /// Binds values to the variables of the template, failing fast if any required variable is
+    /// left unbound, before anything is rendered. Variables are bound by the names returned by
+    /// [`Template::variables`], so positional variables are bound by their index. Positional
+    /// variables before the highest bound index are bound to an empty value if not given.
+    ///
+    /// Required variables include those inside the default, alternative or conditional values
+    /// which are used with the bound values, as `B` in `${A:-$B}` if `A` isn't bound. The
+    /// template is rendered using the default options, see [`Xpanda::bind`] to render it using
+    /// the options, schema and variables of an instance instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] with [`ErrorKind::Required`], listing every unbound required variable,
+    /// at the first reference to any of them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::{ErrorKind, Template};
+    ///
+    /// let template = Template::parse("$GREETING ${NAME:-world} $1").unwrap();
+    /// let error = template.bind(HashMap::new()).unwrap_err();
+    /// assert_eq!(error.kind, ErrorKind::Required);
+    /// assert_eq!(error.message, "Unbound required variable(s) GREETING, 1");
+    ///
+    /// let vars = HashMap::from([
+    ///     (String::from("GREETING"), String::from("Hello")),
+    ///     (String::from("1"), String::from("!")),
+    /// ]);
+    /// assert_eq!(template.bind(vars).unwrap().render(), Ok(String::from("Hello world !")));
+    /// ```
+    pub fn bind(&self, vars: HashMap<String, String>) -> Result<BoundTemplate<'_, 'a>, Error> {
+        Xpanda::default().bind(self, vars)
+    }
+
+    /// Substitutes the given variables, which are named like in [`Template::bind`], and folds
+    /// them into a smaller template which only references the variables that are left unknown.
+    /// Rendering the simplified template with the remaining variables yields the same result as
+    /// rendering this template with all of them, such as to substitute the values known at
+    /// build time ahead of the values known at runtime.
+    ///
+    /// Params are folded as far as the known variables allow, for example `${A:-$B}` becomes
+    /// `${B}` if `A` is known to be unset, while a conditional block with a known variable
+    /// is replaced by the branch it would take. Indirect params become params referring to the
+    /// variable directly once its name is known, as `${!REF}` becomes `${NAME}` if `REF` is
+    /// `NAME`, while expressions are only folded if all the variables they refer to are known.
+    /// `${#}` is never folded, as it counts the positional variables given when rendering. The
+    /// front matter, if any, is kept as is.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if rendering a param whose variables are all known fails, such as
+    /// `${VAR:?}` with an empty value. Inside a default or alternative value which may not be
+    /// used, such a param is kept such that rendering fails with the same message instead, if
+    /// the value is used then.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::Template;
+    ///
+    /// let template = Template::parse("https://$HOST:${PORT:-8080}/${DIR:-static}/$FILE").unwrap();
+    /// let vars = HashMap::from([
+    ///     (String::from("HOST"), String::from("example.com")),
+    ///     (String::from("PORT"), String::new()),
+    /// ]);
+    ///
+    /// assert_eq!(
+    ///     template.simplify(&vars),
+    ///     Ok(String::from("https://example.com:8080/${DIR:-static}/${FILE}"))
+    /// );
+    /// ```
+    pub fn simplify(&self, vars: &HashMap<String, String>) -> Result<String, Error> {
+        let xpanda = bind_vars(Xpanda::builder(), vars.clone(), &self.syntax).build();
+        let simplifier = Simplifier::new(vars, self.defaults(), xpanda);
+        let ast = Ast::new(simplifier.simplify_nodes(&self.ast.nodes)?);
+
+        Ok(format!("{}{}", self.header, ast.display(&self.syntax)))
+    }
+
+    /// Composes a template out of the given templates in order, as if their text was
+    /// concatenated, without parsing them again. The front matter of all templates is merged,
+    /// in which case a default value declared by an earlier template takes precedence, and the
+    /// syntax options of the first template are used for formatting the composed template.
+    ///
+    /// Params keep their positions within the template they were parsed from, such that errors
+    /// and [`Template::references`] point into the template the param comes from.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xpanda::{Template, Xpanda};
+    ///
+    /// let header = Template::parse("# ${TITLE:-Untitled}\n").unwrap();
+    /// let body = Template::parse("$BODY").unwrap();
+    /// let template = Template::concat(&[header, body]);
+    ///
+    /// let xpanda = Xpanda::builder()
+    ///     .with_named_vars(HashMap::from([(String::from("BODY"), String::from("Text"))]))
+    ///     .build();
+    /// assert_eq!(template.to_string(), "# ${TITLE:-Untitled}\n${BODY}");
+    /// assert_eq!(xpanda.render(&template), Ok(String::from("# Untitled\nText")));
+    /// ```
+    #[must_use]
+    pub fn concat(templates: &[Self]) -> Self {
+        let mut nodes = Vec::new();
+        let mut param_texts = Vec::new();
+
+        for template in templates {
+            nodes.extend(template.ast.nodes.iter().cloned());
+            param_texts.extend(template.param_texts.iter().cloned());
+        }
+
+        let syntax = templates
+            .first()
+            .map_or_else(Syntax::default, |template| template.syntax.clone());
+        let templates = templates.iter().collect::<Vec<_>>();
+
+        Self::new(
+            Ast::new(nodes),
+            param_texts,
+            merge_front_matter(&templates),
+            merge_headers(&templates),
+            syntax,
+        )
+    }
+
+    /// Composes a template by replacing every `${NAME}` placeholder of the given name with the
+    /// given template, without parsing either of them again. Placeholders are only replaced
+    /// outside of other params, including inside conditional blocks, and only if they have no
+    /// modifier. The front matter of both templates is merged like in [`Template::concat`].
+    ///
+    /// Params keep their positions within the template they were parsed from, such that errors
+    /// and [`Template::references`] point into the template the param comes from.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use xpanda::{ErrorKind, Position, Template, Xpanda};
+    ///
+    /// let layout = Template::parse("<main>\n  $CONTENT\n</main>").unwrap();
+    /// let content = Template::parse("${TEXT:?no text}").unwrap();
+    /// let template = layout.embed("CONTENT", &content);
+    ///
+    /// assert_eq!(template.to_string(), "<main>\n  ${TEXT:?no text}\n</main>");
+    ///
+    /// let error = Xpanda::default().render(&template).unwrap_err();
+    /// assert_eq!(error.kind, ErrorKind::Required);
+    /// assert_eq!(template.references()[0].position, Position::new(0, 1, 1));
+    /// ```
+    #[must_use]
+    pub fn embed(&self, name: &str, template: &Self) -> Self {
+        let mut param_texts = Vec::new();
+        let mut texts = self.param_texts.iter();
+
+        for node in &self.ast.nodes {
+            if !matches!(node, Node::Param(..)) {
+                continue;
+            }
+
+            if is_placeholder(node, name) {
+                texts.next();
+                param_texts.extend(template.param_texts.iter().cloned());
+            } else if contains_placeholder(node, name) {
+                // The text as written no longer matches the block, so it's formatted instead
+                texts.next();
+                let nodes = embed_nodes(slice::from_ref(node), name, &template.ast.nodes);
+                let text = Ast::new(nodes).display(&self.syntax).to_string();
+                param_texts.push(Cow::Owned(text));
+            } else {
+                param_texts.extend(texts.next().cloned());
+            }
+        }
+
+        let templates = [self, template];
+
+        Self::new(
+            Ast::new(embed_nodes(&self.ast.nodes, name, &template.ast.nodes)),
+            param_texts,
+            merge_front_matter(&templates),
+            merge_headers(&templates),
+            self.syntax.clone(),
+        )
+    }
+}
+
+/// Whether the node is a `${NAME}` placeholder of the given name, as replaced by
+/// [`Template::embed`].
+fn is_placeholder(node: &Node, name: &str) -> bool {
+    matches!(
+        node,
+        Node::Param(Param::Simple {
+            identifier: Identifier::Named(other),
+            modifier: None,
+        }, _) if *other == name
+    )
+}
+
+/// Whether the node is a conditional block containing a placeholder replaced by
+/// [`Template::embed`], at any depth.
+fn contains_placeholder(node: &Node, name: &str) -> bool {
+    match node {
+        Node::Param(
+            Param::Conditional {
+                then, otherwise, ..
+            },
+            _,
+        ) => then
+            .iter()
+            .chain(otherwise.iter())
+            .any(|node| is_placeholder(node, name) || contains_placeholder(node, name)),
+        _ => false,
+    }
+}
+
+fn embed_nodes<'a>(nodes: &[Node<'a>], name: &str, embedded: &[Node<'a>]) -> Vec<Node<'a>> {
+    let mut output = Vec::with_capacity(nodes.len());
+
+    for node in nodes {
+        match node {
+            _ if is_placeholder(node, name) => output.extend(embedded.iter().cloned()),
+            Node::Param(
+                Param::Conditional {
+                    identifier,
+                    then,
+                    otherwise,
+                },
+                position,
+            ) => output.push(Node::Param(
+                Param::Conditional {
+                    identifier: identifier.clone(),
+                    then: embed_nodes(then, name, embedded).into_boxed_slice(),
+                    otherwise: embed_nodes(otherwise, name, embedded).into_boxed_slice(),
+                },
+                *position,
+            )),
+            _ => output.push(node.clone()),
+        }
+    }
+
+    output
+}
+
+/// Merges the front matter of the templates, keeping the span of the first one.
+fn merge_front_matter(templates: &[&Template]) -> Option<FrontMatter> {
+    let mut merged: Option<FrontMatter> = None;
+
+    for front_matter in templates
+        .iter()
+        .filter_map(|template| template.front_matter())
+    {
+        let Some(merged) = &mut merged else {
+            merged = Some(front_matter.clone());
+            continue;
+        };
+
+        for name in &front_matter.required {
+            if !merged.required.contains(name) {
+                merged.required.push(name.clone());
+            }
+        }
+
+        merged
+            .defaults
+            .extend(front_matter.defaults.iter().cloned());
+    }
+
+    merged
+}
+
+/// Joins the front matter text of the templates, which is formatted at the start of the
+/// composed template, each on lines of its own.
+fn merge_headers<'a>(templates: &[&Template<'a>]) -> Cow<'a, str> {
+    let mut headers = templates
+        .iter()
+        .map(|template| &template.header)
+        .filter(|header| !header.is_empty());
+
+    let Some(first) = headers.next() else {
+        return Cow::Borrowed("");
+    };
+
+    let mut merged = first.clone();
+
+    for header in headers {
+        if !merged.ends_with('\n') {
+            merged.to_mut().push('\n');
+        }
+
+        merged.to_mut().push_str(header);
+    }
+
+    merged
+}
+
+/// A [`Template`] with values bound to all of its required variables, created using
+/// [`Template::bind`] or [`Xpanda::bind`].
+pub struct BoundTemplate<'t, 'a> {
+    pub(crate) template: &'t Template<'a>,
+    pub(crate) xpanda: Xpanda,
+}
+
+impl fmt::Debug for BoundTemplate<'_, '_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("BoundTemplate")
+            .field("template", &self.template)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BoundTemplate<'_, '_> {
+    /// Renders the template using the bound values. See [`Xpanda::render`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] in the same cases as [`Xpanda::render`], such as when a variable in an
+    /// error param of a default value is unbound.
+    pub fn render(&self) -> Result<String, Error> {
+        self.xpanda.render(self.template)
+    }
+}
+
+/// A reference to a variable in a [`Template`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Reference {
+    /// The name of the variable. Positional variables are represented by their index.
+    pub name: String,
+    /// The position of the `$` starting the reference.
+    pub position: Position,
+    /// Whether a default value is used if the variable is unset.
+    pub has_default: bool,
+    /// Whether the reference is part of the template itself or of the value of another param.
+    pub reached: Reached,
+    /// Whether the value of the variable is the name of another variable to substitute, as in
+    /// `${!VAR}`. Which variable that is can't be known without the value.
+    pub is_indirect: bool,
+}
+
+/// How a [`Reference`] is reached when expanding a template.
+///
+/// References inside the default or alternative value of a param depend on the variable of that
+/// param, which makes it possible to build a graph of the dependencies between variables.
+///
+/// # Examples
+///
+/// ```
+/// use xpanda::{Reached, Template};
+///
+/// let template = Template::parse("${A:-${B:+$C}}").unwrap();
+/// let reached = template
+///     .references()
+///     .into_iter()
+///     .map(|reference| reference.reached)
+///     .collect::<Vec<_>>();
+///
+/// assert_eq!(
+///     reached,
+///     vec![
+///         Reached::Directly,
+///         Reached::ViaDefault(String::from("A")),
+///         Reached::ViaAlternative(String::from("B")),
+///     ]
+/// );
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Reached {
+    /// The reference is part of the template itself.
+    Directly,
+    /// The reference is part of the default value for the named variable, as `B` in
+    /// `${A:-$B}`, and is only resolved if that variable is unset.
+    ViaDefault(String),
+    /// The reference is part of the alternative value for the named variable, as `B` in
+    /// `${A:+$B}`, and is only resolved if that variable is set.
+    ViaAlternative(String),
+    /// The reference is part of the error message for the named variable, as `B` in
+    /// `${A:?$B}`, and is only resolved if that variable is unset.
+    ViaError(String),
+}
+
+/// An error which occurred while parsing a [`Template`], together with the part of the template
+/// parsed before the error. Tooling such as linters can still inspect the valid start of a
+/// template this way.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct ParseError<'a> {
+    /// The template up until the param or text in which the error occurred.
+    pub partial: Template<'a>,
+    pub error: Error,
+}
+
+impl Display for ParseError<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::error::Error for ParseError<'_> {}
+
+impl From<ParseError<'_>> for Error {
+    fn from(error: ParseError) -> Self {
+        error.error
+    }
+}
+
+impl Display for Template<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.header, self.ast.display(&self.syntax))
+    }
+}
+
+/// Adds variables named like those returned by [`Template::variables`] to the builder, with
+/// single characters only bound as special variables if they are enabled in the template's
+/// syntax. Positional variables replace those of the builder, if any are given.
+pub fn bind_vars(builder: Builder, vars: HashMap<String, String>, syntax: &Syntax) -> Builder {
+    let mut positional_vars = Vec::new();
+    let mut special_vars = HashMap::new();
+    let mut named_vars = HashMap::new();
+
+    for (name, value) in vars {
+        match Identifier::from_name(&name, |c| syntax.is_special_var(c)) {
+            Identifier::Indexed(0) | Identifier::Namespaced { .. } | Identifier::Dynamic(_) => {},
+            Identifier::Indexed(index) => {
+                if positional_vars.len() < index {
+                    positional_vars.resize(index, String::new());
+                }
+
+                positional_vars[index - 1] = value;
+            },
+            Identifier::Special(c) => {
+                special_vars.insert(c, value);
+            },
+            Identifier::Named(_) => {
+                named_vars.insert(name, value);
+            },
+        }
+    }
+
+    let builder = if positional_vars.is_empty() {
+        builder
+    } else {
+        builder.without_positional_vars()
+    };
+
+    builder
+        .with_positional_vars(positional_vars)
+        .with_special_vars(special_vars)
+        .with_named_vars(named_vars)
+}
+
+/// The identifier of the variable a param needs to be set, if any.
+fn required_identifier<'n, 'a>(param: &'n Param<'a>) -> Option<&'n Identifier<'a>> {
+    let identifier = match param {
+        Param::Simple { identifier, .. } | Param::WithError { identifier, .. } => Some(identifier),
+        #[cfg(feature = "length")]
+        Param::Length { identifier } => Some(identifier),
+        #[cfg(feature = "indirection")]
+        Param::Ref { identifier } => Some(identifier),
+        _ => None,
+    }?;
+
+    match identifier {
+        Identifier::Indexed(0) | Identifier::Namespaced { .. } | Identifier::Dynamic(_) => None,
+        _ => Some(identifier),
+    }
+}
+
+/// Tells whether a variable is empty, or [`None`] if it's unset.
+type IsEmpty<'f> = &'f dyn Fn(&Identifier) -> Option<bool>;
+
+/// Collects the variables which must be set to render the nodes, see
+/// [`Template::required_identifiers`].
+fn collect_required<'n, 'a>(
+    nodes: &'n [Node<'a>],
+    is_empty: Option<IsEmpty>,
+    required: &mut Vec<(&'n Identifier<'a>, Position)>,
+) {
+    for node in nodes {
+        let Node::Param(param, position) = node else {
+            continue;
+        };
+
+        if let Some(Identifier::Dynamic(parts)) = param.identifier() {
+            collect_required(parts, is_empty, required);
+        }
+
+        if let Some(identifier) = required_identifier(param) {
+            required.push((identifier, *position));
+        }
+
+        let Some(is_empty_var) = is_empty else {
+            continue;
+        };
+        let is_set = |identifier, treat_empty_as_unset: bool| {
+            is_empty_var(identifier).is_some_and(|is_empty| !(is_empty && treat_empty_as_unset))
+        };
+
+        match param {
+            Param::WithDefault {
+                identifier,
+                default,
+                treat_empty_as_unset,
+            } if !is_set(identifier, *treat_empty_as_unset) => {
+                collect_required(slice::from_ref(default), is_empty, required);
+            },
+            Param::WithAlt {
+                identifier,
+                alt,
+                treat_empty_as_unset,
+            } if is_set(identifier, *treat_empty_as_unset) => {
+                collect_required(slice::from_ref(alt), is_empty, required);
+            },
+            Param::Conditional {
+                identifier,
+                then,
+                otherwise,
+            } => {
+                let nodes = if is_set(identifier, true) {
+                    then
+                } else {
+                    otherwise
+                };
+                collect_required(nodes, is_empty, required);
+            },
+            _ => {},
+        }
+    }
+}
+
+fn collect_dynamic_identifiers<'n, 'a>(
+    nodes: &'n [Node<'a>],
+    identifiers: &mut Vec<&'n Identifier<'a>>,
+) {
+    for node in nodes {
+        let Node::Param(param, _) = node else {
+            continue;
+        };
+
+        if let Some(identifier @ Identifier::Dynamic(parts)) = param.identifier() {
+            identifiers.push(identifier);
+            collect_dynamic_identifiers(parts, identifiers);
+        }
+
+        match param {
+            Param::WithDefault { default: node, .. } | Param::WithAlt { alt: node, .. } => {
+                collect_dynamic_identifiers(slice::from_ref(node.as_ref()), identifiers);
+            },
+            Param::WithError { error, .. } => collect_dynamic_identifiers(error, identifiers),
+            Param::Conditional {
+                then, otherwise, ..
+            } => {
+                collect_dynamic_identifiers(then, identifiers);
+                collect_dynamic_identifiers(otherwise, identifiers);
+            },
+            Param::Simple { .. } => {},
+            #[cfg(feature = "length")]
+            Param::Length { .. } | Param::Arity => {},
+            #[cfg(feature = "indirection")]
+            Param::Ref { .. } => {},
+        }
+    }
+}
+
+// The syntax is only needed to recover the identifiers of the variables of expressions
+#[cfg_attr(not(feature = "expr"), allow(clippy::only_used_in_recursion))]
+fn collect_references<'n>(
+    node: &'n Node<'n>,
+    reached: &Reached,
+    syntax: &Syntax,
+    references: &mut Vec<(Cow<'n, Identifier<'n>>, Reference)>,
+) {
+    #[cfg(feature = "expr")]
+    if let Node::Expr(expression, position) = node {
+        for name in expression.variables() {
+            let identifier = Identifier::from_name(name, |c| syntax.is_special_var(c));
+            references.push((
+                Cow::Owned(identifier),
+                Reference {
+                    name: name.to_string(),
+                    position: *position,
+                    has_default: false,
+                    reached: reached.clone(),
+                    is_indirect: false,
+                },
+            ));
+        }
+    }
+
+    let Node::Param(param, position) = node else {
+        return;
+    };
+
+    let mut add = |identifier: &'n Identifier<'n>, has_default: bool, is_indirect: bool| {
+        // The variable a dynamic name refers to is only known once it's computed, unlike the
+        // variables its parts refer to
+        if let Identifier::Dynamic(parts) = identifier {
+            for part in parts {
+                collect_references(part, reached, syntax, references);
+            }
+
+            return;
+        }
+
+        references.push((
+            Cow::Borrowed(identifier),
+            Reference {
+                name: identifier.to_string(),
+                position: *position,
+                has_default,
+                reached: reached.clone(),
+                is_indirect,
+            },
+        ));
+    };
+
+    match param {
+        Param::Simple { identifier, .. } => add(identifier, false, false),
+        Param::WithError {
+            identifier, error, ..
+        } => {
+            add(identifier, false, false);
+            let reached = Reached::ViaError(identifier.to_string());
+
+            for node in error {
+                collect_references(node, &reached, syntax, references);
+            }
+        },
+        #[cfg(feature = "length")]
+        Param::Length { identifier } => add(identifier, false, false),
+        #[cfg(feature = "indirection")]
+        Param::Ref { identifier } => add(identifier, false, true),
+        Param::WithDefault {
+            identifier,
+            default: node,
+            ..
+        } => {
+            add(identifier, true, false);
+            let reached = Reached::ViaDefault(identifier.to_string());
+            collect_references(node, &reached, syntax, references);
+        },
+        Param::WithAlt {
+            identifier,
+            alt: node,
+            ..
+        } => {
+            add(identifier, false, false);
+            let reached = Reached::ViaAlternative(identifier.to_string());
+            collect_references(node, &reached, syntax, references);
+        },
+        Param::Conditional {
+            identifier,
+            then,
+            otherwise,
+        } => {
+            add(identifier, false, false);
+            let reached = Reached::ViaAlternative(identifier.to_string());
+
+            for node in then {
+                collect_references(node, &reached, syntax, references);
+            }
+
+            let reached = Reached::ViaDefault(identifier.to_string());
+
+            for node in otherwise {
+                collect_references(node, &reached, syntax, references);
+            }
+        },
+        #[cfg(feature = "length")]
+        Param::Arity => {},
+    }
+}
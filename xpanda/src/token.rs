@@ -1,13 +1,24 @@
+use crate::syntax::{COMMENT, RAW_BLOCK_OPEN};
+use std::borrow::Cow;
 use std::fmt::{self, Display, Formatter};
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Token<'a> {
-    /// Any text outside of a param
-    Text(String),
+    /// Any text, borrowed from the input unless unescaping required a copy. Outside of a param an
+    /// escaped `$$` is a token of its own, so such text is always borrowed.
+    Text(Cow<'a, str>),
+    /// The text of a raw block, as in `$[[raw]]text$[[/raw]]`
+    Raw(&'a str),
+    /// The opening marker of a raw block which is never closed
+    UnclosedRaw,
+    /// A comment line, including its line break
+    Comment,
     /// The name of a named variable or environment variable
     Identifier(&'a str),
     /// The index of a positional variable
-    Index(usize),
+    Index(&'a str),
+    /// The character of a special parameter, as in `$?`
+    Special(char),
     OpenBrace,
     CloseBrace,
     DollarSign,
@@ -20,14 +31,19 @@ pub enum Token<'a> {
     Comma,
     Caret,
     Tilde,
+    AtSign,
 }
 
 impl Display for Token<'_> {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
-            Self::Text(text) => write!(f, "\"{}\"", text),
-            Self::Identifier(name) => write!(f, "\"{}\"", name),
-            Self::Index(index) => write!(f, "{}", index),
+            Self::Text(text) => write!(f, "\"{text}\""),
+            Self::Raw(text) => write!(f, "\"{text}\""),
+            Self::UnclosedRaw => write!(f, "'{RAW_BLOCK_OPEN}'"),
+            Self::Comment => write!(f, "'{COMMENT}'"),
+            Self::Identifier(name) => write!(f, "\"{name}\""),
+            Self::Index(index) => write!(f, "{index}"),
+            Self::Special(c) => write!(f, "'{c}'"),
             Self::OpenBrace => write!(f, "'{{'"),
             Self::CloseBrace => write!(f, "'}}'"),
             Self::DollarSign => write!(f, "'$'"),
@@ -40,6 +56,7 @@ impl Display for Token<'_> {
             Self::Comma => write!(f, "','"),
             Self::Caret => write!(f, "'^'"),
             Self::Tilde => write!(f, "'~'"),
+            Self::AtSign => write!(f, "'@'"),
         }
     }
 }
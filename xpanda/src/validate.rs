@@ -0,0 +1,111 @@
+use crate::eval::Source;
+use crate::syntax::Syntax;
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt::{self, Display, Formatter};
+
+/// A named variable rejected by [`Builder::validate_vars`](crate::Builder::validate_vars),
+/// returned by [`Builder::try_build`](crate::Builder::try_build).
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct InvalidVar {
+    /// The name of the variable, converted lossily if it isn't valid UTF-8.
+    pub name: String,
+    /// Where the variable comes from, see [`Source`].
+    pub source: Option<Source>,
+    pub reason: InvalidVarReason,
+}
+
+/// Why a named variable is rejected by
+/// [`Builder::validate_vars`](crate::Builder::validate_vars).
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum InvalidVarReason {
+    /// The name is empty, so the variable can never be referenced.
+    EmptyName,
+    /// The name contains a character other than letters, digits and underscores, such as a
+    /// space, so the variable can never be referenced.
+    InvalidChar(char),
+    /// The name starts with a digit, so the variable can only be referenced with
+    /// [`Builder::lenient_identifiers`](crate::Builder::lenient_identifiers) set.
+    LeadingDigit,
+    /// The name consists of only digits, which always refer to a positional variable.
+    Numeric,
+    /// The name or value of an environment variable isn't valid UTF-8, and was converted
+    /// lossily.
+    NotUtf8,
+}
+
+impl Display for InvalidVar {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name = &self.name;
+
+        match self.reason {
+            InvalidVarReason::EmptyName => write!(f, "Variable with an empty name can't be used"),
+            InvalidVarReason::InvalidChar(c) => {
+                write!(
+                    f,
+                    "Variable '{name}' can't be used, its name contains {c:?}"
+                )
+            },
+            InvalidVarReason::LeadingDigit => write!(
+                f,
+                "Variable '{name}' can't be used, its name starts with a digit"
+            ),
+            InvalidVarReason::Numeric => write!(
+                f,
+                "Variable '{name}' can't be used, its name refers to a positional variable"
+            ),
+            InvalidVarReason::NotUtf8 => {
+                write!(f, "Environment variable '{name}' isn't valid UTF-8")
+            },
+        }
+    }
+}
+
+impl error::Error for InvalidVar {}
+
+/// Finds the named variables which can't be referenced using the syntax, or which were
+/// converted lossily from the environment, sorted by name.
+pub fn invalid_vars(
+    named_vars: &HashMap<String, String>,
+    named_sources: &HashMap<String, Source>,
+    not_utf8: &HashSet<String>,
+    syntax: &Syntax,
+) -> Vec<InvalidVar> {
+    let mut invalid = named_vars
+        .keys()
+        .filter_map(|name| {
+            let reason = if not_utf8.contains(name) {
+                InvalidVarReason::NotUtf8
+            } else {
+                invalid_name(name, syntax)?
+            };
+
+            Some(InvalidVar {
+                name: name.clone(),
+                source: named_sources.get(name).cloned(),
+                reason,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    invalid.sort_by(|a, b| a.name.cmp(&b.name));
+    invalid
+}
+
+fn invalid_name(name: &str, syntax: &Syntax) -> Option<InvalidVarReason> {
+    if let Some(c) = name.chars().find(|c| !(c.is_alphanumeric() || *c == '_')) {
+        return Some(InvalidVarReason::InvalidChar(c));
+    }
+
+    if name.is_empty() {
+        Some(InvalidVarReason::EmptyName)
+    } else if name.bytes().all(|byte| byte.is_ascii_digit()) {
+        Some(InvalidVarReason::Numeric)
+    } else if name.starts_with(char::is_numeric) && !syntax.lenient_identifiers {
+        Some(InvalidVarReason::LeadingDigit)
+    } else {
+        None
+    }
+}
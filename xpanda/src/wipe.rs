@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+#[cfg(feature = "zeroize")]
+use std::collections::HashSet;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+#[cfg(feature = "zeroize")]
+use zeroize::Zeroize;
+
+/// A buffer collecting the output of an expansion. With the `zeroize` feature enabled, the
+/// buffer is wiped when it's dropped, such as when expanding fails halfway, and so is every
+/// allocation it outgrows, since the output may contain the values of secret variables.
+#[derive(Debug, Default)]
+pub struct Buffer(String);
+
+impl Buffer {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self(String::with_capacity(capacity))
+    }
+
+    pub fn push_str(&mut self, text: &str) {
+        #[cfg(feature = "zeroize")]
+        if self.0.capacity() - self.0.len() < text.len() {
+            let capacity = (self.0.len() + text.len()).max(self.0.capacity() * 2);
+            let mut grown = String::with_capacity(capacity);
+            grown.push_str(&self.0);
+            self.0.zeroize();
+            self.0 = grown;
+        }
+
+        self.0.push_str(text);
+    }
+
+    /// Returns the output, which from then on is the responsibility of the caller.
+    pub fn into_string(mut self) -> String {
+        mem::take(&mut self.0)
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        wipe(mem::take(&mut self.0));
+    }
+}
+
+/// Wipes an intermediate value, such as a copy of the value of a variable, if the `zeroize`
+/// feature is enabled. Otherwise the value is simply dropped.
+pub fn wipe(mut value: String) {
+    #[cfg(feature = "zeroize")]
+    value.zeroize();
+}
+
+/// The values of named variables, shared between an instance, its clones and builders. With the
+/// `zeroize` feature enabled, the values of secret variables are wiped when the variables are
+/// dropped, which is once the last of those sharing them is dropped.
+#[derive(Debug, Clone, Default)]
+pub struct NamedVars {
+    vars: HashMap<String, String>,
+    #[cfg(feature = "zeroize")]
+    secret: HashSet<String>,
+}
+
+impl NamedVars {
+    /// Marks the variables of the given names as secret.
+    #[cfg(feature = "zeroize")]
+    pub fn add_secret(&mut self, names: impl IntoIterator<Item = String>) {
+        self.secret.extend(names);
+    }
+}
+
+impl Deref for NamedVars {
+    type Target = HashMap<String, String>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.vars
+    }
+}
+
+impl DerefMut for NamedVars {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.vars
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Drop for NamedVars {
+    fn drop(&mut self) {
+        for name in &self.secret {
+            if let Some(value) = self.vars.get_mut(name) {
+                value.zeroize();
+            }
+        }
+    }
+}
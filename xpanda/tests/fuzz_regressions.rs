@@ -0,0 +1,47 @@
+//! Replays the inputs in `xpanda-fuzz/regressions` through the same code as the fuzz targets, so
+//! that findings of past fuzzing, such as minimized crashes, stay fixed. Every file in the
+//! directory named after a target is an input of that target.
+
+#[path = "../../xpanda-fuzz/targets/harness.rs"]
+mod harness;
+
+use std::fs;
+use std::panic;
+use std::path::Path;
+
+fn replay(target: &str, run: fn(&[u8])) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../xpanda-fuzz/regressions")
+        .join(target);
+    let mut paths = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    assert!(!paths.is_empty(), "No inputs in {}", dir.display());
+
+    let panicked = paths
+        .iter()
+        .filter(|path| {
+            let data = fs::read(path).unwrap();
+            panic::catch_unwind(|| run(&data)).is_err()
+        })
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>();
+
+    assert!(
+        panicked.is_empty(),
+        "Inputs of {target} panicked: {panicked:?}"
+    );
+}
+
+#[test]
+fn fuzz_input_regressions() {
+    replay("fuzz_input", harness::fuzz_input);
+}
+
+#[test]
+fn fuzz_vars_regressions() {
+    replay("fuzz_vars", harness::fuzz_vars);
+}
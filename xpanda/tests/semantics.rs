@@ -0,0 +1,21 @@
+//! Runs the cases in `semantics`, which pin down how inputs are expanded, against the default
+//! configuration. Every file in the directory is a case, see `xpanda::Case` for their format.
+
+use std::path::Path;
+use xpanda::Corpus;
+
+#[test]
+fn semantics() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../semantics");
+    let corpus = Corpus::load(&dir).unwrap();
+
+    assert!(!corpus.cases.is_empty(), "No cases in {}", dir.display());
+
+    let mismatches = corpus
+        .run(|builder| builder)
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+
+    assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+}
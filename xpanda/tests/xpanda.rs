@@ -1,5 +1,11 @@
 use std::collections::HashMap;
-use xpanda::{Error, Xpanda};
+use std::sync::{Arc, Mutex};
+use xpanda::{
+    Access, AnsiCQuotes, Builder, CachedXpanda, Case, Context, Corpus, Document, EnvProvider,
+    Error, ErrorKind, Expected, FileProvider, GlobEngine, InvalidVarReason, LengthPolicy, Linter,
+    Pattern, PatternEngine, Phase, Position, Resolution, Rule, Schema, Severity, Source, Span,
+    Template, UnsetEvent, UnsetOutcome, VarSchema, WarningKind, Xpanda,
+};
 
 #[test]
 fn simple_index() {
@@ -39,9 +45,11 @@ fn simple_index_no_unset() {
     assert_eq!(
         xpanda.expand(input),
         Err(Error {
-            message: String::from("'1' is unset"),
+            kind: ErrorKind::Unset,
+            message: String::from("'1' is unset, 0 positional variable(s) are set"),
             line: 1,
-            col: 1
+            col: 1,
+            span: Some(Span::default()),
         })
     );
 }
@@ -93,13 +101,41 @@ fn simple_named_no_unset() {
     assert_eq!(
         xpanda.expand(input),
         Err(Error {
+            kind: ErrorKind::Unset,
             message: String::from("'VAR' is unset"),
             line: 1,
-            col: 1
+            col: 1,
+            span: Some(Span::default()),
         })
     );
 }
 
+#[test]
+fn simple_named_no_empty() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::new());
+    let xpanda = Xpanda::builder()
+        .no_empty(true)
+        .with_named_vars(named_vars)
+        .build();
+
+    assert_eq!(
+        xpanda.expand("$VAR"),
+        Err(Error {
+            kind: ErrorKind::Unset,
+            message: String::from("'VAR' is empty"),
+            line: 1,
+            col: 1,
+            span: Some(Span::default()),
+        })
+    );
+    assert_eq!(
+        xpanda.expand("${VAR:-default}"),
+        Ok(String::from("default"))
+    );
+    assert_eq!(xpanda.expand("${VAR-default}$OTHER"), Ok(String::new()));
+}
+
 #[test]
 fn braced_index() {
     let positional_vars = vec![String::from("woop")];
@@ -272,9 +308,11 @@ fn error_index() {
     assert_eq!(
         xpanda.expand(input),
         Err(Error {
+            kind: ErrorKind::Required,
             message: String::from("msg"),
             line: 1,
-            col: 1
+            col: 1,
+            span: Some(Span::default()),
         })
     );
 }
@@ -287,9 +325,11 @@ fn error_named() {
     assert_eq!(
         xpanda.expand(input),
         Err(Error {
+            kind: ErrorKind::Required,
             message: String::from("msg"),
             line: 1,
-            col: 1
+            col: 1,
+            span: Some(Span::default()),
         })
     );
 }
@@ -305,9 +345,11 @@ fn error_index_no_empty() {
     assert_eq!(
         xpanda.expand(input),
         Err(Error {
+            kind: ErrorKind::Required,
             message: String::from("msg"),
             line: 1,
-            col: 1
+            col: 1,
+            span: Some(Span::default()),
         })
     );
 }
@@ -322,13 +364,58 @@ fn error_named_no_empty() {
     assert_eq!(
         xpanda.expand(input),
         Err(Error {
+            kind: ErrorKind::Required,
             message: String::from("msg"),
             line: 1,
-            col: 1
+            col: 1,
+            span: Some(Span::default()),
         })
     );
 }
 
+#[test]
+fn error_interpolated_message() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("OTHER"), String::from("DB_URL"));
+    named_vars.insert(String::from("EMPTY"), String::new());
+    let xpanda = Xpanda::builder()
+        .with_positional_vars(vec![String::from("one")])
+        .with_named_vars(named_vars)
+        .build();
+    let message = |input| xpanda.expand(input).map_err(|error| error.message);
+
+    assert_eq!(
+        message("${VAR:?Missing $OTHER for ${1}: try ${HINT:-again}?}"),
+        Err(String::from("Missing DB_URL for one: try again?"))
+    );
+    assert_eq!(
+        message("${VAR?costs $$5, ${OTHER@base64}-$1}"),
+        Err(String::from("costs $5, REJfVVJM-one"))
+    );
+    assert_eq!(
+        message("${VAR?${EMPTY:?${OTHER} is unset} is unset}"),
+        Err(String::from("DB_URL is unset"))
+    );
+    assert_eq!(message("${EMPTY?never $UNSET?}"), Ok(String::new()));
+}
+
+#[test]
+fn error_interpolated_message_no_unset() {
+    let xpanda = Xpanda::builder().no_unset(true).build();
+
+    assert_eq!(
+        xpanda.expand("${VAR?see $DOCS}").unwrap_err().message,
+        "'DOCS' is unset"
+    );
+    assert_eq!(
+        xpanda
+            .expand("${VAR?see ${DOCS:-the docs}}")
+            .unwrap_err()
+            .message,
+        "see the docs"
+    );
+}
+
 #[test]
 fn error_no_message() {
     let xpanda = Xpanda::default();
@@ -337,9 +424,11 @@ fn error_no_message() {
     assert_eq!(
         xpanda.expand(input),
         Err(Error {
+            kind: ErrorKind::Required,
             message: String::from("'VAR' is unset"),
             line: 1,
-            col: 1
+            col: 1,
+            span: Some(Span::default()),
         })
     );
 }
@@ -354,14 +443,17 @@ fn error_no_message_no_empty() {
     assert_eq!(
         xpanda.expand(input),
         Err(Error {
+            kind: ErrorKind::Required,
             message: String::from("'VAR' is unset or empty"),
             line: 1,
-            col: 1
+            col: 1,
+            span: Some(Span::default()),
         })
     );
 }
 
 #[test]
+#[cfg(feature = "length")]
 fn len_index() {
     let positional_vars = vec![String::from("four")];
     let xpanda = Xpanda::builder()
@@ -373,6 +465,7 @@ fn len_index() {
 }
 
 #[test]
+#[cfg(feature = "length")]
 fn len_named() {
     let mut named_vars = HashMap::new();
     named_vars.insert(String::from("VAR"), String::from("four"));
@@ -383,6 +476,7 @@ fn len_named() {
 }
 
 #[test]
+#[cfg(feature = "length")]
 fn len_missing() {
     let xpanda = Xpanda::default();
     let input = "${#VAR}";
@@ -391,6 +485,7 @@ fn len_missing() {
 }
 
 #[test]
+#[cfg(feature = "length")]
 fn len_no_unset() {
     let xpanda = Xpanda::builder().no_unset(true).build();
     let input = "${#VAR}";
@@ -398,13 +493,35 @@ fn len_no_unset() {
     assert_eq!(
         xpanda.expand(input),
         Err(Error {
+            kind: ErrorKind::Unset,
             message: String::from("'VAR' is unset"),
             line: 1,
-            col: 1
+            col: 1,
+            span: Some(Span::default()),
         })
     );
 }
 
+#[test]
+#[cfg(feature = "length")]
+fn len_mode() {
+    use xpanda::LengthMode;
+
+    // "e" followed by a combining acute accent, and a family emoji joined by zero width joiners
+    let value = "e\u{301}\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+    let expand = |length_mode| {
+        Xpanda::builder()
+            .with_positional_vars(vec![String::from(value)])
+            .length_mode(length_mode)
+            .build()
+            .expand("${#1}")
+    };
+
+    assert_eq!(expand(LengthMode::Bytes), Ok(String::from("21")));
+    assert_eq!(expand(LengthMode::Chars), Ok(String::from("7")));
+    assert_eq!(expand(LengthMode::Graphemes), Ok(String::from("2")));
+}
+
 #[test]
 fn missing_close_brace() {
     let mut named_vars = HashMap::new();
@@ -415,9 +532,114 @@ fn missing_close_brace() {
     assert_eq!(
         xpanda.expand(input),
         Err(Error {
-            message: String::from("Invalid param, unexpected EOF"),
+            kind: ErrorKind::Syntax,
+            message: String::from("Expected a matching '}' for the '${' opened"),
+            line: 1,
+            col: 1,
+            span: Some(Span::new(Position::new(0, 1, 1), Position::new(2, 1, 3))),
+        })
+    );
+}
+
+#[test]
+fn missing_close_brace_nested() {
+    let xpanda = Xpanda::default();
+
+    assert_eq!(
+        xpanda.expand("pre ${A:-${B}"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from("Expected a matching '}' for the '${' opened"),
+            line: 1,
+            col: 5,
+            span: Some(Span::new(Position::new(4, 1, 5), Position::new(6, 1, 7))),
+        })
+    );
+    assert_eq!(
+        xpanda.expand("${A}\n${B-${C"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from("Expected a matching '}' for the '${' opened"),
+            line: 2,
+            col: 5,
+            span: Some(Span::new(Position::new(9, 2, 5), Position::new(11, 2, 7))),
+        })
+    );
+}
+
+#[test]
+#[cfg(not(feature = "case-modifiers"))]
+fn case_modifiers_disabled() {
+    let xpanda = Xpanda::default();
+
+    assert_eq!(
+        xpanda.expand("${VAR^^}"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from(
+                "Case modifiers are not supported, xpanda was built without the 'case-modifiers' \
+                 feature"
+            ),
+            line: 1,
+            col: 7,
+            span: Some(Span::new(Position::new(5, 1, 6), Position::new(6, 1, 7))),
+        })
+    );
+}
+
+#[test]
+#[cfg(not(feature = "indirection"))]
+fn indirection_disabled() {
+    let xpanda = Xpanda::default();
+    let error = xpanda.expand("${!VAR}").unwrap_err();
+
+    assert_eq!(error.kind, ErrorKind::Syntax);
+    assert_eq!(
+        error.message,
+        "Indirect params are not supported, xpanda was built without the 'indirection' feature"
+    );
+}
+
+#[test]
+#[cfg(not(feature = "length"))]
+fn length_disabled() {
+    let xpanda = Xpanda::default();
+
+    for input in ["${#VAR}", "${#}"] {
+        let error = xpanda.expand(input).unwrap_err();
+
+        assert_eq!(error.kind, ErrorKind::Syntax);
+        assert_eq!(
+            error.message,
+            "Length params are not supported, xpanda was built without the 'length' feature"
+        );
+        assert_eq!(error.span.unwrap().start.index, 2);
+    }
+}
+
+#[test]
+#[cfg(feature = "case-modifiers")]
+fn unexpected_close_brace() {
+    let xpanda = Xpanda::default();
+
+    assert_eq!(
+        xpanda.expand("${}"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from("Empty param"),
+            line: 1,
+            col: 4,
+            span: Some(Span::new(Position::new(2, 1, 3), Position::new(3, 1, 4))),
+        })
+    );
+    assert_eq!(
+        xpanda.expand("${VAR^:x}"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from("Expected '}', found ':'"),
             line: 1,
-            col: 6
+            col: 8,
+            span: Some(Span::new(Position::new(6, 1, 7), Position::new(7, 1, 8))),
         })
     );
 }
@@ -432,9 +654,11 @@ fn unexpected_token() {
     assert_eq!(
         xpanda.expand(input),
         Err(Error {
+            kind: ErrorKind::Syntax,
             message: String::from("Unexpected token ':'"),
             line: 1,
-            col: 7
+            col: 7,
+            span: Some(Span::new(Position::new(5, 1, 6), Position::new(6, 1, 7))),
         })
     );
 }
@@ -457,6 +681,7 @@ fn multiline() {
 }
 
 #[test]
+#[cfg(feature = "case-modifiers")]
 fn uppercase_first() {
     let mut named_vars = HashMap::new();
     named_vars.insert(String::from("VAR"), String::from("woop"));
@@ -467,6 +692,7 @@ fn uppercase_first() {
 }
 
 #[test]
+#[cfg(feature = "case-modifiers")]
 fn uppercase_first_empty() {
     let mut named_vars = HashMap::new();
     named_vars.insert(String::from("VAR"), String::from(""));
@@ -477,6 +703,7 @@ fn uppercase_first_empty() {
 }
 
 #[test]
+#[cfg(feature = "case-modifiers")]
 fn uppercase_all() {
     let mut named_vars = HashMap::new();
     named_vars.insert(String::from("VAR"), String::from("woop"));
@@ -487,6 +714,7 @@ fn uppercase_all() {
 }
 
 #[test]
+#[cfg(feature = "case-modifiers")]
 fn lowercase_first() {
     let mut named_vars = HashMap::new();
     named_vars.insert(String::from("VAR"), String::from("WOOP"));
@@ -497,6 +725,7 @@ fn lowercase_first() {
 }
 
 #[test]
+#[cfg(feature = "case-modifiers")]
 fn lowercase_first_empty() {
     let mut named_vars = HashMap::new();
     named_vars.insert(String::from("VAR"), String::from(""));
@@ -507,6 +736,7 @@ fn lowercase_first_empty() {
 }
 
 #[test]
+#[cfg(feature = "case-modifiers")]
 fn lowercase_all() {
     let mut named_vars = HashMap::new();
     named_vars.insert(String::from("VAR"), String::from("WOOP"));
@@ -517,6 +747,7 @@ fn lowercase_all() {
 }
 
 #[test]
+#[cfg(feature = "case-modifiers")]
 fn reverse_case_first() {
     let mut named_vars = HashMap::new();
     named_vars.insert(String::from("VAR"), String::from("wOoP"));
@@ -527,6 +758,7 @@ fn reverse_case_first() {
 }
 
 #[test]
+#[cfg(feature = "case-modifiers")]
 fn reverse_case_first_empty() {
     let mut named_vars = HashMap::new();
     named_vars.insert(String::from("VAR"), String::from(""));
@@ -537,6 +769,7 @@ fn reverse_case_first_empty() {
 }
 
 #[test]
+#[cfg(feature = "case-modifiers")]
 fn reverse_case_all() {
     let mut named_vars = HashMap::new();
     named_vars.insert(String::from("VAR"), String::from("wOoP"));
@@ -547,49 +780,4857 @@ fn reverse_case_all() {
 }
 
 #[test]
-fn syntax_error() {
+fn urlencode() {
     let mut named_vars = HashMap::new();
-    named_vars.insert(String::from("VAR"), String::from("wOoP"));
+    named_vars.insert(String::from("VAR"), String::from("p@ss word/å"));
     let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+    let input = "${VAR@urlencode}";
 
     assert_eq!(
-        xpanda.expand("${VAR"),
+        xpanda.expand(input),
+        Ok(String::from("p%40ss%20word%2F%C3%A5"))
+    );
+}
+
+#[test]
+fn urldecode() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::from("p%40ss%20word%2f%C3%A5"));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+    let input = "${VAR@urldecode}";
+
+    assert_eq!(xpanda.expand(input), Ok(String::from("p@ss word/å")));
+}
+
+#[test]
+fn urldecode_invalid() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::from("100%"));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+    let input = "${VAR@urldecode}";
+
+    assert_eq!(
+        xpanda.expand(input),
         Err(Error {
-            message: String::from("Invalid param, unexpected EOF"),
+            kind: ErrorKind::InvalidValue,
+            message: String::from("'VAR' is not valid percent-encoded UTF-8"),
             line: 1,
-            col: 6,
+            col: 1,
+            span: Some(Span::default()),
+        })
+    );
+}
+
+#[test]
+fn base64() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::from("woop"));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+    let input = "${VAR@base64}";
+
+    assert_eq!(xpanda.expand(input), Ok(String::from("d29vcA==")));
+}
+
+#[test]
+fn base64d() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::from("d29vcA=="));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+    let input = "${VAR@base64d}";
+
+    assert_eq!(xpanda.expand(input), Ok(String::from("woop")));
+}
+
+#[test]
+fn base64d_invalid() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::from("woop!"));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+    let input = "${VAR@base64d}";
+
+    assert_eq!(
+        xpanda.expand(input),
+        Err(Error {
+            kind: ErrorKind::InvalidValue,
+            message: String::from("'VAR' is not valid base64-encoded UTF-8"),
+            line: 1,
+            col: 1,
+            span: Some(Span::default()),
         })
     );
+}
+
+#[test]
+fn shellquote() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::from("it's $HOME"));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+    let input = "${VAR@shellquote}";
+
+    assert_eq!(xpanda.expand(input), Ok(String::from("'it'\\''s $HOME'")));
+}
+
+#[test]
+fn pad() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::from("42"));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+
+    assert_eq!(xpanda.expand("${VAR@pad:5}"), Ok(String::from("   42")));
+    assert_eq!(xpanda.expand("${VAR@pad:05}"), Ok(String::from("00042")));
+    assert_eq!(xpanda.expand("${VAR@pad:1}"), Ok(String::from("42")));
+}
+
+#[test]
+fn pad_zero_negative() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::from("-4.2"));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+    let input = "${VAR@pad:06}";
+
+    assert_eq!(xpanda.expand(input), Ok(String::from("-004.2")));
+}
+
+#[test]
+fn fixed() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::from("0.125"));
+    named_vars.insert(String::from("INT"), String::from("8080"));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+
+    assert_eq!(xpanda.expand("${VAR@fixed:1}"), Ok(String::from("0.1")));
+    assert_eq!(xpanda.expand("${INT@fixed:2}"), Ok(String::from("8080.00")));
+    assert_eq!(xpanda.expand("${VAR@fixed:0}"), Ok(String::from("0")));
+}
+
+#[test]
+fn numeric_modifier_empty() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::new());
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+
+    assert_eq!(xpanda.expand("${VAR@pad:3}"), Ok(String::new()));
+    assert_eq!(xpanda.expand("${VAR@fixed:3}"), Ok(String::new()));
+}
+
+#[test]
+fn numeric_modifier_not_a_number() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::from("woop"));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+    let error = Err(Error {
+        kind: ErrorKind::InvalidValue,
+        message: String::from("'VAR' is not a number"),
+        line: 1,
+        col: 1,
+        span: Some(Span::default()),
+    });
+
+    assert_eq!(xpanda.expand("${VAR@pad:3}"), error);
+    assert_eq!(xpanda.expand("${VAR@fixed:3}"), error);
+}
+
+#[test]
+fn numeric_modifier_invalid_argument() {
+    let xpanda = Xpanda::default();
+
     assert_eq!(
-        xpanda.expand("${VAR-"),
+        xpanda.expand("${VAR@pad:x}"),
         Err(Error {
-            message: String::from("Unexpected EOF"),
+            kind: ErrorKind::Syntax,
+            message: String::from("Invalid argument 'x' for modifier 'pad', expected a number"),
             line: 1,
-            col: 7,
+            col: 12,
+            span: Some(Span::new(
+                Position::new(10, 1, 11),
+                Position::new(11, 1, 12)
+            )),
         })
     );
     assert_eq!(
-        xpanda.expand("${VAR "),
+        xpanda.expand("${VAR@fixed}"),
         Err(Error {
-            message: String::from("Invalid param, unexpected token \" \""),
+            kind: ErrorKind::Syntax,
+            message: String::from("Modifier 'fixed' expects an argument"),
             line: 1,
-            col: 6,
+            col: 12,
+            span: Some(Span::new(Position::new(6, 1, 7), Position::new(11, 1, 12))),
         })
     );
+}
+
+#[test]
+fn numeric_modifier_argument_too_large() {
+    let xpanda = Xpanda::default();
+
+    assert_eq!(
+        xpanda
+            .expand("${VAR@pad:65536}")
+            .map_err(|error| error.message),
+        Err(String::from(
+            "Argument '65536' for modifier 'pad' is too large, expected at most 65535"
+        ))
+    );
     assert_eq!(
-        xpanda.expand("${#"),
+        xpanda.expand("${VAR@fixed:99999999999}").unwrap_err().kind,
+        ErrorKind::Syntax
+    );
+    assert_eq!(xpanda.expand("${VAR@pad:65535}"), Ok(String::new()));
+}
+
+#[test]
+fn truncate() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::from("åäö xyz"));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+
+    assert_eq!(xpanda.expand("${VAR@trunc:3}"), Ok(String::from("åäö")));
+    assert_eq!(
+        xpanda.expand("${VAR@trunc:5:...}"),
+        Ok(String::from("åä..."))
+    );
+    assert_eq!(
+        xpanda.expand("${VAR@trunc:7:...}"),
+        Ok(String::from("åäö xyz"))
+    );
+    assert_eq!(xpanda.expand("${VAR@trunc:2:...}"), Ok(String::from("..")));
+    assert_eq!(xpanda.expand("${VAR@trunc:4:$}}"), Ok(String::from("åäö}")));
+    assert_eq!(xpanda.expand("${VAR@trunc:0}"), Ok(String::new()));
+    assert_eq!(
+        xpanda
+            .expand("${VAR@trunc:x}")
+            .map_err(|error| error.message),
+        Err(String::from(
+            "Invalid argument 'x' for modifier 'trunc', expected a number"
+        ))
+    );
+}
+
+#[test]
+fn truncate_display() {
+    let xpanda = Xpanda::default();
+
+    for input in [
+        "${VAR@trunc:3}",
+        "${VAR@trunc:3:...}",
+        "${VAR@trunc:3:$}$$}",
+    ] {
+        assert_eq!(xpanda.parse(input).unwrap().to_string(), input);
+    }
+}
+
+#[test]
+fn max_value_length() {
+    let named_vars = HashMap::from([
+        (String::from("SHORT"), String::from("abc")),
+        (String::from("LONG"), String::from("abcdef")),
+    ]);
+    let build = |policy| {
+        Xpanda::builder()
+            .with_named_vars(named_vars.clone())
+            .max_value_length(3, policy)
+            .build()
+    };
+    let error = build(LengthPolicy::Error);
+    let truncate = build(LengthPolicy::Truncate);
+
+    assert_eq!(error.expand("$SHORT"), Ok(String::from("abc")));
+    assert_eq!(
+        error.expand("$LONG"),
         Err(Error {
-            message: String::from("Expected identifier or close brace, found EOF"),
+            kind: ErrorKind::InvalidValue,
+            message: String::from("'LONG' is longer than 3 characters"),
             line: 1,
-            col: 4,
+            col: 1,
+            span: Some(Span::default()),
         })
     );
+    assert_eq!(error.expand("${UNSET-abcdef}"), Ok(String::from("abcdef")));
     assert_eq!(
-        xpanda.expand("${VAR-:def}"),
+        truncate.expand("$LONG ${LONG@trunc:5:.}"),
+        Ok(String::from("abc abc"))
+    );
+}
+
+#[test]
+fn nesting_too_deep() {
+    let xpanda = Xpanda::default();
+    let nested = |depth: usize| "${A:-".repeat(depth) + "x" + &"}".repeat(depth);
+
+    assert_eq!(xpanda.expand(&nested(64)), Ok(String::from("x")));
+    assert_eq!(
+        xpanda.expand(&nested(65)),
         Err(Error {
-            message: String::from("Unexpected token ':'"),
+            kind: ErrorKind::Syntax,
+            message: String::from("Params can't be nested more than 64 levels deep"),
             line: 1,
-            col: 7,
+            col: 321,
+            span: Some(Span::new(
+                Position::new(320, 1, 321),
+                Position::new(322, 1, 323)
+            )),
+        })
+    );
+    assert_eq!(
+        xpanda.expand(&"${A:-".repeat(100_000)).unwrap_err().kind,
+        ErrorKind::Syntax
+    );
+}
+
+#[test]
+fn nesting_deep_small_stack() {
+    // Deeply nested input must neither overflow the stack nor take long, whichever way it's
+    // used, even on a quarter of the default stack size of spawned threads
+    let deep = [
+        "${A:-".repeat(64) + "x" + &"}".repeat(64),
+        "${1:+".repeat(64) + "x" + &"}".repeat(64),
+        "${A-${1:+".repeat(32) + "$$" + &"}".repeat(64),
+        "${A:-".repeat(1_000_000),
+        "${A:-$".repeat(100_000),
+        "${".repeat(1_000_000),
+        "}".repeat(1_000_000),
+    ];
+
+    let thread = std::thread::Builder::new()
+        .stack_size(512 * 1024)
+        .spawn(move || {
+            let xpanda = Xpanda::builder()
+                .with_positional_vars(vec![String::from("one")])
+                .build();
+
+            for input in &deep {
+                let expanded = xpanda.expand(input);
+
+                if let Ok(template) = Template::parse(input) {
+                    assert_eq!(Template::parse(&template.to_string()), Ok(template.clone()));
+                    let _ = template.references();
+                    assert_eq!(xpanda.render_compiled(&template.compile()), expanded);
+                } else {
+                    assert_eq!(expanded.unwrap_err().kind, ErrorKind::Syntax);
+                    let _ = Template::parse_partial(input)
+                        .unwrap_err()
+                        .partial
+                        .to_string();
+                }
+            }
         })
+        .unwrap();
+
+    thread.join().unwrap();
+}
+
+#[test]
+fn nesting_shared() {
+    let xpanda = Xpanda::builder().conditionals(true).build();
+    let nested = |blocks: usize, params: usize| {
+        "${if A}".repeat(blocks)
+            + &"${A:-".repeat(params)
+            + "x"
+            + &"}".repeat(params)
+            + &"${end}".repeat(blocks)
+    };
+
+    assert_eq!(xpanda.expand(&nested(64, 0)), Ok(String::new()));
+    assert_eq!(xpanda.expand(&nested(32, 32)), Ok(String::new()));
+    assert_eq!(
+        xpanda
+            .expand(&nested(32, 33))
+            .map_err(|error| error.message),
+        Err(String::from(
+            "Params can't be nested more than 64 levels deep"
+        ))
+    );
+    assert_eq!(
+        xpanda.expand(&nested(65, 0)).map_err(|error| error.message),
+        Err(String::from(
+            "Conditional blocks can't be nested more than 64 levels deep"
+        ))
+    );
+
+    #[cfg(feature = "serialize")]
+    for (blocks, params) in [(0, 64), (32, 32)] {
+        let input = nested(blocks, params);
+        let template = xpanda.parse(&input).unwrap();
+        let bytes = template.to_bytes();
+
+        assert_eq!(Template::from_bytes(&bytes), Ok(template));
+    }
+}
+
+#[test]
+#[cfg(feature = "expr")]
+fn nesting_shared_expressions() {
+    let xpanda = Xpanda::builder().expressions(true).build();
+    let nested = |params: usize, parens: usize| {
+        "${A:-".repeat(params)
+            + "="
+            + &"(".repeat(parens)
+            + "1"
+            + &")".repeat(parens)
+            + &"}".repeat(params)
+    };
+
+    assert_eq!(xpanda.expand(&nested(1, 62)), Ok(String::from("1")));
+    assert_eq!(xpanda.expand(&nested(60, 3)), Ok(String::from("1")));
+    assert_eq!(
+        xpanda.expand(&nested(60, 4)).map_err(|error| error.message),
+        Err(String::from(
+            "Expressions can't be nested more than 64 levels deep"
+        ))
+    );
+}
+
+#[test]
+fn nesting_deep_small_stack_syntax() {
+    // Like above, for nesting shared between params, conditional blocks and expressions, and
+    // for long chains of operators, which don't count as nesting
+    #[allow(unused_mut)]
+    let mut deep = vec![
+        "${if A}".repeat(64) + &"${end}".repeat(64),
+        "${if A}".repeat(32) + &"${A:-".repeat(32) + "x" + &"}".repeat(32) + &"${end}".repeat(32),
+    ];
+
+    #[cfg(feature = "expr")]
+    deep.extend([
+        "${A:-".repeat(63) + "=1" + &"}".repeat(63),
+        String::from("${A:-=") + &"(".repeat(62) + "1" + &")".repeat(62) + "}",
+        String::from("${A:-=") + &"max(".repeat(62) + "1" + &")".repeat(62) + "}",
+        String::from("${A:-=") + &"-".repeat(62) + "1}",
+        String::from("${A:-=1") + &" + 1".repeat(500) + "}",
+        String::from("${A:-=1") + &" && 1 || 0".repeat(200) + "}",
+    ]);
+
+    let thread = std::thread::Builder::new()
+        .stack_size(512 * 1024)
+        .spawn(move || {
+            let builder = Xpanda::builder().conditionals(true);
+            #[cfg(feature = "expr")]
+            let builder = builder.expressions(true);
+            let xpanda = builder.build();
+
+            for input in &deep {
+                let template = xpanda.parse(input).unwrap();
+                let expanded = xpanda.render(&template);
+
+                assert!(expanded.is_ok());
+                assert_eq!(xpanda.expand(&template.to_string()), expanded);
+                assert_eq!(
+                    xpanda.render_compiled(&template.clone().compile()),
+                    expanded
+                );
+            }
+        })
+        .unwrap();
+
+    thread.join().unwrap();
+}
+
+#[test]
+fn modifier_unset() {
+    let xpanda = Xpanda::default();
+    let input = "${VAR@base64}";
+
+    assert_eq!(xpanda.expand(input), Ok(String::new()));
+}
+
+#[test]
+fn unknown_modifier() {
+    let xpanda = Xpanda::default();
+
+    assert_eq!(
+        xpanda.expand("${VAR@woop}"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from("Unknown modifier 'woop'"),
+            line: 1,
+            col: 11,
+            span: Some(Span::new(Position::new(6, 1, 7), Position::new(10, 1, 11))),
+        })
+    );
+    assert_eq!(
+        xpanda.expand("${VAR@}"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from("Expected modifier name, found '}'"),
+            line: 1,
+            col: 8,
+            span: Some(Span::new(Position::new(6, 1, 7), Position::new(7, 1, 8))),
+        })
+    );
+}
+
+#[test]
+#[cfg(feature = "length")]
+fn syntax_error() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::from("wOoP"));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+
+    assert_eq!(
+        xpanda.expand("${VAR"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from("Expected a matching '}' for the '${' opened"),
+            line: 1,
+            col: 1,
+            span: Some(Span::new(Position::new(0, 1, 1), Position::new(2, 1, 3))),
+        })
+    );
+    assert_eq!(
+        xpanda.expand("${VAR-"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from("Expected a matching '}' for the '${' opened"),
+            line: 1,
+            col: 1,
+            span: Some(Span::new(Position::new(0, 1, 1), Position::new(2, 1, 3))),
+        })
+    );
+    assert_eq!(
+        xpanda.expand("${VAR "),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from("Invalid param, unexpected token \" \""),
+            line: 1,
+            col: 6,
+            span: Some(Span::new(Position::new(2, 1, 3), Position::new(5, 1, 6))),
+        })
+    );
+    assert_eq!(
+        xpanda.expand("${#"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from("Expected a matching '}' for the '${' opened"),
+            line: 1,
+            col: 1,
+            span: Some(Span::new(Position::new(0, 1, 1), Position::new(2, 1, 3))),
+        })
+    );
+    assert_eq!(
+        xpanda.expand("${VAR-:def}"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from("Unexpected token ':'"),
+            line: 1,
+            col: 7,
+            span: Some(Span::new(Position::new(5, 1, 6), Position::new(6, 1, 7))),
+        })
+    );
+}
+
+#[test]
+#[cfg(all(feature = "indirection", feature = "length"))]
+fn template_variables() {
+    let template = Template::parse("$A ${B-$C} ${#D} ${!1} ${#} $A").unwrap();
+
+    assert_eq!(template.variables(), vec!["A", "B", "C", "D", "1"]);
+}
+
+#[test]
+#[cfg(feature = "indirection")]
+fn template_references_reached() {
+    use xpanda::Reached;
+
+    let template = Template::parse("${A-${!B}} ${C+$A} $D ${E?$F}").unwrap();
+    let references = template
+        .references()
+        .into_iter()
+        .map(|reference| (reference.name, reference.reached, reference.is_indirect))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        references,
+        vec![
+            (String::from("A"), Reached::Directly, false),
+            (
+                String::from("B"),
+                Reached::ViaDefault(String::from("A")),
+                true
+            ),
+            (String::from("C"), Reached::Directly, false),
+            (
+                String::from("A"),
+                Reached::ViaAlternative(String::from("C")),
+                false
+            ),
+            (String::from("D"), Reached::Directly, false),
+            (String::from("E"), Reached::Directly, false),
+            (
+                String::from("F"),
+                Reached::ViaError(String::from("E")),
+                false
+            ),
+        ]
+    );
+}
+
+#[test]
+#[cfg(all(
+    feature = "case-modifiers",
+    feature = "indirection",
+    feature = "length"
+))]
+fn template_format() {
+    let input = "$A ${B:-$$text} ${C+$D} ${E?msg} ${#F} ${!G} ${#} ${H^^} $$I";
+    let template = Template::parse(input).unwrap();
+    let formatted = template.to_string();
+
+    assert_eq!(
+        formatted,
+        "${A} ${B:-$$text} ${C+${D}} ${E?msg} ${#F} ${!G} ${#} ${H^^} $$I"
+    );
+    assert_eq!(Template::parse(&formatted).unwrap(), template);
+}
+
+#[test]
+fn template_render() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::from("woop"));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+    let template = Template::parse("pre $VAR post").unwrap();
+
+    assert_eq!(xpanda.render(&template), Ok(String::from("pre woop post")));
+}
+
+#[test]
+fn template_parse_partial() {
+    let input = "a $A ${B:-$C} ${D:-";
+    let error = Template::parse_partial(input).unwrap_err();
+
+    assert_eq!(error.partial.to_string(), "a ${A} ${B:-${C}} ");
+    assert_eq!(error.partial.variables(), vec!["A", "B", "C"]);
+    assert_eq!(
+        error.error,
+        Error {
+            kind: ErrorKind::Syntax,
+            message: String::from("Expected a matching '}' for the '${' opened"),
+            line: 1,
+            col: 15,
+            span: Some(Span::new(
+                Position::new(14, 1, 15),
+                Position::new(16, 1, 17)
+            )),
+        }
+    );
+    assert_eq!(Template::parse(input), Err(error.error));
+}
+
+#[test]
+fn template_parse_partial_success() {
+    let template = Template::parse_partial("a $A").unwrap();
+
+    assert_eq!(template, Template::parse("a $A").unwrap());
+}
+
+#[test]
+fn is_fully_expanded() {
+    let xpanda = Xpanda::default();
+
+    assert!(xpanda.is_fully_expanded(""));
+    assert!(xpanda.is_fully_expanded("plain text\nwith {braces} and 100% åäö"));
+    assert!(!xpanda.is_fully_expanded("a $A"));
+    assert!(!xpanda.is_fully_expanded("${A:-default}"));
+    assert!(!xpanda.is_fully_expanded("costs $$5"));
+    assert!(!xpanda.is_fully_expanded("${unclosed"));
+}
+
+#[test]
+fn is_fully_expanded_front_matter() {
+    let input = "#! xpanda: default A=1\ntext";
+
+    assert!(Xpanda::default().is_fully_expanded(input));
+    assert!(!Xpanda::builder()
+        .front_matter(true)
+        .build()
+        .is_fully_expanded(input));
+}
+
+#[test]
+fn escape_round_trip() {
+    let xpanda = Xpanda::builder()
+        .comments(true)
+        .raw_blocks(true)
+        .conditionals(true)
+        .ansi_c_quotes(AnsiCQuotes::Interpret)
+        .build();
+    let texts = [
+        "",
+        "no dollar signs",
+        "$",
+        "$$$ at the end$",
+        "$A ${B:-$C} ${#D} ${!E}",
+        "$# not a comment\n$[[raw]]$A$[[/raw]]\n${if A}${end}",
+        "$'\\t' and 'åäö'",
+    ];
+
+    for text in texts {
+        let escaped = xpanda::escape(text);
+        assert_eq!(xpanda.escape(text).as_ref(), Some(&escaped));
+        assert_eq!(xpanda.expand(&escaped), Ok(String::from(text)));
+        assert_eq!(xpanda::unescape(&escaped), text);
+        assert_eq!(xpanda.unescape(&escaped), text);
+    }
+}
+
+#[test]
+fn escape_opaque_regions() {
+    let xpanda = Xpanda::builder().opaque_regions("{{", "}}").build();
+    let text = "$A {{ $B }} $ {{ $$ }}{{ $C";
+    let escaped = xpanda.escape(text).unwrap();
+
+    assert_eq!(escaped, "$$A {{ $B }} $$ {{ $$ }}{{ $$C");
+    assert_eq!(xpanda.expand(&escaped), Ok(String::from(text)));
+    assert_eq!(xpanda.unescape(&escaped), text);
+}
+
+#[test]
+fn escape_unsupported() {
+    let xpanda = Xpanda::builder()
+        .with_special_vars(HashMap::from([('$', String::from("42"))]))
+        .build();
+
+    assert_eq!(
+        xpanda.escape("no dollar signs"),
+        Some(String::from("no dollar signs"))
+    );
+    assert_eq!(xpanda.escape("$5"), None);
+    assert_eq!(xpanda.unescape("pid $$"), "pid $$");
+
+    let xpanda = Xpanda::builder().front_matter(true).build();
+
+    assert_eq!(xpanda.escape("#! xpanda: default A=1"), None);
+    assert_eq!(
+        xpanda.escape("text\n#! xpanda: default A=1"),
+        Some(String::from("text\n#! xpanda: default A=1"))
+    );
+}
+
+#[test]
+fn parse_partial_front_matter_error() {
+    let xpanda = Xpanda::builder().front_matter(true).build();
+    let error = xpanda.parse_partial("#! xpanda: unknown\n$A").unwrap_err();
+
+    assert_eq!(error.partial.to_string(), "");
+    assert_eq!(error.error.kind, ErrorKind::Syntax);
+}
+
+#[test]
+#[cfg(feature = "length")]
+fn prefixed_positional_vars() {
+    let vars = vec![
+        (String::from("ARG_2"), String::from("second")),
+        (String::from("ARG_1"), String::from("first")),
+        (String::from("ARG_X"), String::from("ignored")),
+        (String::from("ARG_+3"), String::from("ignored")),
+        (String::from("ARG_01"), String::from("ignored")),
+        (String::from("ARG_03"), String::from("ignored")),
+        (String::from("ARG_0"), String::from("ignored")),
+        (String::from("OTHER_3"), String::from("ignored")),
+        (String::from("ARG_4"), String::from("ignored")),
+    ];
+    let xpanda = Xpanda::builder()
+        .with_prefixed_positional_vars("ARG_", vars)
+        .build();
+    let input = "$0 ${#}";
+
+    assert_eq!(xpanda.expand(input), Ok(String::from("first second 2")));
+}
+
+#[test]
+fn positional_iter() {
+    let read = Arc::new(Mutex::new(0));
+    let counter = Arc::clone(&read);
+    let vars = (1..=100).map(move |index| {
+        *counter.lock().unwrap() += 1;
+        format!("arg{index}")
+    });
+    let xpanda = Xpanda::builder()
+        .with_positional_vars(vec![String::from("first")])
+        .with_positional_iter(vars)
+        .with_positional_vars(vec![String::from("last")])
+        .build();
+
+    assert_eq!(xpanda.expand("$1 $3"), Ok(String::from("first arg2")));
+    assert_eq!(*read.lock().unwrap(), 2);
+    assert_eq!(
+        xpanda.expand("$2 ${4:-unset}"),
+        Ok(String::from("arg1 arg3"))
+    );
+    assert_eq!(*read.lock().unwrap(), 3);
+    assert_eq!(xpanda.expand("${102}"), Ok(String::from("last")));
+    assert_eq!(xpanda.expand("${103:-unset}"), Ok(String::from("unset")));
+    assert_eq!(*read.lock().unwrap(), 100);
+}
+
+#[test]
+fn positional_iter_endless() {
+    let xpanda = Xpanda::builder()
+        .no_unset(true)
+        .with_positional_iter((1..).map(|index: u64| index.to_string()))
+        .access_policy(|name| match name {
+            "2" => Access::Hide,
+            _ => Access::Allow,
+        })
+        .build();
+    let xpanda = CachedXpanda::new(xpanda);
+
+    assert_eq!(xpanda.expand("$1 $5"), Ok(String::from("1 5")));
+    assert_eq!(
+        xpanda.expand("$2").unwrap_err().to_string(),
+        "'2' is unset, 2 positional variable(s) are set at line 1, column 1"
+    );
+}
+
+#[test]
+#[cfg(feature = "length")]
+fn positional_iter_arity() {
+    let xpanda = Xpanda::builder()
+        .with_positional_iter((1..=1_000_000).map(|index| index.to_string()))
+        .build();
+
+    assert_eq!(xpanda.expand("${#} $7"), Ok(String::from("1000000 7")));
+
+    let xpanda = Xpanda::builder()
+        .with_positional_iter((1..=10).map(|index| index.to_string()).filter(|_| true))
+        .build();
+
+    assert_eq!(
+        xpanda.expand("${#} $0"),
+        Ok(String::from("10 1 2 3 4 5 6 7 8 9 10"))
+    );
+}
+
+#[test]
+#[cfg(all(
+    feature = "case-modifiers",
+    feature = "indirection",
+    feature = "length"
+))]
+fn value_transform() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::from(" woop "));
+    named_vars.insert(String::from("REF"), String::from("VAR"));
+    let xpanda = Xpanda::builder()
+        .with_named_vars(named_vars)
+        .with_positional_vars(vec![String::from(" one ")])
+        .value_transform(|_, value| value.trim().to_string())
+        .value_transform(|name, value| format!("{name}:{value}"))
+        .build();
+
+    assert_eq!(
+        xpanda.expand("$VAR ${1}"),
+        Ok(String::from("VAR:woop 1:one"))
+    );
+    assert_eq!(xpanda.expand("${!REF}"), Ok(String::from("VAR:woop")));
+    assert_eq!(
+        xpanda.expand("${#VAR} ${VAR^^}"),
+        Ok(String::from("8 VAR:WOOP"))
+    );
+    assert_eq!(
+        xpanda.expand("${MISSING:-default}"),
+        Ok(String::from("default"))
+    );
+}
+
+#[test]
+fn value_transform_before_empty_check() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("BLANK"), String::from("  "));
+    let xpanda = Xpanda::builder()
+        .with_named_vars(named_vars)
+        .value_transform(|_, value| value.trim().to_string())
+        .build();
+
+    assert_eq!(
+        xpanda.expand("${BLANK:-default}"),
+        Ok(String::from("default"))
+    );
+}
+
+#[test]
+#[cfg(feature = "length")]
+fn aliases() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("DB_URL"), String::from("old"));
+    named_vars.insert(String::from("DATABASE_URL"), String::from("new"));
+    let mut aliases = HashMap::new();
+    aliases.insert(String::from("DB_URL"), String::from("DATABASE_URL"));
+    let xpanda = Xpanda::builder()
+        .with_named_vars(named_vars)
+        .with_aliases(aliases)
+        .build();
+
+    assert_eq!(xpanda.expand("$DB_URL"), Ok(String::from("new")));
+    assert_eq!(xpanda.expand("${#DB_URL}"), Ok(String::from("3")));
+    assert_eq!(xpanda.expand("$DATABASE_URL"), Ok(String::from("new")));
+}
+
+#[test]
+fn aliases_unset() {
+    let mut aliases = HashMap::new();
+    aliases.insert(String::from("DB_URL"), String::from("DATABASE_URL"));
+    let xpanda = Xpanda::builder()
+        .no_unset(true)
+        .with_aliases(aliases)
+        .build();
+
+    assert_eq!(
+        xpanda.expand("$DB_URL"),
+        Err(Error {
+            kind: ErrorKind::Unset,
+            message: String::from("'DB_URL' is unset"),
+            line: 1,
+            col: 1,
+            span: Some(Span::default()),
+        })
+    );
+}
+
+#[test]
+fn namespaced() {
+    let xpanda = Xpanda::builder()
+        .register_namespace("upper", |key: &str| Ok(Some(key.to_uppercase())))
+        .register_namespace("none", |_: &str| Ok(None))
+        .build();
+
+    assert_eq!(xpanda.expand("${upper:wo/op}"), Ok(String::from("WO/OP")));
+    assert_eq!(xpanda.expand("${none:woop}"), Ok(String::new()));
+    assert_eq!(xpanda.expand("${VAR:-def}"), Ok(String::from("def")));
+    assert_eq!(
+        xpanda.expand("${none:woop:-def} ${upper:a:b:+alt} ${upper:a$:-b}"),
+        Ok(String::from("def alt A:-B"))
+    );
+    assert_eq!(
+        xpanda
+            .expand("${none:woop:?missing}")
+            .map_err(|error| error.message),
+        Err(String::from("missing"))
+    );
+    assert_eq!(
+        xpanda.parse("${none:a:-b}").unwrap().variables(),
+        vec!["none:a"]
+    );
+
+    let template = xpanda.parse("${upper:a$:-b$}$$$:+}${none:x:-d}").unwrap();
+    assert_eq!(template.to_string(), "${upper:a$:-b$}$$$:+}${none:x:-d}");
+    assert_eq!(
+        xpanda.expand(&template.to_string()),
+        Ok(String::from("A:-B}$:+d"))
+    );
+    assert_eq!(
+        xpanda
+            .expand("${VAR:x}")
+            .map_err(|error| (error.kind, error.message)),
+        Err((
+            ErrorKind::Syntax,
+            String::from("Invalid param, unexpected token \"x\"")
+        ))
+    );
+}
+
+#[test]
+fn namespaced_env() {
+    let xpanda = Xpanda::builder()
+        .register_namespace("env", EnvProvider)
+        .build();
+    let expected = std::env::var("PATH").unwrap_or_default();
+
+    assert_eq!(xpanda.expand("${env:PATH}"), Ok(expected));
+}
+
+#[test]
+fn namespaced_no_unset() {
+    let xpanda = Xpanda::builder()
+        .no_unset(true)
+        .register_namespace("none", |_: &str| Ok(None))
+        .build();
+
+    assert_eq!(
+        xpanda.expand("${none:woop}"),
+        Err(Error {
+            kind: ErrorKind::Unset,
+            message: String::from("'none:woop' is unset"),
+            line: 1,
+            col: 1,
+            span: Some(Span::default()),
+        })
+    );
+}
+
+#[test]
+fn namespaced_error() {
+    let xpanda = Xpanda::builder()
+        .register_namespace("fail", |_: &str| Err(String::from("unreachable")))
+        .build();
+
+    assert_eq!(
+        xpanda.expand("${fail:woop}"),
+        Err(Error {
+            kind: ErrorKind::Provider,
+            message: String::from("Failed to resolve 'fail:woop': unreachable"),
+            line: 1,
+            col: 1,
+            span: Some(Span::default()),
+        })
+    );
+    assert_eq!(
+        xpanda.expand("${unknown:woop}"),
+        Err(Error {
+            kind: ErrorKind::Provider,
+            message: String::from("Unknown namespace 'unknown'"),
+            line: 1,
+            col: 1,
+            span: Some(Span::default()),
+        })
+    );
+}
+
+#[test]
+fn context() {
+    let context = Context::new()
+        .file("in.tpl")
+        .timestamp("2024-01-02T03:04:05Z")
+        .first_line(10);
+    let xpanda = Xpanda::builder()
+        .with_context(context)
+        .register_namespace("xpanda", |_: &str| Ok(Some(String::from("shadowed"))))
+        .build();
+
+    assert_eq!(
+        xpanda.expand("${xpanda:file}\n${xpanda:line} ${xpanda:timestamp}"),
+        Ok(String::from("in.tpl\n11 2024-01-02T03:04:05Z"))
+    );
+    assert_eq!(
+        Xpanda::builder()
+            .with_context(Context::new())
+            .no_unset(true)
+            .build()
+            .expand("${xpanda:line}${xpanda:file}")
+            .map_err(|error| error.message),
+        Err(String::from("'xpanda:file' is unset"))
+    );
+}
+
+#[test]
+fn context_error() {
+    let xpanda = Xpanda::builder().with_context(Context::new()).build();
+
+    assert_eq!(
+        xpanda
+            .expand("${xpanda:woop}")
+            .map_err(|error| error.message),
+        Err(String::from(
+            "Failed to resolve 'xpanda:woop': Unknown key, expected 'line', 'file' or 'timestamp'"
+        ))
+    );
+    assert_eq!(
+        Xpanda::default()
+            .expand("${xpanda:line}")
+            .map_err(|error| error.message),
+        Err(String::from("Unknown namespace 'xpanda'"))
+    );
+}
+
+#[test]
+fn safe_mode_namespaced() {
+    let xpanda = Xpanda::builder()
+        .safe_mode(true)
+        .powershell_env(true)
+        .register_namespace("ns", |key: &str| Ok(Some(key.to_string())))
+        .build();
+
+    assert_eq!(
+        xpanda.expand("a\n ${ns:key}"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from("Namespaced variables are not allowed in safe mode"),
+            line: 2,
+            col: 6,
+            span: Some(Span::new(Position::new(5, 2, 4), Position::new(7, 2, 6))),
+        })
+    );
+    assert_eq!(
+        xpanda.expand("$env:HOME").map_err(|error| error.message),
+        Err(String::from(
+            "Namespaced variables are not allowed in safe mode"
+        ))
+    );
+    assert_eq!(
+        xpanda.expand("${A:-x} ${B@base64} ${A:+$B}"),
+        Ok(String::from("x  "))
+    );
+}
+
+#[test]
+#[cfg(feature = "indirection")]
+fn safe_mode_indirection() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("A"), String::from("SECRET"));
+    let xpanda = Xpanda::builder()
+        .safe_mode(true)
+        .with_named_vars(named_vars)
+        .build();
+
+    assert_eq!(
+        xpanda.expand("a ${!A}"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from("Indirect params are not allowed in safe mode"),
+            line: 1,
+            col: 6,
+            span: Some(Span::new(Position::new(4, 1, 5), Position::new(5, 1, 6))),
+        })
+    );
+}
+
+#[test]
+#[cfg(feature = "indirection")]
+fn safe_mode_render() {
+    let xpanda = Xpanda::builder().safe_mode(true).build();
+    let render = |input| {
+        xpanda
+            .render(&Template::parse(input).unwrap())
+            .map_err(|error| (error.kind, error.message))
+    };
+
+    assert_eq!(
+        render("${A-${!B}}"),
+        Err((
+            ErrorKind::Syntax,
+            String::from("Indirect params are not allowed in safe mode")
+        ))
+    );
+    assert_eq!(render("${A+${env:HOME}}"), Ok(String::new()));
+    assert_eq!(
+        render("${env:HOME}"),
+        Err((
+            ErrorKind::Syntax,
+            String::from("Namespaced variables are not allowed in safe mode")
+        ))
+    );
+}
+
+#[test]
+fn namespaced_template() {
+    let template = Template::parse("${env:HOME} ${VAR:-${file:./a b}}").unwrap();
+
+    assert_eq!(template.variables(), vec!["env:HOME", "VAR", "file:./a b"]);
+    assert_eq!(template.to_string(), "${env:HOME} ${VAR:-${file:./a b}}");
+}
+
+#[test]
+fn file_provider() {
+    let mut root = std::env::temp_dir();
+    root.push(format!("xpanda-test-file-provider-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("dir")).unwrap();
+    std::fs::write(root.join("dir/secret"), "woop\n").unwrap();
+    std::fs::write(root.join("large"), "0123456789").unwrap();
+
+    let xpanda = Xpanda::builder()
+        .register_namespace("file", FileProvider::new(&root).max_size(5))
+        .register_namespace(
+            "trimmed",
+            FileProvider::new(&root).trim_trailing_newlines(true),
+        )
+        .build();
+
+    assert_eq!(
+        xpanda.expand("${file:dir/secret}"),
+        Ok(String::from("woop\n"))
+    );
+    assert_eq!(
+        xpanda.expand("${trimmed:./dir/secret}"),
+        Ok(String::from("woop"))
+    );
+    assert_eq!(xpanda.expand("${file:missing}"), Ok(String::new()));
+    assert_eq!(
+        xpanda.expand("${file:large}"),
+        Err(Error {
+            kind: ErrorKind::Provider,
+            message: String::from(
+                "Failed to resolve 'file:large': File 'large' exceeds the maximum size of 5 bytes"
+            ),
+            line: 1,
+            col: 1,
+            span: Some(Span::default()),
+        })
+    );
+    assert_eq!(
+        xpanda.expand("${file:dir/../../secret}"),
+        Err(Error {
+            kind: ErrorKind::Provider,
+            message: String::from(
+                "Failed to resolve 'file:dir/../../secret': File 'dir/../../secret' is outside of \
+                 the root directory"
+            ),
+            line: 1,
+            col: 1,
+            span: Some(Span::default()),
+        })
+    );
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn escaped_param_text() {
+    let xpanda = Xpanda::default();
+
+    assert_eq!(xpanda.expand("${VAR-a$}b}"), Ok(String::from("a}b")));
+    assert_eq!(xpanda.expand("${VAR:-$:b}"), Ok(String::from(":b")));
+    assert_eq!(xpanda.expand("${VAR:-a:b}"), Ok(String::from("a:b")));
+    assert_eq!(xpanda.expand("${VAR-a$$b$c}"), Ok(String::from("a$b$c")));
+    assert_eq!(
+        xpanda.expand(r#"${VAR:-"key": "value$}"}"#),
+        Ok(String::from(r#""key": "value}""#))
+    );
+    assert_eq!(
+        xpanda.expand("${VAR?oops$}}"),
+        Err(Error {
+            kind: ErrorKind::Required,
+            message: String::from("oops}"),
+            line: 1,
+            col: 1,
+            span: Some(Span::default()),
+        })
+    );
+}
+
+#[test]
+fn escaped_param_text_round_trip() {
+    let inputs = [
+        "${VAR-a$}b}",
+        "${VAR:-$:b}",
+        "${VAR-a:b$$c}",
+        "${VAR+$}}",
+        "${VAR:?$:oops$}}",
+        "${VAR?a$$b ${A}$:c $$}",
+        "${VAR:?${A:?${B}$}}}",
+        "a}b$$c",
+    ];
+
+    for input in inputs {
+        let template = Template::parse(input).unwrap();
+        let formatted = template.to_string();
+
+        assert_eq!(formatted, input);
+        assert_eq!(Template::parse(&formatted), Ok(template));
+    }
+}
+
+#[test]
+fn raw_param_text_round_trip() {
+    let inputs = [
+        "${A:-a$ b}",
+        "${A:-$$$B}",
+        r"${A:-a\$b}",
+        "${A?oh $$ no}",
+        "${A-${B:+x$1}}",
+    ];
+
+    for input in inputs {
+        let template = Template::parse(input).unwrap();
+        let formatted = template.to_string();
+
+        assert_eq!(formatted, input);
+        assert_eq!(Template::parse(&formatted), Ok(template));
+    }
+}
+
+#[test]
+fn quoted_param_text() {
+    let xpanda = Xpanda::builder().quoting(true).build();
+
+    assert_eq!(
+        xpanda.expand(r#"${VAR:-"a } b"}"#),
+        Ok(String::from("a } b"))
+    );
+    assert_eq!(xpanda.expand("${VAR:-'a } b'}"), Ok(String::from("a } b")));
+    assert_eq!(xpanda.expand("${VAR-':b'}"), Ok(String::from(":b")));
+    assert_eq!(
+        xpanda.expand(r#"${VAR-a" $HOME "b}"#),
+        Ok(String::from("a $HOME b"))
+    );
+    assert_eq!(xpanda.expand(r#"${VAR-"it's"}"#), Ok(String::from("it's")));
+    assert_eq!(
+        xpanda.expand(r#"${VAR-'say "hi"'}"#),
+        Ok(String::from(r#"say "hi""#))
+    );
+    assert_eq!(
+        xpanda.expand(r#"${VAR-"a \"b\" \\ \c"}"#),
+        Ok(String::from(r#"a "b" \ \c"#))
+    );
+    assert_eq!(xpanda.expand(r#"${VAR-""}"#), Ok(String::new()));
+    assert_eq!(
+        xpanda.expand(r#"say "hi""#),
+        Ok(String::from(r#"say "hi""#))
+    );
+    assert_eq!(
+        xpanda.expand("${VAR?'oops}'}"),
+        Err(Error {
+            kind: ErrorKind::Required,
+            message: String::from("oops}"),
+            line: 1,
+            col: 1,
+            span: Some(Span::default()),
+        })
+    );
+}
+
+#[test]
+fn quoted_param_text_disabled() {
+    let xpanda = Xpanda::default();
+
+    assert_eq!(xpanda.expand("${VAR-'a'}"), Ok(String::from("'a'")));
+    assert_eq!(xpanda.expand(r#"${VAR-"a"b}"#), Ok(String::from(r#""a"b"#)));
+}
+
+#[test]
+fn quoted_param_text_unterminated() {
+    let xpanda = Xpanda::builder().quoting(true).build();
+
+    assert_eq!(
+        xpanda.expand(r#"${VAR-"a}"#),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from("Expected a matching '}' for the '${' opened"),
+            line: 1,
+            col: 1,
+            span: Some(Span::new(Position::new(0, 1, 1), Position::new(2, 1, 3))),
+        })
+    );
+}
+
+#[test]
+fn quoted_param_text_round_trip() {
+    let xpanda = Xpanda::builder().quoting(true).build();
+    let inputs = [
+        r#"${VAR-"a } b"}"#,
+        r#"${VAR-'say "hi"'}"#,
+        r"${VAR:-'a\b'}",
+        r#"${VAR:?"it's \\"}"#,
+        r#"${A-${B-"x'}"}}"#,
+    ];
+
+    for input in inputs {
+        let template = xpanda.parse(input).unwrap();
+        let formatted = template.to_string();
+
+        assert_eq!(formatted, input);
+        assert_eq!(xpanda.parse(&formatted), Ok(template));
+    }
+}
+
+#[test]
+fn digit_identifier_strict() {
+    let xpanda = Xpanda::builder()
+        .with_positional_vars(vec![String::from("a"), String::from("b")])
+        .build();
+
+    assert_eq!(
+        xpanda.expand("$2x"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from("Invalid identifier '2x', identifiers can't start with a digit"),
+            line: 1,
+            col: 2,
+            span: Some(Span::new(Position::new(1, 1, 2), Position::new(3, 1, 4))),
+        })
+    );
+    assert_eq!(
+        xpanda.expand("${3_}"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from("Invalid identifier '3_', identifiers can't start with a digit"),
+            line: 1,
+            col: 3,
+            span: Some(Span::new(Position::new(2, 1, 3), Position::new(4, 1, 5))),
+        })
+    );
+    assert_eq!(
+        xpanda.expand("${1abc}"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from(
+                "Invalid identifier '1abc', identifiers can't start with a digit"
+            ),
+            line: 1,
+            col: 3,
+            span: Some(Span::new(Position::new(2, 1, 3), Position::new(6, 1, 7))),
+        })
+    );
+    assert_eq!(xpanda.expand("${12}"), Ok(String::new()));
+    assert_eq!(xpanda.expand("${2}x"), Ok(String::from("bx")));
+}
+
+#[test]
+fn digit_identifier_lenient() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("2x"), String::from("woop"));
+    named_vars.insert(String::from("3_"), String::from("hello"));
+    let xpanda = Xpanda::builder()
+        .with_positional_vars(vec![String::from("a"), String::from("b")])
+        .with_named_vars(named_vars)
+        .lenient_identifiers(true)
+        .build();
+
+    assert_eq!(xpanda.expand("$2x"), Ok(String::from("woop")));
+    assert_eq!(xpanda.expand("${3_}"), Ok(String::from("hello")));
+    assert_eq!(xpanda.expand("${12}"), Ok(String::new()));
+    assert_eq!(xpanda.expand("$2"), Ok(String::from("b")));
+    assert_eq!(xpanda.parse("$2x").unwrap().variables(), vec!["2x"]);
+}
+
+#[test]
+fn index_out_of_range() {
+    let xpanda = Xpanda::default();
+
+    assert_eq!(
+        xpanda.expand("${99999999999999999999999}"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from("Index '99999999999999999999999' is out of range"),
+            line: 1,
+            col: 3,
+            span: Some(Span::new(Position::new(2, 1, 3), Position::new(25, 1, 26))),
+        })
+    );
+    assert_eq!(
+        xpanda.expand("$0x1F"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from(
+                "Invalid identifier '0x1F', identifiers can't start with a digit"
+            ),
+            line: 1,
+            col: 2,
+            span: Some(Span::new(Position::new(1, 1, 2), Position::new(5, 1, 6))),
+        })
+    );
+    assert_eq!(xpanda.expand("${007}"), Ok(String::new()));
+}
+
+#[test]
+#[cfg(feature = "length")]
+fn index_exceeds_positional_vars_no_unset() {
+    let xpanda = Xpanda::builder()
+        .no_unset(true)
+        .with_positional_vars(vec![String::from("a"), String::from("b")])
+        .build();
+
+    assert_eq!(xpanda.expand("$2 ${#}"), Ok(String::from("b 2")));
+    assert_eq!(
+        xpanda.expand("$1 $3"),
+        Err(Error::new(
+            ErrorKind::Unset,
+            String::from("'3' is unset, 2 positional variable(s) are set"),
+            &Position::new(3, 1, 4),
+        ))
+    );
+    assert_eq!(
+        xpanda.expand("$1\n${#3}"),
+        Err(Error::new(
+            ErrorKind::Unset,
+            String::from("'3' is unset, 2 positional variable(s) are set"),
+            &Position::new(3, 2, 1),
+        ))
+    );
+}
+
+#[test]
+#[cfg(feature = "length")]
+fn error_span_byte_offsets() {
+    let xpanda = Xpanda::default();
+    let input = "åäö ${VAR:-${#}";
+    let error = xpanda.expand(input).unwrap_err();
+
+    assert_eq!((error.line, error.col), (1, 5));
+    assert_eq!(
+        error.span,
+        Some(Span::new(Position::new(7, 1, 5), Position::new(9, 1, 7)))
+    );
+    assert_eq!(
+        &input[error.span.unwrap().start.index..error.span.unwrap().end.index],
+        "${"
+    );
+}
+
+#[test]
+fn error_display() {
+    let xpanda = Xpanda::builder().no_unset(true).build();
+
+    assert_eq!(
+        xpanda.expand("a\nb $").unwrap_err().to_string(),
+        "Expected identifier, found EOF at line 2, column 4"
+    );
+    assert_eq!(
+        xpanda.expand("$VAR").unwrap_err().to_string(),
+        "'VAR' is unset at line 1, column 1"
+    );
+    assert_eq!(
+        xpanda.expand("a${VAR?}").unwrap_err().to_string(),
+        "'VAR' is unset at line 1, column 2"
+    );
+}
+
+#[test]
+fn error_into_boxed_error() {
+    fn expand(input: &str) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(Xpanda::default().expand(input)?)
+    }
+
+    let error = expand("${VAR").unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "Expected a matching '}' for the '${' opened at line 1, column 1"
+    );
+    assert!(error.downcast_ref::<Error>().is_some());
+}
+
+#[test]
+fn error_position_multiline() {
+    let xpanda = Xpanda::default();
+    let input = "å\r\nä\n\nöö ${A:-x";
+    let error = xpanda.expand(input).unwrap_err();
+
+    assert_eq!((error.line, error.col), (4, 4));
+    assert_eq!(
+        error.span,
+        Some(Span::new(Position::new(13, 4, 4), Position::new(15, 4, 6)))
+    );
+}
+
+fn record_unset(builder: xpanda::Builder) -> (Xpanda, Arc<Mutex<Vec<UnsetEvent>>>) {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&events);
+    let xpanda = builder
+        .on_unset(move |event| recorded.lock().unwrap().push(event.clone()))
+        .build();
+
+    (xpanda, events)
+}
+
+#[cfg(feature = "length")]
+fn unset_event(
+    name: &str,
+    position: Position,
+    outcome: UnsetOutcome,
+) -> (String, Position, UnsetOutcome) {
+    (String::from(name), position, outcome)
+}
+
+#[test]
+#[cfg(feature = "length")]
+fn on_unset() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("SET"), String::from("woop"));
+    named_vars.insert(String::from("EMPTY"), String::new());
+    let (xpanda, events) = record_unset(Xpanda::builder().with_named_vars(named_vars));
+
+    assert_eq!(
+        xpanda.expand("$SET ${A-x}\n${B:-$C} ${EMPTY:+y}${EMPTY-z}${#D}"),
+        Ok(String::from("woop x\n 0"))
+    );
+
+    let events = events
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|event| (event.name.clone(), event.position, event.outcome))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        events,
+        vec![
+            unset_event("A", Position::new(5, 1, 6), UnsetOutcome::Default),
+            unset_event("B", Position::new(12, 2, 1), UnsetOutcome::Default),
+            unset_event("C", Position::new(17, 2, 6), UnsetOutcome::Empty),
+            unset_event("EMPTY", Position::new(21, 2, 10), UnsetOutcome::Empty),
+            unset_event("D", Position::new(42, 2, 31), UnsetOutcome::Empty),
+        ]
+    );
+}
+
+#[test]
+fn on_unset_error() {
+    let (xpanda, events) = record_unset(Xpanda::builder().no_unset(true));
+
+    assert!(xpanda.expand("${A?}").is_err());
+    assert!(xpanda.expand("$1").is_err());
+
+    let events = events.lock().unwrap();
+
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].name, "A");
+    assert_eq!(events[0].outcome, UnsetOutcome::Error);
+    assert_eq!(events[1].name, "1");
+    assert_eq!(events[1].outcome, UnsetOutcome::Error);
+}
+
+#[test]
+fn on_timing() {
+    let timings = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&timings);
+    let xpanda = Xpanda::builder()
+        .on_timing(move |timing| recorded.lock().unwrap().push(timing.phase))
+        .build();
+
+    let template = xpanda.parse("${A:-a} $B").unwrap();
+    assert_eq!(xpanda.render(&template), Ok(String::from("a ")));
+    assert_eq!(
+        xpanda.render_compiled(&template.compile()),
+        Ok(String::from("a "))
+    );
+    assert!(xpanda.expand("${A").is_err());
+    assert!(xpanda.expand("${A?}").is_err());
+
+    assert_eq!(
+        *timings.lock().unwrap(),
+        vec![
+            Phase::Lex,
+            Phase::Parse,
+            Phase::Eval,
+            Phase::Eval,
+            Phase::Lex,
+            Phase::Parse,
+            Phase::Lex,
+            Phase::Parse,
+            Phase::Eval,
+        ]
+    );
+}
+
+#[test]
+fn validate_vars() {
+    let vars = HashMap::from([
+        (String::from("A B"), String::new()),
+        (String::from(""), String::new()),
+        (String::from("12"), String::new()),
+        (String::from("1abc"), String::new()),
+        (String::from("valid_é1"), String::new()),
+    ]);
+    let builder = Xpanda::builder()
+        .validate_vars(true)
+        .with_named_vars(HashMap::from([(String::from("a-b"), String::new())]))
+        .with_labeled_vars("vars.env", vars.clone());
+
+    let invalid = builder.try_build().err().unwrap();
+    let reasons = invalid
+        .iter()
+        .map(|invalid| (invalid.name.as_str(), invalid.reason))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        reasons,
+        vec![
+            ("", InvalidVarReason::EmptyName),
+            ("12", InvalidVarReason::Numeric),
+            ("1abc", InvalidVarReason::LeadingDigit),
+            ("A B", InvalidVarReason::InvalidChar(' ')),
+            ("a-b", InvalidVarReason::InvalidChar('-')),
+        ]
+    );
+    assert_eq!(
+        invalid[3].source,
+        Some(Source::Named(Some(String::from("vars.env"))))
+    );
+    assert_eq!(invalid[4].source, Some(Source::Named(None)));
+    assert_eq!(
+        invalid[3].to_string(),
+        "Variable 'A B' can't be used, its name contains ' '"
+    );
+
+    let invalid = Xpanda::builder()
+        .validate_vars(true)
+        .lenient_identifiers(true)
+        .with_named_vars(vars.clone())
+        .try_build()
+        .err()
+        .unwrap();
+    assert_eq!(invalid.len(), 3);
+
+    assert!(Xpanda::builder()
+        .with_named_vars(vars.clone())
+        .try_build()
+        .is_ok());
+
+    let xpanda = Xpanda::builder()
+        .validate_vars(true)
+        .with_named_vars(vars)
+        .with_named_vars(HashMap::from([(String::from("A"), String::from("a"))]))
+        .build();
+    assert_eq!(xpanda.expand("$A"), Ok(String::from("a")));
+}
+
+#[test]
+#[cfg(unix)]
+fn validate_vars_env() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    std::env::set_var("XPANDA_TEST_NOT_UTF8", OsStr::from_bytes(b"a\xFFb"));
+    let filter = |name: &str| name == "XPANDA_TEST_NOT_UTF8";
+
+    let xpanda = Xpanda::builder().with_env_vars_matching(filter).build();
+    assert_eq!(
+        xpanda.expand("$XPANDA_TEST_NOT_UTF8"),
+        Ok(String::from("a\u{FFFD}b"))
+    );
+
+    let invalid = Xpanda::builder()
+        .with_env_vars_matching(filter)
+        .validate_vars(true)
+        .try_build()
+        .err()
+        .unwrap();
+    assert_eq!(invalid.len(), 1);
+    assert_eq!(invalid[0].name, "XPANDA_TEST_NOT_UTF8");
+    assert_eq!(invalid[0].source, Some(Source::Env));
+    assert_eq!(invalid[0].reason, InvalidVarReason::NotUtf8);
+
+    // Overriding the variable replaces the invalid value
+    assert!(Xpanda::builder()
+        .with_env_vars_matching(filter)
+        .with_named_vars(HashMap::from([(
+            String::from("XPANDA_TEST_NOT_UTF8"),
+            String::from("ab")
+        )]))
+        .validate_vars(true)
+        .try_build()
+        .is_ok());
+}
+
+#[test]
+fn require_vars() {
+    let xpanda = Xpanda::builder()
+        .require_vars(["DB_URL", "1"])
+        .require_vars(vec![String::from("PORT")])
+        .with_named_vars(HashMap::from([(String::from("PORT"), String::from("80"))]))
+        .build();
+
+    let error = xpanda.expand("$LOG_LEVEL ${DB_URL}").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Unset);
+    assert_eq!(error.message, "'DB_URL' is unset");
+    assert_eq!(
+        xpanda.expand("$1").unwrap_err().message,
+        "'1' is unset, 0 positional variable(s) are set"
+    );
+    assert_eq!(
+        xpanda.expand("${DB_URL-db}:$PORT$LOG_LEVEL$2"),
+        Ok(String::from("db:80"))
+    );
+    assert!(xpanda.expand("${DB_URL:+x}").is_ok());
+    #[cfg(feature = "length")]
+    assert_eq!(
+        xpanda.expand("${#DB_URL}").unwrap_err().kind,
+        ErrorKind::Unset
+    );
+
+    let xpanda = Xpanda::builder()
+        .require_vars(["DATABASE_URL"])
+        .optional_vars(["LOG"])
+        .with_aliases(HashMap::from([
+            (String::from("DB_URL"), String::from("DATABASE_URL")),
+            (String::from("LOG"), String::from("DATABASE_URL")),
+        ]))
+        .build();
+    assert_eq!(
+        xpanda.expand("$DB_URL").unwrap_err().message,
+        "'DB_URL' is unset"
+    );
+    assert_eq!(xpanda.expand("[$LOG]"), Ok(String::from("[]")));
+}
+
+#[test]
+fn optional_vars() {
+    let xpanda = Xpanda::builder()
+        .no_unset(true)
+        .optional_vars(["LOG_LEVEL", "DB_URL"])
+        .require_vars(["DB_URL"])
+        .build();
+
+    assert_eq!(xpanda.expand("[$LOG_LEVEL]"), Ok(String::from("[]")));
+    assert_eq!(xpanda.expand("$DB_URL").unwrap_err().kind, ErrorKind::Unset);
+    assert_eq!(xpanda.expand("$OTHER").unwrap_err().kind, ErrorKind::Unset);
+
+    let template = xpanda.parse("$LOG_LEVEL").unwrap();
+    assert_eq!(
+        xpanda.render_compiled(&template.compile()),
+        Ok(String::new())
+    );
+
+    let xpanda = xpanda.to_builder().optional_vars(["DB_URL"]).build();
+    assert_eq!(xpanda.expand("$DB_URL"), Ok(String::new()));
+}
+
+#[test]
+fn live_env() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(
+        String::from("XPANDA_TEST_LIVE_NAMED"),
+        String::from("named"),
+    );
+    let xpanda = Xpanda::builder()
+        .with_named_vars(named_vars)
+        .with_live_env(true)
+        .no_unset(true)
+        .build();
+
+    std::env::set_var("XPANDA_TEST_LIVE", "first");
+    std::env::set_var("XPANDA_TEST_LIVE_NAMED", "env");
+    assert_eq!(
+        xpanda.expand("$XPANDA_TEST_LIVE"),
+        Ok(String::from("first"))
+    );
+    assert_eq!(
+        xpanda.expand("$XPANDA_TEST_LIVE_NAMED"),
+        Ok(String::from("named"))
+    );
+
+    std::env::set_var("XPANDA_TEST_LIVE", "second");
+    assert_eq!(
+        xpanda.expand("$XPANDA_TEST_LIVE"),
+        Ok(String::from("second"))
+    );
+
+    std::env::remove_var("XPANDA_TEST_LIVE");
+    assert_eq!(
+        xpanda.expand("$XPANDA_TEST_LIVE"),
+        Err(Error {
+            kind: ErrorKind::Unset,
+            message: String::from("'XPANDA_TEST_LIVE' is unset"),
+            line: 1,
+            col: 1,
+            span: Some(Span::default()),
+        })
+    );
+}
+
+#[test]
+fn case_insensitive_env() {
+    std::env::set_var("XPANDA_TEST_CASE", "woop");
+    std::env::set_var("XPANDA_TEST_LIVE_CASE", "live");
+
+    let xpanda = Xpanda::builder()
+        .with_env_vars()
+        .case_insensitive_env(true)
+        .build();
+    assert_eq!(xpanda.expand("$xpanda_test_Case"), Ok(String::from("woop")));
+
+    let xpanda = Xpanda::builder()
+        .with_live_env(true)
+        .case_insensitive_env(true)
+        .build();
+    assert_eq!(
+        xpanda.expand("$Xpanda_Test_Live_Case"),
+        Ok(String::from("live"))
+    );
+
+    let xpanda = Xpanda::builder()
+        .with_env_vars()
+        .case_insensitive_env(false)
+        .build();
+    assert_eq!(xpanda.expand("$xpanda_test_case"), Ok(String::new()));
+}
+
+#[test]
+fn case_insensitive_env_named_vars() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("XPANDA_TEST_NAMED_CASE"), String::from("woop"));
+    let xpanda = Xpanda::builder()
+        .with_named_vars(named_vars)
+        .case_insensitive_env(true)
+        .build();
+
+    assert_eq!(xpanda.expand("$xpanda_test_named_case"), Ok(String::new()));
+}
+
+#[test]
+fn powershell_env() {
+    std::env::set_var("XPANDA_TEST_POWERSHELL", "woop");
+    let xpanda = Xpanda::builder().powershell_env(true).build();
+
+    assert_eq!(
+        xpanda.expand("$env:XPANDA_TEST_POWERSHELL/${env:XPANDA_TEST_POWERSHELL}"),
+        Ok(String::from("woop/woop"))
+    );
+    assert_eq!(xpanda.expand("$env: $env:"), Ok(String::from(": :")));
+    assert_eq!(
+        xpanda.parse("a $env:PATH.").unwrap().to_string(),
+        "a ${env:PATH}."
+    );
+}
+
+#[test]
+fn powershell_env_disabled() {
+    std::env::set_var("XPANDA_TEST_POWERSHELL_DISABLED", "woop");
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("env"), String::from("named"));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+
+    assert_eq!(
+        xpanda.expand("$env:XPANDA_TEST_POWERSHELL_DISABLED"),
+        Ok(String::from("named:XPANDA_TEST_POWERSHELL_DISABLED"))
+    );
+}
+
+#[test]
+fn powershell_env_custom_provider() {
+    let xpanda = Xpanda::builder()
+        .powershell_env(true)
+        .register_namespace("env", |key: &str| Ok(Some(key.to_lowercase())))
+        .build();
+
+    assert_eq!(xpanda.expand("$env:WOOP"), Ok(String::from("woop")));
+}
+
+#[test]
+fn special_vars() {
+    let mut special_vars = HashMap::new();
+    special_vars.insert('?', String::from("1"));
+    special_vars.insert('$', String::from("42"));
+    special_vars.insert('#', String::from("2"));
+    special_vars.insert('a', String::from("ignored"));
+    let xpanda = Xpanda::builder()
+        .no_unset(true)
+        .with_special_vars(special_vars)
+        .build();
+
+    assert_eq!(
+        xpanda.expand("$$: $# args, exit code $?"),
+        Ok(String::from("42: 2 args, exit code 1"))
+    );
+    assert_eq!(xpanda.expand("${A:-$?}${A:-$$}"), Ok(String::from("1$")));
+    assert_eq!(
+        xpanda.expand("$a").unwrap_err().to_string(),
+        "'a' is unset at line 1, column 1"
+    );
+    assert_eq!(xpanda.parse("$?$A").unwrap().to_string(), "$?${A}");
+    assert_eq!(
+        xpanda.sources(&xpanda.parse("$? $# $A").unwrap()),
+        vec![
+            (String::from("?"), Some(Source::Special)),
+            (String::from("#"), Some(Source::Special)),
+            (String::from("A"), None)
+        ]
+    );
+}
+
+#[test]
+fn special_vars_disabled() {
+    let mut special_vars = HashMap::new();
+    special_vars.insert('?', String::from("1"));
+    let xpanda = Xpanda::builder().with_special_vars(special_vars).build();
+
+    assert_eq!(xpanda.expand("$$?"), Ok(String::from("$?")));
+    assert_eq!(
+        xpanda.expand("$!").unwrap_err().to_string(),
+        "Expected identifier, found '!' at line 1, column 3"
+    );
+
+    let template = xpanda.parse("$$ $?").unwrap();
+    let vars = HashMap::from([
+        (String::from("?"), String::from("0")),
+        (String::from("$"), String::from("42")),
+    ]);
+    assert_eq!(
+        template.bind(vars).unwrap().render(),
+        Ok(String::from("$ 0"))
+    );
+}
+
+#[test]
+#[cfg(feature = "expr")]
+fn special_vars_disabled_in_expressions() {
+    let xpanda = Xpanda::builder()
+        .expressions(true)
+        .with_special_vars(HashMap::from([('?', String::from("1"))]))
+        .with_named_vars(HashMap::from([(String::from("!"), String::from("named"))]))
+        .build();
+
+    assert_eq!(xpanda.expand("${A:-=$? + 1}"), Ok(String::from("2")));
+    assert_eq!(xpanda.expand("${A:-=$!}"), Ok(String::from("named")));
+}
+
+#[test]
+fn special_vars_literal_dollar() {
+    let xpanda = Xpanda::builder()
+        .raw_blocks(true)
+        .with_special_vars(HashMap::from([('$', String::from("42"))]))
+        .build();
+
+    assert_eq!(
+        xpanda.expand("pid $$ costs $[[raw]]$$[[/raw]]5"),
+        Ok(String::from("pid 42 costs $5"))
+    );
+    assert_eq!(xpanda.expand("${A:-$$}"), Ok(String::from("$")));
+}
+
+#[test]
+fn front_matter() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("DB_URL"), String::from("db"));
+    named_vars.insert(String::from("PORT"), String::from("5432"));
+    let xpanda = Xpanda::builder()
+        .front_matter(true)
+        .with_named_vars(named_vars)
+        .build();
+    let input = "#! xpanda: require DB_URL, PORT; default REGION=us-east-1\n\
+                 #! xpanda: default PORT=80\n\
+                 $DB_URL:$PORT/$REGION\n";
+
+    assert_eq!(
+        xpanda.expand(input),
+        Ok(String::from("db:5432/us-east-1\n"))
+    );
+    assert_eq!(
+        xpanda
+            .parse("#! xpanda: require DB_URL\n$DB_URL")
+            .unwrap()
+            .to_string(),
+        "#! xpanda: require DB_URL\n${DB_URL}"
+    );
+}
+
+#[test]
+fn front_matter_required() {
+    let xpanda = Xpanda::builder().front_matter(true).build();
+    let input = "#! xpanda: require DB_URL, PORT, REGION; default REGION=us-east-1\n$DB_URL\n";
+
+    assert_eq!(
+        xpanda.expand(input),
+        Err(Error {
+            kind: ErrorKind::Required,
+            message: String::from("Required variable(s) 'DB_URL', 'PORT' are unset"),
+            line: 1,
+            col: 1,
+            span: Some(Span::new(Position::new(0, 1, 1), Position::new(66, 2, 1))),
+        })
+    );
+}
+
+#[test]
+fn front_matter_syntax_error() {
+    let xpanda = Xpanda::builder().front_matter(true).build();
+
+    assert_eq!(
+        xpanda.expand("#! xpanda: require A\n#! xpanda: require B; expect C\n"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from(
+                "Unknown front matter directive 'expect', expected 'require' or 'default'"
+            ),
+            line: 2,
+            col: 22,
+            span: Some(Span::new(
+                Position::new(42, 2, 22),
+                Position::new(51, 2, 31)
+            )),
+        })
+    );
+    assert_eq!(
+        xpanda
+            .expand("#! xpanda: default 1A=b")
+            .unwrap_err()
+            .message,
+        "Invalid variable name '1A' in front matter"
+    );
+    assert_eq!(
+        xpanda.expand("#! xpanda: default A").unwrap_err().message,
+        "Expected 'NAME=value' after 'default'"
+    );
+}
+
+#[test]
+fn front_matter_positions() {
+    let xpanda = Xpanda::builder().front_matter(true).build();
+    let error = xpanda.expand("#! xpanda: default A=b\n${").unwrap_err();
+
+    assert_eq!((error.line, error.col), (2, 1));
+    assert_eq!(
+        error.span,
+        Some(Span::new(Position::new(23, 2, 1), Position::new(25, 2, 3)))
+    );
+
+    let template = xpanda.parse("#! xpanda: default A=b\n$A $B").unwrap();
+    let positions = template
+        .references()
+        .into_iter()
+        .map(|reference| reference.position)
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        positions,
+        vec![Position::new(23, 2, 1), Position::new(26, 2, 4)]
+    );
+}
+
+#[test]
+fn front_matter_disabled() {
+    let xpanda = Xpanda::default();
+
+    assert_eq!(
+        xpanda.expand("#! xpanda: require A\n$A"),
+        Ok(String::from("#! xpanda: require A\n"))
+    );
+}
+
+#[test]
+#[cfg(feature = "regex")]
+fn schema_pattern() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("PORT"), String::from("abc"));
+    let schema = Schema::new().var(VarSchema::new("PORT").pattern("[0-9]+").unwrap());
+    let xpanda = Xpanda::builder()
+        .schema(schema)
+        .with_named_vars(named_vars)
+        .build();
+
+    assert_eq!(
+        xpanda.expand("host:$PORT\nport=${PORT:-80}"),
+        Err(Error {
+            kind: ErrorKind::InvalidValue,
+            message: String::from("'PORT' doesn't match the pattern '[0-9]+'"),
+            line: 1,
+            col: 6,
+            span: Some(Span::new(Position::new(5, 1, 6), Position::new(5, 1, 6))),
+        })
+    );
+
+    let template = xpanda.parse("host:$PORT\nport=${PORT:-80}").unwrap();
+    let positions = xpanda
+        .validate(&template)
+        .into_iter()
+        .map(|error| (error.line, error.col))
+        .collect::<Vec<_>>();
+    assert_eq!(positions, vec![(1, 6), (2, 6)]);
+}
+
+#[test]
+#[cfg(feature = "regex")]
+fn schema_pattern_whole_value() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("PORT"), String::from("80a"));
+    let schema = Schema::new().var(VarSchema::new("PORT").pattern("[0-9]+").unwrap());
+    let xpanda = Xpanda::builder()
+        .schema(schema)
+        .with_named_vars(named_vars)
+        .build();
+
+    assert!(xpanda.expand("$PORT").is_err());
+    assert_eq!(xpanda.expand("unused"), Ok(String::from("unused")));
+}
+
+#[test]
+fn schema_one_of() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("LEVEL"), String::from("info"));
+    let schema = Schema::new().var(VarSchema::new("LEVEL").one_of(["debug", "info"]));
+    let xpanda = Xpanda::builder()
+        .schema(schema.clone())
+        .with_named_vars(named_vars)
+        .build();
+
+    assert_eq!(xpanda.expand("$LEVEL"), Ok(String::from("info")));
+
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("LEVEL"), String::from("loud"));
+    let xpanda = Xpanda::builder()
+        .schema(schema)
+        .with_named_vars(named_vars)
+        .build();
+
+    assert_eq!(
+        xpanda.expand("$LEVEL").unwrap_err().message,
+        "'LEVEL' must be one of 'debug', 'info'"
+    );
+}
+
+#[test]
+fn schema_required() {
+    let schema = Schema::new()
+        .var(VarSchema::new("DB_URL").required(true))
+        .var(VarSchema::new("OPTIONAL").one_of(["a", "b"]));
+    let xpanda = Xpanda::builder().schema(schema).build();
+
+    assert_eq!(
+        xpanda.expand("a $OPTIONAL b"),
+        Err(Error {
+            kind: ErrorKind::Required,
+            message: String::from("'DB_URL' is required by the schema but unset"),
+            line: 1,
+            col: 1,
+            span: Some(Span::default()),
+        })
+    );
+    assert_eq!(xpanda.expand("a $DB_URL").unwrap_err().col, 3);
+}
+
+#[test]
+fn schema_front_matter_default() {
+    let schema = Schema::new().var(VarSchema::new("REGION").required(true).one_of(["eu"]));
+    let xpanda = Xpanda::builder().schema(schema).front_matter(true).build();
+
+    assert_eq!(
+        xpanda.expand("#! xpanda: default REGION=eu\n$REGION"),
+        Ok(String::from("eu"))
+    );
+    assert_eq!(
+        xpanda
+            .expand("#! xpanda: default REGION=us\n$REGION")
+            .unwrap_err()
+            .kind,
+        ErrorKind::InvalidValue
+    );
+}
+
+#[test]
+#[cfg(feature = "regex")]
+fn schema_invalid_pattern() {
+    assert!(VarSchema::new("A")
+        .pattern("(")
+        .unwrap_err()
+        .starts_with("Invalid pattern '('"));
+}
+
+#[test]
+fn cached() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::from("woop"));
+    let xpanda = CachedXpanda::new(Xpanda::builder().with_named_vars(named_vars).build());
+
+    assert!(xpanda.is_empty());
+    assert_eq!(xpanda.expand("$VAR"), Ok(String::from("woop")));
+    assert_eq!(xpanda.expand("$VAR"), Ok(String::from("woop")));
+    assert_eq!(xpanda.expand("${VAR}!"), Ok(String::from("woop!")));
+    assert_eq!(xpanda.len(), 2);
+
+    let error = xpanda.expand("${VAR").unwrap_err();
+    assert_eq!(xpanda.expand("${VAR"), Err(error));
+    assert_eq!(xpanda.len(), 3);
+
+    xpanda.clear();
+    assert!(xpanda.is_empty());
+}
+
+#[test]
+fn cached_capacity() {
+    let xpanda = CachedXpanda::with_capacity(Xpanda::default(), 2);
+
+    assert_eq!(xpanda.expand("a"), Ok(String::from("a")));
+    assert_eq!(xpanda.expand("b"), Ok(String::from("b")));
+    assert_eq!(xpanda.len(), 2);
+    assert_eq!(xpanda.expand("c"), Ok(String::from("c")));
+    assert_eq!(xpanda.len(), 1);
+
+    let xpanda = CachedXpanda::with_capacity(Xpanda::default(), 0);
+    assert_eq!(xpanda.expand("a"), Ok(String::from("a")));
+    assert!(xpanda.is_empty());
+}
+
+#[test]
+fn cached_dynamic_vars() {
+    std::env::set_var("XPANDA_TEST_CACHED", "a");
+    let xpanda = CachedXpanda::new(Xpanda::builder().with_live_env(true).build());
+
+    assert_eq!(xpanda.expand("$XPANDA_TEST_CACHED"), Ok(String::from("a")));
+    std::env::set_var("XPANDA_TEST_CACHED", "b");
+    assert_eq!(xpanda.expand("$XPANDA_TEST_CACHED"), Ok(String::from("b")));
+    assert!(xpanda.is_empty());
+
+    let xpanda = CachedXpanda::new(
+        Xpanda::builder()
+            .register_namespace("ns", |key: &str| Ok(Some(key.to_string())))
+            .build(),
+    );
+    assert_eq!(xpanda.expand("${ns:key}"), Ok(String::from("key")));
+    assert!(xpanda.is_empty());
+}
+
+#[test]
+fn document_split() {
+    let input = "a\n---\nb\r\n---\r\n\n---";
+    let documents = Document::split(input, "---");
+    let texts = documents
+        .iter()
+        .map(|document| (document.text, document.delimiter))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        texts,
+        vec![
+            ("a\n", "---\n"),
+            ("b\r\n", "---\r\n"),
+            ("\n", "---"),
+            ("", "")
+        ]
+    );
+    assert_eq!(documents[1].start, Position::new(6, 3, 1));
+    assert_eq!(documents[3].start, Position::new(18, 7, 1));
+    assert_eq!(Document::split("a --- b", "---")[0].text, "a --- b");
+}
+
+#[test]
+fn document_locate_error() {
+    let input = "a\n---\nb\n  ${A:-x\n";
+    let documents = Document::split(input, "---");
+    let error = Xpanda::default().expand(documents[1].text).unwrap_err();
+    let error = documents[1].locate(error);
+
+    assert_eq!(error.line, 4);
+    assert_eq!(error.col, 3);
+    assert_eq!(error.span.unwrap().start, Position::new(10, 4, 3));
+    assert_eq!(
+        &input[error.span.unwrap().start.index..error.span.unwrap().end.index],
+        "${"
+    );
+}
+
+#[test]
+fn access_policy() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("PUBLIC_A"), String::from("a"));
+    named_vars.insert(String::from("HIDDEN"), String::from("hidden"));
+    named_vars.insert(String::from("SECRET"), String::from("secret"));
+    named_vars.insert(String::from("TOKEN"), String::from("token"));
+    let xpanda = Xpanda::builder()
+        .with_named_vars(named_vars)
+        .with_positional_vars(vec![String::from("one")])
+        .access_policy(|name| match name {
+            "HIDDEN" => Access::Hide,
+            "TOKEN" | "UNSET" => Access::Redact(String::from("***")),
+            "1" => Access::Allow,
+            name if name.starts_with("PUBLIC_") => Access::Allow,
+            _ => Access::Deny,
+        })
+        .build();
+
+    assert_eq!(xpanda.expand("$PUBLIC_A $1"), Ok(String::from("a one")));
+    assert_eq!(
+        xpanda.expand("${HIDDEN-default}"),
+        Ok(String::from("default"))
+    );
+    assert_eq!(xpanda.expand("$TOKEN"), Ok(String::from("***")));
+    assert_eq!(xpanda.expand("${UNSET-unset}"), Ok(String::from("unset")));
+    assert_eq!(
+        xpanda
+            .expand("${SECRET-default}")
+            .map_err(|error| (error.kind, error.message)),
+        Err((
+            ErrorKind::Policy,
+            String::from("Access to 'SECRET' is denied")
+        ))
+    );
+    assert_eq!(
+        xpanda.expand("${2}").map_err(|error| error.kind),
+        Err(ErrorKind::Policy)
+    );
+}
+
+#[test]
+fn access_policy_aliases_and_front_matter() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("PUBLIC_A"), String::from("a"));
+    let mut aliases = HashMap::new();
+    aliases.insert(String::from("A"), String::from("PUBLIC_A"));
+    aliases.insert(String::from("B"), String::from("PUBLIC_B"));
+    let xpanda = Xpanda::builder()
+        .front_matter(true)
+        .with_named_vars(named_vars)
+        .with_aliases(aliases)
+        .access_policy(|name| {
+            if name.starts_with("PUBLIC_") {
+                Access::Hide
+            } else {
+                Access::Deny
+            }
+        })
+        .build();
+
+    assert_eq!(
+        xpanda.expand("#! xpanda: default PUBLIC_B=b\n$A $B"),
+        Ok(String::from(" b"))
+    );
+}
+
+#[test]
+fn reproducible() {
+    std::env::set_var("XPANDA_TEST_REPRODUCIBLE", "woop");
+    std::env::set_var("Xpanda_Test_Reproducible_Sorted", "first");
+    std::env::set_var("XPANDA_TEST_REPRODUCIBLE_SORTED", "second");
+
+    let xpanda = Xpanda::builder().reproducible(true).with_env_vars().build();
+    assert_eq!(
+        xpanda.expand("$xpanda_test_reproducible"),
+        Ok(String::new())
+    );
+
+    let xpanda = Xpanda::builder()
+        .reproducible(true)
+        .case_insensitive_env(true)
+        .with_env_vars()
+        .build();
+    assert_eq!(
+        xpanda.expand("$xpanda_test_reproducible $xpanda_test_reproducible_sorted"),
+        Ok(String::from("woop second"))
+    );
+
+    let xpanda = Xpanda::builder().reproducible(true).build();
+    let derived = xpanda.to_builder().with_env_vars().build();
+    assert_eq!(
+        derived.expand("${xpanda_test_reproducible-unset}"),
+        Ok(String::from("unset"))
+    );
+}
+
+#[test]
+fn report() {
+    let schema = Schema::new().var(VarSchema::new("PORT").one_of(["80", "443"]));
+    let xpanda = Xpanda::builder()
+        .schema(schema)
+        .with_positional_vars(vec![String::from("one")])
+        .with_named_vars(HashMap::from([
+            (String::from("HOST"), String::from("db")),
+            (String::from("PORT"), String::from("x")),
+        ]))
+        .build();
+
+    let report = xpanda.report(&xpanda.parse("$1 $HOST ${USER:-me} $HOST").unwrap());
+    assert_eq!(report.output, Some(String::from("one db me db")));
+    assert_eq!(report.missing, vec!["USER"]);
+    assert_eq!(
+        report.used,
+        vec![
+            (String::from("1"), Source::Positional),
+            (String::from("HOST"), Source::Named(None)),
+        ]
+    );
+    assert_eq!(report.errors, vec![]);
+
+    let report = xpanda.report(&xpanda.parse("$HOST:$PORT ${USER?}").unwrap());
+    assert_eq!(report.output, None);
+    assert_eq!(report.missing, vec!["USER"]);
+    assert_eq!(report.errors.len(), 1);
+    assert_eq!(report.errors[0].kind, ErrorKind::InvalidValue);
+}
+
+#[test]
+fn report_params() {
+    let xpanda = Xpanda::builder()
+        .conditionals(true)
+        .with_named_vars(HashMap::from([
+            (String::from("HOST"), String::from("db")),
+            (String::from("USER"), String::new()),
+        ]))
+        .build();
+    let params = |input: &str| {
+        xpanda
+            .report(&xpanda.parse(input).unwrap())
+            .params
+            .into_iter()
+            .map(|param| (param.text, param.position, param.value))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        params("$$HOST=$HOST\n${if USER}$USER${end}\n${USER:?no user} ${PORT:-${HOST}}"),
+        vec![
+            (
+                String::from("$HOST"),
+                Position::new(7, 1, 8),
+                Some(String::from("db"))
+            ),
+            // The line break left out after the tag is replaced along with the block
+            (
+                String::from("${if USER}$USER${end}\n"),
+                Position::new(13, 2, 1),
+                Some(String::new())
+            ),
+            (
+                String::from("${USER:?no user}"),
+                Position::new(35, 3, 1),
+                None
+            ),
+            (
+                String::from("${PORT:-${HOST}}"),
+                Position::new(52, 3, 18),
+                Some(String::from("db"))
+            ),
+        ]
+    );
+}
+
+#[test]
+fn resolutions() {
+    let xpanda = Xpanda::builder()
+        .front_matter(true)
+        .with_positional_vars(vec![String::new()])
+        .with_named_vars(HashMap::from([
+            (String::from("A"), String::from("åäö")),
+            (String::from("B"), String::new()),
+        ]))
+        .build();
+    let template = xpanda
+        .parse("#! xpanda: default C=c\n$1 ${2-x} ${A:+$B} ${B-b} ${C:-x} ${D:-$A}")
+        .unwrap();
+    let expected = vec![
+        (String::from("1"), Resolution::Empty),
+        (String::from("2"), Resolution::Unset),
+        (String::from("A"), Resolution::Set(3)),
+        (String::from("B"), Resolution::Empty),
+        (String::from("C"), Resolution::Set(1)),
+        (String::from("D"), Resolution::Unset),
+    ];
+
+    assert_eq!(xpanda.resolutions(&template), expected);
+    assert_eq!(xpanda.report(&template).resolutions, expected);
+}
+
+#[test]
+fn unused_vars() {
+    std::env::set_var("XPANDA_TEST_UNUSED", "env");
+    let xpanda = Xpanda::builder()
+        .front_matter(true)
+        .with_env_vars()
+        .with_named_vars(HashMap::from([
+            (String::from("A"), String::from("a")),
+            (String::from("B"), String::from("b")),
+            (String::from("C"), String::from("c")),
+        ]))
+        .with_labeled_vars(
+            "d.env",
+            HashMap::from([(String::from("D"), String::from("d"))]),
+        )
+        .with_aliases(HashMap::from([(String::from("ALIAS"), String::from("B"))]))
+        .build();
+    let unused = |input: &str| xpanda.unused_vars(&xpanda.parse(input).unwrap());
+
+    assert_eq!(
+        unused("$A ${ALIAS:-x}"),
+        vec![
+            (String::from("C"), Source::Named(None)),
+            (
+                String::from("D"),
+                Source::Named(Some(String::from("d.env")))
+            ),
+        ]
+    );
+    assert_eq!(unused("#! xpanda: require C\n$A $B $D"), vec![]);
+
+    let report = xpanda.report(&xpanda.parse("$A $B $C").unwrap());
+    assert_eq!(
+        report.unused,
+        vec![(
+            String::from("D"),
+            Source::Named(Some(String::from("d.env")))
+        )]
+    );
+}
+
+#[test]
+#[cfg(feature = "indirection")]
+fn unused_vars_indirection() {
+    let xpanda = Xpanda::builder()
+        .with_named_vars(HashMap::from([
+            (String::from("NAME"), String::from("TARGET")),
+            (String::from("TARGET"), String::from("t")),
+            (String::from("OTHER"), String::from("o")),
+        ]))
+        .build();
+    let template = xpanda.parse("${!NAME}").unwrap();
+
+    assert_eq!(
+        xpanda.unused_vars(&template),
+        vec![(String::from("OTHER"), Source::Named(None))]
+    );
+}
+
+#[test]
+fn unused_vars_dynamic_names() {
+    let xpanda = Xpanda::builder()
+        .dynamic_names(true)
+        .with_named_vars(HashMap::from([
+            (String::from("ENV"), String::from("PROD")),
+            (String::from("DB_PROD"), String::from("p")),
+            (String::from("DB_STAGE"), String::from("s")),
+            (String::from("PORT_PROD"), String::from("80")),
+        ]))
+        .build();
+    let template = xpanda.parse("${DB_${ENV}} ${X:-${PORT_${ENV}}}").unwrap();
+
+    assert_eq!(
+        xpanda.unused_vars(&template),
+        vec![(String::from("DB_STAGE"), Source::Named(None))]
+    );
+}
+
+#[test]
+#[cfg(feature = "serialize")]
+fn serialize_template_param_texts() {
+    let xpanda = Xpanda::default();
+    let bytes = xpanda.parse("$A ${B:-$A}").unwrap().to_bytes();
+    let report = xpanda.report(&Template::from_bytes(&bytes).unwrap());
+    let texts = report
+        .params
+        .into_iter()
+        .map(|param| param.text)
+        .collect::<Vec<_>>();
+
+    assert_eq!(texts, vec!["$A", "${B:-$A}"]);
+}
+
+#[test]
+fn sources() {
+    std::env::set_var("XPANDA_TEST_SOURCES", "env");
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("XPANDA_TEST_SOURCES"), String::from("named"));
+    named_vars.insert(String::from("TARGET"), String::from("target"));
+    let mut aliases = HashMap::new();
+    aliases.insert(String::from("ALIAS"), String::from("TARGET"));
+    let xpanda = Xpanda::builder()
+        .with_env_vars()
+        .with_labeled_vars("file", named_vars)
+        .with_aliases(aliases)
+        .register_namespace("ns", |key: &str| Ok(Some(key.to_string())))
+        .access_policy(|name| match name {
+            "HIDDEN" => Access::Hide,
+            "DENIED" => Access::Deny,
+            _ => Access::Allow,
+        })
+        .build();
+    let template = Template::parse(
+        "$XPANDA_TEST_SOURCES $PATH $ALIAS ${ns:key} ${other:key} $HIDDEN $DENIED $2 $PATH",
+    )
+    .unwrap();
+
+    assert_eq!(
+        xpanda.sources(&template),
+        vec![
+            (
+                String::from("XPANDA_TEST_SOURCES"),
+                Some(Source::Named(Some(String::from("file"))))
+            ),
+            (String::from("PATH"), Some(Source::Env)),
+            (
+                String::from("ALIAS"),
+                Some(Source::Named(Some(String::from("file"))))
+            ),
+            (
+                String::from("ns:key"),
+                Some(Source::Namespace(String::from("ns")))
+            ),
+            (String::from("other:key"), None),
+            (String::from("HIDDEN"), None),
+            (String::from("DENIED"), None),
+            (String::from("2"), None),
+        ]
+    );
+}
+
+#[test]
+fn on_resolve() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&events);
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("A"), String::from("a"));
+    let xpanda = Xpanda::builder()
+        .front_matter(true)
+        .with_named_vars(named_vars)
+        .register_namespace("ns", |key: &str| Ok(Some(key.to_string())))
+        .on_resolve(move |event| {
+            recorded.lock().unwrap().push((
+                event.name.clone(),
+                event.position,
+                event.source.clone(),
+            ));
+        })
+        .build();
+
+    assert_eq!(
+        xpanda.expand("#! xpanda: default B=b\n$A ${UNSET:-$B} ${ns:key}"),
+        Ok(String::from("a b key"))
+    );
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            (
+                String::from("A"),
+                Position::new(23, 2, 1),
+                Source::Named(None)
+            ),
+            (
+                String::from("B"),
+                Position::new(35, 2, 13),
+                Source::FrontMatter
+            ),
+            (
+                String::from("ns:key"),
+                Position::new(39, 2, 17),
+                Source::Namespace(String::from("ns"))
+            ),
+        ]
+    );
+}
+
+fn record_provider(builder: Builder) -> (Xpanda, Arc<Mutex<Vec<String>>>) {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&calls);
+    let xpanda = builder
+        .register_namespace("ns", move |key: &str| {
+            recorded.lock().unwrap().push(key.to_string());
+            Ok(Some(key.to_string()))
+        })
+        .build();
+
+    (xpanda, calls)
+}
+
+#[test]
+fn evaluation_order() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("SET"), String::from("set"));
+    named_vars.insert(String::from("EMPTY"), String::new());
+    let (xpanda, calls) = record_provider(Xpanda::builder().with_named_vars(named_vars));
+
+    assert_eq!(
+        xpanda.expand(
+            "${ns:a} ${UNSET-${ns:b}} ${SET-${ns:x}} ${SET+${ns:c}} ${UNSET+${ns:x}} \
+             ${EMPTY:-${ns:d}} ${EMPTY:+${ns:x}} ${ns:e}"
+        ),
+        Ok(String::from("a b set c  d  e"))
+    );
+    assert_eq!(*calls.lock().unwrap(), vec!["a", "b", "c", "d", "e"]);
+}
+
+#[test]
+fn evaluation_order_nested() {
+    let (xpanda, calls) = record_provider(Xpanda::builder());
+
+    assert_eq!(
+        xpanda.expand("${A-${B-${ns:a}}} ${A-${B+${ns:x}}} ${ns:b}"),
+        Ok(String::from("a  b"))
+    );
+    assert_eq!(*calls.lock().unwrap(), vec!["a", "b"]);
+}
+
+#[test]
+fn evaluation_order_conditional() {
+    let named_vars = HashMap::from([(String::from("SET"), String::from("set"))]);
+    let builder = Xpanda::builder()
+        .conditionals(true)
+        .with_named_vars(named_vars);
+    let (xpanda, calls) = record_provider(builder);
+    let input = "${ns:a}${if SET}${ns:b}${if UNSET}${ns:x}${else}${ns:c}${end}${else}${ns:x}${end}\
+                 ${if UNSET}${ns:x}${else}${ns:d}${UNSET?}${ns:x}${end}";
+    let template = xpanda.parse(input).unwrap();
+
+    assert!(xpanda.render(&template).is_err());
+    assert_eq!(*calls.lock().unwrap(), vec!["a", "b", "c", "d"]);
+
+    calls.lock().unwrap().clear();
+    assert!(xpanda.render_compiled(&template.compile()).is_err());
+    assert_eq!(*calls.lock().unwrap(), vec!["a", "b", "c", "d"]);
+}
+
+#[test]
+fn evaluation_stops_at_first_error() {
+    let (xpanda, calls) = record_provider(Xpanda::builder());
+    assert!(xpanda.expand("${ns:a} ${UNSET?} ${ns:x}").is_err());
+    assert_eq!(*calls.lock().unwrap(), vec!["a"]);
+
+    let (xpanda, calls) = record_provider(Xpanda::builder().front_matter(true));
+    assert!(xpanda.expand("#! xpanda: require UNSET\n${ns:x}").is_err());
+    assert!(calls.lock().unwrap().is_empty());
+
+    let schema = Schema::new().var(VarSchema::new("UNSET").required(true));
+    let (xpanda, calls) = record_provider(Xpanda::builder().schema(schema));
+    assert!(xpanda.expand("${ns:x}").is_err());
+    assert!(calls.lock().unwrap().is_empty());
+}
+
+#[test]
+#[cfg(feature = "indirection")]
+fn evaluation_order_indirection() {
+    let resolved = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&resolved);
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("REF"), String::from("TARGET"));
+    named_vars.insert(String::from("TARGET"), String::from("target"));
+    let xpanda = Xpanda::builder()
+        .with_named_vars(named_vars)
+        .on_resolve(move |event| recorded.lock().unwrap().push(event.name.clone()))
+        .build();
+
+    assert_eq!(xpanda.expand("${!REF}"), Ok(String::from("target")));
+    assert_eq!(*resolved.lock().unwrap(), vec!["REF", "TARGET"]);
+}
+
+#[test]
+fn provider_metrics() {
+    let xpanda = Xpanda::builder()
+        .register_namespace("ns", |key: &str| match key {
+            "fail" => Err(String::from("failed")),
+            "unset" => Ok(None),
+            _ => Ok(Some(key.to_string())),
+        })
+        .register_namespace("unused", |_: &str| Ok(None))
+        .build();
+
+    assert_eq!(
+        xpanda.expand("${ns:a} ${ns:b} ${ns:unset}"),
+        Ok(String::from("a b "))
+    );
+    assert!(xpanda.expand("${ns:fail}").is_err());
+
+    let metrics = xpanda.provider_metrics();
+    assert_eq!(metrics.keys().collect::<Vec<_>>(), vec!["ns", "unused"]);
+    assert_eq!(metrics["ns"].lookups(), 4);
+    assert_eq!(metrics["ns"].hits(), 2);
+    assert_eq!(metrics["ns"].misses(), 1);
+    assert_eq!(metrics["ns"].failures(), 1);
+    assert_eq!(metrics["ns"].latency_buckets().last().unwrap().1, 4);
+    assert_eq!(metrics["unused"].lookups(), 0);
+    assert_eq!(metrics["unused"].latency_percentile(0.5), None);
+}
+
+#[test]
+fn provider_metrics_latency() {
+    let xpanda = Arc::new(
+        Xpanda::builder()
+            .register_namespace("slow", |key: &str| {
+                std::thread::sleep(std::time::Duration::from_millis(2));
+                Ok(Some(key.to_string()))
+            })
+            .build(),
+    );
+    let threads = (0..4)
+        .map(|_| {
+            let xpanda = Arc::clone(&xpanda);
+            std::thread::spawn(move || xpanda.expand("${slow:a}${slow:b}"))
+        })
+        .collect::<Vec<_>>();
+
+    for thread in threads {
+        assert_eq!(thread.join().unwrap(), Ok(String::from("ab")));
+    }
+
+    let metrics = &xpanda.provider_metrics()["slow"];
+    let median = metrics.latency_percentile(0.5).unwrap();
+    assert_eq!(metrics.lookups(), 8);
+    assert!(median >= std::time::Duration::from_millis(2));
+    assert!(median <= metrics.max_latency());
+    assert!(metrics.total_latency() >= std::time::Duration::from_millis(16));
+}
+
+#[test]
+fn expand_bytes() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::from("ä"));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+
+    assert_eq!(
+        xpanda.expand_bytes(b"\xFF$VAR\xFE ${UNSET:-\x80\x81} \xC3\xA4"),
+        Ok(b"\xFF\xC3\xA4\xFE \x80\x81 \xC3\xA4".to_vec())
+    );
+    assert_eq!(
+        xpanda.expand_bytes(b"\xFF$VAR\xFE ${UNSET:-\x80\x81} \xC3\xA4 ${"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from("Expected a matching '}' for the '${' opened"),
+            line: 1,
+            col: 23,
+            span: Some(Span::new(
+                Position::new(23, 1, 23),
+                Position::new(25, 1, 25)
+            )),
+        })
+    );
+}
+
+#[test]
+fn expand_bytes_reserved() {
+    assert_eq!(
+        Xpanda::default().expand_bytes("a\n\u{10FF05}".as_bytes()),
+        Err(Error::new(
+            ErrorKind::Syntax,
+            String::from("The character U+10FF05 is reserved for passing through invalid UTF-8"),
+            &Position::new(2, 2, 1),
+        ))
+    );
+}
+
+#[test]
+fn render_error_position() {
+    let xpanda = Xpanda::builder()
+        .no_unset(true)
+        .with_named_vars(HashMap::from([(String::from("N"), String::from("x"))]))
+        .build();
+    let template = xpanda.parse("a\nåä ${A?missing} ${B}").unwrap();
+    let compiled = template.clone().compile();
+    let error = |input: &str| xpanda.expand(input).unwrap_err();
+
+    assert_eq!(
+        xpanda.render(&template),
+        Err(Error::new(
+            ErrorKind::Required,
+            String::from("missing"),
+            &Position::new(7, 2, 4),
+        ))
+    );
+    assert_eq!(xpanda.render_compiled(&compiled), xpanda.render(&template));
+    assert_eq!(
+        error("a\n ${N@pad:3}").span.unwrap().start,
+        Position::new(3, 2, 2)
+    );
+    assert_eq!(error("${N} $B").span.unwrap().start, Position::new(5, 1, 6));
+    assert_eq!(
+        error("\n\n${ns:key}").span.unwrap().start,
+        Position::new(2, 3, 1)
+    );
+
+    let error = error("${A?missing} $N");
+    assert_eq!(
+        error.span,
+        Some(Span::new(Position::new(0, 1, 1), Position::new(0, 1, 1)))
+    );
+    assert_eq!(error.to_string(), "missing at line 1, column 1");
+}
+
+#[test]
+fn expand_lines() {
+    let positional_vars = vec![String::from("woop")];
+    let xpanda = Xpanda::builder()
+        .with_positional_vars(positional_vars)
+        .build();
+    let input = "$1\na ${\r\n${1?}${2?}\n$1";
+    let lines = xpanda.expand_lines(input.as_bytes()).collect::<Vec<_>>();
+
+    assert_eq!(
+        lines,
+        vec![
+            Ok(String::from("woop\n")),
+            Err(Error {
+                kind: ErrorKind::Syntax,
+                message: String::from("Expected identifier, found \"\r\""),
+                line: 2,
+                col: 6,
+                span: Some(Span::new(Position::new(7, 2, 5), Position::new(8, 2, 6))),
+            }),
+            Err(Error::new(
+                ErrorKind::Required,
+                String::from("'2' is unset"),
+                &Position::new(14, 3, 6),
+            )),
+            Ok(String::from("woop")),
+        ]
+    );
+}
+
+#[test]
+fn expand_lines_read_error() {
+    let xpanda = Xpanda::default();
+    let mut lines = xpanda.expand_lines(&b"a\nb\xFF\nc\n"[..]);
+
+    assert_eq!(lines.next(), Some(Ok(String::from("a\n"))));
+    assert_eq!(
+        lines.next(),
+        Some(Err(Error::new(
+            ErrorKind::Io,
+            String::from("Failed to read input: stream did not contain valid UTF-8"),
+            &Position::new(2, 2, 1),
+        )))
+    );
+    assert_eq!(lines.next(), None);
+    assert_eq!(lines.line_number(), 2);
+}
+
+#[test]
+fn expand_lines_max_line_bytes() {
+    let xpanda = Xpanda::default();
+    let mut lines = xpanda
+        .expand_lines("åä\nåäö\n".as_bytes())
+        .max_line_bytes(5);
+
+    assert_eq!(lines.next(), Some(Ok(String::from("åä\n"))));
+    assert_eq!(lines.line(), "åä\n");
+    assert_eq!(
+        lines
+            .next()
+            .map(|result| result.map_err(|error| (error.kind, error.message))),
+        Some(Err((
+            ErrorKind::TooLong,
+            String::from("Failed to read input: line exceeds the maximum length of 5 bytes")
+        )))
+    );
+    assert_eq!(lines.next(), None);
+}
+
+#[test]
+fn expand_stream() {
+    let xpanda = Xpanda::builder()
+        .with_positional_vars(vec![String::from("one")])
+        .with_named_vars(HashMap::from([
+            (String::from("A"), String::from("å")),
+            (String::from("LONG"), "woop ".repeat(10)),
+        ]))
+        .build();
+    let inputs = [
+        "",
+        "plain text without params",
+        "$A$A$A$A$A$A$A$A$A$A$A$A$A$A$A$A$A$A$A$A",
+        "åäö $A ${A} $1 $$ $$A $$$A ${UNSET:-åäö} $LONG${A}",
+        "text $$ text $$$$ ${1}${1}${1}${1}${1}${1}${1}${1}${1}${1}",
+        "first line $A\nsecond ${A:+$A} line\n\nlast $1 line",
+        "${UNSET:-${A:+${1}}} ${UNSET-x} $A_ $A-$1. åäö",
+    ];
+
+    for input in inputs {
+        let expected = xpanda.expand(input).unwrap();
+
+        for buffer_bytes in 20..=input.len() + 1 {
+            let chunks = xpanda
+                .expand_stream(input.as_bytes())
+                .buffer_bytes(buffer_bytes)
+                .collect::<Result<Vec<_>, _>>();
+
+            assert_eq!(chunks.map(|chunks| chunks.concat()), Ok(expected.clone()));
+        }
+    }
+}
+
+#[test]
+fn expand_stream_error_position() {
+    let xpanda = Xpanda::default();
+    let input = "a line\nåäö ${UNSET?} and ${A?}\nlast ${";
+    let mut chunks = xpanda.expand_stream(input.as_bytes()).buffer_bytes(12);
+    let mut errors = Vec::new();
+
+    for result in &mut chunks {
+        if let Err(error) = result {
+            errors.push(error);
+        }
+    }
+
+    assert_eq!(
+        errors,
+        vec![
+            Error::new(
+                ErrorKind::Required,
+                String::from("'UNSET' is unset"),
+                &Position::new(14, 2, 5),
+            ),
+            Error::new(
+                ErrorKind::Required,
+                String::from("'A' is unset"),
+                &Position::new(28, 2, 19),
+            ),
+            Error {
+                kind: ErrorKind::Syntax,
+                message: String::from("Expected a matching '}' for the '${' opened"),
+                line: 3,
+                col: 6,
+                span: Some(Span::new(Position::new(39, 3, 6), Position::new(41, 3, 8))),
+            },
+        ]
+    );
+    assert_eq!(chunks.chunk(), "last ${");
+    assert_eq!(chunks.chunk_start(), Position::new(34, 3, 1));
+}
+
+#[test]
+fn expand_stream_read_error() {
+    let xpanda = Xpanda::default();
+    let mut chunks = xpanda.expand_stream(&b"a\nb\xFF\nc\n"[..]);
+
+    assert_eq!(
+        chunks.next(),
+        Some(Err(Error::new(
+            ErrorKind::Io,
+            String::from("Failed to read input: stream did not contain valid UTF-8"),
+            &Position::new(0, 1, 1),
+        )))
+    );
+    assert_eq!(chunks.next(), None);
+}
+
+#[test]
+fn expand_stream_buffer_bytes() {
+    let xpanda = Xpanda::default();
+    let mut chunks = xpanda
+        .expand_stream("åäö\na ${UNSET:-toolong}".as_bytes())
+        .buffer_bytes(10);
+
+    assert_eq!(chunks.next(), Some(Ok(String::from("åäö\n"))));
+    assert_eq!(chunks.next(), Some(Ok(String::from("a "))));
+    assert_eq!(
+        chunks.next(),
+        Some(Err(Error::new(
+            ErrorKind::TooLong,
+            String::from("Failed to read input: param exceeds the maximum length of 10 bytes"),
+            &Position::new(9, 2, 3),
+        )))
+    );
+    assert_eq!(chunks.next(), None);
+}
+
+#[test]
+fn expand_stream_comment_mid_line() {
+    let xpanda = Xpanda::builder()
+        .comments(true)
+        .with_special_vars(HashMap::from([('#', String::from("3"))]))
+        .build();
+    let input = "$# c\nabcdef $# args\nnext\n";
+    let chunks = xpanda
+        .expand_stream(input.as_bytes())
+        .buffer_bytes(8)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+    assert_eq!(chunks.concat(), "abcdef 3 args\nnext\n");
+    assert_eq!(Ok(chunks.concat()), xpanda.expand(input));
+}
+
+#[test]
+fn render_compiled() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("SET"), String::from("set"));
+    named_vars.insert(String::from("EMPTY"), String::new());
+    let xpanda = Xpanda::builder()
+        .with_positional_vars(vec![String::from("one")])
+        .with_named_vars(named_vars)
+        .build();
+    let inputs = [
+        "",
+        "text $$ text",
+        "$1 ${2} $SET ${UNSET}",
+        "${SET:-a}b${UNSET:-c}d",
+        "${EMPTY-a} ${EMPTY:-b} ${EMPTY+c} ${EMPTY:+d}",
+        "${UNSET:-${SET:+${UNSET-a$$b}}}c",
+        "${UNSET:-${UNSET:-${UNSET:-deep}}}",
+        "${SET:+${UNSET:-x}} ${UNSET:+${SET}}",
+        "${SET@urlencode} ${SET@pad:5}",
+        "${SET?} ${UNSET:?custom}",
+    ];
+
+    for input in inputs {
+        let template = Template::parse(input).unwrap();
+        let expected = xpanda.render(&template);
+
+        assert_eq!(xpanda.render_compiled(&template.compile()), expected);
+    }
+}
+
+#[test]
+fn render_compiled_front_matter() {
+    let (xpanda, events) = record_unset(Xpanda::builder().front_matter(true));
+    let input = "#! xpanda: default A=a\n${A:-x} ${B:-$C} ${B:+y}";
+    let template = xpanda.parse(input).unwrap().compile();
+
+    assert_eq!(xpanda.render_compiled(&template), Ok(String::from("a  ")));
+    assert_eq!(
+        template.to_string(),
+        "#! xpanda: default A=a\n${A:-x} ${B:-${C}} ${B:+y}"
+    );
+
+    let events = events
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|event| (event.name.clone(), event.outcome))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        events,
+        vec![
+            (String::from("B"), UnsetOutcome::Default),
+            (String::from("C"), UnsetOutcome::Empty),
+            (String::from("B"), UnsetOutcome::Empty),
+        ]
+    );
+}
+
+#[test]
+fn expand_until_stable() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("A"), String::from("$B"));
+    named_vars.insert(String::from("B"), String::from("${C:-b}"));
+    named_vars.insert(String::from("SELF"), String::from("$SELF"));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+
+    assert_eq!(
+        xpanda.expand_until_stable("$A", 10),
+        Ok((String::from("b"), 3))
+    );
+    assert_eq!(
+        xpanda.expand_until_stable("$A", 1),
+        Ok((String::from("$B"), 1))
+    );
+    assert_eq!(
+        xpanda.expand_until_stable("$A", 0),
+        Ok((String::from("$A"), 0))
+    );
+    assert_eq!(
+        xpanda.expand_until_stable("text", 10),
+        Ok((String::from("text"), 0))
+    );
+    assert_eq!(
+        xpanda.expand_until_stable("$SELF", 10),
+        Ok((String::from("$SELF"), 0))
+    );
+    assert_eq!(
+        xpanda.expand_until_stable("$$A", 10),
+        Ok((String::from("b"), 4))
+    );
+}
+
+#[test]
+fn expand_until_stable_error() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("A"), String::from("${B"));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+
+    assert_eq!(xpanda.expand_until_stable("x $A", 10).unwrap_err().col, 3);
+    assert_eq!(
+        xpanda.expand_until_stable("$$$$", 10).unwrap_err().kind,
+        ErrorKind::Syntax
+    );
+    assert_eq!(
+        xpanda.expand_until_stable("x $A", 1),
+        Ok((String::from("x ${B"), 1))
+    );
+}
+
+#[test]
+fn lint_unreachable_branch() {
+    let xpanda = Xpanda::builder().front_matter(true).build();
+    let input = "#! xpanda: default B=b\n${A:+${A:-x}} ${A-${A+y}} ${A+${A:-z}} $${A} ${B-w}";
+    let lints = Linter::new().lint(&xpanda, input).unwrap();
+    let found = lints
+        .iter()
+        .map(|lint| (lint.rule, lint.position.col))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        found,
+        vec![
+            (Rule::UnreachableBranch, 6),
+            (Rule::UnreachableBranch, 19),
+            (Rule::UnreachableBranch, 46),
+        ]
+    );
+}
+
+#[test]
+fn lint_digit_prefixed_name() {
+    let xpanda = Xpanda::builder().lenient_identifiers(true).build();
+    let lints = Linter::new().lint(&xpanda, "$1abc ${12} ${1}abc").unwrap();
+
+    assert_eq!(lints.len(), 1);
+    assert_eq!(lints[0].rule, Rule::DigitPrefixedName);
+    assert_eq!(
+        lints[0].message,
+        "'1abc' is read as a named variable, write '${1}abc' for the positional variable 1 \
+         followed by text"
+    );
+}
+
+#[test]
+fn lint_suppression() {
+    let linter = Linter::new()
+        .severity(Rule::UnbracedVariable, Severity::Warning)
+        .optional_vars(["A"]);
+    let input = "<!-- xpanda-lint: allow all -->\n$A\n$A\n$A # xpanda-lint: allow missing-default";
+    let lints = linter.lint(&Xpanda::default(), input).unwrap();
+    let found = lints
+        .iter()
+        .map(|lint| (lint.rule, lint.position.line))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        found,
+        vec![
+            (Rule::UnbracedVariable, 3),
+            (Rule::MissingDefault, 3),
+            (Rule::UnbracedVariable, 4),
+        ]
+    );
+}
+
+#[test]
+fn lint_syntax_error() {
+    let error = Linter::new().lint(&Xpanda::default(), "${A").unwrap_err();
+
+    assert_eq!(error.kind, ErrorKind::Syntax);
+}
+
+#[test]
+fn lint_spaced_brace() {
+    let lints = Linter::new()
+        .lint(&Xpanda::default(), "$$ {A} $ {B} ${C")
+        .unwrap();
+
+    assert_eq!(lints.len(), 1);
+    assert_eq!(lints[0].rule, Rule::SpacedBrace);
+    assert_eq!(lints[0].severity, Severity::Error);
+    assert_eq!(lints[0].position, Position::new(7, 1, 8));
+    assert_eq!(lints[0].fix.as_ref().unwrap().span.start.col, 9);
+}
+
+#[test]
+fn fix_multiple_passes() {
+    let xpanda = Xpanda::builder().lenient_identifiers(true).build();
+    let linter = Linter::new().severity(Rule::UnbracedVariable, Severity::Warning);
+
+    assert_eq!(
+        linter.fix(&xpanda, "$ {A} $1abc $B.txt ${C}"),
+        Ok((String::from("${A} ${1}abc ${B}.txt ${C}"), 3))
+    );
+    assert_eq!(linter.fix(&xpanda, "${A}"), Ok((String::from("${A}"), 0)));
+    assert_eq!(
+        linter.fix(&xpanda, "$ {A} ${B").unwrap_err().kind,
+        ErrorKind::Syntax
+    );
+}
+
+#[test]
+fn shared_across_threads() {
+    fn assert_send_sync<T: Send + Sync>(value: T) -> T {
+        value
+    }
+
+    let xpanda = assert_send_sync(
+        Xpanda::builder()
+            .with_positional_vars(vec![String::from("woop")])
+            .build(),
+    );
+    let outputs = std::thread::scope(|scope| {
+        (0..4)
+            .map(|index| {
+                let xpanda = &xpanda;
+                scope.spawn(move || xpanda.expand(&format!("$1 {index} ${{2?missing}}")))
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|thread| thread.join().unwrap().unwrap_err().message)
+            .collect::<Vec<_>>()
+    });
+
+    assert_eq!(outputs, vec![String::from("missing"); 4]);
+    assert_eq!(
+        assert_send_sync(CachedXpanda::new(xpanda)).expand("$1"),
+        Ok(String::from("woop"))
+    );
+}
+
+#[test]
+#[cfg(feature = "expr")]
+fn expr_arithmetic() {
+    let xpanda = Xpanda::builder()
+        .expressions(true)
+        .with_named_vars(HashMap::from([
+            (String::from("CPU"), String::from("6")),
+            (String::from("NAME"), String::from("app")),
+        ]))
+        .build();
+
+    assert_eq!(
+        xpanda.expand("${REPLICAS:-=max(2, $CPU / 2)}"),
+        Ok(String::from("3"))
+    );
+    assert_eq!(
+        xpanda.expand("${X-=(1 + 2) * -$CPU % 5}"),
+        Ok(String::from("-3"))
+    );
+    assert_eq!(xpanda.expand("${X-=7 / 2}"), Ok(String::from("3.5")));
+    assert_eq!(
+        xpanda.expand("${X-=floor(7 / 2)} ${X-=round(2.5)} ${X-=ceil(0.1)}"),
+        Ok(String::from("3 3 1"))
+    );
+    assert_eq!(
+        xpanda.expand("${CPU:+=$CPU > 4 ? 'big' : 'small'}"),
+        Ok(String::from("big"))
+    );
+    assert_eq!(
+        xpanda.expand("${X-=concat($NAME, '-', $CPU * 2)} ${X-=$NAME == \"app\" && !0}"),
+        Ok(String::from("app-12 1"))
+    );
+}
+
+#[test]
+#[cfg(feature = "expr")]
+fn expr_error() {
+    let xpanda = Xpanda::builder()
+        .expressions(true)
+        .with_named_vars(HashMap::from([(String::from("A"), String::from("a"))]))
+        .build();
+
+    let error = xpanda.expand("${X-=$A * 2}").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::InvalidValue);
+    assert_eq!(error.message, "Expected a number in expression, found 'a'");
+    assert_eq!((error.line, error.col), (1, 5));
+
+    let error = xpanda.expand("a\nb ${X-=1 / 0}").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::InvalidValue);
+    assert_eq!(error.message, "Division by zero in expression");
+    assert_eq!((error.line, error.col), (2, 7));
+    assert_eq!(
+        error.span,
+        Some(Span::new(Position::new(8, 2, 7), Position::new(8, 2, 7)))
+    );
+
+    let error = xpanda.expand("${X-=1 +}").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Syntax);
+    assert_eq!(error.message, "Unexpected end of expression");
+    assert_eq!(error.col, 9);
+
+    let error = xpanda.expand("${X-=pow(2)}").unwrap_err();
+    assert_eq!(error.message, "Unknown function 'pow'");
+    assert_eq!(error.col, 6);
+
+    let error = xpanda.expand("${X-=max()}").unwrap_err();
+    assert_eq!(
+        error.message,
+        "Function 'max' expects at least 1 argument(s), found 0"
+    );
+
+    let input = format!("${{X-={}1{}}}", "(".repeat(100), ")".repeat(100));
+    let error = xpanda.expand(&input).unwrap_err();
+    assert_eq!(
+        error.message,
+        "Expressions can't be nested more than 64 levels deep"
+    );
+
+    let input = format!("${{X-=1{}}}", "+1".repeat(2000));
+    let error = xpanda.expand(&input).unwrap_err();
+    assert_eq!(
+        error.message,
+        "Expressions can't be made up of more than 1024 parts"
+    );
+}
+
+#[test]
+#[cfg(feature = "expr")]
+fn expr_template() {
+    let xpanda = Xpanda::builder()
+        .expressions(true)
+        .with_named_vars(HashMap::from([
+            (String::from("B"), String::from("1")),
+            (String::from("C"), String::from("2")),
+            (String::from("E"), String::from("e")),
+        ]))
+        .build();
+    let template = xpanda
+        .parse("${A:-=$B + $C} ${D-$=literal} ${E+='$}'}")
+        .unwrap();
+    let formatted = template.to_string();
+    let compiled = template.clone().compile();
+
+    assert_eq!(template.variables(), vec!["A", "B", "C", "D", "E"]);
+    assert_eq!(formatted, "${A:-=$B + $C} ${D-$=literal} ${E+='$}'}");
+    assert_eq!(xpanda.parse(&formatted).unwrap(), template);
+
+    assert_eq!(xpanda.render(&template), Ok(String::from("3 =literal }")));
+    assert_eq!(
+        xpanda.render_compiled(&compiled),
+        Ok(String::from("3 =literal }"))
+    );
+}
+
+#[test]
+fn expr_disabled() {
+    let xpanda = Xpanda::default();
+    let template = Template::parse("${X-=1 + 1}").unwrap();
+
+    assert_eq!(xpanda.expand("${X-=1 + 1}"), Ok(String::from("=1 + 1")));
+    assert_eq!(template.to_string(), "${X-=1 + 1}");
+}
+
+#[test]
+fn glob_pattern() {
+    let pattern = GlobEngine.compile("[a-c]?*.t[!x]t").unwrap();
+
+    assert!(pattern.is_match("ab.tyt"));
+    assert!(pattern.is_match("c1.2.tat"));
+    assert!(!pattern.is_match("ab.txt"));
+    assert!(!pattern.is_match("d1.tyt"));
+    assert!(!pattern.is_match("a.tyt"));
+    assert!(!pattern.is_match("ab.tyt.bak"));
+    assert!(GlobEngine.compile(r"\*[]]").unwrap().is_match("*]"));
+    assert!(GlobEngine.compile("[a-").is_err());
+}
+
+#[test]
+fn glob_pattern_prefix_suffix() {
+    let pattern = GlobEngine.compile("*/").unwrap();
+    let path = "usr/local/bin";
+
+    assert_eq!(pattern.match_prefix(path, false), Some(4));
+    assert_eq!(pattern.match_prefix(path, true), Some(10));
+    assert_eq!(pattern.match_suffix(path, false), None);
+
+    let pattern = GlobEngine.compile(".*").unwrap();
+    let file = "archive.tar.gz";
+
+    assert_eq!(pattern.match_suffix(file, false), Some(11));
+    assert_eq!(pattern.match_suffix(file, true), Some(7));
+    assert_eq!(pattern.match_prefix(file, false), None);
+}
+
+#[test]
+fn glob_pattern_replace() {
+    let pattern = GlobEngine.compile("o*").unwrap();
+
+    assert_eq!(pattern.find("foo boo", 0), Some(1..7));
+    assert_eq!(pattern.replace("foo boo", "0", false), "f0");
+
+    let pattern = GlobEngine.compile("o").unwrap();
+
+    assert_eq!(pattern.replace("foo boo", "0", false), "f0o boo");
+    assert_eq!(pattern.replace("foo boo", "0", true), "f00 b00");
+    assert_eq!(pattern.replace("åoå", "ä", true), "åäå");
+    assert_eq!(
+        GlobEngine.compile("").unwrap().replace("ab", "-", true),
+        "-a-b-"
+    );
+}
+
+#[test]
+fn glob_pattern_replace_captures() {
+    let pattern = GlobEngine.compile("*:*").unwrap();
+
+    assert_eq!(
+        pattern.captures("registry:5000/nginx:1.25", 0..24),
+        vec![Some(0..24), Some(0..19), Some(20..24)]
+    );
+    assert_eq!(
+        pattern.replace("registry:5000/nginx:1.25", "\\1:latest", false),
+        "registry:5000/nginx:latest"
+    );
+
+    let pattern = GlobEngine.compile("v[0-9]?").unwrap();
+
+    assert_eq!(pattern.replace("v12 v3x", "<&>", true), "<v12> <v3x>");
+    assert_eq!(pattern.replace("v12", "\\2\\1\\3", true), "21");
+    assert_eq!(
+        pattern.replace("v12", "\\& \\\\ \\x \\", true),
+        "& \\ \\x \\"
+    );
+    assert_eq!(
+        GlobEngine
+            .compile("å*")
+            .unwrap()
+            .replace("xåäö", "[\\1]", true),
+        "x[äö]"
+    );
+}
+
+#[test]
+#[cfg(feature = "regex")]
+fn regex_pattern_replace_captures() {
+    use xpanda::RegexEngine;
+
+    let pattern = RegexEngine.compile("([a-z]+)-([0-9]+)?").unwrap();
+
+    assert_eq!(pattern.replace("a-1 b-", "\\2=\\1", true), "1=a =b");
+    assert_eq!(pattern.replace("a-1", "&&\\9", true), "a-1a-1");
+    assert_eq!(
+        RegexEngine
+            .compile("^a")
+            .unwrap()
+            .replace("aa", "[&]", true),
+        "[a]a"
+    );
+}
+
+#[test]
+#[cfg(feature = "regex")]
+fn regex_pattern() {
+    use xpanda::RegexEngine;
+
+    let pattern = RegexEngine.compile("[0-9]+").unwrap();
+
+    assert!(pattern.is_match("42"));
+    assert!(!pattern.is_match("v42"));
+    assert_eq!(pattern.match_prefix("42abc", true), Some(2));
+    assert_eq!(pattern.match_suffix("v1.22", false), Some(4));
+    assert_eq!(pattern.find("v1.22", 2), Some(3..5));
+    assert_eq!(pattern.replace("v1.22", "x", true), "vx.x");
+    assert_eq!(
+        RegexEngine
+            .compile("a|ab")
+            .unwrap()
+            .replace("ab", "x", false),
+        "xb"
+    );
+    assert!(RegexEngine.compile("(").is_err());
+}
+
+#[test]
+fn pattern_operators() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::from("src/main.tar.gz"));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+
+    assert_eq!(xpanda.expand("${VAR#*/}"), Ok(String::from("main.tar.gz")));
+    assert_eq!(xpanda.expand("${VAR#*.}"), Ok(String::from("tar.gz")));
+    assert_eq!(xpanda.expand("${VAR##*.}"), Ok(String::from("gz")));
+    assert_eq!(xpanda.expand("${VAR%.*}"), Ok(String::from("src/main.tar")));
+    assert_eq!(xpanda.expand("${VAR%%.*}"), Ok(String::from("src/main")));
+    assert_eq!(
+        xpanda.expand("${VAR/.tar}"),
+        Ok(String::from("src/main.gz"))
+    );
+    assert_eq!(
+        xpanda.expand("${VAR//[.\\/]/-}"),
+        Ok(String::from("src-main-tar-gz"))
+    );
+    assert_eq!(
+        xpanda.expand("${VAR#x*} ${VAR%x} ${VAR/x/y} ${VAR//}"),
+        Ok(String::from(
+            "src/main.tar.gz src/main.tar.gz src/main.tar.gz src/main.tar.gz"
+        ))
+    );
+    assert_eq!(
+        xpanda.expand("${UNSET#*} ${UNSET/a/b}"),
+        Ok(String::from(" "))
+    );
+    assert_eq!(xpanda.expand("${UNSET:-#}"), Ok(String::from("#")));
+    assert_eq!(
+        xpanda.expand("${VAR#[a-}").map_err(|error| error.kind),
+        Err(ErrorKind::Syntax)
+    );
+}
+
+#[test]
+fn pattern_operators_replace_captures() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(
+        String::from("IMAGE"),
+        String::from("registry:5000/nginx:1.25"),
+    );
+    named_vars.insert(String::from("PATH"), String::from("src/main.rs"));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+
+    assert_eq!(
+        xpanda.expand("${IMAGE/*:*/\\1:latest}"),
+        Ok(String::from("registry:5000/nginx:latest"))
+    );
+    assert_eq!(
+        xpanda.expand("${PATH/*\\/*/\\2 in \\1}"),
+        Ok(String::from("main.rs in src"))
+    );
+    assert_eq!(
+        xpanda.expand("${PATH//[a-z]/<&>}"),
+        Ok(String::from("<s><r><c>/<m><a><i><n>.<r><s>"))
+    );
+    assert_eq!(
+        xpanda.expand("${PATH/main/\\& \\\\ \\3}"),
+        Ok(String::from("src/& \\ .rs"))
+    );
+}
+
+#[test]
+#[cfg(feature = "regex")]
+fn regex_pattern_operators_replace_captures() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("IMAGE"), String::from("nginx:1.25"));
+    let xpanda = Xpanda::builder().with_named_vars(named_vars).build();
+
+    assert_eq!(
+        xpanda.expand("${IMAGE/~([a-z]+):([0-9]+)\\.([0-9]+)~/\\1:\\2 (&)}"),
+        Ok(String::from("nginx:1 (nginx:1.25)"))
+    );
+    assert_eq!(
+        xpanda.expand("${IMAGE//~[0-9]+~/<&>}"),
+        Ok(String::from("nginx:<1>.<25>"))
+    );
+}
+
+#[test]
+fn pattern_operators_display() {
+    let xpanda = Xpanda::default();
+
+    for input in [
+        "${VAR#*/}",
+        "${VAR##*/}",
+        "${VAR%.*}",
+        "${VAR%%.*}",
+        "${VAR/a\\/b/$}}",
+        "${VAR//a/}",
+    ] {
+        assert_eq!(xpanda.parse(input).unwrap().to_string(), input);
+    }
+
+    let xpanda = Xpanda::builder().quoting(true).build();
+    let template = xpanda.parse("${VAR#'#'*} ${VAR%%\"%\"}").unwrap();
+
+    assert_eq!(template.to_string(), "${VAR#\"#*\"} ${VAR%%%}");
+    assert_eq!(xpanda.parse(&template.to_string()), Ok(template));
+}
+
+#[test]
+fn pattern_operators_engine() {
+    #[derive(Debug)]
+    struct Literal(String);
+
+    impl Pattern for Literal {
+        fn is_match(&self, text: &str) -> bool {
+            text == self.0
+        }
+    }
+
+    struct LiteralEngine;
+
+    impl PatternEngine for LiteralEngine {
+        fn compile(&self, pattern: &str) -> Result<Box<dyn Pattern>, String> {
+            Ok(Box::new(Literal(pattern.to_string())))
+        }
+    }
+
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::from("a*b*c"));
+    let xpanda = Xpanda::builder()
+        .with_named_vars(named_vars)
+        .pattern_engine(LiteralEngine)
+        .build();
+
+    assert_eq!(xpanda.expand("${VAR//*/+}"), Ok(String::from("a+b+c")));
+    assert_eq!(xpanda.expand("${VAR#a*}"), Ok(String::from("b*c")));
+}
+
+#[test]
+#[cfg(feature = "regex")]
+fn regex_pattern_operators() {
+    let mut named_vars = HashMap::new();
+    named_vars.insert(String::from("VAR"), String::from("v1.22.3"));
+    let xpanda = Xpanda::builder()
+        .with_named_vars(named_vars)
+        .pattern_engine(GlobEngine)
+        .build();
+
+    assert_eq!(
+        xpanda.expand("${VAR//~[0-9]+~/N}"),
+        Ok(String::from("vN.N.N"))
+    );
+    assert_eq!(xpanda.expand("${VAR##~.*\\.~}"), Ok(String::from("3")));
+    assert_eq!(
+        xpanda.expand("${VAR%~\\.[0-9]+~}"),
+        Ok(String::from("v1.22"))
+    );
+    assert_eq!(
+        xpanda.expand("${VAR/~(~/x}").map_err(|error| error.kind),
+        Err(ErrorKind::Syntax)
+    );
+}
+
+#[test]
+fn template_required_variables() {
+    let template = Xpanda::builder()
+        .front_matter(true)
+        .build()
+        .parse("#! xpanda: require Z\n#! xpanda: default B=b\n$A $B ${C:-$D} $0 ${env:HOME} $Z")
+        .unwrap();
+
+    assert_eq!(template.required_variables(), vec!["Z", "A"]);
+    assert_eq!(
+        template.optional_variables(),
+        vec!["B", "C", "D", "0", "env:HOME"]
+    );
+}
+
+#[test]
+fn template_bind() {
+    let template = Template::parse("a=$A\nb=${B:?missing} c=${C:-$2}").unwrap();
+
+    let error = template
+        .bind(HashMap::from([(String::from("B"), String::from("b"))]))
+        .unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Required);
+    assert_eq!(error.message, "Unbound required variable(s) A, 2");
+    assert_eq!((error.line, error.col), (1, 3));
+
+    let error = template.bind(HashMap::new()).unwrap_err();
+    assert_eq!(error.message, "Unbound required variable(s) A, B, 2");
+
+    let error = template
+        .bind(HashMap::from([
+            (String::from("A"), String::from("a")),
+            (String::from("B"), String::from("b")),
+        ]))
+        .unwrap_err();
+    assert_eq!(error.message, "Unbound required variable(s) 2");
+    assert_eq!((error.line, error.col), (2, 24));
+
+    let bound = template.bind(HashMap::from([
+        (String::from("A"), String::from("a")),
+        (String::from("B"), String::from("b")),
+        (String::from("C"), String::from("c")),
+    ]));
+    assert_eq!(bound.unwrap().render(), Ok(String::from("a=a\nb=b c=c")));
+
+    let vars = HashMap::from([
+        (String::from("A"), String::from("a")),
+        (String::from("B"), String::from("b")),
+        (String::from("2"), String::from("two")),
+    ]);
+    let bound = template.bind(vars).unwrap();
+    assert_eq!(bound.render(), Ok(String::from("a=a\nb=b c=two")));
+}
+
+#[test]
+fn template_bind_nested() {
+    let xpanda = Xpanda::builder().conditionals(true).build();
+    let template = xpanda
+        .parse("${if A}$B${else}${C:+$D}${end} ${E:-${F:?no F}}")
+        .unwrap();
+    let bind = |vars: &[(&str, &str)]| {
+        let vars = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+
+        xpanda.bind(&template, vars).map_err(|error| error.message)
+    };
+
+    assert_eq!(template.required_variables(), Vec::<String>::new());
+    assert_eq!(
+        bind(&[("A", "a")]).unwrap_err(),
+        "Unbound required variable(s) B, F"
+    );
+    assert_eq!(
+        bind(&[("A", ""), ("C", "c"), ("E", "e")]).unwrap_err(),
+        "Unbound required variable(s) D"
+    );
+    assert_eq!(
+        bind(&[("A", "a"), ("B", "b"), ("E", "")]).unwrap_err(),
+        "Unbound required variable(s) F"
+    );
+    assert_eq!(
+        bind(&[("C", "c"), ("D", "d"), ("F", "f")])
+            .unwrap()
+            .render(),
+        Ok(String::from("d f"))
+    );
+}
+
+#[test]
+fn xpanda_bind() {
+    let xpanda = Xpanda::builder()
+        .no_empty(true)
+        .schema(Schema::new().var(VarSchema::new("PORT").one_of(["80", "443"])))
+        .with_named_vars(HashMap::from([(String::from("HOST"), String::from("a"))]))
+        .with_positional_vars(vec![String::from("one"), String::from("two")])
+        .build();
+    let template = Template::parse("$HOST:$PORT $1${2:-x}").unwrap();
+
+    let error = xpanda.bind(&template, HashMap::new()).unwrap_err();
+    assert_eq!(error.message, "Unbound required variable(s) PORT");
+
+    let vars = HashMap::from([(String::from("PORT"), String::from("8080"))]);
+    let error = xpanda.bind(&template, vars).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::InvalidValue);
+
+    let vars = HashMap::from([(String::from("PORT"), String::from("80"))]);
+    let bound = xpanda.bind(&template, vars).unwrap();
+    assert_eq!(bound.render(), Ok(String::from("a:80 onetwo")));
+
+    let vars = HashMap::from([
+        (String::from("PORT"), String::from("80")),
+        (String::from("1"), String::from("uno")),
+    ]);
+    let bound = xpanda.bind(&template, vars).unwrap();
+    assert_eq!(bound.render(), Ok(String::from("a:80 unox")));
+
+    let vars = HashMap::from([
+        (String::from("PORT"), String::from("80")),
+        (String::from("HOST"), String::new()),
+    ]);
+    let bound = xpanda.bind(&template, vars).unwrap();
+    assert_eq!(bound.render().unwrap_err().kind, ErrorKind::Unset);
+}
+
+#[test]
+fn template_simplify() {
+    let vars = HashMap::from([
+        (String::from("A"), String::from("a")),
+        (String::from("EMPTY"), String::new()),
+        (String::from("PRICE"), String::from("$5")),
+        (String::from("1"), String::from("one")),
+    ]);
+    let cases = [
+        ("text $$ $A $1 $2 $U", "text $$ a one ${2} ${U}"),
+        (
+            "${A:-$U} ${EMPTY:-$U} ${EMPTY-$U} ${U:-$A}",
+            "a ${U}  ${U:-a}",
+        ),
+        (
+            "${A:+$U} ${EMPTY:+$U} ${EMPTY+$U} ${U:+${A}}",
+            "${U}  ${U} ${U:+a}",
+        ),
+        ("${A?} ${U?$A is ${EMPTY:-unset}}", "a ${U?a is unset}"),
+        ("${U:-${U:-${EMPTY:-$PRICE}}}", "${U:-${U:-$$5}}"),
+        ("$PRICE ${U-$PRICE}", "$$5 ${U-$$5}"),
+    ];
+
+    for (input, expected) in cases {
+        let template = Template::parse(input).unwrap();
+
+        assert_eq!(
+            template.simplify(&vars),
+            Ok(String::from(expected)),
+            "{input}"
+        );
+    }
+}
+
+#[test]
+fn template_simplify_render() {
+    let input = "${A:-$B} ${B:+${C:-$A}} ${C?} $1 ${2:-$A}";
+    let vars = HashMap::from([
+        (String::from("A"), String::from("a")),
+        (String::from("B"), String::from("b$")),
+        (String::from("C"), String::from("c")),
+        (String::from("1"), String::from("one")),
+        (String::from("2"), String::new()),
+    ]);
+    let expected = Template::parse(input)
+        .unwrap()
+        .bind(vars.clone())
+        .unwrap()
+        .render();
+
+    for (name, value) in &vars {
+        let known = HashMap::from([(name.clone(), value.clone())]);
+        let simplified = Template::parse(input).unwrap().simplify(&known).unwrap();
+        let rendered = Template::parse(&simplified)
+            .unwrap()
+            .bind(vars.clone())
+            .unwrap()
+            .render();
+
+        assert_eq!(rendered, expected, "{simplified}");
+    }
+}
+
+#[test]
+fn template_simplify_remaining_vars() {
+    let mut inputs = vec![
+        "${A:-$E}",
+        "${A-$E}x",
+        "${E:+$E}",
+        "${U:-${A:-$E}} ${E:-$A}",
+        "${A:-${U:-$E}}",
+    ];
+    let mut known_names = vec![vec!["E"], vec!["A", "E"], vec!["U", "E"]];
+
+    if cfg!(feature = "indirection") {
+        inputs.extend(["${!R}", "${A:-${!R}} ${!R}"]);
+        known_names.extend([vec!["R"], vec!["R", "B"]]);
+    }
+
+    let vars = HashMap::from([
+        (String::from("A"), String::from("a")),
+        (String::from("E"), String::new()),
+        (String::from("R"), String::from("B")),
+        (String::from("B"), String::from("b")),
+    ]);
+
+    for input in inputs {
+        let template = Template::parse(input).unwrap();
+        let expected = template.bind(vars.clone()).unwrap().render();
+
+        for names in &known_names {
+            let (known, remaining) = vars
+                .clone()
+                .into_iter()
+                .partition::<HashMap<_, _>, _>(|(name, _)| names.contains(&name.as_str()));
+            let simplified = template.simplify(&known).unwrap();
+            let rendered = Template::parse(&simplified)
+                .unwrap()
+                .bind(remaining)
+                .unwrap()
+                .render();
+
+            assert_eq!(rendered, expected, "{input} simplified as {simplified}");
+        }
+    }
+}
+
+#[test]
+fn template_simplify_raw_text() {
+    let vars = HashMap::from([(String::from("E"), String::new())]);
+    let template = Template::parse("${A:-a$ b} ${B?oh $$ no} $$ ${E:-c$ d} ${E:-$$$F}").unwrap();
+
+    assert_eq!(
+        template.simplify(&vars),
+        Ok(String::from("${A:-a$ b} ${B?oh $$ no} $$ c$$ d $$$$F"))
+    );
+
+    let xpanda = Xpanda::builder().quoting(true).build();
+    let template = xpanda.parse(r#"${A:-"a$b"} ${E:-'c d'}"#).unwrap();
+
+    assert_eq!(
+        template.simplify(&vars),
+        Ok(String::from(r#"${A:-"a$b"} c d"#))
+    );
+}
+
+#[test]
+fn template_simplify_error() {
+    let template = Template::parse("${A:?} ${B:?is empty}").unwrap();
+    let vars = HashMap::from([(String::from("B"), String::new())]);
+    let error = template.simplify(&vars).unwrap_err();
+
+    assert_eq!(error.kind, ErrorKind::Required);
+    assert_eq!(error.message, "is empty");
+
+    // Only fails when rendering if the default value is used then
+    let template = Template::parse("${A:-${B:?is empty}} ${B:-${A-x}}").unwrap();
+    let simplified = template.simplify(&vars).unwrap();
+    let simplified = Template::parse(&simplified).unwrap();
+
+    assert_eq!(simplified.to_string(), "${A:-${B:?is empty}} ${A-x}");
+    assert_eq!(
+        simplified
+            .bind(HashMap::from([(String::from("A"), String::from("a"))]))
+            .unwrap()
+            .render(),
+        Ok(String::from("a a"))
+    );
+    assert_eq!(
+        simplified
+            .bind(HashMap::from([(String::from("B"), String::new())]))
+            .unwrap()
+            .render()
+            .unwrap_err()
+            .message,
+        "is empty"
+    );
+}
+
+#[test]
+fn template_simplify_syntax() {
+    let xpanda = Xpanda::builder()
+        .conditionals(true)
+        .dynamic_names(true)
+        .build();
+    let vars = HashMap::from([
+        (String::from("ENV"), String::from("prod")),
+        (String::from("DEBUG"), String::new()),
+        (
+            String::from("URL_prod"),
+            String::from("https://example.com"),
+        ),
+    ]);
+    let template = xpanda
+        .parse("${if DEBUG}debug${else}${URL_${ENV}}${end} ${if U}\n${U_${ENV}}${end} ${URL_$U}")
+        .unwrap();
+
+    assert_eq!(
+        template.simplify(&vars),
+        Ok(String::from(
+            "https://example.com ${if U}${U_prod}${end} ${URL_${U}}"
+        ))
+    );
+
+    let vars = HashMap::from([(String::from("ENV"), String::from("a-b"))]);
+    let error = template.simplify(&vars).unwrap_err();
+
+    assert_eq!(error.kind, ErrorKind::InvalidValue);
+}
+
+#[test]
+#[cfg(all(
+    feature = "case-modifiers",
+    feature = "length",
+    feature = "indirection"
+))]
+fn template_simplify_params() {
+    let vars = HashMap::from([
+        (String::from("A"), String::from("abc")),
+        (String::from("REF"), String::from("A")),
+        (String::from("UREF"), String::from("U")),
+    ]);
+    let template = Template::parse("${A^^} ${#A} ${!REF} ${!UREF} ${#} ${U,,} ${#U}").unwrap();
+
+    assert_eq!(
+        template.simplify(&vars),
+        Ok(String::from("ABC 3 abc ${U} ${#} ${U,,} ${#U}"))
+    );
+}
+
+#[test]
+#[cfg(feature = "expr")]
+fn template_simplify_expr() {
+    let vars = HashMap::from([
+        (String::from("B"), String::from("2")),
+        (String::from("C"), String::new()),
+    ]);
+    let xpanda = Xpanda::builder().expressions(true).build();
+    let template = xpanda
+        .parse("${A:-=$B * 3} ${A:-=$B * $D} ${C:-=$D + 1}")
+        .unwrap();
+
+    assert_eq!(
+        template.simplify(&vars),
+        Ok(String::from("${A:-6} ${A:-=$B * $D} ${C:-=$D + 1}"))
+    );
+}
+
+#[test]
+fn template_concat() {
+    let xpanda = Xpanda::builder()
+        .front_matter(true)
+        .with_named_vars(HashMap::from([(String::from("A"), String::from("a"))]))
+        .build();
+    let first = xpanda.parse("#! xpanda: default B=1\n$A ").unwrap();
+    let second = xpanda
+        .parse("#! xpanda: require A; default B=2; default C=3\n$B $C")
+        .unwrap();
+    let template = Template::concat(&[first, second]);
+
+    assert_eq!(xpanda.render(&template), Ok(String::from("a 1 3")));
+    assert_eq!(
+        template.to_string(),
+        "#! xpanda: default B=1\n#! xpanda: require A; default B=2; default C=3\n${A} ${B} ${C}"
+    );
+    assert_eq!(template.front_matter().unwrap().required, vec!["A"]);
+
+    let positions = template
+        .references()
+        .into_iter()
+        .map(|reference| reference.position)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        positions,
+        vec![
+            Position::new(23, 2, 1),
+            Position::new(47, 2, 1),
+            Position::new(50, 2, 4)
+        ]
+    );
+
+    let texts = xpanda
+        .report(&template)
+        .params
+        .into_iter()
+        .map(|param| param.text)
+        .collect::<Vec<_>>();
+    assert_eq!(texts, vec!["$A", "$B", "$C"]);
+
+    assert_eq!(Template::concat(&[]), Template::parse("").unwrap());
+}
+
+#[test]
+fn template_embed() {
+    let xpanda = Xpanda::builder()
+        .conditionals(true)
+        .with_named_vars(HashMap::from([
+            (String::from("A"), String::from("a")),
+            (String::from("X"), String::from("x")),
+        ]))
+        .build();
+    let layout = xpanda
+        .parse("[$X] ${if A}<${X}>${else}${if B}$X${end}${end} ${X@urlencode} ${B:-$X} $XY")
+        .unwrap();
+    let template = layout.embed("X", &xpanda.parse("($A)").unwrap());
+
+    assert_eq!(
+        template.to_string(),
+        "[(${A})] ${if A}<(${A})>${else}${if B}(${A})${end}${end} ${X@urlencode} ${B:-${X}} \
+         ${XY}"
+    );
+    assert_eq!(
+        xpanda.render(&template),
+        Ok(String::from("[(a)] <(a)> x x "))
+    );
+
+    let texts = xpanda
+        .report(&template)
+        .params
+        .into_iter()
+        .map(|param| param.text)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        texts,
+        vec![
+            "$A",
+            "${if A}<(${A})>${else}${if B}(${A})${end}${end}",
+            "${X@urlencode}",
+            "${B:-$X}",
+            "$XY"
+        ]
+    );
+}
+
+#[test]
+#[cfg(feature = "zeroize")]
+fn secret_vars() {
+    let token = "x".repeat(1000);
+    let xpanda = Xpanda::builder()
+        .with_named_vars(HashMap::from([(String::from("USER"), String::from("me"))]))
+        .with_secret_vars(HashMap::from([(String::from("TOKEN"), token.clone())]))
+        .build();
+    let expected = format!("me:{token}:{token}:set");
+
+    assert_eq!(
+        xpanda.expand("$USER:$TOKEN:${TOKEN}:${TOKEN:+set}"),
+        Ok(expected.clone())
+    );
+    assert_eq!(
+        xpanda.render_compiled(
+            &Template::parse("$USER:$TOKEN:${TOKEN}:${TOKEN:+set}")
+                .unwrap()
+                .compile()
+        ),
+        Ok(expected)
+    );
+
+    let cached = CachedXpanda::with_capacity(xpanda, 1);
+    assert_eq!(cached.expand("$TOKEN"), Ok(token.clone()));
+    assert_eq!(cached.expand("$TOKEN"), Ok(token));
+    assert_eq!(cached.expand("$USER"), Ok(String::from("me")));
+}
+
+#[test]
+fn opaque_regions() {
+    let xpanda = Xpanda::builder()
+        .opaque_regions("{{", "}}")
+        .with_named_vars(HashMap::from([(String::from("A"), String::from("a"))]))
+        .build();
+
+    assert_eq!(
+        xpanda.expand("$A {{ $A ${B:?} }} ${A}{{$$}}$$ {{ $A"),
+        Ok(String::from("a {{ $A ${B:?} }} a{{$$}}$ {{ a"))
+    );
+    assert_eq!(
+        xpanda.expand("{{ a }} }} {{ $A }}"),
+        Ok(String::from("{{ a }} }} {{ $A }}"))
+    );
+
+    let template = xpanda.parse("$A {{ $B }} $$ {{ $$ }}").unwrap();
+    assert_eq!(template.variables(), vec!["A"]);
+    assert_eq!(template.to_string(), "${A} {{ $B }} $$ {{ $$ }}");
+    assert_eq!(xpanda.parse(&template.to_string()).unwrap(), template);
+
+    let xpanda = Xpanda::builder().opaque_regions("$(", ")").build();
+    assert!(xpanda.expand("$(ls)").is_err());
+}
+
+#[test]
+fn ansi_c_quotes_interpret() {
+    let xpanda = Xpanda::builder()
+        .ansi_c_quotes(AnsiCQuotes::Interpret)
+        .with_named_vars(HashMap::from([(String::from("A"), String::from("a"))]))
+        .build();
+
+    assert_eq!(
+        xpanda.expand(r"$A$'\n\t|\x41\x7|\u00e9\u20AC\U0001F600|\101\0' $A"),
+        Ok(String::from("a\n\t|A\x07|é€😀|A\0 a"))
+    );
+    assert_eq!(
+        xpanda.expand(r"$'\'\\\a\e\q\xg\u\uD800 $'"),
+        Ok(String::from("'\\\x07\x1b\\q\\xg\\u\\uD800 $"))
+    );
+    assert_eq!(
+        xpanda.expand(r"${B:-$'\t'}|${A:+$'\x62'}|$$'\n'"),
+        Ok(String::from("\t|b|$'\\n'"))
+    );
+    assert_eq!(xpanda.parse(r"$'\t'$A").unwrap().to_string(), r"$'\t'${A}");
+}
+
+#[test]
+fn ansi_c_quotes_keep() {
+    let xpanda = Xpanda::builder()
+        .ansi_c_quotes(AnsiCQuotes::Keep)
+        .with_named_vars(HashMap::from([(String::from("A"), String::from("a"))]))
+        .build();
+
+    assert_eq!(
+        xpanda.expand(r"IFS=$'\n' $A $'\'$A\''"),
+        Ok(String::from(r"IFS=$'\n' a $'\'$A\''"))
+    );
+    assert_eq!(xpanda.expand(r"${B:-$'\t'}"), Ok(String::from(r"$'\t'")));
+
+    let template = xpanda.parse(r"$'\n'$A").unwrap();
+    assert_eq!(template.to_string(), r"$'\n'${A}");
+    assert_eq!(
+        xpanda.expand(&template.to_string()),
+        Ok(String::from(r"$'\n'a"))
+    );
+}
+
+#[test]
+fn ansi_c_quotes_error() {
+    let error = Xpanda::default().expand(r"a $'\n'").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Syntax);
+    assert_eq!(error.message, "Expected identifier, found \"'\\n'\"");
+
+    // Quoted text must end on the line it starts on
+    let xpanda = Xpanda::builder()
+        .ansi_c_quotes(AnsiCQuotes::Interpret)
+        .build();
+    let error = xpanda.expand("a $'b\nc'").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Syntax);
+    assert_eq!((error.line, error.col), (1, 6));
+    assert!(xpanda.expand(r"$'a\'").is_err());
+}
+
+#[test]
+fn raw_blocks() {
+    let xpanda = Xpanda::builder()
+        .raw_blocks(true)
+        .with_named_vars(HashMap::from([(String::from("A"), String::from("a"))]))
+        .build();
+
+    assert_eq!(
+        xpanda.expand("$A $[[raw]]echo \"$1 ${2:-x}\"\n$$ }$[[/raw]] $$[[raw]] ${A}"),
+        Ok(String::from("a echo \"$1 ${2:-x}\"\n$$ } $[[raw]] a"))
+    );
+    assert_eq!(xpanda.expand("$[[raw]]$[[/raw]]"), Ok(String::new()));
+    assert_eq!(
+        xpanda
+            .expand("${A:-$[[raw]]}")
+            .map_err(|error| error.message),
+        Err(String::from("Expected identifier, found \"[[raw]]\""))
+    );
+
+    let template = xpanda.parse("$[[raw]]$A$[[/raw]]$$ $A").unwrap();
+    assert_eq!(template.variables(), vec!["A"]);
+    assert_eq!(template.to_string(), "$[[raw]]$A$[[/raw]]$$ ${A}");
+    assert_eq!(xpanda.parse(&template.to_string()).unwrap(), template);
+
+    assert!(Xpanda::default().expand("$[[raw]]$A$[[/raw]]").is_err());
+}
+
+#[test]
+fn raw_blocks_unclosed() {
+    let xpanda = Xpanda::builder().raw_blocks(true).build();
+
+    assert_eq!(
+        xpanda.expand("a\n $[[raw]] $A"),
+        Err(Error {
+            kind: ErrorKind::Syntax,
+            message: String::from("Unclosed raw block, expected a matching '$[[/raw]]'"),
+            line: 2,
+            col: 2,
+            span: Some(Span::new(Position::new(3, 2, 2), Position::new(11, 2, 10))),
+        })
+    );
+}
+
+#[test]
+fn comments() {
+    let xpanda = Xpanda::builder()
+        .comments(true)
+        .with_named_vars(HashMap::from([(String::from("A"), String::from("a"))]))
+        .build();
+
+    assert_eq!(
+        xpanda.expand("$# ${A\n$A\n$#\r\n$$# text\n$# last"),
+        Ok(String::from("a\n$# text\n"))
+    );
+    assert!(Xpanda::default().expand("$# ${A\n").is_err());
+
+    let error = xpanda.expand("$# comment\n$B ${").unwrap_err();
+    assert_eq!((error.line, error.col), (2, 4));
+    assert_eq!(error.span.unwrap().start, Position::new(14, 2, 4));
+
+    let template = xpanda.parse("$# comment\n$A\n").unwrap();
+    assert_eq!(template.variables(), vec!["A"]);
+    assert_eq!(template.to_string(), "${A}\n");
+}
+
+#[test]
+fn conditionals() {
+    let xpanda = Xpanda::builder()
+        .conditionals(true)
+        .no_unset(true)
+        .with_named_vars(HashMap::from([
+            (String::from("A"), String::from("a")),
+            (String::from("EMPTY"), String::new()),
+        ]))
+        .with_positional_vars(vec![String::from("x")])
+        .build();
+
+    assert_eq!(
+        xpanda.expand("${if A}\nset $A\n${else}\nunset\n${end}\nafter"),
+        Ok(String::from("set a\nafter"))
+    );
+    assert_eq!(
+        xpanda.expand("${if UNSET}set${else}unset $A${end} ${if EMPTY}set${end}."),
+        Ok(String::from("unset a ."))
+    );
+    assert_eq!(
+        xpanda.expand("${if  1 }${if B}b${else}${if A}$1${end}${end}${end}\r\n\n"),
+        Ok(String::from("x\n"))
+    );
+    assert_eq!(
+        xpanda.expand("${else-x} ${end:-y}"),
+        Ok(String::from("x y"))
+    );
+    assert!(Xpanda::default().expand("${if A}a${end}").is_err());
+}
+
+#[test]
+fn conditionals_errors() {
+    let xpanda = Xpanda::builder().conditionals(true).build();
+    let error = |input| {
+        xpanda.expand(input).map_err(|error| {
+            (
+                error.message,
+                error.span.unwrap().start,
+                error.span.unwrap().end,
+            )
+        })
+    };
+
+    assert_eq!(
+        error("a\n${if A}\n${else}"),
+        Err((
+            String::from("Unclosed conditional block, expected '${end}'"),
+            Position::new(2, 2, 1),
+            Position::new(9, 2, 8),
+        ))
+    );
+    assert_eq!(
+        error("${if A}${end} ${end}"),
+        Err((
+            String::from("Unexpected '${end}' outside of a conditional block"),
+            Position::new(14, 1, 15),
+            Position::new(20, 1, 21),
+        ))
+    );
+    assert_eq!(
+        error("${if A}${else}${else}${end}"),
+        Err((
+            String::from("Unexpected second '${else}', expected '${end}'"),
+            Position::new(14, 1, 15),
+            Position::new(21, 1, 22),
+        ))
+    );
+    assert_eq!(
+        error("${if A-x}${end}").map_err(|(message, ..)| message),
+        Err(String::from(
+            "Invalid condition 'A-x', expected a variable name"
+        ))
+    );
+    assert_eq!(
+        error("${A:-${if A}}").map_err(|(message, ..)| message),
+        Err(String::from("Invalid param, unexpected token \" A\""))
+    );
+
+    let nested = "${if A}".repeat(65) + &"${end}".repeat(65);
+    assert!(xpanda.expand(&nested).is_err());
+}
+
+#[test]
+fn conditionals_template() {
+    use xpanda::Reached;
+
+    let xpanda = Xpanda::builder()
+        .conditionals(true)
+        .with_named_vars(HashMap::from([(String::from("A"), String::from("a"))]))
+        .build();
+    let input = "${if A}\n\n$B\n${else}\r\n\r\n$$C${end}\n\n${if 1}${end}";
+    let template = xpanda.parse(input).unwrap();
+
+    assert_eq!(
+        template.to_string(),
+        "${if A}\n\n${B}\n${else}\n\r\n$$C${end}\n\n${if 1}${end}"
+    );
+    assert_eq!(xpanda.parse(&template.to_string()).unwrap(), template);
+    assert_eq!(
+        xpanda.render_compiled(&template.clone().compile()),
+        xpanda.render(&template)
+    );
+    assert_eq!(
+        template
+            .references()
+            .into_iter()
+            .map(|reference| (reference.name, reference.reached))
+            .collect::<Vec<_>>(),
+        vec![
+            (String::from("A"), Reached::Directly),
+            (
+                String::from("B"),
+                Reached::ViaAlternative(String::from("A"))
+            ),
+            (String::from("1"), Reached::Directly),
+        ]
+    );
+    assert!(template.required_variables().is_empty());
+}
+
+#[test]
+#[cfg(feature = "serialize")]
+fn serialize_conditionals() {
+    let xpanda = Xpanda::builder().conditionals(true).build();
+    let template = xpanda
+        .parse("${if A}\n$A${else}${if 1}$1${end}${end}")
+        .unwrap();
+    let bytes = template.to_bytes();
+    let loaded = Template::from_bytes(&bytes).unwrap();
+
+    assert_eq!(loaded, template);
+    assert_eq!(loaded.to_string(), template.to_string());
+}
+
+#[test]
+fn dynamic_names() {
+    let xpanda = Xpanda::builder()
+        .dynamic_names(true)
+        .with_positional_vars(vec![String::from("2"), String::from("b")])
+        .with_named_vars(HashMap::from([
+            (String::from("ENV"), String::from("PROD")),
+            (String::from("DB_PROD"), String::from("db.prod")),
+            (String::from("DB_PROD_PORT"), String::from("5432")),
+            (String::from("PROD"), String::from("prod")),
+        ]))
+        .build();
+
+    assert_eq!(xpanda.expand("${DB_${ENV}}"), Ok(String::from("db.prod")));
+    assert_eq!(xpanda.expand("${DB_$ENV}"), Ok(String::from("db.prod")));
+    assert_eq!(xpanda.expand("${DB_${ENV}_PORT}"), Ok(String::from("5432")));
+    assert_eq!(xpanda.expand("${${ENV}}"), Ok(String::from("prod")));
+    assert_eq!(xpanda.expand("${$1}"), Ok(String::from("b")));
+    assert_eq!(
+        xpanda.expand("${DB_${STAGE:-TEST}:-localhost}"),
+        Ok(String::from("localhost"))
+    );
+    assert_eq!(
+        xpanda.expand("${DB_${ENV}:+$ENV}"),
+        Ok(String::from("PROD"))
+    );
+    assert_eq!(
+        xpanda.expand("${DB_${ENV}_HOST?missing ${ENV}_HOST}"),
+        Err(Error::new(
+            ErrorKind::Required,
+            String::from("missing PROD_HOST"),
+            &Position::default()
+        ))
+    );
+    assert_eq!(
+        xpanda.expand("${DB_${ENV:-a-b}}"),
+        Ok(String::from("db.prod"))
+    );
+    assert_eq!(
+        xpanda.expand("${DB_${STAGE:-a-b}}"),
+        Err(Error::new(
+            ErrorKind::InvalidValue,
+            String::from("Invalid variable name 'DB_a-b' computed from 'DB_${STAGE:-a-b}'"),
+            &Position::new(0, 1, 1)
+        ))
+    );
+
+    let disabled = Xpanda::default();
+    assert!(disabled.expand("${DB_${ENV}}").is_err());
+}
+
+#[test]
+#[cfg(all(
+    feature = "case-modifiers",
+    feature = "length",
+    feature = "indirection"
+))]
+fn dynamic_names_params() {
+    let xpanda = Xpanda::builder()
+        .dynamic_names(true)
+        .with_named_vars(HashMap::from([
+            (String::from("ENV"), String::from("PROD")),
+            (String::from("DB_PROD"), String::from("db.prod")),
+            (String::from("REF"), String::from("DB_PROD")),
+        ]))
+        .build();
+
+    assert_eq!(xpanda.expand("${DB_${ENV}^^}"), Ok(String::from("DB.PROD")));
+    assert_eq!(xpanda.expand("${#DB_${ENV}}"), Ok(String::from("7")));
+    assert_eq!(
+        xpanda.expand("${!${ENV:+REF}}"),
+        Ok(String::from("db.prod"))
+    );
+}
+
+#[test]
+fn dynamic_names_template() {
+    let xpanda = Xpanda::builder().dynamic_names(true).build();
+    let template = xpanda.parse("${A_$B} ${${C}_D:-$E} ${F_${G}H:+x}").unwrap();
+
+    assert_eq!(
+        template.to_string(),
+        "${A_${B}} ${${C}_D:-${E}} ${F_${G}H:+x}"
+    );
+    assert_eq!(xpanda.parse(&template.to_string()).unwrap(), template);
+    assert_eq!(
+        xpanda.render_compiled(&template.clone().compile()),
+        xpanda.render(&template)
+    );
+    assert_eq!(template.variables(), vec!["B", "C", "E", "G"]);
+    assert_eq!(template.required_variables(), vec!["B", "C", "G"]);
+
+    let lints = Linter::new()
+        .severity(Rule::UnbracedVariable, Severity::Warning)
+        .lint(&xpanda, "${A_$B}")
+        .unwrap();
+    assert_eq!(
+        lints
+            .into_iter()
+            .map(|lint| (lint.rule, lint.position))
+            .collect::<Vec<_>>(),
+        vec![(Rule::UnbracedVariable, Position::new(4, 1, 5))]
+    );
+}
+
+#[test]
+#[cfg(feature = "serialize")]
+fn serialize_dynamic_names() {
+    let xpanda = Xpanda::builder().dynamic_names(true).build();
+    let template = xpanda.parse("${A_${B}_C:-${${D}}}").unwrap();
+    let bytes = template.to_bytes();
+    let loaded = Template::from_bytes(&bytes).unwrap();
+
+    assert_eq!(loaded, template);
+    assert_eq!(loaded.to_string(), template.to_string());
+}
+
+#[test]
+#[cfg(feature = "serialize")]
+fn serialize_opaque_regions() {
+    let xpanda = Xpanda::builder().opaque_regions("<%", "%>").build();
+    let template = xpanda.parse("$A <% $B %> $$").unwrap();
+    let bytes = template.to_bytes();
+    let loaded = Template::from_bytes(&bytes).unwrap();
+
+    assert_eq!(loaded, template);
+    assert_eq!(loaded.to_string(), "${A} <% $B %> $$");
+}
+
+#[test]
+#[cfg(feature = "locale")]
+fn case_locale() {
+    let vars = HashMap::from([
+        (String::from("A"), String::from("istanbul")),
+        (String::from("B"), String::from("IĞDIR")),
+    ]);
+    let input = "${A^^} ${A^} ${B,,} ${B,} ${A~} ${B~~}";
+    let turkish = Xpanda::builder()
+        .case_locale("tr-TR".parse().unwrap())
+        .with_named_vars(vars.clone())
+        .build();
+    let default = Xpanda::builder().with_named_vars(vars).build();
+
+    assert_eq!(
+        turkish.expand(input),
+        Ok(String::from("İSTANBUL İstanbul ığdır ıĞDIR İstanbul ığdır"))
+    );
+    assert_eq!(
+        default.expand(input),
+        Ok(String::from("ISTANBUL Istanbul iğdir iĞDIR Istanbul iğdir"))
+    );
+    assert_eq!(
+        "tr_TR!".parse::<xpanda::CaseLocale>(),
+        Err(String::from("Invalid locale 'tr_TR!'"))
+    );
+}
+
+#[test]
+#[cfg(feature = "serialize")]
+fn serialize_template() {
+    let input = "#! xpanda: require A; default B=b\n$A ${B:-${C:+x$1}} ${D:?no $D} ${E@pad:05} \
+                 ${E@trunc:1:.} ${ns:key} ${#} $$ é";
+    let xpanda = Xpanda::builder()
+        .front_matter(true)
+        .with_special_vars(HashMap::from([('$', String::from("42"))]))
+        .with_named_vars(HashMap::from([
+            (String::from("A"), String::from("a")),
+            (String::from("D"), String::from("d")),
+            (String::from("E"), String::from("7")),
+        ]))
+        .register_namespace("ns", FileProvider::new("."))
+        .build();
+    let template = xpanda.parse(input).unwrap();
+    let bytes = template.to_bytes();
+    let loaded = Template::from_bytes(&bytes).unwrap();
+
+    assert_eq!(loaded, template);
+    assert_eq!(loaded.to_string(), template.to_string());
+    assert_eq!(loaded.references(), template.references());
+    assert_eq!(
+        xpanda.render_compiled(&loaded.compile()),
+        xpanda.render(&template)
+    );
+}
+
+#[test]
+#[cfg(feature = "serialize")]
+fn serialize_template_ansi_c_quotes() {
+    for mode in [AnsiCQuotes::Interpret, AnsiCQuotes::Keep] {
+        let xpanda = Xpanda::builder().ansi_c_quotes(mode).build();
+        let template = xpanda.parse(r"$'\t'").unwrap();
+        let bytes = template.to_bytes();
+        let loaded = Template::from_bytes(&bytes).unwrap();
+
+        assert_eq!(loaded, template);
+    }
+}
+
+#[test]
+#[cfg(feature = "serialize")]
+fn serialize_template_error() {
+    let bytes = Template::parse("${A:-${B:?$C}}").unwrap().to_bytes();
+
+    for len in 0..bytes.len() {
+        let error = Template::from_bytes(&bytes[..len]).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Format);
+        assert_eq!(error.span, None);
+        assert_eq!(error.to_string(), error.message);
+    }
+
+    let mut bytes = bytes;
+    bytes[7] += 1;
+    let error = Template::from_bytes(&bytes).unwrap_err();
+    assert_eq!(
+        error.message,
+        "Invalid serialized template: Unsupported template format version 5, expected version 4"
+    );
+
+    let error = Template::from_bytes(b"$A").unwrap_err();
+    assert_eq!(
+        error.message,
+        "Invalid serialized template: Unexpected end of input"
+    );
+
+    let error = Template::from_bytes(b"not a template").unwrap_err();
+    assert_eq!(
+        error.message,
+        "Invalid serialized template: Not a serialized template"
+    );
+}
+
+#[test]
+fn semantics_case() {
+    let case = Case::parse(
+        "case",
+        "Description\n--- vars\nA=a=b\n--- args\n1\n\n--- input\n$A $1 [$2]\n\n--- output\na=b 1 []\n\n",
+    )
+    .unwrap();
+    assert_eq!(case.description, "Description");
+    assert_eq!(case.named_vars.get("A").map(String::as_str), Some("a=b"));
+    assert_eq!(case.positional_vars, vec!["1", ""]);
+    assert_eq!(case.input, "$A $1 [$2]\n");
+    assert_eq!(case.expected, Expected::Output(String::from("a=b 1 []\n")));
+    assert_eq!(case.run(Xpanda::builder()), Ok(()));
+
+    let mismatch = case.run(Xpanda::builder().no_empty(true)).unwrap_err();
+    assert_eq!(mismatch.case, "case");
+    assert_eq!(mismatch.actual.unwrap_err().kind, ErrorKind::Unset);
+
+    let error = Case::parse("case", "--- input\n$A\n--- output\n\n--- error\nunset").unwrap_err();
+    assert_eq!((error.kind, error.line), (ErrorKind::Syntax, 5));
+    let error = Case::parse("case", "--- input\n--- typo\n").unwrap_err();
+    assert_eq!(error.message, "Unknown section 'typo'");
+    let error = Case::parse("case", "--- input\n$A\n").unwrap_err();
+    assert_eq!(error.line, 1);
+
+    let mut corpus = Corpus::default();
+    corpus.cases.push(case);
+    assert_eq!(corpus.run(|builder| builder.no_empty(true)).len(), 1);
+    assert!(Corpus::load("does/not/exist").is_err());
+}
+
+#[test]
+fn to_builder() {
+    let resolved = Arc::new(Mutex::new(Vec::new()));
+    let listener = Arc::clone(&resolved);
+    let base = Xpanda::builder()
+        .quoting(true)
+        .no_unset(true)
+        .with_positional_vars(vec![String::from("one")])
+        .with_named_vars(HashMap::from([
+            (String::from("A"), String::from("a")),
+            (String::from("B"), String::from("b")),
+        ]))
+        .with_aliases(HashMap::from([(String::from("ALIAS"), String::from("A"))]))
+        .on_resolve(move |event| listener.lock().unwrap().push(event.name.clone()))
+        .build();
+    let derived = base
+        .to_builder()
+        .with_named_vars(HashMap::from([(String::from("B"), String::from("c"))]))
+        .build();
+    let clone = derived.clone();
+
+    assert_eq!(base.expand("$1 $ALIAS $B"), Ok(String::from("one a b")));
+    assert_eq!(derived.expand("$1 $ALIAS $B"), Ok(String::from("one a c")));
+    assert_eq!(clone.expand("${C-'}'}"), Ok(String::from("}")));
+    assert_eq!(clone.expand("$C").unwrap_err().kind, ErrorKind::Unset);
+    assert_eq!(resolved.lock().unwrap().len(), 6);
+}
+
+#[test]
+fn positional_names() {
+    let xpanda = Xpanda::builder()
+        .map_positional_names(["HOST", "PORT", "HOST", "USER"])
+        .with_positional_vars(vec![String::from("localhost"), String::from("8080")])
+        .with_named_vars(HashMap::from([
+            (String::from("PORT"), String::from("80")),
+            (String::from("USER"), String::from("me")),
+        ]))
+        .with_aliases(HashMap::from([(
+            String::from("SERVER"),
+            String::from("HOST"),
+        )]))
+        .build();
+
+    assert_eq!(
+        xpanda.expand("$HOST:$PORT $SERVER ${USER} ${HOST:+set}"),
+        Ok(String::from("localhost:8080 localhost me set"))
+    );
+
+    let template = xpanda.parse("$HOST $USER").unwrap();
+    assert_eq!(
+        xpanda.sources(&template),
+        vec![
+            (String::from("HOST"), Some(Source::Positional)),
+            (String::from("USER"), Some(Source::Named(None))),
+        ]
+    );
+}
+
+#[test]
+fn positional_out_of_range_warning() {
+    let warnings = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&warnings);
+    let xpanda = Xpanda::builder()
+        .with_positional_vars(vec![String::from("a")])
+        .on_warning(move |warning| recorded.lock().unwrap().push(warning.clone()))
+        .build();
+
+    let template = xpanda.parse("$1 ${2} ${3-c} ${3:+d}\n$4").unwrap();
+    assert_eq!(xpanda.render(&template), Ok(String::from("a  c \n")));
+    assert_eq!(
+        xpanda.render_compiled(&template.compile()),
+        Ok(String::from("a  c \n"))
+    );
+
+    let warnings = warnings.lock().unwrap();
+    let positions = warnings
+        .iter()
+        .map(|warning| (warning.kind, warning.position.line, warning.position.col))
+        .collect::<Vec<_>>();
+    let out_of_range = |index| WarningKind::PositionalOutOfRange { index, count: 1 };
+
+    assert_eq!(
+        positions,
+        vec![
+            (out_of_range(2), 1, 4),
+            (out_of_range(4), 2, 1),
+            (out_of_range(2), 1, 4),
+            (out_of_range(4), 2, 1),
+        ]
+    );
+    assert_eq!(
+        warnings[0].message,
+        "'$2' is out of range, 1 positional variable(s) are set"
     );
 }